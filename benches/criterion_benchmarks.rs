@@ -3,6 +3,7 @@ use oxidelta::compress::decoder;
 use oxidelta::compress::encoder::{self, CompressOptions};
 use oxidelta::compress::secondary::SecondaryCompression;
 use oxidelta::hash::table::SmallTable;
+use oxidelta::vcdiff::Integrity;
 use std::fs;
 use std::path::Path;
 
@@ -109,6 +110,67 @@ fn bench_decoding_speed(c: &mut Criterion) {
     g.finish();
 }
 
+fn bench_window_integrity(c: &mut Criterion) {
+    let mut g = c.benchmark_group("window_integrity_decode");
+    let source = gen_data(8 * 1024 * 1024, 4);
+    let target = mutate(&source, 2048);
+    g.throughput(Throughput::Bytes(target.len() as u64));
+    for integrity in [Integrity::Adler32, Integrity::Crc32c, Integrity::None] {
+        let mut delta = Vec::new();
+        encoder::encode_all(
+            &mut delta,
+            &source,
+            &target,
+            CompressOptions {
+                integrity,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let name = format!("{integrity:?}");
+        g.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let out = decoder::decode_all(black_box(&source), black_box(&delta)).unwrap();
+                black_box(out);
+            });
+        });
+    }
+    g.finish();
+}
+
+#[cfg(feature = "parallel")]
+fn bench_parallel_decoding(c: &mut Criterion) {
+    let mut g = c.benchmark_group("parallel_vs_sequential_decode");
+    let size = 8 * 1024 * 1024;
+    let source = gen_data(size, 8);
+    let target = mutate(&source, 2048);
+    let mut delta = Vec::new();
+    encoder::encode_all(
+        &mut delta,
+        &source,
+        &target,
+        CompressOptions {
+            window_size: 512 * 1024,
+            max_self_copy_distance: Some(0),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    g.throughput(Throughput::Bytes(target.len() as u64));
+    g.bench_function("sequential", |b| {
+        b.iter(|| black_box(decoder::decode_all(black_box(&source), black_box(&delta)).unwrap()));
+    });
+    g.bench_function("parallel", |b| {
+        b.iter(|| {
+            black_box(decoder::decode_all_parallel(black_box(&source), black_box(&delta)).unwrap())
+        });
+    });
+    g.finish();
+}
+
+#[cfg(not(feature = "parallel"))]
+fn bench_parallel_decoding(_c: &mut Criterion) {}
+
 fn bench_ratio_vs_level(c: &mut Criterion) {
     write_ratio_snapshot();
     let mut g = c.benchmark_group("compression_ratio_vs_level");
@@ -210,6 +272,8 @@ criterion_group!(
     benches,
     bench_encoding_speed,
     bench_decoding_speed,
+    bench_window_integrity,
+    bench_parallel_decoding,
     bench_ratio_vs_level,
     bench_memory_proxy,
     bench_hash_table,
@@ -17,9 +17,14 @@ impl CompressBackend for XorBackend {
         Ok(data.iter().map(|b| b ^ self.key).collect())
     }
 
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, DecodeError> {
+        // XOR never grows a section, so it cannot exceed the limit.
         Ok(data.iter().map(|b| b ^ self.key).collect())
     }
+
+    fn name(&self) -> &str {
+        "xor"
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
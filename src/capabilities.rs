@@ -0,0 +1,209 @@
+// Build capabilities.
+//
+// What this build of oxidelta can encode and decode, as data: the secondary
+// compressors compiled in, the checksum and match kernels in use, and the
+// xdelta3 tuning defaults. `oxidelta config` prints the same report.
+
+/// Encoder tuning defaults, matching xdelta3's `XD3_DEFAULT_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TuningDefaults {
+    /// Compression level (`-0` .. `-9`).
+    pub level: u32,
+    /// Target window size in bytes (`XD3_DEFAULT_WINSIZE`).
+    pub window_size: usize,
+    /// Source window size in bytes (`XD3_DEFAULT_SRCWINSZ`).
+    pub source_window_size: u64,
+    /// Instruction buffer size (`XD3_DEFAULT_IOPT_SIZE`).
+    pub iopt_size: usize,
+    /// Duplicate (previous-match) window size (`XD3_DEFAULT_SPREVSZ`).
+    pub sprevsz: usize,
+    /// Largest window size a decoder accepts (`XD3_HARDMAXWINSIZE`).
+    pub hard_max_window_size: usize,
+}
+
+/// The defaults xdelta3 3.x ships with; the CLI uses them for its flags.
+pub const TUNING_DEFAULTS: TuningDefaults = TuningDefaults {
+    level: 6,
+    window_size: 1 << 23,          // 8 MiB
+    source_window_size: 1 << 26,   // 64 MiB
+    iopt_size: 1 << 15,            // 32 KiB
+    sprevsz: 1 << 18,              // 256 KiB
+    hard_max_window_size: 1 << 24, // 16 MiB
+};
+
+/// Adler-32 implementation used for window checksums.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adler32Backend {
+    /// `simd-adler32`, with its own runtime CPU dispatch (`adler32` feature).
+    Simd,
+    /// The portable byte-at-a-time loop.
+    Scalar,
+}
+
+/// Forward-match kernel selected for this CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKernel {
+    /// x86_64 AVX2, 32 bytes per step (`simd` feature).
+    Avx2,
+    /// x86_64 SSE2, 16 bytes per step (`simd` feature).
+    Sse2,
+    /// aarch64 NEON, 16 bytes per step (`simd` feature).
+    Neon,
+    /// Portable loop comparing 8 bytes at a time.
+    Scalar,
+}
+
+impl MatchKernel {
+    /// Lowercase name, as `oxidelta config` prints it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Avx2 => "avx2",
+            Self::Sse2 => "sse2",
+            Self::Neon => "neon",
+            Self::Scalar => "scalar",
+        }
+    }
+}
+
+impl Adler32Backend {
+    /// Lowercase name, as `oxidelta config` prints it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Simd => "simd",
+            Self::Scalar => "scalar",
+        }
+    }
+}
+
+/// Whether one of xdelta3's own secondary compressors has been checked
+/// against xdelta3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// Round-trips deltas written by xdelta3 itself.
+    Verified,
+    /// Ported from xdelta3's C source, but not yet checked against deltas
+    /// xdelta3 wrote; real ones may be rejected or misread.
+    Unverified,
+}
+
+impl Verification {
+    /// Lowercase name, as `oxidelta config` prints it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::Unverified => "unverified",
+        }
+    }
+}
+
+/// What this build supports; see [`capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Crate version.
+    pub version: &'static str,
+    /// LZMA secondary compression (ID 2, `lzma-secondary` feature).
+    pub secondary_lzma: bool,
+    /// Zlib secondary compression (`zlib-secondary` feature; oxidelta only).
+    pub secondary_zlib: bool,
+    /// Zstandard secondary compression (`zstd-secondary` feature; oxidelta only).
+    pub secondary_zstd: bool,
+    /// xdelta3's DJW static Huffman secondary (ID 1). Decode only, always
+    /// built.
+    pub secondary_djw: Verification,
+    /// xdelta3's FGK adaptive Huffman secondary (ID 16). Decoding is always
+    /// built.
+    pub secondary_fgk: Verification,
+    /// FGK encoding (`fgk-encode` feature), opt-in while `secondary_fgk` is
+    /// unverified.
+    pub secondary_fgk_encode: bool,
+    /// Adler-32 implementation for window checksums.
+    pub adler32: Adler32Backend,
+    /// Multithreaded helpers (`parallel` feature).
+    pub parallel: bool,
+    /// Forward-match kernel in use, after runtime CPU detection.
+    pub match_kernel: MatchKernel,
+    /// Encoder tuning defaults; always [`TUNING_DEFAULTS`].
+    pub defaults: TuningDefaults,
+}
+
+/// Report the features and defaults of this build.
+///
+/// ```
+/// use oxidelta::capabilities::Verification;
+///
+/// let caps = oxidelta::capabilities();
+/// assert_eq!(caps.secondary_djw, Verification::Unverified);
+/// assert_eq!(caps.secondary_fgk, Verification::Unverified);
+/// assert_eq!(caps.defaults.level, 6);
+/// ```
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        secondary_lzma: cfg!(feature = "lzma-secondary"),
+        secondary_zlib: cfg!(feature = "zlib-secondary"),
+        secondary_zstd: cfg!(feature = "zstd-secondary"),
+        secondary_djw: Verification::Unverified,
+        secondary_fgk: Verification::Unverified,
+        secondary_fgk_encode: cfg!(feature = "fgk-encode"),
+        adler32: if cfg!(feature = "adler32") {
+            Adler32Backend::Simd
+        } else {
+            Adler32Backend::Scalar
+        },
+        parallel: cfg!(feature = "parallel"),
+        match_kernel: crate::hash::rolling::forward_match_kernel(),
+        defaults: TUNING_DEFAULTS,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::secondary::{SecondaryId, SecondaryRegistry};
+
+    #[test]
+    fn reflects_compiled_features() {
+        let caps = capabilities();
+        assert_eq!(caps.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(caps.secondary_lzma, cfg!(feature = "lzma-secondary"));
+        assert_eq!(caps.secondary_zlib, cfg!(feature = "zlib-secondary"));
+        assert_eq!(caps.secondary_zstd, cfg!(feature = "zstd-secondary"));
+        assert_eq!(caps.parallel, cfg!(feature = "parallel"));
+        assert_eq!(caps.secondary_fgk_encode, cfg!(feature = "fgk-encode"));
+        assert_eq!(
+            caps.adler32 == Adler32Backend::Simd,
+            cfg!(feature = "adler32")
+        );
+
+        // The secondary flags agree with what the registry can actually do.
+        let registry = SecondaryRegistry::new();
+        for (id, available) in [
+            (SecondaryId::Lzma, caps.secondary_lzma),
+            (SecondaryId::Zlib, caps.secondary_zlib),
+            (SecondaryId::Zstd, caps.secondary_zstd),
+            (SecondaryId::Djw, true),
+            (SecondaryId::Fgk, true),
+        ] {
+            assert_eq!(registry.get(Some(id.id())).is_ok(), available, "{id:?}");
+        }
+
+        if !cfg!(feature = "simd") {
+            assert_eq!(caps.match_kernel, MatchKernel::Scalar);
+        }
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(caps.match_kernel, MatchKernel::Avx2);
+        }
+
+        let d = caps.defaults;
+        assert_eq!(
+            d.hard_max_window_size as u64,
+            crate::vcdiff::header::HARD_MAX_WINSIZE
+        );
+        assert!(d.window_size <= d.hard_max_window_size);
+    }
+}
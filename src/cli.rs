@@ -1,1675 +1,3744 @@
-// Idiomatic Rust CLI for Oxidelta.
-//
-// Uses explicit subcommands and long-form options while preserving
-// the underlying encode/decode/recode/merge behavior.
-
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::process;
-
-use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum, ValueHint};
-
-use crate::compress::decoder::DeltaDecoder;
-use crate::compress::encoder::{CompressOptions, DeltaEncoder};
-use crate::compress::secondary::SecondaryCompression;
-use crate::vcdiff::Instruction;
-use crate::vcdiff::decoder::InstructionIterator;
-use crate::vcdiff::header::{
-    self, FileHeader, VCD_ADDRCOMP, VCD_ADLER32, VCD_APPHEADER, VCD_CODETABLE, VCD_DATACOMP,
-    VCD_INSTCOMP, VCD_SECONDARY, VCD_SOURCE, VCD_TARGET, WindowHeader,
-};
-
-// ---------------------------------------------------------------------------
-// Constants (matching xdelta3 defaults)
-// ---------------------------------------------------------------------------
-
-const XD3_DEFAULT_LEVEL: u32 = 6;
-const XD3_DEFAULT_WINSIZE: usize = 1 << 23; // 8 MiB
-const XD3_DEFAULT_SRCWINSZ: u64 = 1 << 26; // 64 MiB
-const XD3_DEFAULT_IOPT_SIZE: usize = 1 << 15; // 32 KiB
-const XD3_DEFAULT_SPREVSZ: usize = 1 << 18; // 256 KiB
-const XD3_HARDMAXWINSIZE: usize = 1 << 24; // 16 MiB
-
-const BUF_SIZE: usize = 64 * 1024;
-
-// ---------------------------------------------------------------------------
-// Byte size parsing (supports K, M, G suffixes)
-// ---------------------------------------------------------------------------
-
-fn parse_byte_size(s: &str) -> Result<u64, String> {
-    let s = s.trim();
-    if s.is_empty() {
-        return Err("empty size string".into());
-    }
-    let (num_part, multiplier) = match s.as_bytes().last() {
-        Some(b'k' | b'K') => (&s[..s.len() - 1], 1024u64),
-        Some(b'm' | b'M') => (&s[..s.len() - 1], 1024 * 1024),
-        Some(b'g' | b'G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
-        _ => (s, 1u64),
-    };
-    let num: u64 = num_part
-        .trim()
-        .parse()
-        .map_err(|e| format!("invalid size '{s}': {e}"))?;
-    num.checked_mul(multiplier)
-        .ok_or_else(|| format!("size overflow: '{s}'"))
-}
-
-// ---------------------------------------------------------------------------
-// Clap CLI definition
-// ---------------------------------------------------------------------------
-
-/// VCDIFF (RFC 3284) delta encoder/decoder.
-#[derive(Parser, Debug)]
-#[command(
-    name = "oxidelta",
-    version,
-    about = "VCDIFF delta encoder/decoder",
-    arg_required_else_help = true
-)]
-struct Cli {
-    #[command(subcommand)]
-    command: Cmd,
-
-    /// Force overwrite existing output files.
-    #[arg(short = 'f', long, global = true)]
-    force: bool,
-
-    /// Quiet mode (suppress non-error output).
-    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
-    quiet: bool,
-
-    /// Verbose mode (use multiple times for more detail).
-    #[arg(short = 'v', long, global = true, action = ArgAction::Count)]
-    verbose: u8,
-
-    /// Output stats as JSON to stderr.
-    #[arg(long = "json", global = true)]
-    json_output: bool,
-}
-
-#[derive(Subcommand, Debug)]
-enum Cmd {
-    /// Delta encode an input stream.
-    Encode(EncodeArgs),
-    /// Delta decode an input stream.
-    Decode(DecodeArgs),
-    /// Print build/configuration details.
-    Config,
-    /// Print information about the first VCDIFF window.
-    Header(PrintArgs),
-    /// Print information about all VCDIFF windows.
-    Headers(PrintArgs),
-    /// Print entire delta information (headers + instructions).
-    Delta(PrintArgs),
-    /// Re-encode a VCDIFF file with new secondary/app-header settings.
-    Recode(RecodeArgs),
-    /// Merge multiple VCDIFF deltas into one.
-    Merge(MergeArgs),
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
-enum SecondaryArg {
-    None,
-    Lzma,
-    Zlib,
-    Djw,
-    Fgk,
-}
-
-#[derive(Args, Debug)]
-struct EncodeTuningArgs {
-    /// Compression level (0-9).
-    #[arg(long, short = 'l', value_parser = clap::value_parser!(u32).range(0..=9), default_value_t = XD3_DEFAULT_LEVEL)]
-    level: u32,
-
-    /// Input window size (supports K/M/G suffix).
-    #[arg(long = "window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_WINSIZE as u64)]
-    input_window_size: u64,
-
-    /// Source window size (supports K/M/G suffix).
-    #[arg(long = "source-window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_SRCWINSZ)]
-    source_window_size: u64,
-
-    /// Duplicate-window tracking size (supports K/M/G suffix).
-    #[arg(long = "duplicate-window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_SPREVSZ as u64)]
-    sprevsz: u64,
-
-    /// Instruction optimization buffer size (supports K/M/G suffix).
-    #[arg(long = "instruction-buffer-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_IOPT_SIZE as u64)]
-    iopt_size: u64,
-
-    /// Secondary compressor.
-    #[arg(long, value_enum, default_value_t = SecondaryArg::None)]
-    secondary: SecondaryArg,
-
-    /// Disable small string-matching compression.
-    #[arg(long = "disable-small-matches")]
-    no_compress: bool,
-
-    /// Disable Adler-32 checksums.
-    #[arg(long = "no-checksum")]
-    no_checksum: bool,
-}
-
-#[derive(Args, Debug)]
-struct EncodeArgs {
-    /// Source file to copy from.
-    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
-    source: Option<PathBuf>,
-
-    /// Input file (default: stdin).
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
-    input: Option<PathBuf>,
-
-    /// Output file (default: stdout).
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
-    output: Option<PathBuf>,
-
-    /// Write output to stdout.
-    #[arg(short = 'c', long)]
-    stdout: bool,
-
-    /// Check/compute only (do not write output).
-    #[arg(long = "check-only")]
-    no_output: bool,
-
-    #[command(flatten)]
-    tuning: EncodeTuningArgs,
-
-    /// Input file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    input_pos: Option<PathBuf>,
-
-    /// Output file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    output_pos: Option<PathBuf>,
-}
-
-#[derive(Args, Debug)]
-struct DecodeArgs {
-    /// Source file to copy from.
-    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
-    source: Option<PathBuf>,
-
-    /// Input delta file (default: stdin).
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
-    input: Option<PathBuf>,
-
-    /// Output file (default: stdout).
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
-    output: Option<PathBuf>,
-
-    /// Write output to stdout.
-    #[arg(short = 'c', long)]
-    stdout: bool,
-
-    /// Disable Adler-32 verification.
-    #[arg(long = "no-checksum")]
-    no_checksum: bool,
-
-    /// Check/compute only (do not write output).
-    #[arg(long = "check-only")]
-    no_output: bool,
-
-    /// Input file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    input_pos: Option<PathBuf>,
-
-    /// Output file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    output_pos: Option<PathBuf>,
-}
-
-#[derive(Args, Debug)]
-struct PrintArgs {
-    /// VCDIFF input file.
-    #[arg(value_hint = ValueHint::FilePath)]
-    input: PathBuf,
-}
-
-#[derive(Args, Debug)]
-struct RecodeArgs {
-    /// Input VCDIFF file.
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
-    input: Option<PathBuf>,
-
-    /// Output VCDIFF file (default: stdout).
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
-    output: Option<PathBuf>,
-
-    /// Write output to stdout.
-    #[arg(short = 'c', long)]
-    stdout: bool,
-
-    /// Secondary compressor.
-    #[arg(long, value_enum, default_value_t = SecondaryArg::None)]
-    secondary: SecondaryArg,
-
-    /// Replace/attach an application header.
-    #[arg(long = "app-header")]
-    app_header: Option<String>,
-
-    /// Drop the application header.
-    #[arg(long = "drop-app-header", conflicts_with = "app_header")]
-    drop_app_header: bool,
-
-    /// Input file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    input_pos: Option<PathBuf>,
-
-    /// Output file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    output_pos: Option<PathBuf>,
-}
-
-#[derive(Args, Debug)]
-struct MergeArgs {
-    /// Source file to copy from.
-    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
-    source: Option<PathBuf>,
-
-    /// Merge input files (repeat for each patch, in order).
-    #[arg(long = "patch", short = 'p', value_name = "PATCH", value_hint = ValueHint::FilePath, action = ArgAction::Append)]
-    patches: Vec<PathBuf>,
-
-    /// Last patch input file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    last_patch: Option<PathBuf>,
-
-    /// Output file.
-    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
-    output: Option<PathBuf>,
-
-    /// Output file (positional form).
-    #[arg(value_hint = ValueHint::FilePath)]
-    output_pos: Option<PathBuf>,
-
-    /// Write output to stdout.
-    #[arg(short = 'c', long)]
-    stdout: bool,
-
-    #[command(flatten)]
-    tuning: EncodeTuningArgs,
-}
-
-// ---------------------------------------------------------------------------
-// Resolved command + options (flattened from Cli)
-// ---------------------------------------------------------------------------
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Command {
-    Encode,
-    Decode,
-    Config,
-    PrintHdr,
-    PrintHdrs,
-    PrintDelta,
-    Recode,
-    Merge,
-}
-
-#[allow(dead_code)]
-struct Options {
-    command: Command,
-    use_stdout: bool,
-    force: bool,
-    quiet: bool,
-    verbose: u8,
-    level: u32,
-    no_compress: bool,
-    no_checksum: bool,
-    no_output: bool,
-    use_secondary: bool,
-    secondary_name: Option<String>,
-    use_appheader: bool,
-    appheader: Option<String>,
-    source_window_size: u64,
-    input_window_size: usize,
-    iopt_size: usize,
-    sprevsz: usize,
-    source_file: Option<PathBuf>,
-    input_file: Option<PathBuf>,
-    output_file: Option<PathBuf>,
-    merge_files: Vec<PathBuf>,
-    json_output: bool,
-}
-
-fn secondary_name(sec: SecondaryArg) -> Option<String> {
-    match sec {
-        SecondaryArg::None => None,
-        SecondaryArg::Lzma => Some("lzma".to_string()),
-        SecondaryArg::Zlib => Some("zlib".to_string()),
-        SecondaryArg::Djw => Some("djw".to_string()),
-        SecondaryArg::Fgk => Some("fgk".to_string()),
-    }
-}
-
-fn resolve_options(cli: Cli) -> Options {
-    let quiet = cli.quiet;
-    let verbose = cli.verbose.min(2);
-    let force = cli.force;
-    let json_output = cli.json_output;
-
-    match cli.command {
-        Cmd::Encode(args) => {
-            let secondary_name = secondary_name(args.tuning.secondary);
-            Options {
-                command: Command::Encode,
-                use_stdout: args.stdout,
-                force,
-                quiet,
-                verbose,
-                level: args.tuning.level,
-                no_compress: args.tuning.no_compress,
-                no_checksum: args.tuning.no_checksum,
-                no_output: args.no_output,
-                use_secondary: secondary_name.is_some(),
-                secondary_name,
-                use_appheader: true,
-                appheader: None,
-                source_window_size: args.tuning.source_window_size,
-                input_window_size: args.tuning.input_window_size as usize,
-                iopt_size: args.tuning.iopt_size as usize,
-                sprevsz: args.tuning.sprevsz as usize,
-                source_file: args.source,
-                input_file: args.input.or(args.input_pos),
-                output_file: args.output.or(args.output_pos),
-                merge_files: Vec::new(),
-                json_output,
-            }
-        }
-        Cmd::Decode(args) => Options {
-            command: Command::Decode,
-            use_stdout: args.stdout,
-            force,
-            quiet,
-            verbose,
-            level: XD3_DEFAULT_LEVEL,
-            no_compress: false,
-            no_checksum: args.no_checksum,
-            no_output: args.no_output,
-            use_secondary: false,
-            secondary_name: None,
-            use_appheader: true,
-            appheader: None,
-            source_window_size: XD3_DEFAULT_SRCWINSZ,
-            input_window_size: XD3_DEFAULT_WINSIZE,
-            iopt_size: XD3_DEFAULT_IOPT_SIZE,
-            sprevsz: XD3_DEFAULT_SPREVSZ,
-            source_file: args.source,
-            input_file: args.input.or(args.input_pos),
-            output_file: args.output.or(args.output_pos),
-            merge_files: Vec::new(),
-            json_output,
-        },
-        Cmd::Config => Options {
-            command: Command::Config,
-            use_stdout: false,
-            force,
-            quiet,
-            verbose,
-            level: XD3_DEFAULT_LEVEL,
-            no_compress: false,
-            no_checksum: false,
-            no_output: false,
-            use_secondary: false,
-            secondary_name: None,
-            use_appheader: true,
-            appheader: None,
-            source_window_size: XD3_DEFAULT_SRCWINSZ,
-            input_window_size: XD3_DEFAULT_WINSIZE,
-            iopt_size: XD3_DEFAULT_IOPT_SIZE,
-            sprevsz: XD3_DEFAULT_SPREVSZ,
-            source_file: None,
-            input_file: None,
-            output_file: None,
-            merge_files: Vec::new(),
-            json_output,
-        },
-        Cmd::Header(args) => Options {
-            command: Command::PrintHdr,
-            use_stdout: false,
-            force,
-            quiet,
-            verbose,
-            level: XD3_DEFAULT_LEVEL,
-            no_compress: false,
-            no_checksum: false,
-            no_output: false,
-            use_secondary: false,
-            secondary_name: None,
-            use_appheader: true,
-            appheader: None,
-            source_window_size: XD3_DEFAULT_SRCWINSZ,
-            input_window_size: XD3_DEFAULT_WINSIZE,
-            iopt_size: XD3_DEFAULT_IOPT_SIZE,
-            sprevsz: XD3_DEFAULT_SPREVSZ,
-            source_file: None,
-            input_file: Some(args.input),
-            output_file: None,
-            merge_files: Vec::new(),
-            json_output,
-        },
-        Cmd::Headers(args) => Options {
-            command: Command::PrintHdrs,
-            use_stdout: false,
-            force,
-            quiet,
-            verbose,
-            level: XD3_DEFAULT_LEVEL,
-            no_compress: false,
-            no_checksum: false,
-            no_output: false,
-            use_secondary: false,
-            secondary_name: None,
-            use_appheader: true,
-            appheader: None,
-            source_window_size: XD3_DEFAULT_SRCWINSZ,
-            input_window_size: XD3_DEFAULT_WINSIZE,
-            iopt_size: XD3_DEFAULT_IOPT_SIZE,
-            sprevsz: XD3_DEFAULT_SPREVSZ,
-            source_file: None,
-            input_file: Some(args.input),
-            output_file: None,
-            merge_files: Vec::new(),
-            json_output,
-        },
-        Cmd::Delta(args) => Options {
-            command: Command::PrintDelta,
-            use_stdout: false,
-            force,
-            quiet,
-            verbose,
-            level: XD3_DEFAULT_LEVEL,
-            no_compress: false,
-            no_checksum: false,
-            no_output: false,
-            use_secondary: false,
-            secondary_name: None,
-            use_appheader: true,
-            appheader: None,
-            source_window_size: XD3_DEFAULT_SRCWINSZ,
-            input_window_size: XD3_DEFAULT_WINSIZE,
-            iopt_size: XD3_DEFAULT_IOPT_SIZE,
-            sprevsz: XD3_DEFAULT_SPREVSZ,
-            source_file: None,
-            input_file: Some(args.input),
-            output_file: None,
-            merge_files: Vec::new(),
-            json_output,
-        },
-        Cmd::Recode(args) => {
-            let secondary_name = secondary_name(args.secondary);
-            let (use_appheader, appheader) = if args.drop_app_header {
-                (false, None)
-            } else if let Some(app) = args.app_header {
-                (true, Some(app))
-            } else {
-                (true, None)
-            };
-            Options {
-                command: Command::Recode,
-                use_stdout: args.stdout,
-                force,
-                quiet,
-                verbose,
-                level: XD3_DEFAULT_LEVEL,
-                no_compress: false,
-                no_checksum: false,
-                no_output: false,
-                use_secondary: secondary_name.is_some(),
-                secondary_name,
-                use_appheader,
-                appheader,
-                source_window_size: XD3_DEFAULT_SRCWINSZ,
-                input_window_size: XD3_DEFAULT_WINSIZE,
-                iopt_size: XD3_DEFAULT_IOPT_SIZE,
-                sprevsz: XD3_DEFAULT_SPREVSZ,
-                source_file: None,
-                input_file: args.input.or(args.input_pos),
-                output_file: args.output.or(args.output_pos),
-                merge_files: Vec::new(),
-                json_output,
-            }
-        }
-        Cmd::Merge(args) => {
-            let secondary_name = secondary_name(args.tuning.secondary);
-            Options {
-                command: Command::Merge,
-                use_stdout: args.stdout,
-                force,
-                quiet,
-                verbose,
-                level: args.tuning.level,
-                no_compress: args.tuning.no_compress,
-                no_checksum: args.tuning.no_checksum,
-                no_output: false,
-                use_secondary: secondary_name.is_some(),
-                secondary_name,
-                use_appheader: true,
-                appheader: None,
-                source_window_size: args.tuning.source_window_size,
-                input_window_size: args.tuning.input_window_size as usize,
-                iopt_size: args.tuning.iopt_size as usize,
-                sprevsz: args.tuning.sprevsz as usize,
-                source_file: args.source,
-                input_file: args.last_patch,
-                output_file: args.output.or(args.output_pos),
-                merge_files: args.patches,
-                json_output,
-            }
-        }
-    }
-}
-
-#[cfg(any(test, feature = "fuzzing"))]
-pub fn fuzz_try_parse_args(args: &[String]) {
-    let argv: Vec<String> = std::iter::once("oxidelta".to_string())
-        .chain(args.iter().cloned())
-        .collect();
-    if let Ok(cli) = Cli::try_parse_from(argv) {
-        let _ = resolve_options(cli);
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Config command
-// ---------------------------------------------------------------------------
-
-fn cmd_config() -> i32 {
-    let version = env!("CARGO_PKG_VERSION");
-    eprintln!("oxidelta version {version} (Rust), Copyright (C) oxidelta contributors");
-    eprintln!("Licensed under the Apache License, Version 2.0");
-
-    let lzma = cfg!(feature = "lzma-secondary") as u8;
-    let zlib = cfg!(feature = "zlib-secondary") as u8;
-    let adler32 = cfg!(feature = "adler32") as u8;
-    let file_io = cfg!(feature = "file-io") as u8;
-    let ptr_size = std::mem::size_of::<*const ()>();
-
-    eprintln!("SECONDARY_LZMA={lzma}");
-    eprintln!("SECONDARY_ZLIB={zlib}");
-    eprintln!("ADLER32={adler32}");
-    eprintln!("FILE_IO={file_io}");
-    eprintln!("XD3_DEFAULT_LEVEL={XD3_DEFAULT_LEVEL}");
-    eprintln!("XD3_DEFAULT_IOPT_SIZE={XD3_DEFAULT_IOPT_SIZE}");
-    eprintln!("XD3_DEFAULT_SPREVSZ={XD3_DEFAULT_SPREVSZ}");
-    eprintln!("XD3_DEFAULT_SRCWINSZ={XD3_DEFAULT_SRCWINSZ}");
-    eprintln!("XD3_DEFAULT_WINSIZE={XD3_DEFAULT_WINSIZE}");
-    eprintln!("XD3_HARDMAXWINSIZE={XD3_HARDMAXWINSIZE}");
-    eprintln!("sizeof(usize)={ptr_size}");
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Build CompressOptions from CLI options
-// ---------------------------------------------------------------------------
-
-fn build_compress_options(opts: &Options) -> CompressOptions {
-    let secondary = if opts.use_secondary {
-        match opts.secondary_name.as_deref() {
-            #[cfg(feature = "lzma-secondary")]
-            Some("lzma") => SecondaryCompression::Lzma,
-            #[cfg(feature = "zlib-secondary")]
-            Some("zlib") => SecondaryCompression::Zlib { level: opts.level },
-            Some(name) => {
-                eprintln!("oxidelta: warning: unknown secondary compressor '{name}', using none");
-                SecondaryCompression::None
-            }
-            None => {
-                #[cfg(feature = "lzma-secondary")]
-                {
-                    SecondaryCompression::Lzma
-                }
-                #[cfg(not(feature = "lzma-secondary"))]
-                {
-                    SecondaryCompression::None
-                }
-            }
-        }
-    } else {
-        SecondaryCompression::None
-    };
-
-    CompressOptions {
-        level: opts.level,
-        window_size: opts.input_window_size,
-        checksum: !opts.no_checksum,
-        secondary,
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Encode command
-// ---------------------------------------------------------------------------
-
-fn cmd_encode(opts: &Options) -> i32 {
-    let compress_opts = build_compress_options(opts);
-
-    // Read source file (if any) fully into memory.
-    let source = match &opts.source_file {
-        Some(path) => match std::fs::read(path) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("oxidelta: source file: {}: {e}", path.display());
-                return 1;
-            }
-        },
-        None => Vec::new(),
-    };
-
-    // Open input (target): file or stdin.
-    let target_reader: Box<dyn Read> = match &opts.input_file {
-        Some(path) => match File::open(path) {
-            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
-            Err(e) => {
-                eprintln!("oxidelta: input file: {}: {e}", path.display());
-                return 1;
-            }
-        },
-        None => Box::new(BufReader::new(io::stdin())),
-    };
-
-    // Open output: file or stdout.
-    let output_writer: Box<dyn Write> = match (opts.use_stdout, &opts.output_file) {
-        (true, _) | (_, None) => Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock())),
-        (false, Some(path)) => {
-            if path.exists() && !opts.force {
-                eprintln!(
-                    "oxidelta: output file exists, use -f to overwrite: {}",
-                    path.display()
-                );
-                return 1;
-            }
-            match File::create(path) {
-                Ok(f) => Box::new(BufWriter::with_capacity(BUF_SIZE, f)),
-                Err(e) => {
-                    eprintln!("oxidelta: output file: {}: {e}", path.display());
-                    return 1;
-                }
-            }
-        }
-    };
-
-    if opts.no_output {
-        let mut reader = target_reader;
-        let mut buf = vec![0u8; BUF_SIZE];
-        let mut total = 0u64;
-        loop {
-            match reader.read(&mut buf) {
-                Ok(0) => break,
-                Ok(n) => total += n as u64,
-                Err(e) => {
-                    eprintln!("oxidelta: read error: {e}");
-                    return 1;
-                }
-            }
-        }
-        if !opts.quiet {
-            eprintln!("oxidelta: input size: {total}");
-        }
-        return 0;
-    }
-
-    let mut encoder = DeltaEncoder::new(output_writer, &source, compress_opts);
-    let mut reader = target_reader;
-    let mut buf = vec![0u8; BUF_SIZE];
-    let mut total_in = 0u64;
-
-    loop {
-        match reader.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => {
-                total_in += n as u64;
-                if let Err(e) = encoder.write_target(&buf[..n]) {
-                    eprintln!("oxidelta: encode error: {e}");
-                    return 1;
-                }
-            }
-            Err(e) => {
-                eprintln!("oxidelta: read error: {e}");
-                return 1;
-            }
-        }
-    }
-
-    let (mut writer, windows) = match encoder.finish() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("oxidelta: encode finish error: {e}");
-            return 1;
-        }
-    };
-
-    if let Err(e) = writer.flush() {
-        eprintln!("oxidelta: write flush error: {e}");
-        return 1;
-    }
-
-    if opts.verbose > 0 && !opts.quiet {
-        let source_size = source.len() as u64;
-        eprintln!(
-            "oxidelta: encoder: source size: {source_size}, input size: {total_in}, \
-             windows: {windows}"
-        );
-    }
-
-    if opts.json_output {
-        let source_size = source.len() as u64;
-        let json = serde_json::json!({
-            "command": "encode",
-            "source_size": source_size,
-            "input_size": total_in,
-            "windows": windows,
-            "level": opts.level,
-        });
-        eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
-    }
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Decode command
-// ---------------------------------------------------------------------------
-
-fn cmd_decode(opts: &Options) -> i32 {
-    let source = match &opts.source_file {
-        Some(path) => match std::fs::read(path) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("oxidelta: source file: {}: {e}", path.display());
-                return 1;
-            }
-        },
-        None => Vec::new(),
-    };
-
-    let delta_reader: Box<dyn Read> = match &opts.input_file {
-        Some(path) => match File::open(path) {
-            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
-            Err(e) => {
-                eprintln!("oxidelta: input file: {}: {e}", path.display());
-                return 1;
-            }
-        },
-        None => Box::new(BufReader::new(io::stdin())),
-    };
-
-    let mut output_writer: Box<dyn Write> = if opts.no_output {
-        Box::new(io::sink())
-    } else if opts.use_stdout || opts.output_file.is_none() {
-        Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock()))
-    } else {
-        let path = opts.output_file.as_ref().unwrap();
-        if path.exists() && !opts.force {
-            eprintln!(
-                "oxidelta: output file exists, use -f to overwrite: {}",
-                path.display()
-            );
-            return 1;
-        }
-        match File::create(path) {
-            Ok(f) => Box::new(BufWriter::with_capacity(BUF_SIZE, f)),
-            Err(e) => {
-                eprintln!("oxidelta: output file: {}: {e}", path.display());
-                return 1;
-            }
-        }
-    };
-
-    let verify_checksum = !opts.no_checksum;
-    let mut decoder = DeltaDecoder::with_checksum(delta_reader, verify_checksum);
-    let mut src: &[u8] = &source;
-
-    match decoder.decode_to(&mut src, &mut output_writer) {
-        Ok(total) => {
-            if let Err(e) = output_writer.flush() {
-                eprintln!("oxidelta: write flush error: {e}");
-                return 1;
-            }
-            if opts.verbose > 0 && !opts.quiet {
-                let windows = decoder.windows_decoded();
-                eprintln!("oxidelta: decoder: output size: {total}, windows: {windows}");
-            }
-            if opts.json_output {
-                let windows = decoder.windows_decoded();
-                let json = serde_json::json!({
-                    "command": "decode",
-                    "output_size": total,
-                    "windows": windows,
-                });
-                eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
-            }
-        }
-        Err(e) => {
-            eprintln!("oxidelta: decode error: {e}");
-            return 1;
-        }
-    }
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Print commands (printhdr, printhdrs, printdelta)
-// ---------------------------------------------------------------------------
-
-fn cmd_print(opts: &Options) -> i32 {
-    let input_file = match &opts.input_file {
-        Some(path) => path.clone(),
-        None => {
-            eprintln!("oxidelta: print commands require an input file");
-            return 1;
-        }
-    };
-
-    let file = match File::open(&input_file) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("oxidelta: {}: {e}", input_file.display());
-            return 1;
-        }
-    };
-    let mut reader = BufReader::with_capacity(BUF_SIZE, file);
-
-    let file_hdr = match FileHeader::decode(&mut reader) {
-        Ok(hdr) => hdr,
-        Err(e) => {
-            eprintln!("oxidelta: invalid VCDIFF header: {e}");
-            return 1;
-        }
-    };
-
-    // Compute header size: magic(4) + hdr_ind(1) + optional fields.
-    let mut hdr_size: usize = 5;
-    if file_hdr.hdr_ind & VCD_SECONDARY != 0 {
-        hdr_size += 1;
-    }
-    if file_hdr.hdr_ind & VCD_APPHEADER != 0 {
-        if let Some(ref data) = file_hdr.app_header {
-            hdr_size += crate::vcdiff::varint::sizeof_usize(data.len()) + data.len();
-        } else {
-            hdr_size += 1;
-        }
-    }
-
-    println!("VCDIFF version:               0");
-    println!("VCDIFF header size:           {hdr_size}");
-
-    print!("VCDIFF header indicator:      ");
-    let mut any_hdr_flag = false;
-    if file_hdr.hdr_ind & VCD_SECONDARY != 0 {
-        print!("VCD_SECONDARY ");
-        any_hdr_flag = true;
-    }
-    if file_hdr.hdr_ind & VCD_CODETABLE != 0 {
-        print!("VCD_CODETABLE ");
-        any_hdr_flag = true;
-    }
-    if file_hdr.hdr_ind & VCD_APPHEADER != 0 {
-        print!("VCD_APPHEADER ");
-        any_hdr_flag = true;
-    }
-    if !any_hdr_flag {
-        print!("none");
-    }
-    println!();
-
-    let sec_name = match file_hdr.secondary_id {
-        Some(header::VCD_LZMA_ID) => "lzma",
-        Some(header::VCD_DJW_ID) => "djw",
-        Some(header::VCD_FGK_ID) => "fgk",
-        Some(3) => "zlib",
-        Some(id) => {
-            println!("VCDIFF secondary compressor:  unknown (id={id})");
-            ""
-        }
-        None => "none",
-    };
-    if !sec_name.is_empty() {
-        println!("VCDIFF secondary compressor:  {sec_name}");
-    }
-
-    if file_hdr.hdr_ind & VCD_APPHEADER != 0
-        && let Some(ref data) = file_hdr.app_header
-        && !data.is_empty()
-    {
-        let s = String::from_utf8_lossy(data);
-        println!("VCDIFF application header:    {s}");
-    }
-
-    let mut window_num: u64 = 0;
-    let mut target_offset: u64 = 0;
-
-    loop {
-        let wh = match WindowHeader::decode(&mut reader) {
-            Ok(Some(wh)) => wh,
-            Ok(None) => break,
-            Err(e) => {
-                eprintln!("oxidelta: window {window_num}: {e}");
-                return 1;
-            }
-        };
-
-        if window_num > 0 {
-            println!();
-        }
-
-        println!("VCDIFF window number:         {window_num}");
-
-        print!("VCDIFF window indicator:      ");
-        let mut any_win_flag = false;
-        if wh.win_ind & VCD_SOURCE != 0 {
-            print!("VCD_SOURCE ");
-            any_win_flag = true;
-        }
-        if wh.win_ind & VCD_TARGET != 0 {
-            print!("VCD_TARGET ");
-            any_win_flag = true;
-        }
-        if wh.win_ind & VCD_ADLER32 != 0 {
-            print!("VCD_ADLER32 ");
-            any_win_flag = true;
-        }
-        if !any_win_flag {
-            print!("none");
-        }
-        println!();
-
-        if let Some(cksum) = wh.adler32 {
-            println!("VCDIFF adler32 checksum:      {cksum:08X}");
-        }
-
-        if wh.del_ind != 0 {
-            print!("VCDIFF delta indicator:       ");
-            if wh.del_ind & VCD_DATACOMP != 0 {
-                print!("VCD_DATACOMP ");
-            }
-            if wh.del_ind & VCD_INSTCOMP != 0 {
-                print!("VCD_INSTCOMP ");
-            }
-            if wh.del_ind & VCD_ADDRCOMP != 0 {
-                print!("VCD_ADDRCOMP ");
-            }
-            println!();
-        }
-
-        if target_offset > 0 {
-            println!("VCDIFF window at offset:      {target_offset}");
-        }
-
-        if wh.has_source() || wh.has_target() {
-            println!("VCDIFF copy window length:    {}", wh.copy_window_len);
-            println!("VCDIFF copy window offset:    {}", wh.copy_window_offset);
-        }
-
-        println!("VCDIFF delta encoding length: {}", wh.enc_len);
-        println!("VCDIFF target window length:  {}", wh.target_window_len);
-        println!("VCDIFF data section length:   {}", wh.data_len);
-        println!("VCDIFF inst section length:   {}", wh.inst_len);
-        println!("VCDIFF addr section length:   {}", wh.addr_len);
-
-        if opts.command == Command::PrintDelta {
-            let mut data_buf = vec![0u8; wh.data_len as usize];
-            let mut inst_buf = vec![0u8; wh.inst_len as usize];
-            let mut addr_buf = vec![0u8; wh.addr_len as usize];
-
-            if let Err(e) = reader.read_exact(&mut data_buf) {
-                eprintln!("oxidelta: window {window_num} data section: {e}");
-                return 1;
-            }
-            if let Err(e) = reader.read_exact(&mut inst_buf) {
-                eprintln!("oxidelta: window {window_num} inst section: {e}");
-                return 1;
-            }
-            if let Err(e) = reader.read_exact(&mut addr_buf) {
-                eprintln!("oxidelta: window {window_num} addr section: {e}");
-                return 1;
-            }
-
-            let (inst_ref, addr_ref);
-            let decomp_i;
-            let decomp_a;
-            if wh.del_ind != 0 {
-                let (_, i, a) = match crate::compress::secondary::decompress_sections(
-                    &data_buf,
-                    &inst_buf,
-                    &addr_buf,
-                    wh.del_ind,
-                    file_hdr.secondary_id,
-                ) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        eprintln!("oxidelta: window {window_num} decompress: {e}");
-                        return 1;
-                    }
-                };
-                decomp_i = i;
-                decomp_a = a;
-                inst_ref = &decomp_i[..];
-                addr_ref = &decomp_a[..];
-            } else {
-                inst_ref = &inst_buf;
-                addr_ref = &addr_buf;
-            }
-
-            println!("  Offset Code Type1 Size1  @Addr1 + Type2 Size2 @Addr2");
-            let copy_window_len = if wh.has_source() || wh.has_target() {
-                wh.copy_window_len
-            } else {
-                0
-            };
-
-            let iter = InstructionIterator::new(inst_ref, addr_ref, copy_window_len);
-            let mut offset = target_offset;
-            for result in iter {
-                match result {
-                    Ok(inst) => match inst {
-                        Instruction::Add { len } => {
-                            println!("  {offset:06}     ADD  {len:6}");
-                            offset += len as u64;
-                        }
-                        Instruction::Copy { len, addr, .. } => {
-                            let addr_str = if addr >= copy_window_len {
-                                format!("T@{:<6}", addr - copy_window_len)
-                            } else {
-                                format!("S@{:<6}", wh.copy_window_offset + addr)
-                            };
-                            println!("  {offset:06}     CPY  {len:6} {addr_str}");
-                            offset += len as u64;
-                        }
-                        Instruction::Run { len } => {
-                            println!("  {offset:06}     RUN  {len:6}");
-                            offset += len as u64;
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("oxidelta: instruction decode: {e}");
-                        return 1;
-                    }
-                }
-            }
-        } else {
-            // Skip section data for printhdr/printhdrs.
-            let section_total = wh.data_len as usize + wh.inst_len as usize + wh.addr_len as usize;
-            let mut skip_buf = vec![0u8; section_total.min(BUF_SIZE)];
-            let mut remaining = section_total;
-            while remaining > 0 {
-                let to_read = remaining.min(skip_buf.len());
-                if let Err(e) = reader.read_exact(&mut skip_buf[..to_read]) {
-                    eprintln!("oxidelta: window {window_num}: {e}");
-                    return 1;
-                }
-                remaining -= to_read;
-            }
-        }
-
-        target_offset += wh.target_window_len;
-        window_num += 1;
-
-        if opts.command == Command::PrintHdr {
-            break;
-        }
-    }
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Recode command
-// ---------------------------------------------------------------------------
-
-fn cmd_recode(opts: &Options) -> i32 {
-    let input_file = match &opts.input_file {
-        Some(path) => path.clone(),
-        None => {
-            eprintln!("oxidelta: recode requires an input file");
-            return 1;
-        }
-    };
-
-    let file = match File::open(&input_file) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("oxidelta: {}: {e}", input_file.display());
-            return 1;
-        }
-    };
-    let mut reader = BufReader::with_capacity(BUF_SIZE, file);
-
-    let output_writer: Box<dyn Write> = match (opts.use_stdout, &opts.output_file) {
-        (true, _) | (_, None) => Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock())),
-        (false, Some(path)) => {
-            if path.exists() && !opts.force {
-                eprintln!(
-                    "oxidelta: output file exists, use -f to overwrite: {}",
-                    path.display()
-                );
-                return 1;
-            }
-            match File::create(path) {
-                Ok(f) => Box::new(BufWriter::with_capacity(BUF_SIZE, f)),
-                Err(e) => {
-                    eprintln!("oxidelta: output file: {}: {e}", path.display());
-                    return 1;
-                }
-            }
-        }
-    };
-
-    let in_hdr = match FileHeader::decode(&mut reader) {
-        Ok(hdr) => hdr,
-        Err(e) => {
-            eprintln!("oxidelta: invalid VCDIFF header: {e}");
-            return 1;
-        }
-    };
-
-    let compress_opts = build_compress_options(opts);
-    let new_secondary = compress_opts.secondary.backend();
-
-    let mut out_hdr = FileHeader::default();
-    if let Some(ref backend) = new_secondary {
-        out_hdr.hdr_ind |= header::VCD_SECONDARY;
-        out_hdr.secondary_id = Some(backend.id());
-    }
-    if opts.use_appheader {
-        if let Some(ref ah) = opts.appheader {
-            out_hdr.hdr_ind |= header::VCD_APPHEADER;
-            out_hdr.app_header = Some(ah.as_bytes().to_vec());
-        } else if let Some(ref orig_ah) = in_hdr.app_header {
-            out_hdr.hdr_ind |= header::VCD_APPHEADER;
-            out_hdr.app_header = Some(orig_ah.clone());
-        }
-    }
-
-    let mut out_writer = output_writer;
-    if let Err(e) = out_hdr.encode(&mut out_writer) {
-        eprintln!("oxidelta: write header: {e}");
-        return 1;
-    }
-
-    let mut window_num: u64 = 0;
-    loop {
-        let wh = match WindowHeader::decode(&mut reader) {
-            Ok(Some(wh)) => wh,
-            Ok(None) => break,
-            Err(e) => {
-                eprintln!("oxidelta: window {window_num}: {e}");
-                return 1;
-            }
-        };
-
-        let mut data_buf = vec![0u8; wh.data_len as usize];
-        let mut inst_buf = vec![0u8; wh.inst_len as usize];
-        let mut addr_buf = vec![0u8; wh.addr_len as usize];
-
-        if let Err(e) = reader.read_exact(&mut data_buf) {
-            eprintln!("oxidelta: window {window_num} data: {e}");
-            return 1;
-        }
-        if let Err(e) = reader.read_exact(&mut inst_buf) {
-            eprintln!("oxidelta: window {window_num} inst: {e}");
-            return 1;
-        }
-        if let Err(e) = reader.read_exact(&mut addr_buf) {
-            eprintln!("oxidelta: window {window_num} addr: {e}");
-            return 1;
-        }
-
-        let (raw_data, raw_inst, raw_addr) = if wh.del_ind != 0 {
-            match crate::compress::secondary::decompress_sections(
-                &data_buf,
-                &inst_buf,
-                &addr_buf,
-                wh.del_ind,
-                in_hdr.secondary_id,
-            ) {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("oxidelta: window {window_num} decompress: {e}");
-                    return 1;
-                }
-            }
-        } else {
-            (data_buf, inst_buf, addr_buf)
-        };
-
-        let (out_data, out_inst, out_addr, new_del_ind) = if let Some(ref backend) = new_secondary {
-            match crate::compress::secondary::compress_sections(
-                backend.as_ref(),
-                &raw_data,
-                &raw_inst,
-                &raw_addr,
-            ) {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("oxidelta: window {window_num} compress: {e}");
-                    return 1;
-                }
-            }
-        } else {
-            (raw_data, raw_inst, raw_addr, 0u8)
-        };
-
-        let mut new_wh = WindowHeader {
-            win_ind: wh.win_ind,
-            copy_window_len: wh.copy_window_len,
-            copy_window_offset: wh.copy_window_offset,
-            enc_len: 0,
-            target_window_len: wh.target_window_len,
-            del_ind: new_del_ind,
-            data_len: out_data.len() as u64,
-            inst_len: out_inst.len() as u64,
-            addr_len: out_addr.len() as u64,
-            adler32: wh.adler32,
-        };
-        new_wh.enc_len = new_wh.compute_enc_len();
-
-        if let Err(e) = new_wh.encode(&mut out_writer) {
-            eprintln!("oxidelta: write window header: {e}");
-            return 1;
-        }
-        if let Err(e) = out_writer.write_all(&out_data) {
-            eprintln!("oxidelta: write data: {e}");
-            return 1;
-        }
-        if let Err(e) = out_writer.write_all(&out_inst) {
-            eprintln!("oxidelta: write inst: {e}");
-            return 1;
-        }
-        if let Err(e) = out_writer.write_all(&out_addr) {
-            eprintln!("oxidelta: write addr: {e}");
-            return 1;
-        }
-
-        window_num += 1;
-    }
-
-    if let Err(e) = out_writer.flush() {
-        eprintln!("oxidelta: flush: {e}");
-        return 1;
-    }
-
-    if opts.verbose > 0 && !opts.quiet {
-        eprintln!("oxidelta: recode: {window_num} windows processed");
-    }
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Merge command
-// ---------------------------------------------------------------------------
-
-fn cmd_merge(opts: &Options) -> i32 {
-    // xdelta3 merge -m 1.vcdiff -m 2.vcdiff 3.vcdiff merged.vcdiff
-    // All -m files + input positional are patches applied in order.
-    // Output is a single merged delta.
-
-    let mut all_patches: Vec<PathBuf> = opts.merge_files.clone();
-    if let Some(ref input) = opts.input_file {
-        all_patches.push(input.clone());
-    }
-
-    if all_patches.len() < 2 {
-        eprintln!("oxidelta: merge requires at least 2 patches (-m file1 ... fileN)");
-        return 1;
-    }
-
-    let output_path = match &opts.output_file {
-        Some(p) => Some(p.clone()),
-        None if opts.use_stdout => None,
-        None => {
-            eprintln!("oxidelta: merge requires an output file");
-            return 1;
-        }
-    };
-
-    // Apply-chain: decode each patch sequentially.
-    let mut current_source: Vec<u8> = Vec::new();
-
-    for (i, patch_path) in all_patches.iter().enumerate() {
-        let delta_data = match std::fs::read(patch_path) {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("oxidelta: merge: {}: {e}", patch_path.display());
-                return 1;
-            }
-        };
-
-        let source_for_decode = if i == 0 {
-            match &opts.source_file {
-                Some(path) => match std::fs::read(path) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        eprintln!("oxidelta: source file: {}: {e}", path.display());
-                        return 1;
-                    }
-                },
-                None => std::mem::take(&mut current_source),
-            }
-        } else {
-            std::mem::take(&mut current_source)
-        };
-
-        match crate::vcdiff::decode_memory(&delta_data, &source_for_decode) {
-            Ok(decoded) => {
-                current_source = decoded;
-            }
-            Err(e) => {
-                eprintln!(
-                    "oxidelta: merge: patch {}: {}: {e}",
-                    i + 1,
-                    patch_path.display()
-                );
-                return 1;
-            }
-        }
-    }
-
-    // Re-encode: original source -> final target = merged delta.
-    let original_source = match &opts.source_file {
-        Some(path) => match std::fs::read(path) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("oxidelta: source file: {}: {e}", path.display());
-                return 1;
-            }
-        },
-        None => Vec::new(),
-    };
-
-    let final_target = &current_source;
-    let compress_opts = build_compress_options(opts);
-
-    let mut delta_output: Vec<u8> = Vec::new();
-    let mut encoder = DeltaEncoder::new(&mut delta_output, &original_source, compress_opts);
-
-    if let Err(e) = encoder.write_target(final_target) {
-        eprintln!("oxidelta: merge: encode error: {e}");
-        return 1;
-    }
-    if let Err(e) = encoder.finish() {
-        eprintln!("oxidelta: merge: encode finish error: {e}");
-        return 1;
-    }
-
-    if let Some(ref path) = output_path {
-        if path.exists() && !opts.force {
-            eprintln!(
-                "oxidelta: output file exists, use -f to overwrite: {}",
-                path.display()
-            );
-            return 1;
-        }
-        if let Err(e) = std::fs::write(path, &delta_output) {
-            eprintln!("oxidelta: merge: write: {e}");
-            return 1;
-        }
-    } else {
-        let stdout = io::stdout();
-        let mut out = stdout.lock();
-        if let Err(e) = out.write_all(&delta_output) {
-            eprintln!("oxidelta: merge: write: {e}");
-            return 1;
-        }
-    }
-
-    if opts.verbose > 0 && !opts.quiet {
-        eprintln!(
-            "oxidelta: merge: {} patches, output {} bytes",
-            all_patches.len(),
-            delta_output.len()
-        );
-    }
-
-    0
-}
-
-// ---------------------------------------------------------------------------
-// Entry point
-// ---------------------------------------------------------------------------
-
-/// Main CLI entry point. Parses arguments via clap, dispatches commands.
-pub fn run() -> ! {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
-        .format_timestamp(None)
-        .format_target(false)
-        .init();
-
-    let cli = Cli::parse();
-    let mut opts = resolve_options(cli);
-
-    // Validate -W against hard max.
-    if opts.input_window_size > XD3_HARDMAXWINSIZE {
-        eprintln!(
-            "oxidelta: -W: window size {} exceeds max {XD3_HARDMAXWINSIZE}",
-            opts.input_window_size
-        );
-        process::exit(1);
-    }
-
-    // Warn if -c overrides output filename.
-    if opts.use_stdout && opts.output_file.is_some() && !opts.quiet {
-        eprintln!(
-            "oxidelta: warning: -c option overrides output filename: {}",
-            opts.output_file.as_ref().unwrap().display()
-        );
-        opts.output_file = None;
-    }
-
-    let exit_code = match opts.command {
-        Command::Encode => cmd_encode(&opts),
-        Command::Decode => cmd_decode(&opts),
-        Command::Config => cmd_config(),
-        Command::PrintHdr | Command::PrintHdrs | Command::PrintDelta => cmd_print(&opts),
-        Command::Recode => cmd_recode(&opts),
-        Command::Merge => cmd_merge(&opts),
-    };
-
-    process::exit(exit_code);
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn parse_opts(args: &[&str]) -> Options {
-        let argv: Vec<String> = std::iter::once("oxidelta".to_string())
-            .chain(args.iter().map(|s| s.to_string()))
-            .collect();
-        let cli = Cli::try_parse_from(argv).expect("cli parse failed");
-        resolve_options(cli)
-    }
-
-    #[test]
-    fn parse_byte_size_suffixes() {
-        assert_eq!(parse_byte_size("1").unwrap(), 1);
-        assert_eq!(parse_byte_size("2K").unwrap(), 2 * 1024);
-        assert_eq!(parse_byte_size("3m").unwrap(), 3 * 1024 * 1024);
-        assert_eq!(parse_byte_size("4G").unwrap(), 4 * 1024 * 1024 * 1024);
-        assert!(parse_byte_size("").is_err());
-    }
-
-    #[test]
-    fn encode_subcommand_maps_correctly() {
-        let opts = parse_opts(&[
-            "encode",
-            "--source",
-            "source.bin",
-            "--level",
-            "9",
-            "--window-size",
-            "8M",
-            "--secondary",
-            "lzma",
-            "in.bin",
-            "out.vcdiff",
-        ]);
-        assert_eq!(opts.command, Command::Encode);
-        assert_eq!(opts.level, 9);
-        assert_eq!(opts.input_window_size, 8 * 1024 * 1024);
-        assert_eq!(
-            opts.source_file.as_deref(),
-            Some(std::path::Path::new("source.bin"))
-        );
-        assert_eq!(opts.input_file, Some(PathBuf::from("in.bin")));
-        assert_eq!(opts.output_file, Some(PathBuf::from("out.vcdiff")));
-        assert!(opts.use_secondary);
-        assert_eq!(opts.secondary_name.as_deref(), Some("lzma"));
-    }
-
-    #[test]
-    fn decode_subcommand_maps_correctly() {
-        let opts = parse_opts(&[
-            "--quiet",
-            "decode",
-            "--source",
-            "source.bin",
-            "--no-checksum",
-            "--check-only",
-            "in.vcdiff",
-            "out.bin",
-        ]);
-        assert_eq!(opts.command, Command::Decode);
-        assert!(opts.no_checksum);
-        assert!(opts.no_output);
-        assert!(opts.quiet);
-        assert_eq!(
-            opts.source_file.as_deref(),
-            Some(std::path::Path::new("source.bin"))
-        );
-        assert_eq!(opts.input_file, Some(PathBuf::from("in.vcdiff")));
-        assert_eq!(opts.output_file, Some(PathBuf::from("out.bin")));
-    }
-
-    #[test]
-    fn global_stdio_and_force_flags() {
-        let opts = parse_opts(&["--force", "encode", "--stdout", "in", "out"]);
-        assert!(opts.use_stdout);
-        assert!(opts.force);
-    }
-
-    #[test]
-    fn verbose_is_capped() {
-        let verbose = parse_opts(&["--verbose", "--verbose", "--verbose", "encode", "in", "out"]);
-        assert_eq!(verbose.verbose, 2);
-    }
-
-    #[test]
-    fn tuning_flags_parse() {
-        let opts = parse_opts(&[
-            "encode",
-            "--source-window-size",
-            "64M",
-            "--window-size",
-            "8M",
-            "--duplicate-window-size",
-            "256K",
-            "--instruction-buffer-size",
-            "32K",
-            "--disable-small-matches",
-            "--no-checksum",
-            "in",
-            "out",
-        ]);
-        assert_eq!(opts.source_window_size, 64 * 1024 * 1024);
-        assert_eq!(opts.input_window_size, 8 * 1024 * 1024);
-        assert_eq!(opts.sprevsz, 256 * 1024);
-        assert_eq!(opts.iopt_size, 32 * 1024);
-        assert!(opts.no_compress);
-        assert!(opts.no_checksum);
-    }
-
-    #[test]
-    fn recode_app_header_flags() {
-        let enabled = parse_opts(&["recode", "--app-header", "hello", "in", "out"]);
-        assert!(enabled.use_appheader);
-        assert_eq!(enabled.appheader.as_deref(), Some("hello"));
-
-        let dropped = parse_opts(&["recode", "--drop-app-header", "in", "out"]);
-        assert!(!dropped.use_appheader);
-        assert!(dropped.appheader.is_none());
-    }
-
-    #[test]
-    fn merge_flags_parse() {
-        let opts = parse_opts(&[
-            "merge",
-            "--patch",
-            "a.vcdiff",
-            "--patch",
-            "b.vcdiff",
-            "c.vcdiff",
-            "out.vcdiff",
-        ]);
-        assert_eq!(opts.command, Command::Merge);
-        assert_eq!(
-            opts.merge_files,
-            vec![PathBuf::from("a.vcdiff"), PathBuf::from("b.vcdiff")]
-        );
-        assert_eq!(opts.input_file, Some(PathBuf::from("c.vcdiff")));
-        assert_eq!(opts.output_file, Some(PathBuf::from("out.vcdiff")));
-    }
-
-    #[test]
-    fn header_commands_map() {
-        assert_eq!(parse_opts(&["header", "in"]).command, Command::PrintHdr);
-        assert_eq!(parse_opts(&["headers", "in"]).command, Command::PrintHdrs);
-        assert_eq!(parse_opts(&["delta", "in"]).command, Command::PrintDelta);
-    }
-
-    #[test]
-    fn config_command_maps() {
-        assert_eq!(parse_opts(&["config"]).command, Command::Config);
-    }
-
-    #[test]
-    fn compress_options_mapping() {
-        let opts = parse_opts(&[
-            "encode",
-            "--level",
-            "6",
-            "--window-size",
-            "1M",
-            "--no-checksum",
-            "--secondary",
-            "none",
-            "in",
-            "out",
-        ]);
-        let c = build_compress_options(&opts);
-        assert_eq!(c.level, 6);
-        assert_eq!(c.window_size, 1024 * 1024);
-        assert!(!c.checksum);
-        assert!(matches!(c.secondary, SecondaryCompression::None));
-    }
-
-    #[test]
-    fn parse_source_and_secondary() {
-        let opts = parse_opts(&[
-            "encode",
-            "--source",
-            "source.bin",
-            "--secondary",
-            "lzma",
-            "in",
-            "out",
-        ]);
-        assert_eq!(
-            opts.source_file.as_deref(),
-            Some(std::path::Path::new("source.bin"))
-        );
-        assert!(opts.use_secondary);
-        assert_eq!(opts.secondary_name.as_deref(), Some("lzma"));
-    }
-}
+// Idiomatic Rust CLI for Oxidelta.
+//
+// Uses explicit subcommands and long-form options while preserving
+// the underlying encode/decode/recode/merge behavior.
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum, ValueHint};
+
+use crate::capabilities::{Capabilities, TUNING_DEFAULTS, capabilities};
+use crate::compress::decoder::{DeltaDecoder, RangeWriter};
+use crate::compress::encoder::{BlockHasher, CompressOptions, DeltaEncoder, EncodeReport};
+use crate::compress::secondary::{
+    SecondaryCompression, SecondaryId, SecondaryRegistry, SectionLimits,
+};
+use crate::io::{hex, parse_sha256};
+use crate::vcdiff::decoder::InstructionIterator;
+#[cfg(any(test, feature = "parallel"))]
+use crate::vcdiff::decoder::window_headers;
+use crate::vcdiff::header::{
+    FileHeader, HeaderBitPolicy, VCD_ADDRCOMP, VCD_ADLER32, VCD_APPHEADER, VCD_CODETABLE,
+    VCD_DATACOMP, VCD_INSTCOMP, VCD_SECONDARY, VCD_SOURCE, VCD_TARGET, WindowHeader,
+};
+use crate::vcdiff::integrity::{self, Integrity};
+use crate::vcdiff::{AppHeaderMap, Instruction};
+
+// ---------------------------------------------------------------------------
+// Constants (matching xdelta3 defaults)
+// ---------------------------------------------------------------------------
+
+const XD3_DEFAULT_LEVEL: u32 = TUNING_DEFAULTS.level;
+const XD3_DEFAULT_WINSIZE: usize = TUNING_DEFAULTS.window_size;
+const XD3_DEFAULT_SRCWINSZ: u64 = TUNING_DEFAULTS.source_window_size;
+const XD3_DEFAULT_IOPT_SIZE: usize = TUNING_DEFAULTS.iopt_size;
+const XD3_DEFAULT_SPREVSZ: usize = TUNING_DEFAULTS.sprevsz;
+const XD3_HARDMAXWINSIZE: usize = TUNING_DEFAULTS.hard_max_window_size;
+
+const BUF_SIZE: usize = 64 * 1024;
+/// File name that stands for stdin (or stdout) in place of a path.
+const STDIO_PATH: &str = "-";
+/// Under `--mmap-source`, regular source files at least this large are
+/// memory-mapped rather than read into memory.
+#[cfg(feature = "mmap")]
+const MMAP_SOURCE_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+// ---------------------------------------------------------------------------
+// Byte size parsing (supports K, M, G suffixes)
+// ---------------------------------------------------------------------------
+
+fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty size string".into());
+    }
+    let (num_part, multiplier) = match s.as_bytes().last() {
+        Some(b'k' | b'K') => (&s[..s.len() - 1], 1024u64),
+        Some(b'm' | b'M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some(b'g' | b'G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1u64),
+    };
+    let num: u64 = num_part
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid size '{s}': {e}"))?;
+    num.checked_mul(multiplier)
+        .ok_or_else(|| format!("size overflow: '{s}'"))
+}
+
+// ---------------------------------------------------------------------------
+// Progress meter
+// ---------------------------------------------------------------------------
+
+/// Minimum time between progress redraws (~10 Hz).
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Single-line stderr meter: input bytes processed, MiB/s and, when the
+/// input size is known, percentage and ETA.
+struct Progress {
+    enabled: bool,
+    label: &'static str,
+    total: Option<u64>,
+    start: Instant,
+    last_draw: Option<Instant>,
+    drawn: bool,
+}
+
+impl Progress {
+    fn new(enabled: bool, label: &'static str, total: Option<u64>) -> Self {
+        Self {
+            enabled,
+            label,
+            total,
+            start: Instant::now(),
+            last_draw: None,
+            drawn: false,
+        }
+    }
+
+    /// Redraw for `done` input bytes, unless the last redraw was too recent.
+    fn update(&mut self, done: u64) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .last_draw
+            .is_some_and(|last| now - last < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(now);
+        let line = progress_line(self.label, done, self.total, now - self.start);
+        eprint!("\r{line}\x1b[K");
+        self.drawn = true;
+    }
+
+    /// Erase the meter so later messages start on a clean line.
+    fn finish(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[K");
+            self.drawn = false;
+        }
+    }
+}
+
+/// Percentage of `total` done, if the total is known.
+fn progress_percent(done: u64, total: Option<u64>) -> Option<f64> {
+    match total? {
+        0 => Some(100.0),
+        total => Some(done.min(total) as f64 * 100.0 / total as f64),
+    }
+}
+
+fn progress_line(label: &str, done: u64, total: Option<u64>, elapsed: Duration) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { done as f64 / secs } else { 0.0 };
+
+    let mut line = format!("oxidelta: {label}: {:.1} MiB", done as f64 / MIB);
+    if let (Some(total), Some(percent)) = (total, progress_percent(done, total)) {
+        line += &format!(" of {:.1} MiB ({percent:.0}%)", total as f64 / MIB);
+    }
+    line += &format!(", {:.1} MiB/s", rate / MIB);
+    if let Some(total) = total
+        && rate > 0.0
+        && done < total
+    {
+        let eta = ((total - done) as f64 / rate).ceil() as u64;
+        line += &format!(", ETA {}:{:02}", eta / 60, eta % 60);
+    }
+    line
+}
+
+/// Reader that counts the bytes taken from it, for decode progress.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Size of the file at `path`, if it is a regular file.
+fn input_len(path: Option<&PathBuf>) -> Option<u64> {
+    let meta = std::fs::metadata(path?).ok()?;
+    meta.is_file().then_some(meta.len())
+}
+
+// ---------------------------------------------------------------------------
+// Clap CLI definition
+// ---------------------------------------------------------------------------
+
+/// VCDIFF (RFC 3284) delta encoder/decoder.
+#[derive(Parser, Debug)]
+#[command(
+    name = "oxidelta",
+    version,
+    about = "VCDIFF delta encoder/decoder",
+    arg_required_else_help = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Cmd,
+
+    /// Force overwrite existing output files.
+    #[arg(short = 'f', long, global = true)]
+    force: bool,
+
+    /// Quiet mode (suppress non-error output).
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Verbose mode (use multiple times for more detail).
+    #[arg(short = 'v', long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Output stats as JSON to stderr (print commands: report as JSON on stdout).
+    #[arg(long = "json", global = true)]
+    json_output: bool,
+
+    /// Show an encode/decode progress meter on stderr (`--progress` alone
+    /// means `always`; `auto` shows it when stderr is a terminal).
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t = ProgressArg::Auto,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always"
+    )]
+    progress: ProgressArg,
+
+    /// Memory-map source files of 16 MiB or more instead of reading them
+    /// into memory. The source must not be modified or truncated while
+    /// oxidelta runs.
+    #[cfg(feature = "mmap")]
+    #[arg(long = "mmap-source", global = true)]
+    mmap_source: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressArg {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Delta encode an input stream.
+    Encode(EncodeArgs),
+    /// Delta decode an input stream.
+    Decode(DecodeArgs),
+    /// Print build/configuration details.
+    Config,
+    /// Print information about the first VCDIFF window.
+    Header(PrintArgs),
+    /// Print information about all VCDIFF windows.
+    Headers(PrintArgs),
+    /// Print entire delta information (headers + instructions).
+    Delta(PrintArgs),
+    /// Re-encode a VCDIFF file with new secondary/app-header settings.
+    Recode(RecodeArgs),
+    /// Merge multiple VCDIFF deltas into one.
+    Merge(MergeArgs),
+    /// Check that a delta applies cleanly, without writing output.
+    Verify(VerifyArgs),
+    /// Delta from OLD to NEW (alias for `encode --source OLD NEW [OUT]`).
+    Diff(DiffArgs),
+    /// Rebuild NEW from OLD and a delta (alias for `decode --source OLD
+    /// DELTA [OUT]`).
+    Patch(PatchArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SecondaryArg {
+    None,
+    Lzma,
+    Zlib,
+    Zstd,
+    Djw,
+    Fgk,
+}
+
+#[derive(Args, Debug)]
+struct EncodeTuningArgs {
+    /// Compression level (0-9).
+    #[arg(long, short = 'l', value_parser = clap::value_parser!(u32).range(0..=9), default_value_t = XD3_DEFAULT_LEVEL)]
+    level: u32,
+
+    /// Input window size (supports K/M/G suffix).
+    #[arg(long = "window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_WINSIZE as u64)]
+    input_window_size: u64,
+
+    /// Source window size (supports K/M/G suffix).
+    #[arg(long = "source-window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_SRCWINSZ)]
+    source_window_size: u64,
+
+    /// Duplicate-window tracking size (supports K/M/G suffix).
+    #[arg(long = "duplicate-window-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_SPREVSZ as u64)]
+    sprevsz: u64,
+
+    /// Instruction optimization buffer size (supports K/M/G suffix).
+    #[arg(long = "instruction-buffer-size", value_parser = parse_byte_size, default_value_t = XD3_DEFAULT_IOPT_SIZE as u64)]
+    iopt_size: u64,
+
+    /// Secondary compressor.
+    #[arg(long, value_enum, default_value_t = SecondaryArg::None)]
+    secondary: SecondaryArg,
+
+    /// Disable small string-matching compression.
+    #[arg(long = "disable-small-matches")]
+    no_compress: bool,
+
+    /// Disable Adler-32 checksums.
+    #[arg(long = "no-checksum")]
+    no_checksum: bool,
+}
+
+#[derive(Args, Debug)]
+struct EncodeArgs {
+    /// Source file to copy from (`-`: stdin; `fd:N`: file descriptor N, on
+    /// Unix).
+    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
+    source: Option<PathBuf>,
+
+    /// Input file (default or `-`: stdin).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
+    input: Option<PathBuf>,
+
+    /// Output file (default or `-`: stdout).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
+    output: Option<PathBuf>,
+
+    /// Write output to stdout.
+    #[arg(short = 'c', long)]
+    stdout: bool,
+
+    /// Check/compute only (do not write output).
+    #[arg(long = "check-only")]
+    no_output: bool,
+
+    /// Fail unless the delta fits in this many bytes (supports K/M/G
+    /// suffix), retrying at higher levels up to 9 before giving up.
+    #[arg(long = "max-delta-size", value_parser = parse_byte_size)]
+    max_delta_size: Option<u64>,
+
+    /// Encode windows in parallel on this many threads (1 = sequential).
+    #[cfg(feature = "parallel")]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "max_delta_size")]
+    threads: Option<u32>,
+
+    /// Write the SHA-256 of every 4 KiB block of the target to this file,
+    /// one hex digest per line.
+    #[arg(long = "block-hashes", value_name = "PATH", value_hint = ValueHint::FilePath)]
+    block_hashes: Option<PathBuf>,
+
+    #[command(flatten)]
+    tuning: EncodeTuningArgs,
+
+    /// Input file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    input_pos: Option<PathBuf>,
+
+    /// Output file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    output_pos: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+    /// Old file (the source); `-` reads stdin.
+    #[arg(value_hint = ValueHint::FilePath)]
+    old: PathBuf,
+
+    /// New file (the target); `-` reads stdin.
+    #[arg(value_hint = ValueHint::FilePath)]
+    new: PathBuf,
+
+    /// Delta file (default or `-`: stdout).
+    #[arg(value_hint = ValueHint::FilePath)]
+    out: Option<PathBuf>,
+
+    /// Encode windows in parallel on this many threads (1 = sequential).
+    #[cfg(feature = "parallel")]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    #[command(flatten)]
+    tuning: EncodeTuningArgs,
+}
+
+impl From<DiffArgs> for EncodeArgs {
+    fn from(args: DiffArgs) -> Self {
+        Self {
+            source: Some(args.old),
+            input: Some(args.new),
+            output: args.out,
+            stdout: false,
+            no_output: false,
+            max_delta_size: None,
+            #[cfg(feature = "parallel")]
+            threads: args.threads,
+            block_hashes: None,
+            tuning: args.tuning,
+            input_pos: None,
+            output_pos: None,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct PatchArgs {
+    /// Old file (the source); `-` reads stdin.
+    #[arg(value_hint = ValueHint::FilePath)]
+    old: PathBuf,
+
+    /// Delta file; `-` reads stdin.
+    #[arg(value_hint = ValueHint::FilePath)]
+    delta: PathBuf,
+
+    /// Output file (default or `-`: stdout).
+    #[arg(value_hint = ValueHint::FilePath)]
+    out: Option<PathBuf>,
+}
+
+impl From<PatchArgs> for DecodeArgs {
+    fn from(args: PatchArgs) -> Self {
+        Self {
+            source: Some(args.old),
+            input: Some(args.delta),
+            output: args.out,
+            stdout: false,
+            no_checksum: false,
+            no_output: false,
+            skip: 0,
+            length: None,
+            input_pos: None,
+            output_pos: None,
+        }
+    }
+}
+
+/// `None` for the `-` placeholder, which names stdin or stdout.
+fn not_stdio(path: PathBuf) -> Option<PathBuf> {
+    (path.as_os_str() != STDIO_PATH).then_some(path)
+}
+
+#[derive(Args, Debug)]
+struct DecodeArgs {
+    /// Source file to copy from (`-`: stdin; `fd:N`: file descriptor N, on
+    /// Unix).
+    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
+    source: Option<PathBuf>,
+
+    /// Input delta file (default or `-`: stdin).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
+    input: Option<PathBuf>,
+
+    /// Output file (default or `-`: stdout).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
+    output: Option<PathBuf>,
+
+    /// Write output to stdout.
+    #[arg(short = 'c', long)]
+    stdout: bool,
+
+    /// Disable Adler-32 verification.
+    #[arg(long = "no-checksum")]
+    no_checksum: bool,
+
+    /// Check/compute only (do not write output).
+    #[arg(long = "check-only")]
+    no_output: bool,
+
+    /// Leave the first N target bytes out of the output (supports K/M/G
+    /// suffix). The whole delta up to the range is still decoded.
+    #[arg(long, value_name = "N", default_value_t = 0, value_parser = parse_byte_size)]
+    skip: u64,
+
+    /// Write at most N target bytes after `--skip` (supports K/M/G suffix).
+    #[arg(long, value_name = "N", value_parser = parse_byte_size)]
+    length: Option<u64>,
+
+    /// Input file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    input_pos: Option<PathBuf>,
+
+    /// Output file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    output_pos: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Source file to copy from.
+    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
+    source: Option<PathBuf>,
+
+    /// Expected SHA-256 of the decoded output (64 hex digits).
+    #[arg(long = "expect-sha256", value_name = "HEX", value_parser = parse_sha256)]
+    expect_sha256: Option<[u8; 32]>,
+
+    /// Expected size of the decoded output (supports K/M/G suffix).
+    #[arg(long = "expect-size", value_name = "BYTES", value_parser = parse_byte_size)]
+    expect_size: Option<u64>,
+
+    /// Input delta file (default: stdin).
+    #[arg(value_hint = ValueHint::FilePath)]
+    input: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct PrintArgs {
+    /// VCDIFF input file.
+    #[arg(value_hint = ValueHint::FilePath)]
+    input: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct RecodeArgs {
+    /// Input VCDIFF file (default or `-`: stdin).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "input_pos")]
+    input: Option<PathBuf>,
+
+    /// Output VCDIFF file (default or `-`: stdout).
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
+    output: Option<PathBuf>,
+
+    /// Write output to stdout.
+    #[arg(short = 'c', long)]
+    stdout: bool,
+
+    /// Secondary compressor.
+    #[arg(long, value_enum, default_value_t = SecondaryArg::None)]
+    secondary: SecondaryArg,
+
+    /// Replace/attach an application header. Repeat as `KEY=VALUE` to
+    /// store key/value metadata (`vcdiff::AppHeaderMap`); a single value
+    /// without `=` is stored verbatim.
+    #[arg(long = "app-header", value_name = "TEXT|KEY=VALUE")]
+    app_header: Vec<String>,
+
+    /// Drop the application header.
+    #[arg(long = "drop-app-header", conflicts_with = "app_header")]
+    drop_app_header: bool,
+
+    /// Decode fully and re-encode with this window size (supports K/M/G
+    /// suffix). The application header is not carried over.
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, conflicts_with = "app_header")]
+    rewindow: Option<u64>,
+
+    /// Source file the delta copies from (needed by `--rewindow`).
+    #[arg(long, short = 's', value_hint = ValueHint::FilePath, requires = "rewindow")]
+    source: Option<PathBuf>,
+
+    /// Input file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    input_pos: Option<PathBuf>,
+
+    /// Output file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    output_pos: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct MergeArgs {
+    /// Source file to copy from.
+    #[arg(long, short = 's', value_hint = ValueHint::FilePath)]
+    source: Option<PathBuf>,
+
+    /// Merge input files (repeat for each patch, in order).
+    #[arg(long = "patch", short = 'p', value_name = "PATCH", value_hint = ValueHint::FilePath, action = ArgAction::Append)]
+    patches: Vec<PathBuf>,
+
+    /// Last patch input file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    last_patch: Option<PathBuf>,
+
+    /// Output file.
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "output_pos")]
+    output: Option<PathBuf>,
+
+    /// Output file (positional form).
+    #[arg(value_hint = ValueHint::FilePath)]
+    output_pos: Option<PathBuf>,
+
+    /// Write output to stdout.
+    #[arg(short = 'c', long)]
+    stdout: bool,
+
+    #[command(flatten)]
+    tuning: EncodeTuningArgs,
+}
+
+// ---------------------------------------------------------------------------
+// Resolved command + options (flattened from Cli)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Encode,
+    Decode,
+    Config,
+    PrintHdr,
+    PrintHdrs,
+    PrintDelta,
+    Recode,
+    Merge,
+    Verify,
+}
+
+#[allow(dead_code)]
+struct Options {
+    command: Command,
+    use_stdout: bool,
+    force: bool,
+    quiet: bool,
+    verbose: u8,
+    level: u32,
+    no_compress: bool,
+    no_checksum: bool,
+    no_output: bool,
+    use_secondary: bool,
+    secondary_name: Option<String>,
+    use_appheader: bool,
+    appheader: Option<Vec<u8>>,
+    source_window_size: u64,
+    input_window_size: usize,
+    iopt_size: usize,
+    sprevsz: usize,
+    source_file: Option<PathBuf>,
+    input_file: Option<PathBuf>,
+    output_file: Option<PathBuf>,
+    merge_files: Vec<PathBuf>,
+    expect_sha256: Option<[u8; 32]>,
+    /// `verify --expect-size`: decoded length to check.
+    expect_size: Option<u64>,
+    max_delta_size: Option<u64>,
+    block_hashes: Option<PathBuf>,
+    /// `decode --skip`: target bytes to leave out of the output.
+    skip: u64,
+    /// `decode --length`: most target bytes to write after `skip`.
+    length: Option<u64>,
+    rewindow: bool,
+    threads: Option<usize>,
+    json_output: bool,
+    /// Draw the progress meter (already off under `--quiet` and `--json`).
+    progress: bool,
+    /// `--mmap-source`: map large source files rather than read them.
+    mmap_source: bool,
+}
+
+fn secondary_name(sec: SecondaryArg) -> Option<String> {
+    let id = match sec {
+        SecondaryArg::None => return None,
+        SecondaryArg::Lzma => SecondaryId::Lzma,
+        SecondaryArg::Zlib => SecondaryId::Zlib,
+        SecondaryArg::Zstd => SecondaryId::Zstd,
+        SecondaryArg::Djw => SecondaryId::Djw,
+        SecondaryArg::Fgk => SecondaryId::Fgk,
+    };
+    Some(id.name().to_string())
+}
+
+/// `recode --app-header` values as header bytes: one plain value verbatim,
+/// otherwise an `AppHeaderMap` of the `KEY=VALUE` pairs (a value without
+/// `=` is a key with an empty value).
+fn app_header_bytes(values: Vec<String>) -> Vec<u8> {
+    if let [value] = &values[..]
+        && !value.contains('=')
+    {
+        return value.as_bytes().to_vec();
+    }
+    values
+        .iter()
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect::<AppHeaderMap>()
+        .to_bytes()
+}
+
+/// `--threads`, when the `parallel` feature provides it.
+fn encode_threads(args: &EncodeArgs) -> Option<usize> {
+    #[cfg(feature = "parallel")]
+    {
+        args.threads.map(|n| n as usize)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        let _ = args;
+        None
+    }
+}
+
+fn resolve_options(cli: Cli) -> Options {
+    let quiet = cli.quiet;
+    let verbose = cli.verbose.min(2);
+    let force = cli.force;
+    let json_output = cli.json_output;
+    let progress = !quiet
+        && !json_output
+        && match cli.progress {
+            ProgressArg::Always => true,
+            ProgressArg::Never => false,
+            ProgressArg::Auto => io::stderr().is_terminal(),
+        };
+    #[cfg(feature = "mmap")]
+    let mmap_source = cli.mmap_source;
+    #[cfg(not(feature = "mmap"))]
+    let mmap_source = false;
+
+    // `diff` and `patch` are spelled-out forms of `encode` and `decode`.
+    let command = match cli.command {
+        Cmd::Diff(args) => Cmd::Encode(args.into()),
+        Cmd::Patch(args) => Cmd::Decode(args.into()),
+        command => command,
+    };
+
+    match command {
+        Cmd::Encode(args) => {
+            let secondary_name = secondary_name(args.tuning.secondary);
+            let threads = encode_threads(&args);
+            Options {
+                command: Command::Encode,
+                use_stdout: args.stdout,
+                force,
+                quiet,
+                verbose,
+                level: args.tuning.level,
+                no_compress: args.tuning.no_compress,
+                no_checksum: args.tuning.no_checksum,
+                no_output: args.no_output,
+                use_secondary: secondary_name.is_some(),
+                secondary_name,
+                use_appheader: true,
+                appheader: None,
+                source_window_size: args.tuning.source_window_size,
+                input_window_size: args.tuning.input_window_size as usize,
+                iopt_size: args.tuning.iopt_size as usize,
+                sprevsz: args.tuning.sprevsz as usize,
+                source_file: args.source,
+                input_file: args.input.or(args.input_pos).and_then(not_stdio),
+                output_file: args.output.or(args.output_pos).and_then(not_stdio),
+                merge_files: Vec::new(),
+                expect_sha256: None,
+                expect_size: None,
+                max_delta_size: args.max_delta_size,
+                block_hashes: args.block_hashes,
+                skip: 0,
+                length: None,
+                rewindow: false,
+                threads,
+                json_output,
+                progress,
+                mmap_source,
+            }
+        }
+        Cmd::Decode(args) => Options {
+            command: Command::Decode,
+            use_stdout: args.stdout,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: args.no_checksum,
+            no_output: args.no_output,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: args.source,
+            input_file: args.input.or(args.input_pos).and_then(not_stdio),
+            output_file: args.output.or(args.output_pos).and_then(not_stdio),
+            merge_files: Vec::new(),
+            expect_sha256: None,
+            expect_size: None,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: args.skip,
+            length: args.length,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Config => Options {
+            command: Command::Config,
+            use_stdout: false,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: false,
+            no_output: false,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: None,
+            input_file: None,
+            output_file: None,
+            merge_files: Vec::new(),
+            expect_sha256: None,
+            expect_size: None,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: 0,
+            length: None,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Header(args) => Options {
+            command: Command::PrintHdr,
+            use_stdout: false,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: false,
+            no_output: false,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: None,
+            input_file: Some(args.input),
+            output_file: None,
+            merge_files: Vec::new(),
+            expect_sha256: None,
+            expect_size: None,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: 0,
+            length: None,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Headers(args) => Options {
+            command: Command::PrintHdrs,
+            use_stdout: false,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: false,
+            no_output: false,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: None,
+            input_file: Some(args.input),
+            output_file: None,
+            merge_files: Vec::new(),
+            expect_sha256: None,
+            expect_size: None,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: 0,
+            length: None,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Delta(args) => Options {
+            command: Command::PrintDelta,
+            use_stdout: false,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: false,
+            no_output: false,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: None,
+            input_file: Some(args.input),
+            output_file: None,
+            merge_files: Vec::new(),
+            expect_sha256: None,
+            expect_size: None,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: 0,
+            length: None,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Recode(args) => {
+            let secondary_name = secondary_name(args.secondary);
+            let (use_appheader, appheader) = if args.drop_app_header {
+                (false, None)
+            } else if !args.app_header.is_empty() {
+                (true, Some(app_header_bytes(args.app_header)))
+            } else {
+                (true, None)
+            };
+            Options {
+                command: Command::Recode,
+                use_stdout: args.stdout,
+                force,
+                quiet,
+                verbose,
+                level: XD3_DEFAULT_LEVEL,
+                no_compress: false,
+                no_checksum: false,
+                no_output: false,
+                use_secondary: secondary_name.is_some(),
+                secondary_name,
+                use_appheader,
+                appheader,
+                source_window_size: XD3_DEFAULT_SRCWINSZ,
+                input_window_size: args
+                    .rewindow
+                    .map_or(XD3_DEFAULT_WINSIZE, |size| size as usize),
+                iopt_size: XD3_DEFAULT_IOPT_SIZE,
+                sprevsz: XD3_DEFAULT_SPREVSZ,
+                source_file: args.source,
+                input_file: args.input.or(args.input_pos).and_then(not_stdio),
+                output_file: args.output.or(args.output_pos).and_then(not_stdio),
+                merge_files: Vec::new(),
+                expect_sha256: None,
+                expect_size: None,
+                max_delta_size: None,
+                block_hashes: None,
+                skip: 0,
+                length: None,
+                rewindow: args.rewindow.is_some(),
+                threads: None,
+                json_output,
+                progress,
+                mmap_source,
+            }
+        }
+        Cmd::Merge(args) => {
+            let secondary_name = secondary_name(args.tuning.secondary);
+            Options {
+                command: Command::Merge,
+                use_stdout: args.stdout,
+                force,
+                quiet,
+                verbose,
+                level: args.tuning.level,
+                no_compress: args.tuning.no_compress,
+                no_checksum: args.tuning.no_checksum,
+                no_output: false,
+                use_secondary: secondary_name.is_some(),
+                secondary_name,
+                use_appheader: true,
+                appheader: None,
+                source_window_size: args.tuning.source_window_size,
+                input_window_size: args.tuning.input_window_size as usize,
+                iopt_size: args.tuning.iopt_size as usize,
+                sprevsz: args.tuning.sprevsz as usize,
+                source_file: args.source,
+                input_file: args.last_patch,
+                output_file: args.output.or(args.output_pos),
+                merge_files: args.patches,
+                expect_sha256: None,
+                expect_size: None,
+                max_delta_size: None,
+                block_hashes: None,
+                skip: 0,
+                length: None,
+                rewindow: false,
+                threads: None,
+                json_output,
+                progress,
+                mmap_source,
+            }
+        }
+        Cmd::Verify(args) => Options {
+            command: Command::Verify,
+            use_stdout: false,
+            force,
+            quiet,
+            verbose,
+            level: XD3_DEFAULT_LEVEL,
+            no_compress: false,
+            no_checksum: false,
+            no_output: true,
+            use_secondary: false,
+            secondary_name: None,
+            use_appheader: true,
+            appheader: None,
+            source_window_size: XD3_DEFAULT_SRCWINSZ,
+            input_window_size: XD3_DEFAULT_WINSIZE,
+            iopt_size: XD3_DEFAULT_IOPT_SIZE,
+            sprevsz: XD3_DEFAULT_SPREVSZ,
+            source_file: args.source,
+            input_file: args.input,
+            output_file: None,
+            merge_files: Vec::new(),
+            expect_sha256: args.expect_sha256,
+            expect_size: args.expect_size,
+            max_delta_size: None,
+            block_hashes: None,
+            skip: 0,
+            length: None,
+            rewindow: false,
+            threads: None,
+            json_output,
+            progress,
+            mmap_source,
+        },
+        Cmd::Diff(_) | Cmd::Patch(_) => unreachable!("aliases are mapped above"),
+    }
+}
+
+/// Prefix of a `--source` that names an inherited file descriptor.
+#[cfg(unix)]
+const FD_SOURCE_PREFIX: &str = "fd:";
+
+/// Where the `--source` of `encode`/`decode` is read from.
+#[derive(Debug, PartialEq, Eq)]
+enum SourceSpec<'a> {
+    /// A file path; `-` is stdin.
+    Path(&'a std::path::Path),
+    /// `fd:N`: file descriptor `N`, inherited from the parent process.
+    #[cfg(unix)]
+    Fd(std::os::fd::RawFd),
+}
+
+/// Interpret a `--source` argument. On Unix `fd:N` names descriptor `N`
+/// (`./fd:3` still names a file); everything else is a path.
+fn parse_source_spec(path: &std::path::Path) -> Result<SourceSpec<'_>, String> {
+    #[cfg(unix)]
+    if let Some(fd) = path.to_str().and_then(|s| s.strip_prefix(FD_SOURCE_PREFIX)) {
+        return match fd.parse() {
+            Ok(fd) if fd >= 0 => Ok(SourceSpec::Fd(fd)),
+            _ => Err(format!("invalid file descriptor {fd:?}")),
+        };
+    }
+    Ok(SourceSpec::Path(path))
+}
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub fn fuzz_try_parse_args(args: &[String]) {
+    let argv: Vec<String> = std::iter::once("oxidelta".to_string())
+        .chain(args.iter().cloned())
+        .collect();
+    if let Ok(cli) = Cli::try_parse_from(argv) {
+        let _ = resolve_options(cli);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Config command
+// ---------------------------------------------------------------------------
+
+fn cmd_config(opts: &Options) -> i32 {
+    let caps = capabilities();
+    if opts.json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&capabilities_json(&caps)).unwrap()
+        );
+        return 0;
+    }
+
+    let version = caps.version;
+    eprintln!("oxidelta version {version} (Rust), Copyright (C) oxidelta contributors");
+    eprintln!("Licensed under the Apache License, Version 2.0");
+
+    let lzma = caps.secondary_lzma as u8;
+    let zlib = caps.secondary_zlib as u8;
+    let zstd = caps.secondary_zstd as u8;
+    let djw = caps.secondary_djw.name();
+    let fgk = caps.secondary_fgk.name();
+    let fgk_encode = caps.secondary_fgk_encode as u8;
+    let adler32 = cfg!(feature = "adler32") as u8;
+    let file_io = cfg!(feature = "file-io") as u8;
+    let parallel = caps.parallel as u8;
+    let match_kernel = caps.match_kernel.name();
+    let ptr_size = std::mem::size_of::<*const ()>();
+
+    eprintln!("SECONDARY_LZMA={lzma}");
+    eprintln!("SECONDARY_ZLIB={zlib}");
+    eprintln!("SECONDARY_ZSTD={zstd}");
+    eprintln!("SECONDARY_DJW={djw}");
+    eprintln!("SECONDARY_FGK={fgk}");
+    eprintln!("SECONDARY_FGK_ENCODE={fgk_encode}");
+    eprintln!("ADLER32={adler32}");
+    eprintln!("FILE_IO={file_io}");
+    eprintln!("PARALLEL={parallel}");
+    eprintln!("MATCH_KERNEL={match_kernel}");
+    eprintln!("XD3_DEFAULT_LEVEL={XD3_DEFAULT_LEVEL}");
+    eprintln!("XD3_DEFAULT_IOPT_SIZE={XD3_DEFAULT_IOPT_SIZE}");
+    eprintln!("XD3_DEFAULT_SPREVSZ={XD3_DEFAULT_SPREVSZ}");
+    eprintln!("XD3_DEFAULT_SRCWINSZ={XD3_DEFAULT_SRCWINSZ}");
+    eprintln!("XD3_DEFAULT_WINSIZE={XD3_DEFAULT_WINSIZE}");
+    eprintln!("XD3_HARDMAXWINSIZE={XD3_HARDMAXWINSIZE}");
+    eprintln!("sizeof(usize)={ptr_size}");
+    if zlib != 0 || zstd != 0 {
+        eprintln!(
+            "warning: zlib (ID {}) and zstd (ID {}) secondary compression are oxidelta \
+             extensions; xdelta3 cannot decode deltas that use them",
+            SecondaryId::Zlib.id(),
+            SecondaryId::Zstd.id()
+        );
+    }
+
+    0
+}
+
+/// `config --json`: the [`Capabilities`] report as an object.
+fn capabilities_json(caps: &Capabilities) -> serde_json::Value {
+    let d = &caps.defaults;
+    serde_json::json!({
+        "version": caps.version,
+        "secondary": {
+            "lzma": caps.secondary_lzma,
+            "zlib": caps.secondary_zlib,
+            "zstd": caps.secondary_zstd,
+            "djw": caps.secondary_djw.name(),
+            "fgk": caps.secondary_fgk.name(),
+            "fgk_encode": caps.secondary_fgk_encode,
+        },
+        "adler32": caps.adler32.name(),
+        "parallel": caps.parallel,
+        "match_kernel": caps.match_kernel.name(),
+        "defaults": {
+            "level": d.level,
+            "window_size": d.window_size,
+            "source_window_size": d.source_window_size,
+            "iopt_size": d.iopt_size,
+            "sprevsz": d.sprevsz,
+            "hard_max_window_size": d.hard_max_window_size,
+        },
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Build CompressOptions from CLI options
+// ---------------------------------------------------------------------------
+
+fn build_compress_options(opts: &Options) -> CompressOptions {
+    let secondary = if opts.use_secondary {
+        match opts.secondary_name.as_deref() {
+            Some(name) => match SecondaryId::from_name(name) {
+                #[cfg(feature = "lzma-secondary")]
+                Some(SecondaryId::Lzma) => SecondaryCompression::Lzma,
+                #[cfg(feature = "zlib-secondary")]
+                Some(SecondaryId::Zlib) => SecondaryCompression::Zlib { level: opts.level },
+                #[cfg(feature = "zstd-secondary")]
+                Some(SecondaryId::Zstd) => SecondaryCompression::Zstd {
+                    level: opts.level as i32,
+                },
+                #[cfg(feature = "fgk-encode")]
+                Some(SecondaryId::Fgk) => SecondaryCompression::Fgk,
+                #[cfg(not(feature = "fgk-encode"))]
+                Some(SecondaryId::Fgk) => {
+                    eprintln!(
+                        "oxidelta: warning: FGK encoding is not yet verified against xdelta3 \
+                         and needs the `fgk-encode` feature, using none"
+                    );
+                    SecondaryCompression::None
+                }
+                _ => {
+                    eprintln!(
+                        "oxidelta: warning: unknown secondary compressor '{name}', using none"
+                    );
+                    SecondaryCompression::None
+                }
+            },
+            None => {
+                #[cfg(feature = "lzma-secondary")]
+                {
+                    SecondaryCompression::Lzma
+                }
+                #[cfg(not(feature = "lzma-secondary"))]
+                {
+                    SecondaryCompression::None
+                }
+            }
+        }
+    } else {
+        SecondaryCompression::None
+    };
+
+    CompressOptions {
+        level: opts.level,
+        window_size: opts.input_window_size,
+        checksum: !opts.no_checksum,
+        secondary,
+        source_window_size: Some(opts.source_window_size),
+        iopt_size: opts.iopt_size,
+        ..Default::default()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Source file loading
+// ---------------------------------------------------------------------------
+
+/// Source bytes for encode/decode: read into memory, or mapped when large
+/// and `--mmap-source` is given.
+enum SourceBytes {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(crate::io::MmapSource),
+}
+
+impl std::ops::Deref for SourceBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            SourceBytes::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            SourceBytes::Mapped(map) => map,
+        }
+    }
+}
+
+/// Load the `--source` file. With `mmap` (`--mmap-source`), regular files
+/// above `MMAP_SOURCE_THRESHOLD` are memory-mapped instead of read.
+fn load_source(path: &std::path::Path, mmap: bool) -> io::Result<SourceBytes> {
+    let path = match parse_source_spec(path) {
+        Ok(SourceSpec::Path(path)) => path,
+        #[cfg(unix)]
+        Ok(SourceSpec::Fd(fd)) => return load_source_fd(fd, mmap),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+    };
+    if path.as_os_str() == STDIO_PATH {
+        let mut source = Vec::new();
+        io::stdin().lock().read_to_end(&mut source)?;
+        return Ok(SourceBytes::Owned(source));
+    }
+    #[cfg(feature = "mmap")]
+    if mmap {
+        let file = File::open(path)?;
+        return map_or_read(file, 0);
+    }
+    #[cfg(not(feature = "mmap"))]
+    let _ = mmap;
+    std::fs::read(path).map(SourceBytes::Owned)
+}
+
+/// Load the source from inherited descriptor `fd` (`--source fd:N`),
+/// memory-mapping it under `mmap` as [`load_source`] does. Either way the
+/// source starts at the descriptor's current offset, as it would for `cat`;
+/// pipes and sockets are read to their end. The descriptor itself is left
+/// open.
+#[cfg(unix)]
+fn load_source_fd(fd: std::os::fd::RawFd, mmap: bool) -> io::Result<SourceBytes> {
+    // `BorrowedFd` requires an open descriptor, and `N` is whatever the
+    // user typed, so check it first.
+    // SAFETY: F_GETFD only reads the descriptor flags; it fails with EBADF
+    // for a number that is not open.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is open (checked above), and this process never closes
+    // descriptors it did not open, so it stays open for the `dup` below.
+    let owned = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+    let mut file = File::from(owned);
+    #[cfg(feature = "mmap")]
+    if mmap {
+        let offset = std::io::Seek::stream_position(&mut file).unwrap_or(0);
+        return map_or_read(file, offset);
+    }
+    #[cfg(not(feature = "mmap"))]
+    let _ = mmap;
+    let mut source = Vec::new();
+    file.read_to_end(&mut source)?;
+    Ok(SourceBytes::Owned(source))
+}
+
+/// `file` from `offset` on: mapped if it is a regular file with at least
+/// `MMAP_SOURCE_THRESHOLD` bytes left, otherwise read from its current
+/// position.
+#[cfg(feature = "mmap")]
+fn map_or_read(mut file: File, offset: u64) -> io::Result<SourceBytes> {
+    let meta = file.metadata()?;
+    if meta.is_file() && meta.len().saturating_sub(offset) >= MMAP_SOURCE_THRESHOLD {
+        // SAFETY: `--mmap-source` is the user's promise, stated in its help,
+        // that the source is not modified or truncated while we run.
+        let map = unsafe { crate::io::MmapSource::from_file_at(&file, offset) }?;
+        return Ok(SourceBytes::Mapped(map));
+    }
+    let mut source = Vec::new();
+    file.read_to_end(&mut source)?;
+    Ok(SourceBytes::Owned(source))
+}
+
+/// Report (and return true) when both the source and the input would be
+/// read from stdin.
+fn source_and_input_on_stdin(opts: &Options) -> bool {
+    let conflict = opts.input_file.is_none()
+        && opts
+            .source_file
+            .as_ref()
+            .is_some_and(|path| match parse_source_spec(path) {
+                Ok(SourceSpec::Path(path)) => path.as_os_str() == STDIO_PATH,
+                #[cfg(unix)]
+                Ok(SourceSpec::Fd(fd)) => fd == 0,
+                Err(_) => false,
+            });
+    if conflict {
+        eprintln!("oxidelta: the source and the input cannot both be read from stdin");
+    }
+    conflict
+}
+
+// ---------------------------------------------------------------------------
+// Encode command
+// ---------------------------------------------------------------------------
+
+fn cmd_encode(opts: &Options) -> i32 {
+    if source_and_input_on_stdin(opts) {
+        return 1;
+    }
+    let compress_opts = build_compress_options(opts);
+
+    // Read (or map) the source file, if any.
+    let source = match &opts.source_file {
+        Some(path) => match load_source(path, opts.mmap_source) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("oxidelta: source file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => SourceBytes::Owned(Vec::new()),
+    };
+
+    // Open input (target): file or stdin.
+    let target_reader: Box<dyn Read> = match &opts.input_file {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
+            Err(e) => {
+                eprintln!("oxidelta: input file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    if let Some(path) = &opts.block_hashes
+        && path.exists()
+        && !opts.force
+    {
+        eprintln!(
+            "oxidelta: block hash file exists, use -f to overwrite: {}",
+            path.display()
+        );
+        return 1;
+    }
+
+    // Open output: file or stdout. Under `--max-delta-size` the file is only
+    // created once a delta fits, so a missed budget leaves it untouched.
+    let output_path = opts.output_file.as_ref().filter(|_| !opts.use_stdout);
+    if let Some(path) = output_path
+        && path.exists()
+        && !opts.force
+    {
+        eprintln!(
+            "oxidelta: output file exists, use -f to overwrite: {}",
+            path.display()
+        );
+        return 1;
+    }
+    let open_output = || -> Result<Box<dyn Write>, String> {
+        Ok(match output_path {
+            None => Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock())),
+            Some(path) => {
+                let f = File::create(path)
+                    .map_err(|e| format!("output file: {}: {e}", path.display()))?;
+                Box::new(BufWriter::with_capacity(BUF_SIZE, f))
+            }
+        })
+    };
+
+    if opts.no_output {
+        let mut reader = target_reader;
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut total = 0u64;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => total += n as u64,
+                Err(e) => {
+                    eprintln!("oxidelta: read error: {e}");
+                    return 1;
+                }
+            }
+        }
+        if !opts.quiet {
+            eprintln!("oxidelta: input size: {total}");
+        }
+        return 0;
+    }
+
+    let encoded = match (opts.max_delta_size, opts.threads) {
+        (Some(budget), _) => {
+            let verbose = opts.verbose > 0 && !opts.quiet;
+            encode_budgeted(
+                &source,
+                target_reader,
+                open_output,
+                compress_opts,
+                budget,
+                verbose,
+                opts.block_hashes.is_some(),
+            )
+        }
+        #[cfg(feature = "parallel")]
+        (None, Some(threads)) => open_output().and_then(|output_writer| {
+            encode_threaded(
+                &source,
+                target_reader,
+                output_writer,
+                compress_opts,
+                threads,
+                opts.block_hashes.is_some(),
+            )
+        }),
+        _ => open_output().and_then(|output_writer| {
+            let total = input_len(opts.input_file.as_ref());
+            let mut progress = Progress::new(opts.progress, "encode", total);
+            let encoded = encode_streaming(
+                &source,
+                target_reader,
+                output_writer,
+                compress_opts,
+                opts.block_hashes.is_some(),
+                &mut progress,
+            );
+            progress.finish();
+            encoded
+        }),
+    };
+    let EncodeSummary {
+        report,
+        level,
+        block_hashes,
+    } = match encoded {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("oxidelta: {e}");
+            return 1;
+        }
+    };
+
+    if let Some(path) = &opts.block_hashes
+        && let Err(e) = write_block_hashes(path, &block_hashes)
+    {
+        eprintln!("oxidelta: block hashes: {}: {e}", path.display());
+        return 1;
+    }
+
+    let stats = &report.stats;
+    if opts.verbose > 0 && !opts.quiet {
+        let source_size = source.len() as u64;
+        eprintln!(
+            "oxidelta: encoder: source size: {source_size}, input size: {}, \
+             windows: {}",
+            report.target_bytes, report.windows
+        );
+        if opts.verbose > 1 {
+            eprintln!(
+                "oxidelta: matches: {} source copies ({} bytes), {} target copies ({} bytes), \
+                 {} runs ({} bytes), {} adds ({} bytes); lazy hits: {}, chain walks: {}",
+                stats.source_copies,
+                stats.bytes_copied_source,
+                stats.target_copies,
+                stats.bytes_copied_target,
+                stats.runs,
+                stats.bytes_run,
+                stats.adds,
+                stats.bytes_added,
+                stats.lazy_hits,
+                stats.chain_walks,
+            );
+            eprintln!(
+                "oxidelta: hash tables: source {:.1}% full, {:.1}% collisions; \
+                 target {:.1}% full, {:.1}% collisions",
+                stats.large_table_occupancy() * 100.0,
+                stats.large_table_collision_rate() * 100.0,
+                stats.small_table_occupancy() * 100.0,
+                stats.small_table_collision_rate() * 100.0,
+            );
+        }
+    }
+
+    if opts.json_output {
+        let source_size = source.len() as u64;
+        let json = serde_json::json!({
+            "command": "encode",
+            "source_size": source_size,
+            "input_size": report.target_bytes,
+            "output_size": report.delta_bytes,
+            "ratio": report.ratio(),
+            "windows": report.windows,
+            "level": level,
+            "store_fallback": report.store_fallback,
+            "sections": {
+                "data": report.sections.data,
+                "inst": report.sections.inst,
+                "addr": report.sections.addr,
+            },
+            "matches": {
+                "source_copies": stats.source_copies,
+                "target_copies": stats.target_copies,
+                "runs": stats.runs,
+                "adds": stats.adds,
+                "bytes_copied_source": stats.bytes_copied_source,
+                "bytes_copied_target": stats.bytes_copied_target,
+                "bytes_run": stats.bytes_run,
+                "bytes_added": stats.bytes_added,
+            },
+        });
+        eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
+    }
+
+    0
+}
+
+/// Outcome of an encode, for the `-v` and `--json` reports.
+#[derive(Debug)]
+struct EncodeSummary {
+    report: EncodeReport,
+    level: u32,
+    /// Target block digests, when `--block-hashes` asked for them.
+    block_hashes: Vec<[u8; 32]>,
+}
+
+/// Encode `target_reader` window by window straight into `writer`.
+fn encode_streaming(
+    source: &[u8],
+    mut reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+    compress_opts: CompressOptions,
+    block_hashes: bool,
+    progress: &mut Progress,
+) -> Result<EncodeSummary, String> {
+    let level = compress_opts.level;
+    let mut encoder = DeltaEncoder::new(writer, source, compress_opts);
+    if block_hashes {
+        encoder
+            .enable_block_hashes()
+            .map_err(|e| format!("encode error: {e}"))?;
+    }
+    let mut buf = vec![0u8; BUF_SIZE];
+
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                encoder
+                    .write_target(&buf[..n])
+                    .map_err(|e| format!("encode error: {e}"))?;
+                progress.update(encoder.bytes_in());
+            }
+            Err(e) => return Err(format!("read error: {e}")),
+        }
+    }
+
+    let (mut writer, finished, report) = encoder
+        .finish_with_report()
+        .map_err(|e| format!("encode finish error: {e}"))?;
+    writer
+        .flush()
+        .map_err(|e| format!("write flush error: {e}"))?;
+
+    Ok(EncodeSummary {
+        report,
+        level,
+        block_hashes: finished.block_hashes,
+    })
+}
+
+/// Encode under `--threads`: read the whole target, then encode its windows
+/// on a pool of `threads` threads.
+#[cfg(feature = "parallel")]
+fn encode_threaded(
+    source: &[u8],
+    mut reader: Box<dyn Read>,
+    mut writer: Box<dyn Write>,
+    compress_opts: CompressOptions,
+    threads: usize,
+    block_hashes: bool,
+) -> Result<EncodeSummary, String> {
+    let mut target = Vec::new();
+    reader
+        .read_to_end(&mut target)
+        .map_err(|e| format!("read error: {e}"))?;
+
+    let level = compress_opts.level;
+    let delta = encode_with_threads(source, &target, compress_opts, threads)?;
+    writer
+        .write_all(&delta)
+        .and_then(|()| writer.flush())
+        .map_err(|e| format!("write error: {e}"))?;
+    Ok(EncodeSummary {
+        // The parallel encoder does not report match statistics or sections.
+        report: EncodeReport {
+            windows: count_windows(&delta)?,
+            target_bytes: target.len() as u64,
+            delta_bytes: delta.len() as u64,
+            ..Default::default()
+        },
+        level,
+        block_hashes: if block_hashes {
+            hash_blocks(&target)
+        } else {
+            Vec::new()
+        },
+    })
+}
+
+/// `encode_all_parallel` on a scoped pool of `threads` threads, or the
+/// sequential `encode_all` for a single thread.
+#[cfg(feature = "parallel")]
+fn encode_with_threads(
+    source: &[u8],
+    target: &[u8],
+    opts: CompressOptions,
+    threads: usize,
+) -> Result<Vec<u8>, String> {
+    use crate::compress::encoder::{encode_all, encode_all_parallel};
+
+    let encoded = if threads <= 1 {
+        encode_all(Vec::new(), source, target, opts)
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| format!("thread pool: {e}"))?;
+        pool.install(|| encode_all_parallel(Vec::new(), source, target, opts))
+    };
+    encoded.map_err(|e| format!("encode error: {e}"))
+}
+
+/// Encode under `--max-delta-size`: read the whole target, then write the
+/// first delta that fits the budget.
+///
+/// The output is opened with `open_output` only after a delta fits.
+fn encode_budgeted(
+    source: &[u8],
+    mut reader: Box<dyn Read>,
+    open_output: impl FnOnce() -> Result<Box<dyn Write>, String>,
+    compress_opts: CompressOptions,
+    budget: u64,
+    verbose: bool,
+    block_hashes: bool,
+) -> Result<EncodeSummary, String> {
+    let mut target = Vec::new();
+    reader
+        .read_to_end(&mut target)
+        .map_err(|e| format!("read error: {e}"))?;
+
+    let (delta, mut summary) =
+        encode_within_budget(source, &target, compress_opts, budget, verbose)?;
+    let mut writer = open_output()?;
+    writer
+        .write_all(&delta)
+        .and_then(|()| writer.flush())
+        .map_err(|e| format!("write error: {e}"))?;
+    if block_hashes {
+        summary.block_hashes = hash_blocks(&target);
+    }
+    Ok(summary)
+}
+
+/// Encode `target` at `opts.level`, then at each higher level up to 9, until
+/// the delta fits in `budget` bytes.
+///
+/// Each attempt is buffered in memory, so nothing reaches the output until
+/// one fits.
+fn encode_within_budget(
+    source: &[u8],
+    target: &[u8],
+    opts: CompressOptions,
+    budget: u64,
+    verbose: bool,
+) -> Result<(Vec<u8>, EncodeSummary), String> {
+    let mut smallest = u64::MAX;
+    for level in opts.level..=9 {
+        let attempt = CompressOptions {
+            level,
+            window_size: opts.window_size_for(target.len()),
+            ..opts.clone()
+        };
+        let mut encoder = DeltaEncoder::new(Vec::new(), source, attempt);
+        encoder
+            .write_target(target)
+            .map_err(|e| format!("encode error: {e}"))?;
+        let (delta, finished, report) = encoder
+            .finish_with_report()
+            .map_err(|e| format!("encode finish error: {e}"))?;
+
+        let size = delta.len() as u64;
+        if size <= budget {
+            let summary = EncodeSummary {
+                report,
+                level,
+                block_hashes: finished.block_hashes,
+            };
+            return Ok((delta, summary));
+        }
+        if verbose {
+            eprintln!("oxidelta: level {level}: delta is {size} bytes, over budget of {budget}");
+        }
+        smallest = smallest.min(size);
+    }
+    Err(format!(
+        "delta exceeds --max-delta-size {budget} even at level 9 (smallest: {smallest} bytes)"
+    ))
+}
+
+/// Block digests of a target already in memory, as `DeltaEncoder` computes
+/// them while streaming.
+fn hash_blocks(target: &[u8]) -> Vec<[u8; 32]> {
+    let mut hasher = BlockHasher::default();
+    hasher.update(target);
+    hasher.finish()
+}
+
+/// Write the `--block-hashes` sidecar: one hex digest per line, in block
+/// order.
+fn write_block_hashes(path: &std::path::Path, hashes: &[[u8; 32]]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for digest in hashes {
+        writeln!(out, "{}", hex(digest))?;
+    }
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// Decode command
+// ---------------------------------------------------------------------------
+
+fn cmd_decode(opts: &Options) -> i32 {
+    if source_and_input_on_stdin(opts) {
+        return 1;
+    }
+    let source = match &opts.source_file {
+        Some(path) => match load_source(path, opts.mmap_source) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("oxidelta: source file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => SourceBytes::Owned(Vec::new()),
+    };
+
+    let delta_reader: Box<dyn Read> = match &opts.input_file {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
+            Err(e) => {
+                eprintln!("oxidelta: input file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let mut output_writer: Box<dyn Write> = if opts.no_output {
+        Box::new(io::sink())
+    } else if opts.use_stdout || opts.output_file.is_none() {
+        Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock()))
+    } else {
+        let path = opts.output_file.as_ref().unwrap();
+        if path.exists() && !opts.force {
+            eprintln!(
+                "oxidelta: output file exists, use -f to overwrite: {}",
+                path.display()
+            );
+            return 1;
+        }
+        match File::create(path) {
+            Ok(f) => Box::new(BufWriter::with_capacity(BUF_SIZE, f)),
+            Err(e) => {
+                eprintln!("oxidelta: output file: {}: {e}", path.display());
+                return 1;
+            }
+        }
+    };
+
+    let consumed = Rc::new(Cell::new(0));
+    let delta_reader = CountingReader {
+        inner: delta_reader,
+        count: consumed.clone(),
+    };
+    let mut progress = Progress::new(opts.progress, "decode", input_len(opts.input_file.as_ref()));
+
+    let verify_checksum = !opts.no_checksum;
+    let mut decoder = DeltaDecoder::with_checksum(delta_reader, verify_checksum);
+    let mut src: &[u8] = &source;
+
+    // Only `--skip`/`--length` bytes reach the output. Decoding stops once
+    // they are written, unless `--check-only` asks for the whole stream.
+    let end = opts
+        .length
+        .map_or(u64::MAX, |len| opts.skip.saturating_add(len));
+    let mut range = RangeWriter::new(&mut output_writer, 0, opts.skip, end);
+    let decoded = loop {
+        if !opts.no_output && decoder.bytes_decoded() >= end {
+            break Ok(decoder.bytes_decoded());
+        }
+        match decoder.decode_window_to(&mut src, &mut range) {
+            Ok(Some(_)) => progress.update(consumed.get()),
+            Ok(None) => break Ok(decoder.bytes_decoded()),
+            Err(e) => break Err(e),
+        }
+    };
+    progress.finish();
+
+    match decoded {
+        Ok(total) => {
+            if let Err(e) = output_writer.flush() {
+                eprintln!("oxidelta: write flush error: {e}");
+                return 1;
+            }
+            if opts.verbose > 0 && !opts.quiet {
+                let windows = decoder.windows_decoded();
+                eprintln!("oxidelta: decoder: output size: {total}, windows: {windows}");
+            }
+            if opts.json_output {
+                let window_count = decoder.windows_decoded();
+                let windows: Vec<_> = decoder
+                    .window_report()
+                    .iter()
+                    .map(|w| {
+                        serde_json::json!({
+                            "target_len": w.target_len,
+                            "data_len": w.data_len,
+                            "inst_len": w.inst_len,
+                            "addr_len": w.addr_len,
+                            "checksum": w.had_checksum,
+                            "secondary": w.secondary,
+                        })
+                    })
+                    .collect();
+                let json = serde_json::json!({
+                    "command": "decode",
+                    "output_size": total,
+                    "window_count": window_count,
+                    "windows": windows,
+                });
+                eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
+            }
+        }
+        Err(e) => {
+            eprintln!("oxidelta: decode error: {e}");
+            return 1;
+        }
+    }
+
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Verify command
+// ---------------------------------------------------------------------------
+
+/// Output sink for `verify`: counts and hashes bytes, stores nothing.
+struct VerifySink {
+    hasher: sha2::Sha256,
+}
+
+impl Write for VerifySink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        use sha2::Digest;
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn cmd_verify(opts: &Options) -> i32 {
+    use sha2::Digest;
+
+    let source = match &opts.source_file {
+        Some(path) => match load_source(path, opts.mmap_source) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("oxidelta: source file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => SourceBytes::Owned(Vec::new()),
+    };
+
+    let delta_reader: Box<dyn Read> = match &opts.input_file {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
+            Err(e) => {
+                eprintln!("oxidelta: input file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let mut sink = VerifySink {
+        hasher: sha2::Sha256::new(),
+    };
+    let mut decoder = DeltaDecoder::with_checksum(delta_reader, true);
+    let mut src: &[u8] = &source;
+
+    let total = match decoder.decode_to(&mut src, &mut sink) {
+        Ok(total) => total,
+        Err(e) => {
+            eprintln!(
+                "oxidelta: verify: window {}: {e}",
+                decoder.windows_decoded()
+            );
+            return 1;
+        }
+    };
+    let windows = decoder.windows_decoded();
+    if let Some(expected) = opts.expect_size
+        && expected != total
+    {
+        eprintln!("oxidelta: verify: output size mismatch: expected {expected} bytes, got {total}");
+        return 1;
+    }
+
+    let digest: [u8; 32] = sink.hasher.finalize().into();
+    if let Some(expected) = opts.expect_sha256
+        && expected != digest
+    {
+        eprintln!(
+            "oxidelta: verify: SHA-256 mismatch: expected {}, got {}",
+            hex(&expected),
+            hex(&digest)
+        );
+        return 1;
+    }
+
+    if opts.json_output {
+        let json = serde_json::json!({
+            "command": "verify",
+            "output_size": total,
+            "window_count": windows,
+            "sha256": hex(&digest),
+        });
+        eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
+    } else if !opts.quiet {
+        eprintln!("oxidelta: verify: OK, output size: {total}, windows: {windows}");
+        if opts.verbose > 0 {
+            eprintln!("oxidelta: verify: sha256: {}", hex(&digest));
+        }
+    }
+
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Print commands (printhdr, printhdrs, printdelta)
+// ---------------------------------------------------------------------------
+
+fn cmd_print(opts: &Options) -> i32 {
+    let input_file = match &opts.input_file {
+        Some(path) => path.clone(),
+        None => {
+            eprintln!("oxidelta: print commands require an input file");
+            return 1;
+        }
+    };
+
+    let file = match File::open(&input_file) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("oxidelta: {}: {e}", input_file.display());
+            return 1;
+        }
+    };
+    let mut reader = BufReader::with_capacity(BUF_SIZE, file);
+
+    if opts.json_output {
+        return match print_json(&mut reader, opts.command) {
+            Ok(json) => {
+                println!("{}", serde_json::to_string_pretty(&json).unwrap());
+                0
+            }
+            Err(e) => {
+                eprintln!("oxidelta: {e}");
+                1
+            }
+        };
+    }
+
+    let file_hdr = match FileHeader::decode(&mut reader) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("oxidelta: invalid VCDIFF header: {e}");
+            return 1;
+        }
+    };
+
+    if let Err(e) = write_file_header(&mut io::stdout(), &file_hdr, &SecondaryRegistry::new()) {
+        eprintln!("oxidelta: {e}");
+        return 1;
+    }
+
+    let mut window_num: u64 = 0;
+    let mut target_offset: u64 = 0;
+
+    loop {
+        let wh = match WindowHeader::decode(&mut reader) {
+            Ok(Some(wh)) => wh,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("oxidelta: window {window_num}: {e}");
+                return 1;
+            }
+        };
+
+        if window_num > 0 {
+            println!();
+        }
+
+        println!("VCDIFF window number:         {window_num}");
+
+        print!("VCDIFF window indicator:      ");
+        print_flags(&window_flag_names(wh.win_ind));
+
+        if let Some(cksum) = wh.adler32 {
+            println!("VCDIFF adler32 checksum:      {cksum:08X}");
+        }
+
+        if wh.del_ind != 0 {
+            print!("VCDIFF delta indicator:       ");
+            print_flags(&delta_flag_names(wh.del_ind));
+        }
+
+        if target_offset > 0 {
+            println!("VCDIFF window at offset:      {target_offset}");
+        }
+
+        if wh.has_source() || wh.has_target() {
+            println!("VCDIFF copy window length:    {}", wh.copy_window_len);
+            println!("VCDIFF copy window offset:    {}", wh.copy_window_offset);
+        }
+
+        println!("VCDIFF delta encoding length: {}", wh.enc_len);
+        println!("VCDIFF target window length:  {}", wh.target_window_len);
+        println!("VCDIFF data section length:   {}", wh.data_len);
+        println!("VCDIFF inst section length:   {}", wh.inst_len);
+        println!("VCDIFF addr section length:   {}", wh.addr_len);
+
+        if opts.command == Command::PrintDelta {
+            let instructions =
+                match read_window_instructions(&mut reader, &wh, &file_hdr, window_num) {
+                    Ok(insts) => insts,
+                    Err(e) => {
+                        eprintln!("oxidelta: {e}");
+                        return 1;
+                    }
+                };
+
+            println!("  Offset Code Type1 Size1  @Addr1 + Type2 Size2 @Addr2");
+            let copy_window_len = copy_window_len(&wh);
+            let mut offset = target_offset;
+            for inst in instructions {
+                match inst {
+                    Instruction::Add { len } => {
+                        println!("  {offset:06}     ADD  {len:6}");
+                        offset += len as u64;
+                    }
+                    Instruction::Copy { len, addr, .. } => {
+                        let (space, addr) = copy_address(&wh, copy_window_len, addr);
+                        println!("  {offset:06}     CPY  {len:6} {space}@{addr:<6}");
+                        offset += len as u64;
+                    }
+                    Instruction::Run { len } => {
+                        println!("  {offset:06}     RUN  {len:6}");
+                        offset += len as u64;
+                    }
+                }
+            }
+        } else if let Err(e) = skip_window_sections(&mut reader, &wh) {
+            eprintln!("oxidelta: window {window_num}: {e}");
+            return 1;
+        }
+
+        target_offset += wh.target_window_len;
+        window_num += 1;
+
+        if opts.command == Command::PrintHdr {
+            break;
+        }
+    }
+
+    0
+}
+
+/// Resolve a COPY address as `delta` prints it: `('S', offset)` into the
+/// source file or `('T', offset)` into the target window.
+fn copy_address(wh: &WindowHeader, copy_window_len: u64, addr: u64) -> (char, u64) {
+    if addr >= copy_window_len {
+        ('T', addr - copy_window_len)
+    } else {
+        ('S', wh.copy_window_offset + addr)
+    }
+}
+
+/// Build the `--json` report for a print command.
+///
+/// The report holds the file header and one object per window (only the
+/// first for `header`); `delta` also lists each window's instructions as
+/// `{ offset, op, len }` objects, where `op` is `ADD`, `COPY` or `RUN` and
+/// a COPY adds `addr` and `source` (`S` or `T`, as in the text output).
+fn print_json<R: Read>(reader: &mut R, command: Command) -> Result<serde_json::Value, String> {
+    let file_hdr = FileHeader::decode(reader).map_err(|e| format!("invalid VCDIFF header: {e}"))?;
+    let app_header = file_hdr
+        .app_header
+        .as_ref()
+        .filter(|_| file_hdr.hdr_ind & VCD_APPHEADER != 0)
+        .map(|data| String::from_utf8_lossy(data).into_owned());
+    let header = serde_json::json!({
+        "version": 0,
+        "header_size": file_header_size(&file_hdr),
+        "indicator": header_flag_names(file_hdr.hdr_ind),
+        "secondary": file_hdr.secondary_id.map(|id| SecondaryRegistry::new().describe(id)),
+        "code_table": file_hdr.code_table.as_ref().map(|table| serde_json::json!({
+            "near": table.near,
+            "same": table.same,
+            "changed_entries": table.changed_entries(),
+        })),
+        "app_header": app_header,
+    });
+
+    let mut windows = Vec::new();
+    let mut target_offset: u64 = 0;
+    while let Some(wh) =
+        WindowHeader::decode(reader).map_err(|e| format!("window {}: {e}", windows.len()))?
+    {
+        let window_num = windows.len() as u64;
+        let mut window = serde_json::json!({
+            "number": window_num,
+            "indicator": window_flag_names(wh.win_ind),
+            "delta_indicator": delta_flag_names(wh.del_ind),
+            "adler32": wh.adler32.map(|c| format!("{c:08x}")),
+            "target_offset": target_offset,
+            "copy_window_len": wh.copy_window_len,
+            "copy_window_offset": wh.copy_window_offset,
+            "enc_len": wh.enc_len,
+            "target_window_len": wh.target_window_len,
+            "data_len": wh.data_len,
+            "inst_len": wh.inst_len,
+            "addr_len": wh.addr_len,
+        });
+
+        if command == Command::PrintDelta {
+            let copy_window_len = copy_window_len(&wh);
+            let mut offset = target_offset;
+            let instructions: Vec<_> =
+                read_window_instructions(reader, &wh, &file_hdr, window_num)?
+                    .into_iter()
+                    .map(|inst| {
+                        let (json, len) = match inst {
+                            Instruction::Add { len } => (
+                                serde_json::json!({ "offset": offset, "op": "ADD", "len": len }),
+                                len,
+                            ),
+                            Instruction::Run { len } => (
+                                serde_json::json!({ "offset": offset, "op": "RUN", "len": len }),
+                                len,
+                            ),
+                            Instruction::Copy { len, addr, .. } => {
+                                let (space, addr) = copy_address(&wh, copy_window_len, addr);
+                                let json = serde_json::json!({
+                                    "offset": offset,
+                                    "op": "COPY",
+                                    "len": len,
+                                    "addr": addr,
+                                    "source": space.to_string(),
+                                });
+                                (json, len)
+                            }
+                        };
+                        offset += len as u64;
+                        json
+                    })
+                    .collect();
+            window["instructions"] = serde_json::Value::Array(instructions);
+        } else {
+            skip_window_sections(reader, &wh).map_err(|e| format!("window {window_num}: {e}"))?;
+        }
+
+        windows.push(window);
+        target_offset += wh.target_window_len;
+        if command == Command::PrintHdr {
+            break;
+        }
+    }
+
+    Ok(serde_json::json!({ "header": header, "windows": windows }))
+}
+
+/// Encoded size of the file header: magic(4) + hdr_ind(1) + optional fields.
+fn file_header_size(file_hdr: &FileHeader) -> usize {
+    let mut hdr_size: usize = 5;
+    if file_hdr.hdr_ind & VCD_SECONDARY != 0 {
+        hdr_size += 1;
+    }
+    if file_hdr.hdr_ind & VCD_APPHEADER != 0 {
+        if let Some(ref data) = file_hdr.app_header {
+            hdr_size += crate::vcdiff::varint::sizeof_usize(data.len()) + data.len();
+        } else {
+            hdr_size += 1;
+        }
+    }
+    hdr_size
+}
+
+fn header_flag_names(hdr_ind: u8) -> Vec<&'static str> {
+    flag_names(
+        hdr_ind,
+        &[
+            (VCD_SECONDARY, "VCD_SECONDARY"),
+            (VCD_CODETABLE, "VCD_CODETABLE"),
+            (VCD_APPHEADER, "VCD_APPHEADER"),
+        ],
+    )
+}
+
+fn window_flag_names(win_ind: u8) -> Vec<&'static str> {
+    flag_names(
+        win_ind,
+        &[
+            (VCD_SOURCE, "VCD_SOURCE"),
+            (VCD_TARGET, "VCD_TARGET"),
+            (VCD_ADLER32, "VCD_ADLER32"),
+        ],
+    )
+}
+
+fn delta_flag_names(del_ind: u8) -> Vec<&'static str> {
+    flag_names(
+        del_ind,
+        &[
+            (VCD_DATACOMP, "VCD_DATACOMP"),
+            (VCD_INSTCOMP, "VCD_INSTCOMP"),
+            (VCD_ADDRCOMP, "VCD_ADDRCOMP"),
+        ],
+    )
+}
+
+fn flag_names(bits: u8, flags: &[(u8, &'static str)]) -> Vec<&'static str> {
+    flags
+        .iter()
+        .filter(|&&(bit, _)| bits & bit != 0)
+        .map(|&(_, name)| name)
+        .collect()
+}
+
+/// Print the file header fields; secondary compressor names come from
+/// `registry`, so installed custom backends show up by name.
+fn write_file_header(
+    out: &mut dyn Write,
+    file_hdr: &FileHeader,
+    registry: &SecondaryRegistry,
+) -> io::Result<()> {
+    writeln!(out, "VCDIFF version:               0")?;
+    writeln!(
+        out,
+        "VCDIFF header size:           {}",
+        file_header_size(file_hdr)
+    )?;
+    writeln!(
+        out,
+        "VCDIFF header indicator:      {}",
+        flag_list(&header_flag_names(file_hdr.hdr_ind))
+    )?;
+
+    match file_hdr.secondary_id {
+        Some(id) => writeln!(
+            out,
+            "VCDIFF secondary compressor:  {}",
+            registry.describe(id)
+        )?,
+        None => writeln!(out, "VCDIFF secondary compressor:  none")?,
+    }
+
+    if let Some(ref table) = file_hdr.code_table {
+        writeln!(
+            out,
+            "VCDIFF code table:            near {}, same {}, {} of 256 entries changed",
+            table.near,
+            table.same,
+            table.changed_entries()
+        )?;
+    }
+
+    if file_hdr.hdr_ind & VCD_APPHEADER != 0
+        && let Some(ref data) = file_hdr.app_header
+        && !data.is_empty()
+    {
+        let s = match AppHeaderMap::parse(data) {
+            Ok(map) => map
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Err(_) => String::from_utf8_lossy(data).into_owned(),
+        };
+        writeln!(out, "VCDIFF application header:    {s}")?;
+    }
+    Ok(())
+}
+
+fn flag_list(names: &[&str]) -> String {
+    if names.is_empty() {
+        return "none".to_string();
+    }
+    names.iter().map(|name| format!("{name} ")).collect()
+}
+
+/// Print indicator flag names in xdelta3's `printhdr` layout.
+fn print_flags(names: &[&str]) {
+    println!("{}", flag_list(names));
+}
+
+fn copy_window_len(wh: &WindowHeader) -> u64 {
+    if wh.has_source() || wh.has_target() {
+        wh.copy_window_len
+    } else {
+        0
+    }
+}
+
+/// Read a window's sections and decode its instruction list.
+fn read_window_instructions<R: Read>(
+    reader: &mut R,
+    wh: &WindowHeader,
+    file_hdr: &FileHeader,
+    window_num: u64,
+) -> Result<Vec<Instruction>, String> {
+    let mut data_buf = vec![0u8; wh.data_len as usize];
+    let mut inst_buf = vec![0u8; wh.inst_len as usize];
+    let mut addr_buf = vec![0u8; wh.addr_len as usize];
+    reader
+        .read_exact(&mut data_buf)
+        .map_err(|e| format!("window {window_num} data section: {e}"))?;
+    reader
+        .read_exact(&mut inst_buf)
+        .map_err(|e| format!("window {window_num} inst section: {e}"))?;
+    reader
+        .read_exact(&mut addr_buf)
+        .map_err(|e| format!("window {window_num} addr section: {e}"))?;
+
+    if wh.del_ind != 0 {
+        let (_, i, a) = crate::compress::secondary::decompress_sections(
+            &data_buf,
+            &inst_buf,
+            &addr_buf,
+            wh.del_ind,
+            file_hdr.secondary_id,
+            SectionLimits::for_window(wh.target_window_len, u64::MAX),
+        )
+        .map_err(|e| format!("window {window_num} decompress: {e}"))?;
+        inst_buf = i;
+        addr_buf = a;
+    }
+
+    let copy_window_len = copy_window_len(wh);
+    let iter = match file_hdr.code_table {
+        Some(ref table) => {
+            InstructionIterator::with_code_table(&inst_buf, &addr_buf, copy_window_len, table)
+        }
+        None => InstructionIterator::new(&inst_buf, &addr_buf, copy_window_len),
+    };
+    iter.collect::<Result<_, _>>()
+        .map_err(|e| format!("instruction decode: {e}"))
+}
+
+/// Number of windows in an in-memory delta.
+#[cfg(feature = "parallel")]
+fn count_windows(delta: &[u8]) -> Result<u64, String> {
+    window_headers(delta)
+        .map(|headers| headers.len() as u64)
+        .map_err(|e| format!("invalid delta: {e}"))
+}
+
+/// Skip over a window's data, instruction and address sections.
+fn skip_window_sections<R: Read>(reader: &mut R, wh: &WindowHeader) -> io::Result<()> {
+    let section_total = wh.data_len as usize + wh.inst_len as usize + wh.addr_len as usize;
+    let mut skip_buf = vec![0u8; section_total.min(BUF_SIZE)];
+    let mut remaining = section_total;
+    while remaining > 0 {
+        let to_read = remaining.min(skip_buf.len());
+        reader.read_exact(&mut skip_buf[..to_read])?;
+        remaining -= to_read;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Recode command
+// ---------------------------------------------------------------------------
+
+fn cmd_recode(opts: &Options) -> i32 {
+    let mut reader: Box<dyn Read> = match &opts.input_file {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(BufReader::with_capacity(BUF_SIZE, f)),
+            Err(e) => {
+                eprintln!("oxidelta: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let output_writer: Box<dyn Write> = match (opts.use_stdout, &opts.output_file) {
+        (true, _) | (_, None) => Box::new(BufWriter::with_capacity(BUF_SIZE, io::stdout().lock())),
+        (false, Some(path)) => {
+            if path.exists() && !opts.force {
+                eprintln!(
+                    "oxidelta: output file exists, use -f to overwrite: {}",
+                    path.display()
+                );
+                return 1;
+            }
+            match File::create(path) {
+                Ok(f) => Box::new(BufWriter::with_capacity(BUF_SIZE, f)),
+                Err(e) => {
+                    eprintln!("oxidelta: output file: {}: {e}", path.display());
+                    return 1;
+                }
+            }
+        }
+    };
+
+    if opts.rewindow {
+        return recode_rewindow(opts, reader, output_writer);
+    }
+
+    // Recode passes headers through, so keep bits we don't understand
+    // rather than dropping them.
+    let in_hdr = match FileHeader::decode_with_policy(&mut reader, HeaderBitPolicy::Lenient) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("oxidelta: invalid VCDIFF header: {e}");
+            return 1;
+        }
+    };
+    if in_hdr.unknown_bits() != 0 && !opts.quiet {
+        eprintln!(
+            "oxidelta: preserving unknown header indicator bits {:#04X}",
+            in_hdr.unknown_bits()
+        );
+    }
+
+    let compress_opts = build_compress_options(opts);
+    let new_secondary = compress_opts.secondary.backend();
+
+    let mut builder = FileHeader::builder().indicator_bits(in_hdr.unknown_bits());
+    // Instruction sections are copied as-is, so they keep their code table.
+    if let Some(ref table) = in_hdr.code_table {
+        builder = builder.code_table(table.clone());
+    }
+    if let Some(ref backend) = new_secondary {
+        builder = builder.secondary(backend.id());
+    }
+    let mut app_header = if opts.use_appheader {
+        opts.appheader.clone().or_else(|| in_hdr.app_header.clone())
+    } else {
+        None
+    };
+    // The window checksums are copied as they are, so a CRC-32C delta must
+    // keep declaring CRC-32C whatever happens to the rest of the header.
+    let integrity = Integrity::from_app_header(in_hdr.app_header.as_deref());
+    if integrity == Integrity::Crc32c {
+        app_header = Some(integrity::with_crc32c_token(app_header.as_deref()));
+    } else if Integrity::from_app_header(app_header.as_deref()) != integrity {
+        eprintln!("oxidelta: recode: --app-header cannot change the window checksum");
+        return 1;
+    }
+    if let Some(ah) = app_header {
+        builder = builder.app_header(ah);
+    }
+    let out_hdr = match builder.build() {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("oxidelta: {e}");
+            return 1;
+        }
+    };
+
+    let mut out_writer = output_writer;
+    if let Err(e) = out_hdr.encode(&mut out_writer) {
+        eprintln!("oxidelta: write header: {e}");
+        return 1;
+    }
+
+    // When only the application header changes the sections would just
+    // round-trip through the compressor, which costs time and need not
+    // reproduce the input bytes.
+    let passthrough = in_hdr.secondary_id == out_hdr.secondary_id
+        && Integrity::from_app_header(out_hdr.app_header.as_deref()) == integrity;
+
+    let mut window_num: u64 = 0;
+    loop {
+        let wh = match WindowHeader::decode(&mut reader) {
+            Ok(Some(wh)) => wh,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("oxidelta: window {window_num}: {e}");
+                return 1;
+            }
+        };
+
+        let mut data_buf = vec![0u8; wh.data_len as usize];
+        let mut inst_buf = vec![0u8; wh.inst_len as usize];
+        let mut addr_buf = vec![0u8; wh.addr_len as usize];
+
+        if let Err(e) = reader.read_exact(&mut data_buf) {
+            eprintln!("oxidelta: window {window_num} data: {e}");
+            return 1;
+        }
+        if let Err(e) = reader.read_exact(&mut inst_buf) {
+            eprintln!("oxidelta: window {window_num} inst: {e}");
+            return 1;
+        }
+        if let Err(e) = reader.read_exact(&mut addr_buf) {
+            eprintln!("oxidelta: window {window_num} addr: {e}");
+            return 1;
+        }
+
+        if passthrough {
+            let written = wh
+                .encode(&mut out_writer)
+                .and_then(|()| out_writer.write_all(&data_buf))
+                .and_then(|()| out_writer.write_all(&inst_buf))
+                .and_then(|()| out_writer.write_all(&addr_buf));
+            if let Err(e) = written {
+                eprintln!("oxidelta: window {window_num}: write: {e}");
+                return 1;
+            }
+            window_num += 1;
+            continue;
+        }
+
+        let (raw_data, raw_inst, raw_addr) = if wh.del_ind != 0 {
+            match crate::compress::secondary::decompress_sections(
+                &data_buf,
+                &inst_buf,
+                &addr_buf,
+                wh.del_ind,
+                in_hdr.secondary_id,
+                SectionLimits::for_window(wh.target_window_len, u64::MAX),
+            ) {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("oxidelta: window {window_num} decompress: {e}");
+                    return 1;
+                }
+            }
+        } else {
+            (data_buf, inst_buf, addr_buf)
+        };
+
+        let (out_data, out_inst, out_addr, new_del_ind) = if let Some(ref backend) = new_secondary {
+            match crate::compress::secondary::compress_sections(
+                backend.as_ref(),
+                &raw_data,
+                &raw_inst,
+                &raw_addr,
+            ) {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("oxidelta: window {window_num} compress: {e}");
+                    return 1;
+                }
+            }
+        } else {
+            (raw_data, raw_inst, raw_addr, 0u8)
+        };
+
+        let mut new_wh = WindowHeader {
+            win_ind: wh.win_ind,
+            copy_window_len: wh.copy_window_len,
+            copy_window_offset: wh.copy_window_offset,
+            enc_len: 0,
+            target_window_len: wh.target_window_len,
+            del_ind: new_del_ind,
+            data_len: out_data.len() as u64,
+            inst_len: out_inst.len() as u64,
+            addr_len: out_addr.len() as u64,
+            adler32: wh.adler32,
+        };
+        new_wh.enc_len = new_wh.compute_enc_len();
+
+        if let Err(e) = new_wh.encode(&mut out_writer) {
+            eprintln!("oxidelta: write window header: {e}");
+            return 1;
+        }
+        if let Err(e) = out_writer.write_all(&out_data) {
+            eprintln!("oxidelta: write data: {e}");
+            return 1;
+        }
+        if let Err(e) = out_writer.write_all(&out_inst) {
+            eprintln!("oxidelta: write inst: {e}");
+            return 1;
+        }
+        if let Err(e) = out_writer.write_all(&out_addr) {
+            eprintln!("oxidelta: write addr: {e}");
+            return 1;
+        }
+
+        window_num += 1;
+    }
+
+    if let Err(e) = out_writer.flush() {
+        eprintln!("oxidelta: flush: {e}");
+        return 1;
+    }
+
+    if opts.verbose > 0 && !opts.quiet {
+        eprintln!("oxidelta: recode: {window_num} windows processed");
+    }
+
+    0
+}
+
+/// `recode --rewindow`: decode the whole delta and encode the target again
+/// with the requested window size.
+fn recode_rewindow<R: Read>(opts: &Options, mut reader: R, mut writer: Box<dyn Write>) -> i32 {
+    let mut delta = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut delta) {
+        eprintln!("oxidelta: read error: {e}");
+        return 1;
+    }
+
+    let source = match &opts.source_file {
+        Some(path) => match load_source(path, opts.mmap_source) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                eprintln!("oxidelta: source file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let compress_opts = build_compress_options(opts);
+    let (out, windows) = match rewindow_delta(&delta, source.as_deref(), compress_opts) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("oxidelta: recode: {e}");
+            return 1;
+        }
+    };
+
+    if let Err(e) = writer.write_all(&out).and_then(|()| writer.flush()) {
+        eprintln!("oxidelta: write: {e}");
+        return 1;
+    }
+
+    if opts.verbose > 0 && !opts.quiet {
+        eprintln!("oxidelta: recode: re-encoded into {windows} windows");
+    }
+
+    0
+}
+
+/// Reconstruct the target of `delta` and encode it again with
+/// `compress_opts`. Returns the new delta and its window count.
+///
+/// `source` may only be omitted when no window copies from a source.
+fn rewindow_delta(
+    delta: &[u8],
+    source: Option<&[u8]>,
+    compress_opts: CompressOptions,
+) -> Result<(Vec<u8>, u64), String> {
+    if source.is_none() && delta_uses_source(delta)? {
+        return Err("delta copies from a source file; pass it with --source".into());
+    }
+    let source = source.unwrap_or_default();
+    let target = crate::compress::decoder::decode_all(source, delta)
+        .map_err(|e| format!("decode error: {e}"))?;
+
+    let opts = CompressOptions {
+        window_size: compress_opts.window_size_for(target.len()),
+        ..compress_opts
+    };
+    let mut encoder = DeltaEncoder::new(Vec::new(), source, opts);
+    encoder
+        .write_target(&target)
+        .map_err(|e| format!("encode error: {e}"))?;
+    encoder
+        .finish()
+        .map(|(delta, finished)| (delta, finished.windows))
+        .map_err(|e| format!("encode finish error: {e}"))
+}
+
+/// Whether any window of `delta` copies from a source (`VCD_SOURCE`).
+fn delta_uses_source(delta: &[u8]) -> Result<bool, String> {
+    let mut reader = delta;
+    FileHeader::decode_with_policy(&mut reader, HeaderBitPolicy::Lenient)
+        .map_err(|e| format!("invalid VCDIFF header: {e}"))?;
+    let mut window_num = 0u64;
+    loop {
+        let wh = match WindowHeader::decode(&mut reader) {
+            Ok(Some(wh)) => wh,
+            Ok(None) => return Ok(false),
+            Err(e) => return Err(format!("window {window_num}: {e}")),
+        };
+        if wh.has_source() {
+            return Ok(true);
+        }
+        skip_window_sections(&mut reader, &wh).map_err(|e| format!("window {window_num}: {e}"))?;
+        window_num += 1;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Merge command
+// ---------------------------------------------------------------------------
+
+fn cmd_merge(opts: &Options) -> i32 {
+    // xdelta3 merge -m 1.vcdiff -m 2.vcdiff 3.vcdiff merged.vcdiff
+    // All -m files + input positional are patches applied in order.
+    // Output is a single merged delta.
+
+    let mut all_patches: Vec<PathBuf> = opts.merge_files.clone();
+    if let Some(ref input) = opts.input_file {
+        all_patches.push(input.clone());
+    }
+
+    if all_patches.len() < 2 {
+        eprintln!("oxidelta: merge requires at least 2 patches (-m file1 ... fileN)");
+        return 1;
+    }
+
+    let output_path = match &opts.output_file {
+        Some(p) => Some(p.clone()),
+        None if opts.use_stdout => None,
+        None => {
+            eprintln!("oxidelta: merge requires an output file");
+            return 1;
+        }
+    };
+
+    let mut patches = Vec::with_capacity(all_patches.len());
+    for patch_path in &all_patches {
+        match std::fs::read(patch_path) {
+            Ok(d) => patches.push(d),
+            Err(e) => {
+                eprintln!("oxidelta: merge: {}: {e}", patch_path.display());
+                return 1;
+            }
+        }
+    }
+
+    // Only the source's length is needed: the patches are composed
+    // directly, without decoding any intermediate target.
+    let source_len = match &opts.source_file {
+        Some(path) => match std::fs::metadata(path) {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                eprintln!("oxidelta: source file: {}: {e}", path.display());
+                return 1;
+            }
+        },
+        None => match crate::compress::merge::source_extent(&patches[0]) {
+            Ok(len) => len,
+            Err(e) => {
+                eprintln!(
+                    "oxidelta: merge: patch 1: {}: {e}",
+                    all_patches[0].display()
+                );
+                return 1;
+            }
+        },
+    };
+
+    let refs: Vec<&[u8]> = patches.iter().map(Vec::as_slice).collect();
+    let delta_output = match crate::compress::merge_deltas(source_len, &refs) {
+        Ok(delta) => delta,
+        Err(e) => {
+            eprintln!("oxidelta: merge: {e}");
+            return 1;
+        }
+    };
+
+    if let Some(ref path) = output_path {
+        if path.exists() && !opts.force {
+            eprintln!(
+                "oxidelta: output file exists, use -f to overwrite: {}",
+                path.display()
+            );
+            return 1;
+        }
+        if let Err(e) = std::fs::write(path, &delta_output) {
+            eprintln!("oxidelta: merge: write: {e}");
+            return 1;
+        }
+    } else {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if let Err(e) = out.write_all(&delta_output) {
+            eprintln!("oxidelta: merge: write: {e}");
+            return 1;
+        }
+    }
+
+    if opts.verbose > 0 && !opts.quiet {
+        eprintln!(
+            "oxidelta: merge: {} patches, output {} bytes",
+            all_patches.len(),
+            delta_output.len()
+        );
+    }
+
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Entry point
+// ---------------------------------------------------------------------------
+
+/// Main CLI entry point. Parses arguments via clap, dispatches commands.
+pub fn run() -> ! {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    let cli = Cli::parse();
+    let mut opts = resolve_options(cli);
+
+    // Validate -W against hard max.
+    if opts.input_window_size > XD3_HARDMAXWINSIZE {
+        eprintln!(
+            "oxidelta: -W: window size {} exceeds max {XD3_HARDMAXWINSIZE}",
+            opts.input_window_size
+        );
+        process::exit(1);
+    }
+
+    // Warn if -c overrides output filename.
+    if opts.use_stdout && opts.output_file.is_some() && !opts.quiet {
+        eprintln!(
+            "oxidelta: warning: -c option overrides output filename: {}",
+            opts.output_file.as_ref().unwrap().display()
+        );
+        opts.output_file = None;
+    }
+
+    let exit_code = match opts.command {
+        Command::Encode => cmd_encode(&opts),
+        Command::Decode => cmd_decode(&opts),
+        Command::Config => cmd_config(&opts),
+        Command::PrintHdr | Command::PrintHdrs | Command::PrintDelta => cmd_print(&opts),
+        Command::Recode => cmd_recode(&opts),
+        Command::Merge => cmd_merge(&opts),
+        Command::Verify => cmd_verify(&opts),
+    };
+
+    process::exit(exit_code);
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    fn parse_opts(args: &[&str]) -> Options {
+        let argv: Vec<String> = std::iter::once("oxidelta".to_string())
+            .chain(args.iter().map(|s| s.to_string()))
+            .collect();
+        let cli = Cli::try_parse_from(argv).expect("cli parse failed");
+        resolve_options(cli)
+    }
+
+    #[test]
+    fn progress_meter_math() {
+        assert_eq!(progress_percent(0, Some(400)), Some(0.0));
+        assert_eq!(progress_percent(100, Some(400)), Some(25.0));
+        assert_eq!(progress_percent(500, Some(400)), Some(100.0));
+        assert_eq!(progress_percent(0, Some(0)), Some(100.0));
+        assert_eq!(progress_percent(100, None), None);
+
+        const MIB: u64 = 1024 * 1024;
+        assert_eq!(
+            progress_line("encode", 30 * MIB, Some(120 * MIB), Duration::from_secs(3)),
+            "oxidelta: encode: 30.0 MiB of 120.0 MiB (25%), 10.0 MiB/s, ETA 0:09"
+        );
+        assert_eq!(
+            progress_line(
+                "decode",
+                600 * MIB,
+                Some(1800 * MIB),
+                Duration::from_secs(60)
+            ),
+            "oxidelta: decode: 600.0 MiB of 1800.0 MiB (33%), 10.0 MiB/s, ETA 2:00"
+        );
+        // Unknown size (stdin): no percentage or ETA.
+        assert_eq!(
+            progress_line("encode", 5 * MIB, None, Duration::from_secs(2)),
+            "oxidelta: encode: 5.0 MiB, 2.5 MiB/s"
+        );
+        assert_eq!(
+            progress_line("encode", 0, Some(MIB), Duration::ZERO),
+            "oxidelta: encode: 0.0 MiB of 1.0 MiB (0%), 0.0 MiB/s"
+        );
+    }
+
+    #[test]
+    fn progress_flag_resolution() {
+        assert!(parse_opts(&["--progress", "encode", "in", "out"]).progress);
+        assert!(parse_opts(&["encode", "--progress=always", "in", "out"]).progress);
+        assert!(!parse_opts(&["decode", "--progress=never", "in", "out"]).progress);
+        // Suppressed by --quiet and --json whatever the flag says.
+        assert!(!parse_opts(&["-q", "--progress", "encode", "in", "out"]).progress);
+        assert!(!parse_opts(&["--json", "--progress", "decode", "in", "out"]).progress);
+        // `--progress` takes no separate value, so positionals stay put.
+        let opts = parse_opts(&["decode", "--progress", "in", "out"]);
+        assert_eq!(opts.input_file, Some(PathBuf::from("in")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("out")));
+    }
+
+    #[test]
+    fn parse_byte_size_suffixes() {
+        assert_eq!(parse_byte_size("1").unwrap(), 1);
+        assert_eq!(parse_byte_size("2K").unwrap(), 2 * 1024);
+        assert_eq!(parse_byte_size("3m").unwrap(), 3 * 1024 * 1024);
+        assert_eq!(parse_byte_size("4G").unwrap(), 4 * 1024 * 1024 * 1024);
+        assert!(parse_byte_size("").is_err());
+    }
+
+    #[test]
+    fn encode_subcommand_maps_correctly() {
+        let opts = parse_opts(&[
+            "encode",
+            "--source",
+            "source.bin",
+            "--level",
+            "9",
+            "--window-size",
+            "8M",
+            "--secondary",
+            "lzma",
+            "in.bin",
+            "out.vcdiff",
+        ]);
+        assert_eq!(opts.command, Command::Encode);
+        assert_eq!(opts.level, 9);
+        assert_eq!(opts.input_window_size, 8 * 1024 * 1024);
+        assert_eq!(
+            opts.source_file.as_deref(),
+            Some(std::path::Path::new("source.bin"))
+        );
+        assert_eq!(opts.input_file, Some(PathBuf::from("in.bin")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("out.vcdiff")));
+        assert!(opts.use_secondary);
+        assert_eq!(opts.secondary_name.as_deref(), Some("lzma"));
+    }
+
+    #[test]
+    fn decode_subcommand_maps_correctly() {
+        let opts = parse_opts(&[
+            "--quiet",
+            "decode",
+            "--source",
+            "source.bin",
+            "--no-checksum",
+            "--check-only",
+            "in.vcdiff",
+            "out.bin",
+        ]);
+        assert_eq!(opts.command, Command::Decode);
+        assert!(opts.no_checksum);
+        assert!(opts.no_output);
+        assert!(opts.quiet);
+        assert_eq!(
+            opts.source_file.as_deref(),
+            Some(std::path::Path::new("source.bin"))
+        );
+        assert_eq!(opts.input_file, Some(PathBuf::from("in.vcdiff")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("out.bin")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn source_spec_parses_fd_scheme() {
+        use std::path::Path;
+
+        let opts = parse_opts(&["encode", "--source", "fd:3", "new.bin"]);
+        let source = opts.source_file.as_deref().unwrap();
+        assert_eq!(parse_source_spec(source), Ok(SourceSpec::Fd(3)));
+
+        let path = Path::new("./fd:3");
+        assert_eq!(parse_source_spec(path), Ok(SourceSpec::Path(path)));
+        assert_eq!(
+            parse_source_spec(Path::new("-")),
+            Ok(SourceSpec::Path(Path::new("-")))
+        );
+        for bad in ["fd:", "fd:x", "fd:-1"] {
+            assert!(parse_source_spec(Path::new(bad)).is_err(), "{bad}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_source_reads_inherited_fd() {
+        use std::os::fd::AsRawFd;
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"source via descriptor").unwrap();
+        io::Seek::rewind(&mut file).unwrap();
+
+        let spec = PathBuf::from(format!("fd:{}", file.as_raw_fd()));
+        assert_eq!(
+            &*load_source(&spec, false).unwrap(),
+            b"source via descriptor"
+        );
+        // The descriptor stays open for its owner.
+        assert!(file.metadata().is_ok());
+
+        let err = load_source(std::path::Path::new("fd:x"), false)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_source_is_opt_in() {
+        assert!(!parse_opts(&["decode", "--source", "old", "in", "out"]).mmap_source);
+        let opts = parse_opts(&["--mmap-source", "decode", "--source", "old", "in", "out"]);
+        assert!(opts.mmap_source);
+
+        // Large regular files are mapped only when asked to.
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&vec![7u8; MMAP_SOURCE_THRESHOLD as usize])
+            .unwrap();
+        let read = load_source(file.path(), false).unwrap();
+        assert!(matches!(read, SourceBytes::Owned(_)));
+        let mapped = load_source(file.path(), true).unwrap();
+        assert!(matches!(mapped, SourceBytes::Mapped(_)));
+        assert_eq!(&*read, &*mapped);
+    }
+
+    #[test]
+    fn global_stdio_and_force_flags() {
+        let opts = parse_opts(&["--force", "encode", "--stdout", "in", "out"]);
+        assert!(opts.use_stdout);
+        assert!(opts.force);
+    }
+
+    #[test]
+    fn verbose_is_capped() {
+        let verbose = parse_opts(&["--verbose", "--verbose", "--verbose", "encode", "in", "out"]);
+        assert_eq!(verbose.verbose, 2);
+    }
+
+    #[test]
+    fn tuning_flags_parse() {
+        let opts = parse_opts(&[
+            "encode",
+            "--source-window-size",
+            "64M",
+            "--window-size",
+            "8M",
+            "--duplicate-window-size",
+            "256K",
+            "--instruction-buffer-size",
+            "32K",
+            "--disable-small-matches",
+            "--no-checksum",
+            "in",
+            "out",
+        ]);
+        assert_eq!(opts.source_window_size, 64 * 1024 * 1024);
+        assert_eq!(opts.input_window_size, 8 * 1024 * 1024);
+        assert_eq!(opts.sprevsz, 256 * 1024);
+        assert_eq!(opts.iopt_size, 32 * 1024);
+        assert!(opts.no_compress);
+        assert!(opts.no_checksum);
+    }
+
+    #[test]
+    fn recode_app_header_flags() {
+        let enabled = parse_opts(&["recode", "--app-header", "hello", "in", "out"]);
+        assert!(enabled.use_appheader);
+        assert_eq!(enabled.appheader.as_deref(), Some(&b"hello"[..]));
+
+        let pairs = parse_opts(&[
+            "recode",
+            "--app-header",
+            "tool=oxidelta",
+            "--app-header",
+            "note=",
+            "--app-header",
+            "name=grüße=1",
+            "in",
+            "out",
+        ]);
+        let map = AppHeaderMap::parse(pairs.appheader.as_deref().unwrap()).unwrap();
+        let entries: Vec<(&str, &str)> = map.iter().collect();
+        assert_eq!(
+            entries,
+            [("tool", "oxidelta"), ("note", ""), ("name", "grüße=1")]
+        );
+        let single = parse_opts(&["recode", "--app-header", "k=v", "in", "out"]);
+        let map = AppHeaderMap::parse(single.appheader.as_deref().unwrap()).unwrap();
+        assert_eq!(map.get("k"), Some("v"));
+
+        let dropped = parse_opts(&["recode", "--drop-app-header", "in", "out"]);
+        assert!(!dropped.use_appheader);
+        assert!(dropped.appheader.is_none());
+    }
+
+    #[test]
+    fn merge_flags_parse() {
+        let opts = parse_opts(&[
+            "merge",
+            "--patch",
+            "a.vcdiff",
+            "--patch",
+            "b.vcdiff",
+            "c.vcdiff",
+            "out.vcdiff",
+        ]);
+        assert_eq!(opts.command, Command::Merge);
+        assert_eq!(
+            opts.merge_files,
+            vec![PathBuf::from("a.vcdiff"), PathBuf::from("b.vcdiff")]
+        );
+        assert_eq!(opts.input_file, Some(PathBuf::from("c.vcdiff")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("out.vcdiff")));
+    }
+
+    #[test]
+    fn diff_and_patch_aliases_map_to_encode_and_decode() {
+        let opts = parse_opts(&["diff", "-l", "9", "old.bin", "new.bin", "out.vcdiff"]);
+        assert_eq!(opts.command, Command::Encode);
+        assert_eq!(opts.source_file, Some(PathBuf::from("old.bin")));
+        assert_eq!(opts.input_file, Some(PathBuf::from("new.bin")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("out.vcdiff")));
+        assert_eq!(opts.level, 9);
+
+        // `-` is stdin for inputs and stdout for the output.
+        let opts = parse_opts(&["diff", "old.bin", "-", "-"]);
+        assert_eq!(opts.input_file, None);
+        assert_eq!(opts.output_file, None);
+        let opts = parse_opts(&["diff", "-", "new.bin"]);
+        assert_eq!(opts.source_file, Some(PathBuf::from("-")));
+        assert_eq!(opts.input_file, Some(PathBuf::from("new.bin")));
+        assert_eq!(opts.output_file, None);
+        assert!(!source_and_input_on_stdin(&opts));
+        assert!(source_and_input_on_stdin(&parse_opts(&["diff", "-", "-"])));
+
+        let opts = parse_opts(&["patch", "old.bin", "delta.vcdiff", "new.bin"]);
+        assert_eq!(opts.command, Command::Decode);
+        assert_eq!(opts.source_file, Some(PathBuf::from("old.bin")));
+        assert_eq!(opts.input_file, Some(PathBuf::from("delta.vcdiff")));
+        assert_eq!(opts.output_file, Some(PathBuf::from("new.bin")));
+        assert!(!opts.no_checksum);
+
+        let opts = parse_opts(&["--force", "patch", "old.bin", "-"]);
+        assert_eq!(opts.command, Command::Decode);
+        assert!(opts.force);
+        assert_eq!(opts.input_file, None);
+        assert_eq!(opts.output_file, None);
+
+        for missing in [&["oxidelta", "diff", "old.bin"][..], &["oxidelta", "patch"]] {
+            assert!(Cli::try_parse_from(missing).is_err(), "{missing:?}");
+        }
+    }
+
+    #[test]
+    fn dash_paths_mean_stdio() {
+        for args in [
+            &["encode", "-", "-"][..],
+            &["decode", "--input", "-", "--output", "-"],
+            &["recode", "-", "-"],
+        ] {
+            let opts = parse_opts(args);
+            assert_eq!(opts.input_file, None, "{args:?}");
+            assert_eq!(opts.output_file, None, "{args:?}");
+        }
+        let opts = parse_opts(&["encode", "-s", "-", "new.bin", "-"]);
+        assert_eq!(opts.source_file, Some(PathBuf::from("-")));
+        assert_eq!(opts.input_file, Some(PathBuf::from("new.bin")));
+        assert_eq!(opts.output_file, None);
+    }
+
+    #[test]
+    fn decode_range_flags_parse() {
+        let opts = parse_opts(&["decode", "in.vcdiff"]);
+        assert_eq!((opts.skip, opts.length), (0, None));
+        let opts = parse_opts(&["decode", "--skip", "4K", "--length", "100", "in.vcdiff"]);
+        assert_eq!((opts.skip, opts.length), (4096, Some(100)));
+        let opts = parse_opts(&["patch", "old.bin", "in.vcdiff"]);
+        assert_eq!((opts.skip, opts.length), (0, None));
+
+        let argv = ["oxidelta", "decode", "--skip", "-1", "in.vcdiff"];
+        assert!(Cli::try_parse_from(argv).is_err());
+        let argv = ["oxidelta", "encode", "--skip", "10", "new.bin"];
+        assert!(Cli::try_parse_from(argv).is_err());
+    }
+
+    #[test]
+    fn verify_flags_parse() {
+        let digest = "00".repeat(31) + "ff";
+        let opts = parse_opts(&[
+            "verify",
+            "--source",
+            "old.bin",
+            "--expect-sha256",
+            &digest,
+            "--expect-size",
+            "4K",
+            "patch.vcdiff",
+        ]);
+        assert_eq!(opts.command, Command::Verify);
+        assert_eq!(opts.expect_size, Some(4096));
+        assert_eq!(opts.source_file, Some(PathBuf::from("old.bin")));
+        assert_eq!(opts.input_file, Some(PathBuf::from("patch.vcdiff")));
+        assert!(opts.no_output);
+        let mut expected = [0u8; 32];
+        expected[31] = 0xFF;
+        assert_eq!(opts.expect_sha256, Some(expected));
+
+        let opts = parse_opts(&["verify", "patch.vcdiff"]);
+        assert_eq!(opts.source_file, None);
+        assert_eq!(opts.expect_sha256, None);
+        assert_eq!(opts.expect_size, None);
+
+        for bad in ["abc".to_string(), "zz".repeat(32)] {
+            let argv = [
+                "oxidelta",
+                "verify",
+                "--expect-sha256",
+                &bad,
+                "patch.vcdiff",
+            ];
+            assert!(Cli::try_parse_from(argv).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn header_commands_map() {
+        assert_eq!(parse_opts(&["header", "in"]).command, Command::PrintHdr);
+        assert_eq!(parse_opts(&["headers", "in"]).command, Command::PrintHdrs);
+        assert_eq!(parse_opts(&["delta", "in"]).command, Command::PrintDelta);
+    }
+
+    #[test]
+    fn config_command_maps() {
+        assert_eq!(parse_opts(&["config"]).command, Command::Config);
+    }
+
+    #[test]
+    fn compress_options_mapping() {
+        let opts = parse_opts(&[
+            "encode",
+            "--level",
+            "6",
+            "--window-size",
+            "1M",
+            "--no-checksum",
+            "--secondary",
+            "none",
+            "in",
+            "out",
+        ]);
+        let c = build_compress_options(&opts);
+        assert_eq!(c.level, 6);
+        assert_eq!(c.window_size, 1024 * 1024);
+        assert!(!c.checksum);
+        assert!(matches!(c.secondary, SecondaryCompression::None));
+        assert_eq!(c.iopt_size, XD3_DEFAULT_IOPT_SIZE);
+    }
+
+    #[cfg(feature = "zstd-secondary")]
+    #[test]
+    fn build_options_maps_zstd() {
+        let opts = parse_opts(&["encode", "--secondary", "zstd", "-l", "9", "in", "out"]);
+        assert_eq!(opts.secondary_name.as_deref(), Some("zstd"));
+        let c = build_compress_options(&opts);
+        assert!(matches!(
+            c.secondary,
+            SecondaryCompression::Zstd { level: 9 }
+        ));
+    }
+
+    #[cfg(feature = "zlib-secondary")]
+    #[test]
+    fn build_options_maps_secondary_name() {
+        let opts = parse_opts(&["encode", "--secondary", "zlib", "in", "out"]);
+        assert_eq!(opts.secondary_name.as_deref(), Some("zlib"));
+        let c = build_compress_options(&opts);
+        assert!(matches!(c.secondary, SecondaryCompression::Zlib { .. }));
+
+        // FGK encoding is opt-in until it is verified against xdelta3.
+        let opts = parse_opts(&["encode", "--secondary", "fgk", "in", "out"]);
+        let c = build_compress_options(&opts);
+        #[cfg(feature = "fgk-encode")]
+        assert!(matches!(c.secondary, SecondaryCompression::Fgk));
+        #[cfg(not(feature = "fgk-encode"))]
+        assert!(matches!(c.secondary, SecondaryCompression::None));
+
+        // DJW is decode only; encoding with it falls back to none.
+        let opts = parse_opts(&["encode", "--secondary", "djw", "in", "out"]);
+        let c = build_compress_options(&opts);
+        assert!(matches!(c.secondary, SecondaryCompression::None));
+    }
+
+    #[test]
+    fn parse_source_and_secondary() {
+        let opts = parse_opts(&[
+            "encode",
+            "--source",
+            "source.bin",
+            "--secondary",
+            "lzma",
+            "in",
+            "out",
+        ]);
+        assert_eq!(
+            opts.source_file.as_deref(),
+            Some(std::path::Path::new("source.bin"))
+        );
+        assert!(opts.use_secondary);
+        assert_eq!(opts.secondary_name.as_deref(), Some("lzma"));
+    }
+
+    #[test]
+    fn capabilities_json_mirrors_struct() {
+        let caps = capabilities();
+        let json = capabilities_json(&caps);
+        assert_eq!(json["version"], caps.version);
+        assert_eq!(json["secondary"]["lzma"], caps.secondary_lzma);
+        assert_eq!(json["secondary"]["zstd"], caps.secondary_zstd);
+        assert_eq!(json["secondary"]["fgk"], "unverified");
+        assert_eq!(
+            json["secondary"]["fgk_encode"],
+            cfg!(feature = "fgk-encode")
+        );
+        assert_eq!(json["secondary"]["djw"], "unverified");
+        assert_eq!(json["parallel"], caps.parallel);
+        assert_eq!(json["match_kernel"], caps.match_kernel.name());
+        assert_eq!(json["defaults"]["level"], XD3_DEFAULT_LEVEL);
+        assert_eq!(json["defaults"]["window_size"], XD3_DEFAULT_WINSIZE);
+        assert_eq!(json["defaults"]["hard_max_window_size"], XD3_HARDMAXWINSIZE);
+    }
+
+    #[test]
+    fn print_json_reports_windows() {
+        let source = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let mut target = source.clone();
+        target.extend_from_slice(b"and some novel trailing text");
+        let mut delta = Vec::new();
+        let opts = CompressOptions {
+            window_size: 512,
+            ..Default::default()
+        };
+        crate::compress::encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+
+        let json = print_json(&mut io::Cursor::new(&delta), Command::PrintHdrs).unwrap();
+        assert_eq!(json["header"]["version"], 0);
+        assert_eq!(json["header"]["indicator"], serde_json::json!([]));
+        let windows = json["windows"].as_array().unwrap();
+        assert_eq!(windows.len(), target.len().div_ceil(512));
+        let mut offset = 0;
+        for (i, w) in windows.iter().enumerate() {
+            assert_eq!(w["number"], i);
+            assert_eq!(w["target_offset"], offset);
+            assert_eq!(
+                w["indicator"],
+                serde_json::json!(["VCD_SOURCE", "VCD_ADLER32"])
+            );
+            assert_eq!(w["adler32"].as_str().unwrap().len(), 8);
+            assert!(w["data_len"].is_u64() && w["inst_len"].is_u64() && w["addr_len"].is_u64());
+            assert!(w.get("instructions").is_none());
+            offset += w["target_window_len"].as_u64().unwrap();
+        }
+        assert_eq!(offset, target.len() as u64);
+
+        let json = print_json(&mut io::Cursor::new(&delta), Command::PrintHdr).unwrap();
+        assert_eq!(json["windows"].as_array().unwrap().len(), 1);
+
+        let json = print_json(&mut io::Cursor::new(&delta), Command::PrintDelta).unwrap();
+        for w in json["windows"].as_array().unwrap() {
+            let insts = w["instructions"].as_array().unwrap();
+            let covered: u64 = insts.iter().map(|i| i["len"].as_u64().unwrap()).sum();
+            assert_eq!(covered, w["target_window_len"].as_u64().unwrap());
+            assert_eq!(insts[0]["offset"], w["target_offset"]);
+            for inst in insts {
+                match inst["op"].as_str().unwrap() {
+                    "COPY" => assert!(["S", "T"].contains(&inst["source"].as_str().unwrap())),
+                    "ADD" | "RUN" => assert!(inst.get("addr").is_none()),
+                    other => panic!("unexpected instruction type {other}"),
+                }
+            }
+        }
+
+        assert!(print_json(&mut io::Cursor::new(&delta[..3]), Command::PrintHdrs).is_err());
+    }
+
+    #[test]
+    fn print_json_lists_instructions() {
+        use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
+
+        let source = b"0123456789abcdefghij";
+        let mut stream = StreamEncoder::new(Vec::new(), true);
+        let window = |len, offset| Some(SourceWindow { len, offset });
+        let mut we = WindowEncoder::new(window(10, 0), true);
+        we.copy(4, 2, 0);
+        we.add(b"xy");
+        we.copy(3, 10, 0); // target window offset 0
+        we.run(5, b'z');
+        stream.write_window(we, Some(b"2345xy234zzzzz")).unwrap();
+        let mut we = WindowEncoder::new(window(5, 15), true);
+        we.copy(5, 0, 0);
+        we.add(b"!");
+        stream.write_window(we, Some(b"fghij!")).unwrap();
+        let delta = stream.finish().unwrap();
+        assert_eq!(
+            crate::vcdiff::decode_memory(&delta, source).unwrap(),
+            b"2345xy234zzzzzfghij!"
+        );
+
+        let json = print_json(&mut io::Cursor::new(&delta), Command::PrintDelta).unwrap();
+        let instructions: Vec<_> = json["windows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w["instructions"].clone())
+            .collect();
+        assert_eq!(
+            instructions,
+            [
+                serde_json::json!([
+                    { "offset": 0, "op": "COPY", "len": 4, "addr": 2, "source": "S" },
+                    { "offset": 4, "op": "ADD", "len": 2 },
+                    { "offset": 6, "op": "COPY", "len": 3, "addr": 0, "source": "T" },
+                    { "offset": 9, "op": "RUN", "len": 5 },
+                ]),
+                serde_json::json!([
+                    { "offset": 14, "op": "COPY", "len": 5, "addr": 15, "source": "S" },
+                    { "offset": 19, "op": "ADD", "len": 1 },
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_delta_size_bumps_level() {
+        let mut rng = XorShift32::new(0x1234_5678);
+        let mut next = move || rng.next_u32();
+        let source: Vec<u8> = (0..64 * 1024).map(|_| next() as u8).collect();
+        // Short source fragments shuffled into the target: the fast levels
+        // miss many of them, the slow levels find them.
+        let mut target = Vec::new();
+        while target.len() < 64 * 1024 {
+            let start = next() as usize % (source.len() - 64);
+            let len = 12 + next() as usize % 40;
+            target.extend_from_slice(&source[start..start + len]);
+        }
+        let opts = CompressOptions {
+            level: 1,
+            ..Default::default()
+        };
+        let size_at = |level| {
+            let mut delta = Vec::new();
+            let opts = CompressOptions {
+                level,
+                ..opts.clone()
+            };
+            crate::compress::encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+            delta.len() as u64
+        };
+        let (fast, budget, best) = (size_at(1), size_at(6), size_at(9));
+        assert!(best < budget && budget < fast, "{fast} {budget} {best}");
+
+        let (delta, summary) =
+            encode_within_budget(&source, &target, opts.clone(), budget, false).unwrap();
+        assert!(summary.level > 1 && summary.level <= 6);
+        assert!(delta.len() as u64 <= budget);
+        assert_eq!(summary.report.target_bytes, target.len() as u64);
+        assert_eq!(summary.report.delta_bytes, delta.len() as u64);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            target
+        );
+
+        let (_, summary) =
+            encode_within_budget(&source, &target, opts.clone(), fast, false).unwrap();
+        assert_eq!(summary.level, 1);
+
+        let err = encode_within_budget(&source, &target, opts, best - 1, false).unwrap_err();
+        assert!(err.contains(&format!("smallest: {best} bytes")), "{err}");
+
+        let opts = parse_opts(&["encode", "--max-delta-size", "64K", "new.bin"]);
+        assert_eq!(opts.max_delta_size, Some(64 * 1024));
+    }
+
+    #[test]
+    fn rewindow_merges_small_windows() {
+        let source: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        target[5_000..5_100].fill(0xAA);
+        target.extend_from_slice(b"appended tail");
+
+        let mut delta = Vec::new();
+        let opts = CompressOptions {
+            window_size: 1024,
+            ..Default::default()
+        };
+        crate::compress::encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+        let count_windows = |delta: &[u8]| window_headers(delta).unwrap().len();
+        assert_eq!(count_windows(&delta), 20);
+
+        let opts = CompressOptions {
+            window_size: 8 * 1024,
+            ..Default::default()
+        };
+        let (rewindowed, windows) = rewindow_delta(&delta, Some(&source), opts.clone()).unwrap();
+        assert_eq!(windows, 3);
+        assert_eq!(count_windows(&rewindowed), 3);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&rewindowed, &source).unwrap(),
+            target
+        );
+
+        let err = rewindow_delta(&delta, None, opts.clone()).unwrap_err();
+        assert!(err.contains("--source"), "{err}");
+
+        // Without source copies the source is optional.
+        let mut no_source = Vec::new();
+        crate::compress::encoder::encode_all(&mut no_source, &[], &target, Default::default())
+            .unwrap();
+        let (rewindowed, _) = rewindow_delta(&no_source, None, opts).unwrap();
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&rewindowed, &[]).unwrap(),
+            target
+        );
+
+        let opts = parse_opts(&["recode", "--rewindow", "64K", "-s", "old", "in", "out"]);
+        assert!(opts.rewindow);
+        assert_eq!(opts.input_window_size, 64 * 1024);
+        assert_eq!(opts.source_file, Some(PathBuf::from("old")));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn threads_flag_parses() {
+        assert_eq!(
+            parse_opts(&["encode", "--threads", "4", "in"]).threads,
+            Some(4)
+        );
+        assert_eq!(parse_opts(&["encode", "in"]).threads, None);
+        assert_eq!(
+            parse_opts(&["diff", "--threads", "2", "a", "b"]).threads,
+            Some(2)
+        );
+        // Merging composes instructions without encoding, and a delta
+        // budget re-encodes sequentially: neither takes `--threads`.
+        assert!(
+            Cli::try_parse_from(["oxidelta", "merge", "--threads", "2", "-p", "a", "b", "out"])
+                .is_err()
+        );
+        assert!(
+            Cli::try_parse_from([
+                "oxidelta",
+                "encode",
+                "--threads",
+                "2",
+                "--max-delta-size",
+                "1K",
+                "in"
+            ])
+            .is_err()
+        );
+        assert!(Cli::try_parse_from(["oxidelta", "encode", "--threads", "0", "in"]).is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn threaded_encode_is_independent_of_thread_count() {
+        let source: Vec<u8> = (0..200_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut target = source.clone();
+        for chunk in target.chunks_mut(9_000) {
+            chunk[..50].fill(0x5A);
+        }
+        let opts = CompressOptions {
+            window_size: 32 * 1024,
+            ..Default::default()
+        };
+
+        let mut sequential = Vec::new();
+        crate::compress::encoder::encode_all(&mut sequential, &source, &target, opts.clone())
+            .unwrap();
+        assert_eq!(
+            encode_with_threads(&source, &target, opts.clone(), 1).unwrap(),
+            sequential
+        );
+
+        let parallel = encode_with_threads(&source, &target, opts.clone(), 2).unwrap();
+        for threads in [3, 8] {
+            assert_eq!(
+                encode_with_threads(&source, &target, opts.clone(), threads).unwrap(),
+                parallel
+            );
+        }
+        assert_eq!(count_windows(&parallel).unwrap(), 7);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&parallel, &source).unwrap(),
+            target
+        );
+    }
+
+    #[test]
+    fn header_dump_names_registered_backends() {
+        use crate::compress::secondary::CompressBackend;
+        use crate::vcdiff::decoder::DecodeError;
+        use std::sync::Arc;
+
+        struct Reverse;
+        impl CompressBackend for Reverse {
+            fn id(&self) -> u8 {
+                99
+            }
+            fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+                Ok(data.iter().rev().copied().collect())
+            }
+            fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, DecodeError> {
+                Ok(data.iter().rev().copied().collect())
+            }
+            fn name(&self) -> &str {
+                "reverse"
+            }
+        }
+
+        let dump = |id: u8, registry: &SecondaryRegistry| {
+            let hdr = FileHeader {
+                hdr_ind: VCD_SECONDARY,
+                secondary_id: Some(id),
+                ..FileHeader::default()
+            };
+            let mut out = Vec::new();
+            write_file_header(&mut out, &hdr, registry).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut registry = SecondaryRegistry::new();
+        assert!(dump(99, &registry).contains("compressor:  unknown (id=99)\n"));
+        registry.register(99, Arc::new(Reverse));
+        assert!(dump(99, &registry).contains("compressor:  reverse\n"));
+        // IDs without a backend in this build still get their assigned name.
+        assert!(dump(SecondaryId::Djw.id(), &registry).contains("compressor:  djw\n"));
+        #[cfg(feature = "lzma-secondary")]
+        assert!(dump(SecondaryId::Lzma.id(), &registry).contains("compressor:  lzma\n"));
+    }
+
+    #[test]
+    fn print_reports_custom_code_table() {
+        use crate::vcdiff::code_table::{CustomCodeTable, default_code_table};
+        use crate::vcdiff::encoder::{SourceWindow, StreamEncoder};
+
+        let mut table = *default_code_table();
+        table.swap(2, 200);
+        table.swap(20, 30);
+        let mut enc = StreamEncoder::new(Vec::new(), true);
+        enc.set_code_table(CustomCodeTable::new(4, 3, table).unwrap())
+            .unwrap();
+        let source = b"0123456789abcdefghij";
+        let mut we = enc.window_encoder(Some(SourceWindow { len: 20, offset: 0 }), true);
+        we.copy_with_auto_mode(10, 5);
+        we.add(b"new");
+        enc.write_window(we, Some(b"56789abcdenew")).unwrap();
+        let delta = enc.finish().unwrap();
+
+        let mut reader = io::Cursor::new(&delta);
+        let hdr = FileHeader::decode(&mut reader).unwrap();
+        let mut out = Vec::new();
+        write_file_header(&mut out, &hdr, &SecondaryRegistry::new()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains(
+                "VCDIFF code table:            near 4, same 3, 4 of 256 entries changed\n"
+            ),
+            "{out}"
+        );
+
+        let json = print_json(&mut io::Cursor::new(&delta), Command::PrintDelta).unwrap();
+        assert_eq!(
+            json["header"]["code_table"],
+            serde_json::json!({ "near": 4, "same": 3, "changed_entries": 4 })
+        );
+        let insts = &json["windows"][0]["instructions"];
+        assert_eq!(insts[0]["op"], "COPY");
+        assert_eq!(insts[0]["addr"], 5);
+        assert_eq!(insts[1]["op"], "ADD");
+
+        // Deltas on the default table say nothing about it.
+        let mut plain = Vec::new();
+        crate::compress::encoder::encode_all(&mut plain, source, b"56789", Default::default())
+            .unwrap();
+        let json = print_json(&mut io::Cursor::new(&plain), Command::PrintHdr).unwrap();
+        assert!(json["header"]["code_table"].is_null());
+    }
+}
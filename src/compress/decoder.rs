@@ -1,192 +1,1313 @@
-// Streaming delta decoder.
-//
-// DeltaDecoder wraps StreamDecoder with:
-//   - Streaming output via Write trait (doesn't accumulate full target)
-//   - Progress tracking (bytes decoded, windows decoded)
-//   - Window-by-window decoding for constant memory usage
-
-use std::io::{Read, Write};
-
-use crate::vcdiff::decoder::{DecodeError, SourceProvider, StreamDecoder};
-
-// ---------------------------------------------------------------------------
-// DeltaDecoder
-// ---------------------------------------------------------------------------
-
-/// Streaming delta decoder with progress tracking.
-///
-/// Decodes VCDIFF delta streams one window at a time, writing output
-/// to any `impl Write` destination. Only one decoded window is in memory
-/// at a time.
-pub struct DeltaDecoder<R: Read> {
-    inner: StreamDecoder<R>,
-    bytes_decoded: u64,
-    windows_decoded: u64,
-    /// Reusable buffer for decoded window data (cleared between windows).
-    window_buf: Vec<u8>,
-}
-
-impl<R: Read> DeltaDecoder<R> {
-    /// Create a new streaming decoder.
-    pub fn new(reader: R) -> Self {
-        Self {
-            inner: StreamDecoder::new(reader, true),
-            bytes_decoded: 0,
-            windows_decoded: 0,
-            window_buf: Vec::new(),
-        }
-    }
-
-    /// Create a decoder that optionally skips checksum verification.
-    pub fn with_checksum(reader: R, verify: bool) -> Self {
-        Self {
-            inner: StreamDecoder::new(reader, verify),
-            bytes_decoded: 0,
-            windows_decoded: 0,
-            window_buf: Vec::new(),
-        }
-    }
-
-    /// Decode all windows, writing output to `writer`.
-    ///
-    /// Source must implement `SourceProvider` (e.g., `&[u8]`).
-    /// Returns the total number of bytes decoded.
-    pub fn decode_to<S: SourceProvider, W: Write>(
-        &mut self,
-        source: &mut S,
-        writer: &mut W,
-    ) -> Result<u64, DecodeError> {
-        while self.decode_window_to(source, writer)?.is_some() {}
-        Ok(self.bytes_decoded)
-    }
-
-    /// Decode the next window, writing its output to `writer`.
-    ///
-    /// Returns `Some(window_size)` if a window was decoded, or `None`
-    /// if there are no more windows.
-    pub fn decode_window_to<S: SourceProvider, W: Write>(
-        &mut self,
-        source: &mut S,
-        writer: &mut W,
-    ) -> Result<Option<u64>, DecodeError> {
-        self.window_buf.clear();
-        let has_more = self.inner.decode_window(source, &mut self.window_buf)?;
-
-        if !has_more {
-            return Ok(None);
-        }
-
-        let window_size = self.window_buf.len() as u64;
-        writer
-            .write_all(&self.window_buf)
-            .map_err(DecodeError::Io)?;
-
-        self.bytes_decoded += window_size;
-        self.windows_decoded += 1;
-
-        Ok(Some(window_size))
-    }
-
-    /// Total bytes decoded so far.
-    pub fn bytes_decoded(&self) -> u64 {
-        self.bytes_decoded
-    }
-
-    /// Number of windows decoded so far.
-    pub fn windows_decoded(&self) -> u64 {
-        self.windows_decoded
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Convenience function
-// ---------------------------------------------------------------------------
-
-/// Decode a VCDIFF delta from memory.
-///
-/// This is a convenience wrapper around DeltaDecoder for in-memory use.
-pub fn decode_all(source: &[u8], delta: &[u8]) -> Result<Vec<u8>, DecodeError> {
-    // Fast path for in-memory callers: avoid the extra window staging copy
-    // performed by DeltaDecoder::decode_to.
-    crate::vcdiff::decoder::decode_memory(delta, source)
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::compress::encoder::{self, CompressOptions};
-
-    fn encode_test_data(source: &[u8], target: &[u8]) -> Vec<u8> {
-        let mut output = Vec::new();
-        encoder::encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
-        output
-    }
-
-    #[test]
-    fn decode_all_roundtrip() {
-        let source = b"Hello, world!";
-        let target = b"Hello, earth!";
-        let delta = encode_test_data(source, target);
-        let decoded = decode_all(source, &delta).unwrap();
-        assert_eq!(decoded, target);
-    }
-
-    #[test]
-    fn streaming_decode_to_writer() {
-        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-        let target = b"ABCDEFGHIJKLMNOP--CHANGED--0123456789!!!";
-        let delta = encode_test_data(source, target);
-
-        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
-        let mut src: &[u8] = source;
-        let mut output = Vec::new();
-        let total = decoder.decode_to(&mut src, &mut output).unwrap();
-
-        assert_eq!(output, target);
-        assert_eq!(total, target.len() as u64);
-        assert_eq!(decoder.bytes_decoded(), target.len() as u64);
-        assert!(decoder.windows_decoded() >= 1);
-    }
-
-    #[test]
-    fn window_by_window_decode() {
-        let source = b"source data for windowed decoding test";
-        let target = b"source data for windowed decoding test -- with changes!";
-        let delta = encode_test_data(source, target);
-
-        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
-        let mut src: &[u8] = source;
-        let mut output = Vec::new();
-
-        let mut window_count = 0u64;
-        while let Some(size) = decoder.decode_window_to(&mut src, &mut output).unwrap() {
-            assert!(size > 0);
-            window_count += 1;
-        }
-        assert_eq!(window_count, decoder.windows_decoded());
-        assert_eq!(output, target);
-    }
-
-    #[test]
-    fn empty_target() {
-        let delta = encode_test_data(b"", b"");
-        let decoded = decode_all(b"", &delta).unwrap();
-        assert!(decoded.is_empty());
-    }
-
-    #[test]
-    fn no_checksum_verification() {
-        let target = b"test data";
-        let delta = encode_test_data(b"", target);
-
-        let mut decoder = DeltaDecoder::with_checksum(std::io::Cursor::new(&delta), false);
-        let mut src: &[u8] = b"";
-        let mut output = Vec::new();
-        decoder.decode_to(&mut src, &mut output).unwrap();
-        assert_eq!(output, target);
-    }
-}
+// Streaming delta decoder.
+//
+// DeltaDecoder wraps StreamDecoder with:
+//   - Streaming output via Write trait (doesn't accumulate full target)
+//   - Progress tracking (bytes decoded, windows decoded, per-window report)
+//   - Window-by-window decoding for constant memory usage
+//   - Optional bounded mode that streams each window out while decoding it
+//
+// DeltaReader wraps DeltaDecoder as an `io::Read` source of target bytes.
+//
+// `decode_all_parallel` (feature `parallel`) decodes independent windows
+// concurrently.
+
+use std::borrow::Cow;
+use std::io::{BufRead, ErrorKind, Read, Write};
+use std::sync::Arc;
+
+use super::secondary::{CompressBackend, SectionLimits};
+
+use crate::vcdiff::code_table::Instruction;
+use crate::vcdiff::decoder::{
+    self as vcdiff_decoder, DecodeError, DecodeLimits, InstructionIterator, SourceProvider,
+    StreamDecoder,
+};
+use crate::vcdiff::header::{FileHeader, WindowHeader};
+
+// ---------------------------------------------------------------------------
+// DeltaDecoder
+// ---------------------------------------------------------------------------
+
+/// Streaming delta decoder with progress tracking.
+///
+/// Decodes VCDIFF delta streams one window at a time, writing output
+/// to any `impl Write` destination. Only one decoded window is in memory
+/// at a time.
+pub struct DeltaDecoder<R: Read> {
+    inner: StreamDecoder<R>,
+    bytes_decoded: u64,
+    windows_decoded: u64,
+    /// Reusable buffer for decoded window data (cleared between windows).
+    window_buf: Vec<u8>,
+    /// Bounded mode: `Some(retain)` streams windows out while decoding
+    /// (see `with_bounded_memory`).
+    bounded: Option<Option<usize>>,
+    /// One entry per decoded window (see `window_report`).
+    report: Vec<WindowInfo>,
+}
+
+/// Layout of one decoded window, as recorded by [`DeltaDecoder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// Target window length declared in the window header.
+    pub target_len: u64,
+    /// Encoded DATA section length (after any secondary compression).
+    pub data_len: u64,
+    /// Encoded instruction section length.
+    pub inst_len: u64,
+    /// Encoded address section length.
+    pub addr_len: u64,
+    /// Whether the window carried an Adler-32 checksum.
+    pub had_checksum: bool,
+    /// Secondary compressor ID, if any of the window's sections used one.
+    pub secondary: Option<u8>,
+}
+
+impl<R: Read> DeltaDecoder<R> {
+    /// Create a new streaming decoder.
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: StreamDecoder::new(reader, true),
+            bytes_decoded: 0,
+            windows_decoded: 0,
+            window_buf: Vec::new(),
+            bounded: None,
+            report: Vec::new(),
+        }
+    }
+
+    /// Create a decoder that optionally skips checksum verification.
+    pub fn with_checksum(reader: R, verify: bool) -> Self {
+        Self {
+            inner: StreamDecoder::new(reader, verify),
+            bytes_decoded: 0,
+            windows_decoded: 0,
+            window_buf: Vec::new(),
+            bounded: None,
+            report: Vec::new(),
+        }
+    }
+
+    /// Limit the total decoded output to `limit` bytes.
+    ///
+    /// See [`StreamDecoder::with_max_output_bytes`].
+    pub fn with_max_output_bytes(mut self, limit: u64) -> Self {
+        self.inner = self.inner.with_max_output_bytes(limit);
+        self
+    }
+
+    /// Apply the default work limits for untrusted deltas.
+    ///
+    /// See [`StreamDecoder::hardened`].
+    pub fn hardened(mut self) -> Self {
+        self.inner = self.inner.hardened();
+        self
+    }
+
+    /// Bound the work a delta can cause beyond its output size.
+    ///
+    /// See [`StreamDecoder::with_limits`].
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.inner = self.inner.with_limits(limits);
+        self
+    }
+
+    /// Decode concatenated deltas (each with its own file header) as one
+    /// stream.
+    ///
+    /// See [`StreamDecoder::allow_multiple_headers`].
+    pub fn allow_multiple_headers(mut self, allow: bool) -> Self {
+        self.inner = self.inner.allow_multiple_headers(allow);
+        self
+    }
+
+    /// Decode sections tagged with secondary compressor `id` using `backend`.
+    ///
+    /// See [`StreamDecoder::register_secondary`].
+    pub fn register_secondary(&mut self, id: u8, backend: Arc<dyn CompressBackend>) {
+        self.inner.register_secondary(id, backend);
+    }
+
+    /// Decode in bounded memory instead of buffering whole windows.
+    ///
+    /// Each window is written out as it is decoded, keeping only its most
+    /// recent `retain` bytes resident for target self-copies. A self-copy
+    /// reaching further back fails the decode. `None` retains each window's
+    /// largest self-copy distance, so any delta decodes. See
+    /// [`StreamDecoder::decode_window_to`].
+    pub fn with_bounded_memory(mut self, retain: Option<usize>) -> Self {
+        self.bounded = Some(retain);
+        self
+    }
+
+    /// Decode all windows, writing output to `writer`.
+    ///
+    /// Source must implement `SourceProvider` (e.g., `&[u8]`).
+    /// Returns the total number of bytes decoded.
+    pub fn decode_to<S: SourceProvider, W: Write>(
+        &mut self,
+        source: &mut S,
+        writer: &mut W,
+    ) -> Result<u64, DecodeError> {
+        while self.decode_window_to(source, writer)?.is_some() {}
+        Ok(self.bytes_decoded)
+    }
+
+    /// Decode only target bytes `[start, end)`, writing them to `writer`.
+    ///
+    /// Windows are decoded from the current position (the start of the
+    /// target for a fresh decoder) until one reaches `end`; windows past it
+    /// are never read. Earlier bytes are still decoded, since self-copies may
+    /// refer to them, but only the requested slice is written. Returns the
+    /// number of bytes written, which is short if the target ends before
+    /// `end`.
+    pub fn decode_range<S: SourceProvider, W: Write>(
+        &mut self,
+        source: &mut S,
+        start: u64,
+        end: u64,
+        writer: &mut W,
+    ) -> Result<u64, DecodeError> {
+        if start > end {
+            return Err(DecodeError::InvalidInput(format!(
+                "range start {start} is past its end {end}"
+            )));
+        }
+        let mut range = RangeWriter::new(writer, self.bytes_decoded, start, end);
+        while self.bytes_decoded < end && self.decode_window_to(source, &mut range)?.is_some() {}
+        Ok(range.written)
+    }
+
+    /// Decode the next window, writing its output to `writer`.
+    ///
+    /// Returns `Some(window_size)` if a window was decoded, or `None`
+    /// if there are no more windows.
+    pub fn decode_window_to<S: SourceProvider, W: Write>(
+        &mut self,
+        source: &mut S,
+        writer: &mut W,
+    ) -> Result<Option<u64>, DecodeError> {
+        if let Some(retain) = self.bounded {
+            let window_size = self.inner.decode_window_to(source, writer, retain)?;
+            if let Some(size) = window_size {
+                self.bytes_decoded += size;
+                self.windows_decoded += 1;
+                self.record_window();
+            }
+            return Ok(window_size);
+        }
+
+        self.window_buf.clear();
+        let has_more = self.inner.decode_window(source, &mut self.window_buf)?;
+
+        if !has_more {
+            return Ok(None);
+        }
+
+        let window_size = self.window_buf.len() as u64;
+        writer
+            .write_all(&self.window_buf)
+            .map_err(DecodeError::Io)?;
+
+        self.bytes_decoded += window_size;
+        self.windows_decoded += 1;
+        self.record_window();
+
+        Ok(Some(window_size))
+    }
+
+    fn record_window(&mut self) {
+        let Some(wh) = self.inner.last_window_header() else {
+            return;
+        };
+        self.report.push(WindowInfo {
+            target_len: wh.target_window_len,
+            data_len: wh.data_len,
+            inst_len: wh.inst_len,
+            addr_len: wh.addr_len,
+            had_checksum: wh.adler32.is_some(),
+            secondary: if wh.del_ind != 0 {
+                self.inner.secondary_id()
+            } else {
+                None
+            },
+        });
+    }
+
+    /// Total bytes decoded so far.
+    pub fn bytes_decoded(&self) -> u64 {
+        self.bytes_decoded
+    }
+
+    /// Number of windows decoded so far.
+    pub fn windows_decoded(&self) -> u64 {
+        self.windows_decoded
+    }
+
+    /// Per-window layout of everything decoded so far, in stream order.
+    pub fn window_report(&self) -> Vec<WindowInfo> {
+        self.report.clone()
+    }
+}
+
+/// Writer that forwards only target offsets `[start, end)` of what it is
+/// given, for [`DeltaDecoder::decode_range`] and `decode --skip/--length`.
+pub(crate) struct RangeWriter<'w, W: Write> {
+    inner: &'w mut W,
+    /// Target offset of the next byte written.
+    pos: u64,
+    start: u64,
+    end: u64,
+    written: u64,
+}
+
+impl<'w, W: Write> RangeWriter<'w, W> {
+    /// Forward `[start, end)` to `inner`; the first byte written is at
+    /// target offset `pos`.
+    pub(crate) fn new(inner: &'w mut W, pos: u64, start: u64, end: u64) -> Self {
+        Self {
+            inner,
+            pos,
+            start,
+            end,
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for RangeWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let buf_end = self.pos + buf.len() as u64;
+        let from = self.start.clamp(self.pos, buf_end);
+        let to = self.end.clamp(from, buf_end);
+        if to > from {
+            let slice = &buf[(from - self.pos) as usize..(to - self.pos) as usize];
+            self.inner.write_all(slice)?;
+            self.written += slice.len() as u64;
+        }
+        self.pos = buf_end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Read adapter
+// ---------------------------------------------------------------------------
+
+/// A [`DeltaDecoder`] as a [`Read`] source: the bytes read are the target.
+///
+/// Each `read` that finds the internal buffer empty decodes the next window
+/// into it, then hands out bytes from it until it drains. Target self-copies
+/// never reach past the start of their window, so one decoded window is all
+/// the prior output that has to be kept: memory use is the source plus the
+/// largest window, whatever the total target size. Bounded mode does not
+/// lower this, since the window is collected in the buffer either way.
+/// `VCD_TARGET` windows are not supported.
+///
+/// Decode errors surface as [`std::io::Error`]s; an I/O error from the
+/// delta reader is passed through as is, a truncated delta is an
+/// `UnexpectedEof` error and anything else is wrapped.
+///
+/// ```
+/// use std::io::Read;
+/// use oxidelta::compress::{DeltaReader, encoder};
+///
+/// let source = b"hello old world";
+/// let mut delta = Vec::new();
+/// encoder::encode_all(&mut delta, source, b"hello new world", Default::default()).unwrap();
+///
+/// let mut target = Vec::new();
+/// DeltaReader::new(&delta[..], source).read_to_end(&mut target).unwrap();
+/// assert_eq!(target, b"hello new world");
+/// ```
+pub struct DeltaReader<'s, R: Read> {
+    decoder: DeltaDecoder<R>,
+    source: &'s [u8],
+    /// Output of the current window; `buf[pos..]` is still unread.
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'s, R: Read> DeltaReader<'s, R> {
+    /// Decode the delta read from `reader` against `source`.
+    pub fn new(reader: R, source: &'s [u8]) -> Self {
+        Self::from_decoder(DeltaDecoder::new(reader), source)
+    }
+
+    /// Wrap a decoder set up some other way (limits, secondary backends,
+    /// no checksum verification, ...).
+    pub fn from_decoder(decoder: DeltaDecoder<R>, source: &'s [u8]) -> Self {
+        Self {
+            decoder,
+            source,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// The wrapped decoder (for progress and the window report).
+    pub fn decoder(&self) -> &DeltaDecoder<R> {
+        &self.decoder
+    }
+
+    /// Decode windows until there is unread output or the delta ends.
+    fn fill(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() && !self.done {
+            self.buf.clear();
+            self.pos = 0;
+            let mut source = self.source;
+            match self.decoder.decode_window_to(&mut source, &mut self.buf) {
+                Ok(Some(_)) => {}
+                Ok(None) => self.done = true,
+                Err(DecodeError::Io(e)) => return Err(e),
+                Err(e @ DecodeError::Truncated { .. }) => {
+                    return Err(std::io::Error::new(ErrorKind::UnexpectedEof, e));
+                }
+                Err(e) => return Err(std::io::Error::other(e)),
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+}
+
+impl<R: Read> Read for DeltaReader<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for DeltaReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.fill()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Convenience function
+// ---------------------------------------------------------------------------
+
+/// Decode the delta read from `delta_reader` against `source`, writing the
+/// target to `out`. Returns the number of target bytes written.
+///
+/// One entry point for any source kind: a byte slice, a mapped file
+/// ([`MmapSource`](crate::io::MmapSource)), or a file read on demand
+/// ([`SeekSource`](crate::vcdiff::SeekSource)). Windows are decoded one at
+/// a time with [`DeltaDecoder`], so memory use is one window plus what the
+/// source keeps resident. That also rules out `VCD_TARGET` windows, which
+/// copy from earlier windows: [`decode_all`] (over
+/// [`decode_memory`](crate::vcdiff::decode_memory), which also builds
+/// without `std`) keeps the whole target and decodes them.
+///
+/// ```
+/// use oxidelta::compress::{decoder, encoder};
+///
+/// let source = b"hello old world";
+/// let mut delta = Vec::new();
+/// encoder::encode_all(&mut delta, source, b"hello new world", Default::default()).unwrap();
+///
+/// let mut target = Vec::new();
+/// let n = decoder::decode(&delta[..], &source[..], &mut target).unwrap();
+/// assert_eq!((n, &target[..]), (15, &b"hello new world"[..]));
+/// ```
+pub fn decode<S: SourceProvider, R: Read>(
+    delta_reader: R,
+    mut source: S,
+    out: &mut impl Write,
+) -> Result<u64, DecodeError> {
+    DeltaDecoder::new(delta_reader).decode_to(&mut source, out)
+}
+
+/// Decode a VCDIFF delta from memory.
+///
+/// This is a convenience wrapper around DeltaDecoder for in-memory use.
+pub fn decode_all(source: &[u8], delta: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    // Fast path for in-memory callers: avoid the extra window staging copy
+    // performed by DeltaDecoder::decode_to.
+    crate::vcdiff::decoder::decode_memory(delta, source)
+}
+
+// ---------------------------------------------------------------------------
+// Raw window access (analysis helpers)
+// ---------------------------------------------------------------------------
+
+/// One window of an in-memory delta with secondary compression undone.
+pub(crate) struct RawWindow<'a> {
+    pub header: WindowHeader,
+    pub data: Cow<'a, [u8]>,
+    pub inst: Cow<'a, [u8]>,
+    pub addr: Cow<'a, [u8]>,
+}
+
+/// Read window number `at_window` of `delta` at `cursor`, or `None` at the
+/// end. A delta that ends early is [`DecodeError::Truncated`].
+pub(crate) fn next_raw_window<'a>(
+    delta: &'a [u8],
+    cursor: &mut std::io::Cursor<&'a [u8]>,
+    file_hdr: &FileHeader,
+    at_window: u64,
+) -> Result<Option<RawWindow<'a>>, DecodeError> {
+    let header = match WindowHeader::decode(cursor) {
+        Ok(Some(header)) => header,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(vcdiff_decoder::read_error(e, at_window, false)),
+    };
+    let data_start = cursor.position() as usize;
+    let section_end = |start: usize, len: u64| {
+        usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| end <= delta.len())
+            .ok_or(DecodeError::Truncated {
+                at_window,
+                expected_more: true,
+            })
+    };
+    let inst_start = section_end(data_start, header.data_len)?;
+    let addr_start = section_end(inst_start, header.inst_len)?;
+    let addr_end = section_end(addr_start, header.addr_len)?;
+    cursor.set_position(addr_end as u64);
+
+    let (data, inst, addr) = (
+        &delta[data_start..inst_start],
+        &delta[inst_start..addr_start],
+        &delta[addr_start..addr_end],
+    );
+    vcdiff_decoder::check_secondary_id(header.del_ind, file_hdr.secondary_id)?;
+    if header.del_ind == 0 {
+        return Ok(Some(RawWindow {
+            header,
+            data: Cow::Borrowed(data),
+            inst: Cow::Borrowed(inst),
+            addr: Cow::Borrowed(addr),
+        }));
+    }
+    let (data, inst, addr) = crate::compress::secondary::decompress_sections(
+        data,
+        inst,
+        addr,
+        header.del_ind,
+        file_hdr.secondary_id,
+        SectionLimits::for_window(header.target_window_len, u64::MAX),
+    )?;
+    Ok(Some(RawWindow {
+        header,
+        data: Cow::Owned(data),
+        inst: Cow::Owned(inst),
+        addr: Cow::Owned(addr),
+    }))
+}
+
+impl RawWindow<'_> {
+    /// Iterate this window's instructions.
+    pub fn instructions<'s>(&'s self, file_hdr: &'s FileHeader) -> InstructionIterator<'s> {
+        let cwl = self.header.copy_window_len;
+        match file_hdr.code_table {
+            Some(ref table) => {
+                InstructionIterator::with_code_table(&self.inst, &self.addr, cwl, table)
+            }
+            None => InstructionIterator::new(&self.inst, &self.addr, cwl),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parallel decode
+// ---------------------------------------------------------------------------
+
+/// Decode a VCDIFF delta from memory, decoding windows in parallel.
+///
+/// Windows are independent when none of them reads target output: no target
+/// self-copies and no `VCD_TARGET` windows. The window headers and
+/// instruction sections are scanned first; if every window is independent
+/// they are decoded concurrently into separate buffers and concatenated in
+/// order. Otherwise (or for deltas using an application-defined code table)
+/// this falls back to the sequential [`decode_all`], so the result is always
+/// identical to it.
+#[cfg(feature = "parallel")]
+pub fn decode_all_parallel(source: &[u8], delta: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    use rayon::prelude::*;
+
+    use crate::vcdiff::Integrity;
+    use crate::vcdiff::decoder::WindowSections;
+
+    let mut cursor = std::io::Cursor::new(delta);
+    let file_hdr =
+        FileHeader::decode(&mut cursor).map_err(|e| vcdiff_decoder::read_error(e, 0, false))?;
+    if file_hdr.code_table.is_some() {
+        return decode_all(source, delta);
+    }
+    let integrity = Integrity::from_app_header(file_hdr.app_header.as_deref());
+
+    // Scan: collect each window with its (decompressed) sections, bailing
+    // out to the sequential path at the first dependency on target output.
+    let mut windows = Vec::new();
+    while let Some(window) = next_raw_window(delta, &mut cursor, &file_hdr, windows.len() as u64)? {
+        if window.header.has_target() {
+            return decode_all(source, delta);
+        }
+        let cwl = window.header.copy_window_len;
+        for result in window.instructions(&file_hdr) {
+            if let Instruction::Copy { len, addr, .. } = result?
+                && addr + len as u64 > cwl
+            {
+                return decode_all(source, delta);
+            }
+        }
+        windows.push(window);
+    }
+
+    let decoded: Vec<Vec<u8>> = windows
+        .par_iter()
+        .map(|window| {
+            let wh = &window.header;
+            let mut src: &[u8] = source;
+            let mut copy_buf = Vec::new();
+            let mut output = Vec::with_capacity(wh.target_window_len as usize);
+            vcdiff_decoder::decode_window_into(
+                wh,
+                WindowSections {
+                    data: &window.data,
+                    inst: &window.inst,
+                    addr: &window.addr,
+                },
+                &mut src,
+                integrity,
+                &mut copy_buf,
+                &mut output,
+            )?;
+            Ok(output)
+        })
+        .collect::<Result<_, DecodeError>>()?;
+    Ok(decoded.concat())
+}
+
+// ---------------------------------------------------------------------------
+// Decode-and-diff (patch debugging)
+// ---------------------------------------------------------------------------
+
+/// Maximum number of bytes sampled per side of a `DiffRegion`.
+pub const DIFF_SAMPLE_LEN: usize = 16;
+
+/// A contiguous range where the decoded output differs from the expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffRegion {
+    /// Offset of the first differing byte.
+    pub offset: u64,
+    /// Length of the differing range.
+    pub len: u64,
+    /// Leading expected bytes of the range (at most `DIFF_SAMPLE_LEN`; empty
+    /// if the decoded output runs past the end of `expected`).
+    pub expected: Vec<u8>,
+    /// Leading decoded bytes of the range (at most `DIFF_SAMPLE_LEN`; empty
+    /// if the decoded output ends before `expected` does).
+    pub actual: Vec<u8>,
+}
+
+/// Decode `delta` against `source` and report every region where the output
+/// differs from `expected`.
+///
+/// Output is compared window by window as it is decoded, so only one window
+/// is held in memory. Checksums are not verified: the point is to locate the
+/// differences in a patch that is known (or suspected) to be wrong. An empty
+/// result means the decoded output equals `expected`.
+pub fn decode_and_diff(
+    delta: &[u8],
+    source: &[u8],
+    expected: &[u8],
+) -> Result<Vec<DiffRegion>, DecodeError> {
+    let mut decoder = DeltaDecoder::with_checksum(std::io::Cursor::new(delta), false);
+    let mut src: &[u8] = source;
+    let mut diff = DiffWriter {
+        expected,
+        pos: 0,
+        open: None,
+        regions: Vec::new(),
+    };
+    decoder.decode_to(&mut src, &mut diff)?;
+    Ok(diff.finish())
+}
+
+/// `Write` sink comparing incoming bytes against an expected buffer.
+struct DiffWriter<'a> {
+    expected: &'a [u8],
+    pos: u64,
+    open: Option<DiffRegion>,
+    regions: Vec<DiffRegion>,
+}
+
+impl DiffWriter<'_> {
+    fn mismatch(&mut self, expected: Option<u8>, actual: Option<u8>) {
+        let pos = self.pos;
+        let region = self.open.get_or_insert_with(|| DiffRegion {
+            offset: pos,
+            len: 0,
+            expected: Vec::new(),
+            actual: Vec::new(),
+        });
+        if let Some(b) = expected
+            && region.expected.len() < DIFF_SAMPLE_LEN
+        {
+            region.expected.push(b);
+        }
+        if let Some(b) = actual
+            && region.actual.len() < DIFF_SAMPLE_LEN
+        {
+            region.actual.push(b);
+        }
+        region.len += 1;
+    }
+
+    fn close(&mut self) {
+        if let Some(region) = self.open.take() {
+            self.regions.push(region);
+        }
+    }
+
+    fn finish(mut self) -> Vec<DiffRegion> {
+        // Expected bytes the decoded output never produced.
+        while let Some(&b) = self.expected.get(self.pos as usize) {
+            self.mismatch(Some(b), None);
+            self.pos += 1;
+        }
+        self.close();
+        self.regions
+    }
+}
+
+impl Write for DiffWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &actual in buf {
+            let expected = self.expected.get(self.pos as usize).copied();
+            if expected == Some(actual) {
+                self.close();
+            } else {
+                self.mismatch(expected, Some(actual));
+            }
+            self.pos += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Self-copy distance analysis
+// ---------------------------------------------------------------------------
+
+/// Largest distance, in bytes of decoded output, that any COPY in `delta`
+/// reaches back into previously decoded target data. Returns 0 if the delta
+/// never copies from its own output.
+///
+/// A streaming decoder must keep at least this much output history, so a
+/// large value means the delta cannot be applied in bounded memory. Both
+/// in-window self-copies and `VCD_TARGET` windows are counted; source copies
+/// are not. Only headers and instruction/address sections are parsed.
+pub fn max_self_copy_distance(delta: &[u8]) -> Result<u64, DecodeError> {
+    let mut cursor = std::io::Cursor::new(delta);
+    let file_hdr = FileHeader::decode(&mut cursor)?;
+
+    let mut max_distance = 0u64;
+    let mut window_start = 0u64;
+    let mut windows = 0;
+    while let Some(window) = next_raw_window(delta, &mut cursor, &file_hdr, windows)? {
+        windows += 1;
+        let wh = &window.header;
+        let cwl = wh.copy_window_len;
+        let mut pos = 0u64;
+        for result in window.instructions(&file_hdr) {
+            let (len, distance) = match result? {
+                Instruction::Copy { len, addr, .. } => {
+                    let distance = if addr.saturating_add(len as u64) > cwl {
+                        // Reads this window's output: constant distance
+                        // from the write position.
+                        (cwl + pos).checked_sub(addr)
+                    } else if wh.has_target() {
+                        // Reads earlier windows' output.
+                        wh.copy_window_offset
+                            .checked_add(addr)
+                            .and_then(|from| (window_start + pos).checked_sub(from))
+                    } else {
+                        Some(0)
+                    };
+                    let distance = distance.ok_or_else(|| {
+                        DecodeError::InvalidInput(
+                            "COPY reads past the output decoded so far".into(),
+                        )
+                    })?;
+                    (len, distance)
+                }
+                Instruction::Add { len } | Instruction::Run { len } => (len, 0),
+            };
+            max_distance = max_distance.max(distance);
+            pos += len as u64;
+        }
+        window_start += wh.target_window_len;
+    }
+    Ok(max_distance)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::encoder::{self, CompressOptions};
+    use crate::test_util::XorShift32;
+
+    fn encode_test_data(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        encoder::encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
+        output
+    }
+
+    #[test]
+    fn concatenated_deltas_decode_with_multiple_headers() {
+        let source: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut first = source[..12_000].to_vec();
+        first[300] ^= 0xFF;
+        let mut second = source[5_000..].to_vec();
+        second.extend_from_slice(b"tail");
+
+        let opts = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        let mut delta = Vec::new();
+        encoder::encode_all(&mut delta, &source, &first, opts.clone()).unwrap();
+        let first_len = delta.len();
+        encoder::encode_all(
+            &mut delta,
+            &source,
+            &second,
+            CompressOptions {
+                checksum: false,
+                ..opts
+            },
+        )
+        .unwrap();
+
+        let mut decoder = DeltaDecoder::new(delta.as_slice()).allow_multiple_headers(true);
+        let mut output = Vec::new();
+        let total = decoder
+            .decode_to(&mut source.as_slice(), &mut output)
+            .unwrap();
+        assert_eq!(total, (first.len() + second.len()) as u64);
+        assert_eq!(output[..first.len()], first[..]);
+        assert_eq!(output[first.len()..], second[..]);
+        assert_eq!(decoder.windows_decoded(), 3 + 4);
+
+        // Without the flag the second header is an error, after the first
+        // delta's windows.
+        let mut decoder = DeltaDecoder::new(delta.as_slice());
+        let mut output = Vec::new();
+        let err = decoder
+            .decode_to(&mut source.as_slice(), &mut output)
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err}");
+        assert_eq!(output, first);
+        assert!(matches!(
+            decode_all(&source, &delta),
+            Err(DecodeError::InvalidInput(_))
+        ));
+
+        // A truncated second header is reported as such.
+        let mut decoder = DeltaDecoder::new(&delta[..first_len + 2]).allow_multiple_headers(true);
+        assert!(matches!(
+            decoder.decode_to(&mut source.as_slice(), &mut Vec::new()),
+            Err(DecodeError::Truncated {
+                expected_more: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_range_matches_full_decode_slice() {
+        let source: Vec<u8> = (0..16_384u32).map(|i| (i * 7 % 251) as u8).collect();
+        // Self-similar target so windows carry target self-copies.
+        let mut target = Vec::new();
+        for i in 0..600u32 {
+            target.extend_from_slice(&source[(i as usize * 13) % 8_000..][..40]);
+            target.extend_from_slice(&i.to_le_bytes());
+        }
+        let mut delta = Vec::new();
+        let opts = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+        let full = decode_all(&source, &delta).unwrap();
+        assert_eq!(full, target);
+
+        for (start, end) in [(5_000, 13_000), (0, 10), (4_096, 8_192), (7, 7)] {
+            for bounded in [false, true] {
+                let mut decoder = DeltaDecoder::new(delta.as_slice());
+                if bounded {
+                    decoder = decoder.with_bounded_memory(None);
+                }
+                let mut out = Vec::new();
+                let n = decoder
+                    .decode_range(&mut source.as_slice(), start, end, &mut out)
+                    .unwrap();
+                assert_eq!(out, &full[start as usize..end as usize], "{start}..{end}");
+                assert_eq!(n, end - start);
+                // Stops at the window containing `end - 1`.
+                assert!(decoder.bytes_decoded() < end.max(1) + 4096);
+            }
+        }
+
+        // Past the end of the target: short result.
+        let mut decoder = DeltaDecoder::new(delta.as_slice());
+        let mut out = Vec::new();
+        let n = decoder
+            .decode_range(&mut source.as_slice(), 20_000, u64::MAX, &mut out)
+            .unwrap();
+        assert_eq!(out, &full[20_000..]);
+        assert_eq!(n, out.len() as u64);
+
+        let mut decoder = DeltaDecoder::new(delta.as_slice());
+        assert!(matches!(
+            decoder.decode_range(&mut source.as_slice(), 9, 3, &mut Vec::new()),
+            Err(DecodeError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn max_self_copy_distance_bounded_by_option() {
+        // Self-similar target, no source: everything past the first record
+        // is a self-copy.
+        let target: Vec<u8> = (0..512u32)
+            .flat_map(|i| format!("record {:04} payload {:08x}\n", i % 64, i * 7919).into_bytes())
+            .collect();
+
+        let delta = encode_test_data(b"", &target);
+        assert!(max_self_copy_distance(&delta).unwrap() > 256);
+
+        let mut bounded = Vec::new();
+        let opts = CompressOptions {
+            max_self_copy_distance: Some(256),
+            ..Default::default()
+        };
+        encoder::encode_all(&mut bounded, b"", &target, opts).unwrap();
+        let distance = max_self_copy_distance(&bounded).unwrap();
+        assert!(distance > 0 && distance <= 256, "distance {distance}");
+        assert_eq!(decode_all(b"", &bounded).unwrap(), target);
+    }
+
+    #[test]
+    fn max_self_copy_distance_ignores_source_copies() {
+        let source = b"The quick brown fox jumps over the lazy dog. 0123456789";
+        let target = b"The quick brown fox jumps over the lazy cat. 0123456789";
+        let delta = encode_test_data(source, target);
+        assert_eq!(max_self_copy_distance(&delta).unwrap(), 0);
+    }
+
+    #[test]
+    fn max_self_copy_distance_rejects_copies_past_the_output() {
+        use crate::vcdiff::header::VCD_TARGET;
+
+        // A first window copying from target offset 100, before any
+        // target output exists: COPY 4 (opcode 20), VCD_SELF address 0.
+        let mut wh = WindowHeader {
+            win_ind: VCD_TARGET,
+            copy_window_len: 4,
+            copy_window_offset: 100,
+            enc_len: 0,
+            target_window_len: 4,
+            del_ind: 0,
+            data_len: 0,
+            inst_len: 1,
+            addr_len: 1,
+            adler32: None,
+        };
+        wh.enc_len = wh.compute_enc_len();
+        let mut delta = Vec::new();
+        FileHeader::default().encode(&mut delta).unwrap();
+        wh.encode(&mut delta).unwrap();
+        delta.extend_from_slice(&[20, 0]);
+
+        let err = max_self_copy_distance(&delta).unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+    }
+
+    #[test]
+    fn bounded_decode_matches_full_decode() {
+        // Large enough that bounded mode spills each window several times.
+        let source: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 31 % 251) as u8).collect();
+        let target: Vec<u8> = (0..40_000u32)
+            .flat_map(|i| format!("record {:04} payload {:08x}\n", i % 97, i * 7919).into_bytes())
+            .chain(source[1000..30_000].iter().copied())
+            .collect();
+        let opts = CompressOptions {
+            window_size: 256 * 1024,
+            max_self_copy_distance: Some(8192),
+            ..Default::default()
+        };
+        let mut delta = Vec::new();
+        encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+        assert!(max_self_copy_distance(&delta).unwrap() > 64);
+
+        let mut full = Vec::new();
+        let mut src: &[u8] = &source;
+        DeltaDecoder::new(std::io::Cursor::new(&delta))
+            .decode_to(&mut src, &mut full)
+            .unwrap();
+        assert_eq!(full, target);
+
+        for retain in [None, Some(8192)] {
+            let mut bounded = Vec::new();
+            let mut src: &[u8] = &source;
+            let mut decoder =
+                DeltaDecoder::new(std::io::Cursor::new(&delta)).with_bounded_memory(retain);
+            let total = decoder.decode_to(&mut src, &mut bounded).unwrap();
+            assert_eq!(total, target.len() as u64);
+            assert_eq!(bounded, full, "retain {retain:?}");
+            assert!(decoder.windows_decoded() > 1);
+        }
+    }
+
+    #[test]
+    fn window_report_records_each_window() {
+        let source: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut target = source.clone();
+        target.extend_from_slice(b"appended tail that is not in the source");
+        let opts = CompressOptions {
+            window_size: 32 * 1024,
+            ..Default::default()
+        };
+        let mut delta = Vec::new();
+        encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        assert!(decoder.window_report().is_empty());
+        let mut out = Vec::new();
+        decoder.decode_to(&mut &source[..], &mut out).unwrap();
+        assert_eq!(out, target);
+
+        let report = decoder.window_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].target_len, 32 * 1024);
+        assert_eq!(report[1].target_len, target.len() as u64 - 32 * 1024);
+        let mut cursor = std::io::Cursor::new(delta.as_slice());
+        let file_hdr = FileHeader::decode(&mut cursor).unwrap();
+        for (n, info) in report.iter().enumerate() {
+            let wh = next_raw_window(&delta, &mut cursor, &file_hdr, n as u64)
+                .unwrap()
+                .unwrap()
+                .header;
+            assert_eq!(info.data_len, wh.data_len);
+            assert_eq!(info.inst_len, wh.inst_len);
+            assert_eq!(info.addr_len, wh.addr_len);
+            assert!(info.had_checksum);
+            assert_eq!(info.secondary, None);
+        }
+        // The appended literals land in the second window's DATA section.
+        assert!(report[1].data_len >= 39);
+
+        // Bounded decoding records the same report.
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta)).with_bounded_memory(None);
+        decoder
+            .decode_to(&mut &source[..], &mut Vec::new())
+            .unwrap();
+        assert_eq!(decoder.window_report(), report);
+    }
+
+    #[test]
+    fn bounded_decode_verifies_checksum() {
+        let target = XorShift32::new(0x9E37_79B9).bytes(400_000);
+        let mut delta = encode_test_data(b"", &target);
+        let data_start = delta.windows(64).position(|w| w == &target[..64]).unwrap();
+        // Corrupt a byte that is spilled long before the window ends.
+        delta[data_start + 1000] ^= 0x01;
+
+        let mut sink = Vec::new();
+        let err = DeltaDecoder::new(std::io::Cursor::new(&delta))
+            .with_bounded_memory(Some(0))
+            .decode_to(&mut &[][..], &mut sink)
+            .unwrap_err();
+        assert!(
+            matches!(err, DecodeError::ChecksumMismatch { .. }),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn bounded_decode_rejects_self_copy_past_retained_window() {
+        let target: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("record {:04} payload {:08x}\n", i % 97, i * 7919).into_bytes())
+            .collect();
+        let delta = encode_test_data(b"", &target);
+        assert!(max_self_copy_distance(&delta).unwrap() > 16);
+
+        let mut src: &[u8] = b"";
+        let err = DeltaDecoder::new(std::io::Cursor::new(&delta))
+            .with_bounded_memory(Some(16))
+            .decode_to(&mut src, &mut std::io::sink())
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err}");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decode_matches_sequential() {
+        let source: Vec<u8> = (0..=255).cycle().take(512 * 1024).collect();
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(997) {
+            target[i] ^= 0xA5;
+        }
+
+        // Source-only windows: decoded in parallel.
+        let mut independent = Vec::new();
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            max_self_copy_distance: Some(0),
+            ..Default::default()
+        };
+        encoder::encode_all(&mut independent, &source, &target, opts).unwrap();
+        assert_eq!(max_self_copy_distance(&independent).unwrap(), 0);
+        assert_eq!(
+            decode_all_parallel(&source, &independent).unwrap(),
+            decode_all(&source, &independent).unwrap()
+        );
+        assert_eq!(decode_all_parallel(&source, &independent).unwrap(), target);
+
+        // Self-copies force the sequential fallback.
+        let records: Vec<u8> = (0..4096u32)
+            .flat_map(|i| format!("record {:04} payload {:08x}\n", i % 64, i * 7919).into_bytes())
+            .collect();
+        let dependent = encode_test_data(b"", &records);
+        assert!(max_self_copy_distance(&dependent).unwrap() > 0);
+        assert_eq!(decode_all_parallel(b"", &dependent).unwrap(), records);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decode_reports_corruption() {
+        let source: Vec<u8> = (0..=255).cycle().take(64 * 1024).collect();
+        let mut target = source.clone();
+        target[1000] ^= 0xFF;
+        let delta = encode_test_data(&source, &target);
+        assert!(decode_all_parallel(&source, &delta[..delta.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decode_reports_truncation() {
+        let source = XorShift32::new(0x0324_7C0D).bytes(128 * 1024);
+        let mut target = source.clone();
+        target[40_000] ^= 0xFF;
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            max_self_copy_distance: Some(0),
+            ..Default::default()
+        };
+        let mut delta = Vec::new();
+        encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+
+        // Where the second window's header starts.
+        let mut cursor = std::io::Cursor::new(delta.as_slice());
+        let file_hdr = FileHeader::decode(&mut cursor).unwrap();
+        next_raw_window(&delta, &mut cursor, &file_hdr, 0)
+            .unwrap()
+            .unwrap();
+        let second = cursor.position() as usize;
+
+        for (cut, at_window, expected_more) in [
+            (3, 0, false),
+            (second + 2, 1, false),
+            (delta.len() - 1, 1, true),
+        ] {
+            let err = decode_all_parallel(&source, &delta[..cut]).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    DecodeError::Truncated { at_window: w, expected_more: m }
+                        if w == at_window && m == expected_more
+                ),
+                "cut at {cut}: {err:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decode_verifies_crc32c_windows() {
+        use crate::vcdiff::Integrity;
+
+        let source = XorShift32::new(0x0319_C3C0).bytes(256 * 1024);
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(4099) {
+            target[i] ^= 0x5A;
+        }
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            max_self_copy_distance: Some(0),
+            integrity: Integrity::Crc32c,
+            ..Default::default()
+        };
+        let mut delta = Vec::new();
+        encoder::encode_all(&mut delta, &source, &target, opts).unwrap();
+        assert_eq!(max_self_copy_distance(&delta).unwrap(), 0);
+        assert_eq!(decode_all_parallel(&source, &delta).unwrap(), target);
+
+        // A different source byte changes the output, which its CRC-32C
+        // catches.
+        let mut wrong = source.clone();
+        wrong[70_000] ^= 1;
+        let err = decode_all_parallel(&wrong, &delta).unwrap_err();
+        assert!(
+            matches!(err, DecodeError::ChecksumMismatch { .. }),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn decode_all_roundtrip() {
+        let source = b"Hello, world!";
+        let target = b"Hello, earth!";
+        let delta = encode_test_data(source, target);
+        let decoded = decode_all(source, &delta).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[test]
+    fn hardened_decode_accepts_unchanged_large_target() {
+        // A few bytes of source COPYs per window, far beyond the expansion
+        // ratio if source COPYs counted towards it.
+        let source = XorShift32::new(0x0310_5EED).bytes(4 << 20);
+        let delta = encode_test_data(&source, &source);
+        assert!((source.len() / delta.len()) as u64 > DecodeLimits::default().max_expansion_ratio);
+
+        let mut decoder = DeltaDecoder::new(delta.as_slice()).hardened();
+        let mut src: &[u8] = &source;
+        let mut output = Vec::new();
+        decoder.decode_to(&mut src, &mut output).unwrap();
+        assert_eq!(output, source);
+    }
+
+    #[test]
+    fn streaming_decode_to_writer() {
+        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let target = b"ABCDEFGHIJKLMNOP--CHANGED--0123456789!!!";
+        let delta = encode_test_data(source, target);
+
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        let mut src: &[u8] = source;
+        let mut output = Vec::new();
+        let total = decoder.decode_to(&mut src, &mut output).unwrap();
+
+        assert_eq!(output, target);
+        assert_eq!(total, target.len() as u64);
+        assert_eq!(decoder.bytes_decoded(), target.len() as u64);
+        assert!(decoder.windows_decoded() >= 1);
+    }
+
+    #[test]
+    fn window_by_window_decode() {
+        let source = b"source data for windowed decoding test";
+        let target = b"source data for windowed decoding test -- with changes!";
+        let delta = encode_test_data(source, target);
+
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        let mut src: &[u8] = source;
+        let mut output = Vec::new();
+
+        let mut window_count = 0u64;
+        while let Some(size) = decoder.decode_window_to(&mut src, &mut output).unwrap() {
+            assert!(size > 0);
+            window_count += 1;
+        }
+        assert_eq!(window_count, decoder.windows_decoded());
+        assert_eq!(output, target);
+    }
+
+    #[test]
+    fn empty_target() {
+        let delta = encode_test_data(b"", b"");
+        let decoded = decode_all(b"", &delta).unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn decode_and_diff_reports_all_regions() {
+        let source: Vec<u8> = (0..2048u32).map(|i| (i * 13 % 251) as u8).collect();
+        let target = source.clone();
+        let delta = encode_test_data(&source, &target);
+
+        // Expectation differs from the real target in two places.
+        let mut expected = target.clone();
+        expected[100..104].copy_from_slice(b"WXYZ");
+        expected[1500] ^= 0xFF;
+
+        let regions = decode_and_diff(&delta, &source, &expected).unwrap();
+        assert_eq!(regions.len(), 2, "{regions:?}");
+        assert_eq!(regions[0].offset, 100);
+        assert_eq!(regions[0].len, 4);
+        assert_eq!(regions[0].expected, b"WXYZ");
+        assert_eq!(regions[0].actual, &target[100..104]);
+        assert_eq!(regions[1].offset, 1500);
+        assert_eq!(regions[1].len, 1);
+        assert_eq!(regions[1].actual, vec![target[1500]]);
+
+        assert!(
+            decode_and_diff(&delta, &source, &target)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn decode_and_diff_length_mismatch() {
+        let target = b"abcdefgh".repeat(10);
+        let delta = encode_test_data(b"", &target);
+
+        // Expected is longer: missing tail is one region with no actual bytes.
+        let mut longer = target.clone();
+        longer.extend_from_slice(&[0u8; 40]);
+        let regions = decode_and_diff(&delta, b"", &longer).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].offset, 80);
+        assert_eq!(regions[0].len, 40);
+        assert_eq!(regions[0].expected.len(), DIFF_SAMPLE_LEN);
+        assert!(regions[0].actual.is_empty());
+
+        // Expected is shorter: extra decoded bytes form the region.
+        let regions = decode_and_diff(&delta, b"", &target[..70]).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].offset, 70);
+        assert_eq!(regions[0].len, 10);
+        assert!(regions[0].expected.is_empty());
+        assert_eq!(regions[0].actual, &target[70..]);
+    }
+
+    #[test]
+    fn no_checksum_verification() {
+        let target = b"test data";
+        let delta = encode_test_data(b"", target);
+
+        let mut decoder = DeltaDecoder::with_checksum(std::io::Cursor::new(&delta), false);
+        let mut src: &[u8] = b"";
+        let mut output = Vec::new();
+        decoder.decode_to(&mut src, &mut output).unwrap();
+        assert_eq!(output, target);
+    }
+}
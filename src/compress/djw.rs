@@ -0,0 +1,594 @@
+// DJW static Huffman decoding, for xdelta3's `-S djw` sections.
+//
+// A port of the decoding half of xdelta3's djw.h. DJW ("semi-static
+// Huffman") transmits its code tables up front and then codes each symbol
+// with a fixed prefix code:
+//
+//   groups - 1                       3 bits
+//   sector size / 5 - 1              5 bits, only if groups > 1
+//   code-length code: count - 7      4 bits, then that many 4-bit lengths
+//   symbol code lengths              256 per group, coded (see below)
+//   selector code lengths            groups + 1 lengths of 3 bits, if groups > 1
+//   sector selectors                 one group index per sector, coded
+//   symbols                          each sector coded with its group's table
+//
+// Code lengths and selectors go through move-to-front plus a RUN_0/RUN_1
+// run-length code (as in bzip2): a run of the front value is written as its
+// length in bijective base 2, one RUN symbol per digit, least significant
+// first; any other value is sent as its MTF index plus one. For code
+// lengths the MTF list starts in a fixed order chosen by xdelta3, and a
+// symbol whose length was zero in the previous group is not sent at all.
+//
+// Prefix codes are canonical: shorter codes first, then by symbol value.
+// Multi-bit fields are most significant bit first; bits are packed into
+// bytes least significant bit first, as in `fgk.rs`.
+//
+// As with FGK, xdelta3 frames a compressed section as the decoded size (a
+// VCDIFF varint) followed by the bitstream. There is no encoder. The
+// decoder was checked only against hand-built sections, not real
+// `xdelta3 -S djw` output, so `capabilities()` reports it as unverified.
+
+use crate::vcdiff::varint;
+
+const ALPHABET_SIZE: usize = 256;
+
+/// Longest symbol code.
+const MAX_CODELEN: usize = 20;
+/// Code-length alphabet: RUN_0, RUN_1 and MTF indices 1..=20.
+const TOTAL_CODES: usize = MAX_CODELEN + 2;
+/// Code-length code lengths always sent, and the bits of the count of
+/// further ones.
+const EXTRA_12OFFSET: usize = 7;
+const EXTRA_CODE_BITS: usize = 4;
+/// Longest code-length code, and the bits to send one.
+const MAX_CLCLEN: usize = 15;
+const CLCLEN_BITS: usize = 4;
+
+const GROUP_BITS: usize = 3;
+const SECTORSZ_BITS: usize = 5;
+const SECTORSZ_MULT: usize = 5;
+/// Longest selector code, and the bits to send one.
+const MAX_GBCLEN: usize = 7;
+const GBCLEN_BITS: usize = 3;
+
+const RUN_1: usize = 1;
+
+/// Initial MTF order of the code-length values (`djw_init_clen_mtf_1_2`):
+/// zero, the common lengths 4..=8, then the rest, least likely last.
+const CLEN_MTF_INIT: [u8; MAX_CODELEN + 1] = [
+    0, 4, 5, 6, 7, 8, 9, 10, 3, 11, 2, 12, 13, 1, 14, 15, 16, 17, 18, 19, 20,
+];
+
+/// Reads bits least significant first, fetching bytes only as needed.
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    byte: u8,
+    mask: u16,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            pos: 0,
+            byte: 0,
+            mask: 0x100,
+        }
+    }
+
+    fn bit(&mut self) -> Result<usize, &'static str> {
+        if self.mask == 0x100 {
+            self.byte = *self
+                .input
+                .get(self.pos)
+                .ok_or("input ends before the last symbol")?;
+            self.pos += 1;
+            self.mask = 1;
+        }
+        let bit = (self.byte as u16 & self.mask != 0) as usize;
+        self.mask <<= 1;
+        Ok(bit)
+    }
+
+    /// An `n`-bit value, most significant bit first.
+    fn bits(&mut self, n: usize) -> Result<usize, &'static str> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = value << 1 | self.bit()?;
+        }
+        Ok(value)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Canonical prefix codes
+// ---------------------------------------------------------------------------
+
+/// Decoding table for a canonical prefix code (`djw_build_decoder`).
+struct Code {
+    /// Symbols with a code, ordered by code.
+    inorder: Vec<u16>,
+    /// For each length: code minus `base` is the symbol's `inorder` index,
+    /// and `limit` is the largest code of that length.
+    base: [i64; TOTAL_CODES + 1],
+    limit: [i64; TOTAL_CODES + 1],
+    min_len: usize,
+    max_len: usize,
+}
+
+impl Code {
+    /// Build the table for `lengths` (0 = no code), none above `max`.
+    fn new(lengths: &[u8], max: usize) -> Self {
+        let mut count = [0i64; TOTAL_CODES + 1];
+        for &len in lengths {
+            debug_assert!(len as usize <= max);
+            count[len as usize] += 1;
+        }
+        let mut code = Self {
+            inorder: Vec::new(),
+            base: [0; TOTAL_CODES + 1],
+            limit: [-1; TOTAL_CODES + 1],
+            min_len: (1..=max).find(|&l| count[l] != 0).unwrap_or(0),
+            max_len: (1..=max).rev().find(|&l| count[l] != 0).unwrap_or(0),
+        };
+        if code.max_len == 0 {
+            return code;
+        }
+
+        // First `inorder` index of each length.
+        let mut first = [0usize; TOTAL_CODES + 1];
+        code.limit[code.min_len] = count[code.min_len] - 1;
+        for l in code.min_len + 1..=code.max_len {
+            let next_code = (code.limit[l - 1] + 1) << 1;
+            first[l] = first[l - 1] + count[l - 1] as usize;
+            code.limit[l] = next_code + count[l] - 1;
+            code.base[l] = next_code - first[l] as i64;
+        }
+
+        code.inorder = vec![0; lengths.iter().filter(|&&l| l != 0).count()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                code.inorder[first[len as usize]] = symbol as u16;
+                first[len as usize] += 1;
+            }
+        }
+        code
+    }
+
+    /// Read one symbol (`djw_decode_symbol`).
+    fn symbol(&self, bits: &mut BitReader) -> Result<usize, &'static str> {
+        let mut code = 0i64;
+        let mut len = 0;
+        loop {
+            if len == self.max_len {
+                return Err("invalid prefix code");
+            }
+            code = code << 1 | bits.bit()? as i64;
+            len += 1;
+            if len >= self.min_len && code <= self.limit[len] {
+                break;
+            }
+        }
+        let index = code - self.base[len];
+        if index < 0 || index as usize >= self.inorder.len() {
+            return Err("invalid prefix code");
+        }
+        Ok(self.inorder[index as usize] as usize)
+    }
+}
+
+/// Decode `out.len()` MTF/run-length coded values (`djw_decode_1_2`).
+///
+/// With `skip_offset` nonzero, a value whose counterpart `skip_offset`
+/// places back is zero is zero too and is not coded.
+fn decode_mtf_runs(
+    bits: &mut BitReader,
+    code: &Code,
+    mtf: &mut [u8],
+    skip_offset: usize,
+    out: &mut [u8],
+) -> Result<(), &'static str> {
+    let mut n = 0;
+    let mut rep = 0u64;
+    let mut shift = 0u32;
+    while n < out.len() {
+        if skip_offset != 0 && n >= skip_offset && out[n - skip_offset] == 0 {
+            out[n] = 0;
+            n += 1;
+            continue;
+        }
+        if rep != 0 {
+            out[n] = mtf[0];
+            n += 1;
+            rep -= 1;
+            continue;
+        }
+
+        let symbol = code.symbol(bits)?;
+        if symbol <= RUN_1 {
+            // One more bijective base-2 digit of the run length.
+            if shift >= 32 {
+                return Err("invalid repeat code");
+            }
+            rep = (symbol as u64 + 1) << shift;
+            shift += 1;
+        } else {
+            let index = symbol - 1;
+            if index >= mtf.len() {
+                return Err("invalid move-to-front index");
+            }
+            let value = mtf[index];
+            mtf.copy_within(..index, 1);
+            mtf[0] = value;
+            out[n] = value;
+            n += 1;
+            shift = 0;
+        }
+    }
+    if rep != 0 {
+        return Err("invalid repeat code");
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Section framing
+// ---------------------------------------------------------------------------
+
+/// Decompress a DJW section written by xdelta3: its size as a varint, then
+/// the bitstream.
+pub(crate) fn decode_section(section: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (size, n) = varint::read_usize(section).map_err(|_| "bad section size")?;
+    let input = &section[n..];
+    // xdelta3 never compresses an empty section, and every symbol takes at
+    // least one bit, so `size` is bounded by the input.
+    if size == 0 {
+        return Err("empty section");
+    }
+    if size > input.len().saturating_mul(8) {
+        return Err("input ends before the last symbol");
+    }
+    let mut bits = BitReader::new(input);
+
+    let groups = bits.bits(GROUP_BITS)? + 1;
+    let sector_size = if groups > 1 {
+        (bits.bits(SECTORSZ_BITS)? + 1) * SECTORSZ_MULT
+    } else {
+        size
+    };
+    let sectors = 1 + (size - 1) / sector_size;
+
+    // The code-length code, then every group's 256 symbol code lengths.
+    let mut cl_lengths = [0u8; TOTAL_CODES];
+    let cl_count = bits.bits(EXTRA_CODE_BITS)? + EXTRA_12OFFSET;
+    for len in &mut cl_lengths[..cl_count] {
+        *len = bits.bits(CLCLEN_BITS)? as u8;
+    }
+    let cl_code = Code::new(&cl_lengths, MAX_CLCLEN);
+    let mut lengths = vec![0u8; ALPHABET_SIZE * groups];
+    decode_mtf_runs(
+        &mut bits,
+        &cl_code,
+        &mut CLEN_MTF_INIT.clone(),
+        ALPHABET_SIZE,
+        &mut lengths,
+    )?;
+    let codes: Vec<Code> = lengths
+        .chunks(ALPHABET_SIZE)
+        .map(|lengths| Code::new(lengths, MAX_CODELEN))
+        .collect();
+
+    // Which group codes each sector.
+    let mut selectors = vec![0u8; sectors];
+    if groups > 1 {
+        let mut sel_lengths = [0u8; 9];
+        for len in &mut sel_lengths[..=groups] {
+            *len = bits.bits(GBCLEN_BITS)? as u8;
+        }
+        let sel_code = Code::new(&sel_lengths[..=groups], MAX_GBCLEN);
+        let mut sel_mtf: Vec<u8> = (0..=groups as u8).collect();
+        decode_mtf_runs(&mut bits, &sel_code, &mut sel_mtf, 0, &mut selectors)?;
+    }
+
+    let mut out = Vec::with_capacity(size);
+    for &group in &selectors {
+        let code = codes
+            .get(group as usize)
+            .ok_or("sector selects a missing group")?;
+        for _ in 0..sector_size.min(size - out.len()) {
+            out.push(code.symbol(&mut bits)? as u8);
+        }
+    }
+    if bits.pos != input.len() {
+        return Err("unused input after the last symbol");
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    /// Packs bits least significant first; fields most significant bit first.
+    #[derive(Default)]
+    struct BitWriter {
+        out: Vec<u8>,
+        nbits: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: usize, n: usize) {
+            for i in (0..n).rev() {
+                if self.nbits.is_multiple_of(8) {
+                    self.out.push(0);
+                }
+                if value >> i & 1 != 0 {
+                    *self.out.last_mut().unwrap() |= 1 << (self.nbits % 8);
+                }
+                self.nbits += 1;
+            }
+        }
+    }
+
+    /// Huffman code lengths for `freqs`, one bit for a lone symbol.
+    fn huffman_lengths(freqs: &[u64]) -> Vec<u8> {
+        use std::cmp::Reverse;
+        use std::collections::BinaryHeap;
+
+        let mut parent: Vec<usize> = vec![usize::MAX; freqs.len()];
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = freqs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &f)| f != 0)
+            .map(|(s, &f)| Reverse((f, s)))
+            .collect();
+        if heap.len() == 1 {
+            let Reverse((_, s)) = heap.pop().unwrap();
+            let mut lengths = vec![0; freqs.len()];
+            lengths[s] = 1;
+            return lengths;
+        }
+        while heap.len() > 1 {
+            let Reverse((fa, a)) = heap.pop().unwrap();
+            let Reverse((fb, b)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(usize::MAX);
+            parent[a] = node;
+            parent[b] = node;
+            heap.push(Reverse((fa + fb, node)));
+        }
+        (0..freqs.len())
+            .map(|s| {
+                let mut depth = 0;
+                let mut node = s;
+                while freqs[s] != 0 && parent[node] != usize::MAX {
+                    node = parent[node];
+                    depth += 1;
+                }
+                depth
+            })
+            .collect()
+    }
+
+    /// Canonical codes for `lengths`, as `Code::new` assigns them.
+    fn canonical_codes(lengths: &[u8]) -> Vec<usize> {
+        let mut codes = vec![0; lengths.len()];
+        let mut next = 0;
+        for len in 1..=MAX_CODELEN as u8 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == len) {
+                codes[symbol] = next;
+                next += 1;
+            }
+            next <<= 1;
+        }
+        codes
+    }
+
+    /// MTF + RUN_0/RUN_1 symbols for `values`.
+    fn mtf_runs(values: &[u8], mtf: &mut Vec<u8>) -> Vec<usize> {
+        let mut symbols = Vec::new();
+        let mut run = 0usize;
+        let flush = |run: &mut usize, symbols: &mut Vec<usize>| {
+            while *run > 0 {
+                *run -= 1;
+                symbols.push(*run & 1);
+                *run >>= 1;
+            }
+        };
+        for &value in values {
+            if value == mtf[0] {
+                run += 1;
+                continue;
+            }
+            flush(&mut run, &mut symbols);
+            let index = mtf.iter().position(|&v| v == value).unwrap();
+            mtf.remove(index);
+            mtf.insert(0, value);
+            symbols.push(index + 1);
+        }
+        flush(&mut run, &mut symbols);
+        symbols
+    }
+
+    /// A DJW section for `data`, coded with `groups` tables over sectors of
+    /// `sector_size` bytes; sector `i` uses group `i % groups`.
+    fn encode_section(data: &[u8], groups: usize, sector_size: usize) -> Vec<u8> {
+        assert!((1..=8).contains(&groups));
+        let sector_size = if groups == 1 { data.len() } else { sector_size };
+        let selectors: Vec<u8> = (0..data.len().div_ceil(sector_size))
+            .map(|i| (i % groups) as u8)
+            .collect();
+
+        // Per-group lengths over every symbol in `data`, so that no group
+        // lacks a symbol an earlier group has.
+        let mut lengths = Vec::new();
+        for g in 0..groups {
+            let mut freqs = [0u64; ALPHABET_SIZE];
+            for &b in data {
+                freqs[b as usize] = 1;
+            }
+            for (chunk, &sel) in data.chunks(sector_size).zip(&selectors) {
+                if sel as usize == g {
+                    chunk.iter().for_each(|&b| freqs[b as usize] += 4);
+                }
+            }
+            let group_lengths = huffman_lengths(&freqs);
+            assert!(group_lengths.iter().all(|&l| l as usize <= MAX_CODELEN));
+            lengths.push(group_lengths);
+        }
+
+        // Code lengths: skip those zero in the previous group.
+        let mut coded = Vec::new();
+        for (g, group_lengths) in lengths.iter().enumerate() {
+            for (s, &len) in group_lengths.iter().enumerate() {
+                if g == 0 || lengths[g - 1][s] != 0 {
+                    coded.push(len);
+                }
+            }
+        }
+        let cl_symbols = mtf_runs(&coded, &mut CLEN_MTF_INIT.to_vec());
+        let mut cl_freqs = [0u64; TOTAL_CODES];
+        cl_symbols.iter().for_each(|&s| cl_freqs[s] += 1);
+        let cl_lengths = huffman_lengths(&cl_freqs);
+        assert!(cl_lengths.iter().all(|&l| l as usize <= MAX_CLCLEN));
+        let cl_codes = canonical_codes(&cl_lengths);
+        let cl_count = cl_lengths
+            .iter()
+            .rposition(|&l| l != 0)
+            .map_or(0, |i| i + 1)
+            .max(EXTRA_12OFFSET);
+
+        let mut w = BitWriter::default();
+        varint::write_usize(&mut w.out, data.len()).unwrap();
+        w.nbits = w.out.len() * 8;
+        w.bits(groups - 1, GROUP_BITS);
+        if groups > 1 {
+            w.bits(sector_size / SECTORSZ_MULT - 1, SECTORSZ_BITS);
+        }
+        w.bits(cl_count - EXTRA_12OFFSET, EXTRA_CODE_BITS);
+        for &len in &cl_lengths[..cl_count] {
+            w.bits(len as usize, CLCLEN_BITS);
+        }
+        for s in cl_symbols {
+            w.bits(cl_codes[s], cl_lengths[s] as usize);
+        }
+
+        if groups > 1 {
+            let sel_symbols = mtf_runs(&selectors, &mut (0..=groups as u8).collect());
+            let mut sel_freqs = vec![1u64; groups + 1];
+            sel_symbols.iter().for_each(|&s| sel_freqs[s] += 1);
+            let sel_lengths = huffman_lengths(&sel_freqs);
+            let sel_codes = canonical_codes(&sel_lengths);
+            for &len in &sel_lengths {
+                assert!(len as usize <= MAX_GBCLEN);
+                w.bits(len as usize, GBCLEN_BITS);
+            }
+            for s in sel_symbols {
+                w.bits(sel_codes[s], sel_lengths[s] as usize);
+            }
+        }
+
+        let codes: Vec<Vec<usize>> = lengths.iter().map(|l| canonical_codes(l)).collect();
+        for (chunk, &sel) in data.chunks(sector_size).zip(&selectors) {
+            let g = sel as usize;
+            for &b in chunk {
+                w.bits(codes[g][b as usize], lengths[g][b as usize] as usize);
+            }
+        }
+        w.out
+    }
+
+    #[test]
+    fn decodes_single_group_section() {
+        // Hand-assembled: "ab", one group, 'a' = 0 and 'b' = 1 (both length 1).
+        //
+        // The 512 code lengths [0 x 97, 1, 1, 0 x 157] are sent as MTF/run
+        // symbols: run of 97 zeros (RUN digits 0,1,0,0,0,1), value 1 (MTF
+        // index 13, symbol 14), run of 1 (RUN_0), value 0 (index 1, symbol
+        // 2), run of 156 (digits 1,0,1,1,1,0,0). Used symbols RUN_0, RUN_1,
+        // 2 and 14 get 2-bit codes 00, 01, 10, 11.
+        let mut w = BitWriter::default();
+        w.out.push(2);
+        w.nbits = 8;
+        w.bits(0, GROUP_BITS);
+        w.bits(15 - EXTRA_12OFFSET, EXTRA_CODE_BITS);
+        let mut cl_lengths = [0u8; 15];
+        for s in [0, 1, 2, 14] {
+            cl_lengths[s] = 2;
+        }
+        for len in cl_lengths {
+            w.bits(len as usize, CLCLEN_BITS);
+        }
+        let code = |s: usize| [0b00, 0b01, 0b10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0b11][s];
+        for s in [0, 1, 0, 0, 0, 1, 14, 0, 2, 1, 0, 1, 1, 1, 0, 0] {
+            w.bits(code(s), 2);
+        }
+        w.bits(0b0, 1);
+        w.bits(0b1, 1);
+        assert_eq!(decode_section(&w.out).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn decodes_grouped_sections() {
+        let mut rng = XorShift32::new(0xD1A6_0001);
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![7],
+            (0..=255).collect(),
+            b"the quick brown fox jumps over the lazy dog ".repeat(40),
+            (0..5000).map(|_| (rng.below(7) * 31) as u8).collect(),
+            (0..5000).map(|_| rng.next_u32() as u8).collect(),
+            // Alternating regions favour different tables.
+            (0..4000)
+                .map(|i| {
+                    if i / 40 % 2 == 0 {
+                        b"acgt"[rng.below(4) as usize]
+                    } else {
+                        rng.below(256) as u8
+                    }
+                })
+                .collect(),
+        ];
+        for input in &inputs {
+            for (groups, sector_size) in [(1, 0), (2, 40), (3, 5), (8, 160)] {
+                let section = encode_section(input, groups, sector_size);
+                assert_eq!(
+                    decode_section(&section).unwrap(),
+                    *input,
+                    "{} bytes, {groups} groups",
+                    input.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_sections_are_rejected() {
+        let input = b"abracadabra".repeat(20);
+        let section = encode_section(&input, 2, 10);
+        assert!(decode_section(&section[..section.len() - 1]).is_err());
+        let mut padded = section.clone();
+        padded.push(0);
+        assert!(decode_section(&padded).is_err());
+        assert!(decode_section(&[]).is_err());
+        assert!(decode_section(&[0]).is_err());
+        // A size far beyond what the input can hold fails, not allocates.
+        assert!(decode_section(&[0x8F, 0xFF, 0xFF, 0xFF, 0x7F, 0x00]).is_err());
+        // Truncations and bit flips never panic.
+        for cut in 0..section.len() {
+            let _ = decode_section(&section[..cut]);
+        }
+        for i in 1..section.len() {
+            for bit in 0..8 {
+                let mut flipped = section.clone();
+                flipped[i] ^= 1 << bit;
+                let _ = decode_section(&flipped);
+            }
+        }
+    }
+}
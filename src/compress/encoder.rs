@@ -1,693 +1,3153 @@
-// Streaming delta encoder.
-//
-// DeltaEncoder provides a streaming API for delta compression:
-//   - Source is indexed once upfront (MatchEngine reused across windows)
-//   - Target data is fed in chunks via write_target()
-//   - Each full window is compressed and written immediately
-//   - Constant memory: only one target window buffered at a time
-
-use std::io::Write;
-
-use crate::hash::config::{self, MatcherConfig};
-use crate::hash::matching::MatchEngine;
-use crate::vcdiff::code_table::Instruction;
-use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
-
-use super::pipeline;
-use super::secondary::{self, SecondaryCompression};
-
-#[cfg(feature = "parallel")]
-use rayon::prelude::*;
-
-// ---------------------------------------------------------------------------
-// Options
-// ---------------------------------------------------------------------------
-
-/// Configuration for the streaming delta encoder.
-#[derive(Debug, Clone)]
-pub struct CompressOptions {
-    /// Compression level (0-9). Level 0 = store only (no matching).
-    pub level: u32,
-    /// Maximum target window size in bytes.
-    pub window_size: usize,
-    /// Emit Adler-32 checksums per window.
-    pub checksum: bool,
-    /// Secondary compression algorithm for VCDIFF sections.
-    pub secondary: SecondaryCompression,
-}
-
-impl Default for CompressOptions {
-    fn default() -> Self {
-        Self {
-            level: 6,
-            window_size: 1 << 23, // 8 MiB
-            checksum: true,
-            secondary: SecondaryCompression::None,
-        }
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Errors
-// ---------------------------------------------------------------------------
-
-#[derive(Debug)]
-pub enum EncodeError {
-    Io(std::io::Error),
-}
-
-impl std::fmt::Display for EncodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Io(e) => write!(f, "I/O error: {e}"),
-        }
-    }
-}
-
-impl std::error::Error for EncodeError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Self::Io(e) => Some(e),
-        }
-    }
-}
-
-impl From<std::io::Error> for EncodeError {
-    fn from(e: std::io::Error) -> Self {
-        Self::Io(e)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// DeltaEncoder
-// ---------------------------------------------------------------------------
-
-/// Streaming delta encoder.
-///
-/// Indexes the source once upfront, then processes target data in windows.
-/// Each completed window is immediately compressed and written to the output.
-///
-/// # Example
-/// ```no_run
-/// use oxidelta::compress::encoder::{DeltaEncoder, CompressOptions};
-/// let source = b"original data";
-/// let target = b"modified data";
-/// let mut output = Vec::new();
-/// let mut enc = DeltaEncoder::new(&mut output, source, CompressOptions::default());
-/// enc.write_target(target).unwrap();
-/// enc.finish().unwrap();
-/// ```
-pub struct DeltaEncoder<'s, W: Write> {
-    stream: StreamEncoder<W>,
-    opts: CompressOptions,
-    _config: MatcherConfig,
-    source: &'s [u8],
-    engine: Option<MatchEngine>,
-    buffer: Vec<u8>,
-    bytes_in: u64,
-    windows_written: u64,
-    /// Section size hints from the previous window (for capacity pre-allocation).
-    last_data_size: usize,
-    last_inst_size: usize,
-    last_addr_size: usize,
-}
-
-impl<'s, W: Write> DeltaEncoder<'s, W> {
-    /// Create a new streaming encoder.
-    ///
-    /// The source is indexed immediately. For level 0, no index is built.
-    pub fn new(writer: W, source: &'s [u8], opts: CompressOptions) -> Self {
-        let config = config::config_for_level(opts.level);
-
-        let mut stream = StreamEncoder::new(writer, opts.checksum);
-        if let Some(backend) = opts.secondary.backend() {
-            stream.set_secondary_id(backend.id());
-        }
-
-        // Build the match engine and index the source (reused across windows).
-        let engine = if opts.level > 0 && !source.is_empty() {
-            let src: &[u8] = source;
-            let mut eng = MatchEngine::new(config, src.len() as u64, opts.window_size.max(64));
-            eng.index_source(&src);
-            Some(eng)
-        } else if opts.level > 0 {
-            // No source, but still do target self-matching.
-            Some(MatchEngine::new(config, 0, opts.window_size.max(64)))
-        } else {
-            None // Level 0: no matching at all.
-        };
-
-        Self {
-            stream,
-            opts,
-            _config: config,
-            source,
-            engine,
-            buffer: Vec::new(),
-            bytes_in: 0,
-            windows_written: 0,
-            last_data_size: 0,
-            last_inst_size: 0,
-            last_addr_size: 0,
-        }
-    }
-
-    /// Feed target data to the encoder.
-    ///
-    /// Data is buffered internally. Whenever the buffer reaches `window_size`,
-    /// a complete window is encoded and written to the output.
-    pub fn write_target(&mut self, data: &[u8]) -> Result<(), EncodeError> {
-        self.bytes_in += data.len() as u64;
-        let mut offset = 0usize;
-
-        // Complete a partially buffered window first.
-        if !self.buffer.is_empty() {
-            let need = self.opts.window_size - self.buffer.len();
-            let take = need.min(data.len());
-            self.buffer.extend_from_slice(&data[..take]);
-            offset += take;
-
-            if self.buffer.len() == self.opts.window_size {
-                let window = std::mem::take(&mut self.buffer);
-                self.encode_window(&window)?;
-                self.buffer = window;
-                self.buffer.clear();
-            }
-        }
-
-        // Fast path: encode full windows directly from caller-provided input.
-        while offset + self.opts.window_size <= data.len() {
-            let end = offset + self.opts.window_size;
-            self.encode_window(&data[offset..end])?;
-            offset = end;
-        }
-
-        // Buffer any trailing partial window.
-        if offset < data.len() {
-            self.buffer.extend_from_slice(&data[offset..]);
-        }
-
-        Ok(())
-    }
-
-    /// Flush any remaining buffered data and finalize the stream.
-    ///
-    /// Returns the underlying writer and the total number of windows written.
-    pub fn finish(mut self) -> Result<(W, u64), EncodeError> {
-        // Encode the remaining buffer as a final window.
-        if !self.buffer.is_empty() {
-            let remaining = std::mem::take(&mut self.buffer);
-            self.encode_window(&remaining)?;
-        }
-
-        // Handle empty target (no windows written at all).
-        if self.windows_written == 0 {
-            let we = WindowEncoder::new(None, self.opts.checksum);
-            self.stream.write_window(we, Some(b""))?;
-        }
-
-        let windows = self.windows_written;
-        Ok((self.stream.finish()?, windows))
-    }
-
-    /// Number of target bytes received so far.
-    pub fn bytes_in(&self) -> u64 {
-        self.bytes_in
-    }
-
-    /// Number of windows written so far.
-    pub fn windows_written(&self) -> u64 {
-        self.windows_written
-    }
-
-    /// Encode a single target window.
-    fn encode_window(&mut self, window: &[u8]) -> Result<(), EncodeError> {
-        let source_win = if !self.source.is_empty() {
-            Some(SourceWindow {
-                len: self.source.len() as u64,
-                offset: 0,
-            })
-        } else {
-            None
-        };
-
-        // Find matches (or just ADD for level 0).
-        let instructions = if self.opts.level == 0 {
-            if window.is_empty() {
-                Vec::new()
-            } else {
-                vec![Instruction::Add {
-                    len: window.len() as u32,
-                }]
-            }
-        } else {
-            let raw = self.find_matches(window);
-            pipeline::optimize(&raw, window)
-        };
-
-        // Build the VCDIFF window with capacity hints from previous window.
-        let mut we = if self.last_data_size > 0 {
-            WindowEncoder::with_capacity(
-                source_win,
-                self.opts.checksum,
-                self.last_data_size,
-                self.last_inst_size,
-                self.last_addr_size,
-            )
-        } else {
-            WindowEncoder::new(source_win, self.opts.checksum)
-        };
-        emit_instructions(&mut we, window, &instructions);
-
-        // Finalize: with or without secondary compression.
-        if let Some(backend) = self.opts.secondary.backend() {
-            let sections = we.finish_sections(Some(window));
-            // Track section sizes for next window's capacity hints.
-            self.last_data_size = sections.data_section.len();
-            self.last_inst_size = sections.inst_section.len();
-            self.last_addr_size = sections.addr_section.len();
-
-            let (comp_data, comp_inst, comp_addr, del_ind) = secondary::compress_sections(
-                backend.as_ref(),
-                &sections.data_section,
-                &sections.inst_section,
-                &sections.addr_section,
-            )?;
-
-            let assembled_sections = crate::vcdiff::encoder::WindowSections {
-                source_window: sections.source_window,
-                target_len: sections.target_len,
-                checksum: sections.checksum,
-                data_section: comp_data,
-                inst_section: comp_inst,
-                addr_section: comp_addr,
-            };
-
-            let encoded = assembled_sections.assemble(del_ind);
-            self.stream.write_raw_window(&encoded)?;
-        } else {
-            // Track section sizes via finish_sections for capacity hints.
-            let sections = we.finish_sections(Some(window));
-            self.last_data_size = sections.data_section.len();
-            self.last_inst_size = sections.inst_section.len();
-            self.last_addr_size = sections.addr_section.len();
-            let encoded = sections.assemble(0);
-            self.stream.write_raw_window(&encoded)?;
-        }
-
-        self.windows_written += 1;
-        Ok(())
-    }
-
-    /// Find matches using the (reused) match engine.
-    fn find_matches(&mut self, target: &[u8]) -> Vec<Instruction> {
-        let engine = self.engine.as_mut().expect("engine required for level > 0");
-
-        if self.source.is_empty() {
-            engine.find_matches(target, None::<&&[u8]>)
-        } else {
-            let src: &[u8] = self.source;
-            engine.find_matches(target, Some(&src))
-        }
-    }
-}
-
-/// Convenience: encode an entire target at once.
-pub fn encode_all<W: Write>(
-    writer: W,
-    source: &[u8],
-    target: &[u8],
-    mut opts: CompressOptions,
-) -> Result<W, EncodeError> {
-    // Cap window_size to actual target length to avoid over-allocating
-    // hash tables for small inputs.
-    if target.len() < opts.window_size {
-        opts.window_size = target.len().max(64);
-    }
-    let mut enc = DeltaEncoder::new(writer, source, opts);
-    enc.write_target(target)?;
-    let (w, _) = enc.finish()?;
-    Ok(w)
-}
-
-/// Convenience: encode an entire target using parallel independent windows.
-///
-/// This path is gated behind the `parallel` feature and is disabled by default.
-/// It preserves output validity and compatibility, but because each window is
-/// matched independently, instruction choices may differ from `encode_all`.
-#[cfg(feature = "parallel")]
-pub fn encode_all_parallel<W: Write>(
-    writer: W,
-    source: &[u8],
-    target: &[u8],
-    mut opts: CompressOptions,
-) -> Result<W, EncodeError> {
-    if target.len() < opts.window_size {
-        opts.window_size = target.len().max(64);
-    }
-
-    // Keep behavior identical for empty targets.
-    if target.is_empty() {
-        return encode_all(writer, source, target, opts);
-    }
-
-    let window_size = opts.window_size.max(64);
-    let config = config::config_for_level(opts.level);
-    let source_win = if !source.is_empty() {
-        Some(SourceWindow {
-            len: source.len() as u64,
-            offset: 0,
-        })
-    } else {
-        None
-    };
-    let chunks: Vec<&[u8]> = target.chunks(window_size).collect();
-
-    let windows: Result<Vec<Vec<u8>>, EncodeError> = chunks
-        .par_iter()
-        .map(|chunk| {
-            let instructions = if opts.level == 0 {
-                if chunk.is_empty() {
-                    Vec::new()
-                } else {
-                    vec![Instruction::Add {
-                        len: chunk.len() as u32,
-                    }]
-                }
-            } else {
-                let mut engine = if !source.is_empty() {
-                    let src: &[u8] = source;
-                    let mut eng = MatchEngine::new(config, src.len() as u64, chunk.len().max(64));
-                    eng.index_source(&src);
-                    eng
-                } else {
-                    MatchEngine::new(config, 0, chunk.len().max(64))
-                };
-
-                let raw = if source.is_empty() {
-                    engine.find_matches(chunk, None::<&&[u8]>)
-                } else {
-                    let src: &[u8] = source;
-                    engine.find_matches(chunk, Some(&src))
-                };
-                pipeline::optimize(&raw, chunk)
-            };
-
-            let mut we = WindowEncoder::new(source_win, opts.checksum);
-            emit_instructions(&mut we, chunk, &instructions);
-
-            if let Some(backend) = opts.secondary.backend() {
-                let sections = we.finish_sections(Some(chunk));
-                let (comp_data, comp_inst, comp_addr, del_ind) = secondary::compress_sections(
-                    backend.as_ref(),
-                    &sections.data_section,
-                    &sections.inst_section,
-                    &sections.addr_section,
-                )?;
-                let assembled_sections = crate::vcdiff::encoder::WindowSections {
-                    source_window: sections.source_window,
-                    target_len: sections.target_len,
-                    checksum: sections.checksum,
-                    data_section: comp_data,
-                    inst_section: comp_inst,
-                    addr_section: comp_addr,
-                };
-                Ok(assembled_sections.assemble(del_ind))
-            } else {
-                Ok(we.finish_sections(Some(chunk)).assemble(0))
-            }
-        })
-        .collect();
-
-    let mut stream = StreamEncoder::new(writer, opts.checksum);
-    if let Some(backend) = opts.secondary.backend() {
-        stream.set_secondary_id(backend.id());
-    }
-
-    for window in windows? {
-        stream.write_raw_window(&window)?;
-    }
-
-    Ok(stream.finish()?)
-}
-
-// ---------------------------------------------------------------------------
-// Instruction emission helper
-// ---------------------------------------------------------------------------
-
-fn emit_instructions(we: &mut WindowEncoder, target: &[u8], instructions: &[Instruction]) {
-    let mut target_pos = 0usize;
-
-    for inst in instructions {
-        match *inst {
-            Instruction::Add { len } => {
-                let len = len as usize;
-                we.add(&target[target_pos..target_pos + len]);
-                target_pos += len;
-            }
-            Instruction::Copy { len, addr, .. } => {
-                we.copy_with_auto_mode(len, addr);
-                target_pos += len as usize;
-            }
-            Instruction::Run { len } => {
-                let byte = target[target_pos];
-                we.run(len, byte);
-                target_pos += len as usize;
-            }
-        }
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn roundtrip(source: &[u8], target: &[u8], opts: CompressOptions) -> Vec<u8> {
-        let mut output = Vec::new();
-        let enc = DeltaEncoder::new(&mut output, source, opts);
-        // Use encode_all convenience instead to keep it simple.
-        drop(enc);
-        output.clear();
-
-        encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
-
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, source).unwrap();
-        assert_eq!(decoded, target, "roundtrip mismatch");
-        output
-    }
-
-    #[test]
-    fn encode_all_roundtrip() {
-        let source = b"The quick brown fox jumps over the lazy dog.";
-        let target = b"The quick brown cat sits on the lazy mat.";
-        roundtrip(source, target, CompressOptions::default());
-    }
-
-    #[test]
-    fn level_0_store_only() {
-        let target = b"Hello, world!";
-        let mut output = Vec::new();
-        encode_all(
-            &mut output,
-            b"",
-            target,
-            CompressOptions {
-                level: 0,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"").unwrap();
-        assert_eq!(decoded, target);
-    }
-
-    #[test]
-    fn all_levels_roundtrip() {
-        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789abcdefghijklmnopqrstuvwxyz";
-        let target = b"ABCDEFGHIJKLMNOP--CHANGED--UVWXYZ0123456789abcdefghijklmnopqrstuvwxyz!!!";
-
-        for level in 0..=9 {
-            let mut output = Vec::new();
-            encode_all(
-                &mut output,
-                source,
-                target,
-                CompressOptions {
-                    level,
-                    checksum: true,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-
-            let decoded = crate::vcdiff::decoder::decode_memory(&output, source).unwrap();
-            assert_eq!(decoded, target, "level {level} roundtrip failed");
-        }
-    }
-
-    #[test]
-    fn streaming_matches_bulk() {
-        let source = b"AAAA BBBB CCCC DDDD EEEE FFFF GGGG HHHH";
-        let target = b"AAAA CCCC DDDD EEEE xxxx GGGG HHHH IIII";
-
-        // Bulk encode.
-        let mut bulk_output = Vec::new();
-        encode_all(
-            &mut bulk_output,
-            source,
-            target,
-            CompressOptions {
-                level: 6,
-                window_size: 1024, // small windows
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        // Streaming encode (4-byte chunks).
-        let mut stream_output = Vec::new();
-        let mut enc = DeltaEncoder::new(
-            &mut stream_output,
-            source,
-            CompressOptions {
-                level: 6,
-                window_size: 1024,
-                ..Default::default()
-            },
-        );
-        for chunk in target.chunks(4) {
-            enc.write_target(chunk).unwrap();
-        }
-        enc.finish().unwrap();
-
-        // Both should decode to the same target.
-        let bulk_decoded = crate::vcdiff::decoder::decode_memory(&bulk_output, source).unwrap();
-        let stream_decoded = crate::vcdiff::decoder::decode_memory(&stream_output, source).unwrap();
-        assert_eq!(bulk_decoded, target);
-        assert_eq!(stream_decoded, target);
-    }
-
-    #[test]
-    fn empty_target() {
-        let mut output = Vec::new();
-        encode_all(&mut output, b"source", b"", CompressOptions::default()).unwrap();
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"source").unwrap();
-        assert!(decoded.is_empty());
-    }
-
-    #[test]
-    fn no_source() {
-        let target = b"standalone data without any source reference";
-        let mut output = Vec::new();
-        encode_all(&mut output, b"", target, CompressOptions::default()).unwrap();
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"").unwrap();
-        assert_eq!(decoded, target);
-    }
-
-    #[test]
-    fn progress_tracking() {
-        let target = vec![0xAA; 1000];
-        let mut output = Vec::new();
-        let mut enc = DeltaEncoder::new(&mut output, b"", CompressOptions::default());
-        assert_eq!(enc.bytes_in(), 0);
-        assert_eq!(enc.windows_written(), 0);
-        enc.write_target(&target).unwrap();
-        assert_eq!(enc.bytes_in(), 1000);
-        enc.finish().unwrap();
-    }
-
-    #[test]
-    fn xdelta3_can_decode_our_output() {
-        let source = b"The quick brown fox jumps over the lazy dog. 1234567890";
-        let target = b"The quick brown cat sits on the lazy mat. 1234567890!!!";
-        let mut output = Vec::new();
-        encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
-
-        // Verify xdelta3 C library can decode it.
-        match xdelta3::decode(&output, source) {
-            Some(decoded) => assert_eq!(decoded, target),
-            None => panic!("xdelta3 crate failed to decode our output"),
-        }
-    }
-
-    #[cfg(feature = "parallel")]
-    #[test]
-    fn parallel_encode_roundtrip() {
-        let source: Vec<u8> = (0..=255).cycle().take(512 * 1024).collect();
-        let mut target = source.clone();
-        for i in (0..target.len()).step_by(997) {
-            target[i] ^= 0xA5;
-        }
-
-        let mut output = Vec::new();
-        encode_all_parallel(
-            &mut output,
-            &source,
-            &target,
-            CompressOptions {
-                level: 6,
-                window_size: 64 * 1024,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
-        assert_eq!(decoded, target);
-    }
-
-    #[cfg(feature = "lzma-secondary")]
-    #[test]
-    fn secondary_lzma_roundtrip() {
-        let source: Vec<u8> = b"ABCDEFGHIJ".iter().copied().cycle().take(4096).collect();
-        let mut target = source.clone();
-        target[100] = b'X';
-        target[500] = b'Y';
-
-        let mut output = Vec::new();
-        encode_all(
-            &mut output,
-            &source,
-            &target,
-            CompressOptions {
-                level: 6,
-                secondary: SecondaryCompression::Lzma,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
-        assert_eq!(decoded, target);
-    }
-
-    #[cfg(feature = "zlib-secondary")]
-    #[test]
-    fn secondary_zlib_roundtrip() {
-        let source: Vec<u8> = b"ABCDEFGHIJ".iter().copied().cycle().take(4096).collect();
-        let mut target = source.clone();
-        target[100] = b'X';
-        target[500] = b'Y';
-
-        let mut output = Vec::new();
-        encode_all(
-            &mut output,
-            &source,
-            &target,
-            CompressOptions {
-                level: 6,
-                secondary: SecondaryCompression::Zlib { level: 6 },
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
-        assert_eq!(decoded, target);
-    }
-}
+// Streaming delta encoder.
+//
+// DeltaEncoder provides a streaming API for delta compression:
+//   - Source is indexed once upfront (MatchEngine reused across windows),
+//     or per source window when `source_window_size` bounds it
+//   - Target data is fed in chunks via write_target()
+//   - Each full window is compressed and written immediately
+//   - Constant memory: only one target window buffered at a time
+
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+
+use crate::hash::config::{self, MatcherConfig};
+// `SourceData` stays qualified: in scope, its `len` would shadow `<[u8]>::len`.
+#[cfg(feature = "parallel")]
+use crate::hash::matching::SourceIndex;
+use crate::hash::matching::{self, MatchEngine, MatchStats, MatchStrategy, ReaderSource};
+use crate::hash::rolling;
+#[cfg(feature = "suffix-array")]
+use crate::hash::suffix::SuffixArray;
+use crate::vcdiff::code_table::Instruction;
+use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder, WindowSections};
+use crate::vcdiff::header::HARD_MAX_WINSIZE;
+use crate::vcdiff::integrity::{self, Integrity};
+
+use super::pipeline::{self, OptLevel};
+use super::secondary::{self, CompressBackend, SecondaryCompression};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// ---------------------------------------------------------------------------
+// Options
+// ---------------------------------------------------------------------------
+
+/// Configuration for the streaming delta encoder.
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    /// Compression level (0-9). Level 0 = store only (no matching).
+    pub level: u32,
+    /// Maximum target window size in bytes.
+    pub window_size: usize,
+    /// Emit Adler-32 checksums per window.
+    pub checksum: bool,
+    /// Only checksum windows of at least this many target bytes.
+    ///
+    /// Smaller windows (e.g. incremental updates after a large initial
+    /// window) are written without `VCD_ADLER32`, which decoders accept.
+    /// `None` checksums every window. Ignored when `checksum` is off.
+    pub checksum_min_window: Option<usize>,
+    /// Window checksum algorithm.
+    ///
+    /// `Integrity::Crc32c` is an oxidelta extension: the CRC is stored in
+    /// the `VCD_ADLER32` field and declared by a `checksum:crc32c` token in
+    /// the application header (see [`crate::vcdiff::integrity`]). oxidelta
+    /// decoders verify it, but xdelta3 rejects such deltas unless run with
+    /// `-n`. `Integrity::None` is the same as `checksum: false`.
+    pub integrity: Integrity,
+    /// Secondary compression algorithm for VCDIFF sections.
+    pub secondary: SecondaryCompression,
+    /// Always emit runs of this byte as RUN, even where a COPY of equal
+    /// length exists.
+    ///
+    /// Intended for patches applied in place to NOR flash: erased regions
+    /// (`0xFF`) written as RUN need no source reads and are cheap to program,
+    /// so `Some(0xFF)` trades a little delta size for flash-friendly patches.
+    /// Ignored at level 0.
+    pub prefer_run_byte: Option<u8>,
+    /// Instruction-selection effort. `None` derives it from `level`
+    /// (see [`OptLevel::for_level`]).
+    pub opt_level: Option<OptLevel>,
+    /// Instruction optimization buffer, in instructions (xdelta3's iopt
+    /// buffer, `-I`). Coalescing and COPY demotion only look back within
+    /// the buffer (see [`pipeline::optimize_buffered`]); `0` means unbounded.
+    pub iopt_size: usize,
+    /// Never emit target self-copies reaching further back than this many
+    /// bytes; such matches fall back to a source COPY or an ADD.
+    ///
+    /// Bounds the output history a streaming decoder must retain (see
+    /// [`max_self_copy_distance`](crate::compress::max_self_copy_distance)).
+    /// `None` leaves self-copies unrestricted within a window.
+    pub max_self_copy_distance: Option<u64>,
+    /// Search each target window for repeats of its own earlier bytes.
+    ///
+    /// Set to `false` to emit only source COPYs, RUNs and ADDs, for decoders
+    /// that stream output without retaining it (the strict form of
+    /// `max_self_copy_distance`). Ignored at level 0.
+    pub target_self_match: bool,
+    /// Maximum source (copy) window per target window, in bytes.
+    ///
+    /// When the source is larger, [`DeltaEncoder`] slides a window of this
+    /// size along the source, following where recent source matches ended,
+    /// and re-indexes it whenever it moves (like xdelta3's `-B`). Smaller
+    /// windows bound the index memory and keep COPY addresses short, at the
+    /// cost of missing matches outside the window. `None` uses the whole
+    /// source as a single window. `encode_all_parallel` ignores this.
+    pub source_window_size: Option<u64>,
+    /// Custom matcher profile, e.g. from [`MatcherConfig::builder`]. `None`
+    /// uses the profile for `level`. Ignored at level 0, but a profile that
+    /// fails [`MatcherConfig::validate`] is always an `InvalidInput` error.
+    pub matcher: Option<MatcherConfig>,
+    /// End every window except the last at a target offset that is a
+    /// multiple of this many bytes, by shortening it to the boundary.
+    ///
+    /// Aligning windows with the source's natural blocks (e.g. 4 KiB flash
+    /// pages) keeps source copies local to each window. Windows cut short by
+    /// [`DeltaEncoder::set_window_boundaries`] or [`DeltaEncoder::flush`] stay
+    /// where they are; the following windows realign. Values below 2, or
+    /// above `window_size`, disable alignment.
+    pub window_alignment: usize,
+    /// Replace a delta larger than the target with a plain store (ADD-only,
+    /// no source) encoding of the target, whenever that is smaller.
+    ///
+    /// Bounds the delta at the target size plus a few bytes of framing, e.g.
+    /// for OTA updates of incompressible images. [`DeltaEncoder`] then holds
+    /// the delta and the target in memory until `finish`, which reports
+    /// whether the fallback was taken (see [`FinishInfo`]).
+    pub fallback_to_store: bool,
+    /// How source matches are found. `MatchStrategy::SuffixArray` (feature
+    /// `suffix-array`) indexes the whole source in memory, so it ignores
+    /// `source_window_size` and `matcher` and loads a reader-backed source
+    /// up front; without a source the hash engine is used. Ignored at
+    /// level 0.
+    pub match_strategy: MatchStrategy,
+    /// Vary the window length with match density.
+    ///
+    /// After each window, [`DeltaEncoder`] doubles the next window's length
+    /// when source COPYs covered at least three quarters of it, and halves
+    /// it when they covered less than a quarter, between
+    /// `adaptive_min_window` and `adaptive_max_window`. Windows start at
+    /// `window_size`. `encode_all_parallel` ignores this.
+    pub adaptive_windows: bool,
+    /// Shortest window `adaptive_windows` shrinks to; `None` means
+    /// `window_size / 16`, but at least 4 KiB. Never more than
+    /// `window_size`.
+    pub adaptive_min_window: Option<usize>,
+    /// Longest window `adaptive_windows` grows to; `None` means
+    /// [`HARD_MAX_WINSIZE`], the largest window decoders accept, which also
+    /// caps an explicit value.
+    pub adaptive_max_window: Option<usize>,
+    /// Let a window copy from the previous target window instead of the
+    /// source, emitting it as `VCD_TARGET` when that encodes smaller.
+    ///
+    /// Each window is also matched against the target bytes of the window
+    /// before it, which roughly doubles the matching work and keeps one
+    /// more window in memory; it pays off for targets that repeat their own
+    /// content across windows rather than the source's. Decoding a
+    /// `VCD_TARGET` window needs the earlier target output in memory:
+    /// [`decode_all`](crate::compress::decoder::decode_all) handles it, the
+    /// streaming [`DeltaDecoder`](crate::compress::DeltaDecoder) and
+    /// xdelta3 do not. Ignored at level 0 and by `encode_all_parallel`.
+    pub allow_target_windows: bool,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            window_size: 1 << 23, // 8 MiB
+            checksum: true,
+            checksum_min_window: None,
+            integrity: Integrity::Adler32,
+            secondary: SecondaryCompression::None,
+            prefer_run_byte: None,
+            opt_level: None,
+            iopt_size: 0,
+            max_self_copy_distance: None,
+            target_self_match: true,
+            source_window_size: None,
+            matcher: None,
+            window_alignment: 0,
+            fallback_to_store: false,
+            match_strategy: MatchStrategy::Hash,
+            adaptive_windows: false,
+            adaptive_min_window: None,
+            adaptive_max_window: None,
+            allow_target_windows: false,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// The effective instruction-selection level.
+    pub fn effective_opt_level(&self) -> OptLevel {
+        self.opt_level
+            .unwrap_or_else(|| OptLevel::for_level(self.level))
+    }
+
+    /// The effective matcher profile.
+    pub fn effective_matcher(&self) -> MatcherConfig {
+        self.matcher
+            .unwrap_or_else(|| config::config_for_level(self.level))
+    }
+
+    /// Check a custom `matcher` profile (see [`MatcherConfig::validate`]).
+    fn validate_matcher(&self) -> Result<(), EncodeError> {
+        match &self.matcher {
+            Some(matcher) => matcher.validate().map_err(invalid_matcher),
+            None => Ok(()),
+        }
+    }
+
+    /// `window_size` capped to a `target_len`-byte target (but at least 64),
+    /// so a small input does not get hash tables sized for a full window.
+    pub(crate) fn window_size_for(&self, target_len: usize) -> usize {
+        self.window_size.min(target_len.max(64))
+    }
+
+    /// Shortest window `adaptive_windows` shrinks to.
+    fn min_adaptive_window(&self) -> usize {
+        self.adaptive_min_window
+            .unwrap_or((self.window_size / 16).max(4096))
+            .min(self.window_size)
+            .max(1)
+    }
+
+    /// Longest window `adaptive_windows` grows to.
+    fn max_adaptive_window(&self) -> usize {
+        let hard_max = HARD_MAX_WINSIZE as usize;
+        self.adaptive_max_window.unwrap_or(hard_max).min(hard_max)
+    }
+
+    /// Whether a window of `len` target bytes gets a checksum (see
+    /// `checksum_min_window`).
+    pub fn window_checksum(&self, len: usize) -> bool {
+        self.checksum
+            && self.integrity != Integrity::None
+            && self.checksum_min_window.is_none_or(|min| len >= min)
+    }
+
+    /// Whether windows may carry CRC-32C checksums, which the application
+    /// header must declare.
+    fn declares_crc32c(&self) -> bool {
+        self.checksum && self.integrity == Integrity::Crc32c
+    }
+
+    /// An empty window encoder for `len` target bytes, checksummed as
+    /// these options say.
+    fn window_encoder(&self, source: Option<SourceWindow>, len: usize) -> WindowEncoder {
+        let mut we = WindowEncoder::new(source, self.window_checksum(len));
+        we.set_integrity(self.integrity);
+        we
+    }
+
+    /// A stream encoder whose application header declares the window
+    /// checksum, if it is not the standard Adler-32.
+    fn stream_encoder<W: Write>(&self, writer: W) -> StreamEncoder<W> {
+        let mut stream = StreamEncoder::new(writer, self.checksum);
+        if self.declares_crc32c() {
+            stream.set_app_header(integrity::with_crc32c_token(None));
+        }
+        stream
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// Writing the delta failed.
+    Io(std::io::Error),
+    /// The secondary compression backend failed on a window section.
+    Secondary(String),
+    /// The options or arguments cannot be encoded (e.g. a zero window size).
+    InvalidInput(String),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Secondary(msg) => write!(f, "secondary compression failed: {msg}"),
+            Self::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Secondary(_) | Self::InvalidInput(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for EncodeError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn secondary_error(e: std::io::Error) -> EncodeError {
+    EncodeError::Secondary(e.to_string())
+}
+
+fn zero_window_size() -> EncodeError {
+    EncodeError::InvalidInput("window size must be non-zero".into())
+}
+
+fn invalid_matcher(e: config::MatcherConfigError) -> EncodeError {
+    EncodeError::InvalidInput(format!("matcher profile: {e}"))
+}
+
+// ---------------------------------------------------------------------------
+// DeltaEncoder
+// ---------------------------------------------------------------------------
+
+/// Streaming delta encoder.
+///
+/// Indexes the source once upfront, then processes target data in windows.
+/// Each completed window is immediately compressed and written to the output.
+///
+/// # Example
+/// ```no_run
+/// use oxidelta::compress::encoder::{DeltaEncoder, CompressOptions};
+/// let source = b"original data";
+/// let target = b"modified data";
+/// let mut output = Vec::new();
+/// let mut enc = DeltaEncoder::new(&mut output, source, CompressOptions::default());
+/// enc.write_target(target).unwrap();
+/// enc.finish().unwrap();
+/// ```
+pub struct DeltaEncoder<'s, W: Write> {
+    stream: StreamEncoder<Output<W>>,
+    opts: CompressOptions,
+    _config: MatcherConfig,
+    /// Why the custom matcher profile is unusable, reported by the first
+    /// `write_target` or `finish`.
+    invalid_matcher: Option<config::MatcherConfigError>,
+    source: EncoderSource<'s>,
+    /// Offset of `source` within the source the decoder is given; added to
+    /// every window's copy window offset (see `encode_changed_region`).
+    src_base: u64,
+    /// Active source window `[src_start, src_start + src_len)`.
+    src_start: usize,
+    src_len: usize,
+    /// Source position expected to line up with the next target window.
+    src_cursor: u64,
+    /// Whether `set_source_window` fixed the source window, so it no longer
+    /// follows `src_cursor`.
+    src_pinned: bool,
+    engine: Option<MatchEngine>,
+    /// Source index for `MatchStrategy::SuffixArray` (replaces `engine`).
+    #[cfg(feature = "suffix-array")]
+    suffix_array: Option<SuffixArray>,
+    buffer: Vec<u8>,
+    bytes_in: u64,
+    /// Target bytes already encoded into emitted windows.
+    bytes_encoded: u64,
+    /// Pending caller-requested window break offsets (ascending).
+    boundaries: std::collections::VecDeque<u64>,
+    /// Length of the next window before alignment and boundaries;
+    /// `window_size` unless `adaptive_windows` changed it.
+    window_len: usize,
+    windows_written: u64,
+    /// Section size hints from the previous window (for capacity pre-allocation).
+    last_data_size: usize,
+    last_inst_size: usize,
+    last_addr_size: usize,
+    /// Match statistics summed over the windows encoded so far.
+    match_stats: MatchStats,
+    /// Section sizes summed over the windows encoded so far.
+    section_bytes: SectionBytes,
+    /// Target bytes received, kept for `fallback_to_store`.
+    held_target: Vec<u8>,
+    /// Previous window's target bytes and their offset, kept for
+    /// `allow_target_windows`.
+    prev_target: Vec<u8>,
+    prev_target_offset: u64,
+    /// Match engine indexing `prev_target`.
+    target_engine: Option<MatchEngine>,
+    /// Per-block target digests, once enabled.
+    #[cfg(feature = "block-hashes")]
+    block_hasher: Option<BlockHasher>,
+}
+
+/// What [`DeltaEncoder::finish`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishInfo {
+    /// Total number of windows written.
+    pub windows: u64,
+    /// Whether [`CompressOptions::fallback_to_store`] replaced the delta
+    /// with a store encoding.
+    pub store_fallback: bool,
+    /// SHA-256 of each [`BLOCK_HASH_SIZE`] block of the target, the last
+    /// one possibly short. Empty unless block hashes were enabled (see
+    /// `DeltaEncoder::enable_block_hashes`).
+    pub block_hashes: Vec<[u8; 32]>,
+}
+
+/// Totals over a whole encode, from [`DeltaEncoder::finish_with_report`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodeReport {
+    /// Total number of windows written.
+    pub windows: u64,
+    /// Target bytes encoded.
+    pub target_bytes: u64,
+    /// Delta bytes written, file header included.
+    pub delta_bytes: u64,
+    /// Whether the store fallback replaced the delta (see
+    /// [`FinishInfo::store_fallback`]). `stats` and `sections` still
+    /// describe the delta that was discarded.
+    pub store_fallback: bool,
+    /// Match statistics summed over all windows.
+    pub stats: MatchStats,
+    /// Section sizes summed over all windows.
+    pub sections: SectionBytes,
+}
+
+impl EncodeReport {
+    /// Delta size as a fraction of the target size (0 for an empty target).
+    pub fn ratio(&self) -> f64 {
+        if self.target_bytes == 0 {
+            0.0
+        } else {
+            self.delta_bytes as f64 / self.target_bytes as f64
+        }
+    }
+}
+
+/// Bytes in the three sections of VCDIFF windows, after secondary
+/// compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SectionBytes {
+    /// Data section (ADD and RUN bytes).
+    pub data: u64,
+    /// Instruction section.
+    pub inst: u64,
+    /// Address section (COPY addresses).
+    pub addr: u64,
+}
+
+impl SectionBytes {
+    /// Sum of all three sections.
+    pub fn total(&self) -> u64 {
+        self.data + self.inst + self.addr
+    }
+
+    fn add(&mut self, sections: &WindowSections) {
+        self.data += sections.data_section.len() as u64;
+        self.inst += sections.inst_section.len() as u64;
+        self.addr += sections.addr_section.len() as u64;
+    }
+}
+
+/// Block size of [`FinishInfo::block_hashes`].
+pub const BLOCK_HASH_SIZE: usize = 4096;
+
+/// SHA-256 of every [`BLOCK_HASH_SIZE`] block of a byte stream, fed in
+/// arbitrary chunks.
+#[cfg(feature = "block-hashes")]
+#[derive(Default)]
+pub(crate) struct BlockHasher {
+    hasher: sha2::Sha256,
+    filled: usize,
+    hashes: Vec<[u8; 32]>,
+}
+
+#[cfg(feature = "block-hashes")]
+impl BlockHasher {
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        use sha2::Digest;
+
+        while !data.is_empty() {
+            let take = (BLOCK_HASH_SIZE - self.filled).min(data.len());
+            self.hasher.update(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == BLOCK_HASH_SIZE {
+                self.hashes.push(self.hasher.finalize_reset().into());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// The digests, including the trailing partial block.
+    pub(crate) fn finish(mut self) -> Vec<[u8; 32]> {
+        use sha2::Digest;
+
+        if self.filled > 0 {
+            self.hashes.push(self.hasher.finalize().into());
+        }
+        self.hashes
+    }
+}
+
+/// Where a [`DeltaEncoder`] writes: straight through, or held back until
+/// `finish` while `fallback_to_store` may still replace the delta.
+enum Output<W> {
+    Direct(W),
+    Held { writer: W, delta: Vec<u8> },
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Direct(w) => w.write(buf),
+            Self::Held { delta, .. } => delta.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Direct(w) => w.flush(),
+            Self::Held { .. } => Ok(()),
+        }
+    }
+}
+
+impl<'s, W: Write> DeltaEncoder<'s, W> {
+    /// Create a new streaming encoder.
+    ///
+    /// The source is indexed immediately. For level 0, no index is built.
+    pub fn new(writer: W, source: &'s [u8], opts: CompressOptions) -> Self {
+        Self::with_source(writer, EncoderSource::Slice(source), opts)
+    }
+
+    /// Create a streaming encoder that reads the source from `source_reader`.
+    ///
+    /// `source_len` is the number of source bytes. The source is indexed in
+    /// chunks and match extension re-reads it through a bounded block cache,
+    /// so memory use stays flat however large the source is; this is why the
+    /// reader must also be seekable. The output is identical to [`new`] on
+    /// the same bytes.
+    ///
+    /// [`new`]: Self::new
+    pub fn from_reader<R: Read + Seek + 's>(
+        writer: W,
+        source_reader: R,
+        source_len: u64,
+        opts: CompressOptions,
+    ) -> Self {
+        let reader: Box<dyn ReadSeek + 's> = Box::new(source_reader);
+        Self::with_source(
+            writer,
+            EncoderSource::Reader(ReaderSource::new(reader, source_len)),
+            opts,
+        )
+    }
+
+    /// Create a streaming encoder that matches against a shared `dictionary`
+    /// as well as `source`.
+    ///
+    /// The dictionary is placed in front of the source and both are indexed
+    /// together, so the delta's source window addresses `dictionary ||
+    /// source`. To decode, pass that same concatenation as the source:
+    ///
+    /// ```
+    /// use oxidelta::compress::decoder;
+    /// use oxidelta::compress::encoder::{CompressOptions, DeltaEncoder};
+    ///
+    /// let dictionary = b"<!DOCTYPE html><html><head><meta charset=utf-8>";
+    /// let source = b"<body>version one</body>";
+    /// let target = b"<!DOCTYPE html><html><head><meta charset=utf-8><body>version two</body>";
+    ///
+    /// let mut delta = Vec::new();
+    /// let opts = CompressOptions::default();
+    /// let mut enc = DeltaEncoder::with_dictionary(&mut delta, source, dictionary, opts);
+    /// enc.write_target(target).unwrap();
+    /// enc.finish().unwrap();
+    ///
+    /// let decode_source = [&dictionary[..], &source[..]].concat();
+    /// assert_eq!(decoder::decode_all(&decode_source, &delta).unwrap(), target);
+    /// ```
+    pub fn with_dictionary(
+        writer: W,
+        source: &[u8],
+        dictionary: &[u8],
+        opts: CompressOptions,
+    ) -> Self {
+        let combined = [dictionary, source].concat();
+        Self::with_source(writer, EncoderSource::Owned(combined), opts)
+    }
+
+    fn with_source(writer: W, source: EncoderSource<'s>, opts: CompressOptions) -> Self {
+        let config = opts.effective_matcher();
+        let invalid_matcher = config.validate().err();
+
+        let output = if opts.fallback_to_store {
+            Output::Held {
+                writer,
+                delta: Vec::new(),
+            }
+        } else {
+            Output::Direct(writer)
+        };
+        let mut stream = opts.stream_encoder(output);
+        if let Some(backend) = opts.secondary.backend() {
+            stream.set_secondary_id(backend.id());
+        }
+
+        let use_suffix_array =
+            opts.level > 0 && !source.is_empty() && opts.match_strategy != MatchStrategy::Hash;
+        #[cfg(feature = "suffix-array")]
+        let (source, suffix_array) = if use_suffix_array {
+            let source = source.into_memory();
+            let index = SuffixArray::new(source.as_slice().expect("source is in memory"));
+            (source, Some(index))
+        } else {
+            (source, None)
+        };
+
+        let src_len = match opts.source_window_size {
+            Some(limit) if !use_suffix_array => source.len().min(limit.max(1) as usize),
+            _ => source.len(),
+        };
+
+        // Build the match engine and index the (first) source window.
+        let engine = if use_suffix_array || invalid_matcher.is_some() {
+            None
+        } else if opts.level > 0 && !source.is_empty() {
+            let src = source.view(0, src_len);
+            let mut eng = MatchEngine::new(config, src_len as u64, opts.window_size.max(64));
+            eng.set_prefer_run_byte(opts.prefer_run_byte);
+            eng.set_max_self_copy_distance(opts.max_self_copy_distance);
+            eng.set_target_self_match(opts.target_self_match);
+            eng.index_source(&src);
+            Some(eng)
+        } else if opts.level > 0 {
+            // No source, but still do target self-matching.
+            let mut eng = MatchEngine::new(config, 0, opts.window_size.max(64));
+            eng.set_prefer_run_byte(opts.prefer_run_byte);
+            eng.set_max_self_copy_distance(opts.max_self_copy_distance);
+            eng.set_target_self_match(opts.target_self_match);
+            Some(eng)
+        } else {
+            None // Level 0: no matching at all.
+        };
+
+        Self {
+            stream,
+            window_len: opts.window_size,
+            opts,
+            _config: config,
+            invalid_matcher,
+            source,
+            src_base: 0,
+            src_start: 0,
+            src_len,
+            src_cursor: 0,
+            src_pinned: false,
+            engine,
+            #[cfg(feature = "suffix-array")]
+            suffix_array,
+            buffer: Vec::new(),
+            bytes_in: 0,
+            bytes_encoded: 0,
+            boundaries: std::collections::VecDeque::new(),
+            windows_written: 0,
+            last_data_size: 0,
+            last_inst_size: 0,
+            last_addr_size: 0,
+            match_stats: MatchStats::default(),
+            section_bytes: SectionBytes::default(),
+            held_target: Vec::new(),
+            prev_target: Vec::new(),
+            prev_target_offset: 0,
+            target_engine: None,
+            #[cfg(feature = "block-hashes")]
+            block_hasher: None,
+        }
+    }
+
+    /// Compress sections with `backend`, recording `id` as the file header's
+    /// secondary compressor ID.
+    ///
+    /// Overrides `CompressOptions::secondary`. Decoders need the same
+    /// backend registered under `id` (see `StreamDecoder::register_secondary`).
+    ///
+    /// Fails with [`EncodeError::InvalidInput`] once a window has been
+    /// written, since the file header carrying the ID is already out.
+    pub fn register_secondary(
+        &mut self,
+        id: u8,
+        backend: Arc<dyn CompressBackend>,
+    ) -> Result<(), EncodeError> {
+        if self.windows_written > 0 {
+            return Err(EncodeError::InvalidInput(
+                "register_secondary after the first window was written".into(),
+            ));
+        }
+        self.stream.set_secondary_id(id);
+        self.opts.secondary = SecondaryCompression::Custom(backend);
+        Ok(())
+    }
+
+    /// Store `data` as the delta's application header. Must be called before
+    /// any window is written.
+    ///
+    /// With `Integrity::Crc32c` checksums, the `checksum:crc32c` token is
+    /// appended to `data` (see [`CompressOptions::integrity`]).
+    pub fn set_app_header(&mut self, data: Vec<u8>) {
+        debug_assert_eq!(self.windows_written, 0);
+        let data = if self.opts.declares_crc32c() {
+            integrity::with_crc32c_token(Some(&data))
+        } else {
+            data
+        };
+        self.stream.set_app_header(data);
+    }
+
+    /// Record the expected SHA-256 of the target as a `sha256:<hex>`
+    /// application header, which [`patch_file_verified`] checks the output
+    /// against. Must be called before any window is written.
+    ///
+    /// [`patch_file_verified`]: crate::io::patch_file_verified
+    pub fn set_target_sha256(&mut self, digest: &[u8; 32]) {
+        let mut header = String::from("sha256:");
+        for b in digest {
+            header.push_str(&format!("{b:02x}"));
+        }
+        self.set_app_header(header.into_bytes());
+    }
+
+    /// Hash the target in [`BLOCK_HASH_SIZE`] blocks as it is written, and
+    /// report the digests in [`FinishInfo::block_hashes`].
+    ///
+    /// A recipient holding the digests can verify any region of the patched
+    /// file without hashing all of it.
+    ///
+    /// Fails with [`EncodeError::InvalidInput`] once target data has been
+    /// written, since those bytes would be missing from the digests.
+    #[cfg(feature = "block-hashes")]
+    pub fn enable_block_hashes(&mut self) -> Result<(), EncodeError> {
+        if self.bytes_in > 0 {
+            return Err(EncodeError::InvalidInput(
+                "enable_block_hashes after target data was written".into(),
+            ));
+        }
+        self.block_hasher.get_or_insert_with(BlockHasher::default);
+        Ok(())
+    }
+
+    /// Copy from source bytes `[offset, offset + len)` for target data
+    /// written from now on. Buffered target data is first encoded as a
+    /// window with the previous source window (see [`flush`](Self::flush)).
+    ///
+    /// Windows record the region as their copy window (`copy_window_offset`
+    /// and `copy_window_len`), so COPY addresses are relative to `offset`.
+    /// The region is indexed immediately and stays fixed, replacing the
+    /// sliding window of `source_window_size`, until this is called again.
+    /// Callers that know which region of a large source each part of the
+    /// target came from use this to keep indexes and addresses small.
+    ///
+    /// Fails if the region is empty or extends past the source, or under
+    /// `MatchStrategy::SuffixArray`, which always indexes the whole source.
+    pub fn set_source_window(&mut self, offset: u64, len: u64) -> Result<(), EncodeError> {
+        let total = self.source.len() as u64;
+        if len == 0 || offset.checked_add(len).is_none_or(|end| end > total) {
+            return Err(EncodeError::InvalidInput(format!(
+                "source window [{offset}, +{len}) is outside the {total}-byte source"
+            )));
+        }
+        #[cfg(feature = "suffix-array")]
+        if self.suffix_array.is_some() {
+            return Err(EncodeError::InvalidInput(
+                "source windows are not supported by the suffix-array strategy".into(),
+            ));
+        }
+
+        self.flush()?;
+        let (start, len) = (offset as usize, len as usize);
+        if let Some(engine) = self.engine.as_mut()
+            && (start, len) != (self.src_start, self.src_len)
+        {
+            engine.reset_source_index();
+            engine.index_source(&self.source.view(start, len));
+        }
+        self.src_start = start;
+        self.src_len = len;
+        self.src_cursor = offset;
+        self.src_pinned = true;
+        Ok(())
+    }
+
+    /// Force window breaks at the given absolute target offsets.
+    ///
+    /// Each window ends exactly at every boundary, regardless of
+    /// `window_size` (windows are still split at `window_size` in between).
+    /// Structure-aware callers use this to align windows to e.g. archive
+    /// members or ELF sections. Offsets must be strictly increasing and lie
+    /// beyond the target bytes already written; any boundary past the end of
+    /// the target is reported as an error by `finish`. Replaces previously set
+    /// boundaries.
+    pub fn set_window_boundaries(&mut self, boundaries: &[u64]) -> Result<(), EncodeError> {
+        let mut prev = self.bytes_in;
+        for &b in boundaries {
+            if b <= prev {
+                return Err(EncodeError::InvalidInput(format!(
+                    "window boundary {b} must be increasing and beyond offset {prev}"
+                )));
+            }
+            prev = b;
+        }
+        self.boundaries = boundaries.iter().copied().collect();
+        Ok(())
+    }
+
+    /// Feed target data to the encoder.
+    ///
+    /// Data is buffered internally. Whenever the buffer reaches `window_size`
+    /// (or the next window boundary), a complete window is encoded and
+    /// written to the output.
+    pub fn write_target(&mut self, data: &[u8]) -> Result<(), EncodeError> {
+        if let Some(e) = self.invalid_matcher {
+            return Err(invalid_matcher(e));
+        }
+        if self.opts.window_size == 0 && !data.is_empty() {
+            return Err(zero_window_size());
+        }
+        self.record_target(data);
+        let mut offset = 0usize;
+
+        while offset < data.len() {
+            let limit = self.next_window_len();
+
+            // Fast path: encode full windows directly from caller-provided input.
+            if self.buffer.is_empty() && data.len() - offset >= limit {
+                self.encode_window(&data[offset..offset + limit])?;
+                offset += limit;
+                continue;
+            }
+
+            // Buffer towards a partial window.
+            let take = (limit - self.buffer.len()).min(data.len() - offset);
+            self.buffer.extend_from_slice(&data[offset..offset + take]);
+            offset += take;
+
+            if self.buffer.len() == limit {
+                let window = std::mem::take(&mut self.buffer);
+                self.encode_window(&window)?;
+                self.buffer = window;
+                self.buffer.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Feed target data held in several buffers, in order.
+    ///
+    /// Equivalent to one [`write_target`](Self::write_target) call per
+    /// chunk: windows end at the same offsets as for the concatenated bytes,
+    /// and full windows inside a chunk are encoded without being copied.
+    pub fn write_target_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), EncodeError> {
+        for chunk in chunks {
+            self.write_target(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Account for target bytes received, whichever way they are encoded.
+    fn record_target(&mut self, data: &[u8]) {
+        self.bytes_in += data.len() as u64;
+        if self.opts.fallback_to_store {
+            self.held_target.extend_from_slice(data);
+        }
+        #[cfg(feature = "block-hashes")]
+        if let Some(hasher) = &mut self.block_hasher {
+            hasher.update(data);
+        }
+    }
+
+    /// Encode `target`, equal to the source bytes at absolute `offset`, as
+    /// windows holding one source COPY each, without running the matcher.
+    /// Buffered data must have been flushed.
+    fn write_source_copy(&mut self, offset: u64, target: &[u8]) -> Result<(), EncodeError> {
+        debug_assert!(self.buffer.is_empty());
+        self.record_target(target);
+        let mut pos = 0;
+        while pos < target.len() {
+            let len = self.next_window_len().min(target.len() - pos);
+            let source_win = SourceWindow {
+                len: len as u64,
+                offset: offset + pos as u64,
+            };
+            let mut we = self.opts.window_encoder(Some(source_win), len);
+            we.copy_with_auto_mode(len as u32, 0);
+            let sections = we.finish_sections(Some(&target[pos..pos + len]));
+            self.write_sections(sections, 0)?;
+            let copy = Instruction::Copy {
+                len: len as u32,
+                addr: 0,
+                mode: 0,
+            };
+            self.match_stats.tally(&[copy], len as u64);
+            self.bytes_encoded += len as u64;
+            self.windows_written += 1;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Length of the next window: `window_size`, shortened to end on a
+    /// `window_alignment` multiple and cut short at the next pending boundary.
+    fn next_window_len(&mut self) -> usize {
+        let mut len = self.window_len;
+        let alignment = self.opts.window_alignment;
+        if alignment > 1 && alignment <= len {
+            // At least one multiple of `alignment` lies past `bytes_encoded`.
+            let end = (self.bytes_encoded + len as u64) / alignment as u64 * alignment as u64;
+            len = (end - self.bytes_encoded) as usize;
+        }
+        while let Some(&b) = self.boundaries.front() {
+            if b <= self.bytes_encoded {
+                self.boundaries.pop_front();
+                continue;
+            }
+            return len.min((b - self.bytes_encoded) as usize);
+        }
+        len
+    }
+
+    /// Encode any buffered target data as a window now, instead of waiting
+    /// for a full window or [`finish`](Self::finish).
+    pub fn flush(&mut self) -> Result<(), EncodeError> {
+        if !self.buffer.is_empty() {
+            let window = std::mem::take(&mut self.buffer);
+            self.encode_window(&window)?;
+            self.buffer = window;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Encode the buffered target as a window, however short, and flush it
+    /// through to the writer.
+    ///
+    /// For interactive uses such as live sync, where output cannot wait for
+    /// a full `window_size` buffer: latency is bounded by how often this is
+    /// called. Short windows find fewer matches and each carries its own
+    /// header, so frequent calls cost ratio. Later
+    /// [`write_target`](Self::write_target) calls start a fresh window.
+    ///
+    /// Fails with [`EncodeError::InvalidInput`] under
+    /// [`CompressOptions::fallback_to_store`], which holds the whole delta
+    /// back until `finish`.
+    pub fn flush_partial(&mut self) -> Result<(), EncodeError> {
+        if self.opts.fallback_to_store {
+            return Err(EncodeError::InvalidInput(
+                "flush_partial cannot emit windows while fallback_to_store holds the delta".into(),
+            ));
+        }
+        self.flush()?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Flush any remaining buffered data and finalize the stream.
+    ///
+    /// Returns the underlying writer and what was written.
+    pub fn finish(self) -> Result<(W, FinishInfo), EncodeError> {
+        let (writer, info, _) = self.finish_with_report()?;
+        Ok((writer, info))
+    }
+
+    /// Like [`finish`](Self::finish), also returning totals over the whole
+    /// encode: match statistics, section sizes and bytes in and out.
+    pub fn finish_with_report(mut self) -> Result<(W, FinishInfo, EncodeReport), EncodeError> {
+        if let Some(e) = self.invalid_matcher {
+            return Err(invalid_matcher(e));
+        }
+        if let Some(&b) = self.boundaries.iter().find(|&&b| b > self.bytes_in) {
+            return Err(EncodeError::InvalidInput(format!(
+                "window boundary {b} is beyond the end of the target ({} bytes)",
+                self.bytes_in
+            )));
+        }
+
+        // Encode the remaining buffer as a final window.
+        if !self.buffer.is_empty() {
+            let remaining = std::mem::take(&mut self.buffer);
+            self.encode_window(&remaining)?;
+        }
+
+        // Handle empty target (no windows written at all).
+        if self.windows_written == 0 {
+            let we = self.opts.window_encoder(None, 0);
+            self.stream.write_window(we, Some(b""))?;
+        }
+
+        #[cfg(feature = "block-hashes")]
+        let block_hashes = self
+            .block_hasher
+            .take()
+            .map(BlockHasher::finish)
+            .unwrap_or_default();
+        #[cfg(not(feature = "block-hashes"))]
+        let block_hashes = Vec::new();
+        let mut info = FinishInfo {
+            windows: self.windows_written,
+            store_fallback: false,
+            block_hashes,
+        };
+        let mut report = EncodeReport {
+            windows: self.windows_written,
+            target_bytes: self.bytes_in,
+            delta_bytes: self.stream.bytes_written(),
+            store_fallback: false,
+            stats: self.match_stats,
+            sections: self.section_bytes,
+        };
+        let (mut writer, delta) = match self.stream.finish()? {
+            Output::Direct(writer) => return Ok((writer, info, report)),
+            Output::Held { writer, delta } => (writer, delta),
+        };
+        let mut delta = delta;
+        if delta.len() as u64 > self.bytes_in {
+            let (store, windows) = store_delta(&self.held_target, &self.opts)?;
+            if store.len() < delta.len() {
+                delta = store;
+                info.windows = windows;
+                info.store_fallback = true;
+                report.windows = windows;
+                report.delta_bytes = delta.len() as u64;
+                report.store_fallback = true;
+            }
+        }
+        writer.write_all(&delta)?;
+        writer.flush()?;
+        Ok((writer, info, report))
+    }
+
+    /// Number of target bytes received so far.
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in
+    }
+
+    /// Number of windows written so far.
+    pub fn windows_written(&self) -> u64 {
+        self.windows_written
+    }
+
+    /// Match statistics summed over the windows written so far (buffered
+    /// data is not included until it is [`flush`](Self::flush)ed). Counts
+    /// describe the matcher's output, before instruction optimization.
+    pub fn stats(&self) -> &MatchStats {
+        &self.match_stats
+    }
+
+    /// Slide the source window towards `src_cursor`, re-indexing if it moves.
+    ///
+    /// The window advances in quarter-window steps and keeps at least a
+    /// quarter behind the cursor, so small shifts between source and target
+    /// do not force a re-index on every target window.
+    fn position_source_window(&mut self) {
+        let total = self.source.len();
+        if self.src_len >= total || self.src_pinned {
+            return;
+        }
+        let step = (self.src_len / 4).max(1);
+        let cursor = (self.src_cursor as usize).min(total);
+        let start = ((cursor / step).saturating_sub(1) * step).min(total - self.src_len);
+        if start == self.src_start {
+            return;
+        }
+
+        if let Some(engine) = self.engine.as_mut() {
+            // Carry the pending MATCH_TARGET probe over to the new window.
+            let probe = self.src_start as u64 + engine.match_srcpos;
+            engine.reset_source_index();
+            engine.index_source(&self.source.view(start, self.src_len));
+            if let Some(pos) = probe.checked_sub(start as u64)
+                && pos < self.src_len as u64
+            {
+                engine.match_srcpos = pos;
+            }
+        }
+        self.src_start = start;
+    }
+
+    /// Advance `src_cursor` past the window just encoded: to the end of its
+    /// last source COPY plus any target bytes after it, or by the window
+    /// length when nothing was copied from the source.
+    fn advance_source_cursor(&mut self, instructions: &[Instruction], window_len: usize) {
+        let mut target_pos = 0u64;
+        let mut last_copy_end = None;
+        for inst in instructions {
+            let len = match *inst {
+                Instruction::Add { len } | Instruction::Run { len } => len as u64,
+                Instruction::Copy { len, addr, .. } => {
+                    if addr < self.src_len as u64 {
+                        let src_end = self.src_start as u64 + addr + len as u64;
+                        last_copy_end = Some((src_end, target_pos + len as u64));
+                    }
+                    len as u64
+                }
+            };
+            target_pos += len;
+        }
+        self.src_cursor = match last_copy_end {
+            Some((src_end, target_end)) => src_end + (window_len as u64 - target_end),
+            None => self.src_cursor + window_len as u64,
+        };
+    }
+
+    /// Size the next window from the source-copy coverage of the window
+    /// just encoded (see `CompressOptions::adaptive_windows`).
+    fn adapt_window_len(&mut self, instructions: &[Instruction], window_len: usize) {
+        let mut stats = MatchStats::default();
+        stats.tally(instructions, self.src_len as u64);
+        let covered = stats.bytes_copied_source * 4;
+        let len = window_len as u64;
+        self.window_len = if covered >= 3 * len {
+            self.window_len
+                .saturating_mul(2)
+                .min(self.opts.max_adaptive_window())
+        } else if covered < len {
+            (self.window_len / 2).max(self.opts.min_adaptive_window())
+        } else {
+            self.window_len
+        };
+    }
+
+    /// Encode a single target window.
+    fn encode_window(&mut self, window: &[u8]) -> Result<(), EncodeError> {
+        self.position_source_window();
+        let source_win = if !self.source.is_empty() {
+            Some(SourceWindow {
+                len: self.src_len as u64,
+                offset: self.src_base + self.src_start as u64,
+            })
+        } else {
+            None
+        };
+
+        // Find matches (or just ADD for level 0).
+        let instructions = if self.opts.level == 0 {
+            let adds = if window.is_empty() {
+                Vec::new()
+            } else {
+                vec![Instruction::Add {
+                    len: window.len() as u32,
+                }]
+            };
+            self.match_stats.tally(&adds, self.src_len as u64);
+            adds
+        } else {
+            let raw = self.find_matches(window);
+            if let Some(engine) = self.engine.as_ref() {
+                self.match_stats += *engine.last_stats();
+            }
+            pipeline::optimize_buffered(
+                &raw,
+                window,
+                self.src_len as u64,
+                self.opts.effective_opt_level(),
+                self.opts.iopt_size,
+            )
+        };
+        self.advance_source_cursor(&instructions, window.len());
+        if self.opts.adaptive_windows {
+            self.adapt_window_len(&instructions, window.len());
+        }
+
+        // Build the VCDIFF window with capacity hints from previous window.
+        let mut we = if self.last_data_size > 0 {
+            WindowEncoder::with_capacity(
+                source_win,
+                self.opts.window_checksum(window.len()),
+                self.last_data_size,
+                self.last_inst_size,
+                self.last_addr_size,
+            )
+        } else {
+            WindowEncoder::new(source_win, self.opts.window_checksum(window.len()))
+        };
+        we.set_integrity(self.opts.integrity);
+        emit_instructions(&mut we, window, &instructions);
+        let sections = we.finish_sections(Some(window));
+        // Track section sizes for next window's capacity hints.
+        self.last_data_size = sections.data_section.len();
+        self.last_inst_size = sections.inst_section.len();
+        self.last_addr_size = sections.addr_section.len();
+
+        // Finalize: with or without secondary compression.
+        let backend = self.opts.secondary.backend();
+        let (mut sections, mut del_ind) = compress_window(backend.as_deref(), sections)?;
+        if let Some(target) = self.target_window_sections(window) {
+            let (target, target_del_ind) = compress_window(backend.as_deref(), target)?;
+            let len = |s: &WindowSections| {
+                s.data_section.len() + s.inst_section.len() + s.addr_section.len()
+            };
+            if len(&target) < len(&sections) {
+                (sections, del_ind) = (target, target_del_ind);
+            }
+        }
+        self.write_sections(sections, del_ind)?;
+
+        if self.opts.allow_target_windows {
+            self.prev_target.clear();
+            self.prev_target.extend_from_slice(window);
+            self.prev_target_offset = self.bytes_encoded;
+        }
+        self.bytes_encoded += window.len() as u64;
+        self.windows_written += 1;
+        Ok(())
+    }
+
+    /// Encode `window` with the previous target window as its copy window
+    /// (see `CompressOptions::allow_target_windows`), or `None` when that
+    /// is off or there is no previous window.
+    fn target_window_sections(&mut self, window: &[u8]) -> Option<WindowSections> {
+        if !self.opts.allow_target_windows
+            || self.opts.level == 0
+            || self.prev_target.is_empty()
+            || window.is_empty()
+        {
+            return None;
+        }
+        let opts = &self.opts;
+        let config = self._config;
+        let engine = self.target_engine.get_or_insert_with(|| {
+            let winsize = opts.window_size.max(64);
+            let mut eng = MatchEngine::new(config, winsize as u64, winsize);
+            eng.set_prefer_run_byte(opts.prefer_run_byte);
+            eng.set_max_self_copy_distance(opts.max_self_copy_distance);
+            eng.set_target_self_match(opts.target_self_match);
+            eng
+        });
+        let prev: &[u8] = &self.prev_target;
+        engine.reset_source_index();
+        engine.index_source(&prev);
+        let raw = engine.find_matches(window, Some(&prev));
+        let instructions = pipeline::optimize_buffered(
+            &raw,
+            window,
+            prev.len() as u64,
+            opts.effective_opt_level(),
+            opts.iopt_size,
+        );
+
+        let copy_window = SourceWindow {
+            len: prev.len() as u64,
+            offset: self.prev_target_offset,
+        };
+        let mut we = opts.window_encoder(Some(copy_window), window.len());
+        we.set_target_window(true);
+        emit_instructions(&mut we, window, &instructions);
+        Some(we.finish_sections(Some(window)))
+    }
+
+    /// Assemble a finished window and write it, counting its sections.
+    fn write_sections(&mut self, sections: WindowSections, del_ind: u8) -> Result<(), EncodeError> {
+        self.section_bytes.add(&sections);
+        self.stream.write_raw_window(&sections.assemble(del_ind))?;
+        Ok(())
+    }
+
+    /// Find matches using the (reused) match engine.
+    fn find_matches(&mut self, target: &[u8]) -> Vec<Instruction> {
+        #[cfg(feature = "suffix-array")]
+        if let Some(index) = self.suffix_array.as_ref() {
+            let source = self.source.as_slice().expect("source is in memory");
+            let raw = index.find_matches(source, target);
+            self.match_stats.tally(&raw, self.src_len as u64);
+            return raw;
+        }
+
+        let engine = self.engine.as_mut().expect("engine required for level > 0");
+
+        if self.source.is_empty() {
+            engine.find_matches(target, None::<&&[u8]>)
+        } else {
+            let src = self.source.view(self.src_start, self.src_len);
+            engine.find_matches(target, Some(&src))
+        }
+    }
+}
+
+/// Source bytes for a [`DeltaEncoder`]: in memory, or read on demand.
+enum EncoderSource<'s> {
+    Slice(&'s [u8]),
+    /// Dictionary and source joined by `DeltaEncoder::with_dictionary`.
+    Owned(Vec<u8>),
+    Reader(ReaderSource<Box<dyn ReadSeek + 's>>),
+}
+
+/// `Read + Seek` as one object-safe trait.
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl EncoderSource<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Slice(s) => s.len(),
+            Self::Owned(v) => v.len(),
+            Self::Reader(r) => matching::SourceData::len(r) as usize,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The whole source as one slice, unless it is read on demand.
+    #[cfg(feature = "suffix-array")]
+    fn as_slice(&self) -> Option<&[u8]> {
+        match self {
+            Self::Slice(s) => Some(s),
+            Self::Owned(v) => Some(v),
+            Self::Reader(_) => None,
+        }
+    }
+
+    /// Read a reader-backed source fully into memory.
+    #[cfg(feature = "suffix-array")]
+    fn into_memory(self) -> Self {
+        match self {
+            Self::Reader(r) => {
+                let mut bytes = vec![0u8; matching::SourceData::len(&r) as usize];
+                let n = matching::SourceData::get_bytes(&r, 0, &mut bytes);
+                bytes.truncate(n);
+                Self::Owned(bytes)
+            }
+            in_memory => in_memory,
+        }
+    }
+
+    /// The source window `[start, start + len)`, addressed from zero.
+    fn view(&self, start: usize, len: usize) -> SourceView<'_> {
+        let inner: &dyn matching::SourceData = match self {
+            Self::Slice(s) => s,
+            Self::Owned(v) => v,
+            Self::Reader(r) => r,
+        };
+        SourceView {
+            inner,
+            start: start as u64,
+            len: len as u64,
+        }
+    }
+}
+
+/// A sub-range of a source, presented to the match engine as a whole source.
+struct SourceView<'a> {
+    inner: &'a dyn matching::SourceData,
+    start: u64,
+    len: u64,
+}
+
+impl matching::SourceData for SourceView<'_> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let n = buf.len().min((self.len - offset) as usize);
+        self.inner.get_bytes(self.start + offset, &mut buf[..n])
+    }
+    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        if offset + len as u64 > self.len {
+            return None;
+        }
+        self.inner.as_slice(self.start + offset, len)
+    }
+}
+
+/// Encode `target` as ADD-only windows without a source, for
+/// `fallback_to_store`. Returns the delta and its window count.
+fn store_delta(target: &[u8], opts: &CompressOptions) -> Result<(Vec<u8>, u64), EncodeError> {
+    let store = CompressOptions {
+        level: 0,
+        window_size: opts.window_size_for(target.len()),
+        checksum: opts.checksum,
+        checksum_min_window: opts.checksum_min_window,
+        integrity: opts.integrity,
+        ..Default::default()
+    };
+    let mut enc = DeltaEncoder::new(Vec::new(), &[], store);
+    enc.write_target(target)?;
+    let (delta, info) = enc.finish()?;
+    Ok((delta, info.windows))
+}
+
+/// Whether `target` is a byte-for-byte copy of `source` that the matcher
+/// can be skipped for (see [`copy_all_delta`]).
+fn is_unchanged(source: &[u8], target: &[u8], opts: &CompressOptions) -> bool {
+    opts.level > 0
+        && opts.window_size > 0
+        && !target.is_empty()
+        && source.len() == target.len()
+        && rolling::forward_match(source, target, target.len()) == target.len()
+}
+
+/// Apply secondary compression with `backend`, if any, to a window's
+/// sections; returns them with the matching `del_ind`.
+fn compress_window(
+    backend: Option<&dyn CompressBackend>,
+    sections: WindowSections,
+) -> Result<(WindowSections, u8), EncodeError> {
+    let Some(backend) = backend else {
+        return Ok((sections, 0));
+    };
+    let (data_section, inst_section, addr_section, del_ind) = secondary::compress_sections(
+        backend,
+        &sections.data_section,
+        &sections.inst_section,
+        &sections.addr_section,
+    )
+    .map_err(secondary_error)?;
+    let sections = WindowSections {
+        data_section,
+        inst_section,
+        addr_section,
+        ..sections
+    };
+    Ok((sections, del_ind))
+}
+
+/// Encode a target equal to the source as one source COPY per window,
+/// without indexing the source.
+fn copy_all_delta<W: Write>(
+    writer: W,
+    target: &[u8],
+    opts: &CompressOptions,
+) -> Result<W, EncodeError> {
+    let mut window_size = opts.window_size.max(64);
+    if opts.window_alignment > 1 && opts.window_alignment <= window_size {
+        window_size -= window_size % opts.window_alignment;
+    }
+    let mut stream = opts.stream_encoder(writer);
+    let mut offset = 0u64;
+    for chunk in target.chunks(window_size) {
+        let source_win = SourceWindow {
+            len: chunk.len() as u64,
+            offset,
+        };
+        let mut we = opts.window_encoder(Some(source_win), chunk.len());
+        we.copy_with_auto_mode(chunk.len() as u32, 0);
+        stream.write_window(we, Some(chunk))?;
+        offset += chunk.len() as u64;
+    }
+    Ok(stream.finish()?)
+}
+
+/// Lengths of the common prefix and suffix of `source` and `target`, cut
+/// back to whole windows, when together they cover at least half of the
+/// target; the matcher is then only run over the windows between them (see
+/// [`encode_changed_region`]). Skipped under `prefer_run_byte`, whose runs
+/// would be copied instead.
+fn changed_region(source: &[u8], target: &[u8], opts: &CompressOptions) -> Option<(usize, usize)> {
+    if opts.level == 0
+        || opts.window_size == 0
+        || opts.adaptive_windows
+        || opts.prefer_run_byte.is_some()
+        || target.is_empty()
+    {
+        return None;
+    }
+    let n = source.len().min(target.len());
+    let prefix = rolling::forward_match(source, target, n);
+    let rest = n - prefix;
+    let suffix = rolling::backward_match(
+        &source[source.len() - rest..],
+        &target[target.len() - rest..],
+        rest,
+    );
+
+    // Windows keep the layout of a plain encode: `window_size`, shortened
+    // to a multiple of `window_alignment` (see `next_window_len`).
+    let mut step = opts.window_size;
+    if opts.window_alignment > 1 && opts.window_alignment <= step {
+        step -= step % opts.window_alignment;
+    }
+    let prefix = prefix / step * step;
+    let end = (prefix + (target.len() - suffix - prefix).div_ceil(step) * step).min(target.len());
+    let suffix = target.len() - end;
+    (2 * (prefix + suffix) >= target.len()).then_some((prefix, suffix))
+}
+
+/// Encode the common `prefix` and `suffix` (from [`changed_region`]) as
+/// source COPY windows and only the middle of `target` with the matcher,
+/// which indexes just the middle of `source`. Returns the writer and the
+/// encoder's match statistics.
+fn encode_changed_region<W: Write>(
+    writer: W,
+    source: &[u8],
+    target: &[u8],
+    opts: CompressOptions,
+    (prefix, suffix): (usize, usize),
+) -> Result<(W, MatchStats), EncodeError> {
+    let middle = &target[prefix..target.len() - suffix];
+    let middle_source = if middle.is_empty() {
+        &[][..]
+    } else {
+        &source[prefix..source.len() - suffix]
+    };
+    let mut enc = DeltaEncoder::new(writer, middle_source, opts);
+    enc.src_base = prefix as u64;
+    enc.write_source_copy(0, &target[..prefix])?;
+    enc.write_target(middle)?;
+    enc.flush()?;
+    let suffix_offset = (source.len() - suffix) as u64;
+    enc.write_source_copy(suffix_offset, &target[target.len() - suffix..])?;
+    let stats = *enc.stats();
+    let (w, _) = enc.finish()?;
+    Ok((w, stats))
+}
+
+/// Convenience: encode an entire target at once.
+///
+/// A target identical to the source is recognised up front and encoded as
+/// source COPYs of each window, skipping indexing and matching. Likewise a
+/// long common prefix and suffix are copied directly, and only the changed
+/// region between them is indexed and matched.
+pub fn encode_all<W: Write>(
+    writer: W,
+    source: &[u8],
+    target: &[u8],
+    mut opts: CompressOptions,
+) -> Result<W, EncodeError> {
+    opts.validate_matcher()?;
+    opts.window_size = opts.window_size_for(target.len());
+    if is_unchanged(source, target, &opts) {
+        return copy_all_delta(writer, target, &opts);
+    }
+    if let Some(ends) = changed_region(source, target, &opts) {
+        let (w, _) = encode_changed_region(writer, source, target, opts, ends)?;
+        return Ok(w);
+    }
+    let mut enc = DeltaEncoder::new(writer, source, opts);
+    enc.write_target(target)?;
+    let (w, _) = enc.finish()?;
+    Ok(w)
+}
+
+/// Convenience: encode an entire target, matching its windows in parallel.
+///
+/// This path is gated behind the `parallel` feature and is disabled by default.
+/// The source is indexed once and the index shared read-only by all windows;
+/// each window then matches on its own thread with its own target table, and
+/// only writing the delta is serial. Each window's MATCH_TARGET probe starts
+/// at an estimate of the sequential `match_srcpos` (see
+/// `match_target_probes`), so the delta stays close to `encode_all`'s in
+/// size, though instruction choices may differ (for one, the whole source is
+/// indexed regardless of `source_window_size`).
+#[cfg(feature = "parallel")]
+pub fn encode_all_parallel<W: Write>(
+    writer: W,
+    source: &[u8],
+    target: &[u8],
+    mut opts: CompressOptions,
+) -> Result<W, EncodeError> {
+    opts.validate_matcher()?;
+    opts.window_size = opts.window_size_for(target.len());
+
+    // Keep behavior identical for empty and unchanged targets.
+    if target.is_empty() || is_unchanged(source, target, &opts) {
+        return encode_all(writer, source, target, opts);
+    }
+    if opts.window_size == 0 {
+        return Err(zero_window_size());
+    }
+
+    let mut window_size = opts.window_size.max(64);
+    if opts.window_alignment > 1 && opts.window_alignment <= window_size {
+        window_size -= window_size % opts.window_alignment;
+    }
+    let config = opts.effective_matcher();
+    let source_win = if !source.is_empty() {
+        Some(SourceWindow {
+            len: source.len() as u64,
+            offset: 0,
+        })
+    } else {
+        None
+    };
+    let chunks: Vec<&[u8]> = target.chunks(window_size).collect();
+    #[cfg(feature = "suffix-array")]
+    let suffix_array =
+        (opts.level > 0 && !source.is_empty() && opts.match_strategy == MatchStrategy::SuffixArray)
+            .then(|| SuffixArray::new(source));
+    #[cfg(feature = "suffix-array")]
+    let use_hash = suffix_array.is_none();
+    #[cfg(not(feature = "suffix-array"))]
+    let use_hash = true;
+
+    let source_index = (use_hash && opts.level > 0 && !source.is_empty())
+        .then(|| Arc::new(SourceIndex::build(&config, &source)));
+    let probes = match_target_probes(source, &chunks);
+
+    let windows: Result<Vec<Vec<u8>>, EncodeError> = chunks
+        .par_iter()
+        .zip(probes)
+        .map(|(chunk, probe)| {
+            let instructions = if opts.level == 0 {
+                if chunk.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Instruction::Add {
+                        len: chunk.len() as u32,
+                    }]
+                }
+            } else {
+                #[cfg(feature = "suffix-array")]
+                let indexed = suffix_array
+                    .as_ref()
+                    .map(|index| index.find_matches(source, chunk));
+                #[cfg(not(feature = "suffix-array"))]
+                let indexed: Option<Vec<Instruction>> = None;
+
+                let raw = match indexed {
+                    Some(raw) => raw,
+                    None => {
+                        let mut engine = match &source_index {
+                            Some(index) => MatchEngine::with_source_index(
+                                config,
+                                Arc::clone(index),
+                                chunk.len().max(64),
+                            ),
+                            None => MatchEngine::new(config, 0, chunk.len().max(64)),
+                        };
+                        engine.match_srcpos = probe;
+                        engine.set_prefer_run_byte(opts.prefer_run_byte);
+                        engine.set_max_self_copy_distance(opts.max_self_copy_distance);
+                        engine.set_target_self_match(opts.target_self_match);
+
+                        if source.is_empty() {
+                            engine.find_matches(chunk, None::<&&[u8]>)
+                        } else {
+                            let src: &[u8] = source;
+                            engine.find_matches(chunk, Some(&src))
+                        }
+                    }
+                };
+                pipeline::optimize_buffered(
+                    &raw,
+                    chunk,
+                    source.len() as u64,
+                    opts.effective_opt_level(),
+                    opts.iopt_size,
+                )
+            };
+
+            let mut we = opts.window_encoder(source_win, chunk.len());
+            emit_instructions(&mut we, chunk, &instructions);
+
+            if let Some(backend) = opts.secondary.backend() {
+                let sections = we.finish_sections(Some(chunk));
+                let (comp_data, comp_inst, comp_addr, del_ind) = secondary::compress_sections(
+                    backend.as_ref(),
+                    &sections.data_section,
+                    &sections.inst_section,
+                    &sections.addr_section,
+                )
+                .map_err(secondary_error)?;
+                let assembled_sections = crate::vcdiff::encoder::WindowSections {
+                    source_window: sections.source_window,
+                    target_window: sections.target_window,
+                    target_len: sections.target_len,
+                    checksum: sections.checksum,
+                    data_section: comp_data,
+                    inst_section: comp_inst,
+                    addr_section: comp_addr,
+                };
+                Ok(assembled_sections.assemble(del_ind))
+            } else {
+                Ok(we.finish_sections(Some(chunk)).assemble(0))
+            }
+        })
+        .collect();
+
+    let windows = windows?;
+    if opts.fallback_to_store {
+        let delta_len: usize = windows.iter().map(Vec::len).sum();
+        if delta_len as u64 > target.len() as u64 {
+            let (store, _) = store_delta(target, &opts)?;
+            if store.len() < delta_len {
+                let mut writer = writer;
+                writer.write_all(&store)?;
+                writer.flush()?;
+                return Ok(writer);
+            }
+        }
+    }
+
+    let mut stream = opts.stream_encoder(writer);
+    if let Some(backend) = opts.secondary.backend() {
+        stream.set_secondary_id(backend.id());
+    }
+
+    for window in windows {
+        stream.write_raw_window(&window)?;
+    }
+
+    Ok(stream.finish()?)
+}
+
+/// Approximate the `match_srcpos` each of `chunks` would start with if one
+/// engine matched them in order.
+///
+/// The estimate only advances past a window when the source continues the
+/// whole of it. A sequential engine also moves its probe along partial
+/// matches inside a window, so for edited windows this lags behind; the
+/// engine then finds the source position through its index instead.
+#[cfg(feature = "parallel")]
+fn match_target_probes(source: &[u8], chunks: &[&[u8]]) -> Vec<u64> {
+    let mut srcpos = 0usize;
+    chunks
+        .iter()
+        .map(|chunk| {
+            let probe = srcpos as u64;
+            let rest = &source[srcpos.min(source.len())..];
+            if chunk.len() >= config::MIN_MATCH
+                && chunk.len() <= rest.len()
+                && rolling::forward_match(rest, chunk, chunk.len()) == chunk.len()
+            {
+                srcpos += chunk.len();
+            }
+            probe
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Write adapter
+// ---------------------------------------------------------------------------
+
+/// A [`DeltaEncoder`] as a [`Write`] sink: the bytes written are the target.
+///
+/// `write` feeds [`DeltaEncoder::write_target`]; `flush` does nothing, so
+/// wrappers that flush often (`BufWriter`, `io::copy` into a tee) do not cut
+/// windows short; call [`DeltaEncoder::flush_partial`] through
+/// [`encoder`](Self::encoder) to cut one on purpose. Call
+/// [`finish`](Self::finish) to write the last window; dropping the adapter
+/// loses it.
+///
+/// ```
+/// use oxidelta::compress::{CompressOptions, DeltaWriter, decoder};
+///
+/// let source = b"hello old world";
+/// let mut writer = DeltaWriter::new(Vec::new(), source, CompressOptions::default());
+/// std::io::copy(&mut &b"hello new world"[..], &mut writer).unwrap();
+/// let (delta, _) = writer.finish().unwrap();
+/// assert_eq!(decoder::decode_all(source, &delta).unwrap(), b"hello new world");
+/// ```
+pub struct DeltaWriter<'s, W: Write> {
+    encoder: DeltaEncoder<'s, W>,
+}
+
+impl<'s, W: Write> DeltaWriter<'s, W> {
+    /// Encode against `source` into `writer`; see [`DeltaEncoder::new`].
+    pub fn new(writer: W, source: &'s [u8], opts: CompressOptions) -> Self {
+        Self::from_encoder(DeltaEncoder::new(writer, source, opts))
+    }
+
+    /// Wrap an encoder set up some other way (a reader source, an
+    /// application header, ...).
+    pub fn from_encoder(encoder: DeltaEncoder<'s, W>) -> Self {
+        Self { encoder }
+    }
+
+    /// The wrapped encoder.
+    pub fn encoder(&mut self) -> &mut DeltaEncoder<'s, W> {
+        &mut self.encoder
+    }
+
+    /// Encode the remaining target and finalize the delta; see
+    /// [`DeltaEncoder::finish`].
+    pub fn finish(self) -> Result<(W, FinishInfo), EncodeError> {
+        self.encoder.finish()
+    }
+}
+
+impl<W: Write> Write for DeltaWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.encoder.write_target(buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(EncodeError::Io(e)) => Err(e),
+            Err(e) => Err(std::io::Error::other(e)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Instruction emission helper
+// ---------------------------------------------------------------------------
+
+fn emit_instructions(we: &mut WindowEncoder, target: &[u8], instructions: &[Instruction]) {
+    we.push_instructions(target, instructions)
+        .expect("match engine instructions cover the window");
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    fn roundtrip(source: &[u8], target: &[u8], opts: CompressOptions) -> Vec<u8> {
+        let mut output = Vec::new();
+        let enc = DeltaEncoder::new(&mut output, source, opts);
+        // Use encode_all convenience instead to keep it simple.
+        drop(enc);
+        output.clear();
+
+        encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, source).unwrap();
+        assert_eq!(decoded, target, "roundtrip mismatch");
+        output
+    }
+
+    #[test]
+    fn encode_all_roundtrip() {
+        let source = b"The quick brown fox jumps over the lazy dog.";
+        let target = b"The quick brown cat sits on the lazy mat.";
+        roundtrip(source, target, CompressOptions::default());
+    }
+
+    #[test]
+    fn level_0_store_only() {
+        let target = b"Hello, world!";
+        let mut output = Vec::new();
+        encode_all(
+            &mut output,
+            b"",
+            target,
+            CompressOptions {
+                level: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"").unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[test]
+    fn all_levels_roundtrip() {
+        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789abcdefghijklmnopqrstuvwxyz";
+        let target = b"ABCDEFGHIJKLMNOP--CHANGED--UVWXYZ0123456789abcdefghijklmnopqrstuvwxyz!!!";
+
+        for level in 0..=9 {
+            let mut output = Vec::new();
+            encode_all(
+                &mut output,
+                source,
+                target,
+                CompressOptions {
+                    level,
+                    checksum: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let decoded = crate::vcdiff::decoder::decode_memory(&output, source).unwrap();
+            assert_eq!(decoded, target, "level {level} roundtrip failed");
+        }
+    }
+
+    #[test]
+    fn streaming_matches_bulk() {
+        let source = b"AAAA BBBB CCCC DDDD EEEE FFFF GGGG HHHH";
+        let target = b"AAAA CCCC DDDD EEEE xxxx GGGG HHHH IIII";
+
+        // Bulk encode.
+        let mut bulk_output = Vec::new();
+        encode_all(
+            &mut bulk_output,
+            source,
+            target,
+            CompressOptions {
+                level: 6,
+                window_size: 1024, // small windows
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Streaming encode (4-byte chunks).
+        let mut stream_output = Vec::new();
+        let mut enc = DeltaEncoder::new(
+            &mut stream_output,
+            source,
+            CompressOptions {
+                level: 6,
+                window_size: 1024,
+                ..Default::default()
+            },
+        );
+        for chunk in target.chunks(4) {
+            enc.write_target(chunk).unwrap();
+        }
+        enc.finish().unwrap();
+
+        // Both should decode to the same target.
+        let bulk_decoded = crate::vcdiff::decoder::decode_memory(&bulk_output, source).unwrap();
+        let stream_decoded = crate::vcdiff::decoder::decode_memory(&stream_output, source).unwrap();
+        assert_eq!(bulk_decoded, target);
+        assert_eq!(stream_decoded, target);
+    }
+
+    #[test]
+    fn empty_target() {
+        let mut output = Vec::new();
+        encode_all(&mut output, b"source", b"", CompressOptions::default()).unwrap();
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"source").unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn no_source() {
+        let target = b"standalone data without any source reference";
+        let mut output = Vec::new();
+        encode_all(&mut output, b"", target, CompressOptions::default()).unwrap();
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, b"").unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[test]
+    fn unchanged_target_is_copied_whole() {
+        let source: Vec<u8> = (0..1u32 << 20).map(|i| (i * 31 % 251) as u8).collect();
+        let delta = encode_all(Vec::new(), &source, &source, CompressOptions::default()).unwrap();
+        assert!(delta.len() < 32, "{} bytes", delta.len());
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            source
+        );
+
+        // Several windows, each copying its own source segment.
+        let opts = CompressOptions {
+            window_size: 300 * 1024,
+            ..Default::default()
+        };
+        let windowed = encode_all(Vec::new(), &source, &source, opts).unwrap();
+        assert!(windowed.len() < 4 * 32, "{} bytes", windowed.len());
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&windowed, &source).unwrap(),
+            source
+        );
+
+        // One differing byte goes through the matcher.
+        let mut target = source.clone();
+        target[1 << 19] ^= 0xFF;
+        let delta = encode_all(Vec::new(), &source, &target, CompressOptions::default()).unwrap();
+        let mut expected = Vec::new();
+        let mut enc = DeltaEncoder::new(
+            &mut expected,
+            &source,
+            CompressOptions {
+                window_size: target.len(),
+                ..Default::default()
+            },
+        );
+        enc.write_target(&target).unwrap();
+        enc.finish().unwrap();
+        assert_eq!(delta, expected);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            target
+        );
+    }
+
+    #[test]
+    fn changed_region_skips_common_ends() {
+        let source: Vec<u8> = (0..1u32 << 20).map(|i| (i * 31 % 251) as u8).collect();
+        let mut target = source[..600_000].to_vec();
+        target.extend((0..5_000u32).map(|i| (i * 7 % 13) as u8));
+        target.extend_from_slice(&source[610_000..]);
+
+        // The common ends are cut back to whole 64 KiB windows, leaving the
+        // window at [589_824, 655_360) for the matcher.
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            ..Default::default()
+        };
+        let ends = changed_region(&source, &target, &opts).unwrap();
+        assert_eq!(ends, (589_824, target.len() - 655_360));
+        let (delta, trimmed) =
+            encode_changed_region(Vec::new(), &source, &target, opts.clone(), ends).unwrap();
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            target
+        );
+        assert_eq!(
+            encode_all(Vec::new(), &source, &target, opts.clone()).unwrap(),
+            delta
+        );
+
+        // The matcher only indexed the middle of the source.
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+        enc.write_target(&target).unwrap();
+        enc.flush().unwrap();
+        let full = *enc.stats();
+        assert_eq!(trimmed.total_bytes(), target.len() as u64);
+        assert!(trimmed.large_table.inserts * 10 < full.large_table.inserts);
+    }
+
+    #[test]
+    fn finish_report_totals_match_target() {
+        let source: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut target = source.clone();
+        for i in (1_000..target.len()).step_by(7_919) {
+            target[i..i + 40].fill(0);
+        }
+        target.extend((0..3_000u32).map(|i| (i * 7 % 13) as u8));
+
+        let opts = CompressOptions {
+            window_size: 32 * 1024,
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+        for chunk in target.chunks(10_000) {
+            enc.write_target(chunk).unwrap();
+        }
+        let (delta, info, report) = enc.finish_with_report().unwrap();
+        let decoded = crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap();
+        assert_eq!(decoded, target);
+
+        assert_eq!(report.windows, info.windows);
+        assert_eq!(report.windows, target.len().div_ceil(32 * 1024) as u64);
+        assert_eq!(report.target_bytes, decoded.len() as u64);
+        assert_eq!(report.stats.total_bytes(), decoded.len() as u64);
+        assert_eq!(report.delta_bytes, delta.len() as u64);
+        assert!(report.sections.total() < report.delta_bytes);
+        assert!(report.stats.bytes_copied_source > 0 && report.stats.runs > 0);
+        assert!(!report.store_fallback);
+    }
+
+    #[test]
+    fn vectored_feed_matches_contiguous() {
+        let source: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut target = source.clone();
+        for i in (500..target.len()).step_by(3_001) {
+            target[i] ^= 0x55;
+        }
+        let opts = CompressOptions {
+            window_size: 16 * 1024,
+            ..Default::default()
+        };
+
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts.clone());
+        enc.write_target(&target).unwrap();
+        let (contiguous, _) = enc.finish().unwrap();
+
+        // Chunks smaller than, straddling and spanning several windows.
+        let cuts = [0, 7, 7, 16_384, 20_000, 70_000, target.len()];
+        let chunks: Vec<&[u8]> = cuts.windows(2).map(|w| &target[w[0]..w[1]]).collect();
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+        enc.write_target_vectored(&chunks).unwrap();
+        let (vectored, info) = enc.finish().unwrap();
+
+        assert_eq!(vectored, contiguous);
+        assert_eq!(info.windows, target.len().div_ceil(16 * 1024) as u64);
+    }
+
+    #[test]
+    fn adaptive_windows_follow_match_density() {
+        use crate::compress::decoder::DeltaDecoder;
+
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut noise = |n: usize| rng.bytes(n);
+        let source = noise(256 * 1024);
+        // Dense, then sparse (new bytes), then dense again.
+        let mut target = source[..64 * 1024].to_vec();
+        target.extend(noise(64 * 1024));
+        target.extend_from_slice(&source[64 * 1024..192 * 1024]);
+
+        let opts = CompressOptions {
+            window_size: 32 * 1024,
+            adaptive_windows: true,
+            adaptive_max_window: Some(32 * 1024),
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts.clone());
+        enc.write_target(&target).unwrap();
+        let (delta, _) = enc.finish().unwrap();
+        assert_eq!(
+            encode_all(Vec::new(), &source, &target, opts.clone()).unwrap(),
+            delta
+        );
+
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        let mut out = Vec::new();
+        decoder.decode_to(&mut &source[..], &mut out).unwrap();
+        assert_eq!(out, target);
+        let lens: Vec<u64> = decoder
+            .window_report()
+            .iter()
+            .map(|w| w.target_len)
+            .collect();
+        // Full windows over the copied start, halving down to 4 KiB once new
+        // bytes come in, and doubling back to 32 KiB over the copied end.
+        const K: u64 = 1024;
+        let expected = [32, 32, 32, 16, 8, 4, 4, 4, 8, 16, 32, 32, 32, 4].map(|n| n * K);
+        assert_eq!(lens, expected);
+        assert_eq!(lens.iter().sum::<u64>(), target.len() as u64);
+
+        // A higher floor stops the shrinking at 16 KiB.
+        let floored = CompressOptions {
+            adaptive_min_window: Some(16 * 1024),
+            ..opts.clone()
+        };
+        let delta = encode_all(Vec::new(), &source, &target, floored).unwrap();
+        let expected = [32, 32, 32, 16, 16, 16, 32, 32, 32, 16].map(|n| n * K);
+        assert_eq!(window_lengths(&delta), expected);
+
+        // By default windows grow past `window_size`.
+        let unbounded = CompressOptions {
+            adaptive_max_window: None,
+            ..opts
+        };
+        let delta = encode_all(Vec::new(), &source, &target, unbounded).unwrap();
+        let expected = [32, 64, 64, 64, 32].map(|n| n * K);
+        assert_eq!(window_lengths(&delta), expected);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            target
+        );
+    }
+
+    #[test]
+    fn target_windows_copy_from_earlier_target() {
+        use crate::compress::decoder::{self, DeltaDecoder, next_raw_window};
+        use crate::vcdiff::FileHeader;
+        use crate::vcdiff::decoder::DecodeError;
+
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut noise = |n: usize| rng.bytes(n);
+        const WIN: usize = 16 * 1024;
+        let source = noise(WIN);
+        // New bytes, then an edited repeat of them, then a copy of the source.
+        let first = noise(WIN);
+        let mut target = first.clone();
+        target.extend(first.iter().enumerate().map(|(i, &b)| match i % 4096 {
+            100 => b ^ 0xFF,
+            _ => b,
+        }));
+        target.extend_from_slice(&source);
+
+        let encode = |allow_target_windows| {
+            let opts = CompressOptions {
+                window_size: WIN,
+                allow_target_windows,
+                ..Default::default()
+            };
+            let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+            enc.write_target(&target).unwrap();
+            enc.finish().unwrap().0
+        };
+        let plain = encode(false);
+        let delta = encode(true);
+        assert!(
+            delta.len() + WIN / 2 < plain.len(),
+            "{} vs {}",
+            delta.len(),
+            plain.len()
+        );
+        assert_eq!(decoder::decode_all(&source, &delta).unwrap(), target);
+
+        // Only the repeat reads the previous window (`VCD_TARGET`).
+        let mut cursor = std::io::Cursor::new(&delta[..]);
+        let hdr = FileHeader::decode(&mut cursor).unwrap();
+        let mut kinds = Vec::new();
+        while let Some(window) =
+            next_raw_window(&delta, &mut cursor, &hdr, kinds.len() as u64).unwrap()
+        {
+            let wh = &window.header;
+            kinds.push((wh.has_source(), wh.has_target(), wh.copy_window_offset));
+        }
+        assert_eq!(
+            kinds,
+            [(true, false, 0), (false, true, 0), (true, false, 0)]
+        );
+
+        // Streaming decoders drop earlier output, so they cannot follow.
+        let mut streaming = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        let err = streaming
+            .decode_to(&mut &source[..], &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)), "{err}");
+    }
+
+    #[test]
+    fn small_iopt_buffer_roundtrip() {
+        let source: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        for i in (50..target.len()).step_by(97) {
+            target[i] = target[i].wrapping_add(1);
+        }
+
+        let encode = |iopt_size| {
+            let opts = CompressOptions {
+                level: 9,
+                iopt_size,
+                ..Default::default()
+            };
+            encode_all(Vec::new(), &source, &target, opts).unwrap()
+        };
+        let unbounded = encode(0);
+        for iopt_size in [1, 2, 16] {
+            let delta = encode(iopt_size);
+            assert!(delta.len() >= unbounded.len(), "iopt {iopt_size}");
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+                target,
+                "iopt {iopt_size}"
+            );
+        }
+        // A buffer larger than any window's instruction stream changes nothing.
+        assert_eq!(encode(1 << 20), unbounded);
+    }
+
+    #[test]
+    fn checksum_min_window_skips_small_windows() {
+        use crate::compress::decoder::DeltaDecoder;
+
+        let source: Vec<u8> = (0..32 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        target.extend_from_slice(&[1; 100]);
+        target.extend_from_slice(&[2; 1500]);
+
+        let opts = CompressOptions {
+            checksum_min_window: Some(1024),
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+        for part in [&target[..32 * 1024], &target[32 * 1024..32 * 1024 + 100]] {
+            enc.write_target(part).unwrap();
+            enc.flush().unwrap();
+        }
+        enc.write_target(&target[32 * 1024 + 100..]).unwrap();
+        let (delta, _) = enc.finish().unwrap();
+
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        let mut out = Vec::new();
+        decoder.decode_to(&mut &source[..], &mut out).unwrap();
+        assert_eq!(out, target);
+        let windows: Vec<(u64, bool)> = decoder
+            .window_report()
+            .iter()
+            .map(|w| (w.target_len, w.had_checksum))
+            .collect();
+        assert_eq!(windows, [(32 * 1024, true), (100, false), (1500, true)]);
+
+        let opts = CompressOptions {
+            checksum: false,
+            checksum_min_window: Some(1024),
+            ..Default::default()
+        };
+        let delta = encode_all(Vec::new(), &source, &target, opts).unwrap();
+        let mut decoder = DeltaDecoder::new(std::io::Cursor::new(&delta));
+        decoder
+            .decode_to(&mut &source[..], &mut Vec::new())
+            .unwrap();
+        assert!(decoder.window_report().iter().all(|w| !w.had_checksum));
+    }
+
+    #[test]
+    fn progress_tracking() {
+        let target = vec![0xAA; 1000];
+        let mut output = Vec::new();
+        let mut enc = DeltaEncoder::new(&mut output, b"", CompressOptions::default());
+        assert_eq!(enc.bytes_in(), 0);
+        assert_eq!(enc.windows_written(), 0);
+        enc.write_target(&target).unwrap();
+        assert_eq!(enc.bytes_in(), 1000);
+        enc.finish().unwrap();
+    }
+
+    /// Target window lengths of every window in `delta`.
+    fn window_lengths(delta: &[u8]) -> Vec<u64> {
+        crate::vcdiff::decoder::window_headers(delta)
+            .unwrap()
+            .iter()
+            .map(|wh| wh.target_window_len)
+            .collect()
+    }
+
+    #[test]
+    fn sliding_source_window_roundtrip() {
+        use crate::vcdiff::header::{FileHeader, WindowHeader};
+
+        // Pseudo-random source so matches are only found at their true offset.
+        let source = XorShift32::new(0x2545_F491).bytes(1 << 20);
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(50_000) {
+            target[i..i + 8].copy_from_slice(b"patched!");
+        }
+        target.splice(300_000..300_000, b"inserted bytes".iter().copied());
+
+        let mut output = Vec::new();
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            source_window_size: Some(256 * 1024),
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(&mut output, &source, opts);
+        enc.write_target(&target).unwrap();
+        enc.finish().unwrap();
+
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&output, &source).unwrap(),
+            target
+        );
+        assert!(
+            output.len() < target.len() / 20,
+            "delta too large: {}",
+            output.len()
+        );
+
+        let mut cursor = std::io::Cursor::new(output.as_slice());
+        FileHeader::decode(&mut cursor).unwrap();
+        let mut offsets = Vec::new();
+        while let Some(wh) = WindowHeader::decode(&mut cursor).unwrap() {
+            assert_eq!(wh.copy_window_len, 256 * 1024);
+            offsets.push(wh.copy_window_offset);
+            let skip = wh.data_len + wh.inst_len + wh.addr_len;
+            cursor.set_position(cursor.position() + skip);
+        }
+        assert!(offsets.is_sorted());
+        assert_eq!(offsets[0], 0);
+        assert_eq!(*offsets.last().unwrap(), source.len() as u64 - 256 * 1024);
+    }
+
+    #[test]
+    fn explicit_source_window_offsets() {
+        use crate::vcdiff::header::{FileHeader, WindowHeader};
+
+        let source = XorShift32::new(0x2545_F491).bytes(256 * 1024);
+        // Two parts, taken from far-apart regions of the source.
+        let mut target = source[200_000..210_000].to_vec();
+        target[5000] ^= 0xFF;
+        target.extend_from_slice(&source[30_000..34_000]);
+
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, CompressOptions::default());
+        enc.set_source_window(195_000, 20_000).unwrap();
+        enc.write_target(&target[..10_000]).unwrap();
+        enc.set_source_window(30_000, 4_000).unwrap();
+        enc.write_target(&target[10_000..]).unwrap();
+        let (delta, info) = enc.finish().unwrap();
+        assert_eq!(info.windows, 2);
+        assert!(delta.len() < 200, "{} bytes", delta.len());
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, &source).unwrap(),
+            target
+        );
+
+        let mut cursor = std::io::Cursor::new(delta.as_slice());
+        FileHeader::decode(&mut cursor).unwrap();
+        let mut windows = Vec::new();
+        while let Some(wh) = WindowHeader::decode(&mut cursor).unwrap() {
+            windows.push((wh.copy_window_offset, wh.copy_window_len));
+            let skip = wh.data_len + wh.inst_len + wh.addr_len;
+            cursor.set_position(cursor.position() + skip);
+        }
+        assert_eq!(windows, [(195_000, 20_000), (30_000, 4_000)]);
+
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, CompressOptions::default());
+        assert!(enc.set_source_window(0, 0).is_err());
+        assert!(enc.set_source_window(260_000, 10_000).is_err());
+        assert!(enc.set_source_window(u64::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn from_reader_matches_in_memory() {
+        let source = XorShift32::new(0x9E37_79B9).bytes(3 << 20);
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(200_000) {
+            target[i..i + 6].copy_from_slice(b"edited");
+        }
+        target.splice(1_000_000..1_000_000, b"inserted".iter().copied());
+
+        for source_window_size in [None, Some(1 << 20)] {
+            let opts = CompressOptions {
+                window_size: 256 * 1024,
+                source_window_size,
+                ..Default::default()
+            };
+            let mut expected = Vec::new();
+            let mut enc = DeltaEncoder::new(&mut expected, &source, opts.clone());
+            enc.write_target(&target).unwrap();
+            enc.finish().unwrap();
+
+            let mut output = Vec::new();
+            let reader = std::io::Cursor::new(source.as_slice());
+            let mut enc = DeltaEncoder::from_reader(&mut output, reader, source.len() as u64, opts);
+            enc.write_target(&target).unwrap();
+            enc.finish().unwrap();
+
+            assert_eq!(output, expected);
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&output, &source).unwrap(),
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn dictionary_roundtrip() {
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut noise = |n: usize| rng.bytes(n);
+        let dictionary = noise(8 * 1024);
+        let source = noise(32 * 1024);
+        // Boilerplate from the dictionary that the source never contained.
+        let mut target = dictionary[1000..5000].to_vec();
+        target.extend_from_slice(&source[..20_000]);
+        target.extend_from_slice(&dictionary[6000..7000]);
+        target.extend(noise(500));
+
+        let encode = |dictionary: Option<&[u8]>| {
+            let mut delta = Vec::new();
+            let mut enc = match dictionary {
+                Some(dict) => {
+                    DeltaEncoder::with_dictionary(&mut delta, &source, dict, Default::default())
+                }
+                None => DeltaEncoder::new(&mut delta, &source, Default::default()),
+            };
+            enc.write_target(&target).unwrap();
+            enc.finish().unwrap();
+            delta
+        };
+        let with_dict = encode(Some(&dictionary));
+        let without = encode(None);
+        assert!(
+            with_dict.len() + 4000 < without.len(),
+            "{} vs {}",
+            with_dict.len(),
+            without.len()
+        );
+
+        let decode_source = [dictionary.as_slice(), source.as_slice()].concat();
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&with_dict, &decode_source).unwrap(),
+            target
+        );
+        // The source alone is not enough.
+        assert!(crate::vcdiff::decoder::decode_memory(&with_dict, &source).is_err());
+
+        // An empty dictionary is the plain source encoding.
+        assert_eq!(encode(Some(&[])), without);
+    }
+
+    #[test]
+    fn fallback_to_store_bounds_incompressible_deltas() {
+        let mut rng = XorShift32::new(0x9E37_79B9);
+        let mut noise = |n: usize| rng.bytes(n);
+        let source = noise(64 * 1024);
+        let target = noise(64 * 1024);
+        let opts = CompressOptions {
+            window_size: 16 * 1024,
+            fallback_to_store: true,
+            ..Default::default()
+        };
+
+        let encode = |opts: CompressOptions| {
+            let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+            for chunk in target.chunks(1000) {
+                enc.write_target(chunk).unwrap();
+            }
+            enc.finish().unwrap()
+        };
+        let (plain, plain_info) = encode(CompressOptions {
+            fallback_to_store: false,
+            ..opts.clone()
+        });
+        assert!(plain.len() > target.len());
+        assert!(!plain_info.store_fallback);
+
+        let (stored, info) = encode(opts.clone());
+        assert!(info.store_fallback);
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts.clone());
+        enc.write_target(&target).unwrap();
+        let (_, _, report) = enc.finish_with_report().unwrap();
+        assert!(report.store_fallback);
+        assert_eq!(
+            (report.windows, report.delta_bytes),
+            (4, stored.len() as u64)
+        );
+        // Store windows keep the configured window size.
+        assert_eq!(info.windows, 4);
+        assert!(stored.len() < plain.len());
+        assert!(stored.len() <= target.len() + 4 * 32, "{}", stored.len());
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&stored, &[]).unwrap(),
+            target
+        );
+
+        // A delta that actually saves space is kept.
+        let mut edited = source.clone();
+        edited[1234] ^= 0xFF;
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts.clone());
+        enc.write_target(&edited).unwrap();
+        let (delta, info) = enc.finish().unwrap();
+        assert!(!info.store_fallback);
+        assert!(delta.len() < 1024);
+
+        #[cfg(feature = "parallel")]
+        {
+            let parallel = encode_all_parallel(Vec::new(), &source, &target, opts).unwrap();
+            assert!(parallel.len() <= target.len() + 4 * 32);
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&parallel, &source).unwrap(),
+                target
+            );
+        }
+    }
+
+    #[cfg(feature = "block-hashes")]
+    #[test]
+    fn block_hashes_cover_the_target() {
+        use sha2::Digest;
+
+        let source: Vec<u8> = (0..20_000u32).map(|i| (i * 13 % 253) as u8).collect();
+        let mut target = source.clone();
+        target[5000] ^= 0xFF;
+        target.extend_from_slice(b"tail");
+        let expected: Vec<[u8; 32]> = target
+            .chunks(BLOCK_HASH_SIZE)
+            .map(|block| sha2::Sha256::digest(block).into())
+            .collect();
+        assert_eq!(expected.len(), 5);
+
+        for fallback_to_store in [false, true] {
+            let opts = CompressOptions {
+                window_size: 3000,
+                fallback_to_store,
+                ..Default::default()
+            };
+            let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+            enc.enable_block_hashes().unwrap();
+            for chunk in target.chunks(777) {
+                enc.write_target(chunk).unwrap();
+            }
+            let (_, info) = enc.finish().unwrap();
+            assert_eq!(info.block_hashes, expected);
+        }
+
+        // Off by default, and empty for an empty target.
+        let (_, info) = DeltaEncoder::new(Vec::new(), &source, CompressOptions::default())
+            .finish()
+            .unwrap();
+        assert!(info.block_hashes.is_empty());
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, CompressOptions::default());
+        enc.enable_block_hashes().unwrap();
+        assert!(enc.finish().unwrap().1.block_hashes.is_empty());
+
+        // Too late once target bytes have gone by unhashed.
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, CompressOptions::default());
+        enc.write_target(&target[..10]).unwrap();
+        let err = enc.enable_block_hashes().unwrap_err();
+        assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+    }
+
+    #[test]
+    fn custom_matcher_roundtrip() {
+        let source: Vec<u8> = (0..64 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 11) as u8)
+            .collect();
+        let mut target = Vec::new();
+        for i in 0..400usize {
+            let start = (i * 7919) % (source.len() - 64);
+            target.extend_from_slice(&source[start..start + 24 + i % 40]);
+            target.extend_from_slice(b"glue");
+        }
+
+        let encode = |matcher| {
+            let opts = CompressOptions {
+                matcher,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            encode_all(&mut output, &source, &target, opts).unwrap();
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&output, &source).unwrap(),
+                target
+            );
+            output.len()
+        };
+
+        let default_len = encode(None);
+        let sparse = MatcherConfig::builder().large_step(512).build().unwrap();
+        assert!(encode(Some(sparse)) > default_len, "large_step not applied");
+        let thorough = MatcherConfig::builder()
+            .large_look(config::MIN_MATCH)
+            .large_step(1)
+            .small_chain(64)
+            .small_lchain(64)
+            .max_lazy(0)
+            .long_enough(4096)
+            .build()
+            .unwrap();
+        encode(Some(thorough));
+    }
+
+    #[test]
+    fn target_self_match_disabled_emits_no_target_copies() {
+        let source: Vec<u8> = (0..8192u32).map(|i| (i * 13 % 249) as u8).collect();
+        let block = b"a block of text that repeats throughout the target. ";
+        let mut target = block.repeat(50);
+        target.extend_from_slice(&source[1000..5000]);
+        target.extend_from_slice(&block.repeat(50));
+
+        let target_copies = |delta: &[u8]| {
+            use crate::compress::decoder::next_raw_window;
+            use crate::vcdiff::header::FileHeader;
+            let mut cursor = std::io::Cursor::new(delta);
+            let file_hdr = FileHeader::decode(&mut cursor).unwrap();
+            let mut count = 0;
+            let mut windows = 0;
+            while let Some(window) =
+                next_raw_window(delta, &mut cursor, &file_hdr, windows).unwrap()
+            {
+                windows += 1;
+                let source_len = window.header.copy_window_len;
+                for inst in window.instructions(&file_hdr) {
+                    if let Instruction::Copy { addr, .. } = inst.unwrap()
+                        && addr >= source_len
+                    {
+                        count += 1;
+                    }
+                }
+            }
+            count
+        };
+
+        for level in [1, 6, 9] {
+            let mut enabled = Vec::new();
+            let opts = CompressOptions {
+                level,
+                ..Default::default()
+            };
+            encode_all(&mut enabled, &source, &target, opts).unwrap();
+            assert!(target_copies(&enabled) > 0, "level {level}");
+
+            let mut disabled = Vec::new();
+            let opts = CompressOptions {
+                level,
+                target_self_match: false,
+                ..Default::default()
+            };
+            encode_all(&mut disabled, &source, &target, opts).unwrap();
+            assert_eq!(target_copies(&disabled), 0, "level {level}");
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&disabled, &source).unwrap(),
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn window_boundaries_split_windows() {
+        let source: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source[..3000].to_vec();
+        target[1500] ^= 0xFF;
+
+        let mut output = Vec::new();
+        let mut enc = DeltaEncoder::new(
+            &mut output,
+            &source,
+            CompressOptions {
+                window_size: 1024,
+                ..Default::default()
+            },
+        );
+        enc.set_window_boundaries(&[100, 250, 1300]).unwrap();
+        for chunk in target.chunks(77) {
+            enc.write_target(chunk).unwrap();
+        }
+        enc.finish().unwrap();
+
+        // 100, 150, then 1024-capped up to 1300, then window_size chunks.
+        assert_eq!(window_lengths(&output), vec![100, 150, 1024, 26, 1024, 676]);
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[test]
+    fn window_alignment_rounds_window_ends() {
+        let source: Vec<u8> = (0..8192u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source[..5000].to_vec();
+        target[2100] ^= 0xFF;
+
+        let encode = |boundaries: &[u64]| {
+            let mut output = Vec::new();
+            let opts = CompressOptions {
+                window_size: 1000,
+                window_alignment: 256,
+                ..Default::default()
+            };
+            let mut enc = DeltaEncoder::new(&mut output, &source, opts);
+            enc.set_window_boundaries(boundaries).unwrap();
+            for chunk in target.chunks(333) {
+                enc.write_target(chunk).unwrap();
+            }
+            enc.finish().unwrap();
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&output, &source).unwrap(),
+                target
+            );
+            window_lengths(&output)
+        };
+
+        let lengths = encode(&[]);
+        let (last, rest) = lengths.split_last().unwrap();
+        assert!(rest.iter().all(|&len| len == 768), "{lengths:?}");
+        assert_eq!(rest.len() as u64 * 768 + last, target.len() as u64);
+
+        // An unaligned boundary cuts one window short; the next realigns.
+        let lengths = encode(&[300]);
+        assert_eq!(lengths[..2], [300, 980]);
+        let mut end = 0;
+        for len in &lengths[..lengths.len() - 1] {
+            end += len;
+            assert!(end % 256 == 0 || end == 300, "{lengths:?}");
+        }
+    }
+
+    #[test]
+    fn window_boundaries_validated() {
+        let mut output = Vec::new();
+        let mut enc = DeltaEncoder::new(&mut output, b"", CompressOptions::default());
+        assert!(enc.set_window_boundaries(&[10, 10]).is_err());
+        assert!(enc.set_window_boundaries(&[20, 5]).is_err());
+        enc.write_target(&[1u8; 50]).unwrap();
+        assert!(enc.set_window_boundaries(&[50]).is_err());
+        enc.set_window_boundaries(&[60, 100]).unwrap();
+        enc.write_target(&[2u8; 20]).unwrap();
+        // Boundary 100 lies beyond the 70-byte target.
+        assert!(enc.finish().is_err());
+    }
+
+    #[test]
+    fn aggressive_no_larger_than_greedy() {
+        // Corpus-like chunk: text with scattered edits and short repeats.
+        const WORDS: [&str; 12] = [
+            "fn ", "let ", "match ", "window", "source", "target", " => ", "copy_", "addr", "len",
+            ";\n", "    ",
+        ];
+        let mut rng = XorShift32::new(0x5EED_0211);
+        let mut source = Vec::new();
+        while source.len() < 32 * 1024 {
+            source.extend_from_slice(WORDS[rng.below(12) as usize].as_bytes());
+        }
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(61) {
+            target[i] = target[i].wrapping_add(3);
+        }
+
+        let encode_at = |opt_level| {
+            let mut out = Vec::new();
+            encode_all(
+                &mut out,
+                &source,
+                &target,
+                CompressOptions {
+                    opt_level: Some(opt_level),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            out
+        };
+        let greedy = encode_at(OptLevel::Greedy);
+        let aggressive = encode_at(OptLevel::Aggressive);
+        assert!(
+            aggressive.len() <= greedy.len(),
+            "aggressive {} > greedy {}",
+            aggressive.len(),
+            greedy.len()
+        );
+        for delta in [&greedy, &aggressive] {
+            let decoded = crate::vcdiff::decoder::decode_memory(delta, &source).unwrap();
+            assert_eq!(decoded, target);
+        }
+    }
+
+    #[test]
+    fn xdelta3_can_decode_our_output() {
+        let source = b"The quick brown fox jumps over the lazy dog. 1234567890";
+        let target = b"The quick brown cat sits on the lazy mat. 1234567890!!!";
+        let mut output = Vec::new();
+        encode_all(&mut output, source, target, CompressOptions::default()).unwrap();
+
+        // Verify xdelta3 C library can decode it.
+        match xdelta3::decode(&output, source) {
+            Some(decoded) => assert_eq!(decoded, target),
+            None => panic!("xdelta3 crate failed to decode our output"),
+        }
+    }
+
+    #[test]
+    fn prefer_run_byte_emits_run_over_copy() {
+        // Erased (0xFF) region present in both source and target.
+        let mut source = b"bootloader image block ".repeat(16);
+        source.extend(std::iter::repeat_n(0xFF, 4096));
+        source.extend(b"application image block ".repeat(16));
+        let mut target = source.clone();
+        target[10] = b'X';
+
+        let opts = CompressOptions {
+            prefer_run_byte: Some(0xFF),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        encode_all(&mut output, &source, &target, opts).unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
+        assert_eq!(decoded, target);
+
+        // Inspect the single window: the erased region must be a RUN.
+        let mut cursor = std::io::Cursor::new(&output);
+        crate::vcdiff::header::FileHeader::decode(&mut cursor).unwrap();
+        let wh = crate::vcdiff::header::WindowHeader::decode(&mut cursor)
+            .unwrap()
+            .unwrap();
+        let start = cursor.position() as usize + wh.data_len as usize;
+        let inst = &output[start..start + wh.inst_len as usize];
+        let addr = &output[start + inst.len()..start + inst.len() + wh.addr_len as usize];
+        let insts: Vec<Instruction> =
+            crate::vcdiff::decoder::InstructionIterator::new(inst, addr, wh.copy_window_len)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert!(
+            insts.contains(&Instruction::Run { len: 4096 }),
+            "expected RUN(4096) for erased region: {insts:?}"
+        );
+    }
+
+    #[test]
+    fn encoder_stats_cover_all_windows() {
+        let source: Vec<u8> = (0..=255).cycle().take(64 * 1024).collect();
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(1000) {
+            target[i] ^= 0x5A;
+        }
+
+        let mut output = Vec::new();
+        let opts = CompressOptions {
+            window_size: 16 * 1024,
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(&mut output, &source, opts);
+        enc.write_target(&target[..40_000]).unwrap();
+        // Two full windows so far; the rest is buffered.
+        assert_eq!(enc.stats().total_bytes(), 32 * 1024);
+        enc.write_target(&target[40_000..]).unwrap();
+        enc.flush().unwrap();
+        let stats = *enc.stats();
+        let (_, finished) = enc.finish().unwrap();
+
+        assert_eq!(finished.windows, 4);
+        assert_eq!(stats.total_bytes(), target.len() as u64);
+        assert!(stats.bytes_copied_source > stats.bytes_added);
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&output, &source).unwrap(),
+            target
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_encode_roundtrip() {
+        let source: Vec<u8> = (0..=255).cycle().take(512 * 1024).collect();
+        let mut target = source.clone();
+        for i in (0..target.len()).step_by(997) {
+            target[i] ^= 0xA5;
+        }
+
+        let mut output = Vec::new();
+        encode_all_parallel(
+            &mut output,
+            &source,
+            &target,
+            CompressOptions {
+                level: 6,
+                window_size: 64 * 1024,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_encode_stays_close_to_sequential() {
+        let mut rng = XorShift32::new(0x1234_5678);
+        let mut random = |n: usize| rng.bytes(n);
+        let source = random(1 << 20);
+        // Shifted, edited copies of the source scattered through the target,
+        // so neither the unchanged nor the prefix/suffix shortcut applies.
+        let mut target = Vec::new();
+        for i in 0..24 {
+            let at = (i * 40_503) % (source.len() - 50_000);
+            target.extend_from_slice(&source[at..at + 40_000]);
+            target.extend(random(100 + i * 7));
+        }
+        // A stretch continuing the source across several whole windows.
+        target.extend_from_slice(&source[..4 * 16 * 1024]);
+
+        // Windows pick up the probe where the previous whole-window match
+        // left it, and keep it after a window that does not continue.
+        let chunks = [
+            &source[..100],
+            &source[100..200],
+            &[0u8; 100][..],
+            &source[300..400],
+        ];
+        assert_eq!(match_target_probes(&source, &chunks), [0, 100, 200, 200]);
+
+        for level in [3, 6, 9] {
+            let opts = CompressOptions {
+                level,
+                window_size: 16 * 1024,
+                ..Default::default()
+            };
+            let sequential = encode_all(Vec::new(), &source, &target, opts.clone()).unwrap();
+            let parallel = encode_all_parallel(Vec::new(), &source, &target, opts).unwrap();
+            assert_eq!(
+                crate::vcdiff::decoder::decode_memory(&parallel, &source).unwrap(),
+                target
+            );
+            assert!(
+                parallel.len() * 100 <= sequential.len() * 102,
+                "level {level}: parallel {} vs sequential {}",
+                parallel.len(),
+                sequential.len()
+            );
+        }
+    }
+
+    #[cfg(feature = "lzma-secondary")]
+    #[test]
+    fn secondary_lzma_roundtrip() {
+        let source: Vec<u8> = b"ABCDEFGHIJ".iter().copied().cycle().take(4096).collect();
+        let mut target = source.clone();
+        target[100] = b'X';
+        target[500] = b'Y';
+
+        let mut output = Vec::new();
+        encode_all(
+            &mut output,
+            &source,
+            &target,
+            CompressOptions {
+                level: 6,
+                secondary: SecondaryCompression::Lzma,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[cfg(feature = "zlib-secondary")]
+    #[test]
+    fn secondary_zlib_roundtrip() {
+        let source: Vec<u8> = b"ABCDEFGHIJ".iter().copied().cycle().take(4096).collect();
+        let mut target = source.clone();
+        target[100] = b'X';
+        target[500] = b'Y';
+
+        let mut output = Vec::new();
+        encode_all(
+            &mut output,
+            &source,
+            &target,
+            CompressOptions {
+                level: 6,
+                secondary: SecondaryCompression::Zlib { level: 6 },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let decoded = crate::vcdiff::decoder::decode_memory(&output, &source).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    /// Backend whose `compress` always fails.
+    struct FailingBackend;
+
+    impl CompressBackend for FailingBackend {
+        fn id(&self) -> u8 {
+            200
+        }
+        fn compress(&self, _data: &[u8]) -> std::io::Result<Vec<u8>> {
+            Err(std::io::Error::other("backend exploded"))
+        }
+        fn decompress(
+            &self,
+            data: &[u8],
+            _limit: usize,
+        ) -> Result<Vec<u8>, crate::vcdiff::DecodeError> {
+            Ok(data.to_vec())
+        }
+        fn should_compress(&self, _data: &[u8]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn secondary_failure_is_reported() {
+        let source: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        target[100] ^= 0xFF;
+        let opts = CompressOptions {
+            secondary: SecondaryCompression::Custom(Arc::new(FailingBackend)),
+            ..Default::default()
+        };
+
+        let err = encode_all(Vec::new(), &source, &target, opts.clone()).unwrap_err();
+        assert!(
+            matches!(&err, EncodeError::Secondary(msg) if msg.contains("backend exploded")),
+            "{err:?}"
+        );
+
+        #[cfg(feature = "parallel")]
+        {
+            let err = encode_all_parallel(Vec::new(), &source, &target, opts).unwrap_err();
+            assert!(matches!(err, EncodeError::Secondary(_)), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_input_errors() {
+        let opts = CompressOptions {
+            window_size: 0,
+            ..Default::default()
+        };
+        let err = encode_all(Vec::new(), b"abc", b"abcdef", opts.clone()).unwrap_err();
+        assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+        #[cfg(feature = "parallel")]
+        {
+            let err = encode_all_parallel(Vec::new(), b"abc", b"abcdef", opts).unwrap_err();
+            assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+        }
+
+        let mut enc = DeltaEncoder::new(Vec::new(), b"abc", CompressOptions::default());
+        enc.write_target(b"abcdef").unwrap();
+        let err = enc.set_window_boundaries(&[3]).unwrap_err();
+        assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+        enc.set_window_boundaries(&[100]).unwrap();
+        let err = enc.finish().unwrap_err();
+        assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+
+        // A secondary ID cannot be recorded once the header is out.
+        let mut enc = DeltaEncoder::new(Vec::new(), b"abc", CompressOptions::default());
+        enc.register_secondary(200, Arc::new(secondary::NoCompression))
+            .unwrap();
+        enc.write_target(b"abcdef").unwrap();
+        enc.flush_partial().unwrap();
+        let err = enc
+            .register_secondary(201, Arc::new(secondary::NoCompression))
+            .unwrap_err();
+        assert!(matches!(err, EncodeError::InvalidInput(_)), "{err:?}");
+    }
+
+    #[test]
+    fn invalid_matcher_is_rejected() {
+        let opts = CompressOptions {
+            matcher: Some(MatcherConfig {
+                large_step: 0,
+                ..config::config_for_level(6)
+            }),
+            ..Default::default()
+        };
+        let is_large_step = |err: &EncodeError| matches!(err, EncodeError::InvalidInput(msg) if msg.contains("large_step"));
+
+        let err = encode_all(Vec::new(), b"abcdefgh", b"abcdefgh", opts.clone()).unwrap_err();
+        assert!(is_large_step(&err), "{err:?}");
+        #[cfg(feature = "parallel")]
+        {
+            let err = encode_all_parallel(Vec::new(), b"abc", b"abcdef", opts.clone()).unwrap_err();
+            assert!(is_large_step(&err), "{err:?}");
+        }
+
+        let mut enc = DeltaEncoder::new(Vec::new(), b"abc", opts.clone());
+        let err = enc.write_target(b"abcdef").unwrap_err();
+        assert!(is_large_step(&err), "{err:?}");
+        let err = DeltaEncoder::new(Vec::new(), b"abc", opts)
+            .finish()
+            .unwrap_err();
+        assert!(is_large_step(&err), "{err:?}");
+    }
+
+    #[cfg(feature = "suffix-array")]
+    #[test]
+    fn suffix_array_strategy_handles_scattered_edits() {
+        use crate::compress::decoder::decode_all;
+
+        let mut rng = XorShift32::new(0x2545_F491);
+        let source = rng.bytes(256 * 1024);
+        // Firmware-style rebuild: a byte or two patched every 23..87 bytes.
+        let mut target = source.clone();
+        let mut pos = 0;
+        while pos < target.len() {
+            target[pos] = target[pos].wrapping_add(1);
+            pos += 23 + rng.below(64) as usize;
+        }
+
+        let encode = |strategy: MatchStrategy| {
+            let opts = CompressOptions {
+                window_size: 64 * 1024,
+                match_strategy: strategy,
+                ..Default::default()
+            };
+            encode_all(Vec::new(), &source, &target, opts).unwrap()
+        };
+        let hash = encode(MatchStrategy::Hash);
+        let suffix = encode(MatchStrategy::SuffixArray);
+        assert_eq!(decode_all(&source, &suffix).unwrap(), target);
+        assert_eq!(decode_all(&source, &hash).unwrap(), target);
+        assert!(
+            suffix.len() < hash.len(),
+            "suffix array {} bytes, hash {} bytes",
+            suffix.len(),
+            hash.len()
+        );
+
+        // A reader-backed source is loaded and matched the same way.
+        let opts = CompressOptions {
+            window_size: 64 * 1024,
+            match_strategy: MatchStrategy::SuffixArray,
+            ..Default::default()
+        };
+        let mut from_reader = Vec::new();
+        let reader = std::io::Cursor::new(source.as_slice());
+        let mut enc =
+            DeltaEncoder::from_reader(&mut from_reader, reader, source.len() as u64, opts.clone());
+        enc.write_target(&target).unwrap();
+        enc.finish().unwrap();
+        assert_eq!(from_reader, suffix);
+
+        #[cfg(feature = "parallel")]
+        {
+            let parallel = encode_all_parallel(Vec::new(), &source, &target, opts).unwrap();
+            assert_eq!(decode_all(&source, &parallel).unwrap(), target);
+        }
+    }
+}
@@ -0,0 +1,580 @@
+// FGK adaptive Huffman coding, for xdelta3's `-S fgk` sections.
+//
+// A straight port of xdelta3-fgk.h (Faller-Gallager-Knuth, as described by
+// Knuth in "Dynamic Huffman Coding"). Encoder and decoder both start from a
+// tree holding one zero-weight leaf that stands for all 256 unseen symbols
+// and update the tree identically after every symbol, so the code adapts
+// without ever being transmitted.
+//
+// A symbol's code is its root-to-leaf path (0 = left, 1 = right). The first
+// occurrence of a symbol is coded as the path to the zero leaf followed by
+// the symbol's index among the remaining unseen symbols, most significant
+// bit first, in `zero_exp` or `zero_exp + 1` bits. Bits are packed into
+// bytes least significant bit first; the last byte is zero-padded.
+//
+// xdelta3 frames a compressed section as the decoded size (a VCDIFF varint)
+// followed by the bitstream; `encode_section`/`decode_section` read and write
+// that whole frame. Each section starts from a fresh tree. The port has not
+// yet been checked against real `xdelta3 -S fgk` output, so `FgkBackend`
+// does not claim xdelta3 compatibility and encoding with it is behind the
+// `fgk-encode` feature.
+//
+// Nodes are kept in one array and linked by index. The first 256 entries are
+// the leaves (indexed by symbol value), internal nodes are allocated after
+// them. Zero-weight leaves reuse `left_child`/`right_child` as the links of
+// a doubly linked list of unseen symbols, whose head is the leaf that sits
+// in the tree. `left`/`right` order all tree nodes by weight, and a block
+// groups nodes of equal weight under a leader (its rightmost node).
+
+use crate::vcdiff::varint;
+
+const ALPHABET_SIZE: usize = 256;
+const TOTAL_NODES: usize = 2 * ALPHABET_SIZE - 1;
+
+/// Null node or block link.
+const NIL: u16 = u16::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    weight: u32,
+    parent: u16,
+    left_child: u16,
+    right_child: u16,
+    /// Next lighter node in weight order.
+    left: u16,
+    /// Next heavier node in weight order.
+    right: u16,
+    block: u16,
+}
+
+/// Coder state shared by the encoder and the decoder.
+struct Fgk {
+    nodes: Vec<Node>,
+    /// Leader of each block in use; unused entries are recycled through
+    /// `free_blocks`.
+    blocks: Vec<u16>,
+    free_blocks: Vec<u16>,
+    free_node: u16,
+    root: u16,
+    /// Head of the unseen-symbol list, or `NIL` once all have been seen.
+    zeros: u16,
+    /// Unseen symbols left, and `zero_count = 2^zero_exp + zero_rem`.
+    zero_count: usize,
+    zero_exp: usize,
+    zero_rem: usize,
+    coded_bits: Vec<u8>,
+    decode_ptr: u16,
+}
+
+impl Fgk {
+    fn new() -> Self {
+        let mut nodes = vec![
+            Node {
+                weight: 0,
+                parent: NIL,
+                left_child: NIL,
+                right_child: NIL,
+                left: NIL,
+                right: NIL,
+                block: NIL,
+            };
+            TOTAL_NODES
+        ];
+        for (i, node) in nodes.iter_mut().enumerate().take(ALPHABET_SIZE) {
+            if i < ALPHABET_SIZE - 1 {
+                node.right_child = i as u16 + 1;
+            }
+            if i >= 1 {
+                node.left_child = i as u16 - 1;
+            }
+        }
+        let mut fgk = Self {
+            nodes,
+            blocks: Vec::with_capacity(2 * TOTAL_NODES),
+            free_blocks: Vec::new(),
+            free_node: ALPHABET_SIZE as u16,
+            root: 0,
+            zeros: 0,
+            zero_count: ALPHABET_SIZE + 1,
+            zero_exp: 0,
+            zero_rem: 0,
+            coded_bits: Vec::with_capacity(ALPHABET_SIZE),
+            decode_ptr: 0,
+        };
+        fgk.factor_remaining();
+        fgk
+    }
+
+    /// Bits used to code an index among the unseen symbols.
+    fn zero_index_bits(&self) -> usize {
+        if self.zero_rem == 0 {
+            self.zero_exp
+        } else {
+            self.zero_exp + 1
+        }
+    }
+
+    /// Take one symbol off `zero_count` and refactor it.
+    fn factor_remaining(&mut self) {
+        self.zero_count -= 1;
+        let mut i = self.zero_count;
+        self.zero_exp = 0;
+        while i > 1 {
+            self.zero_exp += 1;
+            i >>= 1;
+        }
+        self.zero_rem = self.zero_count - (1 << self.zero_exp);
+    }
+
+    fn make_block(&mut self, leader: u16) -> u16 {
+        match self.free_blocks.pop() {
+            Some(block) => {
+                self.blocks[usize::from(block)] = leader;
+                block
+            }
+            None => {
+                self.blocks.push(leader);
+                (self.blocks.len() - 1) as u16
+            }
+        }
+    }
+
+    fn free_block(&mut self, block: u16) {
+        self.free_blocks.push(block);
+    }
+
+    fn node(&mut self, i: u16) -> &mut Node {
+        &mut self.nodes[usize::from(i)]
+    }
+
+    fn weight(&self, i: u16) -> u32 {
+        self.nodes[usize::from(i)].weight
+    }
+
+    // -----------------------------------------------------------------------
+    // Encoding
+    // -----------------------------------------------------------------------
+
+    /// Append the code for `symbol` to `bits` and update the tree.
+    fn encode(&mut self, symbol: u8, bits: &mut BitWriter) {
+        let mut target = u16::from(symbol);
+        // Collected leaf-first, emitted in reverse.
+        self.coded_bits.clear();
+
+        if self.weight(target) == 0 {
+            let mut where_ = self.zeros;
+            let mut index = 0usize;
+            while where_ != target {
+                where_ = self.nodes[usize::from(where_)].right_child;
+                index += 1;
+            }
+            for bit in 0..self.zero_index_bits() {
+                self.coded_bits.push(((index >> bit) & 1) as u8);
+            }
+            target = self.zeros;
+        }
+
+        while target != self.root {
+            let parent = self.nodes[usize::from(target)].parent;
+            let is_right = self.nodes[usize::from(parent)].right_child == target;
+            self.coded_bits.push(u8::from(is_right));
+            target = parent;
+        }
+
+        for &bit in self.coded_bits.iter().rev() {
+            bits.push(bit);
+        }
+        self.update_tree(u16::from(symbol));
+    }
+
+    // -----------------------------------------------------------------------
+    // Decoding
+    // -----------------------------------------------------------------------
+
+    /// Feed one bit; returns the symbol once its code is complete.
+    fn decode_bit(&mut self, bit: u8) -> Option<u8> {
+        let done = if self.weight(self.decode_ptr) == 0 {
+            self.coded_bits.push(bit);
+            self.coded_bits.len() >= self.zero_index_bits()
+        } else {
+            let ptr = self.nodes[usize::from(self.decode_ptr)];
+            self.decode_ptr = if bit != 0 {
+                ptr.right_child
+            } else {
+                ptr.left_child
+            };
+            let ptr = self.nodes[usize::from(self.decode_ptr)];
+            // A leaf: either a seen symbol, or the zero leaf when its index
+            // needs no bits because one unseen symbol is left.
+            ptr.left_child == NIL && (ptr.weight != 0 || self.zero_count == 1)
+        };
+        done.then(|| self.decode_symbol())
+    }
+
+    fn decode_symbol(&mut self) -> u8 {
+        let mut symbol = self.decode_ptr;
+        if self.weight(symbol) == 0 {
+            let index = self
+                .coded_bits
+                .iter()
+                .fold(0usize, |n, &bit| (n << 1) | usize::from(bit));
+            symbol = self.zeros;
+            for _ in 0..index {
+                let next = self.nodes[usize::from(symbol)].right_child;
+                if next == NIL {
+                    break;
+                }
+                symbol = next;
+            }
+        }
+        self.coded_bits.clear();
+        self.update_tree(symbol);
+        self.decode_ptr = self.root;
+        symbol as u8
+    }
+
+    // -----------------------------------------------------------------------
+    // Tree maintenance
+    // -----------------------------------------------------------------------
+
+    /// Count one more occurrence of `symbol`.
+    fn update_tree(&mut self, symbol: u16) {
+        let mut incr = if self.weight(symbol) == 0 {
+            self.increase_zero_weight(symbol)
+        } else {
+            symbol
+        };
+        while incr != self.root {
+            self.move_right(incr);
+            self.promote(incr);
+            self.node(incr).weight += 1;
+            incr = self.nodes[usize::from(incr)].parent;
+        }
+        let root = self.root;
+        self.node(root).weight += 1;
+    }
+
+    /// Swap `fwd` with the leader of its block, so that it becomes the
+    /// rightmost node of that weight.
+    fn move_right(&mut self, fwd: u16) {
+        let f = self.nodes[usize::from(fwd)];
+        let back = self.blocks[usize::from(f.block)];
+        if fwd == back || f.parent == back || f.weight == 0 {
+            return;
+        }
+
+        // Exchange the two nodes' places in weight order.
+        let back_right = self.nodes[usize::from(back)].right;
+        self.node(back_right).left = fwd;
+        if f.left != NIL {
+            self.node(f.left).right = back;
+        }
+        let tmp = self.nodes[usize::from(fwd)].right;
+        self.node(fwd).right = back_right;
+        if tmp == back {
+            self.node(back).right = fwd;
+        } else {
+            self.node(tmp).left = back;
+            self.node(back).right = tmp;
+        }
+        let tmp = self.nodes[usize::from(back)].left;
+        let fwd_left = self.nodes[usize::from(fwd)].left;
+        self.node(back).left = fwd_left;
+        if tmp == fwd {
+            self.node(fwd).left = back;
+        } else {
+            self.node(tmp).right = fwd;
+            self.node(fwd).left = tmp;
+        }
+
+        // Exchange their places in the tree.
+        let fwd_parent = self.nodes[usize::from(fwd)].parent;
+        let back_parent = self.nodes[usize::from(back)].parent;
+        let fwd_is_right = self.nodes[usize::from(fwd_parent)].right_child == fwd;
+        let back_is_right = self.nodes[usize::from(back_parent)].right_child == back;
+        self.node(fwd).parent = back_parent;
+        self.node(back).parent = fwd_parent;
+        self.set_child(fwd_parent, fwd_is_right, back);
+        self.set_child(back_parent, back_is_right, fwd);
+
+        self.blocks[usize::from(f.block)] = fwd;
+    }
+
+    fn set_child(&mut self, parent: u16, right: bool, child: u16) {
+        if right {
+            self.node(parent).right_child = child;
+        } else {
+            self.node(parent).left_child = child;
+        }
+    }
+
+    /// Move `node`, the leader of its block, into the block of the next
+    /// weight up (or a new block of its own).
+    fn promote(&mut self, node: u16) {
+        let n = self.nodes[usize::from(node)];
+        let (my_left, my_right, cur_block) = (n.left, n.right, n.block);
+        if n.weight == 0 {
+            return;
+        }
+
+        // The parent of the zero leaf: its right child, the only other node
+        // between it and the zero leaf, has already been incremented.
+        if my_left == n.right_child && n.left_child != NIL && self.weight(n.left_child) == 0 {
+            if n.weight + 1 == self.weight(my_right) && my_right != self.root {
+                self.free_block(cur_block);
+                let block = self.nodes[usize::from(my_right)].block;
+                self.node(node).block = block;
+                self.node(my_left).block = block;
+            }
+            return;
+        }
+
+        if my_left == self.zeros {
+            return;
+        }
+
+        if self.nodes[usize::from(my_left)].block == cur_block {
+            self.blocks[usize::from(cur_block)] = my_left;
+        } else {
+            self.free_block(cur_block);
+        }
+
+        self.node(node).block = if n.weight + 1 == self.weight(my_right) && my_right != self.root {
+            self.nodes[usize::from(my_right)].block
+        } else {
+            self.make_block(node)
+        };
+    }
+
+    /// Take `symbol` off the unseen list and give it a leaf of its own,
+    /// splitting the zero leaf with a new internal node.
+    fn increase_zero_weight(&mut self, symbol: u16) -> u16 {
+        if self.zero_count == 1 {
+            // The last unseen symbol already holds the zero leaf.
+            self.node(symbol).right_child = NIL;
+            let right = self.nodes[usize::from(symbol)].right;
+            self.node(symbol).block = if self.weight(right) == 1 {
+                self.nodes[usize::from(right)].block
+            } else {
+                self.make_block(symbol)
+            };
+            self.zeros = NIL;
+            return symbol;
+        }
+
+        let zero_ptr = self.zeros;
+        let internal = self.free_node;
+        self.free_node += 1;
+
+        let z = self.nodes[usize::from(zero_ptr)];
+        *self.node(internal) = Node {
+            weight: 0,
+            parent: z.parent,
+            left_child: NIL,
+            right_child: symbol,
+            left: symbol,
+            right: z.right,
+            block: NIL,
+        };
+
+        if self.zeros == self.root {
+            // The first symbol coded.
+            self.root = internal;
+            let block = self.make_block(symbol);
+            self.node(symbol).block = block;
+            let block = self.make_block(internal);
+            self.node(internal).block = block;
+        } else {
+            self.node(z.right).left = internal;
+            let is_right = self.nodes[usize::from(z.parent)].right_child == zero_ptr;
+            self.set_child(z.parent, is_right, internal);
+            let block = if self.weight(z.right) == 1 {
+                self.nodes[usize::from(z.right)].block
+            } else {
+                self.make_block(internal)
+            };
+            self.node(internal).block = block;
+            self.node(symbol).block = block;
+        }
+
+        self.eliminate_zero(symbol);
+
+        let zeros = self.zeros;
+        self.node(internal).left_child = zeros;
+        let s = self.node(symbol);
+        s.right = internal;
+        s.left = zeros;
+        s.parent = internal;
+        s.left_child = NIL;
+        s.right_child = NIL;
+        let z = self.node(zeros);
+        z.parent = internal;
+        z.right = symbol;
+
+        symbol
+    }
+
+    /// Splice `symbol` out of the unseen list.
+    fn eliminate_zero(&mut self, symbol: u16) {
+        if self.zero_count == 1 {
+            return;
+        }
+        self.factor_remaining();
+
+        let s = self.nodes[usize::from(symbol)];
+        if s.left_child == NIL {
+            self.zeros = self.nodes[usize::from(self.zeros)].right_child;
+            let zeros = self.zeros;
+            self.node(zeros).left_child = NIL;
+        } else if s.right_child == NIL {
+            self.node(s.left_child).right_child = NIL;
+        } else {
+            self.node(s.right_child).left_child = s.left_child;
+            self.node(s.left_child).right_child = s.right_child;
+        }
+    }
+}
+
+/// Packs bits least significant first, as xdelta3's `xd3_encode_bit`.
+struct BitWriter {
+    out: Vec<u8>,
+    byte: u8,
+    mask: u16,
+}
+
+impl BitWriter {
+    fn push(&mut self, bit: u8) {
+        if bit != 0 {
+            self.byte |= self.mask as u8;
+        }
+        if self.mask == 0x80 {
+            self.out.push(self.byte);
+            self.byte = 0;
+            self.mask = 1;
+        } else {
+            self.mask <<= 1;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.mask != 1 {
+            self.out.push(self.byte);
+        }
+        self.out
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Section framing
+// ---------------------------------------------------------------------------
+
+/// Compress a section: its size as a varint, then the FGK bitstream.
+pub(crate) fn encode_section(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter {
+        out: Vec::with_capacity(data.len() / 2 + varint::MAX_VARINT_LEN),
+        byte: 0,
+        mask: 1,
+    };
+    varint::write_usize(&mut bits.out, data.len()).expect("writing to a Vec");
+
+    let mut fgk = Fgk::new();
+    for &symbol in data {
+        fgk.encode(symbol, &mut bits);
+    }
+    bits.finish()
+}
+
+/// Decompress a section written by [`encode_section`] (or by xdelta3).
+pub(crate) fn decode_section(section: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (size, n) = varint::read_usize(section).map_err(|_| "bad section size")?;
+    let input = &section[n..];
+    // Every symbol takes at least one bit, so `size` is bounded by the
+    // input; don't trust it further than that when allocating.
+    let mut out = Vec::with_capacity(size.min(input.len().saturating_mul(8)));
+    if size == 0 {
+        return if input.is_empty() {
+            Ok(out)
+        } else {
+            Err("unused input after the last symbol")
+        };
+    }
+
+    let mut fgk = Fgk::new();
+    for (i, &byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            let Some(symbol) = fgk.decode_bit((byte >> bit) & 1) else {
+                continue;
+            };
+            out.push(symbol);
+            if out.len() == size {
+                // The rest of this byte is padding.
+                return if i + 1 == input.len() {
+                    Ok(out)
+                } else {
+                    Err("unused input after the last symbol")
+                };
+            }
+        }
+    }
+    Err("input ends before the last symbol")
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    #[test]
+    fn first_symbols_are_coded_as_zero_indices() {
+        // 'a' (0x61) first: its index among 256 unseen symbols, MSB first
+        // (0,1,1,0,0,0,0,1); then 11 repeats at depth 1 (bit 1 each). The
+        // 19 bits pack LSB first into 0x86 0xFF 0x07.
+        let section = encode_section(&[b'a'; 12]);
+        assert_eq!(section, [12, 0x86, 0xFF, 0x07]);
+        assert_eq!(decode_section(&section).unwrap(), [b'a'; 12]);
+    }
+
+    #[test]
+    fn roundtrips_every_alphabet_shape() {
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut rand = move || rng.next_u32();
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            vec![7],
+            (0..=255).collect(),
+            (0..=255).rev().cycle().take(2000).collect(),
+            (0..5000).map(|_| (rand() % 7) as u8).collect(),
+            (0..5000).map(|_| rand() as u8).collect(),
+            (0..5000)
+                .map(|_| (rand() % 256).min(rand() % 256) as u8)
+                .collect(),
+            b"the quick brown fox jumps over the lazy dog ".repeat(40),
+        ];
+        for input in inputs {
+            let section = encode_section(&input);
+            assert_eq!(
+                decode_section(&section).unwrap(),
+                input,
+                "{} bytes",
+                input.len()
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_sections_are_rejected() {
+        let section = encode_section(&b"abracadabra".repeat(4));
+        assert!(decode_section(&section[..section.len() - 1]).is_err());
+        let mut padded = section.clone();
+        padded.push(0);
+        assert!(decode_section(&padded).is_err());
+        assert!(decode_section(&[]).is_err());
+        assert!(decode_section(&[0x80]).is_err());
+        // A size far beyond what the input can hold fails, not allocates.
+        assert!(decode_section(&[0x8F, 0xFF, 0xFF, 0xFF, 0x7F, 0x00]).is_err());
+    }
+}
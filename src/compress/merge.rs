@@ -0,0 +1,465 @@
+// Delta merging: compose a chain of deltas into one.
+//
+// Given deltas source->t1, t1->t2, ..., t(n-1)->tn, `merge_deltas` produces
+// a single source->tn delta without materializing any intermediate target
+// (the xdelta3 `merge` command). Each target is described as a list of
+// pieces in terms of the original source:
+//   - Add:  literal bytes (kept in a per-target literal pool)
+//   - Run:  a repeated byte
+//   - Copy: a range of the original source
+// A later delta's COPY from its source is resolved by slicing the earlier
+// target's pieces (COPY-of-COPY chaining); its target self-copies slice the
+// target being built. ADD and RUN pass through unchanged. Memory use is
+// proportional to the number of pieces and literal bytes, not to target size.
+
+use crate::vcdiff::code_table::Instruction;
+use crate::vcdiff::decoder::DecodeError;
+use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
+use crate::vcdiff::header::FileHeader;
+
+use super::decoder::next_raw_window;
+
+/// Target bytes per window of a merged delta.
+const MERGE_WINDOW_SIZE: u64 = 1 << 23; // 8 MiB
+
+// ---------------------------------------------------------------------------
+// Target description
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    /// Literal bytes starting at this offset of the literal pool.
+    Add(usize),
+    Run(u8),
+    /// Bytes starting at this offset of the original source.
+    Copy(u64),
+}
+
+impl Piece {
+    /// The piece for the bytes `skip` bytes into this one.
+    fn advance(self, skip: u64) -> Self {
+        match self {
+            Piece::Add(pool) => Piece::Add(pool + skip as usize),
+            Piece::Run(byte) => Piece::Run(byte),
+            Piece::Copy(offset) => Piece::Copy(offset + skip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    /// Offset of the segment in the target.
+    start: u64,
+    len: u64,
+    piece: Piece,
+}
+
+/// A target expressed as pieces of the original source, runs and literals.
+#[derive(Default)]
+struct Target {
+    segments: Vec<Segment>,
+    pool: Vec<u8>,
+    len: u64,
+}
+
+impl Target {
+    /// Append `len` bytes described by `piece`, extending the last segment
+    /// when the two are contiguous.
+    fn push(&mut self, len: u64, piece: Piece) {
+        if len == 0 {
+            return;
+        }
+        if let Some(last) = self.segments.last_mut() {
+            let contiguous = match (last.piece, piece) {
+                (Piece::Add(a), Piece::Add(b)) => a + last.len as usize == b,
+                (Piece::Run(a), Piece::Run(b)) => a == b,
+                (Piece::Copy(a), Piece::Copy(b)) => a + last.len == b,
+                _ => false,
+            };
+            if contiguous {
+                last.len += len;
+                self.len += len;
+                return;
+            }
+        }
+        self.segments.push(Segment {
+            start: self.len,
+            len,
+            piece,
+        });
+        self.len += len;
+    }
+
+    fn push_literal(&mut self, bytes: &[u8]) {
+        let at = self.pool.len();
+        self.pool.extend_from_slice(bytes);
+        self.push(bytes.len() as u64, Piece::Add(at));
+    }
+
+    /// The pieces covering `offset..offset + len` of this target.
+    fn range(&self, offset: u64, len: u64) -> Result<Vec<(u64, Piece)>, DecodeError> {
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= self.len)
+            .ok_or_else(|| {
+                DecodeError::InvalidInput(format!(
+                    "COPY of {len} bytes at {offset} is outside the {}-byte source",
+                    self.len
+                ))
+            })?;
+        let first = self
+            .segments
+            .partition_point(|seg| seg.start + seg.len <= offset);
+        let mut pieces = Vec::new();
+        for seg in &self.segments[first..] {
+            if seg.start >= end {
+                break;
+            }
+            let from = offset.max(seg.start);
+            let to = end.min(seg.start + seg.len);
+            pieces.push((to - from, seg.piece.advance(from - seg.start)));
+        }
+        Ok(pieces)
+    }
+
+    /// Append bytes `offset..offset + len` of `earlier` (the previous target
+    /// in the chain), copying its literals into this target's pool.
+    fn copy_from(&mut self, earlier: &Target, offset: u64, len: u64) -> Result<(), DecodeError> {
+        for (n, piece) in earlier.range(offset, len)? {
+            match piece {
+                Piece::Add(pool) => self.push_literal(&earlier.pool[pool..pool + n as usize]),
+                other => self.push(n, other),
+            }
+        }
+        Ok(())
+    }
+
+    /// Append bytes `offset..offset + len` of this target itself. The range
+    /// may overlap the bytes being appended (periodic copies).
+    fn copy_within(&mut self, offset: u64, len: u64) -> Result<(), DecodeError> {
+        if offset >= self.len {
+            return Err(DecodeError::InvalidInput(format!(
+                "self-copy from {offset} is past the {} bytes decoded so far",
+                self.len
+            )));
+        }
+        let mut done = 0;
+        while done < len {
+            // At most the bytes that already exist before this chunk.
+            let chunk = (len - done).min(self.len - (offset + done));
+            for (n, piece) in self.range(offset + done, chunk)? {
+                self.push(n, piece);
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Merge
+// ---------------------------------------------------------------------------
+
+/// Merge a chain of deltas into a single delta against the original source.
+///
+/// `deltas[0]` applies to a source of `source_len` bytes, and each later
+/// delta applies to the output of the one before it. The result decodes to
+/// the same bytes as applying them in sequence. No intermediate target is
+/// materialized, so the merged delta carries no Adler-32 checksums and no
+/// secondary compression.
+pub fn merge_deltas(source_len: u64, deltas: &[&[u8]]) -> Result<Vec<u8>, DecodeError> {
+    if deltas.is_empty() {
+        return Err(DecodeError::InvalidInput("no deltas to merge".into()));
+    }
+
+    let mut previous: Option<Target> = None;
+    for delta in deltas {
+        previous = Some(resolve_delta(delta, source_len, previous.as_ref())?);
+    }
+    let target = previous.unwrap_or_default();
+    Ok(emit(&target, source_len))
+}
+
+/// Smallest source length `delta` can be applied to: the end of its
+/// furthest source window. Useful as `merge_deltas`'s `source_len` when the
+/// source file itself is unavailable.
+pub fn source_extent(delta: &[u8]) -> Result<u64, DecodeError> {
+    let mut cursor = std::io::Cursor::new(delta);
+    let file_hdr = FileHeader::decode(&mut cursor)?;
+    let mut extent = 0;
+    let mut windows = 0;
+    while let Some(window) = next_raw_window(delta, &mut cursor, &file_hdr, windows)? {
+        windows += 1;
+        if window.header.has_source() {
+            let wh = &window.header;
+            extent = extent.max(add(wh.copy_window_offset, wh.copy_window_len)?);
+        }
+    }
+    Ok(extent)
+}
+
+/// Describe `delta`'s target in terms of the original source, resolving
+/// source copies through `earlier` (`None` for the first delta).
+fn resolve_delta(
+    delta: &[u8],
+    source_len: u64,
+    earlier: Option<&Target>,
+) -> Result<Target, DecodeError> {
+    let mut cursor = std::io::Cursor::new(delta);
+    let file_hdr = FileHeader::decode(&mut cursor)?;
+    let mut target = Target::default();
+
+    let mut windows = 0;
+    while let Some(window) = next_raw_window(delta, &mut cursor, &file_hdr, windows)? {
+        windows += 1;
+        let wh = &window.header;
+        let window_start = target.len;
+        let cwl = wh.copy_window_len;
+        let data = &window.data[..];
+        let mut data_pos = 0usize;
+
+        for inst in window.instructions(&file_hdr) {
+            match inst? {
+                Instruction::Add { len } => {
+                    let bytes = data_pos
+                        .checked_add(len as usize)
+                        .and_then(|end| data.get(data_pos..end))
+                        .ok_or_else(|| {
+                            DecodeError::InvalidInput("data section underflow (ADD)".into())
+                        })?;
+                    let end = data_pos + bytes.len();
+                    target.push_literal(bytes);
+                    data_pos = end;
+                }
+                Instruction::Run { len } => {
+                    let byte = *data.get(data_pos).ok_or_else(|| {
+                        DecodeError::InvalidInput("data section underflow (RUN)".into())
+                    })?;
+                    target.push(len as u64, Piece::Run(byte));
+                    data_pos += 1;
+                }
+                Instruction::Copy { len, addr, .. } => {
+                    let len = len as u64;
+                    if addr >= cwl {
+                        target.copy_within(add(window_start, addr - cwl)?, len)?;
+                    } else if add(addr, len)? > cwl {
+                        return Err(DecodeError::InvalidInput(
+                            "COPY spans source/target boundary".into(),
+                        ));
+                    } else if wh.has_target() {
+                        target.copy_within(add(wh.copy_window_offset, addr)?, len)?;
+                    } else {
+                        let offset = add(wh.copy_window_offset, addr)?;
+                        match earlier {
+                            Some(earlier) => target.copy_from(earlier, offset, len)?,
+                            None if add(offset, len)? <= source_len => {
+                                target.push(len, Piece::Copy(offset));
+                            }
+                            None => {
+                                return Err(DecodeError::InvalidInput(format!(
+                                    "COPY of {len} bytes at {offset} is outside the \
+                                     {source_len}-byte source"
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if target.len - window_start != wh.target_window_len {
+            return Err(DecodeError::InvalidInput(format!(
+                "target size mismatch: expected {}, got {}",
+                wh.target_window_len,
+                target.len - window_start
+            )));
+        }
+    }
+    Ok(target)
+}
+
+/// `a + b` for offsets read from a delta, which may be arbitrarily large.
+fn add(a: u64, b: u64) -> Result<u64, DecodeError> {
+    a.checked_add(b)
+        .ok_or_else(|| DecodeError::InvalidInput(format!("offset overflow: {a} + {b}")))
+}
+
+/// Encode `target` as a delta against a source of `source_len` bytes.
+fn emit(target: &Target, source_len: u64) -> Vec<u8> {
+    let source_win = (source_len > 0).then_some(SourceWindow {
+        len: source_len,
+        offset: 0,
+    });
+    let mut stream = StreamEncoder::new(Vec::new(), false);
+
+    let mut window_start = 0u64;
+    let mut segments = target.segments.iter().peekable();
+    let mut carry: Option<Segment> = None;
+    while window_start < target.len {
+        let window_end = (window_start + MERGE_WINDOW_SIZE).min(target.len);
+        let mut we = WindowEncoder::new(source_win, false);
+        while let Some(seg) = carry.take().or_else(|| segments.next().copied()) {
+            let take = seg.len.min(window_end - seg.start);
+            match seg.piece {
+                Piece::Add(pool) => we.add(&target.pool[pool..pool + take as usize]),
+                Piece::Run(byte) => we.run(take as u32, byte),
+                Piece::Copy(offset) => we.copy_with_auto_mode(take as u32, offset),
+            }
+            if take < seg.len {
+                // The rest of this segment starts the next window.
+                carry = Some(Segment {
+                    start: seg.start + take,
+                    len: seg.len - take,
+                    piece: seg.piece.advance(take),
+                });
+                break;
+            }
+        }
+        stream
+            .write_window(we, None)
+            .expect("writing to a Vec cannot fail");
+        window_start = window_end;
+    }
+    stream.finish().expect("writing to a Vec cannot fail")
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::decoder::decode_all;
+    use crate::compress::encoder::{CompressOptions, encode_all};
+
+    fn encode(source: &[u8], target: &[u8], opts: CompressOptions) -> Vec<u8> {
+        let mut delta = Vec::new();
+        encode_all(&mut delta, source, target, opts).unwrap();
+        delta
+    }
+
+    fn edit(base: &[u8], seed: u32) -> Vec<u8> {
+        let mut out = base.to_vec();
+        for i in (seed as usize..out.len()).step_by(1500) {
+            out[i] = out[i].wrapping_add(seed as u8);
+        }
+        out.splice(
+            2000..2000,
+            format!("inserted block {seed} ").repeat(20).bytes(),
+        );
+        out.drain(9000..9400);
+        out.extend(std::iter::repeat_n(b'z', 300));
+        out
+    }
+
+    #[test]
+    fn merge_three_patch_chain() {
+        let v0: Vec<u8> = (0..30_000u32).map(|i| ((i * 7) ^ (i >> 5)) as u8).collect();
+        let v1 = edit(&v0, 3);
+        let v2 = edit(&v1, 11);
+        let v3 = edit(&v2, 29);
+        let opts = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        let d1 = encode(&v0, &v1, opts.clone());
+        let d2 = encode(&v1, &v2, opts.clone());
+        let d3 = encode(&v2, &v3, opts);
+
+        // Sequential application.
+        let sequential = decode_all(
+            &decode_all(&decode_all(&v0, &d1).unwrap(), &d2).unwrap(),
+            &d3,
+        )
+        .unwrap();
+        assert_eq!(sequential, v3);
+
+        let merged = merge_deltas(v0.len() as u64, &[&d1, &d2, &d3]).unwrap();
+        assert_eq!(decode_all(&v0, &merged).unwrap(), sequential);
+        // Mostly copies of the original: far smaller than the target.
+        assert!(merged.len() < v3.len() / 4, "merged {} bytes", merged.len());
+    }
+
+    #[test]
+    fn merge_resolves_self_copies_and_runs() {
+        let v0 = b"The quick brown fox jumps over the lazy dog".repeat(10);
+        // No source in the first patch: self-copies and runs only.
+        let v1: Vec<u8> = b"abcabcabcabcabc"
+            .iter()
+            .copied()
+            .chain(std::iter::repeat_n(b'-', 100))
+            .chain(v0[..40].iter().copied())
+            .collect();
+        let v2 = [&v1[10..60], &v1[..], b"tail"].concat();
+        let d1 = encode(&v0, &v1, CompressOptions::default());
+        let d2 = encode(&v1, &v2, CompressOptions::default());
+
+        let merged = merge_deltas(v0.len() as u64, &[&d1, &d2]).unwrap();
+        assert_eq!(decode_all(&v0, &merged).unwrap(), v2);
+    }
+
+    #[test]
+    fn merge_single_delta_and_errors() {
+        let v0 = b"0123456789".repeat(100);
+        let v1 = [&v0[500..], b"new".as_slice(), &v0[..500]].concat();
+        let d1 = encode(&v0, &v1, CompressOptions::default());
+        let merged = merge_deltas(v0.len() as u64, &[&d1]).unwrap();
+        assert_eq!(decode_all(&v0, &merged).unwrap(), v1);
+        assert_eq!(source_extent(&d1).unwrap(), v0.len() as u64);
+
+        assert!(merge_deltas(0, &[]).is_err());
+        // The source is too short for the first delta's copies.
+        assert!(merge_deltas(10, &[&d1]).is_err());
+    }
+
+    #[test]
+    fn merge_rejects_overflowing_offsets() {
+        use crate::vcdiff::header::{VCD_SOURCE, WindowHeader};
+
+        // One COPY of 4 bytes from a copy window that ends past u64::MAX.
+        let inst = [0x14u8]; // COPY, size 4, mode 0
+        let addr = [0x00u8];
+        let mut wh = WindowHeader {
+            win_ind: VCD_SOURCE,
+            copy_window_len: 4,
+            copy_window_offset: u64::MAX - 1,
+            enc_len: 0,
+            target_window_len: 4,
+            del_ind: 0,
+            data_len: 0,
+            inst_len: inst.len() as u64,
+            addr_len: addr.len() as u64,
+            adler32: None,
+        };
+        wh.enc_len = wh.compute_enc_len();
+        let mut delta = Vec::new();
+        FileHeader::builder()
+            .build()
+            .unwrap()
+            .encode(&mut delta)
+            .unwrap();
+        wh.encode(&mut delta).unwrap();
+        delta.extend_from_slice(&inst);
+        delta.extend_from_slice(&addr);
+
+        for err in [
+            merge_deltas(10, &[&delta]).unwrap_err(),
+            source_extent(&delta).unwrap_err(),
+        ] {
+            assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn merge_spans_multiple_output_windows() {
+        let mut target = Target::default();
+        target.push(MERGE_WINDOW_SIZE + 10, Piece::Run(7));
+        target.push_literal(b"xyz");
+        let delta = emit(&target, 0);
+        let decoded = decode_all(b"", &delta).unwrap();
+        assert_eq!(decoded.len() as u64, MERGE_WINDOW_SIZE + 13);
+        assert!(decoded[..decoded.len() - 3].iter().all(|&b| b == 7));
+        assert_eq!(&decoded[decoded.len() - 3..], b"xyz");
+    }
+}
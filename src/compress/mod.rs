@@ -1,18 +1,33 @@
-// Full compression/delta encoding pipeline.
-//
-// This module provides the production-quality streaming API for delta
-// compression, building on the core VCDIFF and hash modules:
-//
-// - `encoder`   — DeltaEncoder: streaming encode with source window reuse
-// - `decoder`   — DeltaDecoder: streaming decode with progress tracking
-// - `pipeline`  — Instruction optimization (coalescing, run detection)
-// - `secondary` — Pluggable secondary compression (LZMA, Zlib, custom)
-
-pub mod decoder;
-pub mod encoder;
-pub mod pipeline;
-pub mod secondary;
-
-pub use decoder::DeltaDecoder;
-pub use encoder::{CompressOptions, DeltaEncoder, EncodeError};
-pub use secondary::{CompressBackend, SecondaryCompression};
+// Full compression/delta encoding pipeline.
+//
+// This module provides the production-quality streaming API for delta
+// compression, building on the core VCDIFF and hash modules:
+//
+// - `encoder`   — DeltaEncoder: streaming encode with source window reuse;
+//                 DeltaWriter wraps it as an `io::Write` sink
+// - `decoder`   — DeltaDecoder: streaming decode with progress tracking;
+//                 DeltaReader wraps it as an `io::Read` source
+// - `merge`     — Compose a chain of deltas without decoding them
+// - `pipeline`  — Instruction optimization (coalescing, run detection)
+// - `secondary` — Pluggable secondary compression (LZMA, Zlib, FGK, custom)
+// - `fgk`       — xdelta3's FGK adaptive Huffman coder, behind `secondary`
+// - `djw`       — xdelta3's DJW static Huffman decoder, behind `secondary`
+
+pub mod decoder;
+mod djw;
+pub mod encoder;
+mod fgk;
+pub mod merge;
+pub mod pipeline;
+pub mod secondary;
+
+pub use decoder::{
+    DeltaDecoder, DeltaReader, DiffRegion, WindowInfo, decode_and_diff, max_self_copy_distance,
+};
+pub use encoder::{
+    BLOCK_HASH_SIZE, CompressOptions, DeltaEncoder, DeltaWriter, EncodeError, EncodeReport,
+    FinishInfo, SectionBytes,
+};
+pub use merge::merge_deltas;
+pub use pipeline::OptLevel;
+pub use secondary::{CompressBackend, SecondaryCompression, SecondaryId, SecondaryRegistry};
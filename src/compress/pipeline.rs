@@ -1,341 +1,959 @@
-// Instruction optimization pipeline.
-//
-// Optimizes the raw instruction stream from MatchEngine before VCDIFF encoding:
-//   - Coalesce adjacent ADDs
-//   - Coalesce adjacent COPYs with contiguous addresses
-//   - Detect runs within ADD data
-//   - Remove zero-length instructions
-
-use crate::hash::config::MIN_RUN;
-use crate::hash::rolling;
-use crate::vcdiff::code_table::Instruction;
-
-/// Optimize an instruction stream for better compression.
-///
-/// The input `instructions` must cover `target` exactly (sum of lengths == target.len()).
-/// Returns an optimized instruction stream with the same coverage guarantee.
-pub fn optimize(instructions: &[Instruction], target: &[u8]) -> Vec<Instruction> {
-    if instructions.is_empty() {
-        return Vec::new();
-    }
-
-    let mut coalesced: Vec<Instruction> = Vec::with_capacity(instructions.len());
-
-    for inst in instructions {
-        // Skip zero-length instructions.
-        let len = inst_len(inst);
-        if len == 0 {
-            continue;
-        }
-
-        // Try to coalesce with the last instruction in result.
-        let merged = if let Some(last) = coalesced.last() {
-            try_coalesce(last, inst)
-        } else {
-            None
-        };
-
-        if let Some(merged) = merged {
-            *coalesced.last_mut().unwrap() = merged;
-        } else {
-            coalesced.push(*inst);
-        }
-    }
-
-    // Split ADDs that contain runs (using cached run-length implementation).
-    let run_length = rolling::run_length_fn();
-    let mut result = Vec::with_capacity(coalesced.len() + coalesced.len() / 2 + 8);
-    split_add_runs(&coalesced, target, run_length, &mut result);
-
-    debug_assert_eq!(
-        result.iter().map(|i| inst_len(i) as usize).sum::<usize>(),
-        target.len(),
-        "instruction optimization broke length invariant"
-    );
-
-    result
-}
-
-/// Try to merge two adjacent instructions into one.
-fn try_coalesce(a: &Instruction, b: &Instruction) -> Option<Instruction> {
-    match (a, b) {
-        // Adjacent ADDs → single ADD.
-        (Instruction::Add { len: l1 }, Instruction::Add { len: l2 }) => {
-            Some(Instruction::Add { len: l1 + l2 })
-        }
-
-        // Adjacent COPYs with contiguous source addresses and same mode.
-        (
-            Instruction::Copy {
-                len: l1,
-                addr: a1,
-                mode: m1,
-            },
-            Instruction::Copy {
-                len: l2,
-                addr: a2,
-                mode: m2,
-            },
-        ) if *m2 == *m1 && *a2 == *a1 + *l1 as u64 => Some(Instruction::Copy {
-            len: l1 + l2,
-            addr: *a1,
-            mode: *m1,
-        }),
-
-        // Adjacent RUNs of the same byte → single RUN.
-        // (We don't know the byte here, so we can't verify — but adjacent RUNs
-        // in the match engine output always have the same byte since they come
-        // from contiguous target positions with identical bytes.)
-        (Instruction::Run { len: l1 }, Instruction::Run { len: l2 }) => {
-            Some(Instruction::Run { len: l1 + l2 })
-        }
-
-        _ => None,
-    }
-}
-
-/// Scan ADD instructions for internal runs and split them out.
-///
-/// If an ADD covers target bytes [pos..pos+len] and there's a run of >= MIN_RUN
-/// identical bytes inside, split into ADD(prefix) + RUN(run) + ADD(suffix).
-fn split_add_runs(
-    instructions: &[Instruction],
-    target: &[u8],
-    run_length: rolling::RunLengthFn,
-    result: &mut Vec<Instruction>,
-) {
-    let mut target_pos = 0usize;
-
-    for inst in instructions {
-        match inst {
-            Instruction::Add { len } => {
-                let len = *len as usize;
-                let data = &target[target_pos..target_pos + len];
-                split_add_with_runs(data, run_length, result);
-                target_pos += len;
-            }
-            Instruction::Copy { len, .. } | Instruction::Run { len } => {
-                result.push(*inst);
-                target_pos += *len as usize;
-            }
-        }
-    }
-}
-
-/// Split a single ADD's data into ADD/RUN segments.
-fn split_add_with_runs(data: &[u8], run_length: rolling::RunLengthFn, out: &mut Vec<Instruction>) {
-    if data.is_empty() {
-        return;
-    }
-    if data.len() < MIN_RUN {
-        out.push(Instruction::Add {
-            len: data.len() as u32,
-        });
-        return;
-    }
-
-    let mut i = 0;
-    while i < data.len() {
-        if data.len() - i < MIN_RUN {
-            out.push(Instruction::Add {
-                len: (data.len() - i) as u32,
-            });
-            break;
-        }
-        // Scan for a run starting at i (SIMD-accelerated).
-        let byte = data[i];
-        let run_len = run_length(&data[i..], byte, data.len() - i);
-
-        if run_len >= MIN_RUN {
-            // Emit the run.
-            out.push(Instruction::Run {
-                len: run_len as u32,
-            });
-            i += run_len;
-        } else {
-            // Scan forward to find the next run (or end of data).
-            let add_start = i;
-            i += run_len;
-            while i < data.len() {
-                let b = data[i];
-                let rl = run_length(&data[i..], b, data.len() - i);
-                if rl >= MIN_RUN {
-                    break; // found a run, stop the ADD here
-                }
-                i += rl;
-            }
-            let add_len = i - add_start;
-            out.push(Instruction::Add {
-                len: add_len as u32,
-            });
-        }
-    }
-}
-
-#[inline]
-fn inst_len(inst: &Instruction) -> u32 {
-    match inst {
-        Instruction::Add { len } | Instruction::Copy { len, .. } | Instruction::Run { len } => *len,
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn total_len(insts: &[Instruction]) -> usize {
-        insts.iter().map(|i| inst_len(i) as usize).sum()
-    }
-
-    #[test]
-    fn coalesce_adjacent_adds() {
-        let target = b"Hello, world!";
-        let insts = vec![
-            Instruction::Add { len: 5 },
-            Instruction::Add { len: 2 },
-            Instruction::Add { len: 6 },
-        ];
-        let opt = optimize(&insts, target);
-        assert_eq!(opt.len(), 1);
-        assert!(matches!(opt[0], Instruction::Add { len: 13 }));
-        assert_eq!(total_len(&opt), target.len());
-    }
-
-    #[test]
-    fn coalesce_contiguous_copies() {
-        let target = vec![0u8; 20];
-        let insts = vec![
-            Instruction::Copy {
-                len: 10,
-                addr: 0,
-                mode: 0,
-            },
-            Instruction::Copy {
-                len: 10,
-                addr: 10,
-                mode: 0,
-            },
-        ];
-        let opt = optimize(&insts, &target);
-        assert_eq!(opt.len(), 1);
-        match opt[0] {
-            Instruction::Copy { len, addr, .. } => {
-                assert_eq!(len, 20);
-                assert_eq!(addr, 0);
-            }
-            _ => panic!("expected COPY"),
-        }
-    }
-
-    #[test]
-    fn no_coalesce_noncontiguous_copies() {
-        let target = vec![0u8; 20];
-        let insts = vec![
-            Instruction::Copy {
-                len: 10,
-                addr: 0,
-                mode: 0,
-            },
-            Instruction::Copy {
-                len: 10,
-                addr: 100,
-                mode: 0,
-            },
-        ];
-        let opt = optimize(&insts, &target);
-        assert_eq!(opt.len(), 2);
-    }
-
-    #[test]
-    fn detect_run_in_add() {
-        // Target: 3 bytes + 10 identical bytes + 3 bytes.
-        let mut target = Vec::new();
-        target.extend_from_slice(b"ABC");
-        target.extend(std::iter::repeat_n(0xAA, 10));
-        target.extend_from_slice(b"XYZ");
-
-        let insts = vec![Instruction::Add {
-            len: target.len() as u32,
-        }];
-        let opt = optimize(&insts, &target);
-
-        // Should be ADD(3) + RUN(10) + ADD(3).
-        assert_eq!(opt.len(), 3);
-        assert!(matches!(opt[0], Instruction::Add { len: 3 }));
-        assert!(matches!(opt[1], Instruction::Run { len: 10 }));
-        assert!(matches!(opt[2], Instruction::Add { len: 3 }));
-        assert_eq!(total_len(&opt), target.len());
-    }
-
-    #[test]
-    fn no_run_below_threshold() {
-        // Short runs (< MIN_RUN) should remain as ADD.
-        let target = vec![0xAA; MIN_RUN - 1];
-        let insts = vec![Instruction::Add {
-            len: target.len() as u32,
-        }];
-        let opt = optimize(&insts, &target);
-        assert_eq!(opt.len(), 1);
-        assert!(matches!(opt[0], Instruction::Add { .. }));
-    }
-
-    #[test]
-    fn remove_zero_length() {
-        let target = b"Hello";
-        let insts = vec![
-            Instruction::Add { len: 0 },
-            Instruction::Add { len: 5 },
-            Instruction::Copy {
-                len: 0,
-                addr: 0,
-                mode: 0,
-            },
-        ];
-        let opt = optimize(&insts, target);
-        assert_eq!(opt.len(), 1);
-        assert!(matches!(opt[0], Instruction::Add { len: 5 }));
-    }
-
-    #[test]
-    fn empty_instructions() {
-        let opt = optimize(&[], b"");
-        assert!(opt.is_empty());
-    }
-
-    #[test]
-    fn coalesce_adjacent_runs() {
-        let target = vec![0xBB; 20];
-        let insts = vec![Instruction::Run { len: 10 }, Instruction::Run { len: 10 }];
-        let opt = optimize(&insts, &target);
-        assert_eq!(opt.len(), 1);
-        assert!(matches!(opt[0], Instruction::Run { len: 20 }));
-    }
-
-    #[test]
-    fn mixed_instructions_preserve_order() {
-        let mut target = Vec::new();
-        target.extend_from_slice(b"ABCD"); // ADD 4
-        target.extend_from_slice(b"EFGH"); // COPY 4
-        target.extend(std::iter::repeat_n(0xFF, 10)); // RUN 10
-        target.extend_from_slice(b"IJ"); // ADD 2
-
-        let insts = vec![
-            Instruction::Add { len: 4 },
-            Instruction::Copy {
-                len: 4,
-                addr: 100,
-                mode: 0,
-            },
-            Instruction::Run { len: 10 },
-            Instruction::Add { len: 2 },
-        ];
-        let opt = optimize(&insts, &target);
-        // No coalescing possible (different types adjacent).
-        assert_eq!(opt.len(), 4);
-        assert_eq!(total_len(&opt), target.len());
-    }
-}
+// Instruction optimization pipeline.
+//
+// Optimizes the raw instruction stream from MatchEngine before VCDIFF encoding:
+//   - Coalesce adjacent ADDs
+//   - Coalesce adjacent COPYs with contiguous addresses
+//   - Detect runs within ADD data
+//   - Remove zero-length instructions
+//   - (Aggressive) demote COPYs to literals where that is cheaper
+//
+// `estimated_size` exposes the encoded-size model for callers comparing
+// candidate instruction streams of their own.
+//
+// `optimize_with` fuses the coalescing steps into one pass; the individual
+// passes are public so callers post-processing their own instruction
+// streams can compose them. `optimize_buffered` bounds the passes to a
+// buffer of instructions, like xdelta3's iopt buffer.
+
+use crate::hash::config::MIN_RUN;
+use crate::hash::rolling;
+use crate::vcdiff::address_cache::AddressCache;
+use crate::vcdiff::code_table::{
+    self, Instruction, InstructionInfo, XD3_ADD, XD3_CPY, XD3_RUN, choose_instruction,
+};
+use crate::vcdiff::varint;
+
+/// How much work the pipeline spends on instruction selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Pass the matcher output through unchanged.
+    Greedy,
+    /// Coalesce adjacent instructions and split runs out of ADDs
+    /// (the behavior of [`optimize`]).
+    Balanced,
+    /// `Balanced`, plus [`demote_to_literals`]: COPYs that cost more to
+    /// encode than their literal bytes become ADDs. It does not choose
+    /// among overlapping candidate matches; the matcher's COPYs are only
+    /// kept or demoted.
+    ///
+    /// No compression level selects it; set `CompressOptions::opt_level`.
+    /// Costs one address-cache pass and O(n · 32) steps over the coalesced
+    /// instructions, typically a few percent of match time.
+    Aggressive,
+}
+
+impl OptLevel {
+    /// Default optimization level for a compression level (0-9).
+    ///
+    /// Level 0 emits a single ADD and needs no optimization; every other
+    /// level uses `Balanced`. `Aggressive` is opt-in only.
+    pub fn for_level(level: u32) -> Self {
+        match level {
+            0 => Self::Greedy,
+            _ => Self::Balanced,
+        }
+    }
+}
+
+/// Optimize an instruction stream for better compression.
+///
+/// The input `instructions` must cover `target` exactly (sum of lengths == target.len()).
+/// Returns an optimized instruction stream with the same coverage guarantee.
+pub fn optimize(instructions: &[Instruction], target: &[u8]) -> Vec<Instruction> {
+    optimize_with(instructions, target, 0, OptLevel::Balanced)
+}
+
+/// Optimize an instruction stream at an explicit [`OptLevel`].
+///
+/// `copy_window_len` is the source copy-window length of the window being
+/// encoded. COPYs are never coalesced across it, and `Aggressive` uses it to
+/// estimate COPY address costs.
+pub fn optimize_with(
+    instructions: &[Instruction],
+    target: &[u8],
+    copy_window_len: u64,
+    level: OptLevel,
+) -> Vec<Instruction> {
+    optimize_span(instructions, target, copy_window_len, 0, level)
+}
+
+/// [`optimize_with`] over a buffer of at most `iopt_size` instructions.
+///
+/// The instruction stream is optimized `iopt_size` instructions at a time,
+/// as xdelta3 flushes its instruction buffer: coalescing and COPY demotion
+/// never reach back into an earlier buffer. Smaller buffers bound the
+/// optimizer's memory at the cost of some missed merges. `0` means
+/// unbounded (same as `optimize_with`).
+pub fn optimize_buffered(
+    instructions: &[Instruction],
+    target: &[u8],
+    copy_window_len: u64,
+    level: OptLevel,
+    iopt_size: usize,
+) -> Vec<Instruction> {
+    if iopt_size == 0 || instructions.len() <= iopt_size {
+        return optimize_span(instructions, target, copy_window_len, 0, level);
+    }
+    let mut result = Vec::with_capacity(instructions.len());
+    let mut pos = 0usize;
+    for buffer in instructions.chunks(iopt_size) {
+        let len: usize = buffer.iter().map(|i| inst_len(i) as usize).sum();
+        let span = &target[pos..pos + len];
+        result.extend(optimize_span(
+            buffer,
+            span,
+            copy_window_len,
+            pos as u64,
+            level,
+        ));
+        pos += len;
+    }
+    result
+}
+
+/// Optimize `instructions` covering `target`, which starts `start` bytes
+/// into the target window.
+fn optimize_span(
+    instructions: &[Instruction],
+    target: &[u8],
+    copy_window_len: u64,
+    start: u64,
+    level: OptLevel,
+) -> Vec<Instruction> {
+    if instructions.is_empty() {
+        return Vec::new();
+    }
+    if level == OptLevel::Greedy {
+        return instructions
+            .iter()
+            .copied()
+            .filter(|i| inst_len(i) > 0)
+            .collect();
+    }
+
+    let mut coalesced = coalesce_by(instructions, |a, b| try_coalesce(a, b, copy_window_len));
+
+    if level == OptLevel::Aggressive {
+        coalesced = demote_from(&coalesced, copy_window_len, start);
+    }
+
+    let result = split_runs(&coalesced, target);
+
+    debug_assert_eq!(
+        result.iter().map(|i| inst_len(i) as usize).sum::<usize>(),
+        target.len(),
+        "instruction optimization broke length invariant"
+    );
+
+    result
+}
+
+// ---------------------------------------------------------------------------
+// Passes
+// ---------------------------------------------------------------------------
+
+/// Merge consecutive ADDs into one ADD, dropping zero-length instructions.
+pub fn coalesce_adds(instructions: &[Instruction]) -> Vec<Instruction> {
+    coalesce_by(instructions, merge_adds)
+}
+
+/// Merge consecutive COPYs that continue each other (`prev.addr + prev.len
+/// == next.addr`, same mode), dropping zero-length instructions.
+///
+/// COPYs are not merged across `copy_window_len`, the boundary between the
+/// source window and target addresses: a COPY spanning it is invalid.
+pub fn merge_contiguous_copies(
+    instructions: &[Instruction],
+    copy_window_len: u64,
+) -> Vec<Instruction> {
+    coalesce_by(instructions, |a, b| merge_copies(a, b, copy_window_len))
+}
+
+/// Split runs of at least `MIN_RUN` identical bytes out of ADDs.
+///
+/// An ADD whose data is a single repeated byte becomes a RUN; one with a run
+/// inside becomes ADD(prefix) + RUN + ADD(suffix). `target` is the window the
+/// instructions cover.
+pub fn split_runs(instructions: &[Instruction], target: &[u8]) -> Vec<Instruction> {
+    let run_length = rolling::run_length_fn();
+    let mut result = Vec::with_capacity(instructions.len() + instructions.len() / 2 + 8);
+    split_add_runs(instructions, target, run_length, &mut result);
+    result
+}
+
+/// Apply `merge` to each instruction and the last one kept, dropping
+/// zero-length instructions.
+fn coalesce_by(
+    instructions: &[Instruction],
+    merge: impl Fn(&Instruction, &Instruction) -> Option<Instruction>,
+) -> Vec<Instruction> {
+    let mut coalesced: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    for inst in instructions {
+        if inst_len(inst) == 0 {
+            continue;
+        }
+        match coalesced.last_mut() {
+            Some(last) => match merge(last, inst) {
+                Some(merged) => *last = merged,
+                None => coalesced.push(*inst),
+            },
+            None => coalesced.push(*inst),
+        }
+    }
+    coalesced
+}
+
+/// Try to merge two adjacent instructions into one.
+fn try_coalesce(a: &Instruction, b: &Instruction, copy_window_len: u64) -> Option<Instruction> {
+    match (a, b) {
+        (Instruction::Add { .. }, _) => merge_adds(a, b),
+        (Instruction::Copy { .. }, _) => merge_copies(a, b, copy_window_len),
+
+        // Adjacent RUNs of the same byte → single RUN.
+        // (We don't know the byte here, so we can't verify — but adjacent RUNs
+        // in the match engine output always have the same byte since they come
+        // from contiguous target positions with identical bytes.)
+        (Instruction::Run { len: l1 }, Instruction::Run { len: l2 }) => {
+            Some(Instruction::Run { len: l1 + l2 })
+        }
+
+        _ => None,
+    }
+}
+
+/// Adjacent ADDs → single ADD.
+fn merge_adds(a: &Instruction, b: &Instruction) -> Option<Instruction> {
+    match (a, b) {
+        (Instruction::Add { len: l1 }, Instruction::Add { len: l2 }) => {
+            Some(Instruction::Add { len: l1 + l2 })
+        }
+        _ => None,
+    }
+}
+
+/// Adjacent COPYs with contiguous addresses and same mode → single COPY.
+fn merge_copies(a: &Instruction, b: &Instruction, copy_window_len: u64) -> Option<Instruction> {
+    match (a, b) {
+        (
+            Instruction::Copy {
+                len: l1,
+                addr: a1,
+                mode: m1,
+            },
+            Instruction::Copy {
+                len: l2,
+                addr: a2,
+                mode: m2,
+            },
+        ) if *m2 == *m1
+            && *a2 == *a1 + *l1 as u64
+            && (*a1 >= copy_window_len || *a2 + *l2 as u64 <= copy_window_len) =>
+        {
+            Some(Instruction::Copy {
+                len: l1 + l2,
+                addr: *a1,
+                mode: *m1,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Scan ADD instructions for internal runs and split them out.
+///
+/// If an ADD covers target bytes [pos..pos+len] and there's a run of >= MIN_RUN
+/// identical bytes inside, split into ADD(prefix) + RUN(run) + ADD(suffix).
+fn split_add_runs(
+    instructions: &[Instruction],
+    target: &[u8],
+    run_length: rolling::RunLengthFn,
+    result: &mut Vec<Instruction>,
+) {
+    let mut target_pos = 0usize;
+
+    for inst in instructions {
+        match inst {
+            Instruction::Add { len } => {
+                let len = *len as usize;
+                let data = &target[target_pos..target_pos + len];
+                split_add_with_runs(data, run_length, result);
+                target_pos += len;
+            }
+            Instruction::Copy { len, .. } | Instruction::Run { len } => {
+                result.push(*inst);
+                target_pos += *len as usize;
+            }
+        }
+    }
+}
+
+/// Split a single ADD's data into ADD/RUN segments.
+fn split_add_with_runs(data: &[u8], run_length: rolling::RunLengthFn, out: &mut Vec<Instruction>) {
+    if data.is_empty() {
+        return;
+    }
+    if data.len() < MIN_RUN {
+        out.push(Instruction::Add {
+            len: data.len() as u32,
+        });
+        return;
+    }
+
+    let mut i = 0;
+    while i < data.len() {
+        if data.len() - i < MIN_RUN {
+            out.push(Instruction::Add {
+                len: (data.len() - i) as u32,
+            });
+            break;
+        }
+        // Scan for a run starting at i (SIMD-accelerated).
+        let byte = data[i];
+        let run_len = run_length(&data[i..], byte, data.len() - i);
+
+        if run_len >= MIN_RUN {
+            // Emit the run.
+            out.push(Instruction::Run {
+                len: run_len as u32,
+            });
+            i += run_len;
+        } else {
+            // Scan forward to find the next run (or end of data).
+            let add_start = i;
+            i += run_len;
+            while i < data.len() {
+                let b = data[i];
+                let rl = run_length(&data[i..], b, data.len() - i);
+                if rl >= MIN_RUN {
+                    break; // found a run, stop the ADD here
+                }
+                i += rl;
+            }
+            let add_len = i - add_start;
+            out.push(Instruction::Add {
+                len: add_len as u32,
+            });
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Aggressive: cost-driven COPY demotion
+// ---------------------------------------------------------------------------
+
+/// Maximum number of instructions merged into one ADD by the DP.
+const DP_LOOKBACK: usize = 32;
+
+/// Estimated encoded bytes of an ADD of `len` literal bytes.
+fn add_cost(len: u64) -> u64 {
+    // Opcode + inline size for 1..=17, else a size varint, plus the literals.
+    let size = if len <= 17 {
+        0
+    } else {
+        varint::sizeof_u64(len) as u64
+    };
+    1 + size + len
+}
+
+/// Estimated encoded bytes of each instruction kept as-is.
+///
+/// COPY addresses are costed by replaying the stream through a fresh
+/// `AddressCache`, exactly as `WindowEncoder` would encode them. The first
+/// instruction is `start` bytes into the target window.
+fn keep_costs(instructions: &[Instruction], copy_window_len: u64, start: u64) -> Vec<u64> {
+    let mut acache = AddressCache::new();
+    let mut here = copy_window_len + start;
+    instructions
+        .iter()
+        .map(|inst| {
+            let cost = match *inst {
+                Instruction::Add { len } => add_cost(len as u64),
+                Instruction::Run { len } => 2 + varint::sizeof_u32(len) as u64,
+                Instruction::Copy { len, addr, .. } => {
+                    let size = if (4..=18).contains(&len) {
+                        0
+                    } else {
+                        varint::sizeof_u32(len) as u64
+                    };
+                    let addr_len = if addr < here {
+                        acache.encode(addr, here).1.len() as u64
+                    } else {
+                        varint::sizeof_u64(addr) as u64
+                    };
+                    1 + size + addr_len
+                }
+            };
+            here += inst_len(inst) as u64;
+            cost
+        })
+        .collect()
+}
+
+/// Bounded dynamic program over the instruction stream: choose which runs of
+/// consecutive ADD/COPY instructions to merge into single ADDs so that the
+/// estimated encoded size is minimal. Keeping everything is always a
+/// candidate, so the estimate never gets worse.
+///
+/// The only alternative to a COPY considered is its literal bytes. Picking
+/// among overlapping candidate matches (trimming one COPY so a longer one
+/// can start earlier) would need the source and the matcher's candidates,
+/// which the pipeline does not see; the COPYs the matcher chose are kept or
+/// turned into literals, never shortened or moved.
+///
+/// This is the extra pass run by [`OptLevel::Aggressive`].
+pub fn demote_to_literals(instructions: &[Instruction], copy_window_len: u64) -> Vec<Instruction> {
+    demote_from(instructions, copy_window_len, 0)
+}
+
+/// [`demote_to_literals`] for instructions starting `start` bytes into
+/// the target window.
+fn demote_from(instructions: &[Instruction], copy_window_len: u64, start: u64) -> Vec<Instruction> {
+    let n = instructions.len();
+    let keep = keep_costs(instructions, copy_window_len, start);
+
+    // best[k] = minimal cost of the first k instructions; from[k] = start of
+    // the ADD segment ending at k, or `usize::MAX` if instruction k-1 is kept.
+    let mut best = vec![0u64; n + 1];
+    let mut from = vec![usize::MAX; n + 1];
+    for k in 1..=n {
+        best[k] = best[k - 1] + keep[k - 1];
+        let mut seg_len = 0u64;
+        for j in (k.saturating_sub(DP_LOOKBACK)..k).rev() {
+            if matches!(instructions[j], Instruction::Run { .. }) {
+                break;
+            }
+            seg_len += inst_len(&instructions[j]) as u64;
+            let cost = best[j] + add_cost(seg_len);
+            if cost < best[k] {
+                best[k] = cost;
+                from[k] = j;
+            }
+        }
+    }
+
+    // Reconstruct back to front.
+    let mut out = Vec::with_capacity(n);
+    let mut k = n;
+    while k > 0 {
+        if from[k] == usize::MAX {
+            out.push(instructions[k - 1]);
+            k -= 1;
+        } else {
+            let j = from[k];
+            let len: u32 = instructions[j..k].iter().map(inst_len).sum();
+            out.push(Instruction::Add { len });
+            k = j;
+        }
+    }
+    out.reverse();
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Cost model
+// ---------------------------------------------------------------------------
+
+/// Encoded size in bytes of `instructions` as one window's DATA, INST and
+/// ADDR sections.
+///
+/// Replays the stream the way `WindowEncoder` emits it with the default code
+/// table: opcodes with inline or varint sizes, double-instruction packing,
+/// and COPY addresses through a fresh address cache (the cache picks the
+/// mode; `Instruction::Copy::mode` is ignored, as in
+/// `WindowEncoder::copy`). Zero-length instructions are skipped. Window
+/// header and secondary compression are not included.
+///
+/// `copy_window_len` is the source copy-window length; COPY addresses at or
+/// above it refer to the target.
+///
+/// ```
+/// use oxidelta::compress::pipeline::estimated_size;
+/// use oxidelta::vcdiff::Instruction;
+///
+/// let literal = [Instruction::Add { len: 40 }];
+/// let copied = [Instruction::Copy { len: 40, addr: 0, mode: 0 }];
+/// assert!(estimated_size(&copied, 100) < estimated_size(&literal, 100));
+/// ```
+pub fn estimated_size(instructions: &[Instruction], copy_window_len: u64) -> usize {
+    let table = code_table::default_code_table();
+    let single = |inst: &InstructionInfo, code: u8| {
+        1 + if table[code as usize].size1 == 0 {
+            varint::sizeof_u32(inst.size)
+        } else {
+            0
+        }
+    };
+
+    let mut acache = AddressCache::new();
+    let mut here = copy_window_len;
+    let mut pending: Option<(InstructionInfo, u8)> = None;
+    let mut size = 0usize;
+    for inst in instructions {
+        let itype = match *inst {
+            _ if inst_len(inst) == 0 => continue,
+            Instruction::Add { len } => {
+                size += len as usize;
+                XD3_ADD
+            }
+            Instruction::Run { .. } => {
+                size += 1;
+                XD3_RUN
+            }
+            Instruction::Copy { addr, .. } => {
+                let (mode, addr) = acache.encode(addr, here);
+                size += addr.len();
+                XD3_CPY + mode
+            }
+        };
+        let info = InstructionInfo {
+            itype,
+            size: inst_len(inst),
+        };
+        let chosen = choose_instruction(pending.as_ref().map(|(prev, _)| prev), &info);
+        if chosen.code2.is_some() {
+            // Both instructions share one opcode byte with implicit sizes.
+            pending = None;
+            size += 1;
+        } else {
+            if let Some((prev, code)) = pending.take() {
+                size += single(&prev, code);
+            }
+            pending = Some((info, chosen.code1));
+        }
+        here += inst_len(inst) as u64;
+    }
+    if let Some((prev, code)) = pending {
+        size += single(&prev, code);
+    }
+    size
+}
+
+#[inline]
+fn inst_len(inst: &Instruction) -> u32 {
+    match inst {
+        Instruction::Add { len } | Instruction::Copy { len, .. } | Instruction::Run { len } => *len,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    fn total_len(insts: &[Instruction]) -> usize {
+        insts.iter().map(|i| inst_len(i) as usize).sum()
+    }
+
+    #[test]
+    fn coalesce_adjacent_adds() {
+        let target = b"Hello, world!";
+        let insts = vec![
+            Instruction::Add { len: 5 },
+            Instruction::Add { len: 2 },
+            Instruction::Add { len: 6 },
+        ];
+        let opt = optimize(&insts, target);
+        assert_eq!(opt.len(), 1);
+        assert!(matches!(opt[0], Instruction::Add { len: 13 }));
+        assert_eq!(total_len(&opt), target.len());
+    }
+
+    #[test]
+    fn coalesce_contiguous_copies() {
+        let target = vec![0u8; 20];
+        let insts = vec![
+            Instruction::Copy {
+                len: 10,
+                addr: 0,
+                mode: 0,
+            },
+            Instruction::Copy {
+                len: 10,
+                addr: 10,
+                mode: 0,
+            },
+        ];
+        let opt = optimize(&insts, &target);
+        assert_eq!(opt.len(), 1);
+        match opt[0] {
+            Instruction::Copy { len, addr, .. } => {
+                assert_eq!(len, 20);
+                assert_eq!(addr, 0);
+            }
+            _ => panic!("expected COPY"),
+        }
+    }
+
+    #[test]
+    fn no_coalesce_noncontiguous_copies() {
+        let target = vec![0u8; 20];
+        let insts = vec![
+            Instruction::Copy {
+                len: 10,
+                addr: 0,
+                mode: 0,
+            },
+            Instruction::Copy {
+                len: 10,
+                addr: 100,
+                mode: 0,
+            },
+        ];
+        let opt = optimize(&insts, &target);
+        assert_eq!(opt.len(), 2);
+    }
+
+    #[test]
+    fn detect_run_in_add() {
+        // Target: 3 bytes + 10 identical bytes + 3 bytes.
+        let mut target = Vec::new();
+        target.extend_from_slice(b"ABC");
+        target.extend(std::iter::repeat_n(0xAA, 10));
+        target.extend_from_slice(b"XYZ");
+
+        let insts = vec![Instruction::Add {
+            len: target.len() as u32,
+        }];
+        let opt = optimize(&insts, &target);
+
+        // Should be ADD(3) + RUN(10) + ADD(3).
+        assert_eq!(opt.len(), 3);
+        assert!(matches!(opt[0], Instruction::Add { len: 3 }));
+        assert!(matches!(opt[1], Instruction::Run { len: 10 }));
+        assert!(matches!(opt[2], Instruction::Add { len: 3 }));
+        assert_eq!(total_len(&opt), target.len());
+    }
+
+    #[test]
+    fn no_run_below_threshold() {
+        // Short runs (< MIN_RUN) should remain as ADD.
+        let target = vec![0xAA; MIN_RUN - 1];
+        let insts = vec![Instruction::Add {
+            len: target.len() as u32,
+        }];
+        let opt = optimize(&insts, &target);
+        assert_eq!(opt.len(), 1);
+        assert!(matches!(opt[0], Instruction::Add { .. }));
+    }
+
+    #[test]
+    fn remove_zero_length() {
+        let target = b"Hello";
+        let insts = vec![
+            Instruction::Add { len: 0 },
+            Instruction::Add { len: 5 },
+            Instruction::Copy {
+                len: 0,
+                addr: 0,
+                mode: 0,
+            },
+        ];
+        let opt = optimize(&insts, target);
+        assert_eq!(opt.len(), 1);
+        assert!(matches!(opt[0], Instruction::Add { len: 5 }));
+    }
+
+    #[test]
+    fn empty_instructions() {
+        let opt = optimize(&[], b"");
+        assert!(opt.is_empty());
+    }
+
+    #[test]
+    fn coalesce_adjacent_runs() {
+        let target = vec![0xBB; 20];
+        let insts = vec![Instruction::Run { len: 10 }, Instruction::Run { len: 10 }];
+        let opt = optimize(&insts, &target);
+        assert_eq!(opt.len(), 1);
+        assert!(matches!(opt[0], Instruction::Run { len: 20 }));
+    }
+
+    #[test]
+    fn opt_level_for_level() {
+        assert_eq!(OptLevel::for_level(0), OptLevel::Greedy);
+        assert_eq!(OptLevel::for_level(1), OptLevel::Balanced);
+        assert_eq!(OptLevel::for_level(6), OptLevel::Balanced);
+        assert_eq!(OptLevel::for_level(9), OptLevel::Balanced);
+    }
+
+    #[test]
+    fn greedy_passes_through() {
+        let target = b"Hello, world!";
+        let insts = vec![
+            Instruction::Add { len: 5 },
+            Instruction::Add { len: 0 },
+            Instruction::Add { len: 8 },
+        ];
+        let opt = optimize_with(&insts, target, 0, OptLevel::Greedy);
+        assert_eq!(
+            opt,
+            vec![Instruction::Add { len: 5 }, Instruction::Add { len: 8 }]
+        );
+    }
+
+    #[test]
+    fn aggressive_demotes_expensive_copy() {
+        // A 4-byte COPY from a far, uncached address between two ADDs costs
+        // more than its literal bytes.
+        let target = vec![0x11u8; 3]
+            .into_iter()
+            .chain([1, 2, 3, 4])
+            .chain([0x22; 3])
+            .collect::<Vec<u8>>();
+        let insts = vec![
+            Instruction::Add { len: 3 },
+            Instruction::Copy {
+                len: 4,
+                addr: 5_000_000,
+                mode: 0,
+            },
+            Instruction::Add { len: 3 },
+        ];
+        let opt = optimize_with(&insts, &target, 10_000_000, OptLevel::Aggressive);
+        assert_eq!(opt, vec![Instruction::Add { len: 10 }]);
+
+        // A long COPY stays a COPY.
+        let target = vec![0u8; 1003];
+        let insts = vec![
+            Instruction::Add { len: 3 },
+            Instruction::Copy {
+                len: 1000,
+                addr: 5_000_000,
+                mode: 0,
+            },
+        ];
+        let opt = optimize_with(&insts, &target, 10_000_000, OptLevel::Aggressive);
+        assert_eq!(opt, insts);
+    }
+
+    #[test]
+    fn buffered_optimize_stays_within_buffer() {
+        let target = vec![7u8; 40];
+        let insts: Vec<Instruction> = (0..10).map(|_| Instruction::Add { len: 4 }).collect();
+        assert_eq!(
+            optimize_buffered(&insts, &target, 0, OptLevel::Balanced, 0),
+            optimize_with(&insts, &target, 0, OptLevel::Balanced)
+        );
+
+        // ADDs in different buffers are never merged; each 12-byte ADD of one
+        // repeated byte becomes a RUN.
+        let opt = optimize_buffered(&insts, &target, 0, OptLevel::Balanced, 3);
+        assert_eq!(
+            opt,
+            vec![
+                Instruction::Run { len: 12 },
+                Instruction::Run { len: 12 },
+                Instruction::Run { len: 12 },
+                Instruction::Add { len: 4 },
+            ]
+        );
+        assert_eq!(total_len(&opt), target.len());
+    }
+
+    #[test]
+    fn coalesce_adds_pass() {
+        let copy = Instruction::Copy {
+            len: 4,
+            addr: 0,
+            mode: 0,
+        };
+        let insts = vec![
+            Instruction::Add { len: 2 },
+            Instruction::Add { len: 0 },
+            Instruction::Add { len: 3 },
+            copy,
+            copy,
+            Instruction::Run { len: 5 },
+            Instruction::Add { len: 1 },
+            Instruction::Add { len: 1 },
+        ];
+        // COPYs and RUNs are left alone.
+        assert_eq!(
+            coalesce_adds(&insts),
+            vec![
+                Instruction::Add { len: 5 },
+                copy,
+                copy,
+                Instruction::Run { len: 5 },
+                Instruction::Add { len: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_contiguous_copies_pass() {
+        let copy = |len, addr| Instruction::Copy { len, addr, mode: 0 };
+        let insts = vec![
+            copy(10, 0),
+            copy(10, 10),
+            copy(5, 20),
+            Instruction::Add { len: 1 },
+            Instruction::Add { len: 1 },
+            copy(5, 25),
+            copy(5, 100),
+        ];
+        assert_eq!(
+            merge_contiguous_copies(&insts, 1000),
+            vec![
+                copy(25, 0),
+                Instruction::Add { len: 1 },
+                Instruction::Add { len: 1 },
+                copy(5, 25),
+                copy(5, 100),
+            ]
+        );
+
+        // Source and target copies meeting at the copy-window boundary stay
+        // separate; copies entirely in target space still merge.
+        let insts = vec![copy(10, 90), copy(10, 100), copy(10, 110)];
+        assert_eq!(
+            merge_contiguous_copies(&insts, 100),
+            vec![copy(10, 90), copy(20, 100)]
+        );
+    }
+
+    #[test]
+    fn split_runs_pass() {
+        // A single repeated byte becomes a RUN; a short one stays an ADD.
+        let mut target = vec![0x77; MIN_RUN + 4];
+        target.extend(std::iter::repeat_n(0x55, MIN_RUN - 1));
+        let insts = vec![
+            Instruction::Add {
+                len: MIN_RUN as u32 + 4,
+            },
+            Instruction::Add {
+                len: MIN_RUN as u32 - 1,
+            },
+        ];
+        assert_eq!(
+            split_runs(&insts, &target),
+            vec![
+                Instruction::Run {
+                    len: MIN_RUN as u32 + 4
+                },
+                Instruction::Add {
+                    len: MIN_RUN as u32 - 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn optimize_never_copies_across_window_boundary() {
+        let target = vec![0u8; 20];
+        let insts = vec![
+            Instruction::Copy {
+                len: 10,
+                addr: 54,
+                mode: 0,
+            },
+            Instruction::Copy {
+                len: 10,
+                addr: 64,
+                mode: 0,
+            },
+        ];
+        let opt = optimize_with(&insts, &target, 64, OptLevel::Balanced);
+        assert_eq!(opt, insts);
+    }
+
+    /// DATA + INST + ADDR bytes `WindowEncoder` emits for `insts`.
+    fn emitted_size(insts: &[Instruction], copy_window_len: u64) -> usize {
+        use crate::vcdiff::encoder::{SourceWindow, WindowEncoder};
+        let target = vec![0u8; total_len(insts)];
+        let source = (copy_window_len > 0).then_some(SourceWindow {
+            len: copy_window_len,
+            offset: 0,
+        });
+        let s = WindowEncoder::from_instructions(source, false, &target, insts)
+            .unwrap()
+            .finish_sections(None);
+        s.data_section.len() + s.inst_section.len() + s.addr_section.len()
+    }
+
+    #[test]
+    fn estimated_size_packs_doubles() {
+        // ADD 1 + COPY 4 (mode 0) share opcode 163: 1 literal, 1 opcode, 1 address byte.
+        let insts = [
+            Instruction::Add { len: 1 },
+            Instruction::Copy {
+                len: 4,
+                addr: 7,
+                mode: 0,
+            },
+        ];
+        assert_eq!(estimated_size(&insts, 100), 3);
+        assert_eq!(emitted_size(&insts, 100), 3);
+        // A RUN never packs and always carries a size varint.
+        assert_eq!(
+            estimated_size(&[Instruction::Run { len: 300 }], 0),
+            1 + 1 + 2
+        );
+        assert_eq!(estimated_size(&[], 0), 0);
+    }
+
+    #[test]
+    fn estimated_size_matches_window_encoder() {
+        let mut rng = XorShift32::new(0x9E37_79B9);
+        let mut next = |bound: u32| rng.below(bound);
+        for copy_window_len in [0u64, 64, 100_000, 1 << 33] {
+            for _ in 0..50 {
+                let mut insts = Vec::new();
+                let mut here = copy_window_len;
+                for _ in 0..next(60) {
+                    let len = if next(4) == 0 { next(2000) } else { next(24) };
+                    let inst = match next(3) {
+                        0 => Instruction::Add { len },
+                        1 => Instruction::Run { len },
+                        // Recent addresses hit the NEAR/SAME caches.
+                        _ if here > 0 => {
+                            let addr = match next(3) {
+                                0 => here - 1 - u64::from(next(64)).min(here - 1),
+                                _ => {
+                                    ((u64::from(next(u32::MAX)) << 8) ^ u64::from(next(256))) % here
+                                }
+                            };
+                            Instruction::Copy { len, addr, mode: 0 }
+                        }
+                        _ => Instruction::Add { len },
+                    };
+                    here += u64::from(len);
+                    insts.push(inst);
+                }
+                assert_eq!(
+                    estimated_size(&insts, copy_window_len),
+                    emitted_size(&insts, copy_window_len),
+                    "{insts:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_instructions_preserve_order() {
+        let mut target = Vec::new();
+        target.extend_from_slice(b"ABCD"); // ADD 4
+        target.extend_from_slice(b"EFGH"); // COPY 4
+        target.extend(std::iter::repeat_n(0xFF, 10)); // RUN 10
+        target.extend_from_slice(b"IJ"); // ADD 2
+
+        let insts = vec![
+            Instruction::Add { len: 4 },
+            Instruction::Copy {
+                len: 4,
+                addr: 100,
+                mode: 0,
+            },
+            Instruction::Run { len: 10 },
+            Instruction::Add { len: 2 },
+        ];
+        let opt = optimize(&insts, &target);
+        // No coalescing possible (different types adjacent).
+        assert_eq!(opt.len(), 4);
+        assert_eq!(total_len(&opt), target.len());
+    }
+}
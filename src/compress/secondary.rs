@@ -1,630 +1,1427 @@
-// Secondary compression for VCDIFF DATA/INST/ADDR sections.
-//
-// Provides a pluggable `CompressBackend` trait with built-in implementations:
-//   - LZMA (via lzma-rs, feature-gated `lzma-secondary`)
-//   - Zlib/Deflate (via flate2, feature-gated `zlib-secondary`)
-//   - NoCompression (passthrough)
-//   - External/custom compressors via the trait
-//
-// The VCDIFF file header stores a secondary compressor ID; xdelta3 defines:
-//   ID 1 = DJW (xdelta3-specific Huffman, not implemented here)
-//   ID 2 = LZMA
-//   ID 16 = FGK (xdelta3-specific, not implemented here)
-//
-// We additionally define:
-//   ID 3 = Zlib/Deflate (Rust-only extension; not decodable by xdelta3 C)
-
-use std::io;
-
-use crate::vcdiff::decoder::DecodeError;
-use crate::vcdiff::header::{VCD_ADDRCOMP, VCD_DATACOMP, VCD_INSTCOMP, VCD_LZMA_ID};
-
-/// Secondary compressor ID for Zlib/Deflate (Rust extension, not in xdelta3 C).
-pub const VCD_ZLIB_ID: u8 = 3;
-
-/// Minimum section size worth compressing.
-const MIN_COMPRESS_SIZE: usize = 32;
-
-/// Encoded DATA/INST/ADDR sections plus delta indicator flags.
-pub type CompressedSections = (Vec<u8>, Vec<u8>, Vec<u8>, u8);
-/// Decoded DATA/INST/ADDR sections.
-pub type DecompressedSections = (Vec<u8>, Vec<u8>, Vec<u8>);
-
-// ---------------------------------------------------------------------------
-// CompressBackend trait
-// ---------------------------------------------------------------------------
-
-/// A pluggable secondary compressor for VCDIFF sections.
-///
-/// Implementations compress/decompress individual DATA, INST, and ADDR sections
-/// after VCDIFF encoding (or before VCDIFF decoding).
-///
-/// # Implementing a custom backend
-///
-/// ```no_run
-/// use oxidelta::compress::secondary::CompressBackend;
-/// use oxidelta::vcdiff::decoder::DecodeError;
-///
-/// struct MyCompressor;
-///
-/// impl CompressBackend for MyCompressor {
-///     fn id(&self) -> u8 { 42 }
-///     fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
-///         Ok(data.to_vec()) // placeholder
-///     }
-///     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-///         Ok(data.to_vec()) // placeholder
-///     }
-/// }
-/// ```
-pub trait CompressBackend: Send + Sync {
-    /// The secondary compressor ID stored in the VCDIFF file header.
-    ///
-    /// Standard IDs: 1 (DJW), 2 (LZMA), 16 (FGK).
-    /// Rust extension: 3 (Zlib).
-    /// Custom implementations should use IDs that don't collide with these.
-    fn id(&self) -> u8;
-
-    /// Compress a section. Returns compressed bytes.
-    ///
-    /// If compression would not reduce size, implementations should return
-    /// the original data unchanged (the caller checks this).
-    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
-
-    /// Decompress a section previously compressed by `compress()`.
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError>;
-
-    /// Whether this section is worth compressing. Default: skip if < 32 bytes.
-    fn should_compress(&self, data: &[u8]) -> bool {
-        data.len() >= MIN_COMPRESS_SIZE
-    }
-}
-
-// ---------------------------------------------------------------------------
-// LZMA backend
-// ---------------------------------------------------------------------------
-
-/// LZMA secondary compressor (ID 2). Cross-compatible with xdelta3 C.
-#[cfg(feature = "lzma-secondary")]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct LzmaBackend;
-
-#[cfg(feature = "lzma-secondary")]
-impl CompressBackend for LzmaBackend {
-    fn id(&self) -> u8 {
-        VCD_LZMA_ID
-    }
-
-    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-        let mut input = io::Cursor::new(data);
-        let mut output = Vec::new();
-        lzma_rs::lzma_compress(&mut input, &mut output)?;
-        Ok(output)
-    }
-
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-        let mut input = io::BufReader::new(io::Cursor::new(data));
-        let mut output = Vec::new();
-        lzma_rs::lzma_decompress(&mut input, &mut output)
-            .map_err(|e| DecodeError::InvalidInput(format!("LZMA decompression failed: {e}")))?;
-        Ok(output)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Zlib backend
-// ---------------------------------------------------------------------------
-
-/// Zlib/Deflate secondary compressor (ID 3). Rust-only extension.
-///
-/// Uses zlib format (deflate + zlib header), not raw deflate,
-/// so the stream is self-describing and includes a checksum.
-#[cfg(feature = "zlib-secondary")]
-#[derive(Debug, Clone, Copy)]
-pub struct ZlibBackend {
-    level: flate2::Compression,
-}
-
-#[cfg(feature = "zlib-secondary")]
-impl ZlibBackend {
-    /// Create a Zlib backend with the given compression level (0-9).
-    pub fn new(level: u32) -> Self {
-        Self {
-            level: flate2::Compression::new(level),
-        }
-    }
-}
-
-#[cfg(feature = "zlib-secondary")]
-impl Default for ZlibBackend {
-    fn default() -> Self {
-        Self::new(6)
-    }
-}
-
-#[cfg(feature = "zlib-secondary")]
-impl CompressBackend for ZlibBackend {
-    fn id(&self) -> u8 {
-        VCD_ZLIB_ID
-    }
-
-    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-        use flate2::write::ZlibEncoder;
-        use io::Write;
-
-        let mut encoder = ZlibEncoder::new(Vec::new(), self.level);
-        encoder.write_all(data)?;
-        encoder.finish()
-    }
-
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-        use flate2::read::ZlibDecoder;
-        use io::Read;
-
-        let mut decoder = ZlibDecoder::new(data);
-        let mut output = Vec::new();
-        decoder
-            .read_to_end(&mut output)
-            .map_err(|e| DecodeError::InvalidInput(format!("Zlib decompression failed: {e}")))?;
-        Ok(output)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// No-compression backend
-// ---------------------------------------------------------------------------
-
-/// Passthrough "compressor" that performs no compression.
-///
-/// Useful as a default or for testing. The `compress` method returns data
-/// unchanged, so `compress_section` will never set compression flags.
-#[derive(Debug, Clone, Copy, Default)]
-pub struct NoCompression;
-
-impl CompressBackend for NoCompression {
-    fn id(&self) -> u8 {
-        0 // Never written to the file header since sections aren't compressed
-    }
-
-    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-        Ok(data.to_vec())
-    }
-
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-        Ok(data.to_vec())
-    }
-
-    fn should_compress(&self, _data: &[u8]) -> bool {
-        false // Never compress
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Section-level compression/decompression helpers
-// ---------------------------------------------------------------------------
-
-/// Compress a single section using the given backend.
-///
-/// Returns compressed data only if it's actually smaller; otherwise returns
-/// the original data unchanged.
-pub fn compress_section(backend: &dyn CompressBackend, data: &[u8]) -> io::Result<Vec<u8>> {
-    if !backend.should_compress(data) {
-        return Ok(data.to_vec());
-    }
-
-    let compressed = backend.compress(data)?;
-
-    if compressed.len() < data.len() {
-        Ok(compressed)
-    } else {
-        Ok(data.to_vec())
-    }
-}
-
-/// Decompress a single section using the given backend.
-pub fn decompress_section(
-    backend: &dyn CompressBackend,
-    data: &[u8],
-) -> Result<Vec<u8>, DecodeError> {
-    backend.decompress(data)
-}
-
-/// Compress all three VCDIFF sections independently.
-///
-/// Returns (data, inst, addr, del_ind) where `del_ind` has
-/// VCD_DATACOMP/VCD_INSTCOMP/VCD_ADDRCOMP bits set for sections
-/// that were actually compressed (i.e., compression reduced size).
-pub fn compress_sections(
-    backend: &dyn CompressBackend,
-    data: &[u8],
-    inst: &[u8],
-    addr: &[u8],
-) -> io::Result<CompressedSections> {
-    let mut del_ind: u8 = 0;
-
-    let comp_data = compress_section(backend, data)?;
-    if comp_data.len() < data.len() {
-        del_ind |= VCD_DATACOMP;
-    }
-
-    let comp_inst = compress_section(backend, inst)?;
-    if comp_inst.len() < inst.len() {
-        del_ind |= VCD_INSTCOMP;
-    }
-
-    let comp_addr = compress_section(backend, addr)?;
-    if comp_addr.len() < addr.len() {
-        del_ind |= VCD_ADDRCOMP;
-    }
-
-    let final_data = if del_ind & VCD_DATACOMP != 0 {
-        comp_data
-    } else {
-        data.to_vec()
-    };
-    let final_inst = if del_ind & VCD_INSTCOMP != 0 {
-        comp_inst
-    } else {
-        inst.to_vec()
-    };
-    let final_addr = if del_ind & VCD_ADDRCOMP != 0 {
-        comp_addr
-    } else {
-        addr.to_vec()
-    };
-
-    Ok((final_data, final_inst, final_addr, del_ind))
-}
-
-/// Decompress sections according to del_ind flags.
-///
-/// `secondary_id` is validated against the backend's ID.
-pub fn decompress_sections(
-    data: &[u8],
-    inst: &[u8],
-    addr: &[u8],
-    del_ind: u8,
-    secondary_id: Option<u8>,
-) -> Result<DecompressedSections, DecodeError> {
-    if del_ind == 0 {
-        return Ok((data.to_vec(), inst.to_vec(), addr.to_vec()));
-    }
-
-    let backend = backend_for_id(secondary_id)?;
-
-    let dec_data = if del_ind & VCD_DATACOMP != 0 {
-        decompress_section(backend.as_ref(), data)?
-    } else {
-        data.to_vec()
-    };
-
-    let dec_inst = if del_ind & VCD_INSTCOMP != 0 {
-        decompress_section(backend.as_ref(), inst)?
-    } else {
-        inst.to_vec()
-    };
-
-    let dec_addr = if del_ind & VCD_ADDRCOMP != 0 {
-        decompress_section(backend.as_ref(), addr)?
-    } else {
-        addr.to_vec()
-    };
-
-    Ok((dec_data, dec_inst, dec_addr))
-}
-
-/// Look up a decompression backend by secondary compressor ID.
-///
-/// This is the decode-side dispatch: given the ID from the file header,
-/// return the appropriate backend to decompress sections.
-pub fn backend_for_id(secondary_id: Option<u8>) -> Result<Box<dyn CompressBackend>, DecodeError> {
-    match secondary_id {
-        #[cfg(feature = "lzma-secondary")]
-        Some(VCD_LZMA_ID) => Ok(Box::new(LzmaBackend)),
-
-        #[cfg(not(feature = "lzma-secondary"))]
-        Some(VCD_LZMA_ID) => Err(DecodeError::Unsupported(
-            "LZMA secondary compression requires the 'lzma-secondary' feature".into(),
-        )),
-
-        #[cfg(feature = "zlib-secondary")]
-        Some(VCD_ZLIB_ID) => Ok(Box::new(ZlibBackend::default())),
-
-        #[cfg(not(feature = "zlib-secondary"))]
-        Some(VCD_ZLIB_ID) => Err(DecodeError::Unsupported(
-            "Zlib secondary compression requires the 'zlib-secondary' feature".into(),
-        )),
-
-        Some(id) => Err(DecodeError::Unsupported(format!(
-            "unsupported secondary compressor ID: {id}"
-        ))),
-        None => Err(DecodeError::InvalidInput(
-            "del_ind indicates secondary compression but no compressor ID in file header".into(),
-        )),
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Default backend selection (for encoder convenience)
-// ---------------------------------------------------------------------------
-
-/// The secondary compression algorithm to use.
-#[derive(Clone, Default)]
-pub enum SecondaryCompression {
-    /// No secondary compression.
-    #[default]
-    None,
-    /// LZMA (ID 2). Cross-compatible with xdelta3 C.
-    #[cfg(feature = "lzma-secondary")]
-    Lzma,
-    /// Zlib/Deflate (ID 3). Rust-only extension.
-    #[cfg(feature = "zlib-secondary")]
-    Zlib {
-        /// Zlib compression level (0-9). Default: 6.
-        level: u32,
-    },
-    /// A custom backend provided by the caller.
-    Custom(std::sync::Arc<dyn CompressBackend>),
-}
-
-impl std::fmt::Debug for SecondaryCompression {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::None => write!(f, "None"),
-            #[cfg(feature = "lzma-secondary")]
-            Self::Lzma => write!(f, "Lzma"),
-            #[cfg(feature = "zlib-secondary")]
-            Self::Zlib { level } => write!(f, "Zlib {{ level: {level} }}"),
-            Self::Custom(b) => write!(f, "Custom(id={})", b.id()),
-        }
-    }
-}
-
-impl SecondaryCompression {
-    /// Return the backend implementation, or `None` for no compression.
-    pub fn backend(&self) -> Option<Box<dyn CompressBackend>> {
-        match self {
-            Self::None => None,
-            #[cfg(feature = "lzma-secondary")]
-            Self::Lzma => Some(Box::new(LzmaBackend)),
-            #[cfg(feature = "zlib-secondary")]
-            Self::Zlib { level } => Some(Box::new(ZlibBackend::new(*level))),
-            Self::Custom(b) => Some(Box::new(ArcBackend(b.clone()))),
-        }
-    }
-
-    /// Whether secondary compression is enabled.
-    pub fn is_enabled(&self) -> bool {
-        !matches!(self, Self::None)
-    }
-}
-
-/// Wrapper to make `Arc<dyn CompressBackend>` implement `CompressBackend`.
-struct ArcBackend(std::sync::Arc<dyn CompressBackend>);
-
-impl CompressBackend for ArcBackend {
-    fn id(&self) -> u8 {
-        self.0.id()
-    }
-    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-        self.0.compress(data)
-    }
-    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-        self.0.decompress(data)
-    }
-    fn should_compress(&self, data: &[u8]) -> bool {
-        self.0.should_compress(data)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[cfg(feature = "lzma-secondary")]
-    #[test]
-    fn lzma_compress_decompress_roundtrip() {
-        let backend = LzmaBackend;
-        let data: Vec<u8> = b"Hello, world! This is test data. "
-            .iter()
-            .copied()
-            .cycle()
-            .take(1024)
-            .collect();
-        let compressed = backend.compress(&data).unwrap();
-        assert!(compressed.len() < data.len());
-        let decompressed = backend.decompress(&compressed).unwrap();
-        assert_eq!(decompressed, data);
-    }
-
-    #[cfg(feature = "zlib-secondary")]
-    #[test]
-    fn zlib_compress_decompress_roundtrip() {
-        let backend = ZlibBackend::default();
-        let data: Vec<u8> = b"Hello, world! This is test data. "
-            .iter()
-            .copied()
-            .cycle()
-            .take(1024)
-            .collect();
-        let compressed = backend.compress(&data).unwrap();
-        assert!(compressed.len() < data.len());
-        let decompressed = backend.decompress(&compressed).unwrap();
-        assert_eq!(decompressed, data);
-    }
-
-    #[test]
-    fn no_compression_passthrough() {
-        let backend = NoCompression;
-        let data = b"test data";
-        assert!(!backend.should_compress(data));
-        let compressed = backend.compress(data).unwrap();
-        assert_eq!(compressed, data);
-        let decompressed = backend.decompress(data).unwrap();
-        assert_eq!(decompressed, data);
-    }
-
-    #[test]
-    fn small_data_not_compressed() {
-        #[cfg(feature = "lzma-secondary")]
-        {
-            let backend = LzmaBackend;
-            assert!(!backend.should_compress(b"tiny"));
-            assert!(!backend.should_compress(&[]));
-        }
-    }
-
-    #[cfg(feature = "lzma-secondary")]
-    #[test]
-    fn compress_sections_roundtrip_lzma() {
-        let backend = LzmaBackend;
-        let data = vec![0xAAu8; 200];
-        let inst = vec![0x42u8; 100];
-        let addr = vec![0x00u8; 80];
-
-        let (c_data, c_inst, c_addr, del_ind) =
-            compress_sections(&backend, &data, &inst, &addr).unwrap();
-
-        let (d_data, d_inst, d_addr) =
-            decompress_sections(&c_data, &c_inst, &c_addr, del_ind, Some(VCD_LZMA_ID)).unwrap();
-
-        assert_eq!(d_data, data);
-        assert_eq!(d_inst, inst);
-        assert_eq!(d_addr, addr);
-    }
-
-    #[cfg(feature = "zlib-secondary")]
-    #[test]
-    fn compress_sections_roundtrip_zlib() {
-        let backend = ZlibBackend::default();
-        let data = vec![0xAAu8; 200];
-        let inst = vec![0x42u8; 100];
-        let addr = vec![0x00u8; 80];
-
-        let (c_data, c_inst, c_addr, del_ind) =
-            compress_sections(&backend, &data, &inst, &addr).unwrap();
-
-        let (d_data, d_inst, d_addr) =
-            decompress_sections(&c_data, &c_inst, &c_addr, del_ind, Some(VCD_ZLIB_ID)).unwrap();
-
-        assert_eq!(d_data, data);
-        assert_eq!(d_inst, inst);
-        assert_eq!(d_addr, addr);
-    }
-
-    #[cfg(feature = "lzma-secondary")]
-    #[test]
-    fn incompressible_data_preserved() {
-        let backend = LzmaBackend;
-        let data: Vec<u8> = (0..=255).cycle().take(256).collect();
-        let compressed = compress_section(&backend, &data).unwrap();
-        if compressed.len() < data.len() {
-            let decompressed = backend.decompress(&compressed).unwrap();
-            assert_eq!(decompressed, data);
-        } else {
-            assert_eq!(compressed, data);
-        }
-    }
-
-    #[test]
-    fn wrong_compressor_id_rejected() {
-        let result = decompress_sections(b"data", b"inst", b"addr", VCD_DATACOMP, Some(99));
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn missing_compressor_id_rejected() {
-        let result = decompress_sections(b"data", b"inst", b"addr", VCD_DATACOMP, None);
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn backend_for_id_dispatch() {
-        #[cfg(feature = "lzma-secondary")]
-        {
-            let b = backend_for_id(Some(VCD_LZMA_ID)).unwrap();
-            assert_eq!(b.id(), VCD_LZMA_ID);
-        }
-        #[cfg(feature = "zlib-secondary")]
-        {
-            let b = backend_for_id(Some(VCD_ZLIB_ID)).unwrap();
-            assert_eq!(b.id(), VCD_ZLIB_ID);
-        }
-        assert!(backend_for_id(Some(99)).is_err());
-        assert!(backend_for_id(None).is_err());
-    }
-
-    #[test]
-    fn secondary_compression_enum() {
-        assert!(!SecondaryCompression::None.is_enabled());
-        assert!(SecondaryCompression::None.backend().is_none());
-
-        #[cfg(feature = "lzma-secondary")]
-        {
-            assert!(SecondaryCompression::Lzma.is_enabled());
-            let b = SecondaryCompression::Lzma.backend().unwrap();
-            assert_eq!(b.id(), VCD_LZMA_ID);
-        }
-
-        #[cfg(feature = "zlib-secondary")]
-        {
-            let zlib = SecondaryCompression::Zlib { level: 6 };
-            assert!(zlib.is_enabled());
-            let b = zlib.backend().unwrap();
-            assert_eq!(b.id(), VCD_ZLIB_ID);
-        }
-    }
-
-    #[test]
-    fn custom_backend() {
-        struct TestBackend;
-        impl CompressBackend for TestBackend {
-            fn id(&self) -> u8 {
-                42
-            }
-            fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
-                // Trivial: reverse the bytes
-                Ok(data.iter().rev().copied().collect())
-            }
-            fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-                Ok(data.iter().rev().copied().collect())
-            }
-        }
-
-        let backend = TestBackend;
-        let data = b"hello world";
-        let compressed = backend.compress(data).unwrap();
-        let decompressed = backend.decompress(&compressed).unwrap();
-        assert_eq!(decompressed, data);
-        assert_eq!(backend.id(), 42);
-    }
-
-    #[cfg(all(feature = "lzma-secondary", feature = "zlib-secondary"))]
-    #[test]
-    fn zlib_vs_lzma_comparison() {
-        let data: Vec<u8> = b"ABCDEFGHIJKLMNOP"
-            .iter()
-            .copied()
-            .cycle()
-            .take(4096)
-            .collect();
-
-        let lzma = LzmaBackend;
-        let zlib = ZlibBackend::default();
-
-        let lzma_compressed = lzma.compress(&data).unwrap();
-        let zlib_compressed = zlib.compress(&data).unwrap();
-
-        // Both should compress well.
-        assert!(lzma_compressed.len() < data.len());
-        assert!(zlib_compressed.len() < data.len());
-
-        // Both should roundtrip correctly.
-        assert_eq!(lzma.decompress(&lzma_compressed).unwrap(), data);
-        assert_eq!(zlib.decompress(&zlib_compressed).unwrap(), data);
-    }
-}
+// Secondary compression for VCDIFF DATA/INST/ADDR sections.
+//
+// Provides a pluggable `CompressBackend` trait with built-in implementations:
+//   - LZMA (via lzma-rs, feature-gated `lzma-secondary`)
+//   - Zlib/Deflate (via flate2, feature-gated `zlib-secondary`)
+//   - Zstandard (via zstd, feature-gated `zstd-secondary`)
+//   - FGK adaptive Huffman (xdelta3's `-S fgk`, built in)
+//   - DJW static Huffman (xdelta3's `-S djw`, built in, decode only)
+//   - NoCompression (passthrough)
+//   - External/custom compressors via the trait
+//
+// `SecondaryRegistry` maps header IDs to backends on the decode side, so
+// applications can decode deltas made with their own compressors.
+//
+// The VCDIFF file header stores a secondary compressor ID; xdelta3 defines:
+//   ID 1 = DJW (xdelta3's static Huffman; decode only, see `djw.rs`)
+//   ID 2 = LZMA
+//   ID 16 = FGK (xdelta3's adaptive Huffman; see `fgk.rs`)
+//
+// We additionally define:
+//   ID 3 = Zlib/Deflate (Rust-only extension; not decodable by xdelta3 C)
+//   ID 4 = Zstandard (Rust-only extension; not decodable by xdelta3 C)
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use crate::vcdiff::decoder::{self, DecodeError};
+use crate::vcdiff::header::{
+    VCD_ADDRCOMP, VCD_DATACOMP, VCD_DJW_ID, VCD_FGK_ID, VCD_INSTCOMP, VCD_LZMA_ID,
+};
+use crate::vcdiff::varint;
+
+/// Secondary compressor ID for Zlib/Deflate (Rust extension, not in xdelta3 C).
+pub const VCD_ZLIB_ID: u8 = 3;
+
+/// Secondary compressor ID for Zstandard (Rust extension, not in xdelta3 C).
+pub const VCD_ZSTD_ID: u8 = 4;
+
+/// Minimum section size worth compressing.
+const MIN_COMPRESS_SIZE: usize = 32;
+
+/// Encoded DATA/INST/ADDR sections plus delta indicator flags.
+pub type CompressedSections = (Vec<u8>, Vec<u8>, Vec<u8>, u8);
+/// Decoded DATA/INST/ADDR sections.
+pub type DecompressedSections = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+// ---------------------------------------------------------------------------
+// Secondary compressor IDs
+// ---------------------------------------------------------------------------
+
+/// A secondary compressor ID as stored in the VCDIFF file header.
+///
+/// This is the single source of truth for mapping between header IDs and
+/// compressor names. Conversion from `u8` is infallible (`TryFrom<u8>` is
+/// provided through `From<u8>`): IDs without a known compressor map to
+/// [`SecondaryId::Unknown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecondaryId {
+    /// DJW static Huffman (ID 1, xdelta3).
+    Djw,
+    /// LZMA (ID 2, xdelta3).
+    Lzma,
+    /// Zlib/Deflate (ID 3, Rust extension).
+    Zlib,
+    /// Zstandard (ID 4, Rust extension).
+    Zstd,
+    /// FGK adaptive Huffman (ID 16, xdelta3).
+    Fgk,
+    /// Any other ID.
+    Unknown(u8),
+}
+
+impl SecondaryId {
+    /// All compressors with an assigned ID.
+    pub const KNOWN: [SecondaryId; 5] = [Self::Djw, Self::Lzma, Self::Zlib, Self::Zstd, Self::Fgk];
+
+    /// The numeric ID stored in the file header.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Djw => VCD_DJW_ID,
+            Self::Lzma => VCD_LZMA_ID,
+            Self::Zlib => VCD_ZLIB_ID,
+            Self::Zstd => VCD_ZSTD_ID,
+            Self::Fgk => VCD_FGK_ID,
+            Self::Unknown(id) => id,
+        }
+    }
+
+    /// The canonical lowercase name (as used by the CLI). `"unknown"` for
+    /// unrecognised IDs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Djw => "djw",
+            Self::Lzma => "lzma",
+            Self::Zlib => "zlib",
+            Self::Zstd => "zstd",
+            Self::Fgk => "fgk",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Look up a known compressor by its canonical name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::KNOWN.into_iter().find(|s| s.name() == name)
+    }
+}
+
+impl From<u8> for SecondaryId {
+    fn from(id: u8) -> Self {
+        match id {
+            VCD_DJW_ID => Self::Djw,
+            VCD_LZMA_ID => Self::Lzma,
+            VCD_ZLIB_ID => Self::Zlib,
+            VCD_ZSTD_ID => Self::Zstd,
+            VCD_FGK_ID => Self::Fgk,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<SecondaryId> for u8 {
+    fn from(id: SecondaryId) -> Self {
+        id.id()
+    }
+}
+
+impl std::fmt::Display for SecondaryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(id) => write!(f, "unknown (id={id})"),
+            known => f.write_str(known.name()),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// CompressBackend trait
+// ---------------------------------------------------------------------------
+
+/// A pluggable secondary compressor for VCDIFF sections.
+///
+/// Implementations compress/decompress individual DATA, INST, and ADDR sections
+/// after VCDIFF encoding (or before VCDIFF decoding).
+///
+/// # Implementing a custom backend
+///
+/// ```no_run
+/// use oxidelta::compress::secondary::CompressBackend;
+/// use oxidelta::vcdiff::decoder::DecodeError;
+///
+/// struct MyCompressor;
+///
+/// impl CompressBackend for MyCompressor {
+///     fn id(&self) -> u8 { 42 }
+///     fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+///         Ok(data.to_vec()) // placeholder
+///     }
+///     fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, DecodeError> {
+///         Ok(data.to_vec()) // placeholder
+///     }
+/// }
+/// ```
+pub trait CompressBackend: Send + Sync {
+    /// The secondary compressor ID stored in the VCDIFF file header.
+    ///
+    /// Standard IDs: 1 (DJW), 2 (LZMA), 16 (FGK).
+    /// Rust extensions: 3 (Zlib), 4 (Zstd).
+    /// Custom implementations should use IDs that don't collide with these.
+    fn id(&self) -> u8;
+
+    /// Compress a section. Returns compressed bytes.
+    ///
+    /// If compression would not reduce size, implementations should return
+    /// the original data unchanged (the caller checks this).
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Decompress a section previously compressed by `compress()`.
+    ///
+    /// Sections come from untrusted deltas: stop and return an error once
+    /// the output would exceed `limit` bytes, the most the section's window
+    /// can use (see [`SectionLimits`]), rather than inflate it all first.
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError>;
+
+    /// Whether this section is worth compressing. Default: skip if < 32 bytes.
+    fn should_compress(&self, data: &[u8]) -> bool {
+        data.len() >= MIN_COMPRESS_SIZE
+    }
+
+    /// Human-readable name, shown by the header dump. Default: `"custom"`.
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// Whether xdelta3 can decode sections compressed by this backend.
+    /// Default: `false`.
+    fn xdelta3_compatible(&self) -> bool {
+        false
+    }
+}
+
+// ---------------------------------------------------------------------------
+// LZMA backend
+// ---------------------------------------------------------------------------
+
+/// LZMA secondary compressor (ID 2). Cross-compatible with xdelta3 C.
+#[cfg(feature = "lzma-secondary")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LzmaBackend;
+
+#[cfg(feature = "lzma-secondary")]
+impl CompressBackend for LzmaBackend {
+    fn id(&self) -> u8 {
+        VCD_LZMA_ID
+    }
+
+    fn name(&self) -> &str {
+        "lzma"
+    }
+
+    fn xdelta3_compatible(&self) -> bool {
+        true
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = io::Cursor::new(data);
+        let mut output = Vec::new();
+        lzma_rs::lzma_compress(&mut input, &mut output)?;
+        Ok(output)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        let mut input = io::BufReader::new(io::Cursor::new(data));
+        let mut output = BoundedWriter::new(limit);
+        // The dictionary buffer only grows with the output, so the same
+        // limit also caps its memory.
+        let options = lzma_rs::decompress::Options {
+            memlimit: Some(limit),
+            ..Default::default()
+        };
+        lzma_rs::lzma_decompress_with_options(&mut input, &mut output, &options)
+            .map_err(|e| DecodeError::InvalidInput(format!("LZMA decompression failed: {e}")))?;
+        Ok(output.out)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Zlib backend
+// ---------------------------------------------------------------------------
+
+/// Zlib/Deflate secondary compressor (ID 3). Rust-only extension.
+///
+/// Uses zlib format (deflate + zlib header), not raw deflate,
+/// so the stream is self-describing and includes a checksum.
+#[cfg(feature = "zlib-secondary")]
+#[derive(Debug, Clone, Copy)]
+pub struct ZlibBackend {
+    level: flate2::Compression,
+}
+
+#[cfg(feature = "zlib-secondary")]
+impl ZlibBackend {
+    /// Create a Zlib backend with the given compression level (0-9).
+    pub fn new(level: u32) -> Self {
+        Self {
+            level: flate2::Compression::new(level),
+        }
+    }
+}
+
+#[cfg(feature = "zlib-secondary")]
+impl Default for ZlibBackend {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+#[cfg(feature = "zlib-secondary")]
+impl CompressBackend for ZlibBackend {
+    fn id(&self) -> u8 {
+        VCD_ZLIB_ID
+    }
+
+    fn name(&self) -> &str {
+        "zlib"
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        use flate2::write::ZlibEncoder;
+        use io::Write;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), self.level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        let decoder = flate2::read::ZlibDecoder::new(data);
+        read_bounded(decoder, limit)
+            .map_err(|e| DecodeError::InvalidInput(format!("Zlib decompression failed: {e}")))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Zstd backend
+// ---------------------------------------------------------------------------
+
+/// Zstandard secondary compressor (ID 4). Rust-only extension.
+///
+/// Better ratio than Zlib at similar speed, but deltas using it cannot be
+/// decoded by xdelta3.
+#[cfg(feature = "zstd-secondary")]
+#[derive(Debug, Clone, Copy)]
+pub struct ZstdBackend {
+    level: i32,
+}
+
+#[cfg(feature = "zstd-secondary")]
+impl ZstdBackend {
+    /// Create a Zstd backend with the given compression level (1-22;
+    /// 0 selects zstd's default).
+    pub fn new(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "zstd-secondary")]
+impl Default for ZstdBackend {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+#[cfg(feature = "zstd-secondary")]
+impl CompressBackend for ZstdBackend {
+    fn id(&self) -> u8 {
+        VCD_ZSTD_ID
+    }
+
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::compress(data, self.level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        zstd::stream::read::Decoder::with_buffer(data)
+            .and_then(|decoder| read_bounded(decoder, limit))
+            .map_err(|e| DecodeError::InvalidInput(format!("Zstd decompression failed: {e}")))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// FGK backend
+// ---------------------------------------------------------------------------
+
+/// FGK adaptive Huffman secondary compressor (ID 16), ported from xdelta3
+/// C's `-S fgk` but not yet checked against its output.
+///
+/// Order-0 only, so it gains less than LZMA, but it is fast. Each section is
+/// coded from a fresh model. Decoding needs no cargo feature; encoding
+/// through [`SecondaryCompression::Fgk`] or the CLI needs `fgk-encode`
+/// until the bitstream is checked against xdelta3.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FgkBackend;
+
+impl CompressBackend for FgkBackend {
+    fn id(&self) -> u8 {
+        VCD_FGK_ID
+    }
+
+    fn name(&self) -> &str {
+        "fgk"
+    }
+
+    fn xdelta3_compatible(&self) -> bool {
+        false // Unverified; see `fgk.rs`
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(super::fgk::encode_section(data))
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        check_declared_size("FGK", data, limit)?;
+        super::fgk::decode_section(data)
+            .map_err(|e| DecodeError::InvalidInput(format!("FGK decompression failed: {e}")))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DJW backend
+// ---------------------------------------------------------------------------
+
+/// DJW static Huffman secondary (ID 1), for decoding xdelta3 `-S djw`
+/// deltas. Not yet checked against real xdelta3 output.
+///
+/// Decode only: `compress` returns its input unchanged, so a section is
+/// never DJW-compressed by oxidelta.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DjwBackend;
+
+impl CompressBackend for DjwBackend {
+    fn id(&self) -> u8 {
+        VCD_DJW_ID
+    }
+
+    fn name(&self) -> &str {
+        "djw"
+    }
+
+    fn should_compress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        check_declared_size("DJW", data, limit)?;
+        super::djw::decode_section(data)
+            .map_err(|e| DecodeError::InvalidInput(format!("DJW decompression failed: {e}")))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// No-compression backend
+// ---------------------------------------------------------------------------
+
+/// Passthrough "compressor" that performs no compression.
+///
+/// Useful as a default or for testing. The `compress` method returns data
+/// unchanged, so `compress_section` will never set compression flags.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCompression;
+
+impl CompressBackend for NoCompression {
+    fn id(&self) -> u8 {
+        0 // Never written to the file header since sections aren't compressed
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        if data.len() > limit {
+            return Err(section_too_large("uncompressed", limit));
+        }
+        Ok(data.to_vec())
+    }
+
+    fn should_compress(&self, _data: &[u8]) -> bool {
+        false // Never compress
+    }
+
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn xdelta3_compatible(&self) -> bool {
+        true // Sections are never compressed
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Section-level compression/decompression helpers
+// ---------------------------------------------------------------------------
+
+/// Compress a single section using the given backend.
+///
+/// Returns compressed data only if it's actually smaller; otherwise returns
+/// the original data unchanged.
+pub fn compress_section(backend: &dyn CompressBackend, data: &[u8]) -> io::Result<Vec<u8>> {
+    if !backend.should_compress(data) {
+        return Ok(data.to_vec());
+    }
+
+    let compressed = backend.compress(data)?;
+
+    if compressed.len() < data.len() {
+        Ok(compressed)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Decompress a single section using the given backend, to at most `limit`
+/// bytes.
+pub fn decompress_section(
+    backend: &dyn CompressBackend,
+    data: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    let out = backend.decompress(data, limit)?;
+    // Backends are expected to stop early; this catches those that don't.
+    if out.len() > limit {
+        return Err(section_too_large(backend.name(), limit));
+    }
+    Ok(out)
+}
+
+/// Largest decompressed sizes a window's sections can have.
+///
+/// Every instruction produces at least one target byte, and takes at most
+/// an opcode and a size varint in the instruction section and an address
+/// varint in the address section; the data section holds at most the
+/// window's target bytes. Anything larger cannot belong to a valid window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionLimits {
+    /// Data section (ADD and RUN bytes).
+    pub data: usize,
+    /// Instruction section.
+    pub inst: usize,
+    /// Address section.
+    pub addr: usize,
+}
+
+impl SectionLimits {
+    /// Limits for a window of `target_len` bytes that may execute at most
+    /// `max_instructions` instructions (`u64::MAX` for no such budget, or
+    /// what remains of `DecodeLimits::max_instructions`).
+    pub fn for_window(target_len: u64, max_instructions: u64) -> Self {
+        let instructions = target_len.min(max_instructions);
+        let varint = varint::MAX_VARINT_LEN as u64;
+        let cap = |len: u64| usize::try_from(len).unwrap_or(usize::MAX);
+        Self {
+            data: cap(target_len),
+            inst: cap(instructions.saturating_mul(1 + varint)),
+            addr: cap(instructions.saturating_mul(varint)),
+        }
+    }
+}
+
+/// The error for a section that decompresses past its limit.
+fn section_too_large(name: &str, limit: usize) -> DecodeError {
+    DecodeError::InvalidInput(format!(
+        "{name} section decompresses to more than {limit} bytes"
+    ))
+}
+
+/// Reject a size-prefixed section (FGK, DJW) that declares more than
+/// `limit` bytes, before decoding it.
+fn check_declared_size(name: &str, data: &[u8], limit: usize) -> Result<(), DecodeError> {
+    match varint::read_usize(data) {
+        Ok((size, _)) if size > limit => Err(section_too_large(name, limit)),
+        _ => Ok(()),
+    }
+}
+
+/// Read `reader` to its end, failing once it yields more than `limit`
+/// bytes.
+#[cfg(any(feature = "zlib-secondary", feature = "zstd-secondary"))]
+fn read_bounded(reader: impl io::Read, limit: usize) -> io::Result<Vec<u8>> {
+    use io::Read;
+
+    let mut out = Vec::new();
+    reader
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(&mut out)?;
+    if out.len() > limit {
+        return Err(io::Error::other(format!("output exceeds {limit} bytes")));
+    }
+    Ok(out)
+}
+
+/// `Write` into a `Vec` that fails once more than `limit` bytes arrive.
+#[cfg(feature = "lzma-secondary")]
+struct BoundedWriter {
+    out: Vec<u8>,
+    limit: usize,
+}
+
+#[cfg(feature = "lzma-secondary")]
+impl BoundedWriter {
+    fn new(limit: usize) -> Self {
+        Self {
+            out: Vec::new(),
+            limit,
+        }
+    }
+}
+
+#[cfg(feature = "lzma-secondary")]
+impl io::Write for BoundedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.limit - self.out.len() {
+            return Err(io::Error::other(format!(
+                "output exceeds {} bytes",
+                self.limit
+            )));
+        }
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compress all three VCDIFF sections independently.
+///
+/// Returns (data, inst, addr, del_ind). Each section is kept compressed only
+/// if that made it strictly smaller; otherwise its raw bytes are emitted and
+/// its VCD_DATACOMP/VCD_INSTCOMP/VCD_ADDRCOMP bit stays clear. Small windows
+/// commonly end up with only the DATA section compressed.
+pub fn compress_sections(
+    backend: &dyn CompressBackend,
+    data: &[u8],
+    inst: &[u8],
+    addr: &[u8],
+) -> io::Result<CompressedSections> {
+    let mut del_ind: u8 = 0;
+    let mut section = |raw: &[u8], flag: u8| -> io::Result<Vec<u8>> {
+        // `compress_section` already falls back to the raw bytes.
+        let out = compress_section(backend, raw)?;
+        if out.len() < raw.len() {
+            del_ind |= flag;
+        }
+        Ok(out)
+    };
+
+    let final_data = section(data, VCD_DATACOMP)?;
+    let final_inst = section(inst, VCD_INSTCOMP)?;
+    let final_addr = section(addr, VCD_ADDRCOMP)?;
+
+    Ok((final_data, final_inst, final_addr, del_ind))
+}
+
+/// Decompress sections according to del_ind flags, each to at most its
+/// entry in `limits`.
+///
+/// `secondary_id` is validated against the backend's ID.
+pub fn decompress_sections(
+    data: &[u8],
+    inst: &[u8],
+    addr: &[u8],
+    del_ind: u8,
+    secondary_id: Option<u8>,
+    limits: SectionLimits,
+) -> Result<DecompressedSections, DecodeError> {
+    if del_ind == 0 {
+        return Ok((data.to_vec(), inst.to_vec(), addr.to_vec()));
+    }
+    let backend = backend_for_id(secondary_id)?;
+    decompress_flagged(backend.as_ref(), data, inst, addr, del_ind, limits)
+}
+
+/// Like [`decompress_sections`], but looks the backend up in `registry`.
+pub fn decompress_sections_with(
+    registry: &SecondaryRegistry,
+    data: &[u8],
+    inst: &[u8],
+    addr: &[u8],
+    del_ind: u8,
+    secondary_id: Option<u8>,
+    limits: SectionLimits,
+) -> Result<DecompressedSections, DecodeError> {
+    if del_ind == 0 {
+        return Ok((data.to_vec(), inst.to_vec(), addr.to_vec()));
+    }
+    let backend = registry.get(secondary_id)?;
+    decompress_flagged(backend, data, inst, addr, del_ind, limits)
+}
+
+fn decompress_flagged(
+    backend: &dyn CompressBackend,
+    data: &[u8],
+    inst: &[u8],
+    addr: &[u8],
+    del_ind: u8,
+    limits: SectionLimits,
+) -> Result<DecompressedSections, DecodeError> {
+    let dec_data = if del_ind & VCD_DATACOMP != 0 {
+        decompress_section(backend, data, limits.data)?
+    } else {
+        data.to_vec()
+    };
+
+    let dec_inst = if del_ind & VCD_INSTCOMP != 0 {
+        decompress_section(backend, inst, limits.inst)?
+    } else {
+        inst.to_vec()
+    };
+
+    let dec_addr = if del_ind & VCD_ADDRCOMP != 0 {
+        decompress_section(backend, addr, limits.addr)?
+    } else {
+        addr.to_vec()
+    };
+
+    Ok((dec_data, dec_inst, dec_addr))
+}
+
+/// Look up a decompression backend by secondary compressor ID.
+///
+/// This is the decode-side dispatch: given the ID from the file header,
+/// return the appropriate backend to decompress sections.
+pub fn backend_for_id(secondary_id: Option<u8>) -> Result<Box<dyn CompressBackend>, DecodeError> {
+    match secondary_id {
+        #[cfg(feature = "lzma-secondary")]
+        Some(VCD_LZMA_ID) => Ok(Box::new(LzmaBackend)),
+
+        #[cfg(not(feature = "lzma-secondary"))]
+        Some(VCD_LZMA_ID) => Err(DecodeError::Unsupported(
+            "LZMA secondary compression requires the 'lzma-secondary' feature".into(),
+        )),
+
+        #[cfg(feature = "zlib-secondary")]
+        Some(VCD_ZLIB_ID) => Ok(Box::new(ZlibBackend::default())),
+
+        #[cfg(not(feature = "zlib-secondary"))]
+        Some(VCD_ZLIB_ID) => Err(DecodeError::Unsupported(
+            "Zlib secondary compression requires the 'zlib-secondary' feature".into(),
+        )),
+
+        #[cfg(feature = "zstd-secondary")]
+        Some(VCD_ZSTD_ID) => Ok(Box::new(ZstdBackend::default())),
+
+        Some(VCD_FGK_ID) => Ok(Box::new(FgkBackend)),
+
+        Some(VCD_DJW_ID) => Ok(Box::new(DjwBackend)),
+
+        Some(id) => Err(unsupported_id(id)),
+        None => Err(decoder::missing_secondary_id()),
+    }
+}
+
+/// The error for a secondary compressor ID with no available backend.
+fn unsupported_id(id: u8) -> DecodeError {
+    match id {
+        #[cfg(not(feature = "lzma-secondary"))]
+        VCD_LZMA_ID => DecodeError::Unsupported(
+            "LZMA secondary compression requires the 'lzma-secondary' feature".into(),
+        ),
+
+        #[cfg(not(feature = "zlib-secondary"))]
+        VCD_ZLIB_ID => DecodeError::Unsupported(
+            "Zlib secondary compression requires the 'zlib-secondary' feature".into(),
+        ),
+
+        #[cfg(not(feature = "zstd-secondary"))]
+        VCD_ZSTD_ID => DecodeError::Unsupported(
+            "Zstd secondary compression requires the 'zstd-secondary' feature".into(),
+        ),
+
+        _ => DecodeError::Unsupported(format!("unsupported secondary compressor ID: {id}")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Backend registry (decode side)
+// ---------------------------------------------------------------------------
+
+/// Maps secondary compressor IDs to decompression backends.
+///
+/// [`SecondaryRegistry::new`] pre-registers the built-in backends enabled by
+/// cargo features (LZMA, Zlib, Zstd), FGK and DJW; applications add their own with
+/// [`register`](Self::register). Install one on a decoder with
+/// `StreamDecoder::register_secondary`.
+#[derive(Clone)]
+pub struct SecondaryRegistry {
+    backends: HashMap<u8, Arc<dyn CompressBackend>>,
+}
+
+impl SecondaryRegistry {
+    /// A registry holding the built-in backends.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register(VCD_FGK_ID, Arc::new(FgkBackend));
+        registry.register(VCD_DJW_ID, Arc::new(DjwBackend));
+        #[cfg(feature = "lzma-secondary")]
+        registry.register(VCD_LZMA_ID, Arc::new(LzmaBackend));
+        #[cfg(feature = "zlib-secondary")]
+        registry.register(VCD_ZLIB_ID, Arc::new(ZlibBackend::default()));
+        #[cfg(feature = "zstd-secondary")]
+        registry.register(VCD_ZSTD_ID, Arc::new(ZstdBackend::default()));
+        registry
+    }
+
+    /// A registry with no backends at all.
+    pub fn empty() -> Self {
+        Self {
+            backends: HashMap::new(),
+        }
+    }
+
+    /// Decode sections tagged with `id` using `backend`, replacing any
+    /// backend (built-in or not) previously registered for `id`.
+    pub fn register(&mut self, id: u8, backend: Arc<dyn CompressBackend>) {
+        self.backends.insert(id, backend);
+    }
+
+    /// Look up the backend for the file header's secondary ID.
+    pub fn get(&self, secondary_id: Option<u8>) -> Result<&dyn CompressBackend, DecodeError> {
+        let id = secondary_id.ok_or_else(decoder::missing_secondary_id)?;
+        match self.backends.get(&id) {
+            Some(backend) => Ok(backend.as_ref()),
+            None => Err(unsupported_id(id)),
+        }
+    }
+
+    /// Display name for a header's secondary ID: the registered backend's
+    /// [`name`](CompressBackend::name), else the [`SecondaryId`] name
+    /// (`"unknown (id=N)"` for unassigned IDs).
+    pub fn describe(&self, id: u8) -> String {
+        match self.backends.get(&id) {
+            Some(backend) => backend.name().to_string(),
+            None => SecondaryId::from(id).to_string(),
+        }
+    }
+}
+
+impl Default for SecondaryRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for SecondaryRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ids: Vec<u8> = self.backends.keys().copied().collect();
+        ids.sort_unstable();
+        f.debug_struct("SecondaryRegistry")
+            .field("ids", &ids)
+            .finish()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Default backend selection (for encoder convenience)
+// ---------------------------------------------------------------------------
+
+/// The secondary compression algorithm to use.
+#[derive(Clone, Default)]
+pub enum SecondaryCompression {
+    /// No secondary compression.
+    #[default]
+    None,
+    /// LZMA (ID 2). Cross-compatible with xdelta3 C.
+    #[cfg(feature = "lzma-secondary")]
+    Lzma,
+    /// Zlib/Deflate (ID 3). Rust-only extension.
+    #[cfg(feature = "zlib-secondary")]
+    Zlib {
+        /// Zlib compression level (0-9). Default: 6.
+        level: u32,
+    },
+    /// Zstandard (ID 4). Rust-only extension.
+    #[cfg(feature = "zstd-secondary")]
+    Zstd {
+        /// Zstd compression level (1-22; 0 = zstd default).
+        level: i32,
+    },
+    /// FGK adaptive Huffman (ID 16), ported from xdelta3 C; unverified,
+    /// so only built with the `fgk-encode` feature.
+    #[cfg(feature = "fgk-encode")]
+    Fgk,
+    /// A custom backend provided by the caller.
+    Custom(std::sync::Arc<dyn CompressBackend>),
+}
+
+impl std::fmt::Debug for SecondaryCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            #[cfg(feature = "lzma-secondary")]
+            Self::Lzma => write!(f, "Lzma"),
+            #[cfg(feature = "zlib-secondary")]
+            Self::Zlib { level } => write!(f, "Zlib {{ level: {level} }}"),
+            #[cfg(feature = "zstd-secondary")]
+            Self::Zstd { level } => write!(f, "Zstd {{ level: {level} }}"),
+            #[cfg(feature = "fgk-encode")]
+            Self::Fgk => write!(f, "Fgk"),
+            Self::Custom(b) => write!(f, "Custom(id={})", b.id()),
+        }
+    }
+}
+
+impl SecondaryCompression {
+    /// Return the backend implementation, or `None` for no compression.
+    pub fn backend(&self) -> Option<Box<dyn CompressBackend>> {
+        match self {
+            Self::None => None,
+            #[cfg(feature = "lzma-secondary")]
+            Self::Lzma => Some(Box::new(LzmaBackend)),
+            #[cfg(feature = "zlib-secondary")]
+            Self::Zlib { level } => Some(Box::new(ZlibBackend::new(*level))),
+            #[cfg(feature = "zstd-secondary")]
+            Self::Zstd { level } => Some(Box::new(ZstdBackend::new(*level))),
+            #[cfg(feature = "fgk-encode")]
+            Self::Fgk => Some(Box::new(FgkBackend)),
+            Self::Custom(b) => Some(Box::new(ArcBackend(b.clone()))),
+        }
+    }
+
+    /// Whether secondary compression is enabled.
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Self::None)
+    }
+}
+
+/// Wrapper to make `Arc<dyn CompressBackend>` implement `CompressBackend`.
+struct ArcBackend(std::sync::Arc<dyn CompressBackend>);
+
+impl CompressBackend for ArcBackend {
+    fn id(&self) -> u8 {
+        self.0.id()
+    }
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.0.compress(data)
+    }
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        self.0.decompress(data, limit)
+    }
+    fn should_compress(&self, data: &[u8]) -> bool {
+        self.0.should_compress(data)
+    }
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+    fn xdelta3_compatible(&self) -> bool {
+        self.0.xdelta3_compatible()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_LIMITS: SectionLimits = SectionLimits {
+        data: usize::MAX,
+        inst: usize::MAX,
+        addr: usize::MAX,
+    };
+
+    #[test]
+    fn secondary_id_roundtrip() {
+        for sec in SecondaryId::KNOWN {
+            assert_eq!(SecondaryId::from(sec.id()), sec);
+            assert_eq!(SecondaryId::from_name(sec.name()), Some(sec));
+            assert_eq!(u8::from(sec), sec.id());
+        }
+        assert_eq!(SecondaryId::from(1), SecondaryId::Djw);
+        assert_eq!(SecondaryId::from(2), SecondaryId::Lzma);
+        assert_eq!(SecondaryId::from(3), SecondaryId::Zlib);
+        assert_eq!(SecondaryId::from(16), SecondaryId::Fgk);
+    }
+
+    #[test]
+    fn secondary_id_unknown() {
+        let sec = SecondaryId::from(42);
+        assert_eq!(sec, SecondaryId::Unknown(42));
+        assert_eq!(sec.id(), 42);
+        assert_eq!(sec.name(), "unknown");
+        assert_eq!(sec.to_string(), "unknown (id=42)");
+        assert_eq!(SecondaryId::from_name("unknown"), None);
+        assert_eq!(SecondaryId::from_name("bzip2"), None);
+    }
+
+    #[cfg(feature = "lzma-secondary")]
+    #[test]
+    fn lzma_compress_decompress_roundtrip() {
+        let backend = LzmaBackend;
+        let data: Vec<u8> = b"Hello, world! This is test data. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(1024)
+            .collect();
+        let compressed = backend.compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        let decompressed = backend.decompress(&compressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[cfg(feature = "zstd-secondary")]
+    #[test]
+    fn zstd_compress_decompress_roundtrip() {
+        let backend = ZstdBackend::default();
+        let data = b"Hello, world! This is test data. ".repeat(32);
+        let compressed = backend.compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(backend.decompress(&compressed, usize::MAX).unwrap(), data);
+        assert_eq!(backend_for_id(Some(VCD_ZSTD_ID)).unwrap().id(), VCD_ZSTD_ID);
+    }
+
+    #[cfg(feature = "zlib-secondary")]
+    #[test]
+    fn zlib_compress_decompress_roundtrip() {
+        let backend = ZlibBackend::default();
+        let data: Vec<u8> = b"Hello, world! This is test data. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(1024)
+            .collect();
+        let compressed = backend.compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        let decompressed = backend.decompress(&compressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn no_compression_passthrough() {
+        let backend = NoCompression;
+        let data = b"test data";
+        assert!(!backend.should_compress(data));
+        let compressed = backend.compress(data).unwrap();
+        assert_eq!(compressed, data);
+        let decompressed = backend.decompress(data, usize::MAX).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn small_data_not_compressed() {
+        #[cfg(feature = "lzma-secondary")]
+        {
+            let backend = LzmaBackend;
+            assert!(!backend.should_compress(b"tiny"));
+            assert!(!backend.should_compress(&[]));
+        }
+    }
+
+    #[cfg(feature = "lzma-secondary")]
+    #[test]
+    fn compress_sections_roundtrip_lzma() {
+        let backend = LzmaBackend;
+        let data = vec![0xAAu8; 200];
+        let inst = vec![0x42u8; 100];
+        let addr = vec![0x00u8; 80];
+
+        let (c_data, c_inst, c_addr, del_ind) =
+            compress_sections(&backend, &data, &inst, &addr).unwrap();
+
+        let (d_data, d_inst, d_addr) = decompress_sections(
+            &c_data,
+            &c_inst,
+            &c_addr,
+            del_ind,
+            Some(VCD_LZMA_ID),
+            NO_LIMITS,
+        )
+        .unwrap();
+
+        assert_eq!(d_data, data);
+        assert_eq!(d_inst, inst);
+        assert_eq!(d_addr, addr);
+    }
+
+    #[cfg(feature = "zlib-secondary")]
+    #[test]
+    fn compress_sections_roundtrip_zlib() {
+        let backend = ZlibBackend::default();
+        let data = vec![0xAAu8; 200];
+        let inst = vec![0x42u8; 100];
+        let addr = vec![0x00u8; 80];
+
+        let (c_data, c_inst, c_addr, del_ind) =
+            compress_sections(&backend, &data, &inst, &addr).unwrap();
+
+        let (d_data, d_inst, d_addr) = decompress_sections(
+            &c_data,
+            &c_inst,
+            &c_addr,
+            del_ind,
+            Some(VCD_ZLIB_ID),
+            NO_LIMITS,
+        )
+        .unwrap();
+
+        assert_eq!(d_data, data);
+        assert_eq!(d_inst, inst);
+        assert_eq!(d_addr, addr);
+    }
+
+    /// Test backend: "compresses" sections of a repeated byte down to two
+    /// bytes and makes anything else one byte larger.
+    struct RleOnly;
+
+    impl CompressBackend for RleOnly {
+        fn id(&self) -> u8 {
+            200
+        }
+        fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            if data.iter().all(|&b| b == data[0]) {
+                Ok(vec![data[0], data.len() as u8])
+            } else {
+                let mut out = data.to_vec();
+                out.push(0);
+                Ok(out)
+            }
+        }
+        fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, DecodeError> {
+            Ok(vec![data[0]; data[1] as usize])
+        }
+    }
+
+    #[test]
+    fn compress_sections_only_keeps_winning_sections() {
+        let data = vec![0xAAu8; 200];
+        let inst: Vec<u8> = (0..100).collect();
+        let addr: Vec<u8> = (0..80).rev().collect();
+
+        let (c_data, c_inst, c_addr, del_ind) =
+            compress_sections(&RleOnly, &data, &inst, &addr).unwrap();
+        assert_eq!(del_ind, VCD_DATACOMP);
+        assert_eq!(c_data, [0xAA, 200]);
+        assert_eq!(c_inst, inst);
+        assert_eq!(c_addr, addr);
+    }
+
+    #[cfg(feature = "zlib-secondary")]
+    #[test]
+    fn compress_sections_data_only_roundtrip() {
+        use crate::test_util::XorShift32;
+
+        // Compressible DATA; INST/ADDR too small or too noisy to shrink.
+        let backend = ZlibBackend::default();
+        let data = b"literal text literal text literal text ".repeat(8);
+        let inst: Vec<u8> = (0..24).collect();
+        let addr = XorShift32::new(0x9E37_79B9).bytes(64);
+
+        let (c_data, c_inst, c_addr, del_ind) =
+            compress_sections(&backend, &data, &inst, &addr).unwrap();
+        assert_eq!(del_ind, VCD_DATACOMP);
+        assert!(c_data.len() < data.len());
+        assert_eq!((&c_inst, &c_addr), (&inst, &addr));
+
+        let (d_data, d_inst, d_addr) = decompress_sections(
+            &c_data,
+            &c_inst,
+            &c_addr,
+            del_ind,
+            Some(VCD_ZLIB_ID),
+            NO_LIMITS,
+        )
+        .unwrap();
+        assert_eq!((d_data, d_inst, d_addr), (data, inst, addr));
+    }
+
+    #[test]
+    fn sections_are_bounded_by_the_window() {
+        // A 16-byte window has at most 16 data bytes and 16 instructions.
+        let limits = SectionLimits::for_window(16, u64::MAX);
+        assert_eq!((limits.data, limits.inst, limits.addr), (16, 176, 160));
+        let limits = SectionLimits::for_window(16, 2);
+        assert_eq!((limits.data, limits.inst, limits.addr), (16, 22, 20));
+
+        // A backend that ignores its limit is still caught afterwards.
+        let err =
+            decompress_flagged(&RleOnly, &[0xAA, 200], b"", b"", VCD_DATACOMP, limits).unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+
+        let zeros = vec![0u8; 1 << 20];
+        let (c_data, _, _, _) = compress_sections(&FgkBackend, &zeros, b"", b"").unwrap();
+        let err = FgkBackend.decompress(&c_data, 16).unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+        assert_eq!(FgkBackend.decompress(&c_data, zeros.len()).unwrap(), zeros);
+
+        let backends: Vec<Box<dyn CompressBackend>> = vec![
+            #[cfg(feature = "lzma-secondary")]
+            Box::new(LzmaBackend),
+            #[cfg(feature = "zlib-secondary")]
+            Box::new(ZlibBackend::default()),
+            #[cfg(feature = "zstd-secondary")]
+            Box::new(ZstdBackend::default()),
+        ];
+        for backend in backends {
+            let bomb = backend.compress(&zeros).unwrap();
+            let err = backend.decompress(&bomb, 16).unwrap_err();
+            assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+            assert_eq!(backend.decompress(&bomb, zeros.len()).unwrap(), zeros);
+        }
+    }
+
+    #[cfg(feature = "lzma-secondary")]
+    #[test]
+    fn incompressible_data_preserved() {
+        let backend = LzmaBackend;
+        let data: Vec<u8> = (0..=255).cycle().take(256).collect();
+        let compressed = compress_section(&backend, &data).unwrap();
+        if compressed.len() < data.len() {
+            let decompressed = backend.decompress(&compressed, usize::MAX).unwrap();
+            assert_eq!(decompressed, data);
+        } else {
+            assert_eq!(compressed, data);
+        }
+    }
+
+    #[test]
+    fn wrong_compressor_id_rejected() {
+        let result =
+            decompress_sections(b"data", b"inst", b"addr", VCD_DATACOMP, Some(99), NO_LIMITS);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn missing_compressor_id_rejected() {
+        let result = decompress_sections(b"data", b"inst", b"addr", VCD_DATACOMP, None, NO_LIMITS);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn backend_for_id_dispatch() {
+        #[cfg(feature = "lzma-secondary")]
+        {
+            let b = backend_for_id(Some(VCD_LZMA_ID)).unwrap();
+            assert_eq!(b.id(), VCD_LZMA_ID);
+        }
+        #[cfg(feature = "zlib-secondary")]
+        {
+            let b = backend_for_id(Some(VCD_ZLIB_ID)).unwrap();
+            assert_eq!(b.id(), VCD_ZLIB_ID);
+        }
+        assert!(backend_for_id(Some(99)).is_err());
+        assert!(backend_for_id(None).is_err());
+    }
+
+    #[test]
+    fn djw_sections_decode() {
+        // "ab" as one DJW group; see `djw::tests::decodes_single_group_section`.
+        let section = [
+            0x02, 0x08, 0x22, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0xC0, 0x49, 0x54, 0x10,
+        ];
+        let (data, inst, addr) = decompress_sections(
+            &section,
+            b"i",
+            b"",
+            VCD_DATACOMP,
+            Some(VCD_DJW_ID),
+            NO_LIMITS,
+        )
+        .unwrap();
+        assert_eq!(
+            (&data[..], &inst[..], &addr[..]),
+            (&b"ab"[..], &b"i"[..], &b""[..])
+        );
+
+        // Decode only: nothing is ever DJW-compressed.
+        let backend = DjwBackend;
+        let text = b"compressible compressible compressible".to_vec();
+        assert_eq!(compress_section(&backend, &text).unwrap(), text);
+        let err = decompress_sections(b"data", b"", b"", VCD_DATACOMP, Some(VCD_DJW_ID), NO_LIMITS)
+            .unwrap_err();
+        match err {
+            DecodeError::InvalidInput(msg) => assert!(msg.contains("DJW"), "{msg}"),
+            other => panic!("expected InvalidInput, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn compress_sections_roundtrip_fgk() {
+        let data = b"aaaaaaaabbbbccde".repeat(16);
+        let inst = vec![0x13u8; 100];
+        let addr: Vec<u8> = (0..80).map(|i| (i % 5) as u8).collect();
+
+        let (c_data, c_inst, c_addr, del_ind) =
+            compress_sections(&FgkBackend, &data, &inst, &addr).unwrap();
+        assert_eq!(del_ind, VCD_DATACOMP | VCD_INSTCOMP | VCD_ADDRCOMP);
+        assert!(c_data.len() < data.len() / 3);
+
+        let (d_data, d_inst, d_addr) = decompress_sections(
+            &c_data,
+            &c_inst,
+            &c_addr,
+            del_ind,
+            Some(VCD_FGK_ID),
+            NO_LIMITS,
+        )
+        .unwrap();
+        assert_eq!((d_data, d_inst, d_addr), (data, inst, addr));
+
+        let err = FgkBackend
+            .decompress(&c_data[..c_data.len() - 1], usize::MAX)
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+    }
+
+    #[test]
+    fn backend_names_and_compatibility() {
+        let none = NoCompression;
+        assert_eq!((none.name(), none.xdelta3_compatible()), ("none", true));
+        assert_eq!(
+            (FgkBackend.name(), FgkBackend.xdelta3_compatible()),
+            ("fgk", false)
+        );
+        #[cfg(feature = "lzma-secondary")]
+        assert_eq!(
+            (LzmaBackend.name(), LzmaBackend.xdelta3_compatible()),
+            ("lzma", true)
+        );
+        #[cfg(feature = "zlib-secondary")]
+        {
+            let zlib = ZlibBackend::default();
+            assert_eq!((zlib.name(), zlib.xdelta3_compatible()), ("zlib", false));
+        }
+        #[cfg(feature = "zstd-secondary")]
+        {
+            let zstd = ZstdBackend::default();
+            assert_eq!((zstd.name(), zstd.xdelta3_compatible()), ("zstd", false));
+        }
+
+        // Wrapped custom backends forward both; the defaults apply otherwise.
+        let custom = SecondaryCompression::Custom(Arc::new(NoCompression));
+        assert_eq!(custom.backend().unwrap().name(), "none");
+        let registry = SecondaryRegistry::empty();
+        assert_eq!(registry.describe(VCD_LZMA_ID), "lzma");
+        assert_eq!(registry.describe(200), "unknown (id=200)");
+    }
+
+    #[test]
+    fn registry_builtins_and_custom_ids() {
+        let mut registry = SecondaryRegistry::new();
+        #[cfg(feature = "lzma-secondary")]
+        assert_eq!(registry.get(Some(VCD_LZMA_ID)).unwrap().id(), VCD_LZMA_ID);
+        #[cfg(feature = "zlib-secondary")]
+        assert_eq!(registry.get(Some(VCD_ZLIB_ID)).unwrap().id(), VCD_ZLIB_ID);
+        assert_eq!(registry.get(Some(VCD_FGK_ID)).unwrap().id(), VCD_FGK_ID);
+        assert!(matches!(
+            registry.get(None),
+            Err(DecodeError::InvalidInput(_))
+        ));
+
+        match registry.get(Some(77)) {
+            Err(DecodeError::Unsupported(msg)) => assert!(msg.contains("77"), "{msg}"),
+            Err(other) => panic!("expected Unsupported, got {other:?}"),
+            Ok(_) => panic!("ID 77 should not be registered"),
+        }
+        registry.register(77, Arc::new(NoCompression));
+        assert!(registry.get(Some(77)).is_ok());
+        assert!(SecondaryRegistry::empty().get(Some(VCD_LZMA_ID)).is_err());
+
+        let (d, i, a) = decompress_sections_with(
+            &registry,
+            b"data",
+            b"inst",
+            b"addr",
+            VCD_DATACOMP,
+            Some(77),
+            NO_LIMITS,
+        )
+        .unwrap();
+        assert_eq!(
+            (&d[..], &i[..], &a[..]),
+            (&b"data"[..], &b"inst"[..], &b"addr"[..])
+        );
+    }
+
+    #[test]
+    fn secondary_compression_enum() {
+        assert!(!SecondaryCompression::None.is_enabled());
+        assert!(SecondaryCompression::None.backend().is_none());
+
+        #[cfg(feature = "lzma-secondary")]
+        {
+            assert!(SecondaryCompression::Lzma.is_enabled());
+            let b = SecondaryCompression::Lzma.backend().unwrap();
+            assert_eq!(b.id(), VCD_LZMA_ID);
+        }
+
+        #[cfg(feature = "zlib-secondary")]
+        {
+            let zlib = SecondaryCompression::Zlib { level: 6 };
+            assert!(zlib.is_enabled());
+            let b = zlib.backend().unwrap();
+            assert_eq!(b.id(), VCD_ZLIB_ID);
+        }
+    }
+
+    #[test]
+    fn custom_backend() {
+        struct TestBackend;
+        impl CompressBackend for TestBackend {
+            fn id(&self) -> u8 {
+                42
+            }
+            fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+                // Trivial: reverse the bytes
+                Ok(data.iter().rev().copied().collect())
+            }
+            fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, DecodeError> {
+                Ok(data.iter().rev().copied().collect())
+            }
+        }
+
+        let backend = TestBackend;
+        let data = b"hello world";
+        let compressed = backend.compress(data).unwrap();
+        let decompressed = backend.decompress(&compressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(backend.id(), 42);
+    }
+
+    #[cfg(all(feature = "lzma-secondary", feature = "zlib-secondary"))]
+    #[test]
+    fn zlib_vs_lzma_comparison() {
+        let data: Vec<u8> = b"ABCDEFGHIJKLMNOP"
+            .iter()
+            .copied()
+            .cycle()
+            .take(4096)
+            .collect();
+
+        let lzma = LzmaBackend;
+        let zlib = ZlibBackend::default();
+
+        let lzma_compressed = lzma.compress(&data).unwrap();
+        let zlib_compressed = zlib.compress(&data).unwrap();
+
+        // Both should compress well.
+        assert!(lzma_compressed.len() < data.len());
+        assert!(zlib_compressed.len() < data.len());
+
+        // Both should roundtrip correctly.
+        assert_eq!(lzma.decompress(&lzma_compressed, usize::MAX).unwrap(), data);
+        assert_eq!(zlib.decompress(&zlib_compressed, usize::MAX).unwrap(), data);
+    }
+}
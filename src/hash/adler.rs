@@ -0,0 +1,125 @@
+// Rolling Adler-32 over a fixed-length window.
+//
+// Yields the same value as the per-window VCDIFF checksum (RFC 1950
+// Adler-32) for the bytes currently in the window, so callers can compare
+// regions of two files block by block without re-hashing each block.
+
+const MOD_ADLER: u32 = 65521;
+
+/// Adler-32 that can slide along the input one byte at a time.
+///
+/// Feed the first `window_len` bytes with [`update`](Self::update), then
+/// call [`roll`](Self::roll) for each further byte. [`digest`](Self::digest)
+/// always equals the Adler-32 of the bytes currently in the window.
+///
+/// ```
+/// use oxidelta::hash::adler::RollingAdler32;
+/// let data = b"abcdefgh";
+/// let mut rolling = RollingAdler32::new();
+/// data[..4].iter().for_each(|&b| rolling.update(b));
+/// rolling.roll(data[0], data[4], 4);
+///
+/// let mut fresh = RollingAdler32::new();
+/// data[1..5].iter().for_each(|&b| fresh.update(b));
+/// assert_eq!(rolling.digest(), fresh.digest());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollingAdler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for RollingAdler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RollingAdler32 {
+    /// Checksum of the empty window.
+    pub const fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    /// Append `byte` to the window.
+    #[inline]
+    pub fn update(&mut self, byte: u8) {
+        self.a = (self.a + u32::from(byte)) % MOD_ADLER;
+        self.b = (self.b + self.a) % MOD_ADLER;
+    }
+
+    /// Slide a window of `window_len` bytes forward by one: drop `out_byte`
+    /// from the front and append `in_byte`.
+    #[inline]
+    pub fn roll(&mut self, out_byte: u8, in_byte: u8, window_len: usize) {
+        let out = u32::from(out_byte);
+        let len_out = (window_len as u64 % u64::from(MOD_ADLER)) as u32 * out % MOD_ADLER;
+        self.a = (self.a + MOD_ADLER - out + u32::from(in_byte)) % MOD_ADLER;
+        // B' = B - window_len * out - 1 + A'
+        self.b = (self.b + (MOD_ADLER - len_out) + self.a + (MOD_ADLER - 1)) % MOD_ADLER;
+    }
+
+    /// The Adler-32 of the current window.
+    pub const fn digest(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    fn fresh(data: &[u8]) -> u32 {
+        let mut hasher = RollingAdler32::new();
+        data.iter().for_each(|&b| hasher.update(b));
+        hasher.digest()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(RollingAdler32::new().digest(), 1);
+        assert_eq!(fresh(b"Wikipedia"), 0x11E6_0398);
+        // Large enough for both sums to wrap the modulus.
+        #[cfg(feature = "adler32")]
+        {
+            let data = vec![0xFFu8; 100_000];
+            assert_eq!(fresh(&data), simd_adler32::adler32(&data.as_slice()));
+        }
+    }
+
+    #[test]
+    fn rolling_equals_fresh_digest() {
+        let mut data = XorShift32::new(0x2545_F491).bytes(20_000);
+        data.extend(std::iter::repeat_n(0xFF, 10_000));
+
+        for window_len in [1, 16, 4096, 10_000] {
+            let mut rolling = RollingAdler32::new();
+            data[..window_len].iter().for_each(|&b| rolling.update(b));
+            for start in 1..=data.len() - window_len {
+                rolling.roll(data[start - 1], data[start + window_len - 1], window_len);
+                if start % 997 == 0 || start == data.len() - window_len {
+                    assert_eq!(
+                        rolling.digest(),
+                        fresh(&data[start..start + window_len]),
+                        "window_len {window_len} at {start}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_vcdiff_window_checksum() {
+        use crate::vcdiff::header::{FileHeader, WindowHeader};
+
+        let target: Vec<u8> = (0..5000u32).map(|i| (i * 31 % 253) as u8).collect();
+        let mut delta = Vec::new();
+        crate::compress::encoder::encode_all(&mut delta, &[], &target, Default::default()).unwrap();
+
+        let mut cursor = std::io::Cursor::new(delta.as_slice());
+        FileHeader::decode(&mut cursor).unwrap();
+        let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
+        assert_eq!(wh.adler32, Some(fresh(&target)));
+    }
+}
@@ -1,156 +1,355 @@
-// Matcher profiles matching xdelta3's `xdelta3-cfgs.h`.
-//
-// Each profile defines tuning parameters for the hash/match engine.
-
-/// Minimum COPY length (VCDIFF code table minimum).
-pub const MIN_MATCH: usize = 4;
-
-/// Minimum run worth encoding.
-pub const MIN_RUN: usize = 8;
-
-/// Default input window size (8 MiB).
-pub const DEFAULT_WINSIZE: usize = 1 << 23;
-
-/// Default prev-chain array size (256 KiB).
-pub const DEFAULT_SPREVSZ: usize = 1 << 18;
-
-/// Default source window size (64 MiB).
-pub const DEFAULT_SRCWINSZ: usize = 1 << 26;
-
-/// Maximum LRU block cache entries.
-pub const MAX_LRU_SIZE: usize = 32;
-
-/// Matcher profile configuration.
-///
-/// Field names match xdelta3's `xd3_smatcher` structure.
-#[derive(Debug, Clone, Copy)]
-pub struct MatcherConfig {
-    /// Name for display purposes.
-    pub name: &'static str,
-    /// Large (source) hash window width.
-    pub large_look: usize,
-    /// Large hash step size (bytes between indexed positions).
-    pub large_step: usize,
-    /// Small (target) hash window width (always 4).
-    pub small_look: usize,
-    /// Maximum chain length for small-match search.
-    pub small_chain: usize,
-    /// Maximum chain length for small-match lazy search.
-    pub small_lchain: usize,
-    /// Maximum match length before lazy matching is skipped.
-    pub max_lazy: usize,
-    /// Match length considered "long enough" to stop searching.
-    pub long_enough: usize,
-}
-
-/// Compression levels mapping to profiles (matches xdelta3-main.h).
-///
-/// - Level 0: NOCOMPRESS + fastest
-/// - Level 1: fastest
-/// - Level 2: faster
-/// - Levels 3-5: fast
-/// - Level 6: default
-/// - Levels 7-9: slow
-pub fn config_for_level(level: u32) -> MatcherConfig {
-    match level {
-        0 | 1 => FASTEST,
-        2 => FASTER,
-        3..=5 => FAST,
-        6 => DEFAULT,
-        _ => SLOW,
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Profile definitions (exact match of xdelta3-cfgs.h)
-// ---------------------------------------------------------------------------
-
-pub const FASTEST: MatcherConfig = MatcherConfig {
-    name: "fastest",
-    large_look: 9,
-    large_step: 26,
-    small_look: 4,
-    small_chain: 1,
-    small_lchain: 1,
-    max_lazy: 6,
-    long_enough: 6,
-};
-
-pub const FASTER: MatcherConfig = MatcherConfig {
-    name: "faster",
-    large_look: 9,
-    large_step: 15,
-    small_look: 4,
-    small_chain: 1,
-    small_lchain: 1,
-    max_lazy: 18,
-    long_enough: 18,
-};
-
-pub const FAST: MatcherConfig = MatcherConfig {
-    name: "fast",
-    large_look: 9,
-    large_step: 8,
-    small_look: 4,
-    small_chain: 4,
-    small_lchain: 1,
-    max_lazy: 18,
-    long_enough: 35,
-};
-
-pub const DEFAULT: MatcherConfig = MatcherConfig {
-    name: "default",
-    large_look: 9,
-    large_step: 3,
-    small_look: 4,
-    small_chain: 8,
-    small_lchain: 2,
-    max_lazy: 36,
-    long_enough: 70,
-};
-
-pub const SLOW: MatcherConfig = MatcherConfig {
-    name: "slow",
-    large_look: 9,
-    large_step: 2,
-    small_look: 4,
-    small_chain: 44,
-    small_lchain: 13,
-    max_lazy: 90,
-    long_enough: 70,
-};
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn all_profiles_have_slook_4() {
-        for p in [FASTEST, FASTER, FAST, DEFAULT, SLOW] {
-            assert_eq!(
-                p.small_look, MIN_MATCH,
-                "profile {} has wrong small_look",
-                p.name
-            );
-        }
-    }
-
-    #[test]
-    fn all_profiles_have_llook_9() {
-        for p in [FASTEST, FASTER, FAST, DEFAULT, SLOW] {
-            assert_eq!(p.large_look, 9, "profile {} has wrong large_look", p.name);
-        }
-    }
-
-    #[test]
-    fn level_mapping() {
-        assert_eq!(config_for_level(0).name, "fastest");
-        assert_eq!(config_for_level(1).name, "fastest");
-        assert_eq!(config_for_level(2).name, "faster");
-        assert_eq!(config_for_level(3).name, "fast");
-        assert_eq!(config_for_level(5).name, "fast");
-        assert_eq!(config_for_level(6).name, "default");
-        assert_eq!(config_for_level(7).name, "slow");
-        assert_eq!(config_for_level(9).name, "slow");
-    }
-}
+// Matcher profiles matching xdelta3's `xdelta3-cfgs.h`.
+//
+// Each profile defines tuning parameters for the hash/match engine.
+
+/// Minimum COPY length (VCDIFF code table minimum).
+pub const MIN_MATCH: usize = 4;
+
+/// Minimum run worth encoding.
+pub const MIN_RUN: usize = 8;
+
+/// Default input window size (8 MiB).
+pub const DEFAULT_WINSIZE: usize = 1 << 23;
+
+/// Default prev-chain array size (256 KiB).
+pub const DEFAULT_SPREVSZ: usize = 1 << 18;
+
+/// Default source window size (64 MiB).
+pub const DEFAULT_SRCWINSZ: usize = 1 << 26;
+
+/// Maximum LRU block cache entries.
+pub const MAX_LRU_SIZE: usize = 32;
+
+/// Matcher profile configuration.
+///
+/// Field names match xdelta3's `xd3_smatcher` structure.
+#[derive(Debug, Clone, Copy)]
+pub struct MatcherConfig {
+    /// Name for display purposes.
+    pub name: &'static str,
+    /// Large (source) hash window width.
+    pub large_look: usize,
+    /// Large hash step size (bytes between indexed positions).
+    pub large_step: usize,
+    /// Small (target) hash window width (always 4).
+    pub small_look: usize,
+    /// Maximum chain length for small-match search.
+    pub small_chain: usize,
+    /// Maximum chain length for small-match lazy search.
+    pub small_lchain: usize,
+    /// Maximum match length before lazy matching is skipped.
+    pub max_lazy: usize,
+    /// Match length considered "long enough" to stop searching.
+    pub long_enough: usize,
+}
+
+impl MatcherConfig {
+    /// Start a custom profile from the `default` profile's parameters.
+    pub fn builder() -> MatcherConfigBuilder {
+        MatcherConfigBuilder::new(DEFAULT)
+    }
+
+    /// Check the invariants the match engine relies on.
+    pub fn validate(&self) -> Result<(), MatcherConfigError> {
+        if self.small_look != MIN_MATCH {
+            return Err(MatcherConfigError::SmallLook(self.small_look));
+        }
+        if self.large_look < MIN_MATCH {
+            return Err(MatcherConfigError::LargeLook(self.large_look));
+        }
+        if self.large_step == 0 {
+            return Err(MatcherConfigError::LargeStep);
+        }
+        if self.small_chain == 0 {
+            return Err(MatcherConfigError::SmallChain);
+        }
+        if self.small_lchain == 0 || self.small_lchain > self.small_chain {
+            return Err(MatcherConfigError::SmallLazyChain {
+                small_lchain: self.small_lchain,
+                small_chain: self.small_chain,
+            });
+        }
+        if self.long_enough < MIN_MATCH {
+            return Err(MatcherConfigError::LongEnough(self.long_enough));
+        }
+        Ok(())
+    }
+}
+
+/// Builder for custom matcher profiles, validated by [`build`].
+///
+/// ```
+/// use oxidelta::hash::config::MatcherConfig;
+/// let config = MatcherConfig::builder()
+///     .large_step(4)
+///     .small_chain(16)
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.name, "custom");
+/// ```
+///
+/// [`build`]: MatcherConfigBuilder::build
+#[derive(Debug, Clone, Copy)]
+pub struct MatcherConfigBuilder {
+    config: MatcherConfig,
+}
+
+impl MatcherConfigBuilder {
+    /// Start from an existing profile's parameters.
+    pub fn new(base: MatcherConfig) -> Self {
+        Self {
+            config: MatcherConfig {
+                name: "custom",
+                ..base
+            },
+        }
+    }
+
+    /// Large (source) hash window width; at least [`MIN_MATCH`].
+    pub fn large_look(mut self, large_look: usize) -> Self {
+        self.config.large_look = large_look;
+        self
+    }
+
+    /// Bytes between indexed source positions; at least 1.
+    pub fn large_step(mut self, large_step: usize) -> Self {
+        self.config.large_step = large_step;
+        self
+    }
+
+    /// Maximum chain length for small-match search; at least 1.
+    pub fn small_chain(mut self, small_chain: usize) -> Self {
+        self.config.small_chain = small_chain;
+        self
+    }
+
+    /// Maximum chain length for lazy search; 1 to `small_chain`.
+    pub fn small_lchain(mut self, small_lchain: usize) -> Self {
+        self.config.small_lchain = small_lchain;
+        self
+    }
+
+    /// Match length at which lazy matching is skipped; 0 disables it.
+    pub fn max_lazy(mut self, max_lazy: usize) -> Self {
+        self.config.max_lazy = max_lazy;
+        self
+    }
+
+    /// Match length that ends the chain search; at least [`MIN_MATCH`].
+    pub fn long_enough(mut self, long_enough: usize) -> Self {
+        self.config.long_enough = long_enough;
+        self
+    }
+
+    /// Validate and return the profile.
+    pub fn build(self) -> Result<MatcherConfig, MatcherConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// A matcher profile parameter outside the range the engine supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatcherConfigError {
+    /// `small_look` is not [`MIN_MATCH`].
+    SmallLook(usize),
+    /// `large_look` is below [`MIN_MATCH`].
+    LargeLook(usize),
+    /// `large_step` is zero.
+    LargeStep,
+    /// `small_chain` is zero.
+    SmallChain,
+    /// `small_lchain` is zero or exceeds `small_chain`.
+    SmallLazyChain {
+        small_lchain: usize,
+        small_chain: usize,
+    },
+    /// `long_enough` is below [`MIN_MATCH`].
+    LongEnough(usize),
+}
+
+impl std::fmt::Display for MatcherConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SmallLook(v) => write!(f, "small_look must be {MIN_MATCH}, got {v}"),
+            Self::LargeLook(v) => write!(f, "large_look must be at least {MIN_MATCH}, got {v}"),
+            Self::LargeStep => f.write_str("large_step must be at least 1"),
+            Self::SmallChain => f.write_str("small_chain must be at least 1"),
+            Self::SmallLazyChain {
+                small_lchain,
+                small_chain,
+            } => write!(
+                f,
+                "small_lchain must be between 1 and small_chain ({small_chain}), got {small_lchain}"
+            ),
+            Self::LongEnough(v) => write!(f, "long_enough must be at least {MIN_MATCH}, got {v}"),
+        }
+    }
+}
+
+impl std::error::Error for MatcherConfigError {}
+
+/// Compression levels mapping to profiles (matches xdelta3-main.h).
+///
+/// - Level 0: NOCOMPRESS + fastest
+/// - Level 1: fastest
+/// - Level 2: faster
+/// - Levels 3-5: fast
+/// - Level 6: default
+/// - Levels 7-9: slow
+pub fn config_for_level(level: u32) -> MatcherConfig {
+    match level {
+        0 | 1 => FASTEST,
+        2 => FASTER,
+        3..=5 => FAST,
+        6 => DEFAULT,
+        _ => SLOW,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Profile definitions (exact match of xdelta3-cfgs.h)
+// ---------------------------------------------------------------------------
+
+pub const FASTEST: MatcherConfig = MatcherConfig {
+    name: "fastest",
+    large_look: 9,
+    large_step: 26,
+    small_look: 4,
+    small_chain: 1,
+    small_lchain: 1,
+    max_lazy: 6,
+    long_enough: 6,
+};
+
+pub const FASTER: MatcherConfig = MatcherConfig {
+    name: "faster",
+    large_look: 9,
+    large_step: 15,
+    small_look: 4,
+    small_chain: 1,
+    small_lchain: 1,
+    max_lazy: 18,
+    long_enough: 18,
+};
+
+pub const FAST: MatcherConfig = MatcherConfig {
+    name: "fast",
+    large_look: 9,
+    large_step: 8,
+    small_look: 4,
+    small_chain: 4,
+    small_lchain: 1,
+    max_lazy: 18,
+    long_enough: 35,
+};
+
+pub const DEFAULT: MatcherConfig = MatcherConfig {
+    name: "default",
+    large_look: 9,
+    large_step: 3,
+    small_look: 4,
+    small_chain: 8,
+    small_lchain: 2,
+    max_lazy: 36,
+    long_enough: 70,
+};
+
+pub const SLOW: MatcherConfig = MatcherConfig {
+    name: "slow",
+    large_look: 9,
+    large_step: 2,
+    small_look: 4,
+    small_chain: 44,
+    small_lchain: 13,
+    max_lazy: 90,
+    long_enough: 70,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_profiles_have_slook_4() {
+        for p in [FASTEST, FASTER, FAST, DEFAULT, SLOW] {
+            assert_eq!(
+                p.small_look, MIN_MATCH,
+                "profile {} has wrong small_look",
+                p.name
+            );
+        }
+    }
+
+    #[test]
+    fn all_profiles_have_llook_9() {
+        for p in [FASTEST, FASTER, FAST, DEFAULT, SLOW] {
+            assert_eq!(p.large_look, 9, "profile {} has wrong large_look", p.name);
+        }
+    }
+
+    #[test]
+    fn level_mapping() {
+        assert_eq!(config_for_level(0).name, "fastest");
+        assert_eq!(config_for_level(1).name, "fastest");
+        assert_eq!(config_for_level(2).name, "faster");
+        assert_eq!(config_for_level(3).name, "fast");
+        assert_eq!(config_for_level(5).name, "fast");
+        assert_eq!(config_for_level(6).name, "default");
+        assert_eq!(config_for_level(7).name, "slow");
+        assert_eq!(config_for_level(9).name, "slow");
+    }
+
+    #[test]
+    fn builtin_profiles_validate() {
+        for p in [FASTEST, FASTER, FAST, DEFAULT, SLOW] {
+            assert_eq!(p.validate(), Ok(()), "profile {}", p.name);
+        }
+    }
+
+    #[test]
+    fn builder_enforces_invariants() {
+        let config = MatcherConfig::builder()
+            .large_look(16)
+            .large_step(5)
+            .small_chain(20)
+            .small_lchain(4)
+            .max_lazy(0)
+            .long_enough(100)
+            .build()
+            .unwrap();
+        assert_eq!(config.name, "custom");
+        assert_eq!(
+            (config.large_look, config.large_step, config.small_look),
+            (16, 5, MIN_MATCH)
+        );
+        assert_eq!((config.small_chain, config.small_lchain), (20, 4));
+        assert_eq!((config.max_lazy, config.long_enough), (0, 100));
+
+        let b = MatcherConfigBuilder::new(SLOW);
+        assert_eq!(b.build().unwrap().small_chain, SLOW.small_chain);
+        assert_eq!(
+            b.large_look(3).build().unwrap_err(),
+            MatcherConfigError::LargeLook(3)
+        );
+        assert_eq!(
+            b.large_step(0).build().unwrap_err(),
+            MatcherConfigError::LargeStep
+        );
+        assert_eq!(
+            b.small_chain(0).build().unwrap_err(),
+            MatcherConfigError::SmallChain
+        );
+        assert_eq!(
+            b.small_chain(2).small_lchain(3).build().unwrap_err(),
+            MatcherConfigError::SmallLazyChain {
+                small_lchain: 3,
+                small_chain: 2
+            }
+        );
+        assert_eq!(
+            b.long_enough(2).build().unwrap_err(),
+            MatcherConfigError::LongEnough(2)
+        );
+    }
+}
@@ -1,984 +1,1951 @@
-// Block matching algorithm for delta compression.
-//
-// Implements the core xdelta3 string-matching loop:
-//   1. Run-length detection
-//   2. Large (source) match via Rabin-Karp hash
-//   3. Small (target self) match via 4-byte hash + chaining
-//   4. Lazy matching for improved compression
-//   5. Greedy forward/backward match extension
-
-use super::config::{MIN_MATCH, MIN_RUN, MatcherConfig};
-use super::rolling::{self, LargeHash};
-use super::table::{LargeTable, SmallTable};
-use crate::vcdiff::code_table::Instruction;
-
-// ---------------------------------------------------------------------------
-// Match result
-// ---------------------------------------------------------------------------
-
-/// A match found by the engine, to be turned into an Instruction.
-#[derive(Debug, Clone, Copy)]
-pub struct Match {
-    /// Position in the target where the match starts.
-    pub target_pos: usize,
-    /// Length of the match.
-    pub length: usize,
-    /// If source match: absolute offset in source.
-    /// If target self-match: position in target.
-    pub addr: u64,
-    /// True if this is a source copy, false if target self-copy.
-    pub is_source: bool,
-}
-
-// ---------------------------------------------------------------------------
-// Source provider trait for the engine
-// ---------------------------------------------------------------------------
-
-/// Provides source data for match extension against source blocks.
-pub trait SourceData {
-    /// Total source length.
-    fn len(&self) -> u64;
-    /// Whether source contains no bytes.
-    fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
-    /// Read bytes from the source at the given offset.
-    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize;
-    /// Direct slice access (for in-memory sources).
-    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]>;
-}
-
-impl SourceData for &[u8] {
-    fn len(&self) -> u64 {
-        <[u8]>::len(self) as u64
-    }
-    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
-        let off = offset as usize;
-        if off >= <[u8]>::len(self) {
-            return 0;
-        }
-        let avail = &self[off..];
-        let n = buf.len().min(avail.len());
-        buf[..n].copy_from_slice(&avail[..n]);
-        n
-    }
-    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
-        let off = offset as usize;
-        if off + len <= <[u8]>::len(self) {
-            Some(&self[off..off + len])
-        } else {
-            None
-        }
-    }
-}
-
-impl SourceData for Vec<u8> {
-    fn len(&self) -> u64 {
-        <[u8]>::len(self) as u64
-    }
-    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
-        let s: &[u8] = self;
-        s.get_bytes(offset, buf)
-    }
-    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
-        let off = offset as usize;
-        if off + len <= <[u8]>::len(self) {
-            Some(&self[off..off + len])
-        } else {
-            None
-        }
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Match engine
-// ---------------------------------------------------------------------------
-
-/// The delta compression match engine.
-///
-/// Scans the input (target) data, finding matches against the source and
-/// against earlier parts of the target.  Produces a sequence of
-/// `Instruction` values (ADD, COPY, RUN) ready for VCDIFF encoding.
-pub struct MatchEngine {
-    config: MatcherConfig,
-    large_hash: LargeHash,
-    large_table: LargeTable,
-    small_table: SmallTable,
-    /// Previous-position chain size.
-    _sprevsz: usize,
-    /// Source position to try matching at the start of the next window.
-    /// Matches xdelta3's `match_srcpos` / `MATCH_TARGET` mechanism.
-    /// Initially 0, updated when a match extends to the end of a window.
-    pub match_srcpos: u64,
-    /// Cached CPU-dispatched match comparator.
-    forward_match_fn: rolling::MatchFn,
-    /// Cached CPU-dispatched backward comparator.
-    backward_match_fn: rolling::MatchFn,
-    /// Cached CPU-dispatched run scanner.
-    run_length_fn: rolling::RunLengthFn,
-}
-
-impl MatchEngine {
-    /// Create a new engine with the given matcher profile.
-    ///
-    /// `source_len`: total source file length (0 if no source).
-    /// `winsize`: target input window size.
-    pub fn new(config: MatcherConfig, source_len: u64, winsize: usize) -> Self {
-        let large_hash = LargeHash::new(config.large_look);
-
-        // Large table sizing: match xdelta3 `xd3_encode_init`.
-        // It uses source max_winsize / large_step, where max_winsize is
-        // rounded to power-of-two and clamped to at least XD3_ALLOCSIZE.
-        const XD3_ALLOCSIZE: usize = 1 << 14; // 16 KiB
-        let large_slots = if source_len > 0 {
-            let src_len = source_len as usize;
-            let src_max_winsize = src_len
-                .checked_next_power_of_two()
-                .unwrap_or(src_len)
-                .max(XD3_ALLOCSIZE);
-            (src_max_winsize / config.large_step).max(8)
-        } else {
-            8
-        };
-        let large_table = LargeTable::new(large_slots);
-
-        // Small table sizing: one entry per byte of target window.
-        let small_table_slots = winsize;
-
-        // Prev chain: only needed when chain > 1.
-        // Cap to the actual window size to avoid over-allocating for small inputs.
-        let sprevsz = if config.small_chain > 1 || config.small_lchain > 1 {
-            let max = super::config::DEFAULT_SPREVSZ;
-            let capped = winsize.next_power_of_two().min(max);
-            capped.max(16) // minimum 16 entries
-        } else {
-            0
-        };
-
-        let small_table = SmallTable::new(small_table_slots, sprevsz);
-
-        Self {
-            config,
-            large_hash,
-            large_table,
-            small_table,
-            _sprevsz: sprevsz,
-            match_srcpos: 0,
-            forward_match_fn: rolling::forward_match_fn(),
-            backward_match_fn: rolling::backward_match_fn(),
-            run_length_fn: rolling::run_length_fn(),
-        }
-    }
-
-    /// Index source data into the large hash table.
-    ///
-    /// Checksums are inserted in reverse order within the data (matching
-    /// xdelta3's `xd3_srcwin_move_point` which scans blocks backward).
-    /// Last-written wins, so earlier positions take priority.
-    pub fn index_source<S: SourceData>(&mut self, source: &S) {
-        let src_len = source.len() as usize;
-        let look = self.config.large_look;
-        let step = self.config.large_step;
-
-        if src_len < look {
-            return;
-        }
-
-        // Fast path for contiguous in-memory sources (the common case).
-        if let Some(src) = source.as_slice(0, src_len) {
-            let mut pos = src_len - look;
-            loop {
-                let cksum = self.large_hash.checksum(&src[pos..]);
-                self.large_table.insert(cksum, pos as u64);
-                if pos < step {
-                    break;
-                }
-                pos -= step;
-            }
-            return;
-        }
-
-        // Process in chunks that fit in memory (like xdelta3 block processing).
-        let chunk_size = 1 << 20; // 1 MiB chunks
-        let mut offset = 0usize;
-
-        while offset < src_len {
-            let end = (offset + chunk_size).min(src_len);
-            let chunk_len = end - offset;
-
-            if chunk_len < look {
-                break;
-            }
-
-            if let Some(chunk) = source.as_slice(offset as u64, chunk_len) {
-                // Index in reverse (last-written = earliest position wins).
-                let mut pos = chunk_len - look;
-                loop {
-                    let cksum = self.large_hash.checksum(&chunk[pos..]);
-                    self.large_table.insert(cksum, (offset + pos) as u64);
-
-                    if pos < step {
-                        break;
-                    }
-                    pos -= step;
-                }
-            }
-
-            offset = end;
-        }
-    }
-
-    /// Find all matches in `target` against `source` and earlier target data.
-    ///
-    /// Returns a list of instructions (ADD, COPY, RUN) covering the full target.
-    pub fn find_matches<S: SourceData>(
-        &mut self,
-        target: &[u8],
-        source: Option<&S>,
-    ) -> Vec<Instruction> {
-        let do_large = source.is_some();
-        let do_small = true; // always do target self-matching
-        let target_len = target.len();
-        let use_prefetch = target_len >= (1 << 18);
-        let slook = self.config.small_look;
-        let llook = self.config.large_look;
-        let source_len = source.map_or(0u64, |s| s.len());
-        let source_contiguous = source.and_then(|s| s.as_slice(0, s.len() as usize));
-        let run_length = self.run_length_fn;
-        let forward_match = self.forward_match_fn;
-
-        self.small_table.reset();
-
-        let mut matches: Vec<Match> = Vec::with_capacity((target_len / 32).max(16));
-        let mut input_pos: usize = 0;
-        let mut min_match = MIN_MATCH;
-
-        // Run-length state.
-        let mut run_l: usize;
-        let mut run_c: u8;
-
-        // Checksum state.
-        let mut scksum: u32;
-        let mut lcksum: u64 = 0;
-
-        if target_len < slook {
-            return Self::emit_add_all(target);
-        }
-
-        // Initialize checksums at position 0.
-        scksum = rolling::small_cksum(target);
-        let (rl, rc) = rolling::comprun(target, slook);
-        run_l = rl;
-        run_c = rc;
-
-        if do_large && target_len >= llook {
-            lcksum = self.large_hash.checksum(target);
-        }
-
-        // --- Initial match probe (MATCH_TARGET) ---
-        // Matches xdelta3's MATCH_TARGET mechanism: before the main loop,
-        // try a forward match from match_srcpos (initially 0) against the
-        // start of the target. This catches matches at source positions
-        // not covered by the large hash table's step-based indexing.
-        if let Some(src) = source_contiguous {
-            let src_pos = self.match_srcpos as usize;
-            if src_pos < src.len() {
-                let max_fwd = target_len.min(src.len() - src_pos);
-                if max_fwd >= MIN_MATCH {
-                    let fwd_len = forward_match(&src[src_pos..], target, max_fwd);
-                    if fwd_len >= MIN_MATCH {
-                        matches.push(Match {
-                            target_pos: 0,
-                            length: fwd_len,
-                            addr: src_pos as u64,
-                            is_source: true,
-                        });
-                        input_pos = fwd_len;
-                        if fwd_len == target_len {
-                            self.match_srcpos = src_pos as u64 + fwd_len as u64;
-                        }
-                        if input_pos + slook <= target_len {
-                            scksum = rolling::small_cksum(&target[input_pos..]);
-                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
-                            run_l = rl2;
-                            run_c = rc2;
-                            if do_large && input_pos + llook <= target_len {
-                                lcksum = self.large_hash.checksum(&target[input_pos..]);
-                            }
-                        }
-                    }
-                }
-            }
-        } else if let Some(src) = source {
-            let src_pos = self.match_srcpos;
-            if (src_pos as usize) < src.len() as usize {
-                let max_fwd = target_len.min((src.len() - src_pos) as usize);
-                if max_fwd >= MIN_MATCH {
-                    let fwd_len = if let Some(src_slice) = src.as_slice(src_pos, max_fwd) {
-                        forward_match(src_slice, target, max_fwd)
-                    } else {
-                        0
-                    };
-                    if fwd_len >= MIN_MATCH {
-                        matches.push(Match {
-                            target_pos: 0,
-                            length: fwd_len,
-                            addr: src_pos,
-                            is_source: true,
-                        });
-                        input_pos = fwd_len;
-                        // If the match extends to the end of the target,
-                        // set match_srcpos for the next window.
-                        if fwd_len == target_len {
-                            self.match_srcpos = src_pos + fwd_len as u64;
-                        }
-                        // Re-initialize checksums at the new position.
-                        if input_pos + slook <= target_len {
-                            scksum = rolling::small_cksum(&target[input_pos..]);
-                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
-                            run_l = rl2;
-                            run_c = rc2;
-                            if do_large && input_pos + llook <= target_len {
-                                lcksum = self.large_hash.checksum(&target[input_pos..]);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        loop {
-            if input_pos + slook > target_len {
-                break;
-            }
-
-            if use_prefetch && do_small {
-                self.small_table.prefetch_bucket(scksum as u64);
-            }
-            if use_prefetch && do_large && input_pos + llook <= target_len {
-                self.large_table.prefetch_bucket(lcksum);
-            }
-
-            // Matches xdelta3 HANDLELAZY behavior: after setting min_match
-            // for lazy search, the next iteration advances by one byte without
-            // decrementing min_match first.
-            let mut skip_min_match_decay = false;
-
-            // --- 1. Try RUN ---
-            if run_l == slook {
-                // Expand run forward (SIMD-accelerated).
-                let remaining = target_len - input_pos - run_l;
-                let total_run = run_l + run_length(&target[input_pos + run_l..], run_c, remaining);
-                if total_run >= min_match && total_run >= MIN_RUN {
-                    matches.push(Match {
-                        target_pos: input_pos,
-                        length: total_run,
-                        addr: 0,
-                        is_source: false,
-                    });
-                    // Mark as RUN (addr=u64::MAX sentinel).
-                    matches.last_mut().unwrap().addr = u64::MAX;
-
-                    if !try_lazy(total_run, self.config.max_lazy, input_pos, target_len) {
-                        input_pos += total_run;
-                        min_match = MIN_MATCH;
-                        if input_pos + slook <= target_len {
-                            scksum = rolling::small_cksum(&target[input_pos..]);
-                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
-                            run_l = rl2;
-                            run_c = rc2;
-                            if do_large && input_pos + llook <= target_len {
-                                lcksum = self.large_hash.checksum(&target[input_pos..]);
-                            }
-                        }
-                        continue;
-                    }
-                    min_match = total_run;
-                    skip_min_match_decay = true;
-                    // Fall through to advance by 1 (lazy).
-                }
-            }
-
-            // --- 2. Try LARGE (source) match ---
-            if do_large
-                && input_pos + llook <= target_len
-                && let Some(src_pos) = self.large_table.lookup(lcksum)
-            {
-                let m = if let Some(src) = source_contiguous {
-                    self.extend_source_match_slice(target, src, input_pos, src_pos)
-                } else if let Some(src) = source {
-                    self.extend_source_match(target, src, input_pos, src_pos, source_len)
-                } else {
-                    None
-                };
-
-                if let Some(m) = m {
-                    // Match xdelta3: source matches are accepted based on
-                    // forward extension length (match_fwd), not total (back+fwd).
-                    let back_len = input_pos - m.target_pos;
-                    let fwd_len = m.length - back_len;
-                    if fwd_len >= min_match {
-                        // Erase any previous matches that this backward-extended
-                        // match now covers (iopt-style erasure).
-                        if back_len > 0 {
-                            while let Some(last) = matches.last() {
-                                if last.target_pos >= m.target_pos {
-                                    matches.pop();
-                                } else {
-                                    break;
-                                }
-                            }
-                        }
-
-                        matches.push(m);
-                        if !try_lazy(fwd_len, self.config.max_lazy, input_pos, target_len) {
-                            // Advance past the forward part only.
-                            // Match covers [input_pos - back_len, input_pos + fwd_len).
-                            input_pos += fwd_len;
-                            min_match = MIN_MATCH;
-                            if input_pos + slook <= target_len {
-                                scksum = rolling::small_cksum(&target[input_pos..]);
-                                let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
-                                run_l = rl2;
-                                run_c = rc2;
-                                if do_large && input_pos + llook <= target_len {
-                                    lcksum = self.large_hash.checksum(&target[input_pos..]);
-                                }
-                            }
-                            continue;
-                        }
-                        min_match = fwd_len;
-                        skip_min_match_decay = true;
-                    }
-                }
-            }
-
-            // --- 3. Try SMALL (target self) match ---
-            if do_small {
-                let match_result = self.small_match(target, input_pos, scksum, min_match);
-
-                // Always insert current position.
-                self.small_table.insert(scksum as u64, input_pos as u64);
-
-                if let Some(m) = match_result
-                    && m.length >= min_match
-                {
-                    matches.push(m);
-                    if !try_lazy(m.length, self.config.max_lazy, input_pos, target_len) {
-                        input_pos += m.length;
-                        min_match = MIN_MATCH;
-                        if input_pos + slook <= target_len {
-                            scksum = rolling::small_cksum(&target[input_pos..]);
-                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
-                            run_l = rl2;
-                            run_c = rc2;
-                            if do_large && input_pos + llook <= target_len {
-                                lcksum = self.large_hash.checksum(&target[input_pos..]);
-                            }
-                        }
-                        continue;
-                    }
-                    min_match = m.length;
-                    skip_min_match_decay = true;
-                }
-            } else {
-                self.small_table.insert(scksum as u64, input_pos as u64);
-            }
-
-            // --- 4. Advance by 1 (lazy matching or no match found) ---
-            if !skip_min_match_decay && min_match > MIN_MATCH {
-                min_match -= 1;
-            }
-
-            input_pos += 1;
-            if input_pos + slook > target_len {
-                break;
-            }
-
-            // Incremental updates — use unchecked access since we verified bounds above.
-            // Safety: input_pos >= 1, and input_pos + slook <= target.len(),
-            // so target[input_pos..input_pos+4] is valid (slook=4).
-            // small_cksum_at needs ptr to input_pos (4 readable bytes).
-            unsafe {
-                let base_ptr = target.as_ptr().add(input_pos);
-                scksum = rolling::small_cksum_at(base_ptr);
-            }
-
-            // Run update — input_pos + slook - 1 < target_len is guaranteed
-            // since input_pos + slook <= target_len.
-            {
-                let next_byte = target[input_pos + slook - 1];
-                if next_byte == run_c {
-                    run_l += 1;
-                } else {
-                    run_c = next_byte;
-                    run_l = 1;
-                }
-            }
-
-            if do_large && input_pos + llook <= target_len {
-                // Safety: input_pos >= 1, input_pos + llook <= target_len,
-                // so target[input_pos-1..input_pos-1+llook+1] is valid.
-                unsafe {
-                    let base_ptr = target.as_ptr().add(input_pos - 1);
-                    lcksum = self.large_hash.update_at(lcksum, base_ptr);
-                }
-            }
-        }
-
-        // Convert matches to instructions.
-        Self::matches_to_instructions(target, source_len, &matches)
-    }
-
-    // -----------------------------------------------------------------------
-    // Small (target) match scanning — matches xd3_smatch
-    // -----------------------------------------------------------------------
-
-    #[inline(always)]
-    fn small_match(
-        &self,
-        target: &[u8],
-        input_pos: usize,
-        _scksum: u32,
-        min_match: usize,
-    ) -> Option<Match> {
-        let scksum = _scksum as u64;
-        let head = self.small_table.lookup(scksum)?;
-        let head = head as usize;
-
-        let is_lazy = min_match > MIN_MATCH;
-        let max_chain = if is_lazy {
-            self.config.small_lchain
-        } else {
-            self.config.small_chain
-        };
-
-        let mut best_len = 0usize;
-        let mut best_offset = 0usize;
-        let mut base = head;
-        let mut chain = max_chain;
-
-        loop {
-            // Compare target[base..] with target[input_pos..].
-            let max_cmp = target.len() - input_pos;
-            let ref_start = base;
-            let inp_start = input_pos;
-
-            if ref_start >= inp_start {
-                break; // can't copy from future
-            }
-
-            // VCDIFF target COPY allows overlap, so small matches are allowed
-            // to extend all the way to end-of-input (matches xdelta3).
-            let cmp_len_limit = max_cmp;
-
-            let cmp_len =
-                (self.forward_match_fn)(&target[ref_start..], &target[inp_start..], cmp_len_limit);
-
-            if cmp_len > best_len {
-                best_len = cmp_len;
-                best_offset = base;
-
-                if cmp_len >= self.config.long_enough || inp_start + cmp_len >= target.len() {
-                    break;
-                }
-            }
-
-            chain -= 1;
-            if chain == 0 {
-                break;
-            }
-
-            // Walk chain.
-            match self.small_table.chain_prev(base as u64, input_pos as u64) {
-                Some(prev) => base = prev as usize,
-                None => break,
-            }
-        }
-
-        if best_len < MIN_MATCH {
-            return None;
-        }
-
-        // Efficiency filter: reject short matches with expensive addresses.
-        // Matches xdelta3's filter in xd3_smatch.
-        let distance = input_pos - best_offset;
-        if best_len == 4 && distance >= 1 << 14 {
-            return None;
-        }
-        if best_len == 5 && distance >= 1 << 21 {
-            return None;
-        }
-
-        Some(Match {
-            target_pos: input_pos,
-            length: best_len,
-            addr: best_offset as u64,
-            is_source: false,
-        })
-    }
-
-    // -----------------------------------------------------------------------
-    // Source match extension — matches xd3_source_extend_match
-    // -----------------------------------------------------------------------
-
-    #[inline(always)]
-    fn extend_source_match_slice(
-        &self,
-        target: &[u8],
-        source: &[u8],
-        input_pos: usize,
-        src_pos: u64,
-    ) -> Option<Match> {
-        let src_pos = src_pos as usize;
-        if src_pos >= source.len() {
-            return None;
-        }
-
-        let max_fwd = (target.len() - input_pos).min(source.len() - src_pos);
-        let fwd_len = (self.forward_match_fn)(&source[src_pos..], &target[input_pos..], max_fwd);
-        if fwd_len < MIN_MATCH {
-            return None;
-        }
-
-        let max_back = input_pos.min(src_pos);
-        let back_len = if max_back > 0 {
-            (self.backward_match_fn)(
-                &source[src_pos - max_back..src_pos],
-                &target[input_pos - max_back..input_pos],
-                max_back,
-            )
-        } else {
-            0
-        };
-
-        Some(Match {
-            target_pos: input_pos - back_len,
-            length: back_len + fwd_len,
-            addr: (src_pos - back_len) as u64,
-            is_source: true,
-        })
-    }
-
-    fn extend_source_match<S: SourceData>(
-        &self,
-        target: &[u8],
-        source: &S,
-        input_pos: usize,
-        src_pos: u64,
-        source_len: u64,
-    ) -> Option<Match> {
-        // Forward extension.
-        let max_fwd = target.len() - input_pos;
-        let src_avail = (source_len - src_pos) as usize;
-        let max_fwd = max_fwd.min(src_avail);
-
-        let fwd_len = if let Some(src_slice) = source.as_slice(src_pos, max_fwd) {
-            (self.forward_match_fn)(src_slice, &target[input_pos..], max_fwd)
-        } else {
-            // Fallback for non-contiguous sources.
-            let mut buf = [0u8; 16 * 1024];
-            let mut total = 0;
-            let mut off = 0usize;
-            while off < max_fwd {
-                let chunk = (max_fwd - off).min(buf.len());
-                let n = source.get_bytes(src_pos + off as u64, &mut buf[..chunk]);
-                if n == 0 {
-                    break;
-                }
-                let m = (self.forward_match_fn)(&buf[..n], &target[input_pos + off..], n);
-                total += m;
-                if m < n {
-                    break;
-                }
-                off += n;
-            }
-            total
-        };
-
-        if fwd_len < MIN_MATCH {
-            return None;
-        }
-
-        // Backward extension (SIMD-accelerated when source is contiguous).
-        let max_back = input_pos.min(src_pos as usize);
-        let mut back_len = 0usize;
-        if max_back > 0
-            && let Some(src_slice) = source.as_slice(src_pos - max_back as u64, max_back)
-        {
-            let tgt_slice = &target[input_pos - max_back..input_pos];
-            back_len = (self.backward_match_fn)(src_slice, tgt_slice, max_back);
-        }
-
-        let total_len = back_len + fwd_len;
-        let match_start_target = input_pos - back_len;
-        let match_start_source = src_pos - back_len as u64;
-
-        Some(Match {
-            target_pos: match_start_target,
-            length: total_len,
-            addr: match_start_source,
-            is_source: true,
-        })
-    }
-
-    // -----------------------------------------------------------------------
-    // Convert matches to instructions
-    // -----------------------------------------------------------------------
-
-    fn emit_add_all(target: &[u8]) -> Vec<Instruction> {
-        if target.is_empty() {
-            return Vec::new();
-        }
-        vec![Instruction::Add {
-            len: target.len() as u32,
-        }]
-    }
-
-    fn matches_to_instructions(
-        target: &[u8],
-        source_len: u64,
-        matches: &[Match],
-    ) -> Vec<Instruction> {
-        let mut instructions = Vec::with_capacity(matches.len().saturating_mul(2) + 1);
-        let mut covered_to: usize = 0;
-
-        // Sort matches by target position, preferring longer matches.
-        // For overlapping matches from lazy matching, keep the best.
-        let mut sorted: Vec<Match> = Vec::with_capacity(matches.len());
-        for &m in matches {
-            // Remove matches covered by later, better overlapping ones.
-            while let Some(last) = sorted.last() {
-                if last.target_pos + last.length > m.target_pos && m.length > last.length {
-                    sorted.pop();
-                } else {
-                    break;
-                }
-            }
-            // Only add if not fully covered by previous.
-            if sorted.last().is_none_or(|last| {
-                m.target_pos >= last.target_pos + last.length || m.length > last.length
-            }) {
-                // Trim overlap with previous.
-                sorted.push(m);
-            }
-        }
-
-        for m in &sorted {
-            let m_start = m.target_pos;
-            let m_end = m_start + m.length;
-
-            if m_start < covered_to {
-                continue; // skip fully overlapped
-            }
-
-            // Emit ADD for gap before this match.
-            if m_start > covered_to {
-                instructions.push(Instruction::Add {
-                    len: (m_start - covered_to) as u32,
-                });
-            }
-
-            // Emit the match.
-            if m.addr == u64::MAX {
-                // RUN instruction.
-                instructions.push(Instruction::Run {
-                    len: m.length as u32,
-                });
-            } else if m.is_source {
-                // Source COPY — address is absolute source offset.
-                instructions.push(Instruction::Copy {
-                    len: m.length as u32,
-                    addr: m.addr,
-                    mode: 0,
-                });
-            } else {
-                // Target self-copy — address in combined space = source_len + target_offset.
-                instructions.push(Instruction::Copy {
-                    len: m.length as u32,
-                    addr: source_len + m.addr,
-                    mode: 0,
-                });
-            }
-
-            covered_to = m_end;
-        }
-
-        // Trailing ADD.
-        if covered_to < target.len() {
-            instructions.push(Instruction::Add {
-                len: (target.len() - covered_to) as u32,
-            });
-        }
-
-        instructions
-    }
-}
-
-/// Should we try lazy matching?
-///
-/// Matches xdelta3's `TRYLAZYLEN(LEN, POS, MAX)`:
-///   `max_lazy > 0 && len < max_lazy && pos + len <= avail_in - 2`
-/// The `-2` ensures enough data remains for a lazy match to be worthwhile
-/// (the next match starts at pos+1 and must match at least 2 extra bytes).
-#[inline(always)]
-fn try_lazy(match_len: usize, max_lazy: usize, pos: usize, avail_in: usize) -> bool {
-    max_lazy > 0 && match_len < max_lazy && pos + match_len + 2 <= avail_in
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::hash::config;
-
-    #[test]
-    fn no_source_add_only() {
-        let mut engine = MatchEngine::new(config::FASTEST, 0, 1 << 16);
-        let target = b"Hello, world!";
-        let instructions = engine.find_matches(target, None::<&&[u8]>);
-        assert!(!instructions.is_empty());
-        // Should be a single ADD.
-        match instructions[0] {
-            Instruction::Add { len } => assert_eq!(len, target.len() as u32),
-            _ => panic!("expected ADD"),
-        }
-    }
-
-    #[test]
-    fn source_copy_identical() {
-        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-        let target = source;
-        let src: &[u8] = source;
-
-        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
-        engine.index_source(&src);
-        let instructions = engine.find_matches(target, Some(&src));
-
-        // Should have at least one COPY instruction.
-        let has_copy = instructions
-            .iter()
-            .any(|i| matches!(i, Instruction::Copy { .. }));
-        assert!(
-            has_copy,
-            "expected COPY for identical data: {instructions:?}"
-        );
-    }
-
-    #[test]
-    fn target_self_copy() {
-        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
-        // Repeating pattern — should find self-copies.
-        let target = b"ABCDABCDABCDABCDABCDABCDABCDABCD";
-        let instructions = engine.find_matches(target, None::<&&[u8]>);
-
-        let has_copy = instructions
-            .iter()
-            .any(|i| matches!(i, Instruction::Copy { .. }));
-        assert!(
-            has_copy,
-            "expected self-COPY for repeating data: {instructions:?}"
-        );
-    }
-
-    #[test]
-    fn run_detection() {
-        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
-        let target = vec![0xAA; 100];
-        let instructions = engine.find_matches(&target, None::<&&[u8]>);
-
-        let has_run = instructions
-            .iter()
-            .any(|i| matches!(i, Instruction::Run { .. }));
-        assert!(has_run, "expected RUN for constant data: {instructions:?}");
-    }
-
-    #[test]
-    fn instructions_cover_full_target() {
-        let source = b"The quick brown fox jumps over the lazy dog.";
-        let target = b"The quick brown cat sits on the lazy mat.";
-        let src: &[u8] = source;
-
-        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
-        engine.index_source(&src);
-        let instructions = engine.find_matches(target, Some(&src));
-
-        // Sum of all instruction lengths must equal target length.
-        let total: u32 = instructions
-            .iter()
-            .map(|i| match i {
-                Instruction::Add { len } => *len,
-                Instruction::Copy { len, .. } => *len,
-                Instruction::Run { len } => *len,
-            })
-            .sum();
-        assert_eq!(
-            total,
-            target.len() as u32,
-            "instructions don't cover full target"
-        );
-    }
-
-    #[test]
-    fn small_target_no_panic() {
-        let mut engine = MatchEngine::new(config::FASTEST, 0, 1 << 16);
-        // Target smaller than slook (4 bytes).
-        for len in 0..4 {
-            let target = vec![0x42; len];
-            let insts = engine.find_matches(&target, None::<&&[u8]>);
-            let total: u32 = insts
-                .iter()
-                .map(|i| match i {
-                    Instruction::Add { len } => *len,
-                    Instruction::Copy { len, .. } => *len,
-                    Instruction::Run { len } => *len,
-                })
-                .sum();
-            assert_eq!(total, len as u32);
-        }
-    }
-
-    #[test]
-    fn empty_target() {
-        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
-        let insts = engine.find_matches(b"", None::<&&[u8]>);
-        assert!(insts.is_empty());
-    }
-
-    #[test]
-    fn all_profiles_produce_valid_output() {
-        let source = b"AAAA BBBB CCCC DDDD EEEE FFFF GGGG HHHH";
-        let target = b"AAAA CCCC DDDD EEEE xxxx GGGG HHHH IIII";
-
-        for profile in [
-            config::FASTEST,
-            config::FASTER,
-            config::FAST,
-            config::DEFAULT,
-            config::SLOW,
-        ] {
-            let src: &[u8] = source;
-            let mut engine = MatchEngine::new(profile, src.len() as u64, target.len());
-            engine.index_source(&src);
-            let instructions = engine.find_matches(target, Some(&src));
-
-            let total: u32 = instructions
-                .iter()
-                .map(|i| match i {
-                    Instruction::Add { len } => *len,
-                    Instruction::Copy { len, .. } => *len,
-                    Instruction::Run { len } => *len,
-                })
-                .sum();
-            assert_eq!(
-                total,
-                target.len() as u32,
-                "profile {} produced wrong total length",
-                profile.name
-            );
-        }
-    }
-}
+// Block matching algorithm for delta compression.
+//
+// Implements the core xdelta3 string-matching loop:
+//   1. Run-length detection
+//   2. Large (source) match via Rabin-Karp hash
+//   3. Small (target self) match via 4-byte hash + chaining
+//   4. Lazy matching for improved compression
+//   5. Greedy forward/backward match extension
+
+use std::sync::Arc;
+
+use super::config::{MIN_MATCH, MIN_RUN, MatcherConfig};
+use super::rolling::{self, LargeHash};
+use super::table::{LargeTable, SmallTable, TableLoad};
+use crate::io::{BlockReader, CACHE_BLOCK_SIZE};
+use crate::vcdiff::code_table::Instruction;
+
+// ---------------------------------------------------------------------------
+// Match result
+// ---------------------------------------------------------------------------
+
+/// A match found by the engine, to be turned into an Instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Match {
+    /// Position in the target where the match starts.
+    pub target_pos: usize,
+    /// Length of the match.
+    pub length: usize,
+    /// If source match: absolute offset in source.
+    /// If target self-match: position in target.
+    pub addr: u64,
+    /// True if this is a source copy, false if target self-copy.
+    pub is_source: bool,
+}
+
+impl Match {
+    /// Whether this match should replace `other` where the two overlap.
+    ///
+    /// The longer match wins. Among equal lengths a RUN beats any COPY
+    /// (it needs no address), a source COPY beats a target COPY, and then
+    /// the lower address wins. A full tie keeps `other`, the match found
+    /// first. This is the only tie-break used when matches become
+    /// instructions, so equally valid candidates always resolve the same
+    /// way.
+    fn outranks(&self, other: &Match) -> bool {
+        self.rank() > other.rank()
+    }
+
+    fn rank(&self) -> (usize, u8, core::cmp::Reverse<u64>) {
+        let kind = match (self.addr == u64::MAX, self.is_source) {
+            (true, _) => 2,
+            (false, true) => 1,
+            (false, false) => 0,
+        };
+        (self.length, kind, core::cmp::Reverse(self.addr))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Source provider trait for the engine
+// ---------------------------------------------------------------------------
+
+/// Provides source data for match extension against source blocks.
+pub trait SourceData {
+    /// Total source length.
+    fn len(&self) -> u64;
+    /// Whether source contains no bytes.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Read bytes from the source at the given offset.
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize;
+    /// Direct slice access (for in-memory sources).
+    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]>;
+}
+
+impl SourceData for &[u8] {
+    fn len(&self) -> u64 {
+        <[u8]>::len(self) as u64
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        let off = offset as usize;
+        if off >= <[u8]>::len(self) {
+            return 0;
+        }
+        let avail = &self[off..];
+        let n = buf.len().min(avail.len());
+        buf[..n].copy_from_slice(&avail[..n]);
+        n
+    }
+    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let off = offset as usize;
+        if off + len <= <[u8]>::len(self) {
+            Some(&self[off..off + len])
+        } else {
+            None
+        }
+    }
+}
+
+impl SourceData for Vec<u8> {
+    fn len(&self) -> u64 {
+        <[u8]>::len(self) as u64
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        let s: &[u8] = self;
+        s.get_bytes(offset, buf)
+    }
+    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let off = offset as usize;
+        if off + len <= <[u8]>::len(self) {
+            Some(&self[off..off + len])
+        } else {
+            None
+        }
+    }
+}
+
+/// Pull-based source backed by a caller-provided range fetch.
+///
+/// `fetch(offset, buf)` fills `buf` with source bytes starting at `offset`
+/// and returns the number of bytes written. Useful for sources that are
+/// expensive to load whole but cheap to read in ranges (object storage,
+/// compressed archives). The engine fetches the whole source once while
+/// indexing, then only the regions it extends matches over.
+pub struct FnSource<F> {
+    len: u64,
+    fetch: std::cell::RefCell<F>,
+}
+
+impl<F: FnMut(u64, &mut [u8]) -> usize> FnSource<F> {
+    /// Wrap a range-fetch closure for a source of `len` bytes.
+    pub fn new(len: u64, fetch: F) -> Self {
+        Self {
+            len,
+            fetch: std::cell::RefCell::new(fetch),
+        }
+    }
+}
+
+impl<F: FnMut(u64, &mut [u8]) -> usize> SourceData for FnSource<F> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let n = buf.len().min((self.len - offset) as usize);
+        (self.fetch.borrow_mut())(offset, &mut buf[..n])
+    }
+    fn as_slice(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Default cache budget for [`ReaderSource::new`].
+pub const DEFAULT_READER_CACHE: usize = 8 * 1024 * 1024;
+
+/// Source backed by a seekable reader, with a bounded block cache.
+///
+/// Bytes are read in 64 KiB blocks and kept in a least-recently-used cache
+/// of at most `cache_bytes`; a miss seeks and re-reads the block. Memory use
+/// is therefore bounded however large the source is. A read error ends the
+/// read short, which the engine treats as a non-matching region.
+pub struct ReaderSource<R> {
+    cache: std::cell::RefCell<BlockReader<R>>,
+}
+
+impl<R: std::io::Read + std::io::Seek> ReaderSource<R> {
+    /// Wrap `reader`, a source of `len` bytes, with the default cache budget.
+    pub fn new(reader: R, len: u64) -> Self {
+        Self::with_cache_size(reader, len, DEFAULT_READER_CACHE)
+    }
+
+    /// Wrap `reader` with a cache of at most `cache_bytes` (at least one block).
+    pub fn with_cache_size(reader: R, len: u64, cache_bytes: usize) -> Self {
+        let blocks = cache_bytes / CACHE_BLOCK_SIZE;
+        Self {
+            cache: std::cell::RefCell::new(BlockReader::new(reader, len, blocks)),
+        }
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek> SourceData for ReaderSource<R> {
+    fn len(&self) -> u64 {
+        self.cache.borrow().len()
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        self.cache.borrow_mut().read_at(offset, buf).unwrap_or(0)
+    }
+    fn as_slice(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
+        None
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Match statistics
+// ---------------------------------------------------------------------------
+
+/// Counters describing one `find_matches` call (or, summed, several).
+///
+/// Instruction and byte counts describe the returned instruction list;
+/// `lazy_hits` and `chain_walks` describe the search that produced it.
+/// The hash table fields are the tables' load at the end of the call; when
+/// stats are summed the occupancy and collision-rate helpers average them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    /// COPY instructions reading the source.
+    pub source_copies: u64,
+    /// COPY instructions reading earlier target bytes.
+    pub target_copies: u64,
+    /// RUN instructions.
+    pub runs: u64,
+    /// ADD instructions.
+    pub adds: u64,
+    /// Bytes produced by source COPYs.
+    pub bytes_copied_source: u64,
+    /// Bytes produced by target COPYs.
+    pub bytes_copied_target: u64,
+    /// Bytes produced by RUNs.
+    pub bytes_run: u64,
+    /// Literal bytes produced by ADDs.
+    pub bytes_added: u64,
+    /// Matches accepted while a lazy search for a longer match was active.
+    pub lazy_hits: u64,
+    /// Candidates compared while walking target hash chains.
+    pub chain_walks: u64,
+    /// Load of the source (large) hash table, since it was last indexed.
+    pub large_table: TableLoad,
+    /// Bucket count of the source hash table (0 without a source).
+    pub large_table_slots: u64,
+    /// Load of the target (small) hash table for this window.
+    pub small_table: TableLoad,
+    /// Bucket count of the target hash table (0 without self-matching).
+    pub small_table_slots: u64,
+}
+
+impl MatchStats {
+    /// Count the instructions and bytes of `instructions`, whose COPY
+    /// addresses below `source_len` read the source.
+    pub fn tally(&mut self, instructions: &[Instruction], source_len: u64) {
+        for inst in instructions {
+            match *inst {
+                Instruction::Add { len } => {
+                    self.adds += 1;
+                    self.bytes_added += len as u64;
+                }
+                Instruction::Run { len } => {
+                    self.runs += 1;
+                    self.bytes_run += len as u64;
+                }
+                Instruction::Copy { len, addr, .. } if addr < source_len => {
+                    self.source_copies += 1;
+                    self.bytes_copied_source += len as u64;
+                }
+                Instruction::Copy { len, .. } => {
+                    self.target_copies += 1;
+                    self.bytes_copied_target += len as u64;
+                }
+            }
+        }
+    }
+
+    /// Total target bytes covered by the counted instructions.
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_copied_source + self.bytes_copied_target + self.bytes_run + self.bytes_added
+    }
+
+    /// Fraction of source hash table buckets in use.
+    pub fn large_table_occupancy(&self) -> f64 {
+        ratio(self.large_table.filled, self.large_table_slots)
+    }
+
+    /// Fraction of source hash table inserts that replaced an entry.
+    pub fn large_table_collision_rate(&self) -> f64 {
+        self.large_table.collision_rate()
+    }
+
+    /// Fraction of target hash table buckets in use.
+    pub fn small_table_occupancy(&self) -> f64 {
+        ratio(self.small_table.filled, self.small_table_slots)
+    }
+
+    /// Fraction of target hash table inserts that landed in a used bucket.
+    pub fn small_table_collision_rate(&self) -> f64 {
+        self.small_table.collision_rate()
+    }
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+impl std::ops::AddAssign for MatchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.source_copies += other.source_copies;
+        self.target_copies += other.target_copies;
+        self.runs += other.runs;
+        self.adds += other.adds;
+        self.bytes_copied_source += other.bytes_copied_source;
+        self.bytes_copied_target += other.bytes_copied_target;
+        self.bytes_run += other.bytes_run;
+        self.bytes_added += other.bytes_added;
+        self.lazy_hits += other.lazy_hits;
+        self.chain_walks += other.chain_walks;
+        self.large_table += other.large_table;
+        self.large_table_slots += other.large_table_slots;
+        self.small_table += other.small_table;
+        self.small_table_slots += other.small_table_slots;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Match strategy
+// ---------------------------------------------------------------------------
+
+/// How the encoder finds matches against the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchStrategy {
+    /// xdelta3's hash matching ([`MatchEngine`]): source and target
+    /// self-matches, with the search effort set by the matcher profile.
+    #[default]
+    Hash,
+    /// bsdiff-style longest matches from a suffix array over the whole
+    /// source ([`SuffixArray`](super::suffix::SuffixArray)). Finds short
+    /// matches the sampled hash table misses, which suits targets with many
+    /// small scattered edits, at the cost of O(n log² n) indexing, about
+    /// three words of memory per source byte while indexing and one word per
+    /// byte after. Produces no target self-copies.
+    #[cfg(feature = "suffix-array")]
+    SuffixArray,
+}
+
+// ---------------------------------------------------------------------------
+// Source index
+// ---------------------------------------------------------------------------
+
+/// Source checksums in the large hash table: the part of a [`MatchEngine`]
+/// that depends only on the source.
+///
+/// An index built once can be shared, read-only, by engines matching
+/// different target windows on different threads (see
+/// [`MatchEngine::with_source_index`]); each engine keeps its own small
+/// table for target self-matches.
+#[derive(Clone)]
+pub struct SourceIndex {
+    large_hash: LargeHash,
+    large_table: LargeTable,
+    large_step: usize,
+}
+
+impl SourceIndex {
+    /// An empty index sized for a source of `source_len` bytes.
+    pub fn new(config: &MatcherConfig, source_len: u64) -> Self {
+        // Large table sizing: match xdelta3 `xd3_encode_init`.
+        // It uses source max_winsize / large_step, where max_winsize is
+        // rounded to power-of-two and clamped to at least XD3_ALLOCSIZE.
+        const XD3_ALLOCSIZE: usize = 1 << 14; // 16 KiB
+        let large_slots = if source_len > 0 {
+            let src_len = source_len as usize;
+            let src_max_winsize = src_len
+                .checked_next_power_of_two()
+                .unwrap_or(src_len)
+                .max(XD3_ALLOCSIZE);
+            (src_max_winsize / config.large_step).max(8)
+        } else {
+            8
+        };
+        Self {
+            large_hash: LargeHash::new(config.large_look),
+            large_table: LargeTable::new(large_slots),
+            large_step: config.large_step,
+        }
+    }
+
+    /// Index all of `source`, as [`MatchEngine::index_source`] does.
+    pub fn build<S: SourceData>(config: &MatcherConfig, source: &S) -> Self {
+        let mut index = Self::new(config, source.len());
+        index.insert_from(source, 0);
+        index
+    }
+
+    /// Index the blocks of `source` starting at or after `lowest`, last
+    /// block first (see [`MatchEngine::index_source`]).
+    fn insert_from<S: SourceData>(&mut self, source: &S, lowest: usize) {
+        let src_len = source.len() as usize;
+        let look = self.large_hash.look;
+        let step = self.large_step;
+
+        if src_len < look || src_len - look < lowest {
+            return;
+        }
+        // Positions below this are the last of their walk.
+        let last = lowest + step;
+
+        // Fast path for contiguous in-memory sources (the common case).
+        if let Some(src) = source.as_slice(0, src_len) {
+            let mut pos = src_len - look;
+            loop {
+                let cksum = self.large_hash.checksum(&src[pos..]);
+                self.large_table.insert(cksum, pos as u64);
+                if pos < last {
+                    break;
+                }
+                pos -= step;
+            }
+            return;
+        }
+
+        // Non-contiguous sources: walk the same positions as the fast path,
+        // fetching overlapping chunks (like xdelta3 block processing).
+        const CHUNK_SIZE: usize = 1 << 20; // 1 MiB chunks
+        let mut buf = Vec::new();
+        let mut pos = src_len - look;
+        loop {
+            // Chunk covers [chunk_start, pos + look).
+            let chunk_end = pos + look;
+            let chunk_start = chunk_end.saturating_sub(CHUNK_SIZE).max(lowest);
+            let chunk_len = chunk_end - chunk_start;
+            let chunk = match source.as_slice(chunk_start as u64, chunk_len) {
+                Some(slice) => slice,
+                None => {
+                    buf.resize(chunk_len, 0);
+                    let n = source.get_bytes(chunk_start as u64, &mut buf);
+                    if n < chunk_len {
+                        return; // short read: leave the rest unindexed
+                    }
+                    &buf[..]
+                }
+            };
+
+            // Index in reverse (last-written = earliest position wins).
+            loop {
+                let cksum = self.large_hash.checksum(&chunk[pos - chunk_start..]);
+                self.large_table.insert(cksum, pos as u64);
+                if pos < last {
+                    return;
+                }
+                pos -= step;
+                if pos < chunk_start {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Match engine
+// ---------------------------------------------------------------------------
+
+/// The delta compression match engine.
+///
+/// Scans the input (target) data, finding matches against the source and
+/// against earlier parts of the target.  Produces a sequence of
+/// `Instruction` values (ADD, COPY, RUN) ready for VCDIFF encoding.
+pub struct MatchEngine {
+    config: MatcherConfig,
+    large_hash: LargeHash,
+    /// Source checksums; shared with other engines until this one re-indexes.
+    source_index: Arc<SourceIndex>,
+    small_table: SmallTable,
+    /// Previous-position chain size.
+    _sprevsz: usize,
+    /// Source position to try matching at the start of the next window.
+    /// Matches xdelta3's `match_srcpos` / `MATCH_TARGET` mechanism.
+    /// Initially 0, updated when a match extends to the end of a window.
+    pub match_srcpos: u64,
+    /// Cached CPU-dispatched match comparator.
+    forward_match_fn: rolling::MatchFn,
+    /// Cached CPU-dispatched backward comparator.
+    backward_match_fn: rolling::MatchFn,
+    /// Cached CPU-dispatched run scanner.
+    run_length_fn: rolling::RunLengthFn,
+    /// Byte whose runs are always emitted as RUN, even inside COPY matches.
+    prefer_run_byte: Option<u8>,
+    /// Furthest back a target self-copy may reach, in bytes.
+    max_self_copy_distance: Option<u64>,
+    /// Whether to search the target window for self-copies.
+    target_self_match: bool,
+    /// Counters from the most recent `find_matches` call.
+    last_stats: MatchStats,
+}
+
+impl MatchEngine {
+    /// Create a new engine with the given matcher profile.
+    ///
+    /// `source_len`: total source file length (0 if no source).
+    /// `winsize`: target input window size.
+    pub fn new(config: MatcherConfig, source_len: u64, winsize: usize) -> Self {
+        let source_index = Arc::new(SourceIndex::new(&config, source_len));
+        Self::with_source_index(config, source_index, winsize)
+    }
+
+    /// Create an engine that matches against an already built
+    /// [`SourceIndex`], without copying it.
+    ///
+    /// Engines sharing one index can match target windows in parallel.
+    /// `config` must use the large-hash parameters the index was built
+    /// with. Re-indexing through this engine copies the index first.
+    pub fn with_source_index(
+        config: MatcherConfig,
+        source_index: Arc<SourceIndex>,
+        winsize: usize,
+    ) -> Self {
+        debug_assert_eq!(source_index.large_hash.look, config.large_look);
+        debug_assert_eq!(source_index.large_step, config.large_step);
+        let large_hash = source_index.large_hash.clone();
+
+        // Small table sizing: one entry per byte of target window.
+        let small_table_slots = winsize;
+
+        // Prev chain: only needed when chain > 1.
+        // Cap to the actual window size to avoid over-allocating for small inputs.
+        let sprevsz = if config.small_chain > 1 || config.small_lchain > 1 {
+            let max = super::config::DEFAULT_SPREVSZ;
+            let capped = winsize.next_power_of_two().min(max);
+            capped.max(16) // minimum 16 entries
+        } else {
+            0
+        };
+
+        let small_table = SmallTable::new(small_table_slots, sprevsz);
+
+        Self {
+            config,
+            large_hash,
+            source_index,
+            small_table,
+            _sprevsz: sprevsz,
+            match_srcpos: 0,
+            forward_match_fn: rolling::forward_match_fn(),
+            backward_match_fn: rolling::backward_match_fn(),
+            run_length_fn: rolling::run_length_fn(),
+            prefer_run_byte: None,
+            max_self_copy_distance: None,
+            target_self_match: true,
+            last_stats: MatchStats::default(),
+        }
+    }
+
+    /// Counters from the most recent [`find_matches`](Self::find_matches)
+    /// call.
+    pub fn last_stats(&self) -> &MatchStats {
+        &self.last_stats
+    }
+
+    /// Prefer RUN over COPY for runs of `byte`.
+    ///
+    /// When set, any run of `byte` at least `MIN_RUN` long is emitted as a
+    /// RUN instruction even if a source or target COPY also covers it. COPY
+    /// matches spanning such a run are split around it.
+    pub fn set_prefer_run_byte(&mut self, byte: Option<u8>) {
+        self.prefer_run_byte = byte;
+    }
+
+    /// Limit target self-copies to at most `max` bytes behind the current
+    /// position. Candidates further back are skipped, so the bytes are
+    /// covered by a source COPY or an ADD instead.
+    pub fn set_max_self_copy_distance(&mut self, max: Option<u64>) {
+        self.max_self_copy_distance = max;
+    }
+
+    /// Forget the indexed source so a different source window can be
+    /// passed to [`index_source`](Self::index_source).
+    ///
+    /// Also rewinds `match_srcpos`; callers that slide the window may set it
+    /// to the equivalent position in the new window afterwards.
+    pub fn reset_source_index(&mut self) {
+        Arc::make_mut(&mut self.source_index).large_table.reset();
+        self.match_srcpos = 0;
+    }
+
+    /// Enable or disable target self-matching (on by default).
+    ///
+    /// When disabled the small hash table is never consulted, so only source
+    /// COPYs, RUNs and ADDs are produced and a decoder never needs earlier
+    /// output of the window.
+    pub fn set_target_self_match(&mut self, enabled: bool) {
+        self.target_self_match = enabled;
+    }
+
+    /// Index source data into the large hash table.
+    ///
+    /// Checksums are inserted in reverse order within the data (matching
+    /// xdelta3's `xd3_srcwin_move_point` which scans blocks backward).
+    /// Last-written wins, so earlier positions take priority.
+    pub fn index_source<S: SourceData>(&mut self, source: &S) {
+        self.index_source_from(source, 0);
+    }
+
+    /// Index only the bytes appended to a source since it was last indexed.
+    ///
+    /// `source` is the grown source and `from_offset` its length when it was
+    /// indexed before; checksums are inserted for the blocks that reach past
+    /// that offset, and the existing entries stay in the table. This lets a
+    /// long-lived engine follow a source that only grows (e.g. a log or a
+    /// chain of versions) without re-indexing all of it.
+    ///
+    /// Caveats compared with [`reset_source_index`](Self::reset_source_index)
+    /// followed by [`index_source`](Self::index_source):
+    ///
+    /// - Appended positions are inserted last, so where a checksum collides
+    ///   they replace older entries: later positions now take priority.
+    /// - Full indexing samples positions every `large_step` bytes counted
+    ///   back from the end of the source, so the sample grids of the old
+    ///   and appended parts need not line up.
+    /// - The table keeps the size chosen by [`new`](Self::new); pass the
+    ///   expected final source length there, or an overfull table loses
+    ///   entries.
+    pub fn index_source_append<S: SourceData>(&mut self, source: &S, from_offset: usize) {
+        let look = self.config.large_look;
+        self.index_source_from(source, (from_offset + 1).saturating_sub(look));
+    }
+
+    /// Index the blocks of `source` starting at or after `lowest`.
+    fn index_source_from<S: SourceData>(&mut self, source: &S, lowest: usize) {
+        Arc::make_mut(&mut self.source_index).insert_from(source, lowest);
+    }
+
+    /// Find all matches in `target` against `source` and earlier target data.
+    ///
+    /// Returns a list of instructions (ADD, COPY, RUN) covering the full target.
+    /// Counters for the call are available from [`last_stats`](Self::last_stats).
+    pub fn find_matches<S: SourceData>(
+        &mut self,
+        target: &[u8],
+        source: Option<&S>,
+    ) -> Vec<Instruction> {
+        let mut stats = MatchStats::default();
+        let instructions = self.find_matches_counted(target, source, &mut stats);
+        stats.tally(&instructions, source.map_or(0, |s| s.len()));
+        if source.is_some() {
+            stats.large_table = self.source_index.large_table.load();
+            stats.large_table_slots = self.source_index.large_table.size() as u64;
+        }
+        if self.target_self_match {
+            stats.small_table = self.small_table.load();
+            stats.small_table_slots = self.small_table.size() as u64;
+        }
+        self.last_stats = stats;
+        instructions
+    }
+
+    fn find_matches_counted<S: SourceData>(
+        &mut self,
+        target: &[u8],
+        source: Option<&S>,
+        stats: &mut MatchStats,
+    ) -> Vec<Instruction> {
+        let do_large = source.is_some();
+        let do_small = self.target_self_match;
+        let target_len = target.len();
+        let use_prefetch = target_len >= (1 << 18);
+        let slook = self.config.small_look;
+        let llook = self.config.large_look;
+        let source_len = source.map_or(0u64, |s| s.len());
+        let source_contiguous = source.and_then(|s| s.as_slice(0, s.len() as usize));
+        let run_length = self.run_length_fn;
+        let forward_match = self.forward_match_fn;
+
+        self.small_table.reset();
+
+        let mut matches: Vec<Match> = Vec::with_capacity((target_len / 32).max(16));
+        let mut input_pos: usize = 0;
+        let mut min_match = MIN_MATCH;
+
+        // Run-length state.
+        let mut run_l: usize;
+        let mut run_c: u8;
+
+        // Checksum state.
+        let mut scksum: u32;
+        let mut lcksum: u64 = 0;
+
+        if target_len < slook {
+            return Self::emit_add_all(target);
+        }
+
+        // Initialize checksums at position 0.
+        scksum = rolling::small_cksum(target);
+        let (rl, rc) = rolling::comprun(target, slook);
+        run_l = rl;
+        run_c = rc;
+
+        if do_large && target_len >= llook {
+            lcksum = self.large_hash.checksum(target);
+        }
+
+        // --- Initial match probe (MATCH_TARGET) ---
+        // Matches xdelta3's MATCH_TARGET mechanism: before the main loop,
+        // try a forward match from match_srcpos (initially 0) against the
+        // start of the target. This catches matches at source positions
+        // not covered by the large hash table's step-based indexing.
+        if let Some(src) = source_contiguous {
+            let src_pos = self.match_srcpos as usize;
+            if src_pos < src.len() {
+                let max_fwd = target_len.min(src.len() - src_pos);
+                if max_fwd >= MIN_MATCH {
+                    let fwd_len = forward_match(&src[src_pos..], target, max_fwd);
+                    if fwd_len >= MIN_MATCH {
+                        matches.push(Match {
+                            target_pos: 0,
+                            length: fwd_len,
+                            addr: src_pos as u64,
+                            is_source: true,
+                        });
+                        input_pos = fwd_len;
+                        if fwd_len == target_len {
+                            self.match_srcpos = src_pos as u64 + fwd_len as u64;
+                        }
+                        if input_pos + slook <= target_len {
+                            scksum = rolling::small_cksum(&target[input_pos..]);
+                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
+                            run_l = rl2;
+                            run_c = rc2;
+                            if do_large && input_pos + llook <= target_len {
+                                lcksum = self.large_hash.checksum(&target[input_pos..]);
+                            }
+                        }
+                    }
+                }
+            }
+        } else if let Some(src) = source {
+            let src_pos = self.match_srcpos;
+            if (src_pos as usize) < src.len() as usize {
+                let max_fwd = target_len.min((src.len() - src_pos) as usize);
+                if max_fwd >= MIN_MATCH {
+                    let fwd_len = self.forward_match_source(src, src_pos, target, max_fwd);
+                    if fwd_len >= MIN_MATCH {
+                        matches.push(Match {
+                            target_pos: 0,
+                            length: fwd_len,
+                            addr: src_pos,
+                            is_source: true,
+                        });
+                        input_pos = fwd_len;
+                        // If the match extends to the end of the target,
+                        // set match_srcpos for the next window.
+                        if fwd_len == target_len {
+                            self.match_srcpos = src_pos + fwd_len as u64;
+                        }
+                        // Re-initialize checksums at the new position.
+                        if input_pos + slook <= target_len {
+                            scksum = rolling::small_cksum(&target[input_pos..]);
+                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
+                            run_l = rl2;
+                            run_c = rc2;
+                            if do_large && input_pos + llook <= target_len {
+                                lcksum = self.large_hash.checksum(&target[input_pos..]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        loop {
+            if input_pos + slook > target_len {
+                break;
+            }
+
+            if use_prefetch && do_small {
+                self.small_table.prefetch_bucket(scksum as u64);
+            }
+            if use_prefetch && do_large && input_pos + llook <= target_len {
+                self.source_index.large_table.prefetch_bucket(lcksum);
+            }
+
+            // Matches xdelta3 HANDLELAZY behavior: after setting min_match
+            // for lazy search, the next iteration advances by one byte without
+            // decrementing min_match first.
+            let mut skip_min_match_decay = false;
+
+            // --- 1. Try RUN ---
+            if run_l == slook {
+                // Expand run forward (SIMD-accelerated).
+                let remaining = target_len - input_pos - run_l;
+                let total_run = run_l + run_length(&target[input_pos + run_l..], run_c, remaining);
+                if total_run >= min_match && total_run >= MIN_RUN {
+                    if min_match > MIN_MATCH {
+                        stats.lazy_hits += 1;
+                    }
+                    matches.push(Match {
+                        target_pos: input_pos,
+                        length: total_run,
+                        addr: 0,
+                        is_source: false,
+                    });
+                    // Mark as RUN (addr=u64::MAX sentinel).
+                    matches.last_mut().unwrap().addr = u64::MAX;
+
+                    if !try_lazy(total_run, self.config.max_lazy, input_pos, target_len) {
+                        input_pos += total_run;
+                        min_match = MIN_MATCH;
+                        if input_pos + slook <= target_len {
+                            scksum = rolling::small_cksum(&target[input_pos..]);
+                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
+                            run_l = rl2;
+                            run_c = rc2;
+                            if do_large && input_pos + llook <= target_len {
+                                lcksum = self.large_hash.checksum(&target[input_pos..]);
+                            }
+                        }
+                        continue;
+                    }
+                    min_match = total_run;
+                    skip_min_match_decay = true;
+                    // Fall through to advance by 1 (lazy).
+                }
+            }
+
+            // --- 2. Try LARGE (source) match ---
+            if do_large
+                && input_pos + llook <= target_len
+                && let Some(src_pos) = self.source_index.large_table.lookup(lcksum)
+            {
+                let m = if let Some(src) = source_contiguous {
+                    self.extend_source_match_slice(target, src, input_pos, src_pos)
+                } else if let Some(src) = source {
+                    self.extend_source_match(target, src, input_pos, src_pos, source_len)
+                } else {
+                    None
+                };
+
+                if let Some(m) = m {
+                    // Match xdelta3: source matches are accepted based on
+                    // forward extension length (match_fwd), not total (back+fwd).
+                    let back_len = input_pos - m.target_pos;
+                    let fwd_len = m.length - back_len;
+                    if fwd_len >= min_match {
+                        if min_match > MIN_MATCH {
+                            stats.lazy_hits += 1;
+                        }
+                        // Erase any previous matches that this backward-extended
+                        // match now covers (iopt-style erasure).
+                        if back_len > 0 {
+                            while let Some(last) = matches.last() {
+                                if last.target_pos >= m.target_pos {
+                                    matches.pop();
+                                } else {
+                                    break;
+                                }
+                            }
+                        }
+
+                        matches.push(m);
+                        if !try_lazy(fwd_len, self.config.max_lazy, input_pos, target_len) {
+                            // Advance past the forward part only.
+                            // Match covers [input_pos - back_len, input_pos + fwd_len).
+                            input_pos += fwd_len;
+                            min_match = MIN_MATCH;
+                            if input_pos + slook <= target_len {
+                                scksum = rolling::small_cksum(&target[input_pos..]);
+                                let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
+                                run_l = rl2;
+                                run_c = rc2;
+                                if do_large && input_pos + llook <= target_len {
+                                    lcksum = self.large_hash.checksum(&target[input_pos..]);
+                                }
+                            }
+                            continue;
+                        }
+                        min_match = fwd_len;
+                        skip_min_match_decay = true;
+                    }
+                }
+            }
+
+            // --- 3. Try SMALL (target self) match ---
+            if do_small {
+                let match_result =
+                    self.small_match(target, input_pos, scksum, min_match, &mut stats.chain_walks);
+
+                // Always insert current position.
+                self.small_table.insert(scksum as u64, input_pos as u64);
+
+                if let Some(m) = match_result
+                    && m.length >= min_match
+                {
+                    if min_match > MIN_MATCH {
+                        stats.lazy_hits += 1;
+                    }
+                    matches.push(m);
+                    if !try_lazy(m.length, self.config.max_lazy, input_pos, target_len) {
+                        input_pos += m.length;
+                        min_match = MIN_MATCH;
+                        if input_pos + slook <= target_len {
+                            scksum = rolling::small_cksum(&target[input_pos..]);
+                            let (rl2, rc2) = rolling::comprun(&target[input_pos..], slook);
+                            run_l = rl2;
+                            run_c = rc2;
+                            if do_large && input_pos + llook <= target_len {
+                                lcksum = self.large_hash.checksum(&target[input_pos..]);
+                            }
+                        }
+                        continue;
+                    }
+                    min_match = m.length;
+                    skip_min_match_decay = true;
+                }
+            }
+
+            // --- 4. Advance by 1 (lazy matching or no match found) ---
+            if !skip_min_match_decay && min_match > MIN_MATCH {
+                min_match -= 1;
+            }
+
+            input_pos += 1;
+            if input_pos + slook > target_len {
+                break;
+            }
+
+            // Incremental updates — use unchecked access since we verified bounds above.
+            // Safety: input_pos >= 1, and input_pos + slook <= target.len(),
+            // so target[input_pos..input_pos+4] is valid (slook=4).
+            // small_cksum_at needs ptr to input_pos (4 readable bytes).
+            unsafe {
+                let base_ptr = target.as_ptr().add(input_pos);
+                scksum = rolling::small_cksum_at(base_ptr);
+            }
+
+            // Run update — input_pos + slook - 1 < target_len is guaranteed
+            // since input_pos + slook <= target_len.
+            {
+                let next_byte = target[input_pos + slook - 1];
+                if next_byte == run_c {
+                    run_l += 1;
+                } else {
+                    run_c = next_byte;
+                    run_l = 1;
+                }
+            }
+
+            if do_large && input_pos + llook <= target_len {
+                // Safety: input_pos >= 1, input_pos + llook <= target_len,
+                // so target[input_pos-1..input_pos-1+llook+1] is valid.
+                unsafe {
+                    let base_ptr = target.as_ptr().add(input_pos - 1);
+                    lcksum = self.large_hash.update_at(lcksum, base_ptr);
+                }
+            }
+        }
+
+        // Convert matches to instructions.
+        Self::matches_to_instructions(target, source_len, &matches, self.prefer_run_byte)
+    }
+
+    // -----------------------------------------------------------------------
+    // Small (target) match scanning — matches xd3_smatch
+    // -----------------------------------------------------------------------
+
+    #[inline(always)]
+    fn small_match(
+        &self,
+        target: &[u8],
+        input_pos: usize,
+        _scksum: u32,
+        min_match: usize,
+        chain_walks: &mut u64,
+    ) -> Option<Match> {
+        let scksum = _scksum as u64;
+        // The small table keeps positions as u32 (it skips any that do not
+        // fit), so they fit `usize` on every target.
+        let head = self.small_table.lookup(scksum)?;
+        debug_assert!(head < input_pos as u64, "small table entry from the future");
+        let head = head as usize;
+
+        let is_lazy = min_match > MIN_MATCH;
+        let max_chain = if is_lazy {
+            self.config.small_lchain
+        } else {
+            self.config.small_chain
+        };
+
+        let mut best_len = 0usize;
+        let mut best_offset = 0usize;
+        let mut base = head;
+        let mut chain = max_chain;
+
+        loop {
+            // Compare target[base..] with target[input_pos..].
+            let max_cmp = target.len() - input_pos;
+            let ref_start = base;
+            let inp_start = input_pos;
+
+            if ref_start >= inp_start {
+                break; // can't copy from future
+            }
+            // Chains run from newest to oldest, so every later candidate is
+            // further back too.
+            if let Some(max) = self.max_self_copy_distance
+                && (inp_start - ref_start) as u64 > max
+            {
+                break;
+            }
+
+            // VCDIFF target COPY allows overlap, so small matches are allowed
+            // to extend all the way to end-of-input (matches xdelta3).
+            let cmp_len_limit = max_cmp;
+            *chain_walks += 1;
+
+            let cmp_len =
+                (self.forward_match_fn)(&target[ref_start..], &target[inp_start..], cmp_len_limit);
+
+            if cmp_len > best_len {
+                best_len = cmp_len;
+                best_offset = base;
+
+                if cmp_len >= self.config.long_enough || inp_start + cmp_len >= target.len() {
+                    break;
+                }
+            }
+
+            chain -= 1;
+            if chain == 0 {
+                break;
+            }
+
+            // Walk chain.
+            match self.small_table.chain_prev(base as u64, input_pos as u64) {
+                Some(prev) => base = prev as usize,
+                None => break,
+            }
+        }
+
+        if best_len < MIN_MATCH {
+            return None;
+        }
+
+        // Efficiency filter: reject short matches with expensive addresses.
+        // Matches xdelta3's filter in xd3_smatch.
+        let distance = input_pos - best_offset;
+        if best_len == 4 && distance >= 1 << 14 {
+            return None;
+        }
+        if best_len == 5 && distance >= 1 << 21 {
+            return None;
+        }
+
+        Some(Match {
+            target_pos: input_pos,
+            length: best_len,
+            addr: best_offset as u64,
+            is_source: false,
+        })
+    }
+
+    // -----------------------------------------------------------------------
+    // Source match extension — matches xd3_source_extend_match
+    // -----------------------------------------------------------------------
+
+    #[inline(always)]
+    fn extend_source_match_slice(
+        &self,
+        target: &[u8],
+        source: &[u8],
+        input_pos: usize,
+        src_pos: u64,
+    ) -> Option<Match> {
+        let src_pos = src_pos as usize;
+        if src_pos >= source.len() {
+            return None;
+        }
+
+        let max_fwd = (target.len() - input_pos).min(source.len() - src_pos);
+        let fwd_len = (self.forward_match_fn)(&source[src_pos..], &target[input_pos..], max_fwd);
+        if fwd_len < MIN_MATCH {
+            return None;
+        }
+
+        let max_back = input_pos.min(src_pos);
+        let back_len = if max_back > 0 {
+            (self.backward_match_fn)(
+                &source[src_pos - max_back..src_pos],
+                &target[input_pos - max_back..input_pos],
+                max_back,
+            )
+        } else {
+            0
+        };
+
+        Some(Match {
+            target_pos: input_pos - back_len,
+            length: back_len + fwd_len,
+            addr: (src_pos - back_len) as u64,
+            is_source: true,
+        })
+    }
+
+    fn extend_source_match<S: SourceData>(
+        &self,
+        target: &[u8],
+        source: &S,
+        input_pos: usize,
+        src_pos: u64,
+        source_len: u64,
+    ) -> Option<Match> {
+        // Forward extension.
+        let max_fwd = target.len() - input_pos;
+        let src_avail = (source_len - src_pos) as usize;
+        let max_fwd = max_fwd.min(src_avail);
+
+        let fwd_len = self.forward_match_source(source, src_pos, &target[input_pos..], max_fwd);
+
+        if fwd_len < MIN_MATCH {
+            return None;
+        }
+
+        // Backward extension (SIMD-accelerated when source is contiguous).
+        let max_back = input_pos.min(src_pos as usize);
+        let back_len = if max_back == 0 {
+            0
+        } else if let Some(src_slice) = source.as_slice(src_pos - max_back as u64, max_back) {
+            let tgt_slice = &target[input_pos - max_back..input_pos];
+            (self.backward_match_fn)(src_slice, tgt_slice, max_back)
+        } else {
+            self.backward_match_chunked(source, src_pos, &target[..input_pos], max_back)
+        };
+
+        let total_len = back_len + fwd_len;
+        let match_start_target = input_pos - back_len;
+        let match_start_source = src_pos - back_len as u64;
+
+        Some(Match {
+            target_pos: match_start_target,
+            length: total_len,
+            addr: match_start_source,
+            is_source: true,
+        })
+    }
+
+    /// Forward-compare `source[src_pos..]` with `target`, up to `max` bytes.
+    /// Falls back to chunked `get_bytes` reads for non-contiguous sources.
+    fn forward_match_source<S: SourceData>(
+        &self,
+        source: &S,
+        src_pos: u64,
+        target: &[u8],
+        max: usize,
+    ) -> usize {
+        if let Some(src_slice) = source.as_slice(src_pos, max) {
+            return (self.forward_match_fn)(src_slice, target, max);
+        }
+        // Start small and double: most candidates mismatch early.
+        let mut buf = [0u8; 16 * 1024];
+        let mut want = 64;
+        let mut total = 0;
+        while total < max {
+            let chunk = (max - total).min(want);
+            want = (want * 2).min(buf.len());
+            let n = source.get_bytes(src_pos + total as u64, &mut buf[..chunk]);
+            if n == 0 {
+                break;
+            }
+            let m = (self.forward_match_fn)(&buf[..n], &target[total..], n);
+            total += m;
+            if m < n {
+                break;
+            }
+        }
+        total
+    }
+
+    /// Backward-compare the bytes before `source[src_pos]` with the tail of
+    /// `target`, up to `max` bytes, using chunked `get_bytes` reads.
+    fn backward_match_chunked<S: SourceData>(
+        &self,
+        source: &S,
+        src_pos: u64,
+        target: &[u8],
+        max: usize,
+    ) -> usize {
+        // Start small and double: most candidates mismatch early.
+        let mut buf = [0u8; 16 * 1024];
+        let mut want = 64;
+        let mut total = 0;
+        while total < max {
+            let chunk = (max - total).min(want);
+            want = (want * 2).min(buf.len());
+            let start = src_pos - (total + chunk) as u64;
+            if source.get_bytes(start, &mut buf[..chunk]) < chunk {
+                break;
+            }
+            let tgt_end = target.len() - total;
+            let m =
+                (self.backward_match_fn)(&buf[..chunk], &target[tgt_end - chunk..tgt_end], chunk);
+            total += m;
+            if m < chunk {
+                break;
+            }
+        }
+        total
+    }
+
+    // -----------------------------------------------------------------------
+    // Convert matches to instructions
+    // -----------------------------------------------------------------------
+
+    fn emit_add_all(target: &[u8]) -> Vec<Instruction> {
+        if target.is_empty() {
+            return Vec::new();
+        }
+        vec![Instruction::Add {
+            len: inst_len(target.len()),
+        }]
+    }
+
+    fn matches_to_instructions(
+        target: &[u8],
+        source_len: u64,
+        matches: &[Match],
+        prefer_run_byte: Option<u8>,
+    ) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(matches.len().saturating_mul(2) + 1);
+        let mut covered_to: usize = 0;
+
+        // Matches arrive in target order. Where lazy matching left
+        // overlapping candidates, keep the one that ranks highest (see
+        // `Match::outranks`).
+        let mut sorted: Vec<Match> = Vec::with_capacity(matches.len());
+        for &m in matches {
+            // Remove matches covered by later, better overlapping ones.
+            while let Some(last) = sorted.last() {
+                if last.target_pos + last.length > m.target_pos && m.outranks(last) {
+                    sorted.pop();
+                } else {
+                    break;
+                }
+            }
+            // Only add if it does not lose to an overlapping previous one.
+            if sorted.last().is_none_or(|last| {
+                m.target_pos >= last.target_pos + last.length || m.outranks(last)
+            }) {
+                sorted.push(m);
+            }
+        }
+
+        for m in &sorted {
+            let m_start = m.target_pos;
+            let m_end = m_start + m.length;
+
+            if m_start < covered_to {
+                continue; // skip fully overlapped
+            }
+
+            // Emit ADD for gap before this match.
+            if m_start > covered_to {
+                instructions.push(Instruction::Add {
+                    len: inst_len(m_start - covered_to),
+                });
+            }
+
+            // Emit the match.
+            if m.addr == u64::MAX {
+                // RUN instruction.
+                instructions.push(Instruction::Run {
+                    len: inst_len(m.length),
+                });
+            } else if let Some(byte) = prefer_run_byte {
+                Self::push_copy_preferring_runs(&mut instructions, target, source_len, m, byte);
+            } else {
+                Self::push_copy(&mut instructions, source_len, m, 0, m.length);
+            }
+
+            covered_to = m_end;
+        }
+
+        // Trailing ADD.
+        if covered_to < target.len() {
+            instructions.push(Instruction::Add {
+                len: inst_len(target.len() - covered_to),
+            });
+        }
+
+        instructions
+    }
+
+    /// Push a COPY for the sub-range `[offset, offset + len)` of match `m`.
+    fn push_copy(
+        instructions: &mut Vec<Instruction>,
+        source_len: u64,
+        m: &Match,
+        offset: usize,
+        len: usize,
+    ) {
+        // All in u64: a source of 4 GiB or more puts every target address
+        // past `u32::MAX`, whatever the pointer width.
+        let offset = offset as u64;
+        let addr = if m.is_source {
+            // Source COPY — address is absolute source offset.
+            debug_assert!(
+                m.addr + offset + len as u64 <= source_len,
+                "source COPY at {} runs past the source ({source_len} bytes)",
+                m.addr + offset
+            );
+            m.addr + offset
+        } else {
+            // Target self-copy — address in combined space = source_len + target_offset.
+            debug_assert!(
+                source_len.checked_add(m.addr + offset).is_some(),
+                "target COPY address overflows u64"
+            );
+            source_len + m.addr + offset
+        };
+        instructions.push(Instruction::Copy {
+            len: inst_len(len),
+            addr,
+            mode: 0,
+        });
+    }
+
+    /// Push a COPY match, carving out runs of `byte` (>= `MIN_RUN`) as RUNs.
+    fn push_copy_preferring_runs(
+        instructions: &mut Vec<Instruction>,
+        target: &[u8],
+        source_len: u64,
+        m: &Match,
+        byte: u8,
+    ) {
+        let region = &target[m.target_pos..m.target_pos + m.length];
+        let mut piece_start = 0usize;
+        let mut pos = 0usize;
+        while pos < region.len() {
+            if region[pos] != byte {
+                pos += 1;
+                continue;
+            }
+            let run = region[pos..].iter().take_while(|&&b| b == byte).count();
+            if run >= MIN_RUN {
+                if pos > piece_start {
+                    Self::push_copy(instructions, source_len, m, piece_start, pos - piece_start);
+                }
+                instructions.push(Instruction::Run { len: inst_len(run) });
+                piece_start = pos + run;
+            }
+            pos += run;
+        }
+        if piece_start < region.len() {
+            Self::push_copy(
+                instructions,
+                source_len,
+                m,
+                piece_start,
+                region.len() - piece_start,
+            );
+        }
+    }
+}
+
+/// Instruction length for a span of `len` target bytes. Windows stay far
+/// below 4 GiB, so the narrowing never truncates.
+#[inline]
+fn inst_len(len: usize) -> u32 {
+    debug_assert!(
+        u32::try_from(len).is_ok(),
+        "instruction length {len} overflows u32"
+    );
+    len as u32
+}
+
+/// Should we try lazy matching?
+///
+/// Matches xdelta3's `TRYLAZYLEN(LEN, POS, MAX)`:
+///   `max_lazy > 0 && len < max_lazy && pos + len <= avail_in - 2`
+/// The `-2` ensures enough data remains for a lazy match to be worthwhile
+/// (the next match starts at pos+1 and must match at least 2 extra bytes).
+#[inline(always)]
+fn try_lazy(match_len: usize, max_lazy: usize, pos: usize, avail_in: usize) -> bool {
+    max_lazy > 0 && match_len < max_lazy && pos + match_len + 2 <= avail_in
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::config;
+    use crate::test_util::XorShift32;
+
+    #[test]
+    fn no_source_add_only() {
+        let mut engine = MatchEngine::new(config::FASTEST, 0, 1 << 16);
+        let target = b"Hello, world!";
+        let instructions = engine.find_matches(target, None::<&&[u8]>);
+        assert!(!instructions.is_empty());
+        // Should be a single ADD.
+        match instructions[0] {
+            Instruction::Add { len } => assert_eq!(len, target.len() as u32),
+            _ => panic!("expected ADD"),
+        }
+    }
+
+    #[test]
+    fn source_copy_identical() {
+        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let target = source;
+        let src: &[u8] = source;
+
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.index_source(&src);
+        let instructions = engine.find_matches(target, Some(&src));
+
+        // Should have at least one COPY instruction.
+        let has_copy = instructions
+            .iter()
+            .any(|i| matches!(i, Instruction::Copy { .. }));
+        assert!(
+            has_copy,
+            "expected COPY for identical data: {instructions:?}"
+        );
+    }
+
+    #[test]
+    fn target_self_copy() {
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
+        // Repeating pattern — should find self-copies.
+        let target = b"ABCDABCDABCDABCDABCDABCDABCDABCD";
+        let instructions = engine.find_matches(target, None::<&&[u8]>);
+
+        let has_copy = instructions
+            .iter()
+            .any(|i| matches!(i, Instruction::Copy { .. }));
+        assert!(
+            has_copy,
+            "expected self-COPY for repeating data: {instructions:?}"
+        );
+    }
+
+    #[test]
+    fn run_detection() {
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
+        let target = vec![0xAA; 100];
+        let instructions = engine.find_matches(&target, None::<&&[u8]>);
+
+        let has_run = instructions
+            .iter()
+            .any(|i| matches!(i, Instruction::Run { .. }));
+        assert!(has_run, "expected RUN for constant data: {instructions:?}");
+    }
+
+    #[test]
+    fn instructions_cover_full_target() {
+        let source = b"The quick brown fox jumps over the lazy dog.";
+        let target = b"The quick brown cat sits on the lazy mat.";
+        let src: &[u8] = source;
+
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.index_source(&src);
+        let instructions = engine.find_matches(target, Some(&src));
+
+        // Sum of all instruction lengths must equal target length.
+        let total: u32 = instructions
+            .iter()
+            .map(|i| match i {
+                Instruction::Add { len } => *len,
+                Instruction::Copy { len, .. } => *len,
+                Instruction::Run { len } => *len,
+            })
+            .sum();
+        assert_eq!(
+            total,
+            target.len() as u32,
+            "instructions don't cover full target"
+        );
+    }
+
+    #[test]
+    fn small_target_no_panic() {
+        let mut engine = MatchEngine::new(config::FASTEST, 0, 1 << 16);
+        // Target smaller than slook (4 bytes).
+        for len in 0..4 {
+            let target = vec![0x42; len];
+            let insts = engine.find_matches(&target, None::<&&[u8]>);
+            let total: u32 = insts
+                .iter()
+                .map(|i| match i {
+                    Instruction::Add { len } => *len,
+                    Instruction::Copy { len, .. } => *len,
+                    Instruction::Run { len } => *len,
+                })
+                .sum();
+            assert_eq!(total, len as u32);
+        }
+    }
+
+    #[test]
+    fn empty_target() {
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, 1 << 16);
+        let insts = engine.find_matches(b"", None::<&&[u8]>);
+        assert!(insts.is_empty());
+    }
+
+    #[test]
+    fn prefer_run_byte_splits_source_copy() {
+        // Source and target share a 0xFF-erased region in the middle.
+        let mut source = b"firmware header v1.0 ".repeat(8);
+        source.extend(std::iter::repeat_n(0xFF, 256));
+        source.extend(b"firmware trailer block ".repeat(8));
+        let target = source.clone();
+        let src: &[u8] = &source;
+
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.index_source(&src);
+        let plain = engine.find_matches(&target, Some(&src));
+        assert!(
+            !plain.iter().any(|i| matches!(i, Instruction::Run { .. })),
+            "identical data should be pure COPY by default: {plain:?}"
+        );
+
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.set_prefer_run_byte(Some(0xFF));
+        engine.index_source(&src);
+        let insts = engine.find_matches(&target, Some(&src));
+        assert!(
+            insts.contains(&Instruction::Run { len: 256 }),
+            "expected RUN(256) for erased region: {insts:?}"
+        );
+
+        // Pieces around the RUN still address the right source bytes.
+        let mut pos = 0usize;
+        for inst in &insts {
+            match *inst {
+                Instruction::Copy { len, addr, .. } => {
+                    let a = addr as usize;
+                    assert_eq!(
+                        &source[a..a + len as usize],
+                        &target[pos..pos + len as usize]
+                    );
+                    pos += len as usize;
+                }
+                Instruction::Run { len } => {
+                    assert!(target[pos..pos + len as usize].iter().all(|&b| b == 0xFF));
+                    pos += len as usize;
+                }
+                Instruction::Add { len } => pos += len as usize,
+            }
+        }
+        assert_eq!(pos, target.len());
+    }
+
+    #[test]
+    fn max_self_copy_distance_limits_target_copies() {
+        let block = b"a block of text that repeats throughout the target. ";
+        let target = block.repeat(40);
+
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, target.len());
+        let insts = engine.find_matches(&target, None::<&&[u8]>);
+        assert!(insts.iter().any(|i| matches!(i, Instruction::Copy { .. })));
+
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, target.len());
+        engine.set_max_self_copy_distance(Some(16));
+        let insts = engine.find_matches(&target, None::<&&[u8]>);
+        let mut pos = 0u64;
+        for inst in &insts {
+            let len = match *inst {
+                Instruction::Copy { len, addr, .. } => {
+                    assert!(pos - addr <= 16, "copy at {pos} from {addr}");
+                    len
+                }
+                Instruction::Add { len } | Instruction::Run { len } => len,
+            };
+            pos += len as u64;
+        }
+        assert_eq!(pos, target.len() as u64);
+    }
+
+    #[test]
+    fn target_self_match_disabled() {
+        let block = b"a block of text that repeats throughout the target. ";
+        let target = block.repeat(40);
+
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, target.len());
+        engine.set_target_self_match(false);
+        let insts = engine.find_matches(&target, None::<&&[u8]>);
+        assert!(!insts.iter().any(|i| matches!(i, Instruction::Copy { .. })));
+        assert_eq!(engine.last_stats().target_copies, 0);
+        assert_eq!(engine.last_stats().chain_walks, 0);
+    }
+
+    #[test]
+    fn match_stats_report_table_load() {
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut noise = |n: usize| rng.bytes(n);
+        let source = noise(64 * 1024);
+        let unrelated = noise(16 * 1024);
+        let repetitive = b"abcd".repeat(4 * 1024);
+
+        let mut engine = MatchEngine::new(config::DEFAULT, source.len() as u64, 16 * 1024);
+        engine.index_source(&source.as_slice());
+        engine.find_matches(&unrelated, Some(&source.as_slice()));
+        let varied = *engine.last_stats();
+        assert!(varied.large_table_slots > 0 && varied.small_table_slots > 0);
+        assert!(varied.large_table.inserts > 0);
+        assert!(varied.large_table_occupancy() > 0.0);
+        // Nothing matches, so every target position is inserted.
+        assert!(varied.small_table.inserts > 16_000);
+        assert!(varied.small_table_occupancy() > 0.5);
+
+        engine.find_matches(&repetitive, Some(&source.as_slice()));
+        let repeated = *engine.last_stats();
+        // Only four distinct checksums, however many positions are inserted.
+        assert!(repeated.small_table.filled <= 4);
+        assert!(repeated.small_table_occupancy() < varied.small_table_occupancy());
+        // The source table is untouched by find_matches.
+        assert_eq!(repeated.large_table, varied.large_table);
+
+        let mut total = varied;
+        total += repeated;
+        assert_eq!(total.small_table_slots, 2 * varied.small_table_slots);
+        assert_eq!(
+            total.small_table.inserts,
+            varied.small_table.inserts + repeated.small_table.inserts
+        );
+
+        let mut engine = MatchEngine::new(config::DEFAULT, 0, repetitive.len());
+        engine.set_target_self_match(false);
+        engine.find_matches(&repetitive, None::<&&[u8]>);
+        let stats = engine.last_stats();
+        assert_eq!((stats.large_table_slots, stats.small_table_slots), (0, 0));
+        assert_eq!(stats.small_table_occupancy(), 0.0);
+    }
+
+    #[test]
+    fn match_stats_sum_to_target() {
+        let source: Vec<u8> = (0..4096u32).map(|i| (i * 37 % 251) as u8).collect();
+        // Source copy, a run, fresh literals, then a repeat of the literals.
+        let literals = b"some literal bytes that appear nowhere in the source!";
+        let mut target = source[100..1100].to_vec();
+        target.extend(std::iter::repeat_n(0xEE, 200));
+        target.extend_from_slice(literals);
+        target.extend_from_slice(literals);
+
+        let mut engine = MatchEngine::new(config::DEFAULT, source.len() as u64, target.len());
+        let src: &[u8] = &source;
+        engine.index_source(&src);
+        let insts = engine.find_matches(&target, Some(&src));
+        let stats = *engine.last_stats();
+
+        assert_eq!(stats.total_bytes(), target.len() as u64);
+        assert_eq!(
+            stats.source_copies + stats.target_copies + stats.runs + stats.adds,
+            insts.len() as u64
+        );
+        assert!(stats.bytes_copied_source >= 1000);
+        assert_eq!(stats.bytes_run, 200);
+        assert_eq!(stats.runs, 1);
+        assert!(stats.bytes_copied_target >= literals.len() as u64 - 4);
+        assert!(stats.bytes_added >= literals.len() as u64);
+        assert!(stats.chain_walks > 0);
+
+        // A fresh call replaces the previous counters.
+        engine.find_matches(&target[..16], Some(&src));
+        assert_eq!(engine.last_stats().total_bytes(), 16);
+    }
+
+    #[test]
+    fn fn_source_matches_in_memory() {
+        use std::cell::RefCell;
+
+        let source: Vec<u8> = (0..256 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut target = source[8192..16384].to_vec();
+        target[1000] ^= 0x55;
+        target.extend_from_slice(b"trailing novel bytes");
+        target.extend_from_slice(&source[100_000..104_000]);
+
+        let src: &[u8] = &source;
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.index_source(&src);
+        let expected = engine.find_matches(&target, Some(&src));
+
+        let log = RefCell::new(Vec::new());
+        let fn_src = FnSource::new(source.len() as u64, |offset, buf: &mut [u8]| {
+            log.borrow_mut().push((offset, buf.len()));
+            let n = buf.len().min(source.len() - offset as usize);
+            buf[..n].copy_from_slice(&source[offset as usize..offset as usize + n]);
+            n
+        });
+        let mut engine = MatchEngine::new(config::DEFAULT, fn_src.len(), target.len());
+        engine.index_source(&fn_src);
+        let indexed: usize = log.borrow().iter().map(|&(_, len)| len).sum();
+        assert!(indexed >= source.len(), "indexing reads the whole source");
+        log.borrow_mut().clear();
+
+        let insts = engine.find_matches(&target, Some(&fn_src));
+        assert_eq!(insts, expected);
+
+        // Matching only fetches the regions it extends over.
+        let fetched: usize = log.borrow().iter().map(|&(_, len)| len).sum();
+        assert!(!log.borrow().is_empty());
+        assert!(
+            fetched < source.len() / 4,
+            "matching fetched {fetched} of {} source bytes",
+            source.len()
+        );
+    }
+
+    #[test]
+    fn reader_source_evicts_and_rereads() {
+        let source: Vec<u8> = (0..256 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut target = source[200_000..210_000].to_vec();
+        target.extend_from_slice(&source[5_000..9_000]);
+        target[3000] ^= 0x55;
+
+        let src: &[u8] = &source;
+        let mut engine = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        engine.index_source(&src);
+        let expected = engine.find_matches(&target, Some(&src));
+
+        // Two cached blocks: reads across and between blocks must evict.
+        let reader = ReaderSource::with_cache_size(
+            std::io::Cursor::new(source.clone()),
+            source.len() as u64,
+            2 * CACHE_BLOCK_SIZE,
+        );
+        for offset in [0u64, 65_530, 200_000, 10, 131_071, 262_000] {
+            let mut buf = [0u8; 100];
+            let n = reader.get_bytes(offset, &mut buf);
+            let end = (offset as usize + 100).min(source.len());
+            assert_eq!(&buf[..n], &source[offset as usize..end]);
+        }
+        assert_eq!(reader.get_bytes(source.len() as u64, &mut [0u8; 4]), 0);
+
+        let mut engine = MatchEngine::new(config::DEFAULT, reader.len(), target.len());
+        engine.index_source(&reader);
+        assert_eq!(engine.find_matches(&target, Some(&reader)), expected);
+    }
+
+    #[test]
+    fn shared_source_index_matches_own_index() {
+        let source: Vec<u8> = (0..64 * 1024u32).map(|i| (i * 31 + i / 97) as u8).collect();
+        let mut target = source[5000..40_000].to_vec();
+        target.extend_from_slice(b"inserted text that is not in the source");
+        target.extend_from_slice(&source[20_000..30_000]);
+        let src: &[u8] = &source;
+
+        let mut own = MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+        own.index_source(&src);
+        let expected = own.find_matches(&target, Some(&src));
+
+        let index = Arc::new(SourceIndex::build(&config::DEFAULT, &src));
+        let mut engines: Vec<MatchEngine> = (0..2)
+            .map(|_| {
+                MatchEngine::with_source_index(config::DEFAULT, Arc::clone(&index), target.len())
+            })
+            .collect();
+        for engine in &mut engines {
+            assert_eq!(engine.find_matches(&target, Some(&src)), expected);
+        }
+        assert_eq!(
+            engines[0].last_stats().large_table,
+            own.last_stats().large_table
+        );
+
+        // Re-indexing one engine leaves the shared index alone.
+        engines[0].reset_source_index();
+        assert!(Arc::strong_count(&index) == 2);
+        assert_eq!(engines[1].find_matches(&target, Some(&src)), expected);
+    }
+
+    #[test]
+    fn append_index_matches_full_reindex() {
+        let mut rng = XorShift32::new(0x2545_F491);
+        let mut random = |n: usize| rng.bytes(n);
+        let old = random(48 * 1024);
+        let mut grown = old.clone();
+        grown.extend(random(48 * 1024));
+        // Pieces from both the old and the appended part, with edits.
+        let mut target = Vec::new();
+        for i in 0..11 {
+            let at = i * 8 * 1024 + 100;
+            target.extend_from_slice(&grown[at..at + 6000]);
+            target.extend(random(50));
+        }
+
+        let src: &[u8] = &grown;
+        let copied = |engine: &mut MatchEngine| {
+            engine.find_matches(&target, Some(&src));
+            engine.last_stats().bytes_copied_source
+        };
+        let engine = || MatchEngine::new(config::DEFAULT, src.len() as u64, target.len());
+
+        let mut full = engine();
+        full.index_source(&src);
+        let mut stale = engine();
+        stale.index_source(&old.as_slice());
+        let mut appended = engine();
+        appended.index_source(&old.as_slice());
+        appended.index_source_append(&src, old.len());
+
+        let (full, stale, appended_bytes) =
+            (copied(&mut full), copied(&mut stale), copied(&mut appended));
+        assert!(full > 60_000, "{full}");
+        assert!(stale < full * 2 / 3, "{stale} of {full}");
+        assert!(
+            appended_bytes * 100 >= full * 99,
+            "{appended_bytes} of {full}"
+        );
+
+        // Reader-backed sources index the same blocks.
+        let reader = ReaderSource::new(std::io::Cursor::new(grown.clone()), src.len() as u64);
+        let mut chunked = engine();
+        chunked.index_source(&old.as_slice());
+        chunked.index_source_append(&reader, old.len());
+        assert_eq!(
+            chunked.find_matches(&target, Some(&src)),
+            appended.find_matches(&target, Some(&src))
+        );
+    }
+
+    #[test]
+    fn all_profiles_produce_valid_output() {
+        let source = b"AAAA BBBB CCCC DDDD EEEE FFFF GGGG HHHH";
+        let target = b"AAAA CCCC DDDD EEEE xxxx GGGG HHHH IIII";
+
+        for profile in [
+            config::FASTEST,
+            config::FASTER,
+            config::FAST,
+            config::DEFAULT,
+            config::SLOW,
+        ] {
+            let src: &[u8] = source;
+            let mut engine = MatchEngine::new(profile, src.len() as u64, target.len());
+            engine.index_source(&src);
+            let instructions = engine.find_matches(target, Some(&src));
+
+            let total: u32 = instructions
+                .iter()
+                .map(|i| match i {
+                    Instruction::Add { len } => *len,
+                    Instruction::Copy { len, .. } => *len,
+                    Instruction::Run { len } => *len,
+                })
+                .sum();
+            assert_eq!(
+                total,
+                target.len() as u32,
+                "profile {} produced wrong total length",
+                profile.name
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_matches_resolve_deterministically() {
+        let target = [0u8; 32];
+        let at = |length, addr, is_source| Match {
+            target_pos: 4,
+            length,
+            addr,
+            is_source,
+        };
+        let to_instructions =
+            |matches: &[Match]| MatchEngine::matches_to_instructions(&target, 100, matches, None);
+
+        // Equal-length candidates: the source copy at the lower address
+        // wins, whatever order they were found in.
+        let candidates = [at(8, 0, false), at(8, 40, true), at(8, 20, true)];
+        let expected = [
+            Instruction::Add { len: 4 },
+            Instruction::Copy {
+                len: 8,
+                addr: 20,
+                mode: 0,
+            },
+            Instruction::Add { len: 20 },
+        ];
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let matches = order.map(|i| candidates[i]);
+            assert_eq!(to_instructions(&matches), expected, "{order:?}");
+        }
+
+        // A RUN beats copies of its length; a longer match beats all.
+        let run = at(8, u64::MAX, false);
+        assert_eq!(
+            to_instructions(&[at(8, 20, true), run])[1],
+            Instruction::Run { len: 8 }
+        );
+        assert_eq!(
+            to_instructions(&[run, at(9, 3, false)])[1],
+            Instruction::Copy {
+                len: 9,
+                addr: 103,
+                mode: 0,
+            }
+        );
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn copy_addresses_past_u32_are_exact() {
+        // A 5 GiB source: only its length matters for the address math.
+        let source_len = 5u64 << 30;
+        let mut target = vec![b'x'; 64];
+        target[40..56].fill(0);
+        let matches = [
+            Match {
+                target_pos: 0,
+                length: 16,
+                addr: (1 << 32) + 7,
+                is_source: true,
+            },
+            Match {
+                target_pos: 24,
+                length: 40,
+                addr: 3,
+                is_source: false,
+            },
+        ];
+        let copy = |len, addr| Instruction::Copy { len, addr, mode: 0 };
+        assert_eq!(
+            MatchEngine::matches_to_instructions(&target, source_len, &matches, None),
+            [
+                copy(16, (1 << 32) + 7),
+                Instruction::Add { len: 8 },
+                copy(40, source_len + 3),
+            ]
+        );
+
+        // Carving a run out of the target COPY keeps each piece's offset.
+        assert_eq!(
+            MatchEngine::matches_to_instructions(&target, source_len, &matches, Some(0)),
+            [
+                copy(16, (1 << 32) + 7),
+                Instruction::Add { len: 8 },
+                copy(16, source_len + 3),
+                Instruction::Run { len: 16 },
+                copy(8, source_len + 3 + 32),
+            ]
+        );
+    }
+}
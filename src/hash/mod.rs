@@ -1,12 +1,17 @@
-// Hash and matching algorithms for xdelta delta compression.
-//
-// This module provides:
-// - Rolling hash functions (small 4-byte and large Rabin-Karp)
-// - Hash tables with HASH_CKOFFSET semantics
-// - Block matching with forward/backward extension
-// - Matcher profiles (fastest..slow)
-
-pub mod config;
-pub mod matching;
-pub mod rolling;
-pub mod table;
+// Hash and matching algorithms for xdelta delta compression.
+//
+// This module provides:
+// - Rolling hash functions (small 4-byte and large Rabin-Karp)
+// - Hash tables with HASH_CKOFFSET semantics
+// - Block matching with forward/backward extension
+// - Matcher profiles (fastest..slow)
+// - A rolling Adler-32 for block-change detection
+// - A suffix-array matcher for scattered edits (`suffix-array` feature)
+
+pub mod adler;
+pub mod config;
+pub mod matching;
+pub mod rolling;
+#[cfg(feature = "suffix-array")]
+pub mod suffix;
+pub mod table;
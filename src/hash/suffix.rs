@@ -0,0 +1,235 @@
+// Suffix-array matching (bsdiff-style), an alternative to the hash engine.
+//
+// The source is indexed by a suffix array, so the longest source match for
+// every target position can be found by binary search. Unlike the sampled
+// large-hash table this finds short matches anywhere in the source, which
+// pays off for targets with many small scattered edits (firmware images).
+// Indexing by prefix doubling costs O(n log² n) time and about three words
+// per source byte while building (the array and two rank arrays), one word
+// per byte once built; only source COPYs, RUNs and ADDs are produced.
+
+use super::config::{MIN_MATCH, MIN_RUN};
+use super::rolling;
+use crate::vcdiff::code_table::Instruction;
+
+/// Suffix array over a source buffer.
+///
+/// Holds only the sorted suffix offsets: queries take the source slice the
+/// array was built from, and results are meaningless for any other slice.
+///
+/// ```
+/// use oxidelta::hash::suffix::SuffixArray;
+/// let source = b"the quick brown fox";
+/// let sa = SuffixArray::new(source);
+/// assert_eq!(sa.longest_match(source, b"brown cow"), (10, 6));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SuffixArray {
+    sa: Vec<usize>,
+}
+
+impl SuffixArray {
+    /// Sort the suffixes of `source` (prefix doubling).
+    pub fn new(source: &[u8]) -> Self {
+        let n = source.len();
+        let mut sa: Vec<usize> = (0..n).collect();
+        if n == 0 {
+            return Self { sa };
+        }
+        let mut rank: Vec<usize> = source.iter().map(|&b| usize::from(b)).collect();
+        let mut next = vec![0usize; n];
+        let mut k = 1;
+        loop {
+            // Order by the first 2k bytes: (rank of i, rank of i + k), where
+            // a suffix ending before i + k sorts first.
+            let key = |i: usize| (rank[i], if i + k < n { rank[i + k] + 1 } else { 0 });
+            sa.sort_unstable_by_key(|&i| key(i));
+            next[sa[0]] = 0;
+            for w in 1..n {
+                next[sa[w]] = next[sa[w - 1]] + usize::from(key(sa[w - 1]) < key(sa[w]));
+            }
+            std::mem::swap(&mut rank, &mut next);
+            if rank[sa[n - 1]] == n - 1 || k >= n {
+                break;
+            }
+            k *= 2;
+        }
+        Self { sa }
+    }
+
+    /// Number of indexed suffixes (the source length).
+    pub fn len(&self) -> usize {
+        self.sa.len()
+    }
+
+    /// Whether the indexed source was empty.
+    pub fn is_empty(&self) -> bool {
+        self.sa.is_empty()
+    }
+
+    /// Longest prefix of `pattern` that occurs in `source`, as
+    /// `(source offset, length)`; `(0, 0)` when not even one byte matches.
+    pub fn longest_match(&self, source: &[u8], pattern: &[u8]) -> (usize, usize) {
+        if self.sa.is_empty() || pattern.is_empty() {
+            return (0, 0);
+        }
+        // Binary search for where `pattern` would sort; the longest common
+        // prefix is with one of the two neighbouring suffixes.
+        let (mut lo, mut hi) = (0, self.sa.len() - 1);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let suffix = &source[self.sa[mid]..];
+            let n = suffix.len().min(pattern.len());
+            if suffix[..n] < pattern[..n] {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let lo_len = rolling::forward_match(&source[self.sa[lo]..], pattern, usize::MAX);
+        let hi_len = rolling::forward_match(&source[self.sa[hi]..], pattern, usize::MAX);
+        if hi_len > lo_len {
+            (self.sa[hi], hi_len)
+        } else if lo_len > 0 {
+            (self.sa[lo], lo_len)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Cover `target` with source COPYs, RUNs and ADDs.
+    ///
+    /// Greedy: each position takes the longest source match, preferring the
+    /// previous COPY's alignment on ties so that edits in place keep their
+    /// addresses close together. Runs at least as long as the best match are
+    /// emitted as RUN. The result goes through the usual instruction
+    /// pipeline, like [`MatchEngine::find_matches`](super::matching::MatchEngine::find_matches)
+    /// output.
+    pub fn find_matches(&self, source: &[u8], target: &[u8]) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut add_start = 0;
+        let mut pos = 0;
+        // Source offset minus target offset of the last COPY.
+        let mut last_delta: Option<isize> = None;
+
+        while pos < target.len() {
+            let rest = &target[pos..];
+            let run = rest.iter().take_while(|&&b| b == rest[0]).count();
+            let (mut addr, mut len) = self.longest_match(source, rest);
+            if let Some(delta) = last_delta
+                && let Some(aligned) = pos.checked_add_signed(delta)
+                && aligned < source.len()
+            {
+                let aligned_len = rolling::forward_match(&source[aligned..], rest, usize::MAX);
+                if aligned_len >= len {
+                    (addr, len) = (aligned, aligned_len);
+                }
+            }
+
+            let (inst, advance) = if run >= MIN_RUN && run >= len {
+                (Instruction::Run { len: run as u32 }, run)
+            } else if len >= MIN_MATCH {
+                last_delta = Some(addr as isize - pos as isize);
+                let copy = Instruction::Copy {
+                    len: len as u32,
+                    addr: addr as u64,
+                    mode: 0,
+                };
+                (copy, len)
+            } else {
+                pos += 1;
+                continue;
+            };
+            if pos > add_start {
+                instructions.push(Instruction::Add {
+                    len: (pos - add_start) as u32,
+                });
+            }
+            instructions.push(inst);
+            pos += advance;
+            add_start = pos;
+        }
+        if target.len() > add_start {
+            instructions.push(Instruction::Add {
+                len: (target.len() - add_start) as u32,
+            });
+        }
+        instructions
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::XorShift32;
+
+    fn naive(source: &[u8]) -> Vec<usize> {
+        let mut sa: Vec<usize> = (0..source.len()).collect();
+        sa.sort_by_key(|&i| &source[i..]);
+        sa
+    }
+
+    #[test]
+    fn matches_naive_sort() {
+        let mut rng = XorShift32::new(0x9E37_79B9);
+        let random: Vec<u8> = (0..2_000).map(|_| rng.below(4) as u8).collect();
+        let inputs: [&[u8]; 5] = [b"", b"a", b"banana", &[7u8; 300], &random];
+        for input in inputs {
+            assert_eq!(SuffixArray::new(input).sa, naive(input), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn longest_match_finds_any_offset() {
+        let source = b"abracadabra, abracadabra!";
+        let sa = SuffixArray::new(source);
+        assert_eq!(sa.longest_match(source, b"cadabra!?"), (17, 8));
+        assert_eq!(sa.longest_match(source, b"zzz"), (0, 0));
+        assert_eq!(sa.longest_match(source, b""), (0, 0));
+        assert_eq!(SuffixArray::new(b"").longest_match(b"", b"abc"), (0, 0));
+    }
+
+    #[test]
+    fn instructions_cover_target() {
+        let source: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        for i in (100..target.len()).step_by(500) {
+            target[i] ^= 0xFF;
+        }
+        target.extend_from_slice(&[0u8; 64]);
+
+        let instructions = SuffixArray::new(&source).find_matches(&source, &target);
+        let mut rebuilt = Vec::new();
+        for inst in &instructions {
+            match *inst {
+                Instruction::Add { len } => {
+                    let at = rebuilt.len();
+                    rebuilt.extend_from_slice(&target[at..at + len as usize]);
+                }
+                Instruction::Run { len } => {
+                    let byte = target[rebuilt.len()];
+                    rebuilt.extend(std::iter::repeat_n(byte, len as usize));
+                }
+                Instruction::Copy { len, addr, .. } => {
+                    let addr = addr as usize;
+                    rebuilt.extend_from_slice(&source[addr..addr + len as usize]);
+                }
+            }
+        }
+        assert_eq!(rebuilt, target);
+        // One ADD per edit, and the trailing zeros become a RUN.
+        let adds = instructions
+            .iter()
+            .filter(|i| matches!(i, Instruction::Add { .. }))
+            .count();
+        assert_eq!(adds, 20);
+        assert!(matches!(
+            instructions.last(),
+            Some(Instruction::Run { len: 64 })
+        ));
+    }
+}
@@ -1,439 +1,1087 @@
-// File-level I/O helpers for delta encoding/decoding.
-//
-// Provides `encode_file()` and `decode_file()` convenience functions that
-// wrap the streaming pipeline with proper buffered I/O. Optionally computes
-// streaming SHA-256 checksums (feature-gated behind `file-io`).
-
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::Path;
-
-#[cfg(feature = "file-io")]
-use sha2::Digest;
-
-use crate::compress::decoder::DeltaDecoder;
-use crate::compress::encoder::{CompressOptions, DeltaEncoder, EncodeError};
-use crate::vcdiff::decoder::DecodeError;
-
-// ---------------------------------------------------------------------------
-// Stats
-// ---------------------------------------------------------------------------
-
-/// Statistics returned by `encode_file()`.
-#[derive(Debug, Clone)]
-pub struct EncodeStats {
-    /// Source file size in bytes.
-    pub source_size: u64,
-    /// Target file size in bytes.
-    pub target_size: u64,
-    /// Delta output size in bytes.
-    pub delta_size: u64,
-    /// Number of VCDIFF windows written.
-    pub windows: u64,
-    /// SHA-256 of the source file (if `file-io` feature is enabled).
-    pub source_sha256: Option<[u8; 32]>,
-    /// SHA-256 of the target file (if `file-io` feature is enabled).
-    pub target_sha256: Option<[u8; 32]>,
-}
-
-/// Statistics returned by `decode_file()`.
-#[derive(Debug, Clone)]
-pub struct DecodeStats {
-    /// Source file size in bytes.
-    pub source_size: u64,
-    /// Delta file size in bytes.
-    pub delta_size: u64,
-    /// Reconstructed output size in bytes.
-    pub output_size: u64,
-    /// Number of VCDIFF windows decoded.
-    pub windows: u64,
-    /// SHA-256 of the reconstructed output (if `file-io` feature is enabled).
-    pub output_sha256: Option<[u8; 32]>,
-}
-
-// ---------------------------------------------------------------------------
-// Errors
-// ---------------------------------------------------------------------------
-
-/// Error type for file I/O operations.
-#[derive(Debug)]
-pub enum IoError {
-    /// I/O error (file open, read, write).
-    Io(io::Error),
-    /// Delta encoding error.
-    Encode(EncodeError),
-    /// Delta decoding error.
-    Decode(DecodeError),
-}
-
-impl std::fmt::Display for IoError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Io(e) => write!(f, "I/O error: {e}"),
-            Self::Encode(e) => write!(f, "encode error: {e}"),
-            Self::Decode(e) => write!(f, "decode error: {e}"),
-        }
-    }
-}
-
-impl std::error::Error for IoError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Self::Io(e) => Some(e),
-            Self::Encode(e) => Some(e),
-            Self::Decode(e) => Some(e),
-        }
-    }
-}
-
-impl From<io::Error> for IoError {
-    fn from(e: io::Error) -> Self {
-        Self::Io(e)
-    }
-}
-
-impl From<EncodeError> for IoError {
-    fn from(e: EncodeError) -> Self {
-        Self::Encode(e)
-    }
-}
-
-impl From<DecodeError> for IoError {
-    fn from(e: DecodeError) -> Self {
-        Self::Decode(e)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Default buffer size
-// ---------------------------------------------------------------------------
-
-const BUF_SIZE: usize = 64 * 1024; // 64 KiB
-
-// ---------------------------------------------------------------------------
-// encode_file
-// ---------------------------------------------------------------------------
-
-/// Encode a delta between a source file and target file, writing to `delta_path`.
-///
-/// The source is read fully into memory (matching xdelta3's behavior).
-/// The target is streamed through a `BufReader`. The delta output uses `BufWriter`.
-///
-/// When the `file-io` feature is enabled, SHA-256 checksums are computed
-/// incrementally as data flows through the pipeline.
-pub fn encode_file(
-    source_path: &Path,
-    target_path: &Path,
-    delta_path: &Path,
-    opts: CompressOptions,
-) -> Result<EncodeStats, IoError> {
-    // Read source fully into memory.
-    let source = std::fs::read(source_path)?;
-    let source_size = source.len() as u64;
-
-    // Open target for streaming read.
-    let target_file = File::open(target_path)?;
-    let target_size = target_file.metadata()?.len();
-    let mut target_reader = BufReader::with_capacity(BUF_SIZE, target_file);
-
-    // Open delta output.
-    let delta_file = File::create(delta_path)?;
-    let delta_writer = BufWriter::with_capacity(BUF_SIZE, delta_file);
-
-    // Create encoder.
-    let mut encoder = DeltaEncoder::new(delta_writer, &source, opts);
-
-    // Stream target through the encoder, optionally hashing.
-    #[cfg(feature = "file-io")]
-    let mut target_hasher = sha2::Sha256::new();
-    #[cfg(feature = "file-io")]
-    let source_sha256 = {
-        let mut h = sha2::Sha256::new();
-        h.update(&source);
-        Some(h.finalize().into())
-    };
-    #[cfg(not(feature = "file-io"))]
-    let source_sha256: Option<[u8; 32]> = None;
-
-    let mut buf = vec![0u8; BUF_SIZE];
-    loop {
-        let n = target_reader.read(&mut buf)?;
-        if n == 0 {
-            break;
-        }
-        #[cfg(feature = "file-io")]
-        {
-            target_hasher.update(&buf[..n]);
-        }
-        encoder.write_target(&buf[..n])?;
-    }
-
-    let (writer, windows) = encoder.finish()?;
-    let delta_size = writer
-        .into_inner()
-        .map_err(|e| e.into_error())?
-        .metadata()?
-        .len();
-
-    #[cfg(feature = "file-io")]
-    let target_sha256 = Some(target_hasher.finalize().into());
-    #[cfg(not(feature = "file-io"))]
-    let target_sha256: Option<[u8; 32]> = None;
-
-    Ok(EncodeStats {
-        source_size,
-        target_size,
-        delta_size,
-        windows,
-        source_sha256,
-        target_sha256,
-    })
-}
-
-// ---------------------------------------------------------------------------
-// decode_file
-// ---------------------------------------------------------------------------
-
-/// Decode a VCDIFF delta file using a source file, writing to `output_path`.
-///
-/// The source is read fully into memory. The delta is streamed via `BufReader`.
-/// The output uses `BufWriter`.
-///
-/// When the `file-io` feature is enabled, a SHA-256 checksum of the output
-/// is computed incrementally.
-pub fn decode_file(
-    source_path: &Path,
-    delta_path: &Path,
-    output_path: &Path,
-) -> Result<DecodeStats, IoError> {
-    // Read source fully into memory.
-    let source = std::fs::read(source_path)?;
-    let source_size = source.len() as u64;
-
-    // Open delta for streaming read.
-    let delta_file = File::open(delta_path)?;
-    let delta_size = delta_file.metadata()?.len();
-    let delta_reader = BufReader::with_capacity(BUF_SIZE, delta_file);
-
-    // Open output.
-    let output_file = File::create(output_path)?;
-
-    // Wrap the output writer to optionally hash as we write.
-    #[cfg(feature = "file-io")]
-    let mut output_hasher = sha2::Sha256::new();
-
-    let mut output_writer = BufWriter::with_capacity(BUF_SIZE, output_file);
-
-    // Decode.
-    let mut decoder = DeltaDecoder::new(delta_reader);
-    let mut src: &[u8] = &source;
-
-    #[cfg(feature = "file-io")]
-    let output_size = {
-        let mut hashing_writer = HashingWriter {
-            inner: &mut output_writer,
-            hasher: &mut output_hasher,
-        };
-        decoder.decode_to(&mut src, &mut hashing_writer)?
-    };
-
-    #[cfg(not(feature = "file-io"))]
-    let output_size = decoder.decode_to(&mut src, &mut output_writer)?;
-
-    let windows = decoder.windows_decoded();
-
-    output_writer.flush()?;
-
-    #[cfg(feature = "file-io")]
-    let output_sha256 = Some(output_hasher.finalize().into());
-    #[cfg(not(feature = "file-io"))]
-    let output_sha256: Option<[u8; 32]> = None;
-
-    Ok(DecodeStats {
-        source_size,
-        delta_size,
-        output_size,
-        windows,
-        output_sha256,
-    })
-}
-
-// ---------------------------------------------------------------------------
-// Hashing writer (used with file-io feature)
-// ---------------------------------------------------------------------------
-
-#[cfg(feature = "file-io")]
-struct HashingWriter<'a, W: Write> {
-    inner: &'a mut W,
-    hasher: &'a mut sha2::Sha256,
-}
-
-#[cfg(feature = "file-io")]
-impl<W: Write> Write for HashingWriter<'_, W> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let n = self.inner.write(buf)?;
-        use sha2::Digest;
-        self.hasher.update(&buf[..n]);
-        Ok(n)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-
-    fn write_temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
-        let dir = std::env::temp_dir().join("xdelta_io_test");
-        std::fs::create_dir_all(&dir).unwrap();
-        let path = dir.join(name);
-        let mut f = File::create(&path).unwrap();
-        f.write_all(data).unwrap();
-        path
-    }
-
-    fn cleanup_temp_files(paths: &[&Path]) {
-        for p in paths {
-            let _ = std::fs::remove_file(p);
-        }
-    }
-
-    #[test]
-    fn encode_decode_file_roundtrip() {
-        let source_data = b"The quick brown fox jumps over the lazy dog. 1234567890";
-        let target_data = b"The quick brown cat sits on the lazy mat. 1234567890!!!";
-
-        let source_path = write_temp_file("source.bin", source_data);
-        let target_path = write_temp_file("target.bin", target_data);
-        let delta_path = write_temp_file("delta.vcdiff", b"");
-        let output_path = write_temp_file("output.bin", b"");
-
-        let enc_stats = encode_file(
-            &source_path,
-            &target_path,
-            &delta_path,
-            CompressOptions::default(),
-        )
-        .unwrap();
-
-        assert_eq!(enc_stats.source_size, source_data.len() as u64);
-        assert_eq!(enc_stats.target_size, target_data.len() as u64);
-        assert!(enc_stats.delta_size > 0);
-        assert!(enc_stats.windows >= 1);
-
-        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
-
-        assert_eq!(dec_stats.output_size, target_data.len() as u64);
-        assert!(dec_stats.windows >= 1);
-
-        let output_data = std::fs::read(&output_path).unwrap();
-        assert_eq!(output_data, target_data);
-
-        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
-    }
-
-    #[test]
-    fn encode_decode_no_source() {
-        let target_data = b"standalone data without any source";
-
-        let source_path = write_temp_file("nosrc_source.bin", b"");
-        let target_path = write_temp_file("nosrc_target.bin", target_data);
-        let delta_path = write_temp_file("nosrc_delta.vcdiff", b"");
-        let output_path = write_temp_file("nosrc_output.bin", b"");
-
-        encode_file(
-            &source_path,
-            &target_path,
-            &delta_path,
-            CompressOptions::default(),
-        )
-        .unwrap();
-
-        decode_file(&source_path, &delta_path, &output_path).unwrap();
-
-        let output_data = std::fs::read(&output_path).unwrap();
-        assert_eq!(output_data, target_data);
-
-        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
-    }
-
-    #[cfg(feature = "file-io")]
-    #[test]
-    fn sha256_checksums_computed() {
-        let source_data = b"source for checksum test";
-        let target_data = b"target for checksum test";
-
-        let source_path = write_temp_file("sha_source.bin", source_data);
-        let target_path = write_temp_file("sha_target.bin", target_data);
-        let delta_path = write_temp_file("sha_delta.vcdiff", b"");
-        let output_path = write_temp_file("sha_output.bin", b"");
-
-        let enc_stats = encode_file(
-            &source_path,
-            &target_path,
-            &delta_path,
-            CompressOptions::default(),
-        )
-        .unwrap();
-
-        assert!(enc_stats.source_sha256.is_some());
-        assert!(enc_stats.target_sha256.is_some());
-
-        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
-
-        assert!(dec_stats.output_sha256.is_some());
-        // The output SHA-256 should match the target SHA-256 from encoding.
-        assert_eq!(dec_stats.output_sha256, enc_stats.target_sha256);
-
-        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
-    }
-
-    #[test]
-    fn large_file_multi_window() {
-        // 1 MiB of data with small windows to force multiple windows.
-        let source_data: Vec<u8> = (0..=255u8).cycle().take(1 << 20).collect();
-        let mut target_data = source_data.clone();
-        // Modify scattered bytes.
-        for i in (0..target_data.len()).step_by(4096) {
-            target_data[i] = target_data[i].wrapping_add(1);
-        }
-
-        let source_path = write_temp_file("large_source.bin", &source_data);
-        let target_path = write_temp_file("large_target.bin", &target_data);
-        let delta_path = write_temp_file("large_delta.vcdiff", b"");
-        let output_path = write_temp_file("large_output.bin", b"");
-
-        let enc_stats = encode_file(
-            &source_path,
-            &target_path,
-            &delta_path,
-            CompressOptions {
-                window_size: 64 * 1024, // 64 KiB windows
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        assert!(enc_stats.windows > 1, "expected multiple windows");
-        assert!(
-            enc_stats.delta_size < enc_stats.target_size,
-            "delta should be smaller than target"
-        );
-
-        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
-        assert_eq!(dec_stats.output_size, target_data.len() as u64);
-
-        let output_data = std::fs::read(&output_path).unwrap();
-        assert_eq!(output_data, target_data);
-
-        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
-    }
-}
+// File-level I/O helpers for delta encoding/decoding.
+//
+// Provides `encode_file()` and `decode_file()` convenience functions that
+// wrap the streaming pipeline with proper buffered I/O, plus `diff_files()`
+// and `patch_file()` which additionally write their output atomically.
+// Optionally computes streaming SHA-256 checksums (feature-gated behind
+// `file-io`), which `patch_file_verified()` checks against a `sha256:<hex>`
+// application header. `MmapSource` (feature `mmap`) maps large source files
+// instead of reading them into memory, and `BlockReader` caches blocks of
+// seekable sources for `ReaderSource` and `SeekSource`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "file-io")]
+use sha2::Digest;
+
+use crate::compress::decoder::DeltaDecoder;
+use crate::compress::encoder::{CompressOptions, DeltaEncoder, EncodeError};
+#[cfg(feature = "file-io")]
+use crate::vcdiff::app_header::{AppHeaderMap, SHA256_KEY};
+use crate::vcdiff::decoder::DecodeError;
+#[cfg(feature = "file-io")]
+use crate::vcdiff::header::FileHeader;
+
+// ---------------------------------------------------------------------------
+// Stats
+// ---------------------------------------------------------------------------
+
+/// Statistics returned by `encode_file()`.
+#[derive(Debug, Clone)]
+pub struct EncodeStats {
+    /// Source file size in bytes.
+    pub source_size: u64,
+    /// Target file size in bytes.
+    pub target_size: u64,
+    /// Delta output size in bytes.
+    pub delta_size: u64,
+    /// Number of VCDIFF windows written.
+    pub windows: u64,
+    /// SHA-256 of the source file (if `file-io` feature is enabled).
+    pub source_sha256: Option<[u8; 32]>,
+    /// SHA-256 of the target file (if `file-io` feature is enabled).
+    pub target_sha256: Option<[u8; 32]>,
+}
+
+/// Statistics returned by `decode_file()`.
+#[derive(Debug, Clone)]
+pub struct DecodeStats {
+    /// Source file size in bytes.
+    pub source_size: u64,
+    /// Delta file size in bytes.
+    pub delta_size: u64,
+    /// Reconstructed output size in bytes.
+    pub output_size: u64,
+    /// Number of VCDIFF windows decoded.
+    pub windows: u64,
+    /// SHA-256 of the reconstructed output (if `file-io` feature is enabled).
+    pub output_sha256: Option<[u8; 32]>,
+}
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+/// Error type for file I/O operations.
+#[derive(Debug)]
+pub enum IoError {
+    /// I/O error (file open, read, write).
+    Io(io::Error),
+    /// Delta encoding error.
+    Encode(EncodeError),
+    /// Delta decoding error.
+    Decode(DecodeError),
+    /// An input file does not exist.
+    NotFound(PathBuf),
+    /// The output's SHA-256 differs from the one the delta carries.
+    HashMismatch {
+        expected: [u8; 32],
+        actual: [u8; 32],
+    },
+}
+
+impl std::fmt::Display for IoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Encode(e) => write!(f, "encode error: {e}"),
+            Self::Decode(e) => write!(f, "decode error: {e}"),
+            Self::NotFound(path) => write!(f, "file not found: {}", path.display()),
+            Self::HashMismatch { expected, actual } => write!(
+                f,
+                "SHA-256 mismatch: expected {}, got {}",
+                hex(expected),
+                hex(actual)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            Self::NotFound(_) | Self::HashMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<EncodeError> for IoError {
+    fn from(e: EncodeError) -> Self {
+        Self::Encode(e)
+    }
+}
+
+impl From<DecodeError> for IoError {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Default buffer size
+// ---------------------------------------------------------------------------
+
+const BUF_SIZE: usize = 64 * 1024; // 64 KiB
+
+// ---------------------------------------------------------------------------
+// encode_file
+// ---------------------------------------------------------------------------
+
+/// Encode a delta between a source file and target file, writing to `delta_path`.
+///
+/// The source is read fully into memory (matching xdelta3's behavior).
+/// The target is streamed through a `BufReader`. The delta output uses `BufWriter`.
+///
+/// When the `file-io` feature is enabled, SHA-256 checksums are computed
+/// incrementally as data flows through the pipeline.
+pub fn encode_file(
+    source_path: &Path,
+    target_path: &Path,
+    delta_path: &Path,
+    opts: CompressOptions,
+) -> Result<EncodeStats, IoError> {
+    let delta_file = File::create(delta_path)?;
+    encode_into(source_path, target_path, &delta_file, opts)
+}
+
+/// [`encode_file`] against an already-open delta file.
+fn encode_into(
+    source_path: &Path,
+    target_path: &Path,
+    delta_file: &File,
+    opts: CompressOptions,
+) -> Result<EncodeStats, IoError> {
+    // Read source fully into memory.
+    let source = std::fs::read(source_path)?;
+    let source_size = source.len() as u64;
+
+    // Open target for streaming read.
+    let target_file = File::open(target_path)?;
+    let target_size = target_file.metadata()?.len();
+    let mut target_reader = BufReader::with_capacity(BUF_SIZE, target_file);
+
+    let delta_writer = BufWriter::with_capacity(BUF_SIZE, delta_file);
+
+    // Create encoder.
+    let mut encoder = DeltaEncoder::new(delta_writer, &source, opts);
+
+    // Stream target through the encoder, optionally hashing.
+    #[cfg(feature = "file-io")]
+    let mut target_hasher = sha2::Sha256::new();
+    #[cfg(feature = "file-io")]
+    let source_sha256 = {
+        let mut h = sha2::Sha256::new();
+        h.update(&source);
+        Some(h.finalize().into())
+    };
+    #[cfg(not(feature = "file-io"))]
+    let source_sha256: Option<[u8; 32]> = None;
+
+    let mut buf = vec![0u8; BUF_SIZE];
+    loop {
+        let n = target_reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        #[cfg(feature = "file-io")]
+        {
+            target_hasher.update(&buf[..n]);
+        }
+        encoder.write_target(&buf[..n])?;
+    }
+
+    let (writer, finished) = encoder.finish()?;
+    let delta_size = writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .metadata()?
+        .len();
+
+    #[cfg(feature = "file-io")]
+    let target_sha256 = Some(target_hasher.finalize().into());
+    #[cfg(not(feature = "file-io"))]
+    let target_sha256: Option<[u8; 32]> = None;
+
+    Ok(EncodeStats {
+        source_size,
+        target_size,
+        delta_size,
+        windows: finished.windows,
+        source_sha256,
+        target_sha256,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// decode_file
+// ---------------------------------------------------------------------------
+
+/// Decode a VCDIFF delta file using a source file, writing to `output_path`.
+///
+/// The source is read fully into memory. The delta is streamed via `BufReader`.
+/// The output uses `BufWriter`.
+///
+/// When the `file-io` feature is enabled, a SHA-256 checksum of the output
+/// is computed incrementally.
+pub fn decode_file(
+    source_path: &Path,
+    delta_path: &Path,
+    output_path: &Path,
+) -> Result<DecodeStats, IoError> {
+    let output_file = File::create(output_path)?;
+    decode_into(source_path, delta_path, &output_file)
+}
+
+/// [`decode_file`] against an already-open output file.
+fn decode_into(
+    source_path: &Path,
+    delta_path: &Path,
+    output_file: &File,
+) -> Result<DecodeStats, IoError> {
+    // Read source fully into memory.
+    let source = std::fs::read(source_path)?;
+    let source_size = source.len() as u64;
+
+    // Open delta for streaming read.
+    let delta_file = File::open(delta_path)?;
+    let delta_size = delta_file.metadata()?.len();
+    let delta_reader = BufReader::with_capacity(BUF_SIZE, delta_file);
+
+    // Wrap the output writer to optionally hash as we write.
+    #[cfg(feature = "file-io")]
+    let mut output_hasher = sha2::Sha256::new();
+
+    let mut output_writer = BufWriter::with_capacity(BUF_SIZE, output_file);
+
+    // Decode.
+    let mut decoder = DeltaDecoder::new(delta_reader);
+    let mut src: &[u8] = &source;
+
+    #[cfg(feature = "file-io")]
+    let output_size = {
+        let mut hashing_writer = HashingWriter {
+            inner: &mut output_writer,
+            hasher: &mut output_hasher,
+        };
+        decoder.decode_to(&mut src, &mut hashing_writer)?
+    };
+
+    #[cfg(not(feature = "file-io"))]
+    let output_size = decoder.decode_to(&mut src, &mut output_writer)?;
+
+    let windows = decoder.windows_decoded();
+
+    output_writer.flush()?;
+
+    #[cfg(feature = "file-io")]
+    let output_sha256 = Some(output_hasher.finalize().into());
+    #[cfg(not(feature = "file-io"))]
+    let output_sha256: Option<[u8; 32]> = None;
+
+    Ok(DecodeStats {
+        source_size,
+        delta_size,
+        output_size,
+        windows,
+        output_sha256,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Atomic file-to-file API
+// ---------------------------------------------------------------------------
+
+/// Encode a delta from `source` to `target`, writing it atomically to `out`.
+///
+/// Like [`encode_file`], but the delta is written to a temporary file next
+/// to `out` and renamed into place only once complete, so `out` is never
+/// left holding a partial delta. Missing inputs are reported as
+/// [`IoError::NotFound`] before anything is written.
+pub fn diff_files(
+    source: &Path,
+    target: &Path,
+    out: &Path,
+    opts: CompressOptions,
+) -> Result<EncodeStats, IoError> {
+    require_file(source)?;
+    require_file(target)?;
+    write_atomically(out, |tmp| encode_into(source, target, tmp, opts))
+}
+
+/// Apply the delta at `delta` to `source`, writing the result atomically to
+/// `out`.
+///
+/// The delta is streamed window by window rather than read into memory.
+/// As with [`diff_files`], `out` is replaced only after decoding (including
+/// checksum verification) succeeds.
+pub fn patch_file(source: &Path, delta: &Path, out: &Path) -> Result<DecodeStats, IoError> {
+    require_file(source)?;
+    require_file(delta)?;
+    write_atomically(out, |tmp| decode_into(source, delta, tmp))
+}
+
+/// Like [`patch_file`], but also checks the output against the SHA-256 the
+/// delta carries.
+///
+/// The expected hash is a `sha256:<hex>` token in the delta's application
+/// header (see [`DeltaEncoder::set_target_sha256`]); tokens are separated by
+/// whitespace or `/`. A header that is an [`AppHeaderMap`] carries it as a
+/// `sha256` entry instead. On a mismatch `out` is left untouched and
+/// [`IoError::HashMismatch`] is returned. Deltas without such a token are
+/// applied unverified; a token that is not 64 hex digits is a
+/// [`DecodeError`].
+#[cfg(feature = "file-io")]
+pub fn patch_file_verified(
+    source: &Path,
+    delta: &Path,
+    out: &Path,
+) -> Result<DecodeStats, IoError> {
+    require_file(source)?;
+    require_file(delta)?;
+    let mut reader = BufReader::new(File::open(delta)?);
+    let header = FileHeader::decode(&mut reader).map_err(DecodeError::from)?;
+    let expected = match header.app_header.as_deref() {
+        Some(app_header) => app_header_sha256(app_header)?,
+        None => None,
+    };
+
+    write_atomically(out, |tmp| {
+        let stats = decode_into(source, delta, tmp)?;
+        if let (Some(expected), Some(actual)) = (expected, stats.output_sha256)
+            && expected != actual
+        {
+            return Err(IoError::HashMismatch { expected, actual });
+        }
+        Ok(stats)
+    })
+}
+
+/// The digest of the first `sha256:<hex>` token in an application header,
+/// or of its `sha256` entry if the header is an [`AppHeaderMap`].
+///
+/// A header without either yields `None`; one whose token or entry is not
+/// 64 hex digits is an error rather than an unverified delta.
+#[cfg(feature = "file-io")]
+fn app_header_sha256(app_header: &[u8]) -> Result<Option<[u8; 32]>, DecodeError> {
+    let map = AppHeaderMap::parse(app_header).ok();
+    let digest = match &map {
+        Some(map) => map.get(SHA256_KEY),
+        None => std::str::from_utf8(app_header).ok().and_then(|text| {
+            text.split(|c: char| c.is_ascii_whitespace() || c == '/')
+                .find_map(|token| token.strip_prefix("sha256:"))
+        }),
+    };
+    digest
+        .map(|digest| {
+            parse_sha256(digest)
+                .map_err(|e| DecodeError::InvalidInput(format!("application header sha256: {e}")))
+        })
+        .transpose()
+}
+
+/// Lowercase hex of `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parse a SHA-256 digest written as 64 hex digits.
+#[cfg(any(feature = "file-io", feature = "cli"))]
+pub(crate) fn parse_sha256(s: &str) -> Result<[u8; 32], String> {
+    let s = s.trim();
+    if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("expected 64 hex digits, got '{s}'"));
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(s.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).expect("hex digits are ASCII");
+        *byte = u8::from_str_radix(pair, 16).expect("checked hex digits");
+    }
+    Ok(digest)
+}
+
+fn require_file(path: &Path) -> Result<(), IoError> {
+    match std::fs::metadata(path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(IoError::NotFound(path.to_owned())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Run `write` against a temporary file beside `out`, then sync it and
+/// rename it over `out`. The temporary file is removed on failure.
+///
+/// The temporary file is created with `create_new`, under a name carrying
+/// the process id, a per-call counter and a randomly seeded hash, so it is
+/// never an existing file or a link planted at a guessed name. On unix the
+/// parent directory is synced after the rename so the new entry is durable.
+fn write_atomically<T>(
+    out: &Path,
+    write: impl FnOnce(&File) -> Result<T, IoError>,
+) -> Result<T, IoError> {
+    let (tmp, file) = create_temp_beside(out)?;
+    let result = write(&file).and_then(|value| {
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, out)?;
+        #[cfg(unix)]
+        {
+            let parent = match out.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => Path::new("."),
+            };
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Exclusively create a fresh hidden file next to `out`.
+fn create_temp_beside(out: &Path) -> Result<(PathBuf, File), IoError> {
+    use std::hash::{BuildHasher, Hasher};
+
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+    let file_name = out.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+    })?;
+    let mut attempts = 0;
+    loop {
+        let counter = NEXT_TMP.fetch_add(1, Ordering::Relaxed);
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(counter);
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!(
+            ".oxidelta-tmp-{}-{}-{:016x}",
+            std::process::id(),
+            counter,
+            hasher.finish()
+        ));
+        let tmp = out.with_file_name(tmp_name);
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(file) => return Ok((tmp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 16 => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Memory-mapped source
+// ---------------------------------------------------------------------------
+
+/// A read-only memory-mapped source file.
+///
+/// Usable both for encoding (`SourceData`, or as a `&[u8]` via `Deref`) and
+/// decoding (`SourceProvider`). Slices point straight into the mapping, so
+/// the zero-copy paths stay active without the file being read into memory;
+/// pages are loaded on demand and can be evicted under memory pressure.
+///
+/// The constructors are `unsafe`: the mapped bytes change under the
+/// encoder or decoder if anyone modifies the file, and truncating it turns
+/// reads into SIGBUS. Read the source into memory (or use `SeekSource`)
+/// unless the file is known to stay unchanged.
+#[cfg(feature = "mmap")]
+pub struct MmapSource {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapSource {
+    /// Map the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`from_file_at`](Self::from_file_at).
+    pub unsafe fn open(path: &Path) -> io::Result<Self> {
+        // SAFETY: passed on to the caller.
+        unsafe { Self::from_file(&File::open(path)?) }
+    }
+
+    /// Map an already open file, from its start.
+    ///
+    /// # Safety
+    ///
+    /// As for [`from_file_at`](Self::from_file_at).
+    pub unsafe fn from_file(file: &File) -> io::Result<Self> {
+        // SAFETY: passed on to the caller.
+        unsafe { Self::from_file_at(file, 0) }
+    }
+
+    /// Map an already open file from byte `offset` to its end.
+    ///
+    /// # Safety
+    ///
+    /// Neither this process nor any other may modify or truncate the file
+    /// while the returned `MmapSource` is alive.
+    pub unsafe fn from_file_at(file: &File, offset: u64) -> io::Result<Self> {
+        // SAFETY: the caller keeps the file unchanged while it is mapped.
+        let map = unsafe { memmap2::MmapOptions::new().offset(offset).map(file)? };
+        Ok(Self { map })
+    }
+
+    /// The mapped bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MmapSource {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl crate::hash::matching::SourceData for MmapSource {
+    fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+    fn get_bytes(&self, offset: u64, buf: &mut [u8]) -> usize {
+        self.as_bytes().get_bytes(offset, buf)
+    }
+    fn as_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        self.as_bytes()
+            .get(offset as usize..(offset as usize).checked_add(len)?)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl crate::vcdiff::decoder::SourceProvider for MmapSource {
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let mut bytes = self.as_bytes();
+        bytes.read_source(offset, buf)
+    }
+    fn source_len(&self) -> Option<u64> {
+        Some(self.map.len() as u64)
+    }
+    fn source_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        self.as_bytes()
+            .get(offset as usize..(offset as usize).checked_add(len)?)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Block-cached reader
+// ---------------------------------------------------------------------------
+
+/// Block size of [`BlockReader`]'s cache.
+pub(crate) const CACHE_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Seekable reader with a least-recently-used cache of 64 KiB blocks.
+///
+/// Backs both `ReaderSource` (encoding) and `SeekSource` (decoding): a read
+/// seeks to each block it touches and reads it whole, unless the block is
+/// already cached, so memory use is bounded by the cache however large the
+/// reader is.
+pub(crate) struct BlockReader<R> {
+    reader: R,
+    len: u64,
+    blocks: Vec<CachedBlock>,
+    capacity: usize,
+    clock: u64,
+}
+
+struct CachedBlock {
+    index: u64,
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+impl<R: Read + io::Seek> BlockReader<R> {
+    /// Wrap `reader`, `len` bytes long, caching up to `blocks` blocks (at
+    /// least one).
+    pub(crate) fn new(reader: R, len: u64, blocks: usize) -> Self {
+        Self {
+            reader,
+            len,
+            blocks: Vec::new(),
+            capacity: blocks.max(1),
+            clock: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Copy the bytes at `offset` into `buf`, returning how many were read.
+    ///
+    /// Reads stop short at `len`, or where the reader ends before it.
+    pub(crate) fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.len {
+            return Ok(0);
+        }
+        let n = buf.len().min((self.len - offset) as usize);
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let block = self.block(pos / CACHE_BLOCK_SIZE as u64)?;
+            let within = (pos % CACHE_BLOCK_SIZE as u64) as usize;
+            if within >= block.len() {
+                break;
+            }
+            let take = (block.len() - within).min(n - done);
+            buf[done..done + take].copy_from_slice(&block[within..within + take]);
+            done += take;
+        }
+        Ok(done)
+    }
+
+    /// Block `index`, read on a miss.
+    fn block(&mut self, index: u64) -> io::Result<&[u8]> {
+        self.clock += 1;
+        let slot = match self.blocks.iter().position(|b| b.index == index) {
+            Some(slot) => slot,
+            None => {
+                let block = CachedBlock {
+                    index,
+                    data: self.read_block(index)?,
+                    last_used: 0,
+                };
+                if self.blocks.len() < self.capacity {
+                    self.blocks.push(block);
+                    self.blocks.len() - 1
+                } else {
+                    let (slot, _) = self
+                        .blocks
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, b)| b.last_used)
+                        .expect("cache holds at least one block");
+                    self.blocks[slot] = block;
+                    slot
+                }
+            }
+        };
+        let block = &mut self.blocks[slot];
+        block.last_used = self.clock;
+        Ok(&block.data)
+    }
+
+    fn read_block(&mut self, index: u64) -> io::Result<Vec<u8>> {
+        let start = index * CACHE_BLOCK_SIZE as u64;
+        let want = (self.len - start).min(CACHE_BLOCK_SIZE as u64) as usize;
+        self.reader.seek(io::SeekFrom::Start(start))?;
+        let mut data = vec![0u8; want];
+        let mut filled = 0;
+        while filled < want {
+            match self.reader.read(&mut data[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        data.truncate(filled);
+        Ok(data)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Hashing writer (used with file-io feature)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "file-io")]
+struct HashingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hasher: &'a mut sha2::Sha256,
+}
+
+#[cfg(feature = "file-io")]
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        use sha2::Digest;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join("xdelta_io_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let mut f = File::create(&path).unwrap();
+        f.write_all(data).unwrap();
+        path
+    }
+
+    fn cleanup_temp_files(paths: &[&Path]) {
+        for p in paths {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+
+    #[test]
+    fn encode_decode_file_roundtrip() {
+        let source_data = b"The quick brown fox jumps over the lazy dog. 1234567890";
+        let target_data = b"The quick brown cat sits on the lazy mat. 1234567890!!!";
+
+        let source_path = write_temp_file("source.bin", source_data);
+        let target_path = write_temp_file("target.bin", target_data);
+        let delta_path = write_temp_file("delta.vcdiff", b"");
+        let output_path = write_temp_file("output.bin", b"");
+
+        let enc_stats = encode_file(
+            &source_path,
+            &target_path,
+            &delta_path,
+            CompressOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(enc_stats.source_size, source_data.len() as u64);
+        assert_eq!(enc_stats.target_size, target_data.len() as u64);
+        assert!(enc_stats.delta_size > 0);
+        assert!(enc_stats.windows >= 1);
+
+        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
+
+        assert_eq!(dec_stats.output_size, target_data.len() as u64);
+        assert!(dec_stats.windows >= 1);
+
+        let output_data = std::fs::read(&output_path).unwrap();
+        assert_eq!(output_data, target_data);
+
+        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
+    }
+
+    #[test]
+    fn encode_decode_no_source() {
+        let target_data = b"standalone data without any source";
+
+        let source_path = write_temp_file("nosrc_source.bin", b"");
+        let target_path = write_temp_file("nosrc_target.bin", target_data);
+        let delta_path = write_temp_file("nosrc_delta.vcdiff", b"");
+        let output_path = write_temp_file("nosrc_output.bin", b"");
+
+        encode_file(
+            &source_path,
+            &target_path,
+            &delta_path,
+            CompressOptions::default(),
+        )
+        .unwrap();
+
+        decode_file(&source_path, &delta_path, &output_path).unwrap();
+
+        let output_data = std::fs::read(&output_path).unwrap();
+        assert_eq!(output_data, target_data);
+
+        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
+    }
+
+    #[cfg(feature = "file-io")]
+    #[test]
+    fn sha256_checksums_computed() {
+        let source_data = b"source for checksum test";
+        let target_data = b"target for checksum test";
+
+        let source_path = write_temp_file("sha_source.bin", source_data);
+        let target_path = write_temp_file("sha_target.bin", target_data);
+        let delta_path = write_temp_file("sha_delta.vcdiff", b"");
+        let output_path = write_temp_file("sha_output.bin", b"");
+
+        let enc_stats = encode_file(
+            &source_path,
+            &target_path,
+            &delta_path,
+            CompressOptions::default(),
+        )
+        .unwrap();
+
+        assert!(enc_stats.source_sha256.is_some());
+        assert!(enc_stats.target_sha256.is_some());
+
+        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
+
+        assert!(dec_stats.output_sha256.is_some());
+        // The output SHA-256 should match the target SHA-256 from encoding.
+        assert_eq!(dec_stats.output_sha256, enc_stats.target_sha256);
+
+        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
+    }
+
+    #[cfg(feature = "file-io")]
+    #[test]
+    fn patch_file_verified_checks_embedded_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        target.splice(9_000..9_000, b"some freshly inserted text".iter().copied());
+        let digest: [u8; 32] = sha2::Sha256::digest(&target).into();
+
+        let encode = |digest: &[u8; 32]| {
+            let opts = CompressOptions {
+                checksum: false,
+                ..Default::default()
+            };
+            let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+            enc.set_target_sha256(digest);
+            enc.write_target(&target).unwrap();
+            enc.finish().unwrap().0
+        };
+        let delta = encode(&digest);
+
+        let source_path = dir.path().join("source.bin");
+        let delta_path = dir.path().join("patch.vcdiff");
+        let out = dir.path().join("out.bin");
+        std::fs::write(&source_path, &source).unwrap();
+        std::fs::write(&delta_path, &delta).unwrap();
+
+        let stats = patch_file_verified(&source_path, &delta_path, &out).unwrap();
+        assert_eq!(stats.output_sha256, Some(digest));
+        assert_eq!(std::fs::read(&out).unwrap(), target);
+        std::fs::remove_file(&out).unwrap();
+
+        // Corrupt an ADD byte: the window decodes (no Adler-32) but the
+        // output no longer matches the header's hash.
+        let mut corrupted = delta.clone();
+        let at = corrupted.windows(8).position(|w| w == b"freshly ").unwrap();
+        corrupted[at] ^= 0x20;
+        std::fs::write(&delta_path, &corrupted).unwrap();
+        let err = patch_file_verified(&source_path, &delta_path, &out).unwrap_err();
+        assert!(
+            matches!(err, IoError::HashMismatch { expected, .. } if expected == digest),
+            "{err}"
+        );
+        assert!(!out.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // Plain `patch_file` does not look at the header.
+        patch_file(&source_path, &delta_path, &out).unwrap();
+
+        // A delta without the token is applied unverified.
+        std::fs::remove_file(&out).unwrap();
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, Default::default());
+        enc.set_app_header(b"source.bin/target.bin/".to_vec());
+        enc.write_target(&target).unwrap();
+        std::fs::write(&delta_path, enc.finish().unwrap().0).unwrap();
+        patch_file_verified(&source_path, &delta_path, &out).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), target);
+    }
+
+    #[cfg(feature = "file-io")]
+    #[test]
+    fn app_header_sha256_tokens() {
+        let digest = [0xABu8; 32];
+        let token = format!("sha256:{}", hex(&digest));
+        let sha = |header: &[u8]| app_header_sha256(header).unwrap();
+        assert_eq!(sha(token.as_bytes()), Some(digest));
+        let header = format!("old.bin/new.bin/{token} lzma");
+        assert_eq!(sha(header.as_bytes()), Some(digest));
+        assert_eq!(sha(b"old.bin/new.bin/"), None);
+        assert_eq!(sha(&[0xFF, 0xFE]), None);
+
+        let map = AppHeaderMap::from_iter([("tool", "oxidelta"), (SHA256_KEY, &hex(&digest))]);
+        assert_eq!(sha(&map.to_bytes()), Some(digest));
+        let map = AppHeaderMap::from_iter([("note", token.as_str())]);
+        assert_eq!(sha(&map.to_bytes()), None);
+
+        // A malformed digest is an error, not a header without one.
+        let bad_hex = format!("sha256:{}", "zz".repeat(32));
+        for header in [b"sha256:abcd".as_slice(), bad_hex.as_bytes()] {
+            let err = app_header_sha256(header).unwrap_err();
+            assert!(matches!(err, DecodeError::InvalidInput(_)), "{err}");
+        }
+        let map = AppHeaderMap::from_iter([(SHA256_KEY, "abcd")]);
+        assert!(app_header_sha256(&map.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn diff_files_reports_missing_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().join("present.bin");
+        std::fs::write(&present, b"data").unwrap();
+        let missing = dir.path().join("missing.bin");
+        let out = dir.path().join("out.vcdiff");
+
+        let err = diff_files(&missing, &present, &out, CompressOptions::default()).unwrap_err();
+        assert!(
+            matches!(err, IoError::NotFound(ref p) if *p == missing),
+            "{err}"
+        );
+        let err = patch_file(&present, &missing, &out).unwrap_err();
+        assert!(
+            matches!(err, IoError::NotFound(ref p) if *p == missing),
+            "{err}"
+        );
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn patch_file_keeps_existing_output_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.bin");
+        let delta = dir.path().join("bad.vcdiff");
+        let out = dir.path().join("out.bin");
+        std::fs::write(&source, b"source").unwrap();
+        std::fs::write(&delta, b"not a vcdiff stream").unwrap();
+        std::fs::write(&out, b"previous contents").unwrap();
+
+        assert!(patch_file(&source, &delta, &out).is_err());
+        assert_eq!(std::fs::read(&out).unwrap(), b"previous contents");
+        // No temporary file left behind.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn concurrent_patch_files_use_distinct_temporaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.bin");
+        let target = dir.path().join("target.bin");
+        let delta = dir.path().join("delta.vcdiff");
+        let out = dir.path().join("out.bin");
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data[1000..]).unwrap();
+        std::fs::write(&target, &data).unwrap();
+        diff_files(&source, &target, &delta, CompressOptions::default()).unwrap();
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| patch_file(&source, &delta, &out)))
+                .collect();
+            for worker in workers {
+                worker.join().unwrap().unwrap();
+            }
+        });
+        assert_eq!(std::fs::read(&out).unwrap(), data);
+        // Only the inputs and the output remain.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn temporaries_are_created_exclusively_under_unpredictable_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.bin");
+        let (a, _) = create_temp_beside(&out).unwrap();
+        let (b, _) = create_temp_beside(&out).unwrap();
+        assert_ne!(a, b);
+        // Beyond pid and counter, each name ends in a random component.
+        let suffix = |p: &Path| {
+            let name = p.file_name().unwrap().to_str().unwrap().to_owned();
+            name.rsplit('-').next().unwrap().to_owned()
+        };
+        assert_ne!(suffix(&a), suffix(&b));
+        assert_eq!(suffix(&a).len(), 16);
+        // The reserved files are fresh and empty, and `out` is untouched.
+        assert_eq!(std::fs::metadata(&a).unwrap().len(), 0);
+        assert!(!out.exists());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_source_encode_decode() {
+        use crate::compress::decoder;
+        use crate::compress::encoder::encode_all;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source.bin");
+        let source: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &source).unwrap();
+        let mut target = source.clone();
+        target[12_345] ^= 0xFF;
+        target.extend_from_slice(b"appended tail");
+
+        // SAFETY: nothing else touches the temporary file.
+        let mapped = unsafe { MmapSource::open(&path) }.unwrap();
+        assert_eq!(mapped.as_bytes(), &source[..]);
+        // Offsets need not be page-aligned.
+        let file = File::open(&path).unwrap();
+        let tail = unsafe { MmapSource::from_file_at(&file, 4097) }.unwrap();
+        assert_eq!(tail.as_bytes(), &source[4097..]);
+
+        let mut delta = Vec::new();
+        encode_all(&mut delta, &mapped, &target, CompressOptions::default()).unwrap();
+        let mut in_memory = Vec::new();
+        encode_all(&mut in_memory, &source, &target, CompressOptions::default()).unwrap();
+        assert_eq!(delta, in_memory);
+
+        let mut provider = unsafe { MmapSource::open(&path) }.unwrap();
+        let mut output = Vec::new();
+        DeltaDecoder::new(std::io::Cursor::new(&delta))
+            .decode_to(&mut provider, &mut output)
+            .unwrap();
+        assert_eq!(output, target);
+        assert_eq!(decoder::decode_all(&mapped, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn large_file_multi_window() {
+        // 1 MiB of data with small windows to force multiple windows.
+        let source_data: Vec<u8> = (0..=255u8).cycle().take(1 << 20).collect();
+        let mut target_data = source_data.clone();
+        // Modify scattered bytes.
+        for i in (0..target_data.len()).step_by(4096) {
+            target_data[i] = target_data[i].wrapping_add(1);
+        }
+
+        let source_path = write_temp_file("large_source.bin", &source_data);
+        let target_path = write_temp_file("large_target.bin", &target_data);
+        let delta_path = write_temp_file("large_delta.vcdiff", b"");
+        let output_path = write_temp_file("large_output.bin", b"");
+
+        let enc_stats = encode_file(
+            &source_path,
+            &target_path,
+            &delta_path,
+            CompressOptions {
+                window_size: 64 * 1024, // 64 KiB windows
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(enc_stats.windows > 1, "expected multiple windows");
+        assert!(
+            enc_stats.delta_size < enc_stats.target_size,
+            "delta should be smaller than target"
+        );
+
+        let dec_stats = decode_file(&source_path, &delta_path, &output_path).unwrap();
+        assert_eq!(dec_stats.output_size, target_data.len() as u64);
+
+        let output_data = std::fs::read(&output_path).unwrap();
+        assert_eq!(output_data, target_data);
+
+        cleanup_temp_files(&[&source_path, &target_path, &delta_path, &output_path]);
+    }
+}
@@ -1,31 +1,56 @@
-//! Oxidelta: VCDIFF (RFC 3284) delta encoding/decoding in Rust.
-//!
-//! The crate provides:
-//! - A pure-Rust VCDIFF engine (`vcdiff`)
-//! - High-level compression APIs (`compress`)
-//! - File-oriented helpers (`io`)
-//! - An optional CLI (`cli` feature)
-//!
-//! # Quick Start
-//!
-//! ```no_run
-//! use oxidelta::compress::encoder::{self, CompressOptions};
-//! use oxidelta::compress::decoder;
-//!
-//! let source = b"hello old world";
-//! let target = b"hello new world";
-//!
-//! let mut delta = Vec::new();
-//! encoder::encode_all(&mut delta, source, target, CompressOptions::default()).unwrap();
-//! let decoded = decoder::decode_all(source, &delta).unwrap();
-//! assert_eq!(decoded, target);
-//! ```
-
-pub mod compress;
-pub mod engine;
-pub mod hash;
-pub mod io;
-pub mod vcdiff;
-
-#[cfg(feature = "cli")]
-pub mod cli;
+//! Oxidelta: VCDIFF (RFC 3284) delta encoding/decoding in Rust.
+//!
+//! The crate provides:
+//! - A pure-Rust VCDIFF engine (`vcdiff`)
+//! - High-level compression APIs (`compress`)
+//! - One-shot `diff` / `patch` entry points (`engine`)
+//! - File-oriented helpers (`io`)
+//! - An optional CLI (`cli` feature)
+//!
+//! The default `std` feature enables everything above. With
+//! `default-features = false, features = ["alloc"]` the crate is `no_std`
+//! and only the `vcdiff` decoder is built; see [`vcdiff::decode_memory`].
+//!
+//! # Quick Start
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")] {
+//! use oxidelta::compress::encoder::{self, CompressOptions};
+//! use oxidelta::compress::decoder;
+//!
+//! let source = b"hello old world";
+//! let target = b"hello new world";
+//!
+//! let mut delta = Vec::new();
+//! encoder::encode_all(&mut delta, source, target, CompressOptions::default()).unwrap();
+//! let decoded = decoder::decode_all(source, &delta).unwrap();
+//! assert_eq!(decoded, target);
+//! # }
+//! ```
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod capabilities;
+#[cfg(feature = "std")]
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod hash;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "alloc")]
+pub mod vcdiff;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
+#[cfg(all(test, feature = "std"))]
+mod test_util;
+
+#[cfg(feature = "std")]
+pub use capabilities::{Capabilities, capabilities};
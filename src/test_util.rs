@@ -0,0 +1,34 @@
+// Shared helpers for unit tests.
+
+use alloc::vec::Vec;
+
+/// Marsaglia xorshift32: a small, deterministic generator for test data.
+///
+/// Not for anything but tests. The state must be nonzero.
+pub(crate) struct XorShift32(u32);
+
+impl XorShift32 {
+    pub(crate) fn new(seed: u32) -> Self {
+        debug_assert_ne!(seed, 0, "xorshift32 is stuck at zero");
+        Self(seed)
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// A value in `0..bound`, by plain modulo reduction.
+    pub(crate) fn below(&mut self, bound: u32) -> u32 {
+        self.next_u32() % bound
+    }
+
+    /// `n` bytes, each the low byte of one step.
+    pub(crate) fn bytes(&mut self, n: usize) -> Vec<u8> {
+        (0..n).map(|_| self.next_u32() as u8).collect()
+    }
+}
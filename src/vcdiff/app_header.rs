@@ -0,0 +1,216 @@
+// Structured application headers.
+//
+// RFC 3284 leaves the application header opaque, and xdelta3 fills it with
+// `source/target/` file names. `AppHeaderMap` is an oxidelta convention for
+// tools that want typed metadata instead: an ordered list of UTF-8
+// `(key, value)` pairs.
+//
+// Layout: the three bytes `\0kv`, then for each pair the key length (a VCDIFF
+// varint), the key, the value length and the value. The leading NUL keeps the
+// map from being mistaken for a textual header; decoders that do not know
+// the format just carry the bytes along.
+//
+// Two keys are reserved for what textual headers say with tokens: a
+// `checksum` entry of `crc32c` declares CRC-32C window checksums (the
+// `checksum:crc32c` token) and a `sha256` entry holds the target's hex
+// digest (the `sha256:<hex>` token). Inside a map only the entries count.
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::varint;
+
+/// Bytes that open an encoded [`AppHeaderMap`].
+pub const APP_HEADER_MAP_MAGIC: &[u8] = b"\0kv";
+
+/// Reserved key naming the window checksum; see [`Integrity`].
+///
+/// [`Integrity`]: super::Integrity
+pub const CHECKSUM_KEY: &str = "checksum";
+
+/// Reserved key holding the target's SHA-256 as lowercase hex.
+pub const SHA256_KEY: &str = "sha256";
+
+/// Ordered string key/value metadata stored in the application header.
+///
+/// Keys need not be unique; [`get`](Self::get) returns the first match.
+///
+/// ```
+/// use oxidelta::vcdiff::{AppHeaderMap, FileHeader};
+///
+/// let mut meta = AppHeaderMap::new();
+/// meta.push("tool", "oxidelta 0.1");
+/// meta.push("target", "new.bin");
+///
+/// let hdr = FileHeader::builder().app_header(meta.to_bytes()).build().unwrap();
+/// let parsed = AppHeaderMap::parse(hdr.app_header.as_deref().unwrap()).unwrap();
+/// assert_eq!(parsed.get("target"), Some("new.bin"));
+/// assert_eq!(parsed, meta);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppHeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl AppHeaderMap {
+    /// An empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a pair, after any existing ones.
+    pub fn push(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((key.into(), value.into()));
+    }
+
+    /// The value of the first pair with `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The pairs, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Encode the map as application header bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = APP_HEADER_MAP_MAGIC.to_vec();
+        let mut buf = [0u8; varint::MAX_VARINT_LEN];
+        for (key, value) in &self.entries {
+            for field in [key, value] {
+                let n = varint::encode_u64(field.len() as u64, &mut buf);
+                out.extend_from_slice(&buf[varint::MAX_VARINT_LEN - n..]);
+                out.extend_from_slice(field.as_bytes());
+            }
+        }
+        out
+    }
+
+    /// Parse application header bytes written by [`to_bytes`](Self::to_bytes).
+    pub fn parse(data: &[u8]) -> Result<Self, AppHeaderMapError> {
+        let mut rest = data
+            .strip_prefix(APP_HEADER_MAP_MAGIC)
+            .ok_or(AppHeaderMapError::NotAMap)?;
+        let mut map = Self::new();
+        while !rest.is_empty() {
+            let key = read_field(&mut rest)?;
+            let value = read_field(&mut rest)?;
+            map.push(key, value);
+        }
+        Ok(map)
+    }
+}
+
+/// Read one length-prefixed string off the front of `rest`.
+fn read_field(rest: &mut &[u8]) -> Result<String, AppHeaderMapError> {
+    let (len, n) = varint::read_usize(rest).map_err(|_| AppHeaderMapError::Truncated)?;
+    let end = n
+        .checked_add(len)
+        .filter(|&end| end <= rest.len())
+        .ok_or(AppHeaderMapError::Truncated)?;
+    let field = core::str::from_utf8(&rest[n..end])
+        .map_err(|_| AppHeaderMapError::InvalidUtf8)?
+        .to_string();
+    *rest = &rest[end..];
+    Ok(field)
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for AppHeaderMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            entries: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Application header bytes that are not a valid [`AppHeaderMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppHeaderMapError {
+    /// The bytes do not start with [`APP_HEADER_MAP_MAGIC`].
+    NotAMap,
+    /// A length runs past the end of the header, or a value is missing.
+    Truncated,
+    /// A key or value is not UTF-8.
+    InvalidUtf8,
+}
+
+impl core::fmt::Display for AppHeaderMapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::NotAMap => "application header is not a key/value map",
+            Self::Truncated => "application header map is truncated",
+            Self::InvalidUtf8 => "application header map entry is not UTF-8",
+        })
+    }
+}
+
+impl core::error::Error for AppHeaderMapError {}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_empty_and_non_ascii_entries() {
+        let maps = [
+            AppHeaderMap::new(),
+            AppHeaderMap::from_iter([("", "")]),
+            AppHeaderMap::from_iter([
+                ("tool", "oxidelta 0.1"),
+                ("empty", ""),
+                ("dateiname", "grüße/€.bin"),
+                ("名前", "ファイル"),
+                ("tool", "second value"),
+            ]),
+            AppHeaderMap::from_iter([("long", "x".repeat(300))]),
+        ];
+        for map in maps {
+            let bytes = map.to_bytes();
+            assert!(bytes.starts_with(APP_HEADER_MAP_MAGIC));
+            assert_eq!(AppHeaderMap::parse(&bytes).unwrap(), map);
+        }
+
+        let map = AppHeaderMap::from_iter([("k", "first"), ("empty", ""), ("k", "second")]);
+        assert_eq!(map.get("k"), Some("first"));
+        assert_eq!(map.get("empty"), Some(""));
+        assert_eq!(map.get("missing"), None);
+        assert_eq!(map.len(), 3);
+        let keys: Vec<&str> = map.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, ["k", "empty", "k"]);
+    }
+
+    #[test]
+    fn malformed_maps_are_rejected() {
+        use AppHeaderMapError::*;
+        assert_eq!(AppHeaderMap::parse(b"old.bin/new.bin/"), Err(NotAMap));
+        assert_eq!(AppHeaderMap::parse(b""), Err(NotAMap));
+        assert_eq!(AppHeaderMap::parse(b"\0kv"), Ok(AppHeaderMap::new()));
+        // Key without a value, length past the end, unterminated varint.
+        assert_eq!(AppHeaderMap::parse(b"\0kv\x01k"), Err(Truncated));
+        assert_eq!(AppHeaderMap::parse(b"\0kv\x05ab"), Err(Truncated));
+        assert_eq!(AppHeaderMap::parse(b"\0kv\x81"), Err(Truncated));
+        assert_eq!(AppHeaderMap::parse(b"\0kv\x01k\x01\xFF"), Err(InvalidUtf8));
+    }
+}
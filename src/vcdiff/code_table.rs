@@ -1,502 +1,1083 @@
-// VCDIFF default code table (RFC 3284, Section 5.6).
-//
-// Byte-for-byte compatible with xdelta3's `xd3_build_code_table` using the
-// `__rfc3284_code_table_desc` descriptor.  The generated table has exactly
-// 256 entries.
-
-/// Instruction types matching xdelta3's `xd3_rtype` constants.
-pub const XD3_NOOP: u8 = 0;
-pub const XD3_ADD: u8 = 1;
-pub const XD3_RUN: u8 = 2;
-/// COPY modes are represented as XD3_CPY + mode (0..8 for default table).
-pub const XD3_CPY: u8 = 3;
-
-/// Minimum match length for COPY instructions (RFC 3284).
-pub const MIN_MATCH: u8 = 4;
-
-/// A single entry in the 256-element VCDIFF code table.
-///
-/// Each opcode can encode one or two instructions.  When `type2 == XD3_NOOP`,
-/// the opcode encodes a single instruction.  When `size1 == 0` (or `size2 == 0`),
-/// the actual size is read as a varint from the instruction stream.
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
-pub struct CodeTableEntry {
-    pub type1: u8,
-    pub size1: u8,
-    pub type2: u8,
-    pub size2: u8,
-}
-
-/// The complete 256-entry VCDIFF code table.
-pub type CodeTable = [CodeTableEntry; 256];
-
-/// Build the default RFC 3284 code table.
-///
-/// This is an exact Rust translation of xdelta3's `xd3_build_code_table`
-/// with the `__rfc3284_code_table_desc` descriptor.
-pub fn build_default_code_table() -> CodeTable {
-    let mut tbl = [CodeTableEntry::default(); 256];
-    let mut idx: usize = 0;
-
-    // Descriptor constants (from __rfc3284_code_table_desc).
-    const ADD_SIZES: u8 = 17;
-    const NEAR_MODES: usize = 4;
-    const SAME_MODES: usize = 3;
-    const CPY_SIZES: u8 = 15;
-    const ADDCOPY_ADD_MAX: u8 = 4;
-    const ADDCOPY_NEAR_CPY_MAX: u8 = 6;
-    const ADDCOPY_SAME_CPY_MAX: u8 = 4;
-    const COPYADD_ADD_MAX: u8 = 1;
-    const COPYADD_NEAR_CPY_MAX: u8 = 4;
-    const COPYADD_SAME_CPY_MAX: u8 = 4;
-    const CPY_MODES: usize = 2 + NEAR_MODES + SAME_MODES; // 9
-
-    // --- Index 0: RUN size=0 ---
-    tbl[idx] = CodeTableEntry {
-        type1: XD3_RUN,
-        size1: 0,
-        type2: XD3_NOOP,
-        size2: 0,
-    };
-    idx += 1;
-
-    // --- Index 1: ADD size=0 ---
-    tbl[idx] = CodeTableEntry {
-        type1: XD3_ADD,
-        size1: 0,
-        type2: XD3_NOOP,
-        size2: 0,
-    };
-    idx += 1;
-
-    // --- Indices 2..18: ADD size=1..17 ---
-    for size1 in 1..=ADD_SIZES {
-        tbl[idx] = CodeTableEntry {
-            type1: XD3_ADD,
-            size1,
-            type2: XD3_NOOP,
-            size2: 0,
-        };
-        idx += 1;
-    }
-
-    // --- COPY instructions: for each mode, size=0 then sizes 4..18 ---
-    for mode in 0..CPY_MODES as u8 {
-        // size=0 (size follows as varint)
-        tbl[idx] = CodeTableEntry {
-            type1: XD3_CPY + mode,
-            size1: 0,
-            type2: XD3_NOOP,
-            size2: 0,
-        };
-        idx += 1;
-
-        // sizes MIN_MATCH..MIN_MATCH+CPY_SIZES-1
-        for size1 in MIN_MATCH..MIN_MATCH + CPY_SIZES {
-            tbl[idx] = CodeTableEntry {
-                type1: XD3_CPY + mode,
-                size1,
-                type2: XD3_NOOP,
-                size2: 0,
-            };
-            idx += 1;
-        }
-    }
-
-    // --- ADD+COPY double instructions ---
-    for mode in 0..CPY_MODES as u8 {
-        let near_limit = 2 + NEAR_MODES as u8;
-        let cpy_max = if mode < near_limit {
-            ADDCOPY_NEAR_CPY_MAX
-        } else {
-            ADDCOPY_SAME_CPY_MAX
-        };
-
-        for add_size in 1..=ADDCOPY_ADD_MAX {
-            for cpy_size in MIN_MATCH..=cpy_max {
-                tbl[idx] = CodeTableEntry {
-                    type1: XD3_ADD,
-                    size1: add_size,
-                    type2: XD3_CPY + mode,
-                    size2: cpy_size,
-                };
-                idx += 1;
-            }
-        }
-    }
-
-    // --- COPY+ADD double instructions ---
-    for mode in 0..CPY_MODES as u8 {
-        let near_limit = 2 + NEAR_MODES as u8;
-        let cpy_max = if mode < near_limit {
-            COPYADD_NEAR_CPY_MAX
-        } else {
-            COPYADD_SAME_CPY_MAX
-        };
-
-        for cpy_size in MIN_MATCH..=cpy_max {
-            for add_size in 1..=COPYADD_ADD_MAX {
-                tbl[idx] = CodeTableEntry {
-                    type1: XD3_CPY + mode,
-                    size1: cpy_size,
-                    type2: XD3_ADD,
-                    size2: add_size,
-                };
-                idx += 1;
-            }
-        }
-    }
-
-    debug_assert_eq!(idx, 256, "code table must have exactly 256 entries");
-    tbl
-}
-
-/// Return a reference to the lazily-initialized default code table.
-pub fn default_code_table() -> &'static CodeTable {
-    use std::sync::LazyLock;
-    static TABLE: LazyLock<CodeTable> = LazyLock::new(build_default_code_table);
-    &TABLE
-}
-
-// ---------------------------------------------------------------------------
-// Instruction chooser (encoder side)
-//
-// Matches xdelta3's `xd3_choose_instruction`.
-// ---------------------------------------------------------------------------
-
-/// Result of `choose_instruction`: the primary opcode and an optional
-/// double-instruction opcode that merges this instruction with the previous one.
-#[derive(Debug, Clone, Copy)]
-pub struct ChosenInstruction {
-    /// Single-instruction opcode for this instruction alone.
-    pub code1: u8,
-    /// If `Some`, a double opcode that encodes the *previous* instruction
-    /// together with this one.  The previous instruction's `code2` should
-    /// be set to this value.
-    pub code2: Option<u8>,
-}
-
-/// Instruction descriptor passed to `choose_instruction`.
-#[derive(Debug, Clone, Copy)]
-pub struct InstructionInfo {
-    /// XD3_ADD, XD3_RUN, or XD3_CPY + mode.
-    pub itype: u8,
-    /// Instruction size.
-    pub size: u32,
-}
-
-/// Choose opcode(s) for an instruction, potentially forming a double
-/// instruction with `prev` (the previously queued instruction).
-///
-/// Exact match of xdelta3's `xd3_choose_instruction`.
-pub fn choose_instruction(
-    prev: Option<&InstructionInfo>,
-    inst: &InstructionInfo,
-) -> ChosenInstruction {
-    match inst.itype {
-        XD3_RUN => ChosenInstruction {
-            code1: 0,
-            code2: None,
-        },
-
-        XD3_ADD => {
-            let mut code1 = 1u8;
-            let mut code2 = None;
-
-            if inst.size <= 17 {
-                code1 += inst.size as u8; // codes 2..18
-
-                if inst.size == 1
-                    && let Some(prev) = prev
-                    && prev.size == 4
-                    && prev.itype >= XD3_CPY
-                {
-                    // COPY(4,mode)+ADD(1) double
-                    code2 = Some(247 + (prev.itype - XD3_CPY));
-                }
-            }
-
-            ChosenInstruction { code1, code2 }
-        }
-
-        _ => {
-            // XD3_CPY + mode
-            let mode = inst.itype - XD3_CPY;
-            let mut code1 = 19 + 16 * mode; // base for this mode, size=0
-            let mut code2 = None;
-
-            if inst.size >= 4 && inst.size <= 18 {
-                code1 += (inst.size as u8) - 3; // size 4 -> +1, ... size 18 -> +15
-
-                if let Some(prev) = prev
-                    && prev.itype == XD3_ADD
-                    && prev.size <= 4
-                {
-                    if inst.size <= 6 && mode <= 5 {
-                        // ADD(1..4)+COPY(4..6) for modes 0..5
-                        code2 = Some(
-                            163 + (mode * 12)
-                                + (3 * ((prev.size as u8) - 1))
-                                + ((inst.size as u8) - 4),
-                        );
-                    } else if inst.size == 4 && mode >= 6 {
-                        // ADD(1..4)+COPY(4) for modes 6..8
-                        code2 = Some(235 + ((mode - 6) * 4) + ((prev.size as u8) - 1));
-                    }
-                }
-            }
-
-            ChosenInstruction { code1, code2 }
-        }
-    }
-}
-
-// ---------------------------------------------------------------------------
-// High-level instruction type for public API
-// ---------------------------------------------------------------------------
-
-/// Decoded instruction.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Instruction {
-    /// Emit `len` literal bytes from the data section.
-    Add { len: u32 },
-    /// Copy `len` bytes starting at `addr` (in the combined source+target address space).
-    Copy { len: u32, addr: u64, mode: u8 },
-    /// Repeat a single byte (read from data section) `len` times.
-    Run { len: u32 },
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn table_has_256_entries() {
-        let table = build_default_code_table();
-        assert_eq!(table.len(), 256);
-    }
-
-    #[test]
-    fn index_0_is_run() {
-        let t = default_code_table();
-        assert_eq!(t[0].type1, XD3_RUN);
-        assert_eq!(t[0].size1, 0);
-        assert_eq!(t[0].type2, XD3_NOOP);
-    }
-
-    #[test]
-    fn index_1_is_add_size0() {
-        let t = default_code_table();
-        assert_eq!(t[1].type1, XD3_ADD);
-        assert_eq!(t[1].size1, 0);
-        assert_eq!(t[1].type2, XD3_NOOP);
-    }
-
-    #[test]
-    fn indices_2_to_18_are_add() {
-        let t = default_code_table();
-        for (i, size) in (2..=18).zip(1..=17u8) {
-            assert_eq!(t[i].type1, XD3_ADD, "index {i}");
-            assert_eq!(t[i].size1, size, "index {i}");
-            assert_eq!(t[i].type2, XD3_NOOP, "index {i}");
-        }
-    }
-
-    #[test]
-    fn copy_mode_0_starts_at_19() {
-        let t = default_code_table();
-        // Index 19: CPY mode=0, size=0
-        assert_eq!(t[19].type1, XD3_CPY);
-        assert_eq!(t[19].size1, 0);
-        // Index 20: CPY mode=0, size=4
-        assert_eq!(t[20].type1, XD3_CPY);
-        assert_eq!(t[20].size1, 4);
-        // Index 34: CPY mode=0, size=18
-        assert_eq!(t[34].type1, XD3_CPY);
-        assert_eq!(t[34].size1, 18);
-    }
-
-    #[test]
-    fn copy_mode_1_starts_at_35() {
-        let t = default_code_table();
-        assert_eq!(t[35].type1, XD3_CPY + 1);
-        assert_eq!(t[35].size1, 0);
-    }
-
-    #[test]
-    fn last_copy_mode_8() {
-        let t = default_code_table();
-        // Mode 8: starts at 19 + 8*16 = 147
-        assert_eq!(t[147].type1, XD3_CPY + 8);
-        assert_eq!(t[147].size1, 0);
-        assert_eq!(t[162].type1, XD3_CPY + 8);
-        assert_eq!(t[162].size1, 18);
-    }
-
-    #[test]
-    fn add_copy_doubles_start_at_163() {
-        let t = default_code_table();
-        // Index 163: ADD(1)+CPY(4,mode=0)
-        assert_eq!(t[163].type1, XD3_ADD);
-        assert_eq!(t[163].size1, 1);
-        assert_eq!(t[163].type2, XD3_CPY);
-        assert_eq!(t[163].size2, 4);
-    }
-
-    #[test]
-    fn copy_add_doubles_start_at_247() {
-        let t = default_code_table();
-        // Index 247: CPY(4,mode=0)+ADD(1)
-        assert_eq!(t[247].type1, XD3_CPY);
-        assert_eq!(t[247].size1, 4);
-        assert_eq!(t[247].type2, XD3_ADD);
-        assert_eq!(t[247].size2, 1);
-    }
-
-    #[test]
-    fn index_255_is_last() {
-        let t = default_code_table();
-        // Index 255: CPY(4,mode=8)+ADD(1)
-        assert_eq!(t[255].type1, XD3_CPY + 8);
-        assert_eq!(t[255].size1, 4);
-        assert_eq!(t[255].type2, XD3_ADD);
-        assert_eq!(t[255].size2, 1);
-    }
-
-    #[test]
-    fn all_doubles_have_nonzero_sizes() {
-        let t = default_code_table();
-        for (i, entry) in t.iter().enumerate() {
-            if entry.type2 != XD3_NOOP {
-                assert_ne!(entry.size1, 0, "double at {i} has size1=0");
-                assert_ne!(entry.size2, 0, "double at {i} has size2=0");
-            }
-        }
-    }
-
-    #[test]
-    fn choose_run() {
-        let r = choose_instruction(
-            None,
-            &InstructionInfo {
-                itype: XD3_RUN,
-                size: 10,
-            },
-        );
-        assert_eq!(r.code1, 0);
-        assert!(r.code2.is_none());
-    }
-
-    #[test]
-    fn choose_add_small() {
-        for size in 1..=17u32 {
-            let r = choose_instruction(
-                None,
-                &InstructionInfo {
-                    itype: XD3_ADD,
-                    size,
-                },
-            );
-            assert_eq!(r.code1, 1 + size as u8);
-        }
-    }
-
-    #[test]
-    fn choose_add_large() {
-        let r = choose_instruction(
-            None,
-            &InstructionInfo {
-                itype: XD3_ADD,
-                size: 18,
-            },
-        );
-        assert_eq!(r.code1, 1); // size=0 entry, size emitted separately
-    }
-
-    #[test]
-    fn choose_copy_mode0_size4() {
-        let r = choose_instruction(
-            None,
-            &InstructionInfo {
-                itype: XD3_CPY,
-                size: 4,
-            },
-        );
-        assert_eq!(r.code1, 20); // 19 + (4-3) = 20
-    }
-
-    #[test]
-    fn choose_double_add_copy() {
-        let prev = InstructionInfo {
-            itype: XD3_ADD,
-            size: 1,
-        };
-        let inst = InstructionInfo {
-            itype: XD3_CPY,
-            size: 4,
-        };
-        let r = choose_instruction(Some(&prev), &inst);
-        assert_eq!(r.code2, Some(163)); // ADD(1)+CPY(4,mode=0)
-    }
-
-    #[test]
-    fn choose_double_copy_add() {
-        let prev = InstructionInfo {
-            itype: XD3_CPY,
-            size: 4,
-        };
-        let inst = InstructionInfo {
-            itype: XD3_ADD,
-            size: 1,
-        };
-        let r = choose_instruction(Some(&prev), &inst);
-        assert_eq!(r.code2, Some(247)); // CPY(4,mode=0)+ADD(1)
-    }
-
-    #[test]
-    fn choose_double_add_copy_mode6() {
-        let prev = InstructionInfo {
-            itype: XD3_ADD,
-            size: 2,
-        };
-        let inst = InstructionInfo {
-            itype: XD3_CPY + 6,
-            size: 4,
-        };
-        let r = choose_instruction(Some(&prev), &inst);
-        // 235 + (6-6)*4 + (2-1) = 236
-        assert_eq!(r.code2, Some(236));
-    }
-
-    #[test]
-    fn code_table_matches_descriptor_offsets() {
-        // Verify the descriptor offsets from __rfc3284_code_table_desc.
-        // addcopy_max_sizes[0] = {6, 163, 3} means:
-        //   mode 0 add-copy doubles start at index 163, max cpy=6, mult=3
-        let t = default_code_table();
-
-        // Mode 0: starts at 163, 4 add sizes * 3 cpy sizes = 12 entries
-        assert_eq!(t[163].type1, XD3_ADD);
-        assert_eq!(t[163].type2, XD3_CPY);
-        assert_eq!(t[174].type1, XD3_ADD);
-        assert_eq!(t[174].type2, XD3_CPY);
-
-        // Mode 1: starts at 175
-        assert_eq!(t[175].type1, XD3_ADD);
-        assert_eq!(t[175].type2, XD3_CPY + 1);
-
-        // Modes 6,7,8 (SAME modes): 4 add * 1 cpy = 4 entries each
-        // Mode 6 starts at 235
-        assert_eq!(t[235].type1, XD3_ADD);
-        assert_eq!(t[235].type2, XD3_CPY + 6);
-        assert_eq!(t[235].size2, 4);
-
-        // copyadd_max_sizes[0] = {4, 247, 1}
-        assert_eq!(t[247].type1, XD3_CPY);
-        assert_eq!(t[247].type2, XD3_ADD);
-    }
-}
+// VCDIFF default code table (RFC 3284, Section 5.6).
+//
+// Byte-for-byte compatible with xdelta3's `xd3_build_code_table` using the
+// `__rfc3284_code_table_desc` descriptor.  The generated table has exactly
+// 256 entries.
+//
+// Application-defined tables (RFC 3284, Section 7) are serialized as a
+// 1536-byte string and shipped as a VCDIFF delta against the default
+// table's string.
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use super::decoder::{self, DecodeError};
+#[cfg(feature = "std")]
+use super::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
+use super::header::{VCD_APPHEADER, VCD_CODETABLE, VCD_SECONDARY};
+
+/// Instruction types matching xdelta3's `xd3_rtype` constants.
+pub const XD3_NOOP: u8 = 0;
+pub const XD3_ADD: u8 = 1;
+pub const XD3_RUN: u8 = 2;
+/// COPY modes are represented as XD3_CPY + mode (0..8 for default table).
+pub const XD3_CPY: u8 = 3;
+
+/// Minimum match length for COPY instructions (RFC 3284).
+pub const MIN_MATCH: u8 = 4;
+
+/// A single entry in the 256-element VCDIFF code table.
+///
+/// Each opcode can encode one or two instructions.  When `type2 == XD3_NOOP`,
+/// the opcode encodes a single instruction.  When `size1 == 0` (or `size2 == 0`),
+/// the actual size is read as a varint from the instruction stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CodeTableEntry {
+    pub type1: u8,
+    pub size1: u8,
+    pub type2: u8,
+    pub size2: u8,
+}
+
+/// The complete 256-entry VCDIFF code table, indexed by opcode.
+///
+/// Dereferences to the entry array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeTable(pub [CodeTableEntry; 256]);
+
+impl core::ops::Deref for CodeTable {
+    type Target = [CodeTableEntry; 256];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl core::ops::DerefMut for CodeTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl From<[CodeTableEntry; 256]> for CodeTable {
+    fn from(entries: [CodeTableEntry; 256]) -> Self {
+        Self(entries)
+    }
+}
+
+/// Build the default RFC 3284 code table.
+///
+/// This is an exact Rust translation of xdelta3's `xd3_build_code_table`
+/// with the `__rfc3284_code_table_desc` descriptor.  It is a `const fn` so
+/// the table can live in a `static` without lazy initialization.
+pub const fn build_default_code_table() -> CodeTable {
+    const fn entry(type1: u8, size1: u8, type2: u8, size2: u8) -> CodeTableEntry {
+        CodeTableEntry {
+            type1,
+            size1,
+            type2,
+            size2,
+        }
+    }
+
+    let mut tbl = [entry(XD3_NOOP, 0, XD3_NOOP, 0); 256];
+    let mut idx: usize = 0;
+
+    // Descriptor constants (from __rfc3284_code_table_desc).
+    const ADD_SIZES: u8 = 17;
+    const NEAR_MODES: usize = 4;
+    const SAME_MODES: usize = 3;
+    const CPY_SIZES: u8 = 15;
+    const ADDCOPY_ADD_MAX: u8 = 4;
+    const ADDCOPY_NEAR_CPY_MAX: u8 = 6;
+    const ADDCOPY_SAME_CPY_MAX: u8 = 4;
+    const COPYADD_ADD_MAX: u8 = 1;
+    const COPYADD_NEAR_CPY_MAX: u8 = 4;
+    const COPYADD_SAME_CPY_MAX: u8 = 4;
+    const CPY_MODES: u8 = (2 + NEAR_MODES + SAME_MODES) as u8; // 9
+    const NEAR_LIMIT: u8 = 2 + NEAR_MODES as u8;
+
+    // `for` loops are not allowed in a const fn, hence the `while` loops.
+
+    // --- Index 0: RUN size=0 ---
+    tbl[idx] = entry(XD3_RUN, 0, XD3_NOOP, 0);
+    idx += 1;
+
+    // --- Index 1: ADD size=0 ---
+    tbl[idx] = entry(XD3_ADD, 0, XD3_NOOP, 0);
+    idx += 1;
+
+    // --- Indices 2..18: ADD size=1..17 ---
+    let mut size1 = 1;
+    while size1 <= ADD_SIZES {
+        tbl[idx] = entry(XD3_ADD, size1, XD3_NOOP, 0);
+        idx += 1;
+        size1 += 1;
+    }
+
+    // --- COPY instructions: for each mode, size=0 then sizes 4..18 ---
+    let mut mode = 0;
+    while mode < CPY_MODES {
+        // size=0 (size follows as varint)
+        tbl[idx] = entry(XD3_CPY + mode, 0, XD3_NOOP, 0);
+        idx += 1;
+
+        // sizes MIN_MATCH..MIN_MATCH+CPY_SIZES-1
+        let mut size1 = MIN_MATCH;
+        while size1 < MIN_MATCH + CPY_SIZES {
+            tbl[idx] = entry(XD3_CPY + mode, size1, XD3_NOOP, 0);
+            idx += 1;
+            size1 += 1;
+        }
+        mode += 1;
+    }
+
+    // --- ADD+COPY double instructions ---
+    let mut mode = 0;
+    while mode < CPY_MODES {
+        let cpy_max = if mode < NEAR_LIMIT {
+            ADDCOPY_NEAR_CPY_MAX
+        } else {
+            ADDCOPY_SAME_CPY_MAX
+        };
+
+        let mut add_size = 1;
+        while add_size <= ADDCOPY_ADD_MAX {
+            let mut cpy_size = MIN_MATCH;
+            while cpy_size <= cpy_max {
+                tbl[idx] = entry(XD3_ADD, add_size, XD3_CPY + mode, cpy_size);
+                idx += 1;
+                cpy_size += 1;
+            }
+            add_size += 1;
+        }
+        mode += 1;
+    }
+
+    // --- COPY+ADD double instructions ---
+    let mut mode = 0;
+    while mode < CPY_MODES {
+        let cpy_max = if mode < NEAR_LIMIT {
+            COPYADD_NEAR_CPY_MAX
+        } else {
+            COPYADD_SAME_CPY_MAX
+        };
+
+        let mut cpy_size = MIN_MATCH;
+        while cpy_size <= cpy_max {
+            let mut add_size = 1;
+            while add_size <= COPYADD_ADD_MAX {
+                tbl[idx] = entry(XD3_CPY + mode, cpy_size, XD3_ADD, add_size);
+                idx += 1;
+                add_size += 1;
+            }
+            cpy_size += 1;
+        }
+        mode += 1;
+    }
+
+    assert!(idx == 256, "code table must have exactly 256 entries");
+    CodeTable(tbl)
+}
+
+/// Return a reference to the default code table.
+pub fn default_code_table() -> &'static CodeTable {
+    static TABLE: CodeTable = build_default_code_table();
+    &TABLE
+}
+
+// ---------------------------------------------------------------------------
+// Instruction chooser (encoder side)
+//
+// Matches xdelta3's `xd3_choose_instruction`.
+// ---------------------------------------------------------------------------
+
+/// Result of `choose_instruction`: the primary opcode and an optional
+/// double-instruction opcode that merges this instruction with the previous one.
+#[derive(Debug, Clone, Copy)]
+pub struct ChosenInstruction {
+    /// Single-instruction opcode for this instruction alone.
+    pub code1: u8,
+    /// If `Some`, a double opcode that encodes the *previous* instruction
+    /// together with this one.  The previous instruction's `code2` should
+    /// be set to this value.
+    pub code2: Option<u8>,
+}
+
+/// Instruction descriptor passed to `choose_instruction`.
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionInfo {
+    /// XD3_ADD, XD3_RUN, or XD3_CPY + mode.
+    pub itype: u8,
+    /// Instruction size.
+    pub size: u32,
+}
+
+/// Choose opcode(s) for an instruction, potentially forming a double
+/// instruction with `prev` (the previously queued instruction).
+///
+/// Exact match of xdelta3's `xd3_choose_instruction`.
+pub fn choose_instruction(
+    prev: Option<&InstructionInfo>,
+    inst: &InstructionInfo,
+) -> ChosenInstruction {
+    match inst.itype {
+        XD3_RUN => ChosenInstruction {
+            code1: 0,
+            code2: None,
+        },
+
+        XD3_ADD => {
+            let mut code1 = 1u8;
+            let mut code2 = None;
+
+            if inst.size <= 17 {
+                code1 += inst.size as u8; // codes 2..18
+
+                if inst.size == 1
+                    && let Some(prev) = prev
+                    && prev.size == 4
+                    && prev.itype >= XD3_CPY
+                {
+                    // COPY(4,mode)+ADD(1) double
+                    code2 = Some(247 + (prev.itype - XD3_CPY));
+                }
+            }
+
+            ChosenInstruction { code1, code2 }
+        }
+
+        _ => {
+            // XD3_CPY + mode
+            let mode = inst.itype - XD3_CPY;
+            let mut code1 = 19 + 16 * mode; // base for this mode, size=0
+            let mut code2 = None;
+
+            if inst.size >= 4 && inst.size <= 18 {
+                code1 += (inst.size as u8) - 3; // size 4 -> +1, ... size 18 -> +15
+
+                if let Some(prev) = prev
+                    && prev.itype == XD3_ADD
+                    && prev.size <= 4
+                {
+                    if inst.size <= 6 && mode <= 5 {
+                        // ADD(1..4)+COPY(4..6) for modes 0..5
+                        code2 = Some(
+                            163 + (mode * 12)
+                                + (3 * ((prev.size as u8) - 1))
+                                + ((inst.size as u8) - 4),
+                        );
+                    } else if inst.size == 4 && mode >= 6 {
+                        // ADD(1..4)+COPY(4) for modes 6..8
+                        code2 = Some(235 + ((mode - 6) * 4) + ((prev.size as u8) - 1));
+                    }
+                }
+            }
+
+            ChosenInstruction { code1, code2 }
+        }
+    }
+}
+
+impl From<&Instruction> for InstructionInfo {
+    fn from(inst: &Instruction) -> Self {
+        match *inst {
+            Instruction::Add { len } => Self {
+                itype: XD3_ADD,
+                size: len,
+            },
+            Instruction::Copy { len, mode, .. } => Self {
+                itype: XD3_CPY + mode,
+                size: len,
+            },
+            Instruction::Run { len } => Self {
+                itype: XD3_RUN,
+                size: len,
+            },
+        }
+    }
+}
+
+impl CodeTable {
+    /// Opcode for `first` on its own, or for `first` and `second` packed
+    /// into one double-instruction opcode.
+    ///
+    /// `WindowEncoder` picks its opcodes with this. On the default table it
+    /// follows xdelta3's rules (see [`choose_instruction`]); other tables
+    /// are searched for an entry with exactly these types and sizes, and a
+    /// lone instruction falls back to its varint-size entry. `None` means
+    /// the table has no opcode for the pair, so each instruction gets its
+    /// own; it is also returned for empty instructions and for instruction
+    /// types the table lacks. COPY addresses are ignored.
+    ///
+    /// ```
+    /// use oxidelta::vcdiff::{Instruction, code_table::default_code_table};
+    /// let table = default_code_table();
+    /// let add = Instruction::Add { len: 2 };
+    /// let copy = Instruction::Copy { len: 5, addr: 0, mode: 1 };
+    /// assert_eq!(table.encode_pair(&add, None), Some(3));
+    /// assert_eq!(table.encode_pair(&add, Some(&copy)), Some(179));
+    /// assert_eq!(table.encode_pair(&copy, Some(&add)), None);
+    /// ```
+    pub fn encode_pair(&self, first: &Instruction, second: Option<&Instruction>) -> Option<u8> {
+        let second = second.map(InstructionInfo::from);
+        self.pair_opcode(&InstructionInfo::from(first), second.as_ref())
+    }
+
+    /// [`encode_pair`](Self::encode_pair) on instruction descriptors.
+    pub(crate) fn pair_opcode(
+        &self,
+        first: &InstructionInfo,
+        second: Option<&InstructionInfo>,
+    ) -> Option<u8> {
+        if first.size == 0 || second.is_some_and(|second| second.size == 0) {
+            return None;
+        }
+        if core::ptr::eq(self, default_code_table()) {
+            if first.itype > XD3_CPY + 8 || second.is_some_and(|s| s.itype > XD3_CPY + 8) {
+                return None;
+            }
+            return match second {
+                None => Some(choose_instruction(None, first).code1),
+                Some(second) => choose_instruction(Some(first), second).code2,
+            };
+        }
+
+        let sized = |itype: u8, size: u8, info: &InstructionInfo| {
+            itype == info.itype && size != 0 && u32::from(size) == info.size
+        };
+        let opcode = match second {
+            None => self
+                .iter()
+                .position(|e| e.type2 == XD3_NOOP && sized(e.type1, e.size1, first))
+                .or_else(|| {
+                    self.iter()
+                        .position(|e| e.type1 == first.itype && e.size1 == 0 && e.type2 == XD3_NOOP)
+                }),
+            Some(second) => self
+                .iter()
+                .position(|e| sized(e.type1, e.size1, first) && sized(e.type2, e.size2, second)),
+        };
+        opcode.map(|opcode| opcode as u8)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Application-defined code tables (RFC 3284, Section 7)
+// ---------------------------------------------------------------------------
+
+/// Length of a serialized code table: six 256-byte arrays.
+pub const CODE_TABLE_STRING_LEN: usize = 6 * 256;
+
+/// RFC 3284 instruction number for COPY in the serialized table.
+const RFC_COPY: u8 = 3;
+
+/// An application-defined code table with its address cache sizes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomCodeTable {
+    /// NEAR cache size (`s_near`).
+    pub near: u8,
+    /// SAME cache size (`s_same`).
+    pub same: u8,
+    /// The 256 opcode entries. COPY types are `XD3_CPY + mode`.
+    pub table: CodeTable,
+}
+
+impl CustomCodeTable {
+    /// Create a custom table, checking that every COPY mode fits the
+    /// address cache described by `near` and `same`.
+    pub fn new(near: u8, same: u8, table: CodeTable) -> Result<Self, CodeTableError> {
+        let mode_count = 2 + near as usize + same as usize;
+        if XD3_CPY as usize + mode_count > 256 {
+            return Err(CodeTableError::CacheSizes { near, same });
+        }
+        for (opcode, entry) in table.iter().enumerate() {
+            for itype in [entry.type1, entry.type2] {
+                if itype >= XD3_CPY && (itype - XD3_CPY) as usize >= mode_count {
+                    return Err(CodeTableError::InvalidMode {
+                        opcode: opcode as u8,
+                        mode: itype - XD3_CPY,
+                    });
+                }
+            }
+        }
+        Ok(Self { near, same, table })
+    }
+
+    /// Total number of address modes (2 + near + same).
+    pub fn mode_count(&self) -> usize {
+        2 + self.near as usize + self.same as usize
+    }
+
+    /// Number of opcodes whose entry differs from the RFC 3284 default table.
+    pub fn changed_entries(&self) -> usize {
+        self.table
+            .iter()
+            .zip(default_code_table().iter())
+            .filter(|(custom, default)| custom != default)
+            .count()
+    }
+
+    /// Parse a table from its serialized string form.
+    pub fn from_string(near: u8, same: u8, string: &[u8]) -> Result<Self, CodeTableError> {
+        if string.len() != CODE_TABLE_STRING_LEN {
+            return Err(CodeTableError::InvalidLength(string.len()));
+        }
+        let array = |i: usize| &string[i * 256..(i + 1) * 256];
+        let (inst1, inst2, size1, size2, mode1, mode2) =
+            (array(0), array(1), array(2), array(3), array(4), array(5));
+
+        let join = |opcode: usize, inst: u8, mode: u8| match inst {
+            XD3_NOOP | XD3_ADD | XD3_RUN => Ok(inst),
+            RFC_COPY if mode <= u8::MAX - XD3_CPY => Ok(XD3_CPY + mode),
+            RFC_COPY => Err(CodeTableError::InvalidMode {
+                opcode: opcode as u8,
+                mode,
+            }),
+            _ => Err(CodeTableError::InvalidInstruction {
+                opcode: opcode as u8,
+                inst,
+            }),
+        };
+
+        let mut table = CodeTable([CodeTableEntry::default(); 256]);
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = CodeTableEntry {
+                type1: join(i, inst1[i], mode1[i])?,
+                size1: size1[i],
+                type2: join(i, inst2[i], mode2[i])?,
+                size2: size2[i],
+            };
+        }
+        Self::new(near, same, table)
+    }
+
+    /// Serialize the table to the RFC 3284 string form: the `inst1`,
+    /// `inst2`, `size1`, `size2`, `mode1` and `mode2` arrays in order.
+    pub fn to_string_form(&self) -> Vec<u8> {
+        code_table_string(&self.table)
+    }
+
+    /// Encode the header's code table section: `near`, `same`, then the
+    /// table string as a VCDIFF delta against the default table's string.
+    #[cfg(feature = "std")]
+    pub fn encode_section(&self) -> Vec<u8> {
+        let default = code_table_string(default_code_table());
+        let custom = self.to_string_form();
+
+        let mut delta = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, false);
+        let mut we = WindowEncoder::new(
+            Some(SourceWindow {
+                len: CODE_TABLE_STRING_LEN as u64,
+                offset: 0,
+            }),
+            false,
+        );
+        // The strings are aligned, so a positional diff is enough: COPY the
+        // unchanged stretches in place and ADD the rest.
+        let mut pos = 0;
+        let mut add_start = 0;
+        while pos < CODE_TABLE_STRING_LEN {
+            let same = default[pos..]
+                .iter()
+                .zip(&custom[pos..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if same >= MIN_MATCH as usize {
+                we.add(&custom[add_start..pos]);
+                we.copy_with_auto_mode(same as u32, pos as u64);
+                pos += same;
+                add_start = pos;
+            } else {
+                pos += same.max(1);
+            }
+        }
+        we.add(&custom[add_start..]);
+        enc.write_window(we, None).unwrap();
+        enc.finish().unwrap();
+
+        let mut out = Vec::with_capacity(2 + delta.len());
+        out.push(self.near);
+        out.push(self.same);
+        out.extend_from_slice(&delta);
+        out
+    }
+
+    /// Decode a header's code table section (see [`encode_section`]).
+    ///
+    /// [`encode_section`]: Self::encode_section
+    pub fn decode_section(data: &[u8]) -> Result<Self, CodeTableError> {
+        let [near, same, delta @ ..] = data else {
+            return Err(CodeTableError::InvalidLength(data.len()));
+        };
+        let default = code_table_string(default_code_table());
+        let string = decode_table_delta(delta, &default)
+            .map_err(|e| CodeTableError::Delta(e.to_string()))?;
+        Self::from_string(*near, *same, &string)
+    }
+}
+
+/// Decode the delta in a code table section. Its header may not carry a
+/// code table, secondary compressor or application header of its own, so
+/// decoding it never reaches another table section.
+fn decode_table_delta(delta: &[u8], default: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    const NESTED: u8 = VCD_SECONDARY | VCD_CODETABLE | VCD_APPHEADER;
+    if delta.get(4).is_some_and(|&hdr_ind| hdr_ind & NESTED != 0) {
+        return Err(DecodeError::InvalidInput(
+            "code table delta has its own secondary, code table or application header".into(),
+        ));
+    }
+    decoder::decode_memory(delta, default)
+}
+
+/// Serialize any code table to the RFC 3284 string form.
+fn code_table_string(table: &CodeTable) -> Vec<u8> {
+    let split = |itype: u8| {
+        if itype >= XD3_CPY {
+            (RFC_COPY, itype - XD3_CPY)
+        } else {
+            (itype, 0)
+        }
+    };
+    let mut out = vec![0u8; CODE_TABLE_STRING_LEN];
+    for (i, entry) in table.iter().enumerate() {
+        let (inst1, mode1) = split(entry.type1);
+        let (inst2, mode2) = split(entry.type2);
+        out[i] = inst1;
+        out[256 + i] = inst2;
+        out[512 + i] = entry.size1;
+        out[768 + i] = entry.size2;
+        out[1024 + i] = mode1;
+        out[1280 + i] = mode2;
+    }
+    out
+}
+
+/// Opcode lookup for encoding with a [`CustomCodeTable`].
+///
+/// The encoder-side counterpart of `choose_instruction` for tables other
+/// than the default. Where several opcodes fit, the lowest one is used.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct CodeTableIndex {
+    table: CustomCodeTable,
+    /// Single instructions with an explicit size: (type, size) -> opcode.
+    sized: HashMap<(u8, u8), u8>,
+    /// Single instructions whose size follows as a varint, by type.
+    varsize: [Option<u8>; 256],
+    /// Double instructions: (type1, size1, type2, size2) -> opcode.
+    double: HashMap<(u8, u8, u8, u8), u8>,
+}
+
+#[cfg(feature = "std")]
+impl CodeTableIndex {
+    /// Index `table` for encoding.
+    ///
+    /// Fails if ADD, RUN or any COPY mode lacks a single-instruction opcode
+    /// with a varint size, since such instructions could not be encoded.
+    pub fn new(table: CustomCodeTable) -> Result<Self, CodeTableError> {
+        let mut sized = HashMap::new();
+        let mut varsize = [None; 256];
+        let mut double = HashMap::new();
+        for (opcode, e) in table.table.iter().enumerate().rev() {
+            let opcode = opcode as u8;
+            match (e.type1, e.type2) {
+                (XD3_NOOP, _) => {}
+                (t1, XD3_NOOP) if e.size1 == 0 => varsize[t1 as usize] = Some(opcode),
+                (t1, XD3_NOOP) => {
+                    sized.insert((t1, e.size1), opcode);
+                }
+                (t1, t2) if e.size1 != 0 && e.size2 != 0 => {
+                    double.insert((t1, e.size1, t2, e.size2), opcode);
+                }
+                _ => {}
+            }
+        }
+
+        let required = [XD3_ADD, XD3_RUN]
+            .into_iter()
+            .chain((0..table.mode_count() as u8).map(|mode| XD3_CPY + mode));
+        for itype in required {
+            if varsize[itype as usize].is_none() {
+                return Err(CodeTableError::MissingOpcode { itype });
+            }
+        }
+
+        Ok(Self {
+            table,
+            sized,
+            varsize,
+            double,
+        })
+    }
+
+    /// The indexed table.
+    pub fn table(&self) -> &CustomCodeTable {
+        &self.table
+    }
+
+    /// Choose opcode(s) for `inst`, possibly pairing it with `prev`.
+    pub fn choose(
+        &self,
+        prev: Option<&InstructionInfo>,
+        inst: &InstructionInfo,
+    ) -> ChosenInstruction {
+        let size = u8::try_from(inst.size).ok();
+        let code1 = size
+            .and_then(|size| self.sized.get(&(inst.itype, size)).copied())
+            .or(self.varsize[inst.itype as usize])
+            .expect("instruction type validated in CodeTableIndex::new");
+        let code2 = prev.zip(size).and_then(|(prev, size)| {
+            let prev_size = u8::try_from(prev.size).ok()?;
+            self.double
+                .get(&(prev.itype, prev_size, inst.itype, size))
+                .copied()
+        });
+        ChosenInstruction { code1, code2 }
+    }
+}
+
+/// Errors from parsing or validating an application-defined code table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeTableError {
+    /// The table string or section has the wrong length.
+    InvalidLength(usize),
+    /// An entry uses an instruction number other than NOOP/ADD/RUN/COPY.
+    InvalidInstruction { opcode: u8, inst: u8 },
+    /// An entry uses a COPY mode outside the address cache.
+    InvalidMode { opcode: u8, mode: u8 },
+    /// NEAR and SAME sizes give more modes than a code table can address.
+    CacheSizes { near: u8, same: u8 },
+    /// No single-instruction, varint-size opcode exists for this type.
+    MissingOpcode { itype: u8 },
+    /// The embedded table delta failed to decode.
+    Delta(String),
+}
+
+impl core::fmt::Display for CodeTableError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidLength(len) => write!(f, "invalid code table length: {len}"),
+            Self::InvalidInstruction { opcode, inst } => {
+                write!(f, "code table opcode {opcode}: invalid instruction {inst}")
+            }
+            Self::InvalidMode { opcode, mode } => {
+                write!(f, "code table opcode {opcode}: invalid COPY mode {mode}")
+            }
+            Self::CacheSizes { near, same } => {
+                write!(f, "invalid address cache sizes: near={near} same={same}")
+            }
+            Self::MissingOpcode { itype } => {
+                write!(f, "code table has no variable-size opcode for type {itype}")
+            }
+            Self::Delta(e) => write!(f, "code table delta: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for CodeTableError {}
+
+// ---------------------------------------------------------------------------
+// High-level instruction type for public API
+// ---------------------------------------------------------------------------
+
+/// Decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Emit `len` literal bytes from the data section.
+    Add { len: u32 },
+    /// Copy `len` bytes starting at `addr` (in the combined source+target address space).
+    Copy { len: u32, addr: u64, mode: u8 },
+    /// Repeat a single byte (read from data section) `len` times.
+    Run { len: u32 },
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_has_256_entries() {
+        let table = build_default_code_table();
+        assert_eq!(table.len(), 256);
+    }
+
+    #[test]
+    fn index_0_is_run() {
+        let t = default_code_table();
+        assert_eq!(t[0].type1, XD3_RUN);
+        assert_eq!(t[0].size1, 0);
+        assert_eq!(t[0].type2, XD3_NOOP);
+    }
+
+    #[test]
+    fn index_1_is_add_size0() {
+        let t = default_code_table();
+        assert_eq!(t[1].type1, XD3_ADD);
+        assert_eq!(t[1].size1, 0);
+        assert_eq!(t[1].type2, XD3_NOOP);
+    }
+
+    #[test]
+    fn indices_2_to_18_are_add() {
+        let t = default_code_table();
+        for (i, size) in (2..=18).zip(1..=17u8) {
+            assert_eq!(t[i].type1, XD3_ADD, "index {i}");
+            assert_eq!(t[i].size1, size, "index {i}");
+            assert_eq!(t[i].type2, XD3_NOOP, "index {i}");
+        }
+    }
+
+    #[test]
+    fn copy_mode_0_starts_at_19() {
+        let t = default_code_table();
+        // Index 19: CPY mode=0, size=0
+        assert_eq!(t[19].type1, XD3_CPY);
+        assert_eq!(t[19].size1, 0);
+        // Index 20: CPY mode=0, size=4
+        assert_eq!(t[20].type1, XD3_CPY);
+        assert_eq!(t[20].size1, 4);
+        // Index 34: CPY mode=0, size=18
+        assert_eq!(t[34].type1, XD3_CPY);
+        assert_eq!(t[34].size1, 18);
+    }
+
+    #[test]
+    fn copy_mode_1_starts_at_35() {
+        let t = default_code_table();
+        assert_eq!(t[35].type1, XD3_CPY + 1);
+        assert_eq!(t[35].size1, 0);
+    }
+
+    #[test]
+    fn last_copy_mode_8() {
+        let t = default_code_table();
+        // Mode 8: starts at 19 + 8*16 = 147
+        assert_eq!(t[147].type1, XD3_CPY + 8);
+        assert_eq!(t[147].size1, 0);
+        assert_eq!(t[162].type1, XD3_CPY + 8);
+        assert_eq!(t[162].size1, 18);
+    }
+
+    #[test]
+    fn add_copy_doubles_start_at_163() {
+        let t = default_code_table();
+        // Index 163: ADD(1)+CPY(4,mode=0)
+        assert_eq!(t[163].type1, XD3_ADD);
+        assert_eq!(t[163].size1, 1);
+        assert_eq!(t[163].type2, XD3_CPY);
+        assert_eq!(t[163].size2, 4);
+    }
+
+    #[test]
+    fn copy_add_doubles_start_at_247() {
+        let t = default_code_table();
+        // Index 247: CPY(4,mode=0)+ADD(1)
+        assert_eq!(t[247].type1, XD3_CPY);
+        assert_eq!(t[247].size1, 4);
+        assert_eq!(t[247].type2, XD3_ADD);
+        assert_eq!(t[247].size2, 1);
+    }
+
+    #[test]
+    fn index_255_is_last() {
+        let t = default_code_table();
+        // Index 255: CPY(4,mode=8)+ADD(1)
+        assert_eq!(t[255].type1, XD3_CPY + 8);
+        assert_eq!(t[255].size1, 4);
+        assert_eq!(t[255].type2, XD3_ADD);
+        assert_eq!(t[255].size2, 1);
+    }
+
+    #[test]
+    fn all_doubles_have_nonzero_sizes() {
+        let t = default_code_table();
+        for (i, entry) in t.iter().enumerate() {
+            if entry.type2 != XD3_NOOP {
+                assert_ne!(entry.size1, 0, "double at {i} has size1=0");
+                assert_ne!(entry.size2, 0, "double at {i} has size2=0");
+            }
+        }
+    }
+
+    /// An instruction of `itype`; size 0 (varint-sized entries) becomes a
+    /// length too long for any fixed-size opcode.
+    fn instruction(itype: u8, size: u8) -> Instruction {
+        let len = if size == 0 { 100 } else { u32::from(size) };
+        match itype {
+            XD3_ADD => Instruction::Add { len },
+            XD3_RUN => Instruction::Run { len },
+            _ => Instruction::Copy {
+                len,
+                addr: 0,
+                mode: itype - XD3_CPY,
+            },
+        }
+    }
+
+    #[test]
+    fn encode_pair_covers_default_table() {
+        // The default table itself takes xdelta3's rules; a copy of it is
+        // searched entry by entry, and must agree.
+        let copy = *default_code_table();
+        for table in [default_code_table(), &copy] {
+            for (opcode, e) in table.iter().enumerate() {
+                let first = instruction(e.type1, e.size1);
+                let chosen = if e.type2 == XD3_NOOP {
+                    table.encode_pair(&first, None)
+                } else {
+                    table.encode_pair(&first, Some(&instruction(e.type2, e.size2)))
+                };
+                assert_eq!(chosen, Some(opcode as u8), "{e:?}");
+            }
+
+            // Every pair the table lacks is left to two single opcodes, and
+            // singles always resolve to an entry describing them.
+            let mut doubles = 0;
+            let singles: Vec<Instruction> = table
+                .iter()
+                .filter(|e| e.type2 == XD3_NOOP)
+                .map(|e| instruction(e.type1, e.size1))
+                .collect();
+            for first in &singles {
+                let code = table.encode_pair(first, None).unwrap();
+                assert_eq!(
+                    InstructionInfo::from(first).itype,
+                    table[code as usize].type1
+                );
+                for second in &singles {
+                    let Some(code) = table.encode_pair(first, Some(second)) else {
+                        continue;
+                    };
+                    doubles += 1;
+                    let e = table[code as usize];
+                    let (a, b) = (InstructionInfo::from(first), InstructionInfo::from(second));
+                    assert_eq!(
+                        (e.type1, e.type2),
+                        (a.itype, b.itype),
+                        "{first:?} {second:?}"
+                    );
+                    assert_eq!((u32::from(e.size1), u32::from(e.size2)), (a.size, b.size));
+                }
+            }
+            let table_doubles = table.iter().filter(|e| e.type2 != XD3_NOOP).count();
+            assert_eq!(doubles, table_doubles);
+
+            let add = Instruction::Add { len: 1 };
+            assert_eq!(table.encode_pair(&Instruction::Add { len: 0 }, None), None);
+            let bad_mode = Instruction::Copy {
+                len: 4,
+                addr: 0,
+                mode: 9,
+            };
+            assert_eq!(table.encode_pair(&bad_mode, None), None);
+            assert_eq!(table.encode_pair(&add, Some(&bad_mode)), None);
+        }
+    }
+
+    #[test]
+    fn choose_run() {
+        let r = choose_instruction(
+            None,
+            &InstructionInfo {
+                itype: XD3_RUN,
+                size: 10,
+            },
+        );
+        assert_eq!(r.code1, 0);
+        assert!(r.code2.is_none());
+    }
+
+    #[test]
+    fn choose_add_small() {
+        for size in 1..=17u32 {
+            let r = choose_instruction(
+                None,
+                &InstructionInfo {
+                    itype: XD3_ADD,
+                    size,
+                },
+            );
+            assert_eq!(r.code1, 1 + size as u8);
+        }
+    }
+
+    #[test]
+    fn choose_add_large() {
+        let r = choose_instruction(
+            None,
+            &InstructionInfo {
+                itype: XD3_ADD,
+                size: 18,
+            },
+        );
+        assert_eq!(r.code1, 1); // size=0 entry, size emitted separately
+    }
+
+    #[test]
+    fn choose_copy_mode0_size4() {
+        let r = choose_instruction(
+            None,
+            &InstructionInfo {
+                itype: XD3_CPY,
+                size: 4,
+            },
+        );
+        assert_eq!(r.code1, 20); // 19 + (4-3) = 20
+    }
+
+    #[test]
+    fn choose_double_add_copy() {
+        let prev = InstructionInfo {
+            itype: XD3_ADD,
+            size: 1,
+        };
+        let inst = InstructionInfo {
+            itype: XD3_CPY,
+            size: 4,
+        };
+        let r = choose_instruction(Some(&prev), &inst);
+        assert_eq!(r.code2, Some(163)); // ADD(1)+CPY(4,mode=0)
+    }
+
+    #[test]
+    fn choose_double_copy_add() {
+        let prev = InstructionInfo {
+            itype: XD3_CPY,
+            size: 4,
+        };
+        let inst = InstructionInfo {
+            itype: XD3_ADD,
+            size: 1,
+        };
+        let r = choose_instruction(Some(&prev), &inst);
+        assert_eq!(r.code2, Some(247)); // CPY(4,mode=0)+ADD(1)
+    }
+
+    #[test]
+    fn choose_double_add_copy_mode6() {
+        let prev = InstructionInfo {
+            itype: XD3_ADD,
+            size: 2,
+        };
+        let inst = InstructionInfo {
+            itype: XD3_CPY + 6,
+            size: 4,
+        };
+        let r = choose_instruction(Some(&prev), &inst);
+        // 235 + (6-6)*4 + (2-1) = 236
+        assert_eq!(r.code2, Some(236));
+    }
+
+    #[test]
+    fn default_table_string_roundtrip() {
+        let string = code_table_string(default_code_table());
+        assert_eq!(string.len(), CODE_TABLE_STRING_LEN);
+        let parsed = CustomCodeTable::from_string(4, 3, &string).unwrap();
+        assert_eq!(&parsed.table, default_code_table());
+    }
+
+    #[test]
+    fn custom_table_section_roundtrip() {
+        let mut table = *default_code_table();
+        table.swap(2, 200);
+        let custom = CustomCodeTable::new(4, 3, table).unwrap();
+        assert_eq!(custom.changed_entries(), 2);
+        let section = custom.encode_section();
+        // Mostly unchanged: the delta is far smaller than the table string.
+        assert!(section.len() < CODE_TABLE_STRING_LEN / 4);
+        assert_eq!(CustomCodeTable::decode_section(&section).unwrap(), custom);
+    }
+
+    #[test]
+    fn nested_code_table_sections_are_rejected() {
+        let mut table = *default_code_table();
+        table.swap(2, 200);
+        let inner = CustomCodeTable::new(4, 3, table).unwrap().encode_section();
+
+        // A table delta whose header carries a table section of its own,
+        // wrapped once more: each level would decode the one inside it.
+        let nest = |section: &[u8]| {
+            let mut delta = crate::vcdiff::header::VCDIFF_MAGIC.to_vec();
+            delta.push(VCD_CODETABLE);
+            delta.push(section.len() as u8);
+            delta.extend_from_slice(section);
+            let mut out = vec![4, 3];
+            out.extend_from_slice(&delta);
+            out
+        };
+        let twice = nest(&nest(&inner));
+        assert!(twice.len() < 128);
+
+        let err =
+            decode_table_delta(&twice[2..], &code_table_string(default_code_table())).unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err:?}");
+        assert!(matches!(
+            CustomCodeTable::decode_section(&twice),
+            Err(CodeTableError::Delta(_))
+        ));
+
+        let mut file = crate::vcdiff::header::VCDIFF_MAGIC.to_vec();
+        file.push(VCD_CODETABLE);
+        file.push(twice.len() as u8);
+        file.extend_from_slice(&twice);
+        assert!(decoder::decode_memory(&file, b"").is_err());
+    }
+
+    #[test]
+    fn custom_table_rejects_out_of_range_mode() {
+        // Default table uses COPY modes up to 8; near=1, same=0 allows 0..2.
+        let err = CustomCodeTable::new(1, 0, *default_code_table()).unwrap_err();
+        assert!(matches!(err, CodeTableError::InvalidMode { .. }));
+    }
+
+    #[test]
+    fn index_requires_varsize_opcodes() {
+        let mut table = *default_code_table();
+        table[0] = CodeTableEntry::default(); // drop RUN size=0
+        let custom = CustomCodeTable::new(4, 3, table).unwrap();
+        assert_eq!(
+            CodeTableIndex::new(custom).unwrap_err(),
+            CodeTableError::MissingOpcode { itype: XD3_RUN }
+        );
+    }
+
+    #[test]
+    fn code_table_matches_descriptor_offsets() {
+        // Verify the descriptor offsets from __rfc3284_code_table_desc.
+        // addcopy_max_sizes[0] = {6, 163, 3} means:
+        //   mode 0 add-copy doubles start at index 163, max cpy=6, mult=3
+        let t = default_code_table();
+
+        // Mode 0: starts at 163, 4 add sizes * 3 cpy sizes = 12 entries
+        assert_eq!(t[163].type1, XD3_ADD);
+        assert_eq!(t[163].type2, XD3_CPY);
+        assert_eq!(t[174].type1, XD3_ADD);
+        assert_eq!(t[174].type2, XD3_CPY);
+
+        // Mode 1: starts at 175
+        assert_eq!(t[175].type1, XD3_ADD);
+        assert_eq!(t[175].type2, XD3_CPY + 1);
+
+        // Modes 6,7,8 (SAME modes): 4 add * 1 cpy = 4 entries each
+        // Mode 6 starts at 235
+        assert_eq!(t[235].type1, XD3_ADD);
+        assert_eq!(t[235].type2, XD3_CPY + 6);
+        assert_eq!(t[235].size2, 4);
+
+        // copyadd_max_sizes[0] = {4, 247, 1}
+        assert_eq!(t[247].type1, XD3_CPY);
+        assert_eq!(t[247].type2, XD3_ADD);
+    }
+}
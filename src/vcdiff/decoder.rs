@@ -1,949 +1,3297 @@
-// VCDIFF decoder: instruction decoding and window reconstruction.
-//
-// Byte-for-byte compatible with xdelta3's decoder.  Follows the same
-// state progression: parse file header, then for each window parse
-// the window header, read sections, execute instructions.
-//
-// Performance notes:
-//   - Section buffers (data/inst/addr) are reused across windows in StreamDecoder
-//   - Source COPY uses zero-copy slice access when source is in memory
-//   - A reusable copy_buf handles non-contiguous sources without per-COPY allocation
-//   - Output Vec is pre-sized to target_window_len
-
-use std::io::Read;
-
-use super::address_cache::AddressCache;
-use super::code_table::{self, CodeTable, Instruction, XD3_ADD, XD3_CPY, XD3_NOOP, XD3_RUN};
-use super::header::{FileHeader, VCD_TARGET, WindowHeader};
-use super::varint;
-
-// ---------------------------------------------------------------------------
-// Decoder error
-// ---------------------------------------------------------------------------
-
-#[derive(Debug)]
-pub enum DecodeError {
-    Io(std::io::Error),
-    InvalidInput(String),
-    ChecksumMismatch { expected: u32, actual: u32 },
-    Unsupported(String),
-}
-
-impl std::fmt::Display for DecodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Io(e) => write!(f, "I/O error: {e}"),
-            Self::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
-            Self::ChecksumMismatch { expected, actual } => {
-                write!(
-                    f,
-                    "checksum mismatch: expected {expected:#010X}, got {actual:#010X}"
-                )
-            }
-            Self::Unsupported(msg) => write!(f, "unsupported: {msg}"),
-        }
-    }
-}
-
-impl std::error::Error for DecodeError {}
-
-impl From<std::io::Error> for DecodeError {
-    fn from(e: std::io::Error) -> Self {
-        Self::Io(e)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Source provider trait
-// ---------------------------------------------------------------------------
-
-/// Provides source data for COPY instructions that reference source bytes.
-pub trait SourceProvider {
-    /// Read bytes from the source at absolute offset `offset` into `buf`.
-    /// Returns the number of bytes actually read.
-    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError>;
-
-    /// Total source length (if known).
-    fn source_len(&self) -> Option<u64>;
-
-    /// Direct zero-copy slice access for in-memory sources.
-    ///
-    /// Returns `Some(slice)` if the source data at `[offset..offset+len]`
-    /// is available as a contiguous memory slice. Returns `None` if the
-    /// source is not contiguous (e.g., file-backed, chunked).
-    ///
-    /// When available, the decoder uses this to avoid intermediate buffer
-    /// allocations on every COPY instruction.
-    fn source_slice(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
-        None
-    }
-}
-
-/// In-memory source.
-impl SourceProvider for &[u8] {
-    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
-        let offset = offset as usize;
-        if offset >= self.len() {
-            return Ok(0);
-        }
-        let available = &self[offset..];
-        let n = buf.len().min(available.len());
-        buf[..n].copy_from_slice(&available[..n]);
-        Ok(n)
-    }
-
-    fn source_len(&self) -> Option<u64> {
-        Some(self.len() as u64)
-    }
-
-    fn source_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
-        let offset = offset as usize;
-        if offset + len <= self.len() {
-            Some(&self[offset..offset + len])
-        } else {
-            None
-        }
-    }
-}
-
-/// No-source provider (for delta streams without a source file).
-pub struct NoSource;
-
-impl SourceProvider for NoSource {
-    fn read_source(&mut self, _offset: u64, _buf: &mut [u8]) -> Result<usize, DecodeError> {
-        Err(DecodeError::InvalidInput(
-            "COPY references source but no source provided".into(),
-        ))
-    }
-
-    fn source_len(&self) -> Option<u64> {
-        None
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Window decoder
-// ---------------------------------------------------------------------------
-
-/// Borrowed DATA/INST/ADDR section triplet for one window.
-#[derive(Clone, Copy)]
-pub struct WindowSections<'a> {
-    pub data: &'a [u8],
-    pub inst: &'a [u8],
-    pub addr: &'a [u8],
-}
-
-/// Decodes a single VCDIFF window given the three sections and a source.
-///
-/// `copy_buf` is a reusable buffer for source COPY operations when zero-copy
-/// slice access is not available. It is resized as needed and persists across
-/// calls to avoid per-COPY allocations.
-pub fn decode_window<S: SourceProvider>(
-    header: &WindowHeader,
-    data_section: &[u8],
-    inst_section: &[u8],
-    addr_section: &[u8],
-    source: &mut S,
-    verify_checksum: bool,
-    copy_buf: &mut Vec<u8>,
-) -> Result<Vec<u8>, DecodeError> {
-    let target_len = header.target_window_len as usize;
-    let mut output = Vec::with_capacity(target_len);
-    decode_window_into(
-        header,
-        WindowSections {
-            data: data_section,
-            inst: inst_section,
-            addr: addr_section,
-        },
-        source,
-        verify_checksum,
-        copy_buf,
-        &mut output,
-    )?;
-    Ok(output)
-}
-
-/// Decodes a single VCDIFF window, appending output to `output`.
-///
-/// This avoids the intermediate Vec allocation that `decode_window` performs.
-/// Target self-copy addresses are adjusted for the base offset in `output`.
-pub fn decode_window_into<S: SourceProvider>(
-    header: &WindowHeader,
-    sections: WindowSections<'_>,
-    source: &mut S,
-    verify_checksum: bool,
-    copy_buf: &mut Vec<u8>,
-    output: &mut Vec<u8>,
-) -> Result<(), DecodeError> {
-    let mut acache = AddressCache::new();
-    decode_window_with_cache(
-        header,
-        sections.data,
-        sections.inst,
-        sections.addr,
-        source,
-        verify_checksum,
-        copy_buf,
-        output,
-        &mut acache,
-    )
-}
-
-/// Internal: decode a window using a reusable AddressCache (avoids re-allocation).
-#[allow(clippy::too_many_arguments)]
-fn decode_window_with_cache<S: SourceProvider>(
-    header: &WindowHeader,
-    data_section: &[u8],
-    inst_section: &[u8],
-    addr_section: &[u8],
-    source: &mut S,
-    verify_checksum: bool,
-    copy_buf: &mut Vec<u8>,
-    output: &mut Vec<u8>,
-    acache: &mut AddressCache,
-) -> Result<(), DecodeError> {
-    let target_len = header.target_window_len as usize;
-    let copy_window_len = header.copy_window_len;
-    let copy_window_offset = header.copy_window_offset;
-
-    // Base offset: self-copy addresses are relative to window start,
-    // so we need to know where this window starts in the output buffer.
-    let base_offset = output.len();
-    output.reserve(target_len);
-
-    acache.init();
-
-    let mut data_pos: usize = 0;
-    let mut inst_pos: usize = 0;
-    let mut addr_pos: usize = 0;
-
-    let code_table = code_table::default_code_table();
-
-    // Current position in the target address space.
-    let mut target_pos: u64 = 0;
-
-    while inst_pos < inst_section.len() {
-        let opcode = inst_section[inst_pos];
-        inst_pos += 1;
-
-        let entry = &code_table[opcode as usize];
-
-        // Process first half-instruction.
-        if entry.type1 != XD3_NOOP {
-            execute_half_instruction(
-                entry.type1,
-                entry.size1,
-                &mut inst_pos,
-                inst_section,
-                &mut data_pos,
-                data_section,
-                &mut addr_pos,
-                addr_section,
-                acache,
-                copy_window_len,
-                copy_window_offset,
-                &mut target_pos,
-                output,
-                source,
-                copy_buf,
-                base_offset,
-            )?;
-        }
-
-        // Process second half-instruction.
-        if entry.type2 != XD3_NOOP {
-            execute_half_instruction(
-                entry.type2,
-                entry.size2,
-                &mut inst_pos,
-                inst_section,
-                &mut data_pos,
-                data_section,
-                &mut addr_pos,
-                addr_section,
-                acache,
-                copy_window_len,
-                copy_window_offset,
-                &mut target_pos,
-                output,
-                source,
-                copy_buf,
-                base_offset,
-            )?;
-        }
-    }
-
-    // Validate target size.
-    let written = output.len() - base_offset;
-    if written as u64 != header.target_window_len {
-        return Err(DecodeError::InvalidInput(format!(
-            "target size mismatch: expected {}, got {}",
-            header.target_window_len, written
-        )));
-    }
-
-    // Validate checksum.
-    if verify_checksum && let Some(expected) = header.adler32 {
-        let actual = compute_adler32(&output[base_offset..]);
-        if actual != expected {
-            return Err(DecodeError::ChecksumMismatch { expected, actual });
-        }
-    }
-
-    Ok(())
-}
-
-/// Execute a single half-instruction.
-#[allow(clippy::too_many_arguments)]
-#[inline(always)]
-fn execute_half_instruction<S: SourceProvider>(
-    itype: u8,
-    table_size: u8,
-    inst_pos: &mut usize,
-    inst_section: &[u8],
-    data_pos: &mut usize,
-    data_section: &[u8],
-    addr_pos: &mut usize,
-    addr_section: &[u8],
-    acache: &mut AddressCache,
-    copy_window_len: u64,
-    copy_window_offset: u64,
-    target_pos: &mut u64,
-    output: &mut Vec<u8>,
-    source: &mut S,
-    copy_buf: &mut Vec<u8>,
-    base_offset: usize,
-) -> Result<(), DecodeError> {
-    // Resolve size: if table_size==0, read from instruction section.
-    let size = if table_size == 0 {
-        let (val, consumed) = varint::read_u32(&inst_section[*inst_pos..])
-            .map_err(|e| DecodeError::InvalidInput(format!("bad instruction size: {e}")))?;
-        *inst_pos += consumed;
-        val
-    } else {
-        table_size as u32
-    };
-
-    let size_usize = size as usize;
-
-    match itype {
-        XD3_RUN => {
-            // Read 1 byte from data section, repeat `size` times.
-            if *data_pos >= data_section.len() {
-                return Err(DecodeError::InvalidInput(
-                    "data section underflow (RUN)".into(),
-                ));
-            }
-            let byte = data_section[*data_pos];
-            *data_pos += 1;
-            output.resize(output.len() + size_usize, byte);
-            *target_pos += size as u64;
-        }
-
-        XD3_ADD => {
-            // Read `size` bytes from data section.
-            let end = *data_pos + size_usize;
-            if end > data_section.len() {
-                return Err(DecodeError::InvalidInput(
-                    "data section underflow (ADD)".into(),
-                ));
-            }
-            output.extend_from_slice(&data_section[*data_pos..end]);
-            *data_pos += size_usize;
-            *target_pos += size as u64;
-        }
-
-        _ => {
-            // COPY: itype >= XD3_CPY, mode = itype - XD3_CPY
-            let mode = itype - XD3_CPY;
-
-            // Decode address.
-            let here = copy_window_len + *target_pos;
-            let (addr, consumed) = acache
-                .decode(mode, &addr_section[*addr_pos..], here)
-                .map_err(|e| DecodeError::InvalidInput(format!("address decode: {e}")))?;
-            *addr_pos += consumed;
-
-            // Validate: copy must not span source/target boundary.
-            if addr < copy_window_len && addr + size as u64 > copy_window_len {
-                return Err(DecodeError::InvalidInput(
-                    "COPY spans source/target boundary".into(),
-                ));
-            }
-
-            if addr < copy_window_len {
-                // Source copy.
-                let src_offset = copy_window_offset + addr;
-
-                // Zero-copy fast path: use direct slice access when available.
-                if let Some(slice) = source.source_slice(src_offset, size_usize) {
-                    output.extend_from_slice(slice);
-                } else {
-                    // Fallback: use the reusable copy buffer.
-                    copy_buf.resize(size_usize, 0);
-                    let n = source.read_source(src_offset, copy_buf)?;
-                    if n < size_usize {
-                        return Err(DecodeError::InvalidInput(format!(
-                            "source underflow: requested {size_usize} bytes at offset {src_offset}, got {n}"
-                        )));
-                    }
-                    output.extend_from_slice(&copy_buf[..size_usize]);
-                }
-            } else {
-                // Target self-copy.
-                // Addresses in target space are relative to the current window.
-                // Adjust by base_offset because `output` may already contain
-                // previous windows.
-                let tgt_offset = base_offset + (addr - copy_window_len) as usize;
-                if tgt_offset + size_usize <= output.len() {
-                    // Fast path: non-overlapping — use optimized bulk copy.
-                    output.extend_from_within(tgt_offset..tgt_offset + size_usize);
-                } else {
-                    // Slow path: overlapping regions (RLE-like patterns where
-                    // src and dst overlap). Must be byte-by-byte so reads see
-                    // previously written output bytes.
-                    for i in 0..size_usize {
-                        let byte = output[tgt_offset + i];
-                        output.push(byte);
-                    }
-                }
-            }
-
-            *target_pos += size as u64;
-        }
-    }
-
-    Ok(())
-}
-
-// ---------------------------------------------------------------------------
-// Stream decoder
-// ---------------------------------------------------------------------------
-
-/// Decodes a complete VCDIFF stream (file header + all windows).
-///
-/// Buffers are reused across windows to minimize allocations:
-/// - Section buffers (data/inst/addr) grow to the largest section seen
-/// - A copy buffer is reused across COPY instructions
-pub struct StreamDecoder<R: Read> {
-    reader: R,
-    file_header: Option<FileHeader>,
-    verify_checksum: bool,
-    secondary_id: Option<u8>,
-    /// Reusable section buffers (grow to largest section, never shrink).
-    data_buf: Vec<u8>,
-    inst_buf: Vec<u8>,
-    addr_buf: Vec<u8>,
-    /// Reusable buffer for source COPY (fallback when zero-copy unavailable).
-    copy_buf: Vec<u8>,
-    /// Reusable address cache (avoids re-allocation per window).
-    acache: AddressCache,
-}
-
-impl<R: Read> StreamDecoder<R> {
-    /// Create a new stream decoder.
-    pub fn new(reader: R, verify_checksum: bool) -> Self {
-        Self {
-            reader,
-            file_header: None,
-            verify_checksum,
-            secondary_id: None,
-            data_buf: Vec::new(),
-            inst_buf: Vec::new(),
-            addr_buf: Vec::new(),
-            copy_buf: Vec::new(),
-            acache: AddressCache::new(),
-        }
-    }
-
-    /// Read and return the file header.
-    pub fn read_header(&mut self) -> Result<&FileHeader, DecodeError> {
-        if self.file_header.is_none() {
-            let hdr = FileHeader::decode(&mut self.reader)?;
-            self.secondary_id = hdr.secondary_id;
-            self.file_header = Some(hdr);
-        }
-        Ok(self.file_header.as_ref().unwrap())
-    }
-
-    /// The secondary compressor ID from the file header (if any).
-    pub fn secondary_id(&self) -> Option<u8> {
-        self.secondary_id
-    }
-
-    /// Decode the next window into `output`.
-    /// Returns `Ok(false)` when there are no more windows.
-    pub fn decode_window<S: SourceProvider>(
-        &mut self,
-        source: &mut S,
-        output: &mut Vec<u8>,
-    ) -> Result<bool, DecodeError> {
-        // Ensure header is read.
-        if self.file_header.is_none() {
-            let hdr = FileHeader::decode(&mut self.reader)?;
-            self.secondary_id = hdr.secondary_id;
-            self.file_header = Some(hdr);
-        }
-
-        // Try to read the window header.
-        let wh = match WindowHeader::decode(&mut self.reader)? {
-            Some(wh) => wh,
-            None => return Ok(false),
-        };
-
-        if wh.win_ind & VCD_TARGET != 0 {
-            return Err(DecodeError::Unsupported("VCD_TARGET not supported".into()));
-        }
-
-        // Read sections into reusable buffers (resize, not re-allocate).
-        self.data_buf.resize(wh.data_len as usize, 0);
-        self.reader.read_exact(&mut self.data_buf)?;
-
-        self.inst_buf.resize(wh.inst_len as usize, 0);
-        self.reader.read_exact(&mut self.inst_buf)?;
-
-        self.addr_buf.resize(wh.addr_len as usize, 0);
-        self.reader.read_exact(&mut self.addr_buf)?;
-
-        // Decompress sections if secondary compression is indicated.
-        // Note: decompression produces new Vecs (unavoidable since the
-        // decompressed size differs from compressed). The section bufs
-        // still save allocations for the common non-secondary case.
-        let (data_ref, inst_ref, addr_ref);
-        let decomp_d;
-        let decomp_i;
-        let decomp_a;
-        if wh.del_ind != 0 {
-            let (d, i, a) = crate::compress::secondary::decompress_sections(
-                &self.data_buf,
-                &self.inst_buf,
-                &self.addr_buf,
-                wh.del_ind,
-                self.secondary_id,
-            )?;
-            decomp_d = d;
-            decomp_i = i;
-            decomp_a = a;
-            data_ref = &decomp_d[..];
-            inst_ref = &decomp_i[..];
-            addr_ref = &decomp_a[..];
-        } else {
-            data_ref = &self.data_buf;
-            inst_ref = &self.inst_buf;
-            addr_ref = &self.addr_buf;
-        }
-
-        // Decode the window directly into the output buffer (no intermediate Vec).
-        // Reuse the address cache across windows to avoid re-allocation.
-        decode_window_with_cache(
-            &wh,
-            data_ref,
-            inst_ref,
-            addr_ref,
-            source,
-            self.verify_checksum,
-            &mut self.copy_buf,
-            output,
-            &mut self.acache,
-        )?;
-
-        Ok(true)
-    }
-
-    /// Decode all remaining windows, appending to `output`.
-    pub fn decode_all<S: SourceProvider>(
-        &mut self,
-        source: &mut S,
-        output: &mut Vec<u8>,
-    ) -> Result<(), DecodeError> {
-        while self.decode_window(source, output)? {}
-        Ok(())
-    }
-
-    /// Return the file header (panics if not yet read).
-    pub fn file_header(&self) -> Option<&FileHeader> {
-        self.file_header.as_ref()
-    }
-}
-
-// ---------------------------------------------------------------------------
-// High-level convenience: decode in memory
-// ---------------------------------------------------------------------------
-
-/// Decode a VCDIFF delta from memory.
-///
-/// `delta` is the complete VCDIFF-encoded byte stream.
-/// `source` is the source/dictionary data (may be empty).
-/// Returns the reconstructed target.
-pub fn decode_memory(delta: &[u8], source: &[u8]) -> Result<Vec<u8>, DecodeError> {
-    let mut decoder = StreamDecoder::new(std::io::Cursor::new(delta), true);
-    let mut output = Vec::new();
-    let mut src: &[u8] = source;
-    decoder.decode_all(&mut src, &mut output)?;
-    Ok(output)
-}
-
-// ---------------------------------------------------------------------------
-// Adler-32
-// ---------------------------------------------------------------------------
-
-fn compute_adler32(data: &[u8]) -> u32 {
-    #[cfg(feature = "adler32")]
-    {
-        let mut hasher = simd_adler32::Adler32::new();
-        hasher.write(data);
-        hasher.finish()
-    }
-    #[cfg(not(feature = "adler32"))]
-    {
-        const MOD_ADLER: u32 = 65521;
-        let mut a: u32 = 1;
-        let mut b: u32 = 0;
-        for &byte in data {
-            a = (a + u32::from(byte)) % MOD_ADLER;
-            b = (b + a) % MOD_ADLER;
-        }
-        (b << 16) | a
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Instruction iterator (for inspection/debugging)
-// ---------------------------------------------------------------------------
-
-/// Iterate over decoded instructions in a window's instruction section.
-pub struct InstructionIterator<'a> {
-    inst_data: &'a [u8],
-    addr_data: &'a [u8],
-    inst_pos: usize,
-    addr_pos: usize,
-    code_table: &'static CodeTable,
-    acache: AddressCache,
-    copy_window_len: u64,
-    target_pos: u64,
-    /// Buffered second instruction from a double opcode.
-    pending_second: Option<(u8, u8)>,
-}
-
-impl<'a> InstructionIterator<'a> {
-    pub fn new(inst_section: &'a [u8], addr_section: &'a [u8], copy_window_len: u64) -> Self {
-        Self {
-            inst_data: inst_section,
-            addr_data: addr_section,
-            inst_pos: 0,
-            addr_pos: 0,
-            code_table: code_table::default_code_table(),
-            acache: AddressCache::new(),
-            copy_window_len,
-            target_pos: 0,
-            pending_second: None,
-        }
-    }
-
-    fn resolve_half(
-        &mut self,
-        itype: u8,
-        table_size: u8,
-    ) -> Result<Option<Instruction>, DecodeError> {
-        if itype == XD3_NOOP {
-            return Ok(None);
-        }
-
-        let size = if table_size == 0 {
-            let (val, consumed) = varint::read_u32(&self.inst_data[self.inst_pos..])
-                .map_err(|e| DecodeError::InvalidInput(format!("size varint: {e}")))?;
-            self.inst_pos += consumed;
-            val
-        } else {
-            table_size as u32
-        };
-
-        let inst = match itype {
-            XD3_RUN => Instruction::Run { len: size },
-            XD3_ADD => Instruction::Add { len: size },
-            _ => {
-                let mode = itype - XD3_CPY;
-                let here = self.copy_window_len + self.target_pos;
-                let (addr, consumed) = self
-                    .acache
-                    .decode(mode, &self.addr_data[self.addr_pos..], here)
-                    .map_err(|e| DecodeError::InvalidInput(format!("address: {e}")))?;
-                self.addr_pos += consumed;
-                Instruction::Copy {
-                    len: size,
-                    addr,
-                    mode,
-                }
-            }
-        };
-
-        self.target_pos += size as u64;
-        Ok(Some(inst))
-    }
-}
-
-impl Iterator for InstructionIterator<'_> {
-    type Item = Result<Instruction, DecodeError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        // First, drain any pending second half-instruction.
-        if let Some((type2, size2)) = self.pending_second.take() {
-            return match self.resolve_half(type2, size2) {
-                Ok(Some(inst)) => Some(Ok(inst)),
-                Ok(None) => self.next(),
-                Err(e) => Some(Err(e)),
-            };
-        }
-
-        if self.inst_pos >= self.inst_data.len() {
-            return None;
-        }
-
-        let opcode = self.inst_data[self.inst_pos];
-        self.inst_pos += 1;
-        let entry = &self.code_table[opcode as usize];
-
-        // Buffer second half.
-        if entry.type2 != XD3_NOOP {
-            self.pending_second = Some((entry.type2, entry.size2));
-        }
-
-        match self.resolve_half(entry.type1, entry.size1) {
-            Ok(Some(inst)) => Some(Ok(inst)),
-            Ok(None) => self.next(),
-            Err(e) => Some(Err(e)),
-        }
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
-
-    /// Helper: encode instructions into a VCDIFF stream and decode it back.
-    fn roundtrip_instructions(
-        instructions: &[Instruction],
-        source: &[u8],
-        target: &[u8],
-    ) -> Vec<u8> {
-        let src_win = if source.is_empty() {
-            None
-        } else {
-            Some(SourceWindow {
-                len: source.len() as u64,
-                offset: 0,
-            })
-        };
-
-        let mut we = WindowEncoder::new(src_win, true);
-        let mut data_offset: usize = 0;
-
-        for inst in instructions {
-            match *inst {
-                Instruction::Add { len } => {
-                    we.add(&target[data_offset..data_offset + len as usize]);
-                    data_offset += len as usize;
-                }
-                Instruction::Copy { len, addr, .. } => {
-                    we.copy_with_auto_mode(len, addr);
-                    data_offset += len as usize;
-                }
-                Instruction::Run { len } => {
-                    we.run(len, target[data_offset]);
-                    data_offset += len as usize;
-                }
-            }
-        }
-
-        let mut out = Vec::new();
-        let mut enc = StreamEncoder::new(&mut out, true);
-        enc.write_window(we, Some(target)).unwrap();
-        let _ = enc.finish().unwrap();
-        out
-    }
-
-    #[test]
-    fn decode_simple_add() {
-        let target = b"Hello, world!";
-        let instructions = vec![Instruction::Add {
-            len: target.len() as u32,
-        }];
-        let delta = roundtrip_instructions(&instructions, &[], target);
-        let result = decode_memory(&delta, &[]).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn decode_simple_run() {
-        let target = vec![0xAA; 50];
-        let instructions = vec![Instruction::Run { len: 50 }];
-        let delta = roundtrip_instructions(&instructions, &[], &target);
-        let result = decode_memory(&delta, &[]).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn decode_source_copy() {
-        let source = b"ABCDEFGHIJKLMNOP";
-        let target = &source[4..12]; // "EFGHIJKL"
-        let instructions = vec![Instruction::Copy {
-            len: 8,
-            addr: 4,
-            mode: 0,
-        }];
-        let delta = roundtrip_instructions(&instructions, source, target);
-        let result = decode_memory(&delta, source).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn decode_mixed_instructions() {
-        let source = b"The quick brown fox";
-        // Target: "Hello" + copy("quick") + " world"
-        let target = b"Helloquick world";
-        let instructions = vec![
-            Instruction::Add { len: 5 }, // "Hello"
-            Instruction::Copy {
-                len: 5,
-                addr: 4,
-                mode: 0,
-            }, // "quick"
-            Instruction::Add { len: 6 }, // " world"
-        ];
-        let delta = roundtrip_instructions(&instructions, source, target);
-        let result = decode_memory(&delta, source).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn decode_target_self_copy() {
-        let target = b"ABCDABCD";
-        let instructions = vec![
-            Instruction::Add { len: 4 },
-            Instruction::Copy {
-                len: 4,
-                addr: 0,
-                mode: 0,
-            },
-        ];
-        let delta = roundtrip_instructions(&instructions, &[], target);
-        let result = decode_memory(&delta, &[]).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn decode_overlapping_self_copy() {
-        let target = b"AAAAAA";
-        let instructions = vec![
-            Instruction::Add { len: 1 },
-            Instruction::Copy {
-                len: 5,
-                addr: 0,
-                mode: 0,
-            },
-        ];
-        let delta = roundtrip_instructions(&instructions, &[], target);
-        let result = decode_memory(&delta, &[]).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn checksum_verification() {
-        let target = b"test data for checksum";
-        let instructions = vec![Instruction::Add {
-            len: target.len() as u32,
-        }];
-        let delta = roundtrip_instructions(&instructions, &[], target);
-        let result = decode_memory(&delta, &[]).unwrap();
-        assert_eq!(result, target);
-    }
-
-    #[test]
-    fn instruction_iterator_basic() {
-        let target = b"Hello, world!";
-        let instructions = vec![Instruction::Add {
-            len: target.len() as u32,
-        }];
-        let delta = roundtrip_instructions(&instructions, &[], target);
-
-        let mut cursor = std::io::Cursor::new(&delta);
-        let _fh = FileHeader::decode(&mut cursor).unwrap();
-        let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
-
-        let mut data_sec = vec![0u8; wh.data_len as usize];
-        cursor.read_exact(&mut data_sec).unwrap();
-        let mut inst_sec = vec![0u8; wh.inst_len as usize];
-        cursor.read_exact(&mut inst_sec).unwrap();
-        let mut addr_sec = vec![0u8; wh.addr_len as usize];
-        cursor.read_exact(&mut addr_sec).unwrap();
-
-        let iter = InstructionIterator::new(&inst_sec, &addr_sec, 0);
-        let decoded: Vec<_> = iter.collect::<Result<_, _>>().unwrap();
-        assert_eq!(decoded.len(), 1);
-        match decoded[0] {
-            Instruction::Add { len } => assert_eq!(len, target.len() as u32),
-            _ => panic!("expected Add instruction"),
-        }
-    }
-
-    #[test]
-    fn zero_copy_source_slice() {
-        let source = b"ABCDEFGHIJKLMNOP";
-        // Verify source_slice returns the correct data.
-        let src: &[u8] = source;
-        assert_eq!(src.source_slice(4, 8), Some(b"EFGHIJKL".as_slice()));
-        assert_eq!(src.source_slice(0, 16), Some(source.as_slice()));
-        assert_eq!(src.source_slice(15, 2), None); // out of bounds
-        assert_eq!(src.source_slice(0, 0), Some(b"".as_slice()));
-    }
-
-    #[test]
-    fn reusable_buffers_in_stream_decoder() {
-        // Encode two windows.
-        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-        let target1 = b"ABCDEFGH_changed";
-        let target2 = b"MNOPQRST_different";
-
-        let mut delta = Vec::new();
-        let mut enc = crate::vcdiff::encoder::StreamEncoder::new(&mut delta, true);
-        {
-            let src_win = SourceWindow {
-                len: source.len() as u64,
-                offset: 0,
-            };
-            let mut we = WindowEncoder::new(Some(src_win), true);
-            let mut pos = 0usize;
-            we.add(target1);
-            pos += target1.len();
-            let _ = pos;
-            enc.write_window(we, Some(target1)).unwrap();
-        }
-        {
-            let src_win = SourceWindow {
-                len: source.len() as u64,
-                offset: 0,
-            };
-            let mut we = WindowEncoder::new(Some(src_win), true);
-            we.add(target2);
-            enc.write_window(we, Some(target2)).unwrap();
-        }
-        let _ = enc.finish().unwrap();
-
-        // Decode both windows — buffers should be reused.
-        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&delta), true);
-        let mut src: &[u8] = source;
-        let mut output = Vec::new();
-        decoder.decode_all(&mut src, &mut output).unwrap();
-
-        let mut expected = Vec::new();
-        expected.extend_from_slice(target1);
-        expected.extend_from_slice(target2);
-        assert_eq!(output, expected);
-    }
-}
+// VCDIFF decoder: instruction decoding and window reconstruction.
+//
+// Byte-for-byte compatible with xdelta3's decoder.  Follows the same
+// state progression: parse file header, then for each window parse
+// the window header, read sections, execute instructions.
+//
+// Performance notes:
+//   - Section buffers (data/inst/addr) are reused across windows in StreamDecoder
+//   - Source COPY uses zero-copy slice access when source is in memory
+//   - A reusable copy_buf handles non-contiguous sources without per-COPY allocation
+//   - Output Vec is pre-sized to target_window_len
+//   - The window checksum is computed in cache-sized chunks as output is produced,
+//     not in a second pass over the finished window
+//   - Bounded mode (`StreamDecoder::decode_window_to`) streams a window out
+//     while decoding, keeping only the tail needed by target self-copies
+
+#[cfg(not(feature = "std"))]
+use alloc::{borrow::Cow, format, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+use super::address_cache::AddressCache;
+use super::code_table::{
+    self, CodeTable, CustomCodeTable, Instruction, XD3_ADD, XD3_CPY, XD3_NOOP, XD3_RUN,
+};
+use super::header::{FileHeader, VCDIFF_MAGIC, WindowHeader};
+use super::integrity::{Integrity, WindowHasher};
+use super::reader::{ByteReader, Counted, Prefixed, ReadError, is_eof};
+use super::varint;
+#[cfg(feature = "std")]
+use crate::compress::secondary::{self, CompressBackend, SecondaryRegistry};
+
+// ---------------------------------------------------------------------------
+// Decoder error
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(ReadError),
+    InvalidInput(String),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    Unsupported(String),
+    /// The input ended inside a header or section: the delta is incomplete
+    /// rather than malformed, and more bytes may complete it.
+    ///
+    /// `at_window` is the index of the window being read (0 for a cut in
+    /// the file header). `expected_more` is true when the cut fell inside
+    /// the window's sections, whose lengths the window header declared, and
+    /// false when it fell inside a file or window header.
+    Truncated {
+        at_window: u64,
+        expected_more: bool,
+    },
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch: expected {expected:#010X}, got {actual:#010X}"
+                )
+            }
+            Self::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+            Self::Truncated {
+                at_window,
+                expected_more,
+            } => {
+                let part = if *expected_more { "sections" } else { "header" };
+                write!(
+                    f,
+                    "truncated delta: input ends in window {at_window} {part}"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+impl From<ReadError> for DecodeError {
+    fn from(e: ReadError) -> Self {
+        Self::Io(e)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Source provider trait
+// ---------------------------------------------------------------------------
+
+/// Provides source data for COPY instructions that reference source bytes.
+pub trait SourceProvider {
+    /// Read bytes from the source at absolute offset `offset` into `buf`.
+    /// Returns the number of bytes actually read.
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError>;
+
+    /// Total source length (if known).
+    fn source_len(&self) -> Option<u64>;
+
+    /// Direct zero-copy slice access for in-memory sources.
+    ///
+    /// Returns `Some(slice)` if the source data at `[offset..offset+len]`
+    /// is available as a contiguous memory slice. Returns `None` if the
+    /// source is not contiguous (e.g., file-backed, chunked).
+    ///
+    /// When available, the decoder uses this to avoid intermediate buffer
+    /// allocations on every COPY instruction.
+    fn source_slice(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// In-memory source.
+impl SourceProvider for &[u8] {
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let offset = offset as usize;
+        if offset >= self.len() {
+            return Ok(0);
+        }
+        let available = &self[offset..];
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Ok(n)
+    }
+
+    fn source_len(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+
+    fn source_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let offset = offset as usize;
+        if offset + len <= self.len() {
+            Some(&self[offset..offset + len])
+        } else {
+            None
+        }
+    }
+}
+
+/// Copy window of a window being decoded: the source, or for a `VCD_TARGET`
+/// window the earlier target bytes it reads.
+enum CopyWindow<'a, S> {
+    Source(&'a mut S),
+    /// `bytes` start at target offset `offset`.
+    Target {
+        bytes: &'a [u8],
+        offset: u64,
+    },
+}
+
+impl<S: SourceProvider> SourceProvider for CopyWindow<'_, S> {
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        match self {
+            Self::Source(source) => source.read_source(offset, buf),
+            Self::Target {
+                bytes,
+                offset: base,
+            } => match offset.checked_sub(*base) {
+                Some(offset) => bytes.read_source(offset, buf),
+                None => Ok(0),
+            },
+        }
+    }
+
+    fn source_len(&self) -> Option<u64> {
+        match self {
+            Self::Source(source) => source.source_len(),
+            Self::Target { bytes, offset } => Some(offset + bytes.len() as u64),
+        }
+    }
+
+    fn source_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        match self {
+            Self::Source(source) => source.source_slice(offset, len),
+            Self::Target {
+                bytes,
+                offset: base,
+            } => bytes.source_slice(offset.checked_sub(*base)?, len),
+        }
+    }
+}
+
+/// No-source provider (for delta streams without a source file).
+pub struct NoSource;
+
+impl SourceProvider for NoSource {
+    fn read_source(&mut self, _offset: u64, _buf: &mut [u8]) -> Result<usize, DecodeError> {
+        Err(DecodeError::InvalidInput(
+            "COPY references source but no source provided".into(),
+        ))
+    }
+
+    fn source_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Source read on demand from a seekable reader, such as a `File`.
+///
+/// COPYs seek to the block holding their offset and read it whole; the last
+/// few 64 KiB blocks are kept in a least-recently-used cache, so the nearby
+/// copies typical of deltas mostly avoid I/O. Memory use is bounded by the
+/// cache however large the source is. There is no zero-copy
+/// [`source_slice`](SourceProvider::source_slice) access: COPYs go through
+/// the decoder's copy buffer.
+#[cfg(feature = "std")]
+pub struct SeekSource<R> {
+    inner: crate::io::BlockReader<R>,
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + std::io::Seek> SeekSource<R> {
+    /// Wrap `reader`, caching up to 8 blocks (512 KiB). The source length is
+    /// found by seeking to the end.
+    pub fn new(reader: R) -> std::io::Result<Self> {
+        Self::with_cache_blocks(reader, 8)
+    }
+
+    /// Wrap `reader`, caching up to `blocks` 64 KiB blocks (at least one).
+    pub fn with_cache_blocks(mut reader: R, blocks: usize) -> std::io::Result<Self> {
+        let len = reader.seek(std::io::SeekFrom::End(0))?;
+        Ok(Self {
+            inner: crate::io::BlockReader::new(reader, len, blocks),
+        })
+    }
+
+    /// Return the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + std::io::Seek> SourceProvider for SeekSource<R> {
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        Ok(self.inner.read_at(offset, buf)?)
+    }
+
+    fn source_len(&self) -> Option<u64> {
+        Some(self.inner.len())
+    }
+}
+
+/// Source served by a callback: `fetch(offset, len)` returns the `len`
+/// source bytes at `offset`, once per source COPY.
+///
+/// Suits sources that are costly to hold whole, such as ranges fetched from
+/// object storage or extracted from an archive on demand. The source length
+/// is unknown, so copy windows are only checked as COPYs read them; a
+/// callback returning fewer than `len` bytes fails the COPY. See
+/// [`StreamDecoder::decode_with_source_fn`].
+pub struct SourceFn<F>(pub F);
+
+impl<'a, F> SourceProvider for SourceFn<F>
+where
+    F: FnMut(u64, usize) -> Result<Cow<'a, [u8]>, DecodeError>,
+{
+    fn read_source(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let bytes = (self.0)(offset, buf.len())?;
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        Ok(n)
+    }
+
+    fn source_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Window decoder
+// ---------------------------------------------------------------------------
+
+/// Borrowed DATA/INST/ADDR section triplet for one window.
+#[derive(Clone, Copy)]
+pub struct WindowSections<'a> {
+    pub data: &'a [u8],
+    pub inst: &'a [u8],
+    pub addr: &'a [u8],
+}
+
+/// Cursor over an out-of-line ADD literal stream.
+///
+/// **oxidelta extension — not RFC 3284.** Used to decode windows produced by
+/// [`WindowEncoder`] in external-data mode, where ADD bytes are stored in a
+/// separate flat stream rather than each window's DATA section. The cursor
+/// advances across windows, so one `ExternalLiterals` is shared by every
+/// window of a stream.
+///
+/// [`WindowEncoder`]: super::encoder::WindowEncoder
+pub struct ExternalLiterals<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ExternalLiterals<'a> {
+    /// Wrap a literal stream, starting at its first byte.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Number of literal bytes consumed so far.
+    pub fn consumed(&self) -> usize {
+        self.pos
+    }
+
+    /// Literal bytes not yet consumed.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+/// Streams a window's output to a sink while it is decoded, keeping only
+/// the trailing `retain` bytes resident for target self-copies.
+struct WindowSpill<'w> {
+    writer: &'w mut dyn FnMut(&[u8]) -> Result<(), DecodeError>,
+    /// Configured retention (`None` = the window's largest self-copy distance).
+    configured: Option<usize>,
+    /// Retention for the current window.
+    retain: usize,
+    /// Bytes of the current window already written out and dropped.
+    flushed: u64,
+}
+
+/// Output beyond the retained tail is only spilled once this much has
+/// accumulated, so each byte is moved at most a few times.
+const SPILL_CHUNK: usize = 64 * 1024;
+
+impl<'w> WindowSpill<'w> {
+    #[cfg(feature = "std")]
+    fn new(
+        writer: &'w mut dyn FnMut(&[u8]) -> Result<(), DecodeError>,
+        configured: Option<usize>,
+    ) -> Self {
+        Self {
+            writer,
+            configured,
+            retain: 0,
+            flushed: 0,
+        }
+    }
+
+    /// Write out and drop window output that is older than the retained tail.
+    ///
+    /// Bytes not yet fed to `checksum` are hashed before they are dropped.
+    fn spill(
+        &mut self,
+        output: &mut Vec<u8>,
+        base_offset: usize,
+        checksum: Option<&mut WindowChecksum>,
+    ) -> Result<(), DecodeError> {
+        let window_len = output.len() - base_offset;
+        if window_len <= self.retain + SPILL_CHUNK {
+            return Ok(());
+        }
+        if let Some(checksum) = checksum {
+            checksum.catch_up(output, base_offset, self.flushed);
+        }
+        let excess = window_len - self.retain;
+        (self.writer)(&output[base_offset..base_offset + excess])?;
+        output.drain(base_offset..base_offset + excess);
+        self.flushed += excess as u64;
+        Ok(())
+    }
+}
+
+/// Largest distance back from the write position that a target self-copy in
+/// this window reads from.
+fn window_self_copy_distance(
+    inst_section: &[u8],
+    addr_section: &[u8],
+    copy_window_len: u64,
+    custom: Option<&CustomCodeTable>,
+) -> Result<u64, DecodeError> {
+    let iter = match custom {
+        Some(table) => {
+            InstructionIterator::with_code_table(inst_section, addr_section, copy_window_len, table)
+        }
+        None => InstructionIterator::new(inst_section, addr_section, copy_window_len),
+    };
+    let mut pos = 0u64;
+    let mut max_distance = 0u64;
+    for result in iter {
+        let len = match result? {
+            Instruction::Copy { len, addr, .. } => {
+                if addr >= copy_window_len {
+                    max_distance = max_distance.max(copy_window_len + pos - addr);
+                }
+                len
+            }
+            Instruction::Add { len } | Instruction::Run { len } => len,
+        };
+        pos += len as u64;
+    }
+    Ok(max_distance)
+}
+
+/// Check a window's instructions against the instruction budget and the
+/// overlapping self-copy limit of [`DecodeLimits`], before any of them run.
+/// `instructions` counts instructions across windows.
+///
+/// Returns the bytes the window produces other than by source COPYs (when
+/// `from_source`, COPYs below `copy_window_len`), which count against
+/// `max_expansion_ratio`.
+fn check_window_instructions(
+    inst_section: &[u8],
+    addr_section: &[u8],
+    copy_window_len: u64,
+    from_source: bool,
+    custom: Option<&CustomCodeTable>,
+    limits: &DecodeLimits,
+    instructions: &mut u64,
+) -> Result<u64, DecodeError> {
+    let iter = match custom {
+        Some(table) => {
+            InstructionIterator::with_code_table(inst_section, addr_section, copy_window_len, table)
+        }
+        None => InstructionIterator::new(inst_section, addr_section, copy_window_len),
+    };
+    let mut pos = 0u64;
+    let mut expanded = 0u64;
+    for result in iter {
+        *instructions += 1;
+        if *instructions > limits.max_instructions {
+            return Err(DecodeError::Unsupported(format!(
+                "delta exceeds max_instructions: {} allowed",
+                limits.max_instructions
+            )));
+        }
+        let len = match result? {
+            Instruction::Copy { len, addr, .. } => {
+                // A self-copy closer than its length re-reads its own
+                // output and is copied byte by byte.
+                if addr >= copy_window_len
+                    && u64::from(len) > copy_window_len + pos - addr
+                    && u64::from(len) > limits.max_overlapping_copy
+                {
+                    return Err(DecodeError::Unsupported(format!(
+                        "overlapping self-copy exceeds max_overlapping_copy: {len} bytes, {} allowed",
+                        limits.max_overlapping_copy
+                    )));
+                }
+                if !(from_source && addr < copy_window_len) {
+                    expanded += u64::from(len);
+                }
+                len
+            }
+            Instruction::Add { len } | Instruction::Run { len } => {
+                expanded += u64::from(len);
+                len
+            }
+        };
+        pos += len as u64;
+    }
+    Ok(expanded)
+}
+
+/// Decodes a single VCDIFF window given the three sections and a source.
+///
+/// `copy_buf` is a reusable buffer for source COPY operations when zero-copy
+/// slice access is not available. It is resized as needed and persists across
+/// calls to avoid per-COPY allocations.
+pub fn decode_window<S: SourceProvider>(
+    header: &WindowHeader,
+    data_section: &[u8],
+    inst_section: &[u8],
+    addr_section: &[u8],
+    source: &mut S,
+    verify_checksum: bool,
+    copy_buf: &mut Vec<u8>,
+) -> Result<Vec<u8>, DecodeError> {
+    let target_len = header.target_window_len as usize;
+    let mut output = Vec::with_capacity(target_len);
+    let integrity = if verify_checksum {
+        Integrity::Adler32
+    } else {
+        Integrity::None
+    };
+    decode_window_into(
+        header,
+        WindowSections {
+            data: data_section,
+            inst: inst_section,
+            addr: addr_section,
+        },
+        source,
+        integrity,
+        copy_buf,
+        &mut output,
+    )?;
+    Ok(output)
+}
+
+/// Decodes a single VCDIFF window, appending output to `output`.
+///
+/// This avoids the intermediate Vec allocation that `decode_window` performs.
+/// Target self-copy addresses are adjusted for the base offset in `output`.
+/// The window checksum is verified as `integrity`, which the file header's
+/// application header declares (see [`Integrity::from_app_header`]);
+/// `Integrity::None` skips it.
+pub fn decode_window_into<S: SourceProvider>(
+    header: &WindowHeader,
+    sections: WindowSections<'_>,
+    source: &mut S,
+    integrity: Integrity,
+    copy_buf: &mut Vec<u8>,
+    output: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    let mut acache = AddressCache::new();
+    decode_window_with_cache(
+        header,
+        sections.data,
+        sections.inst,
+        sections.addr,
+        source,
+        integrity,
+        copy_buf,
+        output,
+        &mut acache,
+        code_table::default_code_table(),
+        None,
+        None,
+        None,
+    )
+}
+
+/// Decodes a single external-data window, appending output to `output`.
+///
+/// Like [`decode_window_into`], but ADD bytes are taken from `literals`
+/// instead of the DATA section (oxidelta extension, see [`ExternalLiterals`]).
+pub fn decode_window_external_into<S: SourceProvider>(
+    header: &WindowHeader,
+    sections: WindowSections<'_>,
+    literals: &mut ExternalLiterals<'_>,
+    source: &mut S,
+    integrity: Integrity,
+    copy_buf: &mut Vec<u8>,
+    output: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    let mut acache = AddressCache::new();
+    decode_window_with_cache(
+        header,
+        sections.data,
+        sections.inst,
+        sections.addr,
+        source,
+        integrity,
+        copy_buf,
+        output,
+        &mut acache,
+        code_table::default_code_table(),
+        Some(literals),
+        None,
+        None,
+    )
+}
+
+/// Internal: decode a window using a reusable AddressCache (avoids re-allocation).
+///
+/// Window checksums are verified as `integrity`; `Integrity::None` skips them.
+///
+/// `output_limit` caps the bytes this window may produce; the window is
+/// rejected before anything is reserved if its header claims more. With a
+/// `spill`, output older than its retained tail is written out as decoding
+/// proceeds; only the tail is left in `output`.
+#[allow(clippy::too_many_arguments)]
+fn decode_window_with_cache<S: SourceProvider>(
+    header: &WindowHeader,
+    data_section: &[u8],
+    inst_section: &[u8],
+    addr_section: &[u8],
+    source: &mut S,
+    integrity: Integrity,
+    copy_buf: &mut Vec<u8>,
+    output: &mut Vec<u8>,
+    acache: &mut AddressCache,
+    code_table: &CodeTable,
+    mut external: Option<&mut ExternalLiterals<'_>>,
+    output_limit: Option<u64>,
+    mut spill: Option<&mut WindowSpill<'_>>,
+) -> Result<(), DecodeError> {
+    if let Some(limit) = output_limit
+        && header.target_window_len > limit
+    {
+        return Err(output_limit_error(header.target_window_len, limit));
+    }
+    check_copy_window(header, source)?;
+    let target_len = header.target_window_len as usize;
+    let copy_window_len = header.copy_window_len;
+    let copy_window_offset = header.copy_window_offset;
+
+    // Base offset: self-copy addresses are relative to window start,
+    // so we need to know where this window starts in the output buffer.
+    let base_offset = output.len();
+    output.reserve(match spill {
+        Some(ref spill) => target_len.min(spill.retain + SPILL_CHUNK),
+        None => target_len,
+    });
+
+    acache.init();
+
+    let mut checksum = match header.adler32 {
+        Some(_) => WindowChecksum::new(integrity),
+        None => None,
+    };
+
+    let mut data_pos: usize = 0;
+    let mut inst_pos: usize = 0;
+    let mut addr_pos: usize = 0;
+
+    // Current position in the target address space.
+    let mut target_pos: u64 = 0;
+
+    while inst_pos < inst_section.len() {
+        let opcode = inst_section[inst_pos];
+        inst_pos += 1;
+
+        let entry = &code_table[opcode as usize];
+        let flushed = spill.as_ref().map_or(0, |spill| spill.flushed);
+
+        // Process first half-instruction.
+        if entry.type1 != XD3_NOOP {
+            execute_half_instruction(
+                entry.type1,
+                entry.size1,
+                &mut inst_pos,
+                inst_section,
+                &mut data_pos,
+                data_section,
+                &mut addr_pos,
+                addr_section,
+                acache,
+                copy_window_len,
+                copy_window_offset,
+                &mut target_pos,
+                output,
+                source,
+                copy_buf,
+                base_offset,
+                external.as_deref_mut(),
+                output_limit,
+                flushed,
+            )?;
+        }
+
+        // Process second half-instruction.
+        if entry.type2 != XD3_NOOP {
+            execute_half_instruction(
+                entry.type2,
+                entry.size2,
+                &mut inst_pos,
+                inst_section,
+                &mut data_pos,
+                data_section,
+                &mut addr_pos,
+                addr_section,
+                acache,
+                copy_window_len,
+                copy_window_offset,
+                &mut target_pos,
+                output,
+                source,
+                copy_buf,
+                base_offset,
+                external.as_deref_mut(),
+                output_limit,
+                flushed,
+            )?;
+        }
+
+        // Hash output while it is still in cache rather than in a second
+        // pass over the whole window.
+        if let Some(checksum) = checksum.as_mut()
+            && checksum.pending(output, base_offset, flushed) >= ADLER_CHUNK
+        {
+            checksum.catch_up(output, base_offset, flushed);
+        }
+        if let Some(spill) = spill.as_deref_mut() {
+            spill.spill(output, base_offset, checksum.as_mut())?;
+        }
+    }
+
+    // Validate target size.
+    let flushed = spill.as_ref().map_or(0, |spill| spill.flushed);
+    let written = flushed + (output.len() - base_offset) as u64;
+    if written != header.target_window_len {
+        return Err(DecodeError::InvalidInput(format!(
+            "target size mismatch: expected {}, got {}",
+            header.target_window_len, written
+        )));
+    }
+
+    // Validate checksum.
+    if let Some(mut checksum) = checksum
+        && let Some(expected) = header.adler32
+    {
+        checksum.catch_up(output, base_offset, flushed);
+        let actual = checksum.hasher.finish();
+        if actual != expected {
+            return Err(DecodeError::ChecksumMismatch { expected, actual });
+        }
+    }
+
+    Ok(())
+}
+
+/// Reject a `VCD_SOURCE` copy window that extends past the end of the
+/// source, before any instruction runs. Sources of unknown length are
+/// checked per COPY instead.
+fn check_copy_window<S: SourceProvider>(
+    header: &WindowHeader,
+    source: &S,
+) -> Result<(), DecodeError> {
+    if !header.has_source() {
+        return Ok(());
+    }
+    let Some(source_len) = source.source_len() else {
+        return Ok(());
+    };
+    let end = header
+        .copy_window_offset
+        .checked_add(header.copy_window_len);
+    if end.is_none_or(|end| end > source_len) {
+        return Err(DecodeError::InvalidInput(format!(
+            "copy window at offset {} with length {} exceeds source length {source_len}",
+            header.copy_window_offset, header.copy_window_len
+        )));
+    }
+    Ok(())
+}
+
+fn output_limit_error(requested: u64, limit: u64) -> DecodeError {
+    DecodeError::Unsupported(format!(
+        "output exceeds max_output_bytes: {requested} bytes requested, {limit} allowed"
+    ))
+}
+
+fn zero_length_instruction() -> DecodeError {
+    DecodeError::InvalidInput("zero-length instruction".into())
+}
+
+/// Reject a window whose `del_ind` claims secondary compression when the
+/// file header names no secondary compressor.
+pub(crate) fn check_secondary_id(del_ind: u8, secondary_id: Option<u8>) -> Result<(), DecodeError> {
+    if del_ind != 0 && secondary_id.is_none() {
+        return Err(missing_secondary_id());
+    }
+    Ok(())
+}
+
+/// The error for compressed sections in a file with no secondary ID.
+pub(crate) fn missing_secondary_id() -> DecodeError {
+    DecodeError::InvalidInput("del_ind set but no secondary id in header".into())
+}
+
+/// [`DecodeError::Truncated`] at window `at_window` if `e` is the input
+/// running out, otherwise [`DecodeError::Io`].
+pub(crate) fn read_error(e: ReadError, at_window: u64, in_sections: bool) -> DecodeError {
+    if is_eof(&e) {
+        DecodeError::Truncated {
+            at_window,
+            expected_more: in_sections,
+        }
+    } else {
+        DecodeError::Io(e)
+    }
+}
+
+/// The header of every window in an in-memory delta, in order. Sections
+/// are skipped, not decoded.
+#[cfg(any(test, all(feature = "cli", feature = "parallel")))]
+pub(crate) fn window_headers(delta: &[u8]) -> Result<Vec<WindowHeader>, DecodeError> {
+    let mut reader = delta;
+    FileHeader::decode(&mut reader).map_err(|e| read_error(e, 0, false))?;
+    let mut headers = Vec::new();
+    while let Some(wh) =
+        WindowHeader::decode(&mut reader).map_err(|e| read_error(e, headers.len() as u64, false))?
+    {
+        reader = wh
+            .data_len
+            .checked_add(wh.inst_len)
+            .and_then(|n| n.checked_add(wh.addr_len))
+            .and_then(|n| usize::try_from(n).ok())
+            .and_then(|n| reader.get(n..))
+            .ok_or(DecodeError::Truncated {
+                at_window: headers.len() as u64,
+                expected_more: true,
+            })?;
+        headers.push(wh);
+    }
+    Ok(headers)
+}
+
+/// Execute a single half-instruction.
+#[allow(clippy::too_many_arguments)]
+#[inline(always)]
+fn execute_half_instruction<S: SourceProvider>(
+    itype: u8,
+    table_size: u8,
+    inst_pos: &mut usize,
+    inst_section: &[u8],
+    data_pos: &mut usize,
+    data_section: &[u8],
+    addr_pos: &mut usize,
+    addr_section: &[u8],
+    acache: &mut AddressCache,
+    copy_window_len: u64,
+    copy_window_offset: u64,
+    target_pos: &mut u64,
+    output: &mut Vec<u8>,
+    source: &mut S,
+    copy_buf: &mut Vec<u8>,
+    base_offset: usize,
+    external: Option<&mut ExternalLiterals<'_>>,
+    output_limit: Option<u64>,
+    flushed: u64,
+) -> Result<(), DecodeError> {
+    // Resolve size: if table_size==0, read from instruction section.
+    let size = if table_size == 0 {
+        let (val, consumed) = varint::read_u32(&inst_section[*inst_pos..])
+            .map_err(|e| DecodeError::InvalidInput(format!("bad instruction size: {e}")))?;
+        *inst_pos += consumed;
+        val
+    } else {
+        table_size as u32
+    };
+    // The default code table never produces these; xdelta3 rejects them too.
+    if size == 0 {
+        return Err(zero_length_instruction());
+    }
+
+    // Enforce the output cap before the instruction allocates anything.
+    if let Some(limit) = output_limit
+        && *target_pos + size as u64 > limit
+    {
+        return Err(output_limit_error(*target_pos + size as u64, limit));
+    }
+
+    let size_usize = size as usize;
+
+    match itype {
+        XD3_RUN => {
+            // Read 1 byte from data section, repeat `size` times.
+            if *data_pos >= data_section.len() {
+                return Err(DecodeError::InvalidInput(
+                    "data section underflow (RUN)".into(),
+                ));
+            }
+            let byte = data_section[*data_pos];
+            *data_pos += 1;
+            output.resize(output.len() + size_usize, byte);
+            *target_pos += size as u64;
+        }
+
+        XD3_ADD => {
+            if let Some(ext) = external {
+                // Out-of-line literals (oxidelta extension).
+                let end = ext.pos + size_usize;
+                if end > ext.data.len() {
+                    return Err(DecodeError::InvalidInput(
+                        "external literal underflow (ADD)".into(),
+                    ));
+                }
+                output.extend_from_slice(&ext.data[ext.pos..end]);
+                ext.pos = end;
+                *target_pos += size as u64;
+                return Ok(());
+            }
+            // Read `size` bytes from data section.
+            let end = *data_pos + size_usize;
+            if end > data_section.len() {
+                return Err(DecodeError::InvalidInput(
+                    "data section underflow (ADD)".into(),
+                ));
+            }
+            output.extend_from_slice(&data_section[*data_pos..end]);
+            *data_pos += size_usize;
+            *target_pos += size as u64;
+        }
+
+        _ => {
+            // COPY: itype >= XD3_CPY, mode = itype - XD3_CPY
+            let mode = itype - XD3_CPY;
+
+            // Decode address.
+            let here = copy_window_len + *target_pos;
+            let (addr, consumed) = acache
+                .decode(mode, &addr_section[*addr_pos..], here)
+                .map_err(|e| DecodeError::InvalidInput(format!("address decode: {e}")))?;
+            *addr_pos += consumed;
+
+            // Validate: copy must not span source/target boundary.
+            if addr < copy_window_len && addr + size as u64 > copy_window_len {
+                return Err(DecodeError::InvalidInput(
+                    "COPY spans source/target boundary".into(),
+                ));
+            }
+
+            if addr < copy_window_len {
+                // Source copy.
+                let src_offset = copy_window_offset + addr;
+
+                // Zero-copy fast path: use direct slice access when available.
+                if let Some(slice) = source.source_slice(src_offset, size_usize) {
+                    output.extend_from_slice(slice);
+                } else {
+                    // Fallback: use the reusable copy buffer.
+                    copy_buf.resize(size_usize, 0);
+                    let n = source.read_source(src_offset, copy_buf)?;
+                    if n < size_usize {
+                        return Err(DecodeError::InvalidInput(format!(
+                            "source underflow: requested {size_usize} bytes at offset {src_offset}, got {n}"
+                        )));
+                    }
+                    output.extend_from_slice(&copy_buf[..size_usize]);
+                }
+            } else {
+                // Target self-copy.
+                // Addresses in target space are relative to the current window.
+                // Adjust by base_offset because `output` may already contain
+                // previous windows, and by `flushed` because a bounded decode
+                // may already have written out the start of this one.
+                let window_addr = addr - copy_window_len;
+                if window_addr < flushed {
+                    return Err(DecodeError::InvalidInput(format!(
+                        "self-copy from window offset {window_addr} reaches past the retained \
+                         output (first {flushed} bytes already written)"
+                    )));
+                }
+                let tgt_offset = base_offset + (window_addr - flushed) as usize;
+                if tgt_offset + size_usize <= output.len() {
+                    // Fast path: non-overlapping — use optimized bulk copy.
+                    output.extend_from_within(tgt_offset..tgt_offset + size_usize);
+                } else {
+                    // Slow path: overlapping regions (RLE-like patterns where
+                    // src and dst overlap). Must be byte-by-byte so reads see
+                    // previously written output bytes.
+                    for i in 0..size_usize {
+                        let byte = output[tgt_offset + i];
+                        output.push(byte);
+                    }
+                }
+            }
+
+            *target_pos += size as u64;
+        }
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Stream decoder
+// ---------------------------------------------------------------------------
+
+/// Owned DATA/INST/ADDR sections, after secondary decompression.
+type WindowSectionBufs = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+/// Decodes a complete VCDIFF stream (file header + all windows).
+///
+/// Buffers are reused across windows to minimize allocations:
+/// - Section buffers (data/inst/addr) grow to the largest section seen
+/// - A copy buffer is reused across COPY instructions
+pub struct StreamDecoder<R: ByteReader> {
+    reader: Counted<R>,
+    file_header: Option<FileHeader>,
+    verify_checksum: bool,
+    /// Window checksum declared by the file header.
+    integrity: Integrity,
+    secondary_id: Option<u8>,
+    /// Reusable section buffers (grow to largest section, never shrink).
+    data_buf: Vec<u8>,
+    inst_buf: Vec<u8>,
+    addr_buf: Vec<u8>,
+    /// Reusable buffer for source COPY (fallback when zero-copy unavailable).
+    copy_buf: Vec<u8>,
+    /// Earlier target bytes read by the current `VCD_TARGET` window.
+    target_buf: Vec<u8>,
+    /// Reusable retained-tail buffer for `decode_window_to`.
+    #[cfg(feature = "std")]
+    spill_buf: Vec<u8>,
+    /// Reusable address cache (avoids re-allocation per window).
+    acache: AddressCache,
+    /// Cap on the total decoded output (see `with_max_output_bytes`).
+    max_output_bytes: Option<u64>,
+    /// Bytes decoded across all windows so far.
+    total_output: u64,
+    /// Limits for untrusted deltas (see `with_limits`).
+    limits: Option<DecodeLimits>,
+    /// Instructions checked against `limits` so far.
+    instructions: u64,
+    /// Output checked against `max_expansion_ratio` so far (everything but
+    /// source COPYs).
+    expanded_output: u64,
+    /// Secondary backends, when the application registered its own
+    /// (`None` = the built-ins only).
+    #[cfg(feature = "std")]
+    secondary: Option<SecondaryRegistry>,
+    /// Header of the most recently decoded window.
+    last_window: Option<WindowHeader>,
+    /// Continue into further concatenated deltas (see
+    /// `allow_multiple_headers`).
+    multiple_headers: bool,
+    /// Windows fully decoded (and, for `decode_window_to`, written).
+    windows: u64,
+    /// Resume point after the last complete window (see `checkpoint`).
+    boundary: Boundary,
+}
+
+/// Work limits for decoding untrusted deltas (see
+/// [`StreamDecoder::with_limits`]). The defaults reject deltas that no
+/// encoder produces for ordinary data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Most instructions executed across all windows.
+    pub max_instructions: u64,
+    /// Most output bytes per delta byte read (the compression ratio),
+    /// not counting bytes COPYed from the source.
+    pub max_expansion_ratio: u64,
+    /// Longest target self-copy that overlaps its own output; such copies
+    /// are executed byte by byte.
+    pub max_overlapping_copy: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: 1 << 24,
+            max_expansion_ratio: 1024,
+            max_overlapping_copy: 1 << 20,
+        }
+    }
+}
+
+/// Decoder counters at the last window boundary.
+#[derive(Debug, Clone, Copy, Default)]
+struct Boundary {
+    position: u64,
+    windows: u64,
+    output_len: u64,
+}
+
+/// Progress of a [`StreamDecoder`] as of its last complete window, from
+/// [`StreamDecoder::checkpoint`]; pass it to [`StreamDecoder::resume`] to
+/// carry on from there.
+#[derive(Debug, Clone)]
+pub struct DecoderState {
+    /// Delta bytes consumed up to the end of the last complete window. The
+    /// reader given to `resume` must start at this offset.
+    pub position: u64,
+    /// Windows decoded.
+    pub windows: u64,
+    /// Target bytes those windows produced. Output beyond this length (a
+    /// partly written window) must be truncated before resuming.
+    pub output_len: u64,
+    /// The file header in effect, or `None` if it had not been read yet.
+    pub file_header: Option<FileHeader>,
+}
+
+impl DecoderState {
+    /// Secondary compressor ID from the file header, if any.
+    pub fn secondary_id(&self) -> Option<u8> {
+        self.file_header.as_ref().and_then(|hdr| hdr.secondary_id)
+    }
+
+    /// Serialize for storage: the three counters as varints, then a flag
+    /// byte and the encoded file header.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for value in [self.position, self.windows, self.output_len] {
+            varint::write_u64(&mut out, value).expect("writing to a Vec cannot fail");
+        }
+        match &self.file_header {
+            Some(hdr) => {
+                out.push(1);
+                hdr.encode(&mut out).expect("writing to a Vec cannot fail");
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Parse a state written by [`to_bytes`](Self::to_bytes).
+    #[cfg(feature = "std")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let invalid = || DecodeError::InvalidInput("malformed decoder checkpoint".into());
+        let mut rest = bytes;
+        let mut counters = [0u64; 3];
+        for value in &mut counters {
+            let (v, n) = varint::read_u64(rest).map_err(|_| invalid())?;
+            *value = v;
+            rest = &rest[n..];
+        }
+        let (&flag, mut rest) = rest.split_first().ok_or_else(invalid)?;
+        let file_header = match flag {
+            0 => None,
+            1 => Some(FileHeader::decode(&mut rest)?),
+            _ => return Err(invalid()),
+        };
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+        let [position, windows, output_len] = counters;
+        Ok(Self {
+            position,
+            windows,
+            output_len,
+            file_header,
+        })
+    }
+}
+
+impl<R: ByteReader> StreamDecoder<R> {
+    /// Create a new stream decoder.
+    pub fn new(reader: R, verify_checksum: bool) -> Self {
+        Self {
+            reader: Counted {
+                inner: reader,
+                count: 0,
+            },
+            file_header: None,
+            verify_checksum,
+            integrity: Integrity::Adler32,
+            secondary_id: None,
+            data_buf: Vec::new(),
+            inst_buf: Vec::new(),
+            addr_buf: Vec::new(),
+            copy_buf: Vec::new(),
+            target_buf: Vec::new(),
+            #[cfg(feature = "std")]
+            spill_buf: Vec::new(),
+            acache: AddressCache::new(),
+            max_output_bytes: None,
+            total_output: 0,
+            limits: None,
+            instructions: 0,
+            expanded_output: 0,
+            #[cfg(feature = "std")]
+            secondary: None,
+            last_window: None,
+            multiple_headers: false,
+            windows: 0,
+            boundary: Boundary::default(),
+        }
+    }
+
+    /// Continue a decode saved with [`checkpoint`](Self::checkpoint).
+    ///
+    /// `reader` must yield the same delta starting at `state.position`
+    /// (seek a file there first), and the output must already be truncated
+    /// to `state.output_len` bytes; later windows are decoded as if the
+    /// decoder had never stopped, so pass the same source to the decode
+    /// calls. Options such as [`with_max_output_bytes`](Self::with_max_output_bytes),
+    /// [`with_limits`](Self::with_limits) (whose counters restart),
+    /// [`allow_multiple_headers`](Self::allow_multiple_headers) and
+    /// registered secondary backends are not part of the state and must be
+    /// set again.
+    pub fn resume(reader: R, state: DecoderState, verify_checksum: bool) -> Self {
+        let mut decoder = Self::new(reader, verify_checksum);
+        decoder.reader.count = state.position;
+        decoder.windows = state.windows;
+        decoder.total_output = state.output_len;
+        if let Some(hdr) = state.file_header {
+            decoder.install_header(hdr);
+        }
+        decoder.boundary = Boundary {
+            position: state.position,
+            windows: state.windows,
+            output_len: state.output_len,
+        };
+        decoder
+    }
+
+    /// Save progress as of the last complete window.
+    ///
+    /// Always describes a window boundary: after an error, or while a
+    /// `decode_window_to` window is only partly written, it still points at
+    /// the end of the previous window.
+    pub fn checkpoint(&self) -> DecoderState {
+        DecoderState {
+            position: self.boundary.position,
+            windows: self.boundary.windows,
+            output_len: self.boundary.output_len,
+            file_header: self.file_header.clone(),
+        }
+    }
+
+    /// Start over on another delta read from `reader`, returning the old
+    /// reader.
+    ///
+    /// The file header, secondary compressor, address cache and all counters
+    /// are cleared, while the section and copy buffers keep their grown
+    /// capacity, so a long-lived decoder can work through many deltas
+    /// without reallocating. Options (checksum verification, output and
+    /// work limits, registered secondary backends, multiple headers) are
+    /// kept; the [`with_limits`](Self::with_limits) instruction count
+    /// restarts.
+    pub fn reset(&mut self, reader: R) -> R {
+        let old = core::mem::replace(
+            &mut self.reader,
+            Counted {
+                inner: reader,
+                count: 0,
+            },
+        );
+        self.file_header = None;
+        self.secondary_id = None;
+        self.integrity = Integrity::Adler32;
+        if (self.acache.s_near(), self.acache.s_same()) != (4, 3) {
+            self.acache = AddressCache::new();
+        }
+        self.total_output = 0;
+        self.instructions = 0;
+        self.expanded_output = 0;
+        self.last_window = None;
+        self.windows = 0;
+        self.boundary = Boundary::default();
+        old.inner
+    }
+
+    /// Record the current position as a resume point.
+    fn mark_boundary(&mut self) {
+        self.boundary = Boundary {
+            position: self.reader.count,
+            windows: self.windows,
+            output_len: self.total_output,
+        };
+    }
+
+    /// Decode sections whose file header names secondary compressor `id`
+    /// with `backend`, alongside (or instead of) the built-in backends.
+    #[cfg(feature = "std")]
+    pub fn register_secondary(&mut self, id: u8, backend: Arc<dyn CompressBackend>) {
+        self.secondary
+            .get_or_insert_with(SecondaryRegistry::new)
+            .register(id, backend);
+    }
+
+    /// Limit the total decoded output to `limit` bytes.
+    ///
+    /// Guards against deltas that claim huge targets: a window whose header
+    /// exceeds the remaining budget is rejected before its output is
+    /// reserved, and instructions are checked against the budget as they
+    /// execute. Exceeding the limit returns [`DecodeError::Unsupported`].
+    pub fn with_max_output_bytes(mut self, limit: u64) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Apply the default [`DecodeLimits`], for deltas from untrusted
+    /// sources. Combine with [`with_max_output_bytes`](Self::with_max_output_bytes).
+    pub fn hardened(self) -> Self {
+        self.with_limits(DecodeLimits::default())
+    }
+
+    /// Bound the work a delta can cause beyond its output size.
+    ///
+    /// Each window's instructions are checked before they execute: against
+    /// the instruction budget, the overlapping self-copy limit, and the
+    /// expansion ratio, which applies to the output so far less source
+    /// COPYs. A delta of an unchanged source is a few bytes per window, so
+    /// only bytes the delta itself produces are bounded. Exceeding a limit returns
+    /// [`DecodeError::Unsupported`] naming it.
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Decode concatenated deltas as one stream.
+    ///
+    /// When enabled, a VCDIFF file header following the last window of a
+    /// delta starts another delta, whose windows are decoded against the
+    /// same source and appended to the output. Each delta's own header
+    /// (secondary compressor, code table) applies to its windows.
+    ///
+    /// When disabled (the default), a file header after the last window is
+    /// reported as [`DecodeError::InvalidInput`] rather than ignored.
+    pub fn allow_multiple_headers(mut self, allow: bool) -> Self {
+        self.multiple_headers = allow;
+        self
+    }
+
+    /// Read and return the file header.
+    pub fn read_header(&mut self) -> Result<&FileHeader, DecodeError> {
+        if self.file_header.is_none() {
+            let hdr =
+                FileHeader::decode(&mut self.reader).map_err(|e| self.read_failed(e, false))?;
+            self.install_header(hdr);
+        }
+        Ok(self.file_header.as_ref().unwrap())
+    }
+
+    /// Make `hdr` the header for the windows that follow.
+    fn install_header(&mut self, hdr: FileHeader) {
+        self.secondary_id = hdr.secondary_id;
+        self.integrity = Integrity::from_app_header(hdr.app_header.as_deref());
+        let (near, same) = hdr
+            .code_table
+            .as_ref()
+            .map_or((4, 3), |table| (table.near as usize, table.same as usize));
+        if (self.acache.s_near(), self.acache.s_same()) != (near, same) {
+            self.acache = AddressCache::with_sizes(near, same);
+        }
+        self.file_header = Some(hdr);
+        self.mark_boundary();
+    }
+
+    /// Read the next window header, moving on to the next concatenated delta
+    /// when another file header follows. Returns `Ok(None)` at end of input.
+    fn next_window_header(&mut self) -> Result<Option<WindowHeader>, DecodeError> {
+        loop {
+            let mut win_ind = [0u8; 1];
+            match self.reader.fill_exact(&mut win_ind) {
+                Ok(()) => {}
+                Err(e) if is_eof(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            let mut reader = Prefixed::new(&win_ind, &mut self.reader);
+
+            // The first magic byte is never a valid window indicator, so it
+            // can only start another file header.
+            if win_ind[0] != VCDIFF_MAGIC[0] {
+                let wh =
+                    WindowHeader::decode(&mut reader).map_err(|e| self.read_failed(e, false))?;
+                if let Some(ref wh) = wh {
+                    check_secondary_id(wh.del_ind, self.secondary_id)?;
+                }
+                return Ok(wh);
+            }
+            if !self.multiple_headers {
+                return Err(DecodeError::InvalidInput(
+                    "another VCDIFF file header follows the last window \
+                     (concatenated deltas need `allow_multiple_headers`)"
+                        .into(),
+                ));
+            }
+            let hdr = FileHeader::decode(&mut reader).map_err(|e| self.read_failed(e, false))?;
+            self.install_header(hdr);
+        }
+    }
+
+    /// Classify a read error: running out of input inside a header
+    /// (`in_sections` false) or a window's sections is
+    /// [`DecodeError::Truncated`], anything else stays an I/O error.
+    fn read_failed(&self, e: ReadError, in_sections: bool) -> DecodeError {
+        read_error(e, self.windows, in_sections)
+    }
+
+    /// The secondary compressor ID from the file header (if any).
+    pub fn secondary_id(&self) -> Option<u8> {
+        self.secondary_id
+    }
+
+    /// Header of the most recently decoded window, if any.
+    pub fn last_window_header(&self) -> Option<&WindowHeader> {
+        self.last_window.as_ref()
+    }
+
+    /// Decode the next window into `output`.
+    /// Returns `Ok(false)` when there are no more windows.
+    pub fn decode_window<S: SourceProvider>(
+        &mut self,
+        source: &mut S,
+        output: &mut Vec<u8>,
+    ) -> Result<bool, DecodeError> {
+        let decoded = self.decode_next_window(source, output, None, None)?;
+        if decoded {
+            self.window_done();
+        }
+        Ok(decoded)
+    }
+
+    /// Decode the next external-data window into `output`, taking ADD bytes
+    /// from `literals` (oxidelta extension, see [`ExternalLiterals`]).
+    /// Returns `Ok(false)` when there are no more windows.
+    pub fn decode_window_external<S: SourceProvider>(
+        &mut self,
+        source: &mut S,
+        literals: &mut ExternalLiterals<'_>,
+        output: &mut Vec<u8>,
+    ) -> Result<bool, DecodeError> {
+        let decoded = self.decode_next_window(source, output, Some(literals), None)?;
+        if decoded {
+            self.window_done();
+        }
+        Ok(decoded)
+    }
+
+    /// Decode the next window straight to `writer` in bounded memory.
+    ///
+    /// Only the trailing `retain` bytes of the window (plus up to 64 KiB of
+    /// not-yet-spilled output and the largest single instruction) are kept
+    /// resident; older output is written as soon as it is final. A target
+    /// self-copy reaching further back than `retain` fails with
+    /// [`DecodeError::InvalidInput`]. `None` retains the window's largest
+    /// self-copy distance, which always succeeds.
+    ///
+    /// Returns the window's size, or `None` when there are no more windows.
+    /// On error, part of the window may already have been written.
+    #[cfg(feature = "std")]
+    pub fn decode_window_to<S: SourceProvider, W: Write>(
+        &mut self,
+        source: &mut S,
+        writer: &mut W,
+        retain: Option<usize>,
+    ) -> Result<Option<u64>, DecodeError> {
+        let mut tail = core::mem::take(&mut self.spill_buf);
+        tail.clear();
+        let mut sink = |bytes: &[u8]| writer.write_all(bytes).map_err(DecodeError::from);
+        let mut spill = WindowSpill::new(&mut sink, retain);
+        let result = match self.decode_next_window(source, &mut tail, None, Some(&mut spill)) {
+            Ok(true) => (spill.writer)(&tail).map(|()| Some(spill.flushed + tail.len() as u64)),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        self.spill_buf = tail;
+        if let Ok(Some(_)) = result {
+            self.window_done();
+        }
+        result
+    }
+
+    fn decode_next_window<S: SourceProvider>(
+        &mut self,
+        source: &mut S,
+        output: &mut Vec<u8>,
+        external: Option<&mut ExternalLiterals<'_>>,
+        mut spill: Option<&mut WindowSpill<'_>>,
+    ) -> Result<bool, DecodeError> {
+        // Ensure header is read.
+        self.read_header()?;
+
+        // Try to read the window header.
+        let wh = match self.next_window_header()? {
+            Some(wh) => wh,
+            None => return Ok(false),
+        };
+
+        if wh.has_target() {
+            self.load_target_window(&wh, output, spill.is_some())?;
+        }
+
+        self.read_sections(&wh)?;
+
+        // Decompress sections if secondary compression is indicated.
+        // Note: decompression produces new Vecs (unavoidable since the
+        // decompressed size differs from compressed). The section bufs
+        // still save allocations for the common non-secondary case.
+        let (data_ref, inst_ref, addr_ref);
+        let decomp_d;
+        let decomp_i;
+        let decomp_a;
+        if wh.del_ind != 0 {
+            let (d, i, a) = self.decompress_sections(&wh)?;
+            decomp_d = d;
+            decomp_i = i;
+            decomp_a = a;
+            data_ref = &decomp_d[..];
+            inst_ref = &decomp_i[..];
+            addr_ref = &decomp_a[..];
+        } else {
+            data_ref = &self.data_buf;
+            inst_ref = &self.inst_buf;
+            addr_ref = &self.addr_buf;
+        }
+
+        // Decode the window directly into the output buffer (no intermediate Vec).
+        // Reuse the address cache across windows to avoid re-allocation.
+        let custom = self
+            .file_header
+            .as_ref()
+            .and_then(|h| h.code_table.as_ref());
+        let code_table = match custom {
+            Some(custom) => &custom.table,
+            None => code_table::default_code_table(),
+        };
+        if let Some(limits) = &self.limits {
+            let expanded = check_window_instructions(
+                inst_ref,
+                addr_ref,
+                wh.copy_window_len,
+                wh.has_source(),
+                custom,
+                limits,
+                &mut self.instructions,
+            )?;
+            let output = self.expanded_output.saturating_add(expanded);
+            let delta = self.reader.count;
+            if output > delta.saturating_mul(limits.max_expansion_ratio) {
+                return Err(DecodeError::Unsupported(format!(
+                    "output exceeds max_expansion_ratio: {output} bytes beyond source COPYs \
+                     from {delta} delta bytes, {}:1 allowed",
+                    limits.max_expansion_ratio
+                )));
+            }
+            self.expanded_output = output;
+        }
+        if let Some(spill) = spill.as_deref_mut() {
+            spill.flushed = 0;
+            spill.retain = match spill.configured {
+                Some(retain) => retain,
+                None => window_self_copy_distance(inst_ref, addr_ref, wh.copy_window_len, custom)?
+                    as usize,
+            };
+        }
+        let mut copy_window = if wh.has_target() {
+            CopyWindow::Target {
+                bytes: &self.target_buf,
+                offset: wh.copy_window_offset,
+            }
+        } else {
+            CopyWindow::Source(source)
+        };
+        decode_window_with_cache(
+            &wh,
+            data_ref,
+            inst_ref,
+            addr_ref,
+            &mut copy_window,
+            if self.verify_checksum {
+                self.integrity
+            } else {
+                Integrity::None
+            },
+            &mut self.copy_buf,
+            output,
+            &mut self.acache,
+            code_table,
+            external,
+            self.max_output_bytes
+                .map(|max| max.saturating_sub(self.total_output)),
+            spill,
+        )?;
+        self.total_output += wh.target_window_len;
+        self.last_window = Some(wh);
+
+        Ok(true)
+    }
+
+    /// Copy the earlier target bytes a `VCD_TARGET` window reads into
+    /// `target_buf`. They are taken from `output`, which must still hold all
+    /// target output of the delta (as with [`decode_all`](Self::decode_all));
+    /// bounded decoding and callers that clear `output` between windows
+    /// cannot decode such windows.
+    fn load_target_window(
+        &mut self,
+        wh: &WindowHeader,
+        output: &[u8],
+        bounded: bool,
+    ) -> Result<(), DecodeError> {
+        let end = wh.copy_window_offset.checked_add(wh.copy_window_len);
+        if end.is_none_or(|end| end > self.total_output) {
+            return Err(DecodeError::InvalidInput(format!(
+                "target copy window at offset {} with length {} exceeds the {} target \
+                 bytes decoded so far",
+                wh.copy_window_offset, wh.copy_window_len, self.total_output
+            )));
+        }
+        let held = output.len() as u64;
+        if bounded || held < self.total_output {
+            return Err(DecodeError::Unsupported(
+                "VCD_TARGET window needs the earlier target output in the output buffer".into(),
+            ));
+        }
+        let start = (held - self.total_output + wh.copy_window_offset) as usize;
+        self.target_buf.clear();
+        self.target_buf
+            .extend_from_slice(&output[start..start + wh.copy_window_len as usize]);
+        Ok(())
+    }
+
+    /// Count a window whose output is complete and make it a resume point.
+    fn window_done(&mut self) {
+        self.windows += 1;
+        self.mark_boundary();
+    }
+
+    /// Read a window's sections into the reusable buffers (resize, not
+    /// re-allocate).
+    fn read_sections(&mut self, wh: &WindowHeader) -> Result<(), DecodeError> {
+        self.data_buf.resize(wh.data_len as usize, 0);
+        self.inst_buf.resize(wh.inst_len as usize, 0);
+        self.addr_buf.resize(wh.addr_len as usize, 0);
+        let read = self
+            .reader
+            .fill_exact(&mut self.data_buf)
+            .and_then(|()| self.reader.fill_exact(&mut self.inst_buf))
+            .and_then(|()| self.reader.fill_exact(&mut self.addr_buf));
+        read.map_err(|e| self.read_failed(e, true))
+    }
+
+    /// Undo secondary compression of the sections just read, bounded by
+    /// what a window of `wh.target_window_len` bytes can need.
+    #[cfg(feature = "std")]
+    fn decompress_sections(&self, wh: &WindowHeader) -> Result<WindowSectionBufs, DecodeError> {
+        let budget = self.limits.map_or(u64::MAX, |l| {
+            l.max_instructions.saturating_sub(self.instructions)
+        });
+        let limits = secondary::SectionLimits::for_window(wh.target_window_len, budget);
+        match self.secondary {
+            Some(ref registry) => secondary::decompress_sections_with(
+                registry,
+                &self.data_buf,
+                &self.inst_buf,
+                &self.addr_buf,
+                wh.del_ind,
+                self.secondary_id,
+                limits,
+            ),
+            None => secondary::decompress_sections(
+                &self.data_buf,
+                &self.inst_buf,
+                &self.addr_buf,
+                wh.del_ind,
+                self.secondary_id,
+                limits,
+            ),
+        }
+    }
+
+    /// Secondary backends live in `compress`, which needs `std`.
+    #[cfg(not(feature = "std"))]
+    fn decompress_sections(&self, _wh: &WindowHeader) -> Result<WindowSectionBufs, DecodeError> {
+        Err(DecodeError::Unsupported(
+            "secondary compression requires the `std` feature".into(),
+        ))
+    }
+
+    /// Iterate over the instructions of all remaining windows without
+    /// reconstructing the target.
+    ///
+    /// Yields `(window_index, instruction)`, where `window_index` counts the
+    /// windows read by this iterator from zero. Sections are read and
+    /// secondary compression is undone internally; each window's
+    /// instructions are decoded when the iterator reaches it. Iteration ends
+    /// after the first error.
+    pub fn instructions(&mut self) -> StreamInstructions<'_, R> {
+        StreamInstructions {
+            decoder: self,
+            windows_read: 0,
+            pending: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// Read the next window and decode its instruction section, also
+    /// returning the length of its (decompressed) data section.
+    /// Returns `Ok(None)` when there are no more windows.
+    fn next_window_instructions(
+        &mut self,
+    ) -> Result<Option<(Vec<Instruction>, usize)>, DecodeError> {
+        self.read_header()?;
+        let Some(wh) = self.next_window_header()? else {
+            return Ok(None);
+        };
+        self.read_sections(&wh)?;
+
+        let decompressed;
+        let (data_len, inst, addr) = if wh.del_ind != 0 {
+            decompressed = self.decompress_sections(&wh)?;
+            (
+                decompressed.0.len(),
+                &decompressed.1[..],
+                &decompressed.2[..],
+            )
+        } else {
+            (self.data_buf.len(), &self.inst_buf[..], &self.addr_buf[..])
+        };
+        let custom = self
+            .file_header
+            .as_ref()
+            .and_then(|h| h.code_table.as_ref());
+        let iter = match custom {
+            Some(table) => {
+                InstructionIterator::with_code_table(inst, addr, wh.copy_window_len, table)
+            }
+            None => InstructionIterator::new(inst, addr, wh.copy_window_len),
+        };
+        let instructions = iter.collect::<Result<Vec<_>, _>>()?;
+        self.last_window = Some(wh);
+        Ok(Some((instructions, data_len)))
+    }
+
+    /// Decode all remaining windows, appending to `output`.
+    pub fn decode_all<S: SourceProvider>(
+        &mut self,
+        source: &mut S,
+        output: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        while self.decode_window(source, output)? {}
+        Ok(())
+    }
+
+    /// Decode all remaining windows, asking `fetch(offset, len)` for the
+    /// source bytes of each source COPY instead of reading a
+    /// [`SourceProvider`].
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// use oxidelta::vcdiff::StreamDecoder;
+    ///
+    /// let source = b"The quick brown fox jumps over the lazy dog. ".repeat(4);
+    /// let target = [&source[..], b"and again"].concat();
+    /// let delta = oxidelta::engine::diff(&source, &target, 6);
+    ///
+    /// let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+    /// let mut output = Vec::new();
+    /// decoder
+    ///     .decode_with_source_fn(
+    ///         |offset, len| Ok(Cow::Borrowed(&source[offset as usize..][..len])),
+    ///         &mut output,
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(output, target);
+    /// ```
+    pub fn decode_with_source_fn<'a, F>(
+        &mut self,
+        fetch: F,
+        output: &mut Vec<u8>,
+    ) -> Result<(), DecodeError>
+    where
+        F: FnMut(u64, usize) -> Result<Cow<'a, [u8]>, DecodeError>,
+    {
+        self.decode_all(&mut SourceFn(fetch), output)
+    }
+
+    /// Return the file header (panics if not yet read). With
+    /// [`allow_multiple_headers`](Self::allow_multiple_headers) this is the
+    /// header of the delta currently being decoded.
+    pub fn file_header(&self) -> Option<&FileHeader> {
+        self.file_header.as_ref()
+    }
+}
+
+/// Iterator returned by [`StreamDecoder::instructions`].
+pub struct StreamInstructions<'d, R: ByteReader> {
+    decoder: &'d mut StreamDecoder<R>,
+    /// Windows read so far; `pending` belongs to the last of them.
+    windows_read: u64,
+    pending: alloc::vec::IntoIter<Instruction>,
+    done: bool,
+}
+
+impl<R: ByteReader> Iterator for StreamInstructions<'_, R> {
+    type Item = Result<(u64, Instruction), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(inst) = self.pending.next() {
+                return Some(Ok((self.windows_read - 1, inst)));
+            }
+            if self.done {
+                return None;
+            }
+            match self.decoder.next_window_instructions() {
+                Ok(Some((instructions, _))) => {
+                    self.pending = instructions.into_iter();
+                    self.windows_read += 1;
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// High-level convenience: decode in memory
+// ---------------------------------------------------------------------------
+
+/// Decode a VCDIFF delta from memory.
+///
+/// `delta` is the complete VCDIFF-encoded byte stream.
+/// `source` is the source/dictionary data (may be empty).
+/// Returns the reconstructed target.
+pub fn decode_memory(delta: &[u8], source: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut decoder = StreamDecoder::new(delta, true);
+    let mut output = Vec::new();
+    let mut src: &[u8] = source;
+    decoder.decode_all(&mut src, &mut output)?;
+    Ok(output)
+}
+
+/// Structure of a delta checked by [`validate`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Windows in the delta.
+    pub windows: u64,
+    /// Target bytes the windows decode to.
+    pub target_len: u64,
+    /// COPY instructions that read the source; decoding needs the source
+    /// when there is at least one.
+    pub source_copies: u64,
+    /// Windows carrying an Adler-32 checksum.
+    pub adler32_windows: u64,
+    /// Windows carrying a CRC-32C checksum (see [`Integrity`]).
+    pub crc32c_windows: u64,
+}
+
+impl ValidationReport {
+    /// Whether decoding the delta reads the source.
+    pub fn needs_source(&self) -> bool {
+        self.source_copies > 0
+    }
+
+    /// Windows carrying a checksum of either kind.
+    pub fn checksummed_windows(&self) -> u64 {
+        self.adler32_windows + self.crc32c_windows
+    }
+}
+
+/// Check that `delta` is well-formed VCDIFF without the source and without
+/// reconstructing the target.
+///
+/// Every header is parsed, section lengths are checked against `enc_len`,
+/// secondary compression is undone and every instruction is decoded. Each
+/// window's instructions must cover exactly its target length, draw no more
+/// than its data section holds, and keep each COPY on one side of the
+/// source/target boundary. A `VCD_TARGET` copy window must lie within the
+/// target decoded by earlier windows. Checksums are counted by kind, not
+/// verified, since that needs the decoded target.
+///
+/// Deltas whose ADD literals are kept out of line (see [`ExternalLiterals`])
+/// fail the data section check.
+pub fn validate(delta: &[u8]) -> Result<ValidationReport, DecodeError> {
+    let mut decoder = StreamDecoder::new(delta, false);
+    let mut report = ValidationReport::default();
+    while let Some((instructions, data_len)) = decoder.next_window_instructions()? {
+        let wh = decoder
+            .last_window
+            .as_ref()
+            .expect("set with the instructions");
+        if wh.has_target()
+            && wh
+                .copy_window_offset
+                .checked_add(wh.copy_window_len)
+                .is_none_or(|end| end > report.target_len)
+        {
+            return Err(DecodeError::InvalidInput(format!(
+                "VCD_TARGET copy window at {} (+{}) exceeds the {} target bytes decoded so far",
+                wh.copy_window_offset, wh.copy_window_len, report.target_len
+            )));
+        }
+        let mut covered = 0u64;
+        let mut data = 0u64;
+        for inst in &instructions {
+            let len = match *inst {
+                Instruction::Add { len } => {
+                    data += u64::from(len);
+                    len
+                }
+                Instruction::Run { len } => {
+                    data += 1;
+                    len
+                }
+                Instruction::Copy { len, addr, .. } => {
+                    if addr < wh.copy_window_len {
+                        if addr + u64::from(len) > wh.copy_window_len {
+                            return Err(DecodeError::InvalidInput(
+                                "COPY spans source/target boundary".into(),
+                            ));
+                        }
+                        if wh.has_source() {
+                            report.source_copies += 1;
+                        }
+                    }
+                    len
+                }
+            };
+            covered += u64::from(len);
+        }
+        if covered != wh.target_window_len {
+            return Err(DecodeError::InvalidInput(format!(
+                "target size mismatch: expected {}, got {covered}",
+                wh.target_window_len
+            )));
+        }
+        if data > data_len as u64 {
+            return Err(DecodeError::InvalidInput(format!(
+                "data section underflow: instructions need {data} bytes, section has {data_len}"
+            )));
+        }
+        report.windows += 1;
+        report.target_len += covered;
+        if wh.adler32.is_some() {
+            match decoder.integrity {
+                Integrity::Crc32c => report.crc32c_windows += 1,
+                Integrity::Adler32 | Integrity::None => report.adler32_windows += 1,
+            }
+        }
+    }
+    Ok(report)
+}
+
+// ---------------------------------------------------------------------------
+// Window checksum
+// ---------------------------------------------------------------------------
+
+/// Unhashed output is fed to the window checksum once this much has
+/// accumulated: small enough to still be in cache, large enough to amortize
+/// the per-call cost of the SIMD hasher.
+const ADLER_CHUNK: usize = 16 * 1024;
+
+/// Checksum of a window's output, computed as the output is produced.
+struct WindowChecksum {
+    hasher: WindowHasher,
+    /// Bytes of the window fed to `hasher` so far.
+    hashed: u64,
+}
+
+impl WindowChecksum {
+    fn new(integrity: Integrity) -> Option<Self> {
+        Some(Self {
+            hasher: WindowHasher::new(integrity)?,
+            hashed: 0,
+        })
+    }
+
+    /// Bytes of window output not yet hashed. `flushed` is the number of
+    /// leading window bytes already removed from `output`.
+    fn pending(&self, output: &[u8], base_offset: usize, flushed: u64) -> usize {
+        let written = flushed + (output.len() - base_offset) as u64;
+        (written - self.hashed) as usize
+    }
+
+    /// Hash all window output produced so far.
+    fn catch_up(&mut self, output: &[u8], base_offset: usize, flushed: u64) {
+        let start = base_offset + (self.hashed - flushed) as usize;
+        self.hasher.update(&output[start..]);
+        self.hashed = flushed + (output.len() - base_offset) as u64;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Instruction iterator (for inspection/debugging)
+// ---------------------------------------------------------------------------
+
+/// Iterate over decoded instructions in a window's instruction section.
+pub struct InstructionIterator<'a> {
+    inst_data: &'a [u8],
+    addr_data: &'a [u8],
+    inst_pos: usize,
+    addr_pos: usize,
+    code_table: &'a CodeTable,
+    acache: AddressCache,
+    copy_window_len: u64,
+    target_pos: u64,
+    /// Buffered second instruction from a double opcode.
+    pending_second: Option<(u8, u8)>,
+}
+
+impl<'a> InstructionIterator<'a> {
+    pub fn new(inst_section: &'a [u8], addr_section: &'a [u8], copy_window_len: u64) -> Self {
+        Self {
+            inst_data: inst_section,
+            addr_data: addr_section,
+            inst_pos: 0,
+            addr_pos: 0,
+            code_table: code_table::default_code_table(),
+            acache: AddressCache::new(),
+            copy_window_len,
+            target_pos: 0,
+            pending_second: None,
+        }
+    }
+
+    /// Iterate over a window encoded with an application-defined code table.
+    pub fn with_code_table(
+        inst_section: &'a [u8],
+        addr_section: &'a [u8],
+        copy_window_len: u64,
+        table: &'a CustomCodeTable,
+    ) -> Self {
+        Self {
+            code_table: &table.table,
+            acache: AddressCache::with_sizes(table.near as usize, table.same as usize),
+            ..Self::new(inst_section, addr_section, copy_window_len)
+        }
+    }
+
+    fn resolve_half(
+        &mut self,
+        itype: u8,
+        table_size: u8,
+    ) -> Result<Option<Instruction>, DecodeError> {
+        if itype == XD3_NOOP {
+            return Ok(None);
+        }
+
+        let size = if table_size == 0 {
+            let (val, consumed) = varint::read_u32(&self.inst_data[self.inst_pos..])
+                .map_err(|e| DecodeError::InvalidInput(format!("size varint: {e}")))?;
+            self.inst_pos += consumed;
+            val
+        } else {
+            table_size as u32
+        };
+        if size == 0 {
+            return Err(zero_length_instruction());
+        }
+
+        let inst = match itype {
+            XD3_RUN => Instruction::Run { len: size },
+            XD3_ADD => Instruction::Add { len: size },
+            _ => {
+                let mode = itype - XD3_CPY;
+                let here = self.copy_window_len + self.target_pos;
+                let (addr, consumed) = self
+                    .acache
+                    .decode(mode, &self.addr_data[self.addr_pos..], here)
+                    .map_err(|e| DecodeError::InvalidInput(format!("address: {e}")))?;
+                self.addr_pos += consumed;
+                Instruction::Copy {
+                    len: size,
+                    addr,
+                    mode,
+                }
+            }
+        };
+
+        self.target_pos += size as u64;
+        Ok(Some(inst))
+    }
+}
+
+impl Iterator for InstructionIterator<'_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // First, drain any pending second half-instruction.
+        if let Some((type2, size2)) = self.pending_second.take() {
+            return match self.resolve_half(type2, size2) {
+                Ok(Some(inst)) => Some(Ok(inst)),
+                Ok(None) => self.next(),
+                Err(e) => Some(Err(e)),
+            };
+        }
+
+        if self.inst_pos >= self.inst_data.len() {
+            return None;
+        }
+
+        let opcode = self.inst_data[self.inst_pos];
+        self.inst_pos += 1;
+        let entry = &self.code_table[opcode as usize];
+
+        // Buffer second half.
+        if entry.type2 != XD3_NOOP {
+            self.pending_second = Some((entry.type2, entry.size2));
+        }
+
+        match self.resolve_half(entry.type1, entry.size1) {
+            Ok(Some(inst)) => Some(Ok(inst)),
+            Ok(None) => self.next(),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::vcdiff::code_table::CodeTableEntry;
+    use crate::vcdiff::encoder::{SourceWindow, StreamEncoder, WindowEncoder};
+    use crate::vcdiff::header::{VCD_DATACOMP, VCD_SOURCE, VCD_TARGET};
+    use std::io::Read;
+
+    /// Helper: encode instructions into a VCDIFF stream and decode it back.
+    fn roundtrip_instructions(
+        instructions: &[Instruction],
+        source: &[u8],
+        target: &[u8],
+    ) -> Vec<u8> {
+        let src_win = if source.is_empty() {
+            None
+        } else {
+            Some(SourceWindow {
+                len: source.len() as u64,
+                offset: 0,
+            })
+        };
+
+        let mut we = WindowEncoder::new(src_win, true);
+        let mut data_offset: usize = 0;
+
+        for inst in instructions {
+            match *inst {
+                Instruction::Add { len } => {
+                    we.add(&target[data_offset..data_offset + len as usize]);
+                    data_offset += len as usize;
+                }
+                Instruction::Copy { len, addr, .. } => {
+                    we.copy_with_auto_mode(len, addr);
+                    data_offset += len as usize;
+                }
+                Instruction::Run { len } => {
+                    we.run(len, target[data_offset]);
+                    data_offset += len as usize;
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut enc = StreamEncoder::new(&mut out, true);
+        enc.write_window(we, Some(target)).unwrap();
+        let _ = enc.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn decode_simple_add() {
+        let target = b"Hello, world!";
+        let instructions = vec![Instruction::Add {
+            len: target.len() as u32,
+        }];
+        let delta = roundtrip_instructions(&instructions, &[], target);
+        let result = decode_memory(&delta, &[]).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn decode_simple_run() {
+        let target = vec![0xAA; 50];
+        let instructions = vec![Instruction::Run { len: 50 }];
+        let delta = roundtrip_instructions(&instructions, &[], &target);
+        let result = decode_memory(&delta, &[]).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn decode_source_copy() {
+        let source = b"ABCDEFGHIJKLMNOP";
+        let target = &source[4..12]; // "EFGHIJKL"
+        let instructions = vec![Instruction::Copy {
+            len: 8,
+            addr: 4,
+            mode: 0,
+        }];
+        let delta = roundtrip_instructions(&instructions, source, target);
+        let result = decode_memory(&delta, source).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn decode_mixed_instructions() {
+        let source = b"The quick brown fox";
+        // Target: "Hello" + copy("quick") + " world"
+        let target = b"Helloquick world";
+        let instructions = vec![
+            Instruction::Add { len: 5 }, // "Hello"
+            Instruction::Copy {
+                len: 5,
+                addr: 4,
+                mode: 0,
+            }, // "quick"
+            Instruction::Add { len: 6 }, // " world"
+        ];
+        let delta = roundtrip_instructions(&instructions, source, target);
+        let result = decode_memory(&delta, source).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn decode_target_self_copy() {
+        let target = b"ABCDABCD";
+        let instructions = vec![
+            Instruction::Add { len: 4 },
+            Instruction::Copy {
+                len: 4,
+                addr: 0,
+                mode: 0,
+            },
+        ];
+        let delta = roundtrip_instructions(&instructions, &[], target);
+        let result = decode_memory(&delta, &[]).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn decode_overlapping_self_copy() {
+        let target = b"AAAAAA";
+        let instructions = vec![
+            Instruction::Add { len: 1 },
+            Instruction::Copy {
+                len: 5,
+                addr: 0,
+                mode: 0,
+            },
+        ];
+        let delta = roundtrip_instructions(&instructions, &[], target);
+        let result = decode_memory(&delta, &[]).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn checksum_verification() {
+        let target = b"test data for checksum";
+        let instructions = vec![Instruction::Add {
+            len: target.len() as u32,
+        }];
+        let delta = roundtrip_instructions(&instructions, &[], target);
+        let result = decode_memory(&delta, &[]).unwrap();
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn checksum_detects_corruption_across_chunks() {
+        // Several ADLER_CHUNKs of literals, so the checksum is built up in
+        // pieces; corrupt one byte near the start, middle and end.
+        let target: Vec<u8> = (0..5 * ADLER_CHUNK as u32 + 123)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let instructions = [
+            Instruction::Add { len: 1000 },
+            Instruction::Copy {
+                len: 4000,
+                addr: 0,
+                mode: 0,
+            },
+            Instruction::Add {
+                len: target.len() as u32 - 5000,
+            },
+        ];
+        // The COPY overlaps its own output: a period-1000 repeat.
+        let mut target = target;
+        for i in 1000..5000 {
+            target[i] = target[i - 1000];
+        }
+        let delta = roundtrip_instructions(&instructions, &[], &target);
+        assert_eq!(decode_memory(&delta, &[]).unwrap(), target);
+
+        let data_start = delta.windows(64).position(|w| w == &target[..64]).unwrap();
+        for offset in [10, 20_000, target.len() - 4001] {
+            let mut corrupt = delta.clone();
+            corrupt[data_start + offset] ^= 0x01;
+            assert!(
+                matches!(
+                    decode_memory(&corrupt, &[]),
+                    Err(DecodeError::ChecksumMismatch { .. })
+                ),
+                "corruption at data offset {offset} not detected"
+            );
+
+            let mut decoder = StreamDecoder::new(std::io::Cursor::new(&corrupt), false);
+            let mut output = Vec::new();
+            decoder.decode_all(&mut &[][..], &mut output).unwrap();
+            assert_ne!(output, target);
+        }
+    }
+
+    #[test]
+    fn instruction_iterator_basic() {
+        let target = b"Hello, world!";
+        let instructions = vec![Instruction::Add {
+            len: target.len() as u32,
+        }];
+        let delta = roundtrip_instructions(&instructions, &[], target);
+
+        let mut cursor = std::io::Cursor::new(&delta);
+        let _fh = FileHeader::decode(&mut cursor).unwrap();
+        let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
+
+        let mut data_sec = vec![0u8; wh.data_len as usize];
+        cursor.read_exact(&mut data_sec).unwrap();
+        let mut inst_sec = vec![0u8; wh.inst_len as usize];
+        cursor.read_exact(&mut inst_sec).unwrap();
+        let mut addr_sec = vec![0u8; wh.addr_len as usize];
+        cursor.read_exact(&mut addr_sec).unwrap();
+
+        let iter = InstructionIterator::new(&inst_sec, &addr_sec, 0);
+        let decoded: Vec<_> = iter.collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded.len(), 1);
+        match decoded[0] {
+            Instruction::Add { len } => assert_eq!(len, target.len() as u32),
+            _ => panic!("expected Add instruction"),
+        }
+    }
+
+    #[test]
+    fn zero_copy_source_slice() {
+        let source = b"ABCDEFGHIJKLMNOP";
+        // Verify source_slice returns the correct data.
+        let src: &[u8] = source;
+        assert_eq!(src.source_slice(4, 8), Some(b"EFGHIJKL".as_slice()));
+        assert_eq!(src.source_slice(0, 16), Some(source.as_slice()));
+        assert_eq!(src.source_slice(15, 2), None); // out of bounds
+        assert_eq!(src.source_slice(0, 0), Some(b"".as_slice()));
+    }
+
+    #[test]
+    fn reusable_buffers_in_stream_decoder() {
+        // Encode two windows.
+        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let target1 = b"ABCDEFGH_changed";
+        let target2 = b"MNOPQRST_different";
+
+        let mut delta = Vec::new();
+        let mut enc = crate::vcdiff::encoder::StreamEncoder::new(&mut delta, true);
+        {
+            let src_win = SourceWindow {
+                len: source.len() as u64,
+                offset: 0,
+            };
+            let mut we = WindowEncoder::new(Some(src_win), true);
+            let mut pos = 0usize;
+            we.add(target1);
+            pos += target1.len();
+            let _ = pos;
+            enc.write_window(we, Some(target1)).unwrap();
+        }
+        {
+            let src_win = SourceWindow {
+                len: source.len() as u64,
+                offset: 0,
+            };
+            let mut we = WindowEncoder::new(Some(src_win), true);
+            we.add(target2);
+            enc.write_window(we, Some(target2)).unwrap();
+        }
+        let _ = enc.finish().unwrap();
+
+        // Decode both windows — buffers should be reused.
+        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&delta), true);
+        let mut src: &[u8] = source;
+        let mut output = Vec::new();
+        decoder.decode_all(&mut src, &mut output).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(target1);
+        expected.extend_from_slice(target2);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn custom_code_table_roundtrip() {
+        // A small table for a 1-slot NEAR cache and no SAME cache (modes
+        // 0..=2): variable-size singles, ADD 1..=64, and COPY sizes 4..=63
+        // per mode.
+        let mut table = [CodeTableEntry::default(); 256];
+        let single = |itype, size| CodeTableEntry {
+            type1: itype,
+            size1: size,
+            type2: XD3_NOOP,
+            size2: 0,
+        };
+        table[0] = single(XD3_RUN, 0);
+        table[1] = single(XD3_ADD, 0);
+        let mut next = 2;
+        for mode in 0..3 {
+            table[next] = single(XD3_CPY + mode, 0);
+            next += 1;
+        }
+        for size in 1..=64 {
+            table[next] = single(XD3_ADD, size);
+            next += 1;
+        }
+        for mode in 0..3 {
+            for size in 4..=63 {
+                table[next] = single(XD3_CPY + mode, size);
+                next += 1;
+            }
+        }
+        assert_eq!(next, 249);
+        let custom = CustomCodeTable::new(1, 0, table.into()).unwrap();
+
+        let source = b"The quick brown fox jumps over the lazy dog. ".repeat(8);
+        let mut target = source[10..200].to_vec();
+        target.extend_from_slice(b"some brand new literal bytes");
+        target.extend(std::iter::repeat_n(b'z', 300));
+        target.extend_from_slice(&source[..40]);
+
+        let mut out = Vec::new();
+        let mut enc = StreamEncoder::new(&mut out, false);
+        enc.set_code_table(custom.clone()).unwrap();
+        let mut we = enc.window_encoder(
+            Some(SourceWindow {
+                len: source.len() as u64,
+                offset: 0,
+            }),
+            true,
+        );
+        we.copy_with_auto_mode(190, 10);
+        we.add(b"some brand new literal bytes");
+        we.run(300, b'z');
+        we.copy_with_auto_mode(40, 0);
+        enc.write_window(we, Some(&target)).unwrap();
+        enc.finish().unwrap();
+
+        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&out), true);
+        let mut src: &[u8] = &source;
+        let mut decoded = Vec::new();
+        decoder.decode_all(&mut src, &mut decoded).unwrap();
+        assert_eq!(decoded, target);
+        assert_eq!(
+            decoder.file_header().unwrap().code_table.as_ref(),
+            Some(&custom)
+        );
+    }
+
+    #[test]
+    fn custom_code_table_small_caches_roundtrip() {
+        // near=2, same=2: six address modes, each with a variable-size COPY.
+        let mut table = [CodeTableEntry::default(); 256];
+        let single = |itype, size| CodeTableEntry {
+            type1: itype,
+            size1: size,
+            type2: XD3_NOOP,
+            size2: 0,
+        };
+        table[0] = single(XD3_RUN, 0);
+        table[1] = single(XD3_ADD, 0);
+        for mode in 0..6 {
+            table[2 + mode as usize] = single(XD3_CPY + mode, 0);
+        }
+        let custom = CustomCodeTable::new(2, 2, table.into()).unwrap();
+
+        let source: Vec<u8> = (0..2048u32).map(|i| (i * 7 % 253) as u8).collect();
+        // Repeated and nearby addresses hit the NEAR and SAME caches.
+        let copies = [
+            (64u32, 100u64),
+            (32, 100),
+            (40, 180),
+            (16, 1500),
+            (24, 100),
+            (8, 1500),
+        ];
+        let mut target = Vec::new();
+
+        let mut out = Vec::new();
+        let mut enc = StreamEncoder::new(&mut out, false);
+        enc.set_code_table(custom.clone()).unwrap();
+        let mut we = enc.window_encoder(
+            Some(SourceWindow {
+                len: source.len() as u64,
+                offset: 0,
+            }),
+            true,
+        );
+        for &(len, addr) in &copies {
+            we.copy_with_auto_mode(len, addr);
+            target.extend_from_slice(&source[addr as usize..][..len as usize]);
+            we.add(b"!");
+            target.push(b'!');
+        }
+        enc.write_window(we, Some(&target)).unwrap();
+        enc.finish().unwrap();
+
+        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&out), true);
+        let mut src: &[u8] = &source;
+        let mut decoded = Vec::new();
+        decoder.decode_all(&mut src, &mut decoded).unwrap();
+        assert_eq!(decoded, target);
+    }
+
+    #[test]
+    fn external_data_roundtrip() {
+        let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let src_win = SourceWindow {
+            len: source.len() as u64,
+            offset: 0,
+        };
+
+        let mut delta = Vec::new();
+        let mut literals = Vec::new();
+        let mut expected = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, true);
+        {
+            // "xyz" + "CDEFGH" + "!!!!" + "xyz" (self-copy)
+            let target = b"xyzCDEFGH!!!!xyz";
+            let mut we = WindowEncoder::new(Some(src_win), true);
+            we.set_external_data(true);
+            we.add(b"xyz");
+            we.copy_with_auto_mode(6, 2);
+            we.run(4, b'!');
+            we.copy_with_auto_mode(3, src_win.len);
+            literals.extend(we.take_external_data());
+            enc.write_window(we, Some(target)).unwrap();
+            expected.extend_from_slice(target);
+        }
+        {
+            let target = b"novel bytes";
+            let mut we = WindowEncoder::new(Some(src_win), true);
+            we.set_external_data(true);
+            we.add(target);
+            literals.extend(we.take_external_data());
+            enc.write_window(we, Some(target)).unwrap();
+            expected.extend_from_slice(target);
+        }
+        let _ = enc.finish().unwrap();
+        assert_eq!(literals, b"xyznovel bytes");
+
+        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&delta), true);
+        let mut src: &[u8] = source;
+        let mut lits = ExternalLiterals::new(&literals);
+        let mut output = Vec::new();
+        while decoder
+            .decode_window_external(&mut src, &mut lits, &mut output)
+            .unwrap()
+        {}
+        assert_eq!(output, expected);
+        assert_eq!(lits.consumed(), literals.len());
+        assert!(lits.remaining().is_empty());
+
+        // A standard decoder cannot reconstruct the target without the literals.
+        assert!(decode_memory(&delta, source).is_err());
+    }
+
+    /// A delta with one source-less window claiming `target_window_len`
+    /// bytes, built from raw sections.
+    fn crafted_delta(target_window_len: u64, data: &[u8], inst: &[u8]) -> Vec<u8> {
+        crafted_source_delta(0, target_window_len, data, inst, &[])
+    }
+
+    /// [`crafted_delta`] with a copy window of the first `copy_window_len`
+    /// source bytes and an address section.
+    fn crafted_source_delta(
+        copy_window_len: u64,
+        target_window_len: u64,
+        data: &[u8],
+        inst: &[u8],
+        addr: &[u8],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        FileHeader::default().encode(&mut out).unwrap();
+        let wh = WindowHeader {
+            win_ind: if copy_window_len > 0 { VCD_SOURCE } else { 0 },
+            copy_window_len,
+            copy_window_offset: 0,
+            enc_len: 0,
+            target_window_len,
+            del_ind: 0,
+            data_len: data.len() as u64,
+            inst_len: inst.len() as u64,
+            addr_len: addr.len() as u64,
+            adler32: None,
+        };
+        let wh = WindowHeader {
+            enc_len: wh.compute_enc_len(),
+            ..wh
+        };
+        wh.encode(&mut out).unwrap();
+        out.extend_from_slice(data);
+        out.extend_from_slice(inst);
+        out.extend_from_slice(addr);
+        out
+    }
+
+    #[test]
+    fn zero_length_instructions_are_rejected() {
+        // Each window holds a zero-length instruction (explicit size 0)
+        // followed by ADD(1) "A", so the target length alone still checks
+        // out. Opcode 0 is RUN, 1 is ADD and 19 is COPY mode 0, all with
+        // the size in the instruction section; 2 is ADD(1).
+        let cases = [
+            ("ADD", crafted_delta(1, b"A", &[1, 0, 2])),
+            ("RUN", crafted_delta(1, b"xA", &[0, 0, 2])),
+            ("COPY", crafted_source_delta(1, 1, b"A", &[19, 0, 2], &[0])),
+        ];
+        for (name, delta) in &cases {
+            let expect_zero_length = |err: DecodeError| match err {
+                DecodeError::InvalidInput(msg) => {
+                    assert_eq!(msg, "zero-length instruction", "{name}")
+                }
+                other => panic!("{name}: expected InvalidInput, got {other}"),
+            };
+            expect_zero_length(decode_memory(delta, b"S").unwrap_err());
+
+            // Bounded decoding and the instruction iterator agree.
+            let mut decoder = StreamDecoder::new(std::io::Cursor::new(delta), true);
+            let mut src: &[u8] = b"S";
+            let err = decoder
+                .decode_window_to(&mut src, &mut Vec::new(), Some(0))
+                .unwrap_err();
+            expect_zero_length(err);
+
+            let mut cursor = std::io::Cursor::new(delta);
+            FileHeader::decode(&mut cursor).unwrap();
+            let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
+            let sections = &delta[cursor.position() as usize..];
+            let inst = &sections[wh.data_len as usize..][..wh.inst_len as usize];
+            let addr = &sections[(wh.data_len + wh.inst_len) as usize..];
+            let mut iter = InstructionIterator::new(inst, addr, wh.copy_window_len);
+            expect_zero_length(iter.next().unwrap().unwrap_err());
+        }
+
+        // The same windows without the zero-length instruction decode.
+        assert_eq!(
+            decode_memory(&crafted_delta(1, b"A", &[2]), b"").unwrap(),
+            b"A"
+        );
+    }
+
+    #[test]
+    fn del_ind_without_secondary_id_is_rejected() {
+        // ADD(1) "A" with VCD_DATACOMP set, under a header that declares no
+        // secondary compressor.
+        let mut delta = Vec::new();
+        FileHeader::default().encode(&mut delta).unwrap();
+        let wh = WindowHeader {
+            win_ind: 0,
+            copy_window_len: 0,
+            copy_window_offset: 0,
+            enc_len: 0,
+            target_window_len: 1,
+            del_ind: VCD_DATACOMP,
+            data_len: 1,
+            inst_len: 1,
+            addr_len: 0,
+            adler32: None,
+        };
+        let wh = WindowHeader {
+            enc_len: wh.compute_enc_len(),
+            ..wh
+        };
+        wh.encode(&mut delta).unwrap();
+        delta.extend_from_slice(b"A\x02");
+
+        match decode_memory(&delta, b"").unwrap_err() {
+            DecodeError::InvalidInput(msg) => {
+                assert_eq!(msg, "del_ind set but no secondary id in header")
+            }
+            other => panic!("expected InvalidInput, got {other}"),
+        }
+        let mut decoder = StreamDecoder::new(std::io::Cursor::new(&delta), true);
+        assert!(decoder.instructions().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn max_output_rejects_oversized_window_header() {
+        // Claims an 8 MiB target with no instructions at all.
+        let delta = crafted_delta(8 << 20, &[], &[]);
+        let mut decoder =
+            StreamDecoder::new(std::io::Cursor::new(&delta), true).with_max_output_bytes(1 << 20);
+        let mut src: &[u8] = &[];
+        let mut output = Vec::new();
+        let err = decoder.decode_window(&mut src, &mut output).unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)), "{err}");
+        assert!(output.capacity() < 1 << 20);
+    }
+
+    #[test]
+    fn max_output_checked_during_execution() {
+        // The header claims 10 bytes, but a single RUN expands to 1000.
+        let mut inst = vec![0u8]; // RUN, size follows
+        varint::write_u32(&mut inst, 1000).unwrap();
+        let delta = crafted_delta(10, b"A", &inst);
+
+        let mut decoder =
+            StreamDecoder::new(std::io::Cursor::new(&delta), true).with_max_output_bytes(100);
+        let mut src: &[u8] = &[];
+        let mut output = Vec::new();
+        let err = decoder.decode_window(&mut src, &mut output).unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)), "{err}");
+        assert!(output.len() <= 100);
+    }
+
+    #[test]
+    fn max_output_is_cumulative_across_windows() {
+        let mut delta = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, true);
+        for _ in 0..2 {
+            let mut we = WindowEncoder::new(None, true);
+            we.run(100, b'x');
+            enc.write_window(we, Some(&[b'x'; 100])).unwrap();
+        }
+        let _ = enc.finish().unwrap();
+
+        let mut decoder =
+            StreamDecoder::new(std::io::Cursor::new(&delta), true).with_max_output_bytes(200);
+        let mut src: &[u8] = &[];
+        let mut output = Vec::new();
+        decoder.decode_all(&mut src, &mut output).unwrap();
+        assert_eq!(output, vec![b'x'; 200]);
+
+        let mut decoder =
+            StreamDecoder::new(std::io::Cursor::new(&delta), true).with_max_output_bytes(150);
+        let mut output = Vec::new();
+        assert!(decoder.decode_window(&mut src, &mut output).unwrap());
+        let err = decoder.decode_window(&mut src, &mut output).unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)), "{err}");
+    }
+
+    #[test]
+    fn reset_reuses_decoder_across_deltas() {
+        use crate::compress::encoder::{CompressOptions, encode_all};
+
+        let source: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut edited = source.clone();
+        edited[20_000..20_100].fill(7);
+        let grown = [&source[..], &source[..10_000]].concat();
+        let small_windows = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        let cases = [
+            (edited, CompressOptions::default()),
+            (grown, small_windows),
+            (b"short".to_vec(), CompressOptions::default()),
+        ];
+        let deltas: Vec<Vec<u8>> = cases
+            .iter()
+            .map(|(target, opts)| encode_all(Vec::new(), &source, target, opts.clone()).unwrap())
+            .collect();
+
+        let mut decoder = StreamDecoder::new(&b""[..], true);
+        let mut output = Vec::new();
+        for ((target, _), delta) in cases.iter().zip(&deltas) {
+            let data_capacity = decoder.data_buf.capacity();
+            decoder.reset(&delta[..]);
+            assert!(decoder.file_header().is_none());
+            assert_eq!(decoder.data_buf.capacity(), data_capacity);
+
+            output.clear();
+            decoder.decode_all(&mut &source[..], &mut output).unwrap();
+            assert_eq!(&output, target);
+            assert_eq!(decoder.checkpoint().output_len, target.len() as u64);
+            assert_eq!(decoder.checkpoint().position, delta.len() as u64);
+        }
+    }
+
+    #[test]
+    fn target_window_reads_earlier_output() {
+        let delta = |copy_offset: u64| {
+            let mut stream = StreamEncoder::new(Vec::new(), true);
+            let mut we = WindowEncoder::new(None, true);
+            we.add(b"hello world, ");
+            stream.write_window(we, Some(b"hello world, ")).unwrap();
+            let copy_window = SourceWindow {
+                len: 11,
+                offset: copy_offset,
+            };
+            let mut we = WindowEncoder::new(Some(copy_window), true);
+            we.set_target_window(true);
+            we.copy(11, 0, 0);
+            we.add(b"!");
+            stream.write_window(we, Some(b"hello world!")).unwrap();
+            stream.finish().unwrap()
+        };
+
+        // Output already holding caller bytes is fine: offsets count from
+        // where this delta's target starts.
+        let good = delta(0);
+        let mut output = b">> ".to_vec();
+        let mut decoder = StreamDecoder::new(&good[..], true);
+        decoder.decode_all(&mut NoSource, &mut output).unwrap();
+        assert_eq!(output, b">> hello world, hello world!");
+
+        // The copy window must lie in output decoded before the window.
+        let err = decode_memory(&delta(3), &[]).unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidInput(_)), "{err}");
+
+        // A window-at-a-time caller that clears its buffer cannot serve it.
+        let mut decoder = StreamDecoder::new(&good[..], true);
+        let mut window = Vec::new();
+        assert!(decoder.decode_window(&mut NoSource, &mut window).unwrap());
+        window.clear();
+        let err = decoder
+            .decode_window(&mut NoSource, &mut window)
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)), "{err}");
+    }
+
+    #[test]
+    fn truncation_is_distinct_from_corruption() {
+        let encode = |windows: &[&[u8]]| {
+            let mut stream = StreamEncoder::new(Vec::new(), true);
+            for &bytes in windows {
+                let mut we = WindowEncoder::new(None, true);
+                we.add(bytes);
+                stream.write_window(we, Some(bytes)).unwrap();
+            }
+            stream.finish().unwrap()
+        };
+        let first_len = encode(&[b"first window"]).len();
+        let delta = encode(&[b"first window", b"second window"]);
+        let truncated = |cut: usize| match decode_memory(&delta[..cut], &[]) {
+            Err(DecodeError::Truncated {
+                at_window,
+                expected_more,
+            }) => (at_window, expected_more),
+            other => panic!("cut at {cut}: {other:?}"),
+        };
+
+        assert_eq!(truncated(2), (0, false)); // file header
+        assert_eq!(truncated(first_len + 3), (1, false)); // window header
+        assert_eq!(truncated(delta.len() - 1), (1, true)); // sections
+
+        // A cut between windows is a shorter, complete delta.
+        assert_eq!(
+            decode_memory(&delta[..first_len], &[]).unwrap(),
+            b"first window"
+        );
+
+        // Garbage where a window header belongs is not a truncation.
+        let mut corrupt = delta.clone();
+        corrupt[first_len] = 0xFF;
+        let err = decode_memory(&corrupt, &[]).unwrap_err();
+        assert!(!matches!(err, DecodeError::Truncated { .. }), "{err}");
+
+        let err = DecodeError::Truncated {
+            at_window: 1,
+            expected_more: true,
+        };
+        assert_eq!(
+            err.to_string(),
+            "truncated delta: input ends in window 1 sections"
+        );
+    }
+
+    #[test]
+    fn validate_checks_structure_without_source() {
+        use crate::compress::encoder::{CompressOptions, encode_all};
+
+        let source: Vec<u8> = (0..10_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let target = [&source[..6_000], b"new bytes", &source[6_000..]].concat();
+        let opts = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        let delta = encode_all(Vec::new(), &source, &target, opts.clone()).unwrap();
+        let report = validate(&delta).unwrap();
+        assert_eq!(report.windows, 3);
+        assert_eq!(report.target_len, target.len() as u64);
+        assert!(report.needs_source());
+        assert_eq!((report.adler32_windows, report.crc32c_windows), (3, 0));
+
+        let crc_opts = CompressOptions {
+            integrity: Integrity::Crc32c,
+            ..opts.clone()
+        };
+        let delta = encode_all(Vec::new(), &source, &target, crc_opts).unwrap();
+        let report = validate(&delta).unwrap();
+        assert_eq!((report.adler32_windows, report.crc32c_windows), (0, 3));
+        assert_eq!(report.checksummed_windows(), 3);
+
+        let delta = encode_all(Vec::new(), &[], &target, opts).unwrap();
+        let report = validate(&delta).unwrap();
+        assert!(!report.needs_source());
+        assert_eq!(report.target_len, target.len() as u64);
+
+        // ADD(3) "abc".
+        let add = crafted_delta(3, b"abc", &[4]);
+        let report = validate(&add).unwrap();
+        assert_eq!((report.windows, report.checksummed_windows()), (1, 0));
+
+        // ADD(3) "abc", then a VCD_TARGET window: COPY(3) from offset 0.
+        let target_copy = |copy_window_len: u64| {
+            let mut out = crafted_delta(3, b"abc", &[4]);
+            let wh = WindowHeader {
+                win_ind: VCD_TARGET,
+                copy_window_len,
+                copy_window_offset: 0,
+                enc_len: 0,
+                target_window_len: 3,
+                del_ind: 0,
+                data_len: 0,
+                inst_len: 2,
+                addr_len: 1,
+                adler32: None,
+            };
+            let wh = WindowHeader {
+                enc_len: wh.compute_enc_len(),
+                ..wh
+            };
+            wh.encode(&mut out).unwrap();
+            out.extend_from_slice(&[0x13, 3, 0]);
+            out
+        };
+        assert_eq!(decode_memory(&target_copy(3), &[]).unwrap(), b"abcabc");
+        assert_eq!(validate(&target_copy(3)).unwrap().target_len, 6);
+
+        let expect_invalid = |delta: &[u8], needle: &str| match validate(delta) {
+            Err(DecodeError::InvalidInput(msg)) => assert!(msg.contains(needle), "{msg}"),
+            other => panic!("expected InvalidInput({needle}), got {other:?}"),
+        };
+        expect_invalid(&crafted_delta(3, b"ab", &[4]), "data section underflow");
+        expect_invalid(&crafted_delta(5, b"abc", &[4]), "target size mismatch");
+        // COPY(6) at source address 2 of a 4-byte copy window.
+        let spanning = crafted_source_delta(4, 6, b"", &[22], &[2]);
+        expect_invalid(&spanning, "spans source/target boundary");
+        expect_invalid(&target_copy(4), "exceeds the 3 target bytes decoded so far");
+        assert!(validate(&add[..add.len() - 1]).is_err());
+        assert!(validate(b"not a delta").is_err());
+    }
+
+    #[test]
+    fn hardened_limits_are_enforced() {
+        let expect_limit = |err: DecodeError, limit: &str| match err {
+            DecodeError::Unsupported(msg) => assert!(msg.contains(limit), "{msg}"),
+            other => panic!("expected Unsupported({limit}), got {other}"),
+        };
+        let no_limits = DecodeLimits {
+            max_instructions: u64::MAX,
+            max_expansion_ratio: u64::MAX,
+            max_overlapping_copy: u64::MAX,
+        };
+        let decode = |delta: &[u8], limits: DecodeLimits| {
+            let mut decoder = StreamDecoder::new(delta, true).with_limits(limits);
+            let mut src: &[u8] = &[];
+            let mut output = Vec::new();
+            decoder.decode_all(&mut src, &mut output).map(|()| output)
+        };
+
+        // A 1 MiB RUN from a delta of a few bytes.
+        let mut inst = vec![0u8];
+        varint::write_u32(&mut inst, 1 << 20).unwrap();
+        let run = crafted_delta(1 << 20, b"x", &inst);
+        assert_eq!(decode(&run, no_limits).unwrap(), vec![b'x'; 1 << 20]);
+        let mut decoder = StreamDecoder::new(&run[..], true).hardened();
+        let err = decoder.decode_window(&mut &[][..], &mut Vec::new());
+        expect_limit(err.unwrap_err(), "max_expansion_ratio");
+
+        // ADD(1) "a", then COPY of 64 bytes from one byte back.
+        let mut inst = vec![2u8, 19];
+        varint::write_u32(&mut inst, 64).unwrap();
+        let delta = crafted_source_delta(0, 65, b"a", &inst, &[0]);
+        assert_eq!(decode(&delta, no_limits).unwrap(), vec![b'a'; 65]);
+        let limits = DecodeLimits {
+            max_overlapping_copy: 63,
+            ..no_limits
+        };
+        expect_limit(decode(&delta, limits).unwrap_err(), "max_overlapping_copy");
+        let limits = DecodeLimits {
+            max_overlapping_copy: 64,
+            ..no_limits
+        };
+        assert!(decode(&delta, limits).is_ok());
+
+        // Two instructions per window, counted across windows.
+        let mut delta = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, true);
+        for _ in 0..3 {
+            let mut we = WindowEncoder::new(None, true);
+            we.add(b"ab");
+            we.run(8, b'c');
+            let mut target = b"ab".to_vec();
+            target.extend_from_slice(&[b'c'; 8]);
+            enc.write_window(we, Some(&target)).unwrap();
+        }
+        let _ = enc.finish().unwrap();
+        let limits = DecodeLimits {
+            max_instructions: 6,
+            ..no_limits
+        };
+        assert_eq!(decode(&delta, limits).unwrap().len(), 30);
+        let limits = DecodeLimits {
+            max_instructions: 5,
+            ..no_limits
+        };
+        expect_limit(decode(&delta, limits).unwrap_err(), "max_instructions");
+    }
+
+    #[test]
+    fn copy_window_past_source_end_is_rejected() {
+        let source = b"ABCDEFGHIJKLMNOP";
+        let instructions = vec![Instruction::Copy {
+            len: 8,
+            addr: 4,
+            mode: 0,
+        }];
+        let delta = roundtrip_instructions(&instructions, source, &source[4..12]);
+
+        // The window claims all 16 source bytes; only 10 are available.
+        let err = decode_memory(&delta, &source[..10]).unwrap_err();
+        match err {
+            DecodeError::InvalidInput(msg) => {
+                assert!(msg.contains("copy window"), "{msg}");
+                assert!(msg.contains("source length 10"), "{msg}");
+            }
+            other => panic!("expected InvalidInput, got {other}"),
+        }
+        assert!(decode_memory(&delta, &source[..0]).is_err());
+        assert_eq!(decode_memory(&delta, source).unwrap(), &source[4..12]);
+    }
+
+    #[test]
+    fn stream_instructions_span_windows() {
+        let mut delta = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, false);
+        let mut we = WindowEncoder::new(Some(SourceWindow { len: 16, offset: 0 }), false);
+        we.add(b"abc");
+        we.copy_with_auto_mode(8, 4);
+        we.run(5, b'x');
+        enc.write_window(we, None).unwrap();
+        let mut we = WindowEncoder::new(None, false);
+        we.run(7, b'y');
+        we.add(b"zz");
+        enc.write_window(we, None).unwrap();
+        enc.finish().unwrap();
+
+        let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+        let items: Vec<(u64, Instruction)> =
+            decoder.instructions().collect::<Result<_, _>>().unwrap();
+        let per_window = |w| items.iter().filter(|(i, _)| *i == w).count();
+        assert_eq!((per_window(0), per_window(1)), (3, 2));
+        assert_eq!(
+            items[1].1,
+            Instruction::Copy {
+                len: 8,
+                addr: 4,
+                mode: 0
+            }
+        );
+        assert_eq!(items[4], (1, Instruction::Add { len: 2 }));
+        assert_eq!(decoder.last_window_header().unwrap().target_window_len, 9);
+
+        // Truncated input surfaces as an error and ends the iteration.
+        let mut decoder = StreamDecoder::new(&delta[..delta.len() - 1], true);
+        let mut iter = decoder.instructions();
+        assert!(iter.by_ref().take(3).all(|item| item.is_ok()));
+        assert!(matches!(
+            iter.next(),
+            Some(Err(DecodeError::Truncated { .. }))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn checkpoint_resumes_after_interruption() {
+        use crate::compress::encoder::{CompressOptions, encode_all};
+
+        let source: Vec<u8> = (0..20_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        let mut target = source.clone();
+        target[5_000..5_100].fill(b'!');
+        target.extend_from_slice(b"tail bytes");
+        let opts = CompressOptions {
+            window_size: 4096,
+            ..Default::default()
+        };
+        let delta = encode_all(Vec::new(), &source, &target, opts).unwrap();
+        let mut src: &[u8] = &source;
+
+        // Nothing read yet: resuming means starting over.
+        let fresh = StreamDecoder::new(delta.as_slice(), true).checkpoint();
+        assert_eq!((fresh.position, fresh.windows, fresh.output_len), (0, 0, 0));
+        assert!(fresh.file_header.is_none());
+
+        // Find where the first two windows end.
+        let mut full = StreamDecoder::new(delta.as_slice(), true);
+        let mut scratch = Vec::new();
+        let mut ends = Vec::new();
+        for _ in 0..2 {
+            assert!(full.decode_window(&mut src, &mut scratch).unwrap());
+            ends.push(full.checkpoint().position as usize);
+        }
+
+        // Decode one window, then lose the connection halfway through the
+        // second, after part of it reached the output.
+        let mut cut = StreamDecoder::new(&delta[..(ends[0] + ends[1]) / 2], true);
+        let mut output = Vec::new();
+        assert!(
+            cut.decode_window_to(&mut src, &mut output, None)
+                .unwrap()
+                .is_some()
+        );
+        assert!(cut.decode_window_to(&mut src, &mut output, None).is_err());
+        output.extend_from_slice(b"partial window");
+        let state = cut.checkpoint();
+        assert_eq!(state.windows, 1);
+        assert_eq!(state.output_len, 4096);
+        assert_eq!(state.position, ends[0] as u64);
+
+        // The state survives serialization.
+        let state = DecoderState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(state.position, ends[0] as u64);
+        assert_eq!(state.secondary_id(), None);
+        assert!(DecoderState::from_bytes(&[0x80]).is_err());
+
+        output.truncate(state.output_len as usize);
+        let position = state.position as usize;
+        let mut resumed = StreamDecoder::resume(&delta[position..], state, true);
+        while resumed
+            .decode_window_to(&mut src, &mut output, None)
+            .unwrap()
+            .is_some()
+        {}
+        assert_eq!(output, target);
+        let done = resumed.checkpoint();
+        assert_eq!(done.position, delta.len() as u64);
+        assert_eq!(done.output_len, target.len() as u64);
+        assert_eq!(done.windows, target.len().div_ceil(4096) as u64);
+    }
+
+    #[test]
+    fn trailing_bytes_after_last_window() {
+        let mut delta = Vec::new();
+        let mut enc = StreamEncoder::new(&mut delta, false);
+        let mut we = WindowEncoder::new(None, false);
+        we.add(b"abc");
+        enc.write_window(we, None).unwrap();
+        enc.finish().unwrap();
+        assert_eq!(decode_memory(&delta, &[]).unwrap(), b"abc");
+
+        let decode = |delta: &[u8], allow: bool| {
+            let mut decoder = StreamDecoder::new(delta, true).allow_multiple_headers(allow);
+            let mut output = Vec::new();
+            decoder
+                .decode_all(&mut &[][..], &mut output)
+                .map(|()| output)
+        };
+
+        // A second delta (here a copy of the first) is only decoded on request.
+        let doubled = [delta.as_slice(), delta.as_slice()].concat();
+        assert!(matches!(
+            decode(&doubled, false),
+            Err(DecodeError::InvalidInput(_))
+        ));
+        assert_eq!(decode(&doubled, true).unwrap(), b"abcabc");
+
+        // Its instructions continue the window count.
+        let mut decoder = StreamDecoder::new(doubled.as_slice(), true).allow_multiple_headers(true);
+        let windows: Vec<u64> = decoder.instructions().map(|item| item.unwrap().0).collect();
+        assert_eq!(windows, [0, 1]);
+
+        // Anything else after the last window is an error either way.
+        let garbage = [delta.as_slice(), &[0xFF]].concat();
+        for allow in [false, true] {
+            assert!(decode(&garbage, allow).is_err(), "allow {allow}");
+        }
+    }
+
+    #[test]
+    fn seek_source_decodes_source_copies() {
+        /// Cursor that counts block reads (seeks).
+        struct Counting<'a> {
+            inner: std::io::Cursor<&'a [u8]>,
+            seeks: std::rc::Rc<std::cell::Cell<usize>>,
+        }
+        impl Read for Counting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.inner.read(buf)
+            }
+        }
+        impl std::io::Seek for Counting<'_> {
+            fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                self.seeks.set(self.seeks.get() + 1);
+                self.inner.seek(pos)
+            }
+        }
+
+        let source: Vec<u8> = (0..300_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        // Nearby copies from each end of the source, with edits between.
+        let mut target = Vec::new();
+        for i in 0..40usize {
+            let start = if i % 2 == 0 {
+                i * 1_000
+            } else {
+                250_000 + i * 500
+            };
+            target.extend_from_slice(&source[start..start + 700]);
+            target.extend_from_slice(b"edit");
+        }
+        let mut delta = Vec::new();
+        crate::compress::encoder::encode_all(&mut delta, &source, &target, Default::default())
+            .unwrap();
+        let expected = decode_memory(&delta, &source).unwrap();
+        assert_eq!(expected, target);
+
+        let seeks = std::rc::Rc::new(std::cell::Cell::new(0));
+        let reader = Counting {
+            inner: std::io::Cursor::new(source.as_slice()),
+            seeks: seeks.clone(),
+        };
+        let mut src = SeekSource::with_cache_blocks(reader, 2).unwrap();
+        assert_eq!(src.source_len(), Some(source.len() as u64));
+        assert!(src.source_slice(0, 1).is_none());
+
+        let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+        let mut output = Vec::new();
+        decoder.decode_all(&mut src, &mut output).unwrap();
+        assert_eq!(output, target);
+        // One seek to find the length, then one per block fetched: the two
+        // source regions span a handful of blocks, not one read per COPY.
+        assert!(seeks.get() <= 8, "{} seeks", seeks.get());
+
+        // Reads past the end come back short.
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            src.read_source(source.len() as u64 - 4, &mut buf).unwrap(),
+            4
+        );
+        assert_eq!(&buf[..4], &source[source.len() - 4..]);
+    }
+
+    #[test]
+    fn source_fn_serves_requested_ranges() {
+        let source: Vec<u8> = (0..50_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+        let mut target = source[10_000..30_000].to_vec();
+        target.extend_from_slice(b"fresh bytes in the middle");
+        target.extend_from_slice(&source[40_000..45_000]);
+        let mut delta = Vec::new();
+        crate::compress::encoder::encode_all(&mut delta, &source, &target, Default::default())
+            .unwrap();
+
+        let mut ranges = Vec::new();
+        let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+        let mut output = Vec::new();
+        decoder
+            .decode_with_source_fn(
+                |offset, len| {
+                    ranges.push((offset, len));
+                    let start = offset as usize;
+                    Ok(Cow::Owned(source[start..start + len].to_vec()))
+                },
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output, target);
+
+        // One call per source COPY, covering only the regions the target uses.
+        let mut listing = StreamDecoder::new(delta.as_slice(), true);
+        let insts: Vec<_> = listing.instructions().collect::<Result<_, _>>().unwrap();
+        let copy_window_len = listing.last_window_header().unwrap().copy_window_len;
+        let source_copies = insts
+            .iter()
+            .filter(|(_, inst)| matches!(inst, Instruction::Copy { addr, .. } if *addr < copy_window_len))
+            .count();
+        assert_eq!(ranges.len(), source_copies);
+        let fetched: usize = ranges.iter().map(|&(_, len)| len).sum();
+        assert_eq!(fetched, 25_000);
+        assert!(
+            ranges.iter().all(|&(o, l)| (10_000..30_000).contains(&o)
+                || (40_000..45_000).contains(&o) && o as usize + l <= 45_000),
+            "{ranges:?}"
+        );
+
+        // Short answers and callback errors fail the decode.
+        let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+        let err = decoder
+            .decode_with_source_fn(|_, _| Ok(Cow::Borrowed(&b"x"[..])), &mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("source underflow"), "{err}");
+        let mut decoder = StreamDecoder::new(delta.as_slice(), true);
+        let err = decoder
+            .decode_with_source_fn(
+                |_, _| Err(DecodeError::Unsupported("offline".into())),
+                &mut Vec::new(),
+            )
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::Unsupported(_)));
+    }
+
+    #[cfg(feature = "lzma-secondary")]
+    #[test]
+    fn stream_instructions_undo_secondary_compression() {
+        use crate::compress::encoder::{CompressOptions, encode_all};
+        use crate::compress::secondary::SecondaryCompression;
+        use crate::test_util::XorShift32;
+
+        // Literal-heavy, low-entropy target so LZMA shrinks the DATA section.
+        let mut rng = XorShift32::new(0x9E37_79B9);
+        let target: Vec<u8> = (0..4000)
+            .map(|_| b"acgt"[(rng.next_u32() & 3) as usize])
+            .collect();
+        let encode = |secondary| {
+            let opts = CompressOptions {
+                window_size: 2048,
+                secondary,
+                ..Default::default()
+            };
+            encode_all(Vec::new(), &[], &target, opts).unwrap()
+        };
+        let instructions = |delta: &[u8]| {
+            let mut decoder = StreamDecoder::new(delta, true);
+            let items = decoder
+                .instructions()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            (items, decoder.last_window_header().unwrap().del_ind)
+        };
+        let (plain, del_ind) = instructions(&encode(SecondaryCompression::None));
+        assert_eq!((plain.last().unwrap().0, del_ind), (1, 0));
+        let (compressed, del_ind) = instructions(&encode(SecondaryCompression::Lzma));
+        assert_ne!(del_ind, 0);
+        assert_eq!(plain, compressed);
+    }
+}
@@ -1,463 +1,925 @@
-// VCDIFF encoder: instruction encoding and window emission.
-//
-// This module handles the low-level VCDIFF encoding: taking a sequence of
-// ADD/COPY/RUN instructions and producing a valid VCDIFF byte stream.
-// The actual match-finding (delta computation) lives in the engine module;
-// this module is concerned only with format-level encoding.
-
-use std::io::Write;
-
-use super::address_cache::AddressCache;
-use super::code_table::{
-    self, CodeTableEntry, InstructionInfo, XD3_ADD, XD3_CPY, XD3_RUN, choose_instruction,
-};
-use super::header::{self, FileHeader, VCD_ADLER32, VCD_SOURCE, WindowHeader};
-use super::varint;
-
-// ---------------------------------------------------------------------------
-// Window encoder
-// ---------------------------------------------------------------------------
-
-/// Accumulates instructions for a single VCDIFF window and emits encoded bytes.
-pub struct WindowEncoder {
-    /// DATA section: literal bytes for ADD and RUN instructions.
-    data_section: Vec<u8>,
-    /// INST section: opcode bytes (and inline sizes).
-    inst_section: Vec<u8>,
-    /// ADDR section: encoded COPY addresses.
-    addr_section: Vec<u8>,
-
-    /// Address cache.
-    acache: AddressCache,
-
-    /// Pending previous instruction (for double-instruction packing).
-    pending: Option<PendingInst>,
-
-    /// Target bytes emitted so far in this window.
-    target_len: u64,
-    /// Source copy-window parameters (if any).
-    source_window: Option<SourceWindow>,
-
-    /// Whether to emit an Adler-32 checksum.
-    emit_checksum: bool,
-
-    /// Code table reference.
-    code_table: &'static [CodeTableEntry; 256],
-}
-
-#[derive(Clone, Copy)]
-struct PendingInst {
-    info: InstructionInfo,
-    code1: u8,
-}
-
-/// Source copy-window parameters.
-#[derive(Debug, Clone, Copy)]
-pub struct SourceWindow {
-    pub len: u64,
-    pub offset: u64,
-}
-
-impl WindowEncoder {
-    /// Create a new window encoder.
-    pub fn new(source: Option<SourceWindow>, emit_checksum: bool) -> Self {
-        Self {
-            data_section: Vec::new(),
-            inst_section: Vec::new(),
-            addr_section: Vec::new(),
-            acache: AddressCache::new(),
-            pending: None,
-            target_len: 0,
-            source_window: source,
-            emit_checksum,
-            code_table: code_table::default_code_table(),
-        }
-    }
-
-    /// Create a window encoder with pre-allocated section capacities.
-    ///
-    /// Use this when encoding multiple windows to avoid repeated Vec growth.
-    /// Pass the section sizes from the previous window as hints.
-    pub fn with_capacity(
-        source: Option<SourceWindow>,
-        emit_checksum: bool,
-        data_cap: usize,
-        inst_cap: usize,
-        addr_cap: usize,
-    ) -> Self {
-        Self {
-            data_section: Vec::with_capacity(data_cap),
-            inst_section: Vec::with_capacity(inst_cap),
-            addr_section: Vec::with_capacity(addr_cap),
-            acache: AddressCache::new(),
-            pending: None,
-            target_len: 0,
-            source_window: source,
-            emit_checksum,
-            code_table: code_table::default_code_table(),
-        }
-    }
-
-    /// The current position in the combined address space
-    /// (copy_window_len + target bytes so far).
-    #[inline]
-    fn here(&self) -> u64 {
-        self.source_window.map_or(0, |s| s.len) + self.target_len
-    }
-
-    /// Add an ADD instruction with literal data.
-    pub fn add(&mut self, data: &[u8]) {
-        if data.is_empty() {
-            return;
-        }
-        self.data_section.extend_from_slice(data);
-        let inst = InstructionInfo {
-            itype: XD3_ADD,
-            size: data.len() as u32,
-        };
-        self.emit_instruction(inst);
-        self.target_len += data.len() as u64;
-    }
-
-    /// Add a COPY instruction.
-    ///
-    /// `addr` is in the combined source+target address space:
-    ///   - `0..source_window_len` for source copies
-    ///   - `source_window_len..` for target self-copies
-    pub fn copy(&mut self, len: u32, addr: u64, _mode: u8) {
-        if len == 0 {
-            return;
-        }
-        // Encode address.
-        let here = self.here();
-        let (enc_mode, encoded_addr) = self.acache.encode(addr, here);
-        encoded_addr.write_to(&mut self.addr_section).unwrap();
-
-        let inst = InstructionInfo {
-            itype: XD3_CPY + enc_mode,
-            size: len,
-        };
-        self.emit_instruction(inst);
-        self.target_len += len as u64;
-    }
-
-    /// Add a COPY instruction using a raw mode (caller provides the mode).
-    /// Address encoding is handled internally by the address cache.
-    pub fn copy_with_auto_mode(&mut self, len: u32, addr: u64) {
-        // Delegate to the address cache which picks the best mode.
-        self.copy(len, addr, 0); // mode parameter is ignored; acache.encode picks best
-    }
-
-    /// Add a RUN instruction.
-    pub fn run(&mut self, len: u32, byte: u8) {
-        if len == 0 {
-            return;
-        }
-        self.data_section.push(byte);
-        let inst = InstructionInfo {
-            itype: XD3_RUN,
-            size: len,
-        };
-        self.emit_instruction(inst);
-        self.target_len += len as u64;
-    }
-
-    /// Flush any pending instruction and finalize this window.
-    /// Returns the encoded window bytes (without file header).
-    pub fn finish(self, target_data: Option<&[u8]>) -> Vec<u8> {
-        let sections = self.finish_sections(target_data);
-        sections.assemble(0) // del_ind = 0 (no secondary compression)
-    }
-
-    /// Flush pending instructions and return the raw sections + metadata.
-    ///
-    /// This is the low-level API used by the compress module to apply
-    /// secondary compression to sections before assembling the final window.
-    pub fn finish_sections(mut self, target_data: Option<&[u8]>) -> WindowSections {
-        self.flush_pending();
-
-        // Compute checksum.
-        let checksum = if self.emit_checksum {
-            target_data.map(|data| {
-                #[cfg(feature = "adler32")]
-                {
-                    {
-                        let mut hasher = simd_adler32::Adler32::new();
-                        hasher.write(data);
-                        hasher.finish()
-                    }
-                }
-                #[cfg(not(feature = "adler32"))]
-                {
-                    // Fallback: simple Adler-32
-                    adler32_simple(data)
-                }
-            })
-        } else {
-            None
-        };
-
-        WindowSections {
-            source_window: self.source_window,
-            target_len: self.target_len,
-            checksum,
-            data_section: self.data_section,
-            inst_section: self.inst_section,
-            addr_section: self.addr_section,
-        }
-    }
-
-    // -----------------------------------------------------------------------
-    // Internal: instruction emission with double-packing
-    // -----------------------------------------------------------------------
-
-    fn emit_instruction(&mut self, inst: InstructionInfo) {
-        let chosen = choose_instruction(self.pending.as_ref().map(|p| &p.info), &inst);
-
-        if let Some(code2) = chosen.code2 {
-            // Double instruction: emit the previous + current as one opcode.
-            let _prev = self.pending.take().unwrap();
-            self.emit_opcode_double(code2);
-        } else {
-            // Flush any pending single instruction first.
-            self.flush_pending();
-            // Queue current as pending.
-            self.pending = Some(PendingInst {
-                info: inst,
-                code1: chosen.code1,
-            });
-        }
-    }
-
-    fn flush_pending(&mut self) {
-        if let Some(pending) = self.pending.take() {
-            self.emit_opcode_single(pending.code1, &pending.info);
-        }
-    }
-
-    /// Emit a single-instruction opcode.
-    /// If the code table entry has size1==0, emit the size as a varint.
-    fn emit_opcode_single(&mut self, code: u8, inst: &InstructionInfo) {
-        self.inst_section.push(code);
-        let entry = &self.code_table[code as usize];
-        if entry.size1 == 0 {
-            varint::write_u32(&mut self.inst_section, inst.size).unwrap();
-        }
-    }
-
-    /// Emit a double-instruction opcode (both sizes are implicit).
-    fn emit_opcode_double(&mut self, code: u8) {
-        self.inst_section.push(code);
-        // Double instructions always have fixed sizes in the code table.
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Window sections (intermediate result for secondary compression)
-// ---------------------------------------------------------------------------
-
-/// Raw sections from a finalized window, before assembly into bytes.
-///
-/// Allows the compress module to inspect/replace sections (e.g. for
-/// secondary compression) before calling `assemble()`.
-pub struct WindowSections {
-    pub source_window: Option<SourceWindow>,
-    pub target_len: u64,
-    pub checksum: Option<u32>,
-    pub data_section: Vec<u8>,
-    pub inst_section: Vec<u8>,
-    pub addr_section: Vec<u8>,
-}
-
-impl WindowSections {
-    /// Assemble the sections into encoded window bytes.
-    ///
-    /// `del_ind` indicates which sections have secondary compression applied
-    /// (VCD_DATACOMP, VCD_INSTCOMP, VCD_ADDRCOMP).
-    pub fn assemble(self, del_ind: u8) -> Vec<u8> {
-        let mut win_ind = 0u8;
-        if self.source_window.is_some() {
-            win_ind |= VCD_SOURCE;
-        }
-        if self.checksum.is_some() {
-            win_ind |= VCD_ADLER32;
-        }
-
-        let wh = WindowHeader {
-            win_ind,
-            copy_window_len: self.source_window.map_or(0, |s| s.len),
-            copy_window_offset: self.source_window.map_or(0, |s| s.offset),
-            enc_len: 0, // will be computed
-            target_window_len: self.target_len,
-            del_ind,
-            data_len: self.data_section.len() as u64,
-            inst_len: self.inst_section.len() as u64,
-            addr_len: self.addr_section.len() as u64,
-            adler32: self.checksum,
-        };
-        let enc_len = wh.compute_enc_len();
-        let wh = WindowHeader { enc_len, ..wh };
-
-        let mut out = Vec::new();
-        wh.encode(&mut out).unwrap();
-        out.extend_from_slice(&self.data_section);
-        out.extend_from_slice(&self.inst_section);
-        out.extend_from_slice(&self.addr_section);
-        out
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Full-stream encoder
-// ---------------------------------------------------------------------------
-
-/// Encodes a complete VCDIFF stream (file header + windows).
-pub struct StreamEncoder<W: Write> {
-    writer: W,
-    header_written: bool,
-    file_header: FileHeader,
-    #[allow(dead_code)]
-    emit_checksum: bool,
-}
-
-impl<W: Write> StreamEncoder<W> {
-    /// Create a new stream encoder.
-    pub fn new(writer: W, emit_checksum: bool) -> Self {
-        Self {
-            writer,
-            header_written: false,
-            file_header: FileHeader::default(),
-            emit_checksum,
-        }
-    }
-
-    /// Set the application header data.
-    pub fn set_app_header(&mut self, data: Vec<u8>) {
-        self.file_header.hdr_ind |= header::VCD_APPHEADER;
-        self.file_header.app_header = Some(data);
-    }
-
-    /// Write a complete window to the output.
-    pub fn write_window(
-        &mut self,
-        window: WindowEncoder,
-        target_data: Option<&[u8]>,
-    ) -> std::io::Result<()> {
-        if !self.header_written {
-            self.file_header.encode(&mut self.writer)?;
-            self.header_written = true;
-        }
-        let encoded = window.finish(target_data);
-        self.writer.write_all(&encoded)
-    }
-
-    /// Write pre-assembled window bytes to the output.
-    ///
-    /// Used by the compress module which assembles windows itself
-    /// (e.g. after applying secondary compression to sections).
-    pub fn write_raw_window(&mut self, encoded: &[u8]) -> std::io::Result<()> {
-        if !self.header_written {
-            self.file_header.encode(&mut self.writer)?;
-            self.header_written = true;
-        }
-        self.writer.write_all(encoded)
-    }
-
-    /// Set the file header to indicate secondary compression.
-    pub fn set_secondary_id(&mut self, id: u8) {
-        self.file_header.hdr_ind |= header::VCD_SECONDARY;
-        self.file_header.secondary_id = Some(id);
-    }
-
-    /// Flush and return the inner writer.
-    pub fn finish(mut self) -> std::io::Result<W> {
-        if !self.header_written {
-            self.file_header.encode(&mut self.writer)?;
-        }
-        self.writer.flush()?;
-        Ok(self.writer)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Fallback Adler-32 (when the `adler32` feature is disabled)
-// ---------------------------------------------------------------------------
-
-#[cfg(not(feature = "adler32"))]
-fn adler32_simple(data: &[u8]) -> u32 {
-    const MOD_ADLER: u32 = 65521;
-    let mut a: u32 = 1;
-    let mut b: u32 = 0;
-    for &byte in data {
-        a = (a + u32::from(byte)) % MOD_ADLER;
-        b = (b + a) % MOD_ADLER;
-    }
-    (b << 16) | a
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn encode_single_add() {
-        let mut we = WindowEncoder::new(None, false);
-        we.add(b"hello");
-        let bytes = we.finish(None);
-        // Should produce a valid window header + sections.
-        assert!(!bytes.is_empty());
-    }
-
-    #[test]
-    fn encode_single_run() {
-        let mut we = WindowEncoder::new(None, false);
-        we.run(100, 0xAA);
-        let bytes = we.finish(None);
-        assert!(!bytes.is_empty());
-    }
-
-    #[test]
-    fn encode_add_then_copy_packs_double() {
-        let src = SourceWindow {
-            len: 1024,
-            offset: 0,
-        };
-        let mut we = WindowEncoder::new(Some(src), false);
-        // ADD(1) followed by COPY(4, addr=0) should produce a double opcode.
-        we.add(b"X");
-        we.copy_with_auto_mode(4, 0);
-        let bytes = we.finish(None);
-        // The inst section should be compact (double instruction = 1 byte).
-        assert!(!bytes.is_empty());
-    }
-
-    #[test]
-    fn stream_encoder_writes_header() {
-        let mut out = Vec::new();
-        let mut enc = StreamEncoder::new(&mut out, false);
-        let we = WindowEncoder::new(None, false);
-        enc.write_window(we, None).unwrap();
-        let _ = enc.finish().unwrap();
-
-        // Should start with VCDIFF magic.
-        assert_eq!(&out[..4], &header::VCDIFF_MAGIC);
-    }
-
-    #[test]
-    fn encode_with_checksum() {
-        let target = b"hello world";
-        let mut we = WindowEncoder::new(None, true);
-        we.add(target);
-        let bytes = we.finish(Some(target));
-
-        // Parse the window header to verify checksum is present.
-        let mut cursor = std::io::Cursor::new(&bytes);
-        let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
-        assert!(wh.has_checksum());
-        assert!(wh.adler32.is_some());
-    }
-}
+// VCDIFF encoder: instruction encoding and window emission.
+//
+// This module handles the low-level VCDIFF encoding: taking a sequence of
+// ADD/COPY/RUN instructions and producing a valid VCDIFF byte stream.
+// The actual match-finding (delta computation) lives in the engine module;
+// this module is concerned only with format-level encoding.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use super::address_cache::AddressCache;
+use super::code_table::{
+    self, ChosenInstruction, CodeTable, CodeTableError, CodeTableIndex, CustomCodeTable,
+    Instruction, InstructionInfo, XD3_ADD, XD3_CPY, XD3_RUN,
+};
+use super::decoder::DecodeError;
+use super::header::{self, FileHeader, VCD_ADLER32, VCD_SOURCE, VCD_TARGET, WindowHeader};
+use super::integrity::Integrity;
+use super::varint;
+
+// ---------------------------------------------------------------------------
+// Window encoder
+// ---------------------------------------------------------------------------
+
+/// Accumulates instructions for a single VCDIFF window and emits encoded bytes.
+pub struct WindowEncoder {
+    /// DATA section: literal bytes for ADD and RUN instructions.
+    data_section: Vec<u8>,
+    /// INST section: opcode bytes (and inline sizes).
+    inst_section: Vec<u8>,
+    /// ADDR section: encoded COPY addresses.
+    addr_section: Vec<u8>,
+
+    /// Address cache.
+    acache: AddressCache,
+
+    /// Pending previous instruction (for double-instruction packing).
+    pending: Option<PendingInst>,
+
+    /// Target bytes emitted so far in this window.
+    target_len: u64,
+    /// Source copy-window parameters (if any).
+    source_window: Option<SourceWindow>,
+    /// Whether the copy window is earlier target output (`VCD_TARGET`).
+    target_window: bool,
+
+    /// Whether to emit a window checksum.
+    emit_checksum: bool,
+    /// Checksum algorithm (see `set_integrity`).
+    integrity: Integrity,
+
+    /// Code table reference.
+    code_table: &'static CodeTable,
+    /// Application-defined code table, used instead of `code_table` when set.
+    custom_table: Option<Arc<CodeTableIndex>>,
+
+    /// Out-of-line ADD literals (oxidelta extension, see `set_external_data`).
+    external_data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy)]
+struct PendingInst {
+    info: InstructionInfo,
+    code1: u8,
+}
+
+/// Source copy-window parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceWindow {
+    pub len: u64,
+    pub offset: u64,
+}
+
+/// Instructions passed to [`WindowEncoder::from_instructions`] do not
+/// produce exactly the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverageError {
+    /// Instruction `index` would run past the end of the target.
+    Overrun {
+        index: usize,
+        covered: u64,
+        target_len: u64,
+    },
+    /// The instructions end before the target does.
+    Short { covered: u64, target_len: u64 },
+}
+
+impl std::fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Overrun {
+                index,
+                covered,
+                target_len,
+            } => write!(
+                f,
+                "instruction {index} runs past the {target_len}-byte target \
+                 ({covered} bytes already covered)"
+            ),
+            Self::Short {
+                covered,
+                target_len,
+            } => write!(
+                f,
+                "instructions cover {covered} bytes of a {target_len}-byte target"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CoverageError {}
+
+impl WindowEncoder {
+    /// Create a new window encoder.
+    pub fn new(source: Option<SourceWindow>, emit_checksum: bool) -> Self {
+        Self {
+            data_section: Vec::new(),
+            inst_section: Vec::new(),
+            addr_section: Vec::new(),
+            acache: AddressCache::new(),
+            pending: None,
+            target_len: 0,
+            source_window: source,
+            target_window: false,
+            emit_checksum,
+            integrity: Integrity::Adler32,
+            code_table: code_table::default_code_table(),
+            custom_table: None,
+            external_data: None,
+        }
+    }
+
+    /// Create a window encoder with pre-allocated section capacities.
+    ///
+    /// Use this when encoding multiple windows to avoid repeated Vec growth.
+    /// Pass the section sizes from the previous window as hints.
+    pub fn with_capacity(
+        source: Option<SourceWindow>,
+        emit_checksum: bool,
+        data_cap: usize,
+        inst_cap: usize,
+        addr_cap: usize,
+    ) -> Self {
+        Self {
+            data_section: Vec::with_capacity(data_cap),
+            inst_section: Vec::with_capacity(inst_cap),
+            addr_section: Vec::with_capacity(addr_cap),
+            acache: AddressCache::new(),
+            pending: None,
+            target_len: 0,
+            source_window: source,
+            target_window: false,
+            emit_checksum,
+            integrity: Integrity::Adler32,
+            code_table: code_table::default_code_table(),
+            custom_table: None,
+            external_data: None,
+        }
+    }
+
+    /// Build a window from a complete instruction list for `target`.
+    ///
+    /// ADD literals and RUN bytes are taken from `target`; COPY modes are
+    /// chosen as by [`copy_with_auto_mode`](Self::copy_with_auto_mode). The
+    /// instruction lengths must add up to exactly `target.len()`.
+    ///
+    /// ```
+    /// use oxidelta::vcdiff::{Instruction, SourceWindow, WindowEncoder};
+    ///
+    /// let source = SourceWindow { len: 4, offset: 0 };
+    /// let insts = [Instruction::Copy { len: 4, addr: 0, mode: 0 }, Instruction::Add { len: 1 }];
+    /// let we = WindowEncoder::from_instructions(Some(source), true, b"abcd!", &insts).unwrap();
+    /// let window = we.finish(Some(b"abcd!"));
+    /// assert!(!window.is_empty());
+    /// ```
+    pub fn from_instructions(
+        source: Option<SourceWindow>,
+        emit_checksum: bool,
+        target: &[u8],
+        instructions: &[Instruction],
+    ) -> Result<Self, CoverageError> {
+        let mut we = Self::new(source, emit_checksum);
+        we.push_instructions(target, instructions)?;
+        Ok(we)
+    }
+
+    /// Emit `instructions`, which must cover all of `target`, taking ADD and
+    /// RUN bytes from it. On error the window is left partially built.
+    pub(crate) fn push_instructions(
+        &mut self,
+        target: &[u8],
+        instructions: &[Instruction],
+    ) -> Result<(), CoverageError> {
+        let target_len = target.len() as u64;
+        let mut pos = 0u64;
+        for (index, inst) in instructions.iter().enumerate() {
+            let len = match *inst {
+                Instruction::Add { len }
+                | Instruction::Copy { len, .. }
+                | Instruction::Run { len } => u64::from(len),
+            };
+            if len > target_len - pos {
+                return Err(CoverageError::Overrun {
+                    index,
+                    covered: pos,
+                    target_len,
+                });
+            }
+            let at = pos as usize;
+            match *inst {
+                Instruction::Add { len } => self.add(&target[at..at + len as usize]),
+                Instruction::Copy { len, addr, .. } => self.copy_with_auto_mode(len, addr),
+                Instruction::Run { len } => {
+                    if len > 0 {
+                        self.run(len, target[at]);
+                    }
+                }
+            }
+            pos += len;
+        }
+        if pos < target_len {
+            return Err(CoverageError::Short {
+                covered: pos,
+                target_len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Encode this window with an application-defined code table.
+    ///
+    /// Must be called before any instruction is added. The stream's file
+    /// header must carry the same table (see [`StreamEncoder::set_code_table`]).
+    pub fn set_code_table(&mut self, table: Arc<CodeTableIndex>) {
+        debug_assert!(self.pending.is_none() && self.inst_section.is_empty());
+        let custom = table.table();
+        self.acache = AddressCache::with_sizes(custom.near as usize, custom.same as usize);
+        self.custom_table = Some(table);
+    }
+
+    /// Read the copy window from earlier target output instead of the
+    /// source: the window is emitted as `VCD_TARGET`, and the
+    /// [`SourceWindow`] passed to [`new`](Self::new) gives the offset and
+    /// length of the copy window within the target.
+    ///
+    /// xdelta3 cannot decode such windows, and oxidelta only when the
+    /// decoder's output buffer still holds the earlier target (see
+    /// [`StreamDecoder::decode_all`](super::decoder::StreamDecoder::decode_all)).
+    pub fn set_target_window(&mut self, enabled: bool) {
+        self.target_window = enabled;
+    }
+
+    /// Choose the window checksum algorithm (Adler-32 by default).
+    ///
+    /// [`Integrity::Crc32c`] is an oxidelta extension: the stream's
+    /// application header must declare it (see
+    /// [`integrity`](super::integrity)), or decoders verify the CRC as an
+    /// Adler-32 and reject the window. [`Integrity::None`] omits the
+    /// checksum like `emit_checksum = false`.
+    pub fn set_integrity(&mut self, integrity: Integrity) {
+        self.integrity = integrity;
+    }
+
+    /// Enable or disable external-data mode.
+    ///
+    /// **oxidelta extension — not RFC 3284.** In external-data mode the
+    /// literal bytes of ADD instructions are written to a separate flat
+    /// literal stream instead of the window's DATA section (RUN bytes stay
+    /// inline). Retrieve the literals with [`take_external_data`] and decode
+    /// with [`StreamDecoder::decode_window_external`]. Windows produced this
+    /// way cannot be decoded by xdelta3 or any standard VCDIFF decoder.
+    ///
+    /// [`take_external_data`]: Self::take_external_data
+    /// [`StreamDecoder::decode_window_external`]: super::decoder::StreamDecoder::decode_window_external
+    pub fn set_external_data(&mut self, enabled: bool) {
+        self.external_data = if enabled {
+            Some(self.external_data.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    /// Take the out-of-line ADD literals accumulated so far.
+    ///
+    /// Returns an empty Vec when external-data mode is disabled.
+    pub fn take_external_data(&mut self) -> Vec<u8> {
+        self.external_data
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The current position in the combined address space
+    /// (copy_window_len + target bytes so far).
+    #[inline]
+    fn here(&self) -> u64 {
+        self.source_window.map_or(0, |s| s.len) + self.target_len
+    }
+
+    /// Add an ADD instruction with literal data.
+    pub fn add(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        match self.external_data.as_mut() {
+            Some(ext) => ext.extend_from_slice(data),
+            None => self.data_section.extend_from_slice(data),
+        }
+        let inst = InstructionInfo {
+            itype: XD3_ADD,
+            size: data.len() as u32,
+        };
+        self.emit_instruction(inst);
+        self.target_len += data.len() as u64;
+    }
+
+    /// Add a COPY instruction.
+    ///
+    /// `addr` is in the combined source+target address space:
+    ///   - `0..source_window_len` for source copies
+    ///   - `source_window_len..` for target self-copies
+    pub fn copy(&mut self, len: u32, addr: u64, _mode: u8) {
+        if len == 0 {
+            return;
+        }
+        // Encode address.
+        let here = self.here();
+        let (enc_mode, encoded_addr) = self.acache.encode(addr, here);
+        encoded_addr.write_to(&mut self.addr_section).unwrap();
+
+        let inst = InstructionInfo {
+            itype: XD3_CPY + enc_mode,
+            size: len,
+        };
+        self.emit_instruction(inst);
+        self.target_len += len as u64;
+    }
+
+    /// Add a COPY instruction, letting the address cache pick the mode.
+    ///
+    /// The mode is the one [`AddressCache::best_mode`] reports for `addr`
+    /// at the current position. Only modes valid for the window's NEAR/SAME cache sizes are chosen;
+    /// with a custom code table those come from [`set_code_table`].
+    ///
+    /// [`set_code_table`]: Self::set_code_table
+    pub fn copy_with_auto_mode(&mut self, len: u32, addr: u64) {
+        // Delegate to the address cache which picks the best mode.
+        self.copy(len, addr, 0); // mode parameter is ignored; acache.encode picks best
+    }
+
+    /// Add a RUN instruction.
+    pub fn run(&mut self, len: u32, byte: u8) {
+        if len == 0 {
+            return;
+        }
+        self.data_section.push(byte);
+        let inst = InstructionInfo {
+            itype: XD3_RUN,
+            size: len,
+        };
+        self.emit_instruction(inst);
+        self.target_len += len as u64;
+    }
+
+    /// Flush any pending instruction and finalize this window.
+    /// Returns the encoded window bytes (without file header).
+    pub fn finish(self, target_data: Option<&[u8]>) -> Vec<u8> {
+        let sections = self.finish_sections(target_data);
+        sections.assemble(0) // del_ind = 0 (no secondary compression)
+    }
+
+    /// Flush pending instructions and return the raw sections + metadata.
+    ///
+    /// This is the low-level API used by the compress module to apply
+    /// secondary compression to sections before assembling the final window.
+    pub fn finish_sections(mut self, target_data: Option<&[u8]>) -> WindowSections {
+        self.flush_pending();
+
+        let checksum = match target_data {
+            Some(data) if self.emit_checksum => self.integrity.checksum(data),
+            _ => None,
+        };
+
+        WindowSections {
+            source_window: self.source_window,
+            target_window: self.target_window,
+            target_len: self.target_len,
+            checksum,
+            data_section: self.data_section,
+            inst_section: self.inst_section,
+            addr_section: self.addr_section,
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Internal: instruction emission with double-packing
+    // -----------------------------------------------------------------------
+
+    fn emit_instruction(&mut self, inst: InstructionInfo) {
+        let prev = self.pending.as_ref().map(|p| &p.info);
+        let chosen = match self.custom_table {
+            Some(ref index) => index.choose(prev, &inst),
+            None => ChosenInstruction {
+                code1: self
+                    .code_table
+                    .pair_opcode(&inst, None)
+                    .expect("the default table encodes every non-empty instruction"),
+                code2: prev.and_then(|prev| self.code_table.pair_opcode(prev, Some(&inst))),
+            },
+        };
+
+        if let Some(code2) = chosen.code2 {
+            // Double instruction: emit the previous + current as one opcode.
+            let _prev = self.pending.take().unwrap();
+            self.emit_opcode_double(code2);
+        } else {
+            // Flush any pending single instruction first.
+            self.flush_pending();
+            // Queue current as pending.
+            self.pending = Some(PendingInst {
+                info: inst,
+                code1: chosen.code1,
+            });
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.emit_opcode_single(pending.code1, &pending.info);
+        }
+    }
+
+    /// Emit a single-instruction opcode.
+    /// If the code table entry has size1==0, emit the size as a varint.
+    fn emit_opcode_single(&mut self, code: u8, inst: &InstructionInfo) {
+        self.inst_section.push(code);
+        let entry = match self.custom_table {
+            Some(ref index) => &index.table().table[code as usize],
+            None => &self.code_table[code as usize],
+        };
+        if entry.size1 == 0 {
+            varint::write_u32(&mut self.inst_section, inst.size).unwrap();
+        }
+    }
+
+    /// Emit a double-instruction opcode (both sizes are implicit).
+    fn emit_opcode_double(&mut self, code: u8) {
+        self.inst_section.push(code);
+        // Double instructions always have fixed sizes in the code table.
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Window sections (intermediate result for secondary compression)
+// ---------------------------------------------------------------------------
+
+/// Raw sections from a finalized window, before assembly into bytes.
+///
+/// Allows the compress module to inspect/replace sections (e.g. for
+/// secondary compression) before calling `assemble()`. Applications that
+/// store windows in a container of their own can [`assemble`](Self::assemble)
+/// one window at a time and split it again with
+/// [`disassemble`](Self::disassemble).
+///
+/// ```
+/// use oxidelta::vcdiff::{WindowEncoder, WindowSections};
+///
+/// let mut enc = WindowEncoder::new(None, true);
+/// enc.add(b"hello");
+/// let bytes = enc.finish_sections(Some(b"hello")).assemble(0);
+///
+/// let (header, sections) = WindowSections::disassemble(&bytes).unwrap();
+/// assert_eq!(header.target_window_len, 5);
+/// assert_eq!(sections.data_section, b"hello");
+/// assert_eq!(sections.assemble(header.del_ind), bytes);
+/// ```
+#[derive(Debug, Clone)]
+pub struct WindowSections {
+    /// Copy window (`VCD_SOURCE` or `VCD_TARGET`), if the window has one.
+    pub source_window: Option<SourceWindow>,
+    /// `source_window` lies in earlier target output (`VCD_TARGET`).
+    pub target_window: bool,
+    /// Bytes the window decodes to.
+    pub target_len: u64,
+    /// Window checksum (`VCD_ADLER32`).
+    pub checksum: Option<u32>,
+    /// DATA section, as stored (secondary-compressed if `del_ind` says so).
+    pub data_section: Vec<u8>,
+    /// INST section, as stored.
+    pub inst_section: Vec<u8>,
+    /// ADDR section, as stored.
+    pub addr_section: Vec<u8>,
+}
+
+impl WindowSections {
+    /// Assemble the sections into encoded window bytes.
+    ///
+    /// `del_ind` indicates which sections have secondary compression applied
+    /// (VCD_DATACOMP, VCD_INSTCOMP, VCD_ADDRCOMP).
+    pub fn assemble(self, del_ind: u8) -> Vec<u8> {
+        let mut win_ind = 0u8;
+        if self.source_window.is_some() {
+            win_ind |= if self.target_window {
+                VCD_TARGET
+            } else {
+                VCD_SOURCE
+            };
+        }
+        if self.checksum.is_some() {
+            win_ind |= VCD_ADLER32;
+        }
+
+        let wh = WindowHeader {
+            win_ind,
+            copy_window_len: self.source_window.map_or(0, |s| s.len),
+            copy_window_offset: self.source_window.map_or(0, |s| s.offset),
+            enc_len: 0, // will be computed
+            target_window_len: self.target_len,
+            del_ind,
+            data_len: self.data_section.len() as u64,
+            inst_len: self.inst_section.len() as u64,
+            addr_len: self.addr_section.len() as u64,
+            adler32: self.checksum,
+        };
+        let enc_len = wh.compute_enc_len();
+        let wh = WindowHeader { enc_len, ..wh };
+
+        let mut out = Vec::new();
+        wh.encode(&mut out).unwrap();
+        out.extend_from_slice(&self.data_section);
+        out.extend_from_slice(&self.inst_section);
+        out.extend_from_slice(&self.addr_section);
+        out
+    }
+
+    /// Split one encoded window back into its header and sections; the
+    /// inverse of [`assemble`](Self::assemble).
+    ///
+    /// `bytes` must hold exactly one window, without a file header. The
+    /// header's `del_ind` is what `assemble` was given; sections it flags
+    /// are returned still compressed.
+    pub fn disassemble(bytes: &[u8]) -> Result<(WindowHeader, WindowSections), DecodeError> {
+        let truncated = |expected_more| DecodeError::Truncated {
+            at_window: 0,
+            expected_more,
+        };
+        let mut rest = bytes;
+        let wh = match WindowHeader::decode(&mut rest) {
+            Ok(Some(wh)) => wh,
+            Ok(None) => return Err(truncated(false)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(truncated(false));
+            }
+            Err(e) => return Err(DecodeError::Io(e)),
+        };
+
+        let mut take = |len: u64| -> Result<Vec<u8>, DecodeError> {
+            let len = usize::try_from(len)
+                .ok()
+                .filter(|&len| len <= rest.len())
+                .ok_or(truncated(true))?;
+            let (section, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(section.to_vec())
+        };
+        let data_section = take(wh.data_len)?;
+        let inst_section = take(wh.inst_len)?;
+        let addr_section = take(wh.addr_len)?;
+        if !rest.is_empty() {
+            return Err(DecodeError::InvalidInput(format!(
+                "{} bytes after the window's sections",
+                rest.len()
+            )));
+        }
+
+        let sections = WindowSections {
+            source_window: (wh.has_source() || wh.has_target()).then_some(SourceWindow {
+                len: wh.copy_window_len,
+                offset: wh.copy_window_offset,
+            }),
+            target_window: wh.has_target(),
+            target_len: wh.target_window_len,
+            checksum: wh.adler32,
+            data_section,
+            inst_section,
+            addr_section,
+        };
+        Ok((wh, sections))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Full-stream encoder
+// ---------------------------------------------------------------------------
+
+/// Encodes a complete VCDIFF stream (file header + windows).
+pub struct StreamEncoder<W: Write> {
+    writer: W,
+    header_written: bool,
+    file_header: FileHeader,
+    #[allow(dead_code)]
+    emit_checksum: bool,
+    /// Application-defined code table (see `set_code_table`).
+    code_table: Option<Arc<CodeTableIndex>>,
+    /// Header and window bytes written so far.
+    bytes_written: u64,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    /// Create a new stream encoder.
+    pub fn new(writer: W, emit_checksum: bool) -> Self {
+        Self {
+            writer,
+            header_written: false,
+            file_header: FileHeader::default(),
+            emit_checksum,
+            code_table: None,
+            bytes_written: 0,
+        }
+    }
+
+    /// Use an application-defined code table (RFC 3284, Section 7).
+    ///
+    /// The table is shipped in the file header, so this must be called
+    /// before the first window is written. Windows must then be created with
+    /// [`window_encoder`](Self::window_encoder) so they use the same table.
+    /// Fails if the table cannot encode every instruction type.
+    pub fn set_code_table(&mut self, table: CustomCodeTable) -> Result<(), CodeTableError> {
+        debug_assert!(!self.header_written);
+        let index = CodeTableIndex::new(table.clone())?;
+        self.file_header.hdr_ind |= header::VCD_CODETABLE;
+        self.file_header.code_table = Some(table);
+        self.code_table = Some(Arc::new(index));
+        Ok(())
+    }
+
+    /// Create a window encoder using this stream's code table.
+    pub fn window_encoder(
+        &self,
+        source: Option<SourceWindow>,
+        emit_checksum: bool,
+    ) -> WindowEncoder {
+        let mut we = WindowEncoder::new(source, emit_checksum);
+        if let Some(ref table) = self.code_table {
+            we.set_code_table(Arc::clone(table));
+        }
+        we
+    }
+
+    /// Set the application header data.
+    pub fn set_app_header(&mut self, data: Vec<u8>) {
+        self.file_header.hdr_ind |= header::VCD_APPHEADER;
+        self.file_header.app_header = Some(data);
+    }
+
+    /// Write a complete window to the output.
+    pub fn write_window(
+        &mut self,
+        window: WindowEncoder,
+        target_data: Option<&[u8]>,
+    ) -> std::io::Result<()> {
+        debug_assert_eq!(
+            window.custom_table.as_ref().map(Arc::as_ptr),
+            self.code_table.as_ref().map(Arc::as_ptr),
+            "window encoded with a different code table than the stream",
+        );
+        self.write_header()?;
+        let encoded = window.finish(target_data);
+        self.write_bytes(&encoded)
+    }
+
+    /// Write pre-assembled window bytes to the output.
+    ///
+    /// Used by the compress module which assembles windows itself
+    /// (e.g. after applying secondary compression to sections).
+    pub fn write_raw_window(&mut self, encoded: &[u8]) -> std::io::Result<()> {
+        self.write_header()?;
+        self.write_bytes(encoded)
+    }
+
+    /// Number of bytes (file header and windows) written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Set the file header to indicate secondary compression.
+    pub fn set_secondary_id(&mut self, id: u8) {
+        self.file_header.hdr_ind |= header::VCD_SECONDARY;
+        self.file_header.secondary_id = Some(id);
+    }
+
+    /// Flush the inner writer, so everything written so far reaches it.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush and return the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Write the file header, unless it already went out.
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        let mut header = Vec::new();
+        self.file_header.encode(&mut header)?;
+        self.write_bytes(&header)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_inverts_assemble() {
+        let cases = [
+            (None, false, None, 0u8),
+            (Some((100, 7)), false, Some(0xDEAD_BEEF), 0x07),
+            (Some((3, 0)), true, None, 0x02),
+        ];
+        for (window, target_window, checksum, del_ind) in cases {
+            let sections = WindowSections {
+                source_window: window.map(|(len, offset)| SourceWindow { len, offset }),
+                target_window,
+                target_len: 300,
+                checksum,
+                data_section: b"literal data".to_vec(),
+                inst_section: vec![0x01, 0x0C, 0x13],
+                addr_section: if window.is_some() { vec![0x05] } else { vec![] },
+            };
+            let bytes = sections.clone().assemble(del_ind);
+
+            let (wh, back) = WindowSections::disassemble(&bytes).unwrap();
+            assert_eq!(wh.del_ind, del_ind);
+            assert_eq!(wh.adler32, checksum);
+            assert_eq!(wh.has_target(), target_window);
+            assert_eq!(wh.target_window_len, 300);
+            assert_eq!(
+                back.source_window.map(|w| (w.len, w.offset)),
+                window,
+                "{window:?}"
+            );
+            assert_eq!(back.target_window, target_window);
+            assert_eq!((back.target_len, back.checksum), (300, checksum));
+            assert_eq!(back.data_section, sections.data_section);
+            assert_eq!(back.inst_section, sections.inst_section);
+            assert_eq!(back.addr_section, sections.addr_section);
+            assert_eq!(back.assemble(wh.del_ind), bytes);
+
+            // One byte short is truncation; one byte over is rejected.
+            assert!(matches!(
+                WindowSections::disassemble(&bytes[..bytes.len() - 1]),
+                Err(DecodeError::Truncated {
+                    expected_more: true,
+                    ..
+                })
+            ));
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert!(matches!(
+                WindowSections::disassemble(&longer),
+                Err(DecodeError::InvalidInput(_))
+            ));
+        }
+        assert!(matches!(
+            WindowSections::disassemble(&[]),
+            Err(DecodeError::Truncated {
+                expected_more: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn encode_single_add() {
+        let mut we = WindowEncoder::new(None, false);
+        we.add(b"hello");
+        let bytes = we.finish(None);
+        // Should produce a valid window header + sections.
+        assert!(!bytes.is_empty());
+    }
+
+    #[test]
+    fn from_instructions_checks_coverage() {
+        let src = SourceWindow { len: 8, offset: 0 };
+        let source = b"abcdefgh";
+        let target = b"abcdefgh!!!!!!!!?";
+        let insts = [
+            Instruction::Copy {
+                len: 8,
+                addr: 0,
+                mode: 0,
+            },
+            Instruction::Run { len: 8 },
+            Instruction::Add { len: 1 },
+        ];
+
+        let mut by_hand = WindowEncoder::new(Some(src), true);
+        by_hand.copy_with_auto_mode(8, 0);
+        by_hand.run(8, b'!');
+        by_hand.add(b"?");
+        let window = WindowEncoder::from_instructions(Some(src), true, target, &insts)
+            .unwrap()
+            .finish(Some(target));
+        assert_eq!(window, by_hand.finish(Some(target)));
+
+        let mut delta = Vec::new();
+        let mut stream = StreamEncoder::new(&mut delta, true);
+        stream.write_raw_window(&window).unwrap();
+        stream.finish().unwrap();
+        assert_eq!(
+            crate::vcdiff::decoder::decode_memory(&delta, source).unwrap(),
+            target
+        );
+
+        // One byte short, then one instruction too many.
+        let short = WindowEncoder::from_instructions(Some(src), true, target, &insts[..2]);
+        assert_eq!(
+            short.err(),
+            Some(CoverageError::Short {
+                covered: 16,
+                target_len: 17
+            })
+        );
+        let mut long = insts.to_vec();
+        long.push(Instruction::Add { len: 1 });
+        let err = WindowEncoder::from_instructions(Some(src), true, target, &long)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            CoverageError::Overrun {
+                index: 3,
+                covered: 17,
+                target_len: 17
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "instruction 3 runs past the 17-byte target (17 bytes already covered)"
+        );
+    }
+
+    #[test]
+    fn encode_single_run() {
+        let mut we = WindowEncoder::new(None, false);
+        we.run(100, 0xAA);
+        let bytes = we.finish(None);
+        assert!(!bytes.is_empty());
+    }
+
+    #[test]
+    fn encode_add_then_copy_packs_double() {
+        let src = SourceWindow {
+            len: 1024,
+            offset: 0,
+        };
+        let mut we = WindowEncoder::new(Some(src), false);
+        // ADD(1) followed by COPY(4, addr=0) should produce a double opcode.
+        we.add(b"X");
+        we.copy_with_auto_mode(4, 0);
+        let bytes = we.finish(None);
+        // The inst section should be compact (double instruction = 1 byte).
+        assert!(!bytes.is_empty());
+    }
+
+    #[test]
+    fn stream_encoder_writes_header() {
+        let mut out = Vec::new();
+        let mut enc = StreamEncoder::new(&mut out, false);
+        let we = WindowEncoder::new(None, false);
+        enc.write_window(we, None).unwrap();
+        let _ = enc.finish().unwrap();
+
+        // Should start with VCDIFF magic.
+        assert_eq!(&out[..4], &header::VCDIFF_MAGIC);
+    }
+
+    #[test]
+    fn encode_with_checksum() {
+        let target = b"hello world";
+        let mut we = WindowEncoder::new(None, true);
+        we.add(target);
+        let bytes = we.finish(Some(target));
+
+        // Parse the window header to verify checksum is present.
+        let mut cursor = std::io::Cursor::new(&bytes);
+        let wh = WindowHeader::decode(&mut cursor).unwrap().unwrap();
+        assert!(wh.has_checksum());
+        assert!(wh.adler32.is_some());
+    }
+
+    #[test]
+    fn external_data_moves_add_literals_out_of_line() {
+        let mut we = WindowEncoder::new(None, false);
+        we.set_external_data(true);
+        we.add(b"hello");
+        we.run(10, b'z');
+        we.add(b"world");
+        let literals = we.take_external_data();
+        assert_eq!(literals, b"helloworld");
+
+        let sections = we.finish_sections(None);
+        // Only the RUN byte remains in the DATA section.
+        assert_eq!(sections.data_section, b"z");
+        assert_eq!(sections.target_len, 20);
+    }
+}
@@ -0,0 +1,327 @@
+// Window integrity checks.
+//
+// RFC 3284 leaves window checksums to the application; xdelta3 stores an
+// Adler-32 of the window's target bytes (`VCD_ADLER32`). As an oxidelta
+// extension the same field may instead hold a CRC-32C (Castagnoli), which
+// catches more burst errors. The choice is recorded in the application
+// header as the token `checksum:crc32c`, separated from other tokens by `/`
+// or whitespace like the `sha256:` digest. xdelta3 does not know the token
+// and verifies every window as Adler-32, so it rejects such deltas unless
+// checksum verification is disabled (`xdelta3 -n`). An `AppHeaderMap`
+// header declares it with a `checksum` entry of `crc32c` instead.
+
+use super::app_header::{AppHeaderMap, CHECKSUM_KEY};
+
+/// Application header token that marks window checksums as CRC-32C.
+pub const CRC32C_TOKEN: &[u8] = b"checksum:crc32c";
+
+/// [`CHECKSUM_KEY`] value that marks window checksums as CRC-32C.
+const CRC32C_VALUE: &str = "crc32c";
+
+/// Checksum stored with each encoded window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Integrity {
+    /// Adler-32, as written and verified by xdelta3.
+    #[default]
+    Adler32,
+    /// CRC-32C; non-standard, declared by [`CRC32C_TOKEN`].
+    Crc32c,
+    /// No window checksums.
+    None,
+}
+
+impl Integrity {
+    /// The window checksum declared by an application header: CRC-32C if
+    /// it carries [`CRC32C_TOKEN`], or is an [`AppHeaderMap`] whose
+    /// `checksum` entry is `crc32c`; otherwise the standard Adler-32.
+    ///
+    /// ```
+    /// use oxidelta::vcdiff::Integrity;
+    /// assert_eq!(Integrity::from_app_header(Some(b"v2/checksum:crc32c")), Integrity::Crc32c);
+    /// assert_eq!(Integrity::from_app_header(Some(b"v2")), Integrity::Adler32);
+    /// assert_eq!(Integrity::from_app_header(None), Integrity::Adler32);
+    /// ```
+    pub fn from_app_header(app_header: Option<&[u8]>) -> Self {
+        let declared = app_header.is_some_and(|data| match AppHeaderMap::parse(data) {
+            Ok(map) => map.get(CHECKSUM_KEY) == Some(CRC32C_VALUE),
+            Err(_) => data
+                .split(|&b| b == b'/' || b.is_ascii_whitespace())
+                .any(|token| token == CRC32C_TOKEN),
+        });
+        if declared {
+            Self::Crc32c
+        } else {
+            Self::Adler32
+        }
+    }
+
+    /// Checksum of `data` with this algorithm; `None` for [`Integrity::None`].
+    pub fn checksum(self, data: &[u8]) -> Option<u32> {
+        let mut hasher = WindowHasher::new(self)?;
+        hasher.update(data);
+        Some(hasher.finish())
+    }
+}
+
+/// Declare CRC-32C in an application header, unless it already does: an
+/// [`AppHeaderMap`] gets a `checksum` entry, any other header
+/// [`CRC32C_TOKEN`].
+#[cfg(feature = "std")]
+pub(crate) fn with_crc32c_token(app_header: Option<&[u8]>) -> Vec<u8> {
+    let mut out = app_header.unwrap_or_default().to_vec();
+    if Integrity::from_app_header(Some(&out)) == Integrity::Crc32c {
+        return out;
+    }
+    if let Ok(map) = AppHeaderMap::parse(&out) {
+        // `get` sees the first entry, so drop any other checksum declared.
+        let mut map: AppHeaderMap = map.iter().filter(|&(k, _)| k != CHECKSUM_KEY).collect();
+        map.push(CHECKSUM_KEY, CRC32C_VALUE);
+        out = map.to_bytes();
+    } else {
+        if !out.is_empty() {
+            out.push(b'/');
+        }
+        out.extend_from_slice(CRC32C_TOKEN);
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Incremental hashers
+// ---------------------------------------------------------------------------
+
+/// Incremental window checksum of either kind.
+pub(crate) enum WindowHasher {
+    Adler32(Adler32),
+    Crc32c(Crc32c),
+}
+
+impl WindowHasher {
+    pub(crate) fn new(integrity: Integrity) -> Option<Self> {
+        match integrity {
+            Integrity::Adler32 => Some(Self::Adler32(Adler32::new())),
+            Integrity::Crc32c => Some(Self::Crc32c(Crc32c::new())),
+            Integrity::None => None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Adler32(hasher) => hasher.update(data),
+            Self::Crc32c(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        match self {
+            Self::Adler32(hasher) => hasher.finish(),
+            Self::Crc32c(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// Incremental Adler-32 (for windows that are checksummed in pieces).
+#[cfg(feature = "adler32")]
+pub(crate) struct Adler32(simd_adler32::Adler32);
+
+#[cfg(feature = "adler32")]
+impl Adler32 {
+    fn new() -> Self {
+        Self(simd_adler32::Adler32::new())
+    }
+    fn update(&mut self, data: &[u8]) {
+        self.0.write(data);
+    }
+    fn finish(&self) -> u32 {
+        self.0.finish()
+    }
+}
+
+/// Incremental Adler-32 (for windows that are checksummed in pieces).
+#[cfg(not(feature = "adler32"))]
+pub(crate) struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+#[cfg(not(feature = "adler32"))]
+impl Adler32 {
+    fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+    fn update(&mut self, data: &[u8]) {
+        const MOD_ADLER: u32 = 65521;
+        for &byte in data {
+            self.a = (self.a + u32::from(byte)) % MOD_ADLER;
+            self.b = (self.b + self.a) % MOD_ADLER;
+        }
+    }
+    fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+/// Reflected CRC-32C polynomial.
+const CRC32C_POLY: u32 = 0x82F6_3B78;
+
+/// Slicing-by-8 tables: `CRC32C_TABLES[k][b]` is the CRC of byte `b`
+/// followed by `k` zero bytes.
+static CRC32C_TABLES: [[u32; 256]; 8] = crc32c_tables();
+
+const fn crc32c_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = (prev >> 8) ^ tables[0][(prev & 0xFF) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+/// Incremental CRC-32C (table-driven, eight bytes per step).
+pub(crate) struct Crc32c {
+    state: u32,
+}
+
+impl Crc32c {
+    fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let t = &CRC32C_TABLES;
+        let mut crc = self.state;
+        let mut chunks = data.chunks_exact(8);
+        for chunk in &mut chunks {
+            let lo = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) ^ crc;
+            let hi = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            crc = t[7][(lo & 0xFF) as usize]
+                ^ t[6][((lo >> 8) & 0xFF) as usize]
+                ^ t[5][((lo >> 16) & 0xFF) as usize]
+                ^ t[4][(lo >> 24) as usize]
+                ^ t[3][(hi & 0xFF) as usize]
+                ^ t[2][((hi >> 8) & 0xFF) as usize]
+                ^ t[1][((hi >> 16) & 0xFF) as usize]
+                ^ t[0][(hi >> 24) as usize];
+        }
+        for &byte in chunks.remainder() {
+            crc = (crc >> 8) ^ t[0][((crc ^ u32::from(byte)) & 0xFF) as usize];
+        }
+        self.state = crc;
+    }
+
+    fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(Integrity::Crc32c.checksum(b"123456789"), Some(0xE306_9283));
+        assert_eq!(Integrity::Crc32c.checksum(b""), Some(0));
+        assert_eq!(Integrity::Adler32.checksum(b"Wikipedia"), Some(0x11E6_0398));
+        assert_eq!(Integrity::None.checksum(b"abc"), None);
+
+        // Split updates agree with one-shot hashing at every split point.
+        let data: Vec<u8> = (0..100u8).map(|i| i.wrapping_mul(37)).collect();
+        let whole = Integrity::Crc32c.checksum(&data).unwrap();
+        for split in 0..data.len() {
+            let mut hasher = WindowHasher::new(Integrity::Crc32c).unwrap();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), whole, "split at {split}");
+        }
+    }
+
+    #[test]
+    fn crc32c_token_is_added_once() {
+        assert_eq!(with_crc32c_token(None), CRC32C_TOKEN);
+        assert_eq!(
+            with_crc32c_token(Some(b"sha256:ab")),
+            b"sha256:ab/checksum:crc32c"
+        );
+        let marked = with_crc32c_token(Some(b"x checksum:crc32c"));
+        assert_eq!(marked, b"x checksum:crc32c");
+        // A token must match whole, not as a prefix of another.
+        assert_eq!(
+            Integrity::from_app_header(Some(b"checksum:crc32cx")),
+            Integrity::Adler32
+        );
+    }
+
+    #[test]
+    fn crc32c_is_declared_inside_app_header_maps() {
+        let map = AppHeaderMap::from_iter([("tool", "oxidelta"), (CHECKSUM_KEY, "adler32")]);
+        let bytes = map.to_bytes();
+        assert_eq!(Integrity::from_app_header(Some(&bytes)), Integrity::Adler32);
+
+        let marked = with_crc32c_token(Some(&bytes));
+        assert_eq!(Integrity::from_app_header(Some(&marked)), Integrity::Crc32c);
+        let parsed = AppHeaderMap::parse(&marked).unwrap();
+        assert_eq!(parsed.get("tool"), Some("oxidelta"));
+        assert_eq!(parsed.get(CHECKSUM_KEY), Some("crc32c"));
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(with_crc32c_token(Some(&marked)), marked);
+
+        // In a map the text token is just bytes of a value.
+        let map = AppHeaderMap::from_iter([("note", "checksum:crc32c")]);
+        assert_eq!(
+            Integrity::from_app_header(Some(&map.to_bytes())),
+            Integrity::Adler32
+        );
+    }
+
+    #[test]
+    fn crc32c_map_header_roundtrips_through_the_encoder() {
+        use crate::compress::decoder::decode_all;
+        use crate::compress::encoder::{CompressOptions, DeltaEncoder};
+        use crate::vcdiff::header::FileHeader;
+
+        let source = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let mut target = source.clone();
+        target[100] = b'!';
+        let opts = CompressOptions {
+            integrity: Integrity::Crc32c,
+            ..Default::default()
+        };
+        let mut enc = DeltaEncoder::new(Vec::new(), &source, opts);
+        enc.set_app_header(AppHeaderMap::from_iter([("tool", "oxidelta")]).to_bytes());
+        enc.write_target(&target).unwrap();
+        let delta = enc.finish().unwrap().0;
+
+        let header = FileHeader::decode(&mut &delta[..]).unwrap();
+        let app_header = header.app_header.unwrap();
+        let map = AppHeaderMap::parse(&app_header).unwrap();
+        assert_eq!(map.get("tool"), Some("oxidelta"));
+        assert_eq!(map.get(CHECKSUM_KEY), Some("crc32c"));
+        assert_eq!(decode_all(&source, &delta).unwrap(), target);
+    }
+}
@@ -1,27 +1,48 @@
-// VCDIFF format implementation (RFC 3284).
-//
-// This module provides encoding and decoding of the VCDIFF delta format,
-// byte-for-byte compatible with xdelta3.
-//
-// # Modules
-//
-// - `varint`        — Variable-length integer encoding (base-128, big-endian)
-// - `address_cache` — NEAR/SAME address cache for COPY instruction addresses
-// - `code_table`    — Default RFC 3284 code table (256 entries)
-// - `header`        — File header and per-window header encoding/decoding
-// - `encoder`       — Instruction encoding and window emission
-// - `decoder`       — Instruction decoding and window reconstruction
-
-pub mod address_cache;
-pub mod code_table;
-pub mod decoder;
-pub mod encoder;
-pub mod header;
-pub mod varint;
-
-// Re-export key types for convenience.
-pub use address_cache::AddressCache;
-pub use code_table::{CodeTable, CodeTableEntry, Instruction};
-pub use decoder::{DecodeError, InstructionIterator, StreamDecoder, decode_memory};
-pub use encoder::{SourceWindow, StreamEncoder, WindowEncoder, WindowSections};
-pub use header::{FileHeader, VCDIFF_MAGIC, WindowHeader};
+// VCDIFF format implementation (RFC 3284).
+//
+// This module provides encoding and decoding of the VCDIFF delta format,
+// byte-for-byte compatible with xdelta3.
+//
+// # Modules
+//
+// - `varint`        — Variable-length integer encoding (base-128, big-endian)
+// - `address_cache` — NEAR/SAME address cache for COPY instruction addresses
+// - `code_table`    — Default RFC 3284 code table and application-defined tables
+// - `header`        — File header and per-window header encoding/decoding
+// - `app_header`    — `AppHeaderMap`, key/value metadata in the app header
+// - `integrity`     — Window checksums: Adler-32 or CRC-32C (oxidelta extension)
+// - `encoder`       — Instruction encoding and window emission (`std` only)
+// - `decoder`       — Instruction decoding and window reconstruction
+// - `reader`        — `ByteReader`, the decoder's byte source
+//
+// Without the `std` feature only the decoding half is built, reading from
+// byte slices; see `decode_memory`.
+
+pub mod address_cache;
+pub mod app_header;
+pub mod code_table;
+pub mod decoder;
+#[cfg(feature = "std")]
+pub mod encoder;
+pub mod header;
+pub mod integrity;
+pub mod reader;
+pub mod varint;
+
+// Re-export key types for convenience.
+pub use address_cache::{AddressCache, AddressCacheError, EncodedAddr};
+pub use app_header::{AppHeaderMap, AppHeaderMapError};
+pub use code_table::{CodeTable, CodeTableEntry, CodeTableError, CustomCodeTable, Instruction};
+#[cfg(feature = "std")]
+pub use decoder::SeekSource;
+pub use decoder::{
+    DecodeError, DecodeLimits, DecoderState, ExternalLiterals, InstructionIterator, SourceFn,
+    StreamDecoder, StreamInstructions, ValidationReport, decode_memory, validate,
+};
+#[cfg(feature = "std")]
+pub use encoder::{CoverageError, SourceWindow, StreamEncoder, WindowEncoder, WindowSections};
+pub use header::{
+    FileHeader, FileHeaderBuilder, FileHeaderError, HeaderBitPolicy, VCDIFF_MAGIC, WindowHeader,
+};
+pub use integrity::Integrity;
+pub use reader::{ByteReader, ReadError};
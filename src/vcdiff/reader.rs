@@ -0,0 +1,168 @@
+// Byte sources for the VCDIFF decoder.
+//
+// With the `std` feature every `std::io::Read` is a `ByteReader` and read
+// errors are plain `std::io::Error`s, so streaming callers are unaffected.
+// In an `alloc`-only (`no_std`) build byte slices are the readers and errors
+// use the small `ReadError` type defined below.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+
+/// Source of delta bytes for [`StreamDecoder`](super::StreamDecoder) and the
+/// header parsers.
+pub trait ByteReader {
+    /// Fill `buf` completely. Running out of input is reported as an
+    /// unexpected-EOF error (see [`ReadError`]).
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError>;
+}
+
+/// Reader that yields `head` before continuing with `rest`; used to put back
+/// bytes that were read ahead.
+pub(crate) struct Prefixed<'a, R: ?Sized> {
+    head: &'a [u8],
+    rest: &'a mut R,
+}
+
+impl<'a, R: ByteReader + ?Sized> Prefixed<'a, R> {
+    pub(crate) fn new(head: &'a [u8], rest: &'a mut R) -> Self {
+        Self { head, rest }
+    }
+}
+
+impl<R: ByteReader + ?Sized> ByteReader for Prefixed<'_, R> {
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        let n = self.head.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.head[..n]);
+        self.head = &self.head[n..];
+        if n == buf.len() {
+            return Ok(());
+        }
+        self.rest.fill_exact(&mut buf[n..])
+    }
+}
+
+/// Reader that counts the bytes read through it; lets the decoder report
+/// its position in the delta.
+pub(crate) struct Counted<R> {
+    pub(crate) inner: R,
+    pub(crate) count: u64,
+}
+
+impl<R: ByteReader> ByteReader for Counted<R> {
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        self.inner.fill_exact(buf)?;
+        self.count += buf.len() as u64;
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// std: io::Read / io::Error
+// ---------------------------------------------------------------------------
+
+/// Error produced while reading delta bytes.
+#[cfg(feature = "std")]
+pub type ReadError = std::io::Error;
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> ByteReader for R {
+    #[inline]
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        self.read_exact(buf)
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn invalid_data<E>(error: E) -> ReadError
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn is_eof(error: &ReadError) -> bool {
+    error.kind() == std::io::ErrorKind::UnexpectedEof
+}
+
+// ---------------------------------------------------------------------------
+// no_std: byte slices / ReadError
+// ---------------------------------------------------------------------------
+
+/// What went wrong while reading delta bytes.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadErrorKind {
+    /// The input ended before the requested bytes were available.
+    UnexpectedEof,
+    /// The bytes were read but do not form valid VCDIFF.
+    InvalidData,
+}
+
+/// Error produced while reading delta bytes.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadError {
+    kind: ReadErrorKind,
+    message: String,
+}
+
+#[cfg(not(feature = "std"))]
+impl ReadError {
+    /// Create an error of `kind` with a human-readable message.
+    pub fn new(kind: ReadErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ReadErrorKind {
+        self.kind
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for ReadError {}
+
+#[cfg(not(feature = "std"))]
+impl ByteReader for &[u8] {
+    #[inline]
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        if self.len() < buf.len() {
+            return Err(ReadError::new(
+                ReadErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: ByteReader + ?Sized> ByteReader for &mut R {
+    #[inline]
+    fn fill_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        (**self).fill_exact(buf)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn invalid_data<E: core::fmt::Display>(error: E) -> ReadError {
+    ReadError::new(ReadErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn is_eof(error: &ReadError) -> bool {
+    error.kind() == ReadErrorKind::UnexpectedEof
+}
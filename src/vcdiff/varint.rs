@@ -1,317 +1,457 @@
-// VCDIFF variable-length integer encoding (RFC 3284, Section 2).
-//
-// Base-128, big-endian: most-significant group first.
-// Each byte has bit 7 set except the final byte.
-// Identical to xdelta3's `xd3_emit_size` / `xd3_read_size`.
-
-use std::io::{self, Read, Write};
-
-/// Maximum encoded length for a 64-bit value (ceil(64/7) = 10).
-const MAX_VARINT_LEN: usize = 10;
-
-/// Overflow guard for 32-bit accumulator: if these bits are set before a
-/// shift, the next `<< 7` would overflow.
-const U32_OVERFLOW_MASK: u32 = 0xFE00_0000;
-
-/// Overflow guard for 64-bit accumulator.
-const U64_OVERFLOW_MASK: u64 = 0xFE00_0000_0000_0000;
-
-// ---------------------------------------------------------------------------
-// Encoding
-// ---------------------------------------------------------------------------
-
-/// Encode a `u64` as a VCDIFF variable-length integer into `buf`.
-/// Returns the number of bytes written (1..=10).
-///
-/// Matches xdelta3 `EMIT_INTEGER_TYPE`: fills a 10-byte scratch buffer from
-/// the end, MSB set on all bytes, then clears MSB on the final (last) byte.
-#[inline]
-pub fn encode_u64(mut num: u64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
-    let mut i = MAX_VARINT_LEN;
-    loop {
-        i -= 1;
-        buf[i] = (num as u8 & 0x7F) | 0x80;
-        num >>= 7;
-        if num == 0 {
-            break;
-        }
-    }
-    buf[MAX_VARINT_LEN - 1] &= 0x7F; // clear MSB on last byte
-    MAX_VARINT_LEN - i
-}
-
-/// Encode a `u32` as a VCDIFF variable-length integer into `buf`.
-/// Returns the number of bytes written (1..=5).
-#[inline]
-pub fn encode_u32(num: u32, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
-    encode_u64(num as u64, buf)
-}
-
-/// Encode a `usize` and write to a `Write` sink.
-pub fn write_usize<W: Write>(w: &mut W, num: usize) -> io::Result<()> {
-    let mut buf = [0u8; MAX_VARINT_LEN];
-    let len = encode_u64(num as u64, &mut buf);
-    w.write_all(&buf[MAX_VARINT_LEN - len..])
-}
-
-/// Encode a `u64` and write to a `Write` sink.
-pub fn write_u64<W: Write>(w: &mut W, num: u64) -> io::Result<()> {
-    let mut buf = [0u8; MAX_VARINT_LEN];
-    let len = encode_u64(num, &mut buf);
-    w.write_all(&buf[MAX_VARINT_LEN - len..])
-}
-
-/// Encode a `u32` and write to a `Write` sink.
-pub fn write_u32<W: Write>(w: &mut W, num: u32) -> io::Result<()> {
-    write_u64(w, num as u64)
-}
-
-// ---------------------------------------------------------------------------
-// Decoding from byte slices (non-streaming, matches `READ_INTEGER_TYPE`)
-// ---------------------------------------------------------------------------
-
-/// Decode a `u64` from a byte slice, advancing the cursor.
-/// Returns `(value, bytes_consumed)` or an error.
-///
-/// Matches xdelta3 `READ_INTEGER_TYPE` with `UINT64_OFLOW_MASK`.
-pub fn read_u64(data: &[u8]) -> Result<(u64, usize), VarIntError> {
-    let mut val: u64 = 0;
-    for (i, &byte) in data.iter().enumerate() {
-        if val & U64_OVERFLOW_MASK != 0 {
-            return Err(VarIntError::Overflow);
-        }
-        val = (val << 7) | u64::from(byte & 0x7F);
-        if byte & 0x80 == 0 {
-            return Ok((val, i + 1));
-        }
-    }
-    Err(VarIntError::Underflow)
-}
-
-/// Decode a `u32` from a byte slice, advancing the cursor.
-pub fn read_u32(data: &[u8]) -> Result<(u32, usize), VarIntError> {
-    let mut val: u32 = 0;
-    for (i, &byte) in data.iter().enumerate() {
-        if val & U32_OVERFLOW_MASK != 0 {
-            return Err(VarIntError::Overflow);
-        }
-        val = (val << 7) | u32::from(byte & 0x7F);
-        if byte & 0x80 == 0 {
-            return Ok((val, i + 1));
-        }
-    }
-    Err(VarIntError::Underflow)
-}
-
-/// Decode a `usize` from a byte slice.
-pub fn read_usize(data: &[u8]) -> Result<(usize, usize), VarIntError> {
-    // Use u64 internally, then narrow with overflow check.
-    let (val, len) = read_u64(data)?;
-    let val = usize::try_from(val).map_err(|_| VarIntError::Overflow)?;
-    Ok((val, len))
-}
-
-// ---------------------------------------------------------------------------
-// Decoding from `Read` (streaming)
-// ---------------------------------------------------------------------------
-
-/// Read a `u64` varint from a streaming source.
-pub fn stream_read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
-    let mut val: u64 = 0;
-    let mut buf = [0u8; 1];
-    loop {
-        r.read_exact(&mut buf)?;
-        let byte = buf[0];
-        if val & U64_OVERFLOW_MASK != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "varint overflow",
-            ));
-        }
-        val = (val << 7) | u64::from(byte & 0x7F);
-        if byte & 0x80 == 0 {
-            return Ok(val);
-        }
-    }
-}
-
-/// Read a `u32` varint from a streaming source.
-pub fn stream_read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
-    let mut val: u32 = 0;
-    let mut buf = [0u8; 1];
-    loop {
-        r.read_exact(&mut buf)?;
-        let byte = buf[0];
-        if val & U32_OVERFLOW_MASK != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "varint overflow",
-            ));
-        }
-        val = (val << 7) | u32::from(byte & 0x7F);
-        if byte & 0x80 == 0 {
-            return Ok(val);
-        }
-    }
-}
-
-/// Read a `usize` varint from a streaming source.
-pub fn stream_read_usize<R: Read>(r: &mut R) -> io::Result<usize> {
-    let val = stream_read_u64(r)?;
-    usize::try_from(val).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "varint overflow"))
-}
-
-// ---------------------------------------------------------------------------
-// Helpers
-// ---------------------------------------------------------------------------
-
-/// Return the encoded byte-length of a `u64` value.
-/// Matches xdelta3 `xd3_sizeof_uint64_t`.
-#[inline]
-pub fn sizeof_u64(num: u64) -> usize {
-    let bits = 64 - num.leading_zeros();
-    (bits.max(1).div_ceil(7) as usize).min(10)
-}
-
-/// Return the encoded byte-length of a `u32` value.
-/// Matches xdelta3 `xd3_sizeof_uint32_t`.
-#[inline]
-pub fn sizeof_u32(num: u32) -> usize {
-    let bits = 32 - num.leading_zeros();
-    (bits.max(1).div_ceil(7) as usize).min(5)
-}
-
-/// Return the encoded byte-length of a `usize` value.
-#[inline]
-pub fn sizeof_usize(num: usize) -> usize {
-    sizeof_u64(num as u64)
-}
-
-// ---------------------------------------------------------------------------
-// Error type
-// ---------------------------------------------------------------------------
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum VarIntError {
-    /// Not enough input bytes to complete the integer.
-    Underflow,
-    /// Value would overflow the target integer type.
-    Overflow,
-}
-
-impl std::fmt::Display for VarIntError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            VarIntError::Underflow => write!(f, "varint underflow (truncated input)"),
-            VarIntError::Overflow => write!(f, "varint overflow"),
-        }
-    }
-}
-
-impl std::error::Error for VarIntError {}
-
-impl From<VarIntError> for io::Error {
-    fn from(e: VarIntError) -> io::Error {
-        io::Error::new(io::ErrorKind::InvalidData, e)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn roundtrip_u64() {
-        let cases: &[u64] = &[
-            0,
-            1,
-            127,
-            128,
-            255,
-            256,
-            16383,
-            16384,
-            u32::MAX as u64,
-            u64::MAX,
-        ];
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        for &val in cases {
-            let len = encode_u64(val, &mut buf);
-            let (decoded, consumed) = read_u64(&buf[MAX_VARINT_LEN - len..]).unwrap();
-            assert_eq!(decoded, val, "roundtrip failed for {val}");
-            assert_eq!(consumed, len, "length mismatch for {val}");
-            assert_eq!(sizeof_u64(val), len, "sizeof mismatch for {val}");
-        }
-    }
-
-    #[test]
-    fn roundtrip_u32() {
-        let cases: &[u32] = &[0, 1, 127, 128, 16383, 16384, u32::MAX];
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        for &val in cases {
-            let len = encode_u32(val, &mut buf);
-            let (decoded, consumed) = read_u32(&buf[MAX_VARINT_LEN - len..]).unwrap();
-            assert_eq!(decoded, val);
-            assert_eq!(consumed, len);
-            assert_eq!(sizeof_u32(val), len);
-        }
-    }
-
-    #[test]
-    fn encoding_is_big_endian() {
-        // 300 = 0b100101100 = two groups: (10) (0101100) = 0x82 0x2C
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        let len = encode_u64(300, &mut buf);
-        assert_eq!(len, 2);
-        assert_eq!(&buf[MAX_VARINT_LEN - 2..], &[0x82, 0x2C]);
-    }
-
-    #[test]
-    fn single_byte_values() {
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        for val in 0..=127u64 {
-            let len = encode_u64(val, &mut buf);
-            assert_eq!(len, 1);
-            assert_eq!(buf[MAX_VARINT_LEN - 1], val as u8);
-        }
-    }
-
-    #[test]
-    fn overflow_detection_u32() {
-        // Encode u64::MAX and try to decode as u32 -- must fail.
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        let len = encode_u64(u64::MAX, &mut buf);
-        let result = read_u32(&buf[MAX_VARINT_LEN - len..]);
-        assert_eq!(result, Err(VarIntError::Overflow));
-    }
-
-    #[test]
-    fn underflow_detection() {
-        // Truncated: all continuation bytes, no terminator.
-        let data = [0x80, 0x80, 0x80];
-        assert_eq!(read_u64(&data), Err(VarIntError::Underflow));
-    }
-
-    #[test]
-    fn streaming_roundtrip() {
-        let mut buf = [0u8; MAX_VARINT_LEN];
-        let len = encode_u64(123456789, &mut buf);
-        let bytes = &buf[MAX_VARINT_LEN - len..MAX_VARINT_LEN];
-        let mut cursor = std::io::Cursor::new(bytes);
-        let val = stream_read_u64(&mut cursor).unwrap();
-        assert_eq!(val, 123456789);
-    }
-
-    #[test]
-    fn write_read_roundtrip() {
-        let mut out = Vec::new();
-        write_u64(&mut out, 999999).unwrap();
-        let (val, len) = read_u64(&out).unwrap();
-        assert_eq!(val, 999999);
-        assert_eq!(len, out.len());
-    }
-}
+// VCDIFF variable-length integer encoding (RFC 3284, Section 2).
+//
+// Base-128, big-endian: most-significant group first.
+// Each byte has bit 7 set except the final byte.
+// Identical to xdelta3's `xd3_emit_size` / `xd3_read_size`.
+//
+// The signed `_i64` variants are an oxidelta addition (VCDIFF itself has no
+// signed integers): values are zigzag-mapped to `u64` and then encoded as
+// above, so small magnitudes of either sign stay short.
+
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use super::reader::{ByteReader, ReadError, invalid_data};
+
+/// Maximum encoded length for a 64-bit value (ceil(64/7) = 10).
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Overflow guard for 32-bit accumulator: if these bits are set before a
+/// shift, the next `<< 7` would overflow.
+const U32_OVERFLOW_MASK: u32 = 0xFE00_0000;
+
+/// Overflow guard for 64-bit accumulator.
+const U64_OVERFLOW_MASK: u64 = 0xFE00_0000_0000_0000;
+
+// ---------------------------------------------------------------------------
+// Encoding
+// ---------------------------------------------------------------------------
+
+/// Encode a `u64` as a VCDIFF variable-length integer into `buf`.
+/// Returns the number of bytes written (1..=10).
+///
+/// Matches xdelta3 `EMIT_INTEGER_TYPE`: fills a 10-byte scratch buffer from
+/// the end, MSB set on all bytes, then clears MSB on the final (last) byte.
+#[inline]
+pub fn encode_u64(mut num: u64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    let mut i = MAX_VARINT_LEN;
+    loop {
+        i -= 1;
+        buf[i] = (num as u8 & 0x7F) | 0x80;
+        num >>= 7;
+        if num == 0 {
+            break;
+        }
+    }
+    buf[MAX_VARINT_LEN - 1] &= 0x7F; // clear MSB on last byte
+    MAX_VARINT_LEN - i
+}
+
+/// Encode a `u32` as a VCDIFF variable-length integer into `buf`.
+/// Returns the number of bytes written (1..=5).
+#[inline]
+pub fn encode_u32(num: u32, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    encode_u64(num as u64, buf)
+}
+
+/// Encode an `i64` as a zigzag varint into `buf`.
+/// Returns the number of bytes written (1..=10).
+///
+/// The encoded bytes are `buf[MAX_VARINT_LEN - len..]`, as for [`encode_u64`].
+#[inline]
+pub fn encode_i64(num: i64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    encode_u64(zigzag_encode(num), buf)
+}
+
+/// Encode a `usize` and write to a `Write` sink.
+#[cfg(feature = "std")]
+pub fn write_usize<W: Write>(w: &mut W, num: usize) -> io::Result<()> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = encode_u64(num as u64, &mut buf);
+    w.write_all(&buf[MAX_VARINT_LEN - len..])
+}
+
+/// Encode a `u64` and write to a `Write` sink.
+#[cfg(feature = "std")]
+pub fn write_u64<W: Write>(w: &mut W, num: u64) -> io::Result<()> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = encode_u64(num, &mut buf);
+    w.write_all(&buf[MAX_VARINT_LEN - len..])
+}
+
+/// Encode a `u32` and write to a `Write` sink.
+#[cfg(feature = "std")]
+pub fn write_u32<W: Write>(w: &mut W, num: u32) -> io::Result<()> {
+    write_u64(w, num as u64)
+}
+
+// ---------------------------------------------------------------------------
+// Decoding from byte slices (non-streaming, matches `READ_INTEGER_TYPE`)
+// ---------------------------------------------------------------------------
+
+/// Decode a `u64` from a byte slice, advancing the cursor.
+/// Returns `(value, bytes_consumed)` or an error.
+///
+/// Matches xdelta3 `READ_INTEGER_TYPE` with `UINT64_OFLOW_MASK`.
+pub fn read_u64(data: &[u8]) -> Result<(u64, usize), VarIntError> {
+    let mut val: u64 = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if val & U64_OVERFLOW_MASK != 0 {
+            return Err(VarIntError::Overflow);
+        }
+        val = (val << 7) | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok((val, i + 1));
+        }
+    }
+    Err(VarIntError::Underflow)
+}
+
+/// Decode a `u32` from a byte slice, advancing the cursor.
+pub fn read_u32(data: &[u8]) -> Result<(u32, usize), VarIntError> {
+    let mut val: u32 = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if val & U32_OVERFLOW_MASK != 0 {
+            return Err(VarIntError::Overflow);
+        }
+        val = (val << 7) | u32::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok((val, i + 1));
+        }
+    }
+    Err(VarIntError::Underflow)
+}
+
+/// Decode a zigzag `i64` from a byte slice, advancing the cursor.
+/// Returns `(value, bytes_consumed)` or an error.
+pub fn read_i64(data: &[u8]) -> Result<(i64, usize), VarIntError> {
+    let (val, len) = read_u64(data)?;
+    Ok((zigzag_decode(val), len))
+}
+
+/// Decode a `usize` from a byte slice.
+pub fn read_usize(data: &[u8]) -> Result<(usize, usize), VarIntError> {
+    // Use u64 internally, then narrow with overflow check.
+    let (val, len) = read_u64(data)?;
+    let val = usize::try_from(val).map_err(|_| VarIntError::Overflow)?;
+    Ok((val, len))
+}
+
+// ---------------------------------------------------------------------------
+// Decoding from a `ByteReader` (streaming)
+// ---------------------------------------------------------------------------
+
+/// Read a `u64` varint from a streaming source.
+pub fn stream_read_u64<R: ByteReader>(r: &mut R) -> Result<u64, ReadError> {
+    let mut val: u64 = 0;
+    let mut buf = [0u8; 1];
+    loop {
+        r.fill_exact(&mut buf)?;
+        let byte = buf[0];
+        if val & U64_OVERFLOW_MASK != 0 {
+            return Err(invalid_data("varint overflow"));
+        }
+        val = (val << 7) | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+}
+
+/// Read a `u32` varint from a streaming source.
+pub fn stream_read_u32<R: ByteReader>(r: &mut R) -> Result<u32, ReadError> {
+    let mut val: u32 = 0;
+    let mut buf = [0u8; 1];
+    loop {
+        r.fill_exact(&mut buf)?;
+        let byte = buf[0];
+        if val & U32_OVERFLOW_MASK != 0 {
+            return Err(invalid_data("varint overflow"));
+        }
+        val = (val << 7) | u32::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+}
+
+/// Decode a `u64` varint from a `Read`, one byte at a time.
+///
+/// The streaming counterpart of [`read_u64`] for external parsers: input
+/// ending mid-varint is an `UnexpectedEof` error and a value wider than 64
+/// bits is an `InvalidData` error wrapping [`VarIntError::Overflow`].
+#[cfg(feature = "std")]
+pub fn decode_u64<R: Read + ?Sized>(r: &mut R) -> io::Result<u64> {
+    let mut val: u64 = 0;
+    let mut buf = [0u8; 1];
+    loop {
+        r.read_exact(&mut buf)?;
+        let byte = buf[0];
+        if val & U64_OVERFLOW_MASK != 0 {
+            return Err(VarIntError::Overflow.into());
+        }
+        val = (val << 7) | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+}
+
+/// Read a `usize` varint from a streaming source.
+pub fn stream_read_usize<R: ByteReader>(r: &mut R) -> Result<usize, ReadError> {
+    let val = stream_read_u64(r)?;
+    usize::try_from(val).map_err(|_| invalid_data("varint overflow"))
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+/// Return the encoded byte-length of a `u64` value.
+/// Matches xdelta3 `xd3_sizeof_uint64_t`.
+#[inline]
+pub fn sizeof_u64(num: u64) -> usize {
+    let bits = 64 - num.leading_zeros();
+    (bits.max(1).div_ceil(7) as usize).min(10)
+}
+
+/// Return the encoded byte-length of a `u32` value.
+/// Matches xdelta3 `xd3_sizeof_uint32_t`.
+#[inline]
+pub fn sizeof_u32(num: u32) -> usize {
+    let bits = 32 - num.leading_zeros();
+    (bits.max(1).div_ceil(7) as usize).min(5)
+}
+
+/// Return the encoded byte-length of a `usize` value.
+#[inline]
+pub fn sizeof_usize(num: usize) -> usize {
+    sizeof_u64(num as u64)
+}
+
+/// Return the encoded byte-length of a zigzag `i64` value.
+#[inline]
+pub fn sizeof_i64(num: i64) -> usize {
+    sizeof_u64(zigzag_encode(num))
+}
+
+/// Map signed to unsigned so that 0, -1, 1, -2, ... become 0, 1, 2, 3, ...
+#[inline]
+const fn zigzag_encode(num: i64) -> u64 {
+    ((num << 1) ^ (num >> 63)) as u64
+}
+
+/// Inverse of [`zigzag_encode`].
+#[inline]
+const fn zigzag_decode(num: u64) -> i64 {
+    ((num >> 1) as i64) ^ -((num & 1) as i64)
+}
+
+// ---------------------------------------------------------------------------
+// Error type
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarIntError {
+    /// Not enough input bytes to complete the integer.
+    Underflow,
+    /// Value would overflow the target integer type.
+    Overflow,
+}
+
+impl core::fmt::Display for VarIntError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VarIntError::Underflow => write!(f, "varint underflow (truncated input)"),
+            VarIntError::Overflow => write!(f, "varint overflow"),
+        }
+    }
+}
+
+impl core::error::Error for VarIntError {}
+
+#[cfg(feature = "std")]
+impl From<VarIntError> for io::Error {
+    fn from(e: VarIntError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_u64() {
+        let cases: &[u64] = &[
+            0,
+            1,
+            127,
+            128,
+            255,
+            256,
+            16383,
+            16384,
+            u32::MAX as u64,
+            u64::MAX,
+        ];
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        for &val in cases {
+            let len = encode_u64(val, &mut buf);
+            let (decoded, consumed) = read_u64(&buf[MAX_VARINT_LEN - len..]).unwrap();
+            assert_eq!(decoded, val, "roundtrip failed for {val}");
+            assert_eq!(consumed, len, "length mismatch for {val}");
+            assert_eq!(sizeof_u64(val), len, "sizeof mismatch for {val}");
+        }
+    }
+
+    #[test]
+    fn roundtrip_u32() {
+        let cases: &[u32] = &[0, 1, 127, 128, 16383, 16384, u32::MAX];
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        for &val in cases {
+            let len = encode_u32(val, &mut buf);
+            let (decoded, consumed) = read_u32(&buf[MAX_VARINT_LEN - len..]).unwrap();
+            assert_eq!(decoded, val);
+            assert_eq!(consumed, len);
+            assert_eq!(sizeof_u32(val), len);
+        }
+    }
+
+    #[test]
+    fn roundtrip_i64() {
+        let cases: &[i64] = &[
+            0,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            -65,
+            8191,
+            -8192,
+            i32::MAX as i64,
+            i32::MIN as i64,
+            i64::MAX,
+            i64::MIN,
+            i64::MAX - 1,
+            i64::MIN + 1,
+        ];
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        for &val in cases {
+            let len = encode_i64(val, &mut buf);
+            let (decoded, consumed) = read_i64(&buf[MAX_VARINT_LEN - len..]).unwrap();
+            assert_eq!(decoded, val, "roundtrip failed for {val}");
+            assert_eq!(consumed, len, "length mismatch for {val}");
+            assert_eq!(sizeof_i64(val), len, "sizeof mismatch for {val}");
+        }
+    }
+
+    #[test]
+    fn zigzag_keeps_small_magnitudes_short() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        assert_eq!(encode_i64(0, &mut buf), 1);
+        assert_eq!(buf[MAX_VARINT_LEN - 1], 0);
+        assert_eq!(encode_i64(-1, &mut buf), 1);
+        assert_eq!(buf[MAX_VARINT_LEN - 1], 1);
+        assert_eq!(encode_i64(1, &mut buf), 1);
+        assert_eq!(buf[MAX_VARINT_LEN - 1], 2);
+        assert_eq!(sizeof_i64(-64), 1);
+        assert_eq!(sizeof_i64(64), 2);
+        assert_eq!(sizeof_i64(i64::MIN), 10);
+        assert_eq!(sizeof_i64(i64::MAX), 10);
+    }
+
+    #[test]
+    fn read_i64_errors_match_unsigned() {
+        assert_eq!(read_i64(&[0x80, 0x80]), Err(VarIntError::Underflow));
+        let data = [0xFF; 11];
+        assert_eq!(read_i64(&data), Err(VarIntError::Overflow));
+    }
+
+    #[test]
+    fn encoding_is_big_endian() {
+        // 300 = 0b100101100 = two groups: (10) (0101100) = 0x82 0x2C
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        let len = encode_u64(300, &mut buf);
+        assert_eq!(len, 2);
+        assert_eq!(&buf[MAX_VARINT_LEN - 2..], &[0x82, 0x2C]);
+    }
+
+    #[test]
+    fn single_byte_values() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        for val in 0..=127u64 {
+            let len = encode_u64(val, &mut buf);
+            assert_eq!(len, 1);
+            assert_eq!(buf[MAX_VARINT_LEN - 1], val as u8);
+        }
+    }
+
+    #[test]
+    fn overflow_detection_u32() {
+        // Encode u64::MAX and try to decode as u32 -- must fail.
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        let len = encode_u64(u64::MAX, &mut buf);
+        let result = read_u32(&buf[MAX_VARINT_LEN - len..]);
+        assert_eq!(result, Err(VarIntError::Overflow));
+    }
+
+    #[test]
+    fn underflow_detection() {
+        // Truncated: all continuation bytes, no terminator.
+        let data = [0x80, 0x80, 0x80];
+        assert_eq!(read_u64(&data), Err(VarIntError::Underflow));
+    }
+
+    #[test]
+    fn max_length_u64() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        assert_eq!(encode_u64(u64::MAX, &mut buf), MAX_VARINT_LEN);
+        assert_eq!(read_u64(&buf), Ok((u64::MAX, MAX_VARINT_LEN)));
+        assert_eq!(decode_u64(&mut &buf[..]).unwrap(), u64::MAX);
+
+        // One more bit than fits: 0x82 leads 65 significant bits.
+        let mut wide = buf;
+        wide[0] = 0x82;
+        assert_eq!(read_u64(&wide), Err(VarIntError::Overflow));
+        let err = decode_u64(&mut &wide[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let wide_extra = [0xFF; MAX_VARINT_LEN + 1];
+        assert_eq!(read_u64(&wide_extra), Err(VarIntError::Overflow));
+    }
+
+    #[test]
+    fn truncated_u64() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        encode_u64(u64::MAX, &mut buf);
+        for cut in 0..MAX_VARINT_LEN {
+            assert_eq!(read_u64(&buf[..cut]), Err(VarIntError::Underflow), "{cut}");
+            let err = decode_u64(&mut &buf[..cut]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{cut}");
+        }
+    }
+
+    #[test]
+    fn streaming_roundtrip() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        let len = encode_u64(123456789, &mut buf);
+        let bytes = &buf[MAX_VARINT_LEN - len..MAX_VARINT_LEN];
+        let mut cursor = std::io::Cursor::new(bytes);
+        let val = stream_read_u64(&mut cursor).unwrap();
+        assert_eq!(val, 123456789);
+    }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let mut out = Vec::new();
+        write_u64(&mut out, 999999).unwrap();
+        let (val, len) = read_u64(&out).unwrap();
+        assert_eq!(val, 999999);
+        assert_eq!(len, out.len());
+    }
+}
@@ -1,62 +1,716 @@
-use std::process::Command;
-use tempfile::tempdir;
-
-fn bin() -> String {
-    env!("CARGO_BIN_EXE_oxidelta").to_string()
-}
-
-#[test]
-fn cli_encode_decode_roundtrip() {
-    let dir = tempdir().unwrap();
-    let source = dir.path().join("source.bin");
-    let target = dir.path().join("target.bin");
-    let delta = dir.path().join("delta.vcdiff");
-    let output = dir.path().join("output.bin");
-
-    std::fs::write(&source, b"abcde12345abcde12345").unwrap();
-    std::fs::write(&target, b"abcdeXXXXXabcde12345!").unwrap();
-
-    let st = Command::new(bin())
-        .arg("--force")
-        .args(["encode", "--source"])
-        .arg(&source)
-        .arg(&target)
-        .arg(&delta)
-        .status()
-        .unwrap();
-    assert!(st.success());
-
-    let st = Command::new(bin())
-        .arg("--force")
-        .args(["decode", "--source"])
-        .arg(&source)
-        .arg(&delta)
-        .arg(&output)
-        .status()
-        .unwrap();
-    assert!(st.success());
-    assert_eq!(
-        std::fs::read(&output).unwrap(),
-        std::fs::read(&target).unwrap()
-    );
-}
-
-#[test]
-fn cli_no_output_flag() {
-    let dir = tempdir().unwrap();
-    let input = dir.path().join("in.bin");
-    std::fs::write(&input, b"payload").unwrap();
-
-    let st = Command::new(bin())
-        .args(["encode", "--check-only"])
-        .arg(&input)
-        .status()
-        .unwrap();
-    assert!(st.success());
-}
-
-#[test]
-fn cli_config_works() {
-    let out = Command::new(bin()).arg("config").output().unwrap();
-    assert!(out.status.success());
-}
+#![cfg(feature = "std")]
+
+use std::process::Command;
+use tempfile::tempdir;
+
+fn bin() -> String {
+    env!("CARGO_BIN_EXE_oxidelta").to_string()
+}
+
+#[test]
+fn cli_encode_decode_roundtrip() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let output = dir.path().join("output.bin");
+
+    std::fs::write(&source, b"abcde12345abcde12345").unwrap();
+    std::fs::write(&target, b"abcdeXXXXXabcde12345!").unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["encode", "--source"])
+        .arg(&source)
+        .arg(&target)
+        .arg(&delta)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["decode", "--source"])
+        .arg(&source)
+        .arg(&delta)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(st.success());
+    assert_eq!(
+        std::fs::read(&output).unwrap(),
+        std::fs::read(&target).unwrap()
+    );
+}
+
+#[test]
+fn cli_no_output_flag() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("in.bin");
+    std::fs::write(&input, b"payload").unwrap();
+
+    let st = Command::new(bin())
+        .args(["encode", "--check-only"])
+        .arg(&input)
+        .status()
+        .unwrap();
+    assert!(st.success());
+}
+
+#[test]
+fn cli_config_works() {
+    let out = Command::new(bin()).arg("config").output().unwrap();
+    assert!(out.status.success());
+}
+
+#[test]
+fn cli_config_json_reports_capabilities() {
+    let out = Command::new(bin())
+        .args(["config", "--json"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let caps = oxidelta::capabilities();
+    assert!(stdout.contains(&format!("\"version\": \"{}\"", caps.version)));
+    assert!(stdout.contains("\"fgk\": \"unverified\""));
+    assert!(stdout.contains(&format!(
+        "\"match_kernel\": \"{}\"",
+        caps.match_kernel.name()
+    )));
+}
+
+#[test]
+fn cli_recode_preserves_unknown_header_bits() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let recoded = dir.path().join("recoded.vcdiff");
+
+    std::fs::write(&source, b"abcde12345abcde12345").unwrap();
+    std::fs::write(&target, b"abcdeXXXXXabcde12345!").unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["encode", "--source"])
+        .arg(&source)
+        .arg(&target)
+        .arg(&delta)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    // Set a reserved hdr_ind bit (byte 4, after the magic).
+    let mut bytes = std::fs::read(&delta).unwrap();
+    bytes[4] |= 0x40;
+    std::fs::write(&delta, &bytes).unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .arg("recode")
+        .arg(&delta)
+        .arg(&recoded)
+        .status()
+        .unwrap();
+    assert!(st.success());
+    let out = std::fs::read(&recoded).unwrap();
+    assert_eq!(out[4] & 0x40, 0x40, "reserved header bit dropped by recode");
+}
+
+#[cfg(feature = "fgk-encode")]
+#[test]
+fn cli_recode_same_secondary_copies_sections() {
+    use oxidelta::compress::secondary::{CompressBackend, FgkBackend};
+    use oxidelta::vcdiff::FileHeader;
+    use oxidelta::vcdiff::header::{VCD_DATACOMP, VCD_FGK_ID, WindowHeader};
+
+    // One ADD of "ab" whose data section is FGK-coded even though that makes
+    // it longer; re-compressing would store the two bytes raw instead.
+    let data = FgkBackend.compress(b"ab").unwrap();
+    assert!(data.len() > 2);
+    let inst = [3u8]; // ADD, size 2
+    let mut wh = WindowHeader {
+        win_ind: 0,
+        copy_window_len: 0,
+        copy_window_offset: 0,
+        enc_len: 0,
+        target_window_len: 2,
+        del_ind: VCD_DATACOMP,
+        data_len: data.len() as u64,
+        inst_len: inst.len() as u64,
+        addr_len: 0,
+        adler32: None,
+    };
+    wh.enc_len = wh.compute_enc_len();
+    let mut windows = Vec::new();
+    wh.encode(&mut windows).unwrap();
+    windows.extend_from_slice(&data);
+    windows.extend_from_slice(&inst);
+    let mut bytes = Vec::new();
+    FileHeader::builder()
+        .secondary(VCD_FGK_ID)
+        .build()
+        .unwrap()
+        .encode(&mut bytes)
+        .unwrap();
+    bytes.extend_from_slice(&windows);
+
+    let dir = tempdir().unwrap();
+    let delta = dir.path().join("delta.vcdiff");
+    let recoded = dir.path().join("recoded.vcdiff");
+    std::fs::write(&delta, &bytes).unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["recode", "--secondary", "fgk", "--app-header", "k=v"])
+        .arg(&delta)
+        .arg(&recoded)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let out = std::fs::read(&recoded).unwrap();
+    let mut rest = &out[..];
+    let header = FileHeader::decode(&mut rest).unwrap();
+    assert_eq!(header.secondary_id, Some(VCD_FGK_ID));
+    assert!(header.app_header.is_some());
+    assert_eq!(rest, &windows[..]);
+    assert_eq!(
+        oxidelta::compress::decoder::decode_all(b"", &out).unwrap(),
+        b"ab"
+    );
+}
+
+#[test]
+fn cli_recode_writes_app_header_map() {
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let recoded = dir.path().join("recoded.vcdiff");
+    std::fs::write(&target, b"abcde12345abcde12345").unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .arg("encode")
+        .arg(&target)
+        .arg(&delta)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["recode", "--app-header", "tool=oxidelta", "--app-header"])
+        .arg("name=grüße.bin")
+        .arg(&delta)
+        .arg(&recoded)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let bytes = std::fs::read(&recoded).unwrap();
+    let header = oxidelta::vcdiff::FileHeader::decode(&mut &bytes[..]).unwrap();
+    let map = oxidelta::vcdiff::AppHeaderMap::parse(header.app_header.as_deref().unwrap()).unwrap();
+    assert_eq!(map.get("tool"), Some("oxidelta"));
+    assert_eq!(map.get("name"), Some("grüße.bin"));
+
+    let out = Command::new(bin())
+        .arg("header")
+        .arg(&recoded)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let text = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        text.contains("VCDIFF application header:    tool=oxidelta, name=grüße.bin\n"),
+        "{text}"
+    );
+}
+
+#[test]
+fn cli_recode_app_header_keeps_crc32c() {
+    use oxidelta::compress::encoder::{CompressOptions, encode_all};
+    use oxidelta::vcdiff::{AppHeaderMap, FileHeader, Integrity};
+
+    let source_bytes = b"abcde12345abcde12345".repeat(50);
+    let mut target_bytes = source_bytes.clone();
+    target_bytes[500] = b'!';
+    let mut delta_bytes = Vec::new();
+    let opts = CompressOptions {
+        integrity: Integrity::Crc32c,
+        ..Default::default()
+    };
+    encode_all(&mut delta_bytes, &source_bytes, &target_bytes, opts).unwrap();
+
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let recoded = dir.path().join("recoded.vcdiff");
+    let output = dir.path().join("output.bin");
+    std::fs::write(&source, &source_bytes).unwrap();
+    std::fs::write(&delta, &delta_bytes).unwrap();
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["recode", "--app-header", "tool=oxidelta"])
+        .arg(&delta)
+        .arg(&recoded)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let bytes = std::fs::read(&recoded).unwrap();
+    let header = FileHeader::decode(&mut &bytes[..]).unwrap();
+    assert_eq!(
+        Integrity::from_app_header(header.app_header.as_deref()),
+        Integrity::Crc32c
+    );
+    let map = AppHeaderMap::parse(header.app_header.as_deref().unwrap()).unwrap();
+    assert_eq!(map.get("tool"), Some("oxidelta"));
+
+    let st = Command::new(bin())
+        .arg("--force")
+        .args(["decode", "--source"])
+        .arg(&source)
+        .arg(&recoded)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(st.success());
+    assert_eq!(std::fs::read(&output).unwrap(), target_bytes);
+}
+
+#[test]
+fn cli_merge_matches_sequential_patches() {
+    let dir = tempdir().unwrap();
+    let versions: Vec<Vec<u8>> = (0..3u8)
+        .map(|v| {
+            let mut data: Vec<u8> = (0..20_000u32).map(|i| (i % 253) as u8).collect();
+            for i in (v as usize * 7..data.len()).step_by(997) {
+                data[i] = data[i].wrapping_add(v + 1);
+            }
+            data.extend(format!("version {v} trailer").bytes());
+            data
+        })
+        .collect();
+    let paths: Vec<_> = (0..3)
+        .map(|i| dir.path().join(format!("v{i}.bin")))
+        .collect();
+    for (path, data) in paths.iter().zip(&versions) {
+        std::fs::write(path, data).unwrap();
+    }
+
+    let patch = |from: usize, to: usize| {
+        let delta = dir.path().join(format!("{from}-{to}.vcdiff"));
+        let st = Command::new(bin())
+            .arg("--force")
+            .args(["encode", "--source"])
+            .arg(&paths[from])
+            .arg(&paths[to])
+            .arg(&delta)
+            .status()
+            .unwrap();
+        assert!(st.success());
+        delta
+    };
+    let d1 = patch(0, 1);
+    let d2 = patch(1, 2);
+
+    let merged = dir.path().join("merged.vcdiff");
+    let st = Command::new(bin())
+        .args(["merge", "--source"])
+        .arg(&paths[0])
+        .arg("--patch")
+        .arg(&d1)
+        .arg(&d2)
+        .arg(&merged)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let output = dir.path().join("out.bin");
+    let st = Command::new(bin())
+        .args(["decode", "--source"])
+        .arg(&paths[0])
+        .arg(&merged)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(st.success());
+    assert_eq!(std::fs::read(&output).unwrap(), versions[2]);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_verify_reports_mismatches() {
+    use sha2::Digest;
+
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+
+    let source_data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut target_data = source_data.clone();
+    target_data[5000..5010].copy_from_slice(b"0123456789");
+    std::fs::write(&source, &source_data).unwrap();
+    std::fs::write(&target, &target_data).unwrap();
+
+    let st = Command::new(bin())
+        .args(["encode", "--source"])
+        .arg(&source)
+        .arg(&target)
+        .arg(&delta)
+        .status()
+        .unwrap();
+    assert!(st.success());
+
+    let digest: String = sha2::Sha256::digest(&target_data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let verify = |delta: &std::path::Path, sha: &str| {
+        Command::new(bin())
+            .args(["verify", "--source"])
+            .arg(&source)
+            .args(["--expect-sha256", sha])
+            .arg(delta)
+            .output()
+            .unwrap()
+    };
+
+    let out = verify(&delta, &digest);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = Command::new(bin())
+        .args(["verify", "--json", "--source"])
+        .arg(&source)
+        .arg(&delta)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&out.stderr).unwrap();
+    assert_eq!(json["output_size"], target_data.len() as u64);
+    assert_eq!(json["window_count"], 1);
+    assert!(json.get("windows").is_none());
+
+    let out = verify(&delta, &"ab".repeat(32));
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("SHA-256 mismatch"), "{stderr}");
+    assert!(stderr.contains(&digest), "{stderr}");
+
+    let expect_size = |size: usize| {
+        Command::new(bin())
+            .args(["verify", "--source"])
+            .arg(&source)
+            .args(["--expect-size", &size.to_string()])
+            .arg(&delta)
+            .output()
+            .unwrap()
+    };
+    assert!(expect_size(target_data.len()).status.success());
+    let out = expect_size(target_data.len() + 1);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("output size mismatch"), "{stderr}");
+
+    // Flip the last byte of the delta (inside the final window's sections).
+    let mut corrupt = std::fs::read(&delta).unwrap();
+    *corrupt.last_mut().unwrap() ^= 0x40;
+    let corrupt_path = dir.path().join("corrupt.vcdiff");
+    std::fs::write(&corrupt_path, &corrupt).unwrap();
+    let out = verify(&corrupt_path, &digest);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("oxidelta: verify: window 0:"), "{stderr}");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_encode_writes_block_hashes() {
+    use sha2::Digest;
+
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let sidecar = dir.path().join("target.blocks");
+
+    let source_data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let mut target_data = source_data.clone();
+    target_data[9000] ^= 0xFF;
+    std::fs::write(&source, &source_data).unwrap();
+    std::fs::write(&target, &target_data).unwrap();
+
+    let expected: Vec<String> = target_data
+        .chunks(4096)
+        .map(|block| {
+            sha2::Sha256::digest(block)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect()
+        })
+        .collect();
+
+    for extra in [&[][..], &["--max-delta-size", "1M"][..]] {
+        let st = Command::new(bin())
+            .arg("--force")
+            .args(["encode", "--source"])
+            .arg(&source)
+            .arg("--block-hashes")
+            .arg(&sidecar)
+            .args(extra)
+            .arg(&target)
+            .arg(&delta)
+            .status()
+            .unwrap();
+        assert!(st.success());
+        let written = std::fs::read_to_string(&sidecar).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), expected, "{extra:?}");
+    }
+
+    // An existing sidecar is not overwritten without --force.
+    let st = Command::new(bin())
+        .args(["encode", "--source"])
+        .arg(&source)
+        .arg("--block-hashes")
+        .arg(&sidecar)
+        .arg(&target)
+        .arg(dir.path().join("other.vcdiff"))
+        .status()
+        .unwrap();
+    assert!(!st.success());
+}
+
+#[test]
+fn cli_encode_over_budget_keeps_existing_output() {
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let target_data: Vec<u8> = (0..4096u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    std::fs::write(&target, &target_data).unwrap();
+    std::fs::write(&delta, b"previous delta").unwrap();
+
+    let out = Command::new(bin())
+        .arg("--force")
+        .args(["encode", "--max-delta-size", "64"])
+        .arg(&target)
+        .arg(&delta)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("--max-delta-size"), "{stderr}");
+    assert_eq!(std::fs::read(&delta).unwrap(), b"previous delta");
+}
+
+#[test]
+fn cli_diff_patch_aliases_use_stdio() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let old = dir.path().join("old.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let new_data = b"abcdeXXXXXabcde12345!".to_vec();
+    std::fs::write(&old, b"abcde12345abcde12345").unwrap();
+
+    // New file on stdin.
+    let mut child = Command::new(bin())
+        .arg("diff")
+        .arg(&old)
+        .arg("-")
+        .arg(&delta)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&new_data).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    // Rebuilt file on stdout.
+    let out = Command::new(bin())
+        .arg("patch")
+        .arg(&old)
+        .arg(&delta)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, new_data);
+
+    let out = Command::new(bin())
+        .args(["diff", "-", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn cli_dash_paths_use_stdio() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let old = dir.path().join("old.bin");
+    let old_data = b"abcde12345abcde12345".repeat(8);
+    let new_data = [&old_data[..], b"tail"].concat();
+    std::fs::write(&old, &old_data).unwrap();
+    // A file named `-` is neither read nor overwritten.
+    std::fs::write(dir.path().join("-"), b"keep").unwrap();
+
+    let pipe = |args: &[&str], input: &[u8]| {
+        let mut child = Command::new(bin())
+            .args(args)
+            .current_dir(dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success(), "{args:?}");
+        out.stdout
+    };
+
+    let delta = pipe(&["encode", "-s", "old.bin", "-", "-"], &new_data);
+    assert_eq!(&delta[..3], &[0xD6, 0xC3, 0xC4]);
+    let decoded = pipe(
+        &["decode", "-s", "old.bin", "--input", "-", "--output", "-"],
+        &delta,
+    );
+    assert_eq!(decoded, new_data);
+    let recoded = pipe(&["recode", "-", "-"], &delta);
+    assert_eq!(pipe(&["decode", "-s", "old.bin", "-"], &recoded), new_data);
+    assert_eq!(std::fs::read(dir.path().join("-")).unwrap(), b"keep");
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_source_from_inherited_fd() {
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let old = dir.path().join("old.bin");
+    let new = dir.path().join("new.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let old_data = b"abcde12345abcde12345".repeat(8);
+    let new_data = [&old_data[..], b"tail"].concat();
+    std::fs::write(&old, &old_data).unwrap();
+    std::fs::write(&new, &new_data).unwrap();
+
+    // The source arrives on descriptor 0; input and output are files.
+    let st = Command::new(bin())
+        .args(["--force", "encode", "--source", "fd:0", "--input"])
+        .arg(&new)
+        .arg("--output")
+        .arg(&delta)
+        .stdin(Stdio::from(std::fs::File::open(&old).unwrap()))
+        .status()
+        .unwrap();
+    assert!(st.success());
+    let out = Command::new(bin())
+        .args(["decode", "--source", "fd:0", "--input"])
+        .arg(&delta)
+        .args(["--output", "-"])
+        .stdin(Stdio::from(std::fs::File::open(&old).unwrap()))
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, new_data);
+
+    // The source starts at the descriptor's offset, not the file's start.
+    let prefixed = dir.path().join("prefixed.bin");
+    std::fs::write(&prefixed, [&b"junk"[..], &old_data].concat()).unwrap();
+    let mut stdin = std::fs::File::open(&prefixed).unwrap();
+    std::io::Seek::seek(&mut stdin, std::io::SeekFrom::Start(4)).unwrap();
+    let out = Command::new(bin())
+        .args(["decode", "--source", "fd:0", "--input"])
+        .arg(&delta)
+        .args(["--output", "-"])
+        .stdin(Stdio::from(stdin))
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, new_data);
+
+    // Descriptor 0 cannot also carry the input, and junk after `fd:` is
+    // rejected rather than read as a file name.
+    for source in ["fd:0", "fd:zero"] {
+        let out = Command::new(bin())
+            .args(["decode", "--source", source])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!out.status.success(), "{source}");
+    }
+
+    // A descriptor that is not open is reported, not borrowed.
+    let out = Command::new(bin())
+        .args(["decode", "--source", "fd:1000", "--input"])
+        .arg(&delta)
+        .args(["--output", "-"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("fd:1000"), "{stderr}");
+}
+
+#[test]
+fn cli_decode_skip_length_writes_range() {
+    let dir = tempdir().unwrap();
+    let old = dir.path().join("old.bin");
+    let new = dir.path().join("new.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let old_data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut new_data = old_data.clone();
+    new_data.extend_from_slice(&old_data[..20_000]);
+    std::fs::write(&old, &old_data).unwrap();
+    std::fs::write(&new, &new_data).unwrap();
+
+    let status = Command::new(bin())
+        .args(["encode", "--window-size", "4K", "-s"])
+        .arg(&old)
+        .arg(&new)
+        .arg(&delta)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let decode = |extra: &[&str], delta: &std::path::Path| {
+        Command::new(bin())
+            .arg("decode")
+            .args(extra)
+            .arg("-s")
+            .arg(&old)
+            .arg(delta)
+            .output()
+            .unwrap()
+    };
+    let out = decode(&["--skip", "10000", "--length", "25000"], &delta);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, &new_data[10_000..35_000]);
+    // A length past the end stops at the end of the target.
+    let out = decode(&["--skip", "50000", "--length", "1M"], &delta);
+    assert_eq!(out.stdout, &new_data[50_000..]);
+
+    // Windows past the range are not read unless --check-only asks for the
+    // whole stream.
+    let bytes = std::fs::read(&delta).unwrap();
+    let truncated = dir.path().join("truncated.vcdiff");
+    std::fs::write(&truncated, &bytes[..bytes.len() - 10]).unwrap();
+    let out = decode(&["--length", "100"], &truncated);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, &new_data[..100]);
+    let out = decode(&["--length", "100", "--check-only"], &truncated);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+}
@@ -1,4 +1,6 @@
-use oxidelta::io::{decode_file, encode_file};
+#![cfg(feature = "std")]
+
+use oxidelta::io::{decode_file, diff_files, encode_file, patch_file};
 use std::io::{Seek, Write};
 use tempfile::NamedTempFile;
 
@@ -99,3 +101,32 @@ fn edge_case_matrix() {
         assert_eq!(decoded, target);
     }
 }
+
+#[test]
+fn diff_and_patch_files_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let target = dir.path().join("target.bin");
+    let delta = dir.path().join("delta.vcdiff");
+    let output = dir.path().join("output.bin");
+
+    let source_data: Vec<u8> = (0..=255u8).cycle().take(256 * 1024).collect();
+    let mut target_data = source_data.clone();
+    for i in (0..target_data.len()).step_by(3001) {
+        target_data[i] ^= 0x5A;
+    }
+    std::fs::write(&source, &source_data).unwrap();
+    std::fs::write(&target, &target_data).unwrap();
+
+    let opts = oxidelta::compress::encoder::CompressOptions {
+        window_size: 64 * 1024,
+        ..Default::default()
+    };
+    let enc = diff_files(&source, &target, &delta, opts).unwrap();
+    assert!(enc.windows > 1);
+    assert_eq!(enc.delta_size, std::fs::metadata(&delta).unwrap().len());
+
+    let dec = patch_file(&source, &delta, &output).unwrap();
+    assert_eq!(dec.output_size, target_data.len() as u64);
+    assert_eq!(std::fs::read(&output).unwrap(), target_data);
+}
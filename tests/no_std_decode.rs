@@ -0,0 +1,105 @@
+// Decoder tests that only use the `alloc` half of the API.
+//
+// Run with `cargo test --no-default-features --features alloc --test
+// no_std_decode` to check the `no_std` build; they also run in the default
+// configuration. The deltas are fixed bytes because the encoder needs `std`.
+
+#![cfg(feature = "alloc")]
+
+use oxidelta::vcdiff::{DecodeError, FileHeader, WindowHeader, decode_memory};
+
+const SOURCE: &[u8] =
+    b"the quick brown fox jumps over the lazy dog; the quick brown fox jumps again";
+const TARGET: &[u8] =
+    b"the quick brown cat jumps over the lazy dog; the quick brown cat jumps again!!";
+
+/// `SOURCE` -> `TARGET`: one window of source COPYs and ADDs, with Adler-32.
+const SOURCE_DELTA: &[u8] = &[
+    0xD6, 0xC3, 0xC4, 0x00, // magic
+    0x00, // hdr_ind
+    0x05, // win_ind: VCD_SOURCE | VCD_ADLER32
+    0x4C, 0x00, // copy window: len 76, offset 0
+    0x1B, 0x4E, 0x00, // enc_len 27, target_window_len 78, del_ind
+    0x08, 0x07, 0x03, // data, inst, addr lengths
+    0x67, 0xDD, 0x1C, 0x0F, // adler32
+    b'c', b'a', b't', b'c', b'a', b't', b'!', b'!', // data
+    0x20, 0x04, 0x13, 0x2A, 0x04, 0x1C, 0x03, // inst
+    0x00, 0x13, 0x40, // addr
+];
+
+/// No source: a RUN, an ADD, and a target self-COPY.
+const TARGET_DELTA: &[u8] = &[
+    0xD6, 0xC3, 0xC4, 0x00, // magic
+    0x00, // hdr_ind
+    0x04, // win_ind: VCD_ADLER32
+    0x11, 0x35, 0x00, // enc_len 17, target_window_len 53, del_ind
+    0x03, 0x04, 0x01, // data, inst, addr lengths
+    0x1F, 0x63, 0x14, 0x2B, // adler32
+    b'a', b'b', b'c', // data
+    0x00, 0x21, 0x03, 0x22, // inst
+    0x20, // addr
+];
+
+#[test]
+fn decodes_source_copies() {
+    assert_eq!(decode_memory(SOURCE_DELTA, SOURCE).unwrap(), TARGET);
+}
+
+#[test]
+fn decodes_target_self_copies() {
+    let mut expected = vec![b'a'; 32];
+    expected.extend(b"abc".repeat(7));
+    assert_eq!(decode_memory(TARGET_DELTA, &[]).unwrap(), expected);
+}
+
+#[test]
+fn parses_headers_from_slices() {
+    let mut reader = SOURCE_DELTA;
+    let header = FileHeader::decode(&mut reader).unwrap();
+    assert_eq!(header.secondary_id, None);
+    let window = WindowHeader::decode(&mut reader).unwrap().unwrap();
+    assert_eq!(window.target_window_len, TARGET.len() as u64);
+    assert_eq!(window.adler32, Some(0x67DD_1C0F));
+    assert_eq!(
+        reader.len() as u64,
+        window.data_len + window.inst_len + window.addr_len
+    );
+}
+
+#[test]
+fn truncated_delta_is_reported() {
+    for (cut, in_sections) in [(3, false), (SOURCE_DELTA.len() - 1, true)] {
+        let result = decode_memory(&SOURCE_DELTA[..cut], SOURCE);
+        assert!(
+            matches!(
+                result,
+                Err(DecodeError::Truncated { at_window: 0, expected_more })
+                    if expected_more == in_sections
+            ),
+            "cut at {cut}"
+        );
+    }
+}
+
+#[test]
+fn checksum_mismatch_is_detected() {
+    let mut delta = SOURCE_DELTA.to_vec();
+    delta[17] ^= 0x01;
+    assert!(matches!(
+        decode_memory(&delta, SOURCE),
+        Err(DecodeError::ChecksumMismatch { .. })
+    ));
+}
+
+#[cfg(not(feature = "std"))]
+#[test]
+fn secondary_compression_needs_std() {
+    // Same window, but flagged as LZMA-compressed data.
+    let mut delta = SOURCE_DELTA.to_vec();
+    delta.splice(4..5, [0x01, 0x02]); // VCD_SECONDARY, id 2
+    delta[11] = 0x01; // del_ind: VCD_DATACOMP
+    assert!(matches!(
+        decode_memory(&delta, SOURCE),
+        Err(DecodeError::Unsupported(_))
+    ));
+}
@@ -1,6 +1,9 @@
+#![cfg(feature = "std")]
+
 use oxidelta::compress::decoder;
 use oxidelta::compress::encoder::{self, CompressOptions};
 use oxidelta::compress::secondary::SecondaryCompression;
+use oxidelta::vcdiff::AddressCache;
 use proptest::prelude::*;
 
 fn encode(source: &[u8], target: &[u8], level: u32) -> Vec<u8> {
@@ -41,6 +44,39 @@ proptest! {
         prop_assert!(delta.len() < target.len(), "delta={} target={}", delta.len(), target.len());
     }
 
+    #[test]
+    fn prop_address_cache_roundtrip(
+        s_near in 0usize..=8,
+        s_same in 0usize..=4,
+        warmup in proptest::collection::vec(any::<u64>(), 0..32),
+        start in 1u64..=u64::MAX / 4,
+        steps in proptest::collection::vec((any::<u64>(), 1u64..=1 << 20, 0u8..4), 1..256),
+    ) {
+        let mut enc = AddressCache::with_sizes(s_near, s_same);
+        for &addr in &warmup {
+            enc.update(addr);
+        }
+        let mut dec = enc.clone();
+        let mut here = start;
+        let mut prev = 0u64;
+        for (pick, advance, kind) in steps {
+            here += advance;
+            // Mostly random addresses, plus repeats and near-misses of the
+            // previous one so the NEAR and SAME modes get used.
+            let addr = match kind {
+                0 => prev,
+                1 => prev.saturating_add(pick % 300),
+                _ => pick,
+            } % here;
+            let (mode, encoded) = enc.encode(addr, here);
+            prop_assert!((mode as usize) < enc.mode_count());
+            let (decoded, consumed) = dec.decode(mode, encoded.as_bytes(), here).unwrap();
+            prop_assert_eq!((decoded, consumed), (addr, encoded.len()));
+            prop_assert_eq!(&enc, &dec);
+            prev = addr;
+        }
+    }
+
     #[test]
     fn prop_small_mutation_keeps_delta_bounded(
         source in proptest::collection::vec(any::<u8>(), 256..8192),
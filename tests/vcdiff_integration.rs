@@ -7,6 +7,8 @@
 //   - xdelta3 interoperability (if xdelta3 binary is available)
 //   - Decoder robustness against malformed input
 
+#![cfg(feature = "std")]
+
 use oxidelta::vcdiff::{
     code_table::Instruction,
     decoder::{self, StreamDecoder},